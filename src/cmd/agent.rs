@@ -0,0 +1,59 @@
+use crate::cmd::{GlobalArgs, RunCommand};
+use crate::runner::Agent;
+use anyhow::bail;
+use clap::Args;
+use std::io;
+use std::net::TcpListener;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct AgentArgs {
+    /// Listens on the address for requests instead of reading one from
+    /// stdin, a --token is needed to listen beyond localhost
+    #[arg(long, value_name = "ADDR")]
+    pub listen: Option<String>,
+
+    /// Only runs the requests which carry this token
+    #[arg(long)]
+    pub token: Option<String>,
+
+    /// The directory the workflows run in, the current directory if not
+    /// given
+    #[arg(long, value_name = "DIR")]
+    pub dir: Option<PathBuf>,
+}
+
+impl RunCommand for AgentArgs {
+    fn run(&self, global_args: &GlobalArgs) -> anyhow::Result<()> {
+        let dir = match &self.dir {
+            Some(dir) => dir.clone(),
+            None => std::env::current_dir()?,
+        };
+        let mut agent = Agent::new(&dir);
+        if let Some(token) = &self.token {
+            agent = agent.with_token(token.clone());
+        }
+
+        let addr = match &self.listen {
+            Some(addr) => addr,
+            None => {
+                // the events are written to stdout, so nothing else may be
+                if !agent.handle(&mut io::stdin().lock(), &mut io::stdout().lock())? {
+                    bail!("The workflow did not succeed");
+                }
+                return Ok(());
+            }
+        };
+        let listener = TcpListener::bind(addr)?;
+        if self.token.is_none() && !listener.local_addr()?.ip().is_loopback() {
+            bail!(
+                "Refusing to listen on {} without a --token, anyone who can connect could run commands",
+                addr
+            );
+        }
+        if !global_args.quiet {
+            eprintln!("Agent listening on {}", listener.local_addr()?);
+        }
+        agent.serve(listener)
+    }
+}
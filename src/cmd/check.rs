@@ -0,0 +1,286 @@
+use crate::cmd::{GlobalArgs, RunCommand};
+use crate::runner::lint::{apply_fixes, lint_file, Finding, Fix, LintConfig, Severity, RULES};
+use crate::runner::syntax_errors;
+use ansi_term::Colour::{Red, Yellow};
+use anyhow::bail;
+use clap::Args;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Args, Debug)]
+pub struct CheckArgs {
+    /// The path to the workflow to check, not needed with --list-rules
+    #[arg(required_unless_present = "list_rules")]
+    pub workflow: Option<PathBuf>,
+
+    /// Reports the findings of the rule as errors, can be repeated
+    #[arg(long, value_name = "RULE")]
+    pub deny: Vec<String>,
+
+    /// Does not check the rule, can be repeated
+    #[arg(long, value_name = "RULE")]
+    pub allow: Vec<String>,
+
+    /// Rewrites the workflow file to fix the findings which have a fix,
+    /// after asking for confirmation
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub fix: bool,
+
+    /// Applies the fixes without asking for confirmation
+    #[arg(long, requires = "fix", action = clap::ArgAction::SetTrue)]
+    pub yes: bool,
+
+    /// Fails the check on warnings as well as errors, e.g. on deprecated
+    /// variables which are still read
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub strict: bool,
+
+    /// Lists the rules and their severities instead of checking
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub list_rules: bool,
+
+    /// The additional arguments that will be passed along to the workflow
+    #[arg(allow_hyphen_values = true, trailing_var_arg = true)]
+    pub workflow_args: Vec<String>,
+}
+
+impl CheckArgs {
+    /// Returns the config of the workflow's project with the rules given on
+    /// the command line applied, a rule which is both denied and allowed
+    /// is denied.
+    fn config(&self) -> anyhow::Result<LintConfig> {
+        // without a workflow the rules are listed for the current directory
+        let dir = PathBuf::from(".");
+        let mut config = LintConfig::for_workflow(self.workflow.as_ref().unwrap_or(&dir))?;
+        for rule in &self.allow {
+            config.set(rule, Severity::Allow)?;
+        }
+        for rule in &self.deny {
+            config.set(rule, Severity::Error)?;
+        }
+        Ok(config)
+    }
+
+    /// Returns the workflow to check, which is only left out when the
+    /// rules are listed.
+    fn workflow(&self) -> anyhow::Result<&Path> {
+        match &self.workflow {
+            Some(workflow) => Ok(workflow),
+            None => bail!("the path to the workflow to check is required"),
+        }
+    }
+
+    /// Applies the fixes of the findings until there are none left, or the
+    /// user does not confirm them, and returns the findings which are left.
+    fn fix(
+        &self,
+        config: &LintConfig,
+        out: &mut dyn Write,
+        input: &mut dyn BufRead,
+    ) -> anyhow::Result<Vec<Finding>> {
+        let workflow = self.workflow()?;
+        loop {
+            let findings = lint_file(workflow, self.workflow_args.clone(), config)?;
+            let fixes: Vec<&Fix> = findings.iter().filter_map(|f| f.fix.as_ref()).collect();
+            if fixes.is_empty() {
+                return Ok(findings);
+            }
+            for finding in findings.iter().filter(|f| f.fix.is_some()) {
+                print_finding(out, workflow, finding)?;
+            }
+            if !self.yes {
+                write!(
+                    out,
+                    "Apply {} fixes to {}? [y/N] ",
+                    fixes.len(),
+                    workflow.display()
+                )?;
+                out.flush()?;
+                let mut answer = String::new();
+                input.read_line(&mut answer)?;
+                if !matches!(answer.trim(), "y" | "Y" | "yes") {
+                    return Ok(findings);
+                }
+            }
+            let text = fs::read_to_string(workflow)?;
+            let fixed = apply_fixes(&text, &fixes);
+            if fixed == text {
+                return Ok(findings);
+            }
+            fs::write(workflow, fixed)?;
+            writeln!(out, "Applied {} fixes", fixes.len())?;
+        }
+    }
+}
+
+fn print_finding(out: &mut dyn Write, workflow: &Path, finding: &Finding) -> io::Result<()> {
+    let severity = match finding.severity {
+        Severity::Error => Red.paint(finding.severity.to_string()),
+        _ => Yellow.paint(finding.severity.to_string()),
+    };
+    let location = match finding.line {
+        Some(line) => format!("{}:{}", workflow.display(), line),
+        None => workflow.display().to_string(),
+    };
+    writeln!(
+        out,
+        "{}: {}[{}]: {}",
+        location, severity, finding.rule, finding.message
+    )?;
+    match &finding.fix {
+        Some(fix) => writeln!(out, "  fix: {}", fix.description),
+        None => Ok(()),
+    }
+}
+
+impl RunCommand for CheckArgs {
+    fn run(&self, _global_args: &GlobalArgs) -> anyhow::Result<()> {
+        let config = self.config()?;
+        let mut out = io::stdout();
+        if self.list_rules {
+            for rule in RULES.iter() {
+                writeln!(
+                    out,
+                    "{} ({}): {}",
+                    rule.id,
+                    config.severity(rule.id),
+                    rule.description
+                )?;
+            }
+            return Ok(());
+        }
+
+        let workflow = self.workflow()?;
+        let text = fs::read_to_string(workflow)?;
+        let errors = syntax_errors(&workflow.display().to_string(), &text);
+        if !errors.is_empty() {
+            for error in &errors {
+                writeln!(out, "{}\n", error)?;
+            }
+            bail!("{} has {} syntax errors", workflow.display(), errors.len());
+        }
+
+        let findings = match self.fix {
+            true => self.fix(&config, &mut out, &mut io::stdin().lock())?,
+            false => lint_file(workflow, self.workflow_args.clone(), &config)?,
+        };
+        for finding in &findings {
+            print_finding(&mut out, workflow, finding)?;
+        }
+        let errors = findings
+            .iter()
+            .filter(|f| f.severity == Severity::Error)
+            .count();
+        let warnings = findings.len() - errors;
+        if !findings.is_empty() {
+            writeln!(out, "{} errors, {} warnings", errors, warnings)?;
+        }
+        if errors > 0 || (self.strict && warnings > 0) {
+            bail!("{} failed the check", workflow.display());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib::test_utils::TempWorkflowFile;
+    use clap::Parser;
+
+    #[test]
+    fn test_fix_asks_for_confirmation() {
+        let content = "main = workflow(graph = [node(action = action(tool = builtin_tool(name = \"true\")))])\n";
+        let file = TempWorkflowFile::new("test.workflow", content).unwrap();
+        let args = CheckArgs {
+            workflow: Some(file.path()),
+            deny: vec![],
+            allow: vec![],
+            fix: true,
+            yes: false,
+            strict: false,
+            list_rules: false,
+            workflow_args: vec![],
+        };
+        let config = args.config().unwrap();
+        let content = fs::read_to_string(file.path()).unwrap();
+
+        let mut out = vec![];
+        let findings = args.fix(&config, &mut out, &mut "n\n".as_bytes()).unwrap();
+        assert_eq!(findings.len(), 2);
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), content);
+
+        let mut out = vec![];
+        let findings = args
+            .fix(&config, &mut out, &mut "y\ny\n".as_bytes())
+            .unwrap();
+        assert_eq!(findings, vec![]);
+        assert_eq!(
+            fs::read_to_string(file.path()).unwrap(),
+            "main = workflow(entrypoint = \"main\", graph = [node(name = \"main\", action = action(tool = builtin_tool(name = \"true\")))])\n\n"
+        );
+        assert_eq!(
+            String::from_utf8(out)
+                .unwrap()
+                .matches("Applied 1 fixes")
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_strict_fails_on_warnings() {
+        let content = r#"
+db = variable(default = "a", deprecated = "use db_url instead")
+main = workflow(
+    entrypoint = "a",
+    graph = [node(name = "a", action = action(tool = builtin_tool(name = "echo"), args = [db]))],
+)
+"#;
+        let file = TempWorkflowFile::new("test.workflow", content).unwrap();
+        let mut args = CheckArgs {
+            workflow: Some(file.path()),
+            deny: vec![],
+            allow: vec![],
+            fix: false,
+            yes: false,
+            strict: false,
+            list_rules: false,
+            workflow_args: vec![],
+        };
+        let global_args = GlobalArgs {
+            quiet: true,
+            no_pager: true,
+            verbose: 0,
+        };
+        assert!(args.run(&global_args).is_ok());
+        args.strict = true;
+        assert!(args
+            .run(&global_args)
+            .unwrap_err()
+            .to_string()
+            .contains("failed the check"));
+    }
+
+    #[derive(clap::Parser)]
+    struct Check {
+        #[command(flatten)]
+        args: CheckArgs,
+    }
+
+    #[test]
+    fn test_list_rules_without_workflow() {
+        let args = Check::try_parse_from(["check", "--list-rules"])
+            .unwrap()
+            .args;
+        assert_eq!(args.workflow, None);
+        let global_args = GlobalArgs {
+            quiet: true,
+            no_pager: true,
+            verbose: 0,
+        };
+        assert!(args.run(&global_args).is_ok());
+        assert!(Check::try_parse_from(["check"]).is_err());
+    }
+}
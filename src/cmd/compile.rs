@@ -0,0 +1,277 @@
+use crate::cmd::{GlobalArgs, RunCommand};
+use crate::runner::{Runner, WorkflowDelegate};
+use anyhow::bail;
+use clap::Args;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct CompileArgs {
+    /// The path to the workflow to compile
+    pub workflow: PathBuf,
+
+    /// The file the compiled workflow is written to, which `run` can run
+    #[arg(short, long, value_name = "FILE")]
+    pub output: PathBuf,
+
+    /// The additional arguments that will be passed along to the workflow
+    #[arg(allow_hyphen_values = true, trailing_var_arg = true)]
+    pub workflow_args: Vec<String>,
+}
+
+impl RunCommand for CompileArgs {
+    fn run(&self, global_args: &GlobalArgs) -> anyhow::Result<()> {
+        if !self.workflow.exists() {
+            bail!("Workflow does not exist at path {:?}", self.workflow);
+        }
+
+        let runner = Runner::new(
+            self.workflow.clone(),
+            WorkflowDelegate::with_args(self.workflow_args.clone()),
+        )?;
+        fs::write(&self.output, runner.compile()?.to_json()?)?;
+        if !global_args.quiet {
+            println!("Compiled {}", self.output.display());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::run::{run_workflow, RunOptions};
+    use crate::stdlib::ir::{ArgIr, NextIr, WorkflowIr};
+    use crate::stdlib::test_utils::TempWorkflowFile;
+
+    fn global_args() -> GlobalArgs {
+        GlobalArgs {
+            quiet: true,
+            no_pager: false,
+            verbose: 0,
+        }
+    }
+
+    #[test]
+    fn test_compile_and_run() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+marker = variable(default = "built", doc = "The file the build touches")
+profile = variable(default = "debug", cli_flag = "--profile")
+
+main = workflow(
+    entrypoint = "build",
+    graph = [
+        node(
+            name = "build",
+            action = action(tool = builtin_tool(name = "touch"), args = [marker], cwd = "."),
+            next = when(variable = profile, equals = "release", then = "package"),
+        ),
+        node(
+            name = "package",
+            action = action(tool = builtin_tool(name = "touch"), args = ["packaged"], cwd = "."),
+        ),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        let output = file.dir().join("out.json");
+        let args = CompileArgs {
+            workflow: file.path(),
+            output: output.clone(),
+            workflow_args: vec![],
+        };
+        args.run(&global_args()).unwrap();
+
+        let ir = WorkflowIr::from_json(&fs::read_to_string(&output).unwrap()).unwrap();
+        assert_eq!(ir.entrypoint, "build");
+        let names: Vec<&str> = ir.variables.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, ["marker", "profile"]);
+        assert_eq!(
+            ir.nodes[0].actions[0].args,
+            [ArgIr::Variable("marker".to_string())]
+        );
+        assert!(matches!(
+            &ir.nodes[0].next,
+            Some(NextIr::When { variable, then, .. }) if variable == "profile" && then == "package"
+        ));
+
+        let result = run_workflow(
+            &output,
+            &["--profile".to_string(), "release".to_string()],
+            RunOptions {
+                quiet: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(result.nodes.len(), 2);
+        assert!(file.dir().join("built").exists());
+        assert!(file.dir().join("packaged").exists());
+    }
+
+    #[test]
+    fn test_compile_plain_attributes() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+version = variable()
+name = variable()
+host = variable(required = True, prompt = "Which host?", cli_flag = "--host")
+
+main = workflow(
+    entrypoint = "read",
+    env_policy = env_policy(inherit = False, allow = ["PATH"], set = {"HOST": host}),
+    graph = [
+        node(
+            name = "read",
+            action = action(
+                tool = builtin_tool(name = "__not_a_tool__", aliases = ["sh"]),
+                args = ["-c", "echo 1.2 > VERSION; echo $HOST > HOST; exit 3"],
+                setters = [setter(variable = version, from_file = "VERSION")],
+                ok_exit_codes = [0, 3],
+                cwd = ".",
+            ),
+            next = when(variable = version, equals = "1.2", then = "json"),
+        ),
+        sequence(
+            name = "json",
+            actions = [
+                action(tool = builtin_tool(name = "echo"), args = ['{"name": "piped"}']),
+                action(
+                    tool = builtin_tool(name = "cat"),
+                    setters = [setter(variable = name, json_path = ".name")],
+                    quiet = True,
+                    retries = 1,
+                ),
+            ],
+            next = when(variable = name, equals = "piped", then = "write"),
+            timeout = 60,
+            retries = 1,
+            tags = ["json"],
+            pipe = True,
+        ),
+        node(
+            name = "write",
+            action = action(tool = builtin_tool(name = "sh"), args = ["-c", "echo $0 $1 > out", version, name], cwd = "."),
+        ),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        let output = file.dir().join("out.json");
+        let args = CompileArgs {
+            workflow: file.path(),
+            output: output.clone(),
+            workflow_args: vec![],
+        };
+        args.run(&global_args()).unwrap();
+
+        let ir = WorkflowIr::from_json(&fs::read_to_string(&output).unwrap()).unwrap();
+        let host = ir.variables.iter().find(|v| v.name == "host").unwrap();
+        assert_eq!(
+            (host.required, host.prompt.as_deref()),
+            (true, Some("Which host?"))
+        );
+        assert_eq!(ir.env_policy.as_ref().unwrap().allow, ["PATH"]);
+        let read = &ir.nodes[0].actions[0];
+        assert_eq!(read.ok_exit_codes, [0, 3]);
+        assert_eq!(
+            read.setters[0].from_file,
+            Some(ArgIr::Literal("VERSION".to_string()))
+        );
+        let json = &ir.nodes[1];
+        assert!(json.pipe);
+        assert_eq!(
+            (json.timeout, json.retries, &json.tags[..]),
+            (Some(60), 1, &["json".to_string()][..])
+        );
+        assert_eq!(
+            json.actions[1].setters[0].json_path.as_deref(),
+            Some(".name")
+        );
+        assert!(json.actions[1].quiet);
+
+        let result = run_workflow(
+            &output,
+            &["--host".to_string(), "example".to_string()],
+            RunOptions {
+                quiet: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(result.nodes.len(), 3);
+        let read = |name: &str| fs::read_to_string(file.dir().join(name)).unwrap();
+        assert_eq!(read("HOST"), "example\n");
+        assert_eq!(read("out"), "1.2 piped\n");
+    }
+
+    #[test]
+    fn test_setter_function_does_not_compile() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+version = variable()
+
+def _read(ctx):
+    return ctx.stdout
+
+main = workflow(
+    entrypoint = "a",
+    graph = [
+        node(
+            name = "a",
+            action = action(
+                tool = builtin_tool(name = "true"),
+                setters = [setter(implementation = _read, variable = version)],
+            ),
+        ),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        let args = CompileArgs {
+            workflow: file.path(),
+            output: file.dir().join("out.json"),
+            workflow_args: vec![],
+        };
+        assert_eq!(
+            format!("{:#}", args.run(&global_args()).unwrap_err()),
+            "node 'a' can not be compiled: the action running 'true' can not be compiled: setter '_read' is a function, only setters which read the output can be compiled"
+        );
+    }
+
+    #[test]
+    fn test_next_function_does_not_compile() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+def _next(ctx):
+    return None
+
+main = workflow(
+    entrypoint = "a",
+    graph = [
+        node(name = "a", action = action(tool = builtin_tool(name = "true")), next = next(implementation = _next)()),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        let args = CompileArgs {
+            workflow: file.path(),
+            output: file.dir().join("out.json"),
+            workflow_args: vec![],
+        };
+        assert_eq!(
+            args.run(&global_args()).unwrap_err().to_string(),
+            "node 'a' picks its next with a function, only when() can be compiled"
+        );
+        assert!(!file.dir().join("out.json").exists());
+    }
+}
@@ -1,24 +1,81 @@
+use crate::cmd::pager::Pager;
 use crate::cmd::{GlobalArgs, RunCommand};
 use crate::downcast_delegate_ref;
-use crate::runner::{Runner, WorkflowDelegate};
+use crate::runner::{
+    declared_graph, declared_graph_dot, declared_graph_mermaid, Runner, WorkflowDelegate,
+};
 use crate::stdlib::tool::Tool;
-use crate::stdlib::Action;
-use crate::stdlib::{VariableEntry, VariableRef};
-use ansi_term::Colour::{Cyan, Green, Red};
+use crate::stdlib::variable::{VariableScope, SECRET_MASK};
+use crate::stdlib::{Action, Node, Workflow};
+use crate::stdlib::{EnvMode, ValueUpdatedBy, VariableEntry, VariableRef};
+use ansi_term::Colour::{Cyan, Green, Purple, Red};
 use anyhow::bail;
-use clap::Args;
+use clap::{Args, ValueEnum};
+use regex::Regex;
+use serde_json::json;
 use starlark::environment::Module;
 use starlark::eval::Evaluator;
 use starlark::values::FrozenStringValue;
 use std::cmp;
+use std::io::{self, Write};
 use std::ops::Deref;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use terminal_size::{terminal_size, Width};
+
+/// The sections of the describe output.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Section {
+    Vars,
+    Tools,
+    Actions,
+    Graph,
+}
+
+/// How the description is written.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// Tables for reading in a terminal
+    Text,
+    /// A json document for editors and scripts
+    Json,
+}
+
+/// How `--graph` draws the graph.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum GraphFormat {
+    /// For Graphviz
+    Dot,
+    /// For markdown
+    Mermaid,
+}
 
 #[derive(Args, Debug)]
 pub struct DescribeArgs {
     /// The path to the workflow to describe
     pub workflow: PathBuf,
 
+    /// Only show the given sections, e.g. `--only vars,tools`
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub only: Vec<Section>,
+
+    /// Only show entries whose name matches the glob, e.g. `--name 'db_*'`
+    #[arg(long)]
+    pub name: Option<String>,
+
+    /// Writes the description as text or json
+    #[arg(long, value_enum, default_value = "text")]
+    pub format: Format,
+
+    /// Shows the value each source would give the variables, side by side,
+    /// and which of them is used
+    #[arg(long)]
+    pub sources: bool,
+
+    /// Only writes the graph of the workflow, with its nodes and the nodes
+    /// they can go to next, as DOT or mermaid
+    #[arg(long, value_name = "FORMAT", conflicts_with_all = ["format", "sources"])]
+    pub graph: Option<GraphFormat>,
+
     /// The additional arguments that will be passed along to the workflow
     #[arg(allow_hyphen_values = true, trailing_var_arg = true)]
     pub workflow_args: Vec<String>,
@@ -42,29 +99,149 @@ impl AlignedRecord {
         }
     }
 
-    fn display_with_size(&self, max_size: usize) -> String {
+    /// Displays the record with the key padded to `max_size`. The value is
+    /// wrapped so each line fits in `width` columns once it is printed after
+    /// the list marker, continuation lines are aligned under the value.
+    fn display_wrapped(&self, max_size: usize, width: usize) -> String {
+        let indent = LIST_MARKER.len() + max_size + " = ".len();
+        let lines = wrap_painted(&self.right, width.saturating_sub(indent));
         format!(
             "{}{} = {}",
             self.left,
             " ".repeat(max_size - self.size),
-            self.right
+            lines.join(&format!("\n{}", " ".repeat(indent)))
         )
     }
 }
 
-fn print_header(header: &str, width: usize) {
+const LIST_MARKER: &str = "  - ";
+const MIN_VALUE_WIDTH: usize = 20;
+const MAX_VALUE_LINES: usize = 4;
+const RESET: &str = "\x1b[0m";
+
+/// Returns the width of the terminal, falling back to $COLUMNS and then
+/// to 80 columns when stdout is not a terminal.
+fn terminal_width() -> usize {
+    if let Some((Width(width), _)) = terminal_size() {
+        return width as usize;
+    }
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|c| c.parse().ok())
+        .unwrap_or(80)
+}
+
+/// Wraps a value which is painted with a single colour into lines of at most
+/// `width` characters, painting each line. Values which need more than
+/// MAX_VALUE_LINES lines are truncated, with a last line saying how many
+/// lines were left out.
+fn wrap_painted(value: &str, width: usize) -> Vec<String> {
+    let width = width.max(MIN_VALUE_WIDTH);
+    let (prefix, text, suffix) = split_paint(value);
+    let chars: Vec<char> = text.chars().collect();
+    let mut lines: Vec<String> = chars
+        .chunks(width)
+        .map(|chunk| chunk.iter().collect())
+        .collect();
+    let hidden = lines.len().saturating_sub(MAX_VALUE_LINES);
+    lines.truncate(MAX_VALUE_LINES);
+    if lines.is_empty() {
+        lines.push(String::new());
+    }
+    let mut lines: Vec<String> = lines
+        .into_iter()
+        .map(|line| format!("{}{}{}", prefix, line, suffix))
+        .collect();
+    if hidden > 0 {
+        lines.push(format!(
+            "… {} more line{}",
+            hidden,
+            if hidden == 1 { "" } else { "s" }
+        ));
+    }
+    lines
+}
+
+/// Splits a painted value into the colour escape code, the text and the
+/// reset escape code.
+fn split_paint(value: &str) -> (&str, &str, &str) {
+    let prefix_len = match value.strip_prefix("\x1b[") {
+        Some(rest) => match rest.find('m') {
+            Some(end) => end + 3,
+            None => 0,
+        },
+        None => 0,
+    };
+    let suffix_len = if value[prefix_len..].ends_with(RESET) {
+        RESET.len()
+    } else {
+        0
+    };
+    (
+        &value[..prefix_len],
+        &value[prefix_len..value.len() - suffix_len],
+        &value[value.len() - suffix_len..],
+    )
+}
+
+fn print_records(out: &mut dyn Write, records: &[AlignedRecord], width: usize) -> io::Result<()> {
+    let mut max = 0;
+    for r in records {
+        max = cmp::max(max, r.size);
+    }
+
+    for record in records {
+        writeln!(out, "{}{}", LIST_MARKER, record.display_wrapped(max, width))?;
+    }
+
+    writeln!(out)
+}
+
+/// Matches names against a glob where `*` matches any run of characters
+/// and `?` matches a single character.
+#[derive(Debug)]
+struct NameFilter {
+    regex: Option<Regex>,
+}
+
+impl NameFilter {
+    fn new(glob: Option<&str>) -> anyhow::Result<Self> {
+        let regex = match glob {
+            Some(glob) => {
+                let pattern = regex::escape(glob).replace("\\*", ".*").replace("\\?", ".");
+                Some(Regex::new(&format!("^{}$", pattern))?)
+            }
+            None => None,
+        };
+        Ok(NameFilter { regex })
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        match &self.regex {
+            Some(regex) => regex.is_match(name),
+            None => true,
+        }
+    }
+}
+
+fn print_header(out: &mut dyn Write, header: &str, width: usize) -> io::Result<()> {
     let remaining_space = width - header.len() - 2; // 2 for the '=' on either end
 
     let left_spaces = " ".repeat(remaining_space / 2);
     let right_spaces = " ".repeat((remaining_space / 2) + remaining_space % 2);
     let mid_line = format!("={}{}{}=", &left_spaces, Green.paint(header), &right_spaces);
 
-    println!(
+    writeln!(
+        out,
         "\n{}\n{}\n{}\n",
         "=".repeat(width),
         mid_line,
         "=".repeat(width)
-    );
+    )
+}
+
+fn print_group_header(out: &mut dyn Write, group: &str) -> io::Result<()> {
+    writeln!(out, "{}\n", Purple.paint(format!("[{}]", group)))
 }
 
 fn format_optional_string(v: Option<String>) -> String {
@@ -98,13 +275,69 @@ fn format_bool(v: bool) -> String {
     )
 }
 
-fn print_variable_entry(name: &str, var: &VariableEntry) {
-    println!("{}: ", Cyan.paint(name.to_string()));
+/// Returns the name of the source shown next to the value it gives, e.g.
+/// the flag or the name of the env variable.
+fn source_name(source: &ValueUpdatedBy) -> String {
+    match source {
+        ValueUpdatedBy::CLIFlag(flag) => flag.clone(),
+        ValueUpdatedBy::EnvironmentVariable(key) => format!("${}", key),
+        ValueUpdatedBy::DefaultValue => "default".to_string(),
+        ValueUpdatedBy::Source(name) => format!("source {}", name),
+        other => other.to_string(),
+    }
+}
+
+/// Returns the value to show for the variable, masked if it is a secret.
+fn shown_value(var: &VariableEntry, value: Option<String>) -> Option<String> {
+    value.map(|value| match var.is_secret() {
+        true => SECRET_MASK.to_string(),
+        false => value,
+    })
+}
+
+/// Returns the value each source gives the variable along with whether it
+/// is the one used. A custom source which set the value is listed first.
+fn variable_sources(
+    var: &VariableEntry,
+    workflow_args: &[String],
+) -> Vec<(ValueUpdatedBy, Option<String>, bool)> {
+    let used = var.value_ctx().map(|ctx| ctx.updated_by);
+    let mut sources: Vec<_> = var
+        .source_values(workflow_args)
+        .into_iter()
+        .map(|(source, value)| {
+            let is_used = used.as_ref() == Some(&source);
+            (source, shown_value(var, value), is_used)
+        })
+        .collect();
+    if let Some(source @ ValueUpdatedBy::Source(_)) = used {
+        sources.insert(0, (source, shown_value(var, var.value()), true));
+    }
+    sources
+}
+
+fn print_variable_entry(
+    out: &mut dyn Write,
+    name: &str,
+    var: &VariableEntry,
+    sources: Option<&[String]>,
+    width: usize,
+) -> io::Result<()> {
+    writeln!(out, "{}: ", Cyan.paint(name.to_string()))?;
     let value_ctx = var.value_ctx();
 
-    let records = vec![
-        AlignedRecord::new("env", format_optional_string(var.env())),
+    let mut records = vec![
+        AlignedRecord::new("doc", format_optional_string(var.doc())),
+        AlignedRecord::new(
+            "env",
+            format_optional_string(var.env().map(|env| match var.env_mode() {
+                EnvMode::Live => format!("{} (live)", env),
+                EnvMode::Snapshot => env,
+            })),
+        ),
         AlignedRecord::new("cli_flag", format_optional_string(var.cli_flag())),
+        AlignedRecord::new("--var", format_optional_string(var.qualified_name())),
+        AlignedRecord::new("deprecated", format_optional_string(var.deprecated())),
         AlignedRecord::new(
             "readers",
             format!("{}", Green.paint(format!("{}", var.readers()))),
@@ -115,7 +348,7 @@ fn print_variable_entry(name: &str, var: &VariableEntry) {
         ),
         AlignedRecord::new(
             "value",
-            format_optional_string(value_ctx.clone().map(|v| v.value)),
+            format_optional_string(shown_value(var, var.value())),
         ),
         AlignedRecord::new(
             "context",
@@ -125,23 +358,34 @@ fn print_variable_entry(name: &str, var: &VariableEntry) {
             },
         ),
     ];
-    let mut max = 0;
-    for r in &records {
-        max = cmp::max(max, r.size);
-    }
-
-    for record in &records {
-        println!("  - {}", record.display_with_size(max));
+    if let Some(workflow_args) = sources {
+        for (source, value, used) in variable_sources(var, workflow_args) {
+            let value = match (value, used) {
+                (Some(value), true) => Some(format!("{} (used)", value)),
+                (value, _) => value,
+            };
+            records.push(AlignedRecord::new(
+                source_name(&source),
+                format_optional_string(value),
+            ));
+        }
     }
-
-    println!("");
+    print_records(out, &records, width)
 }
 
-fn print_tool(name: &str, tool: &Tool, delegate: &WorkflowDelegate, working_dir: &PathBuf) {
-    println!("{}: ", Cyan.paint(name.to_string()));
+fn print_tool(
+    out: &mut dyn Write,
+    name: &str,
+    tool: &Tool,
+    delegate: &WorkflowDelegate,
+    working_dir: &PathBuf,
+    width: usize,
+) -> io::Result<()> {
+    writeln!(out, "{}: ", Cyan.paint(name.to_string()))?;
 
     let records = vec![
         AlignedRecord::new("is builtin", format_bool(tool.is_builtin())),
+        AlignedRecord::new("is native", format_bool(tool.is_native())),
         AlignedRecord::new(
             "path",
             format_result(
@@ -157,20 +401,18 @@ fn print_tool(name: &str, tool: &Tool, delegate: &WorkflowDelegate, working_dir:
             ),
         ),
     ];
-    let mut max = 0;
-    for r in &records {
-        max = cmp::max(max, r.size);
-    }
-
-    for record in &records {
-        println!("  - {}", record.display_with_size(max));
-    }
-
-    println!("");
+    print_records(out, &records, width)
 }
 
-fn print_action(name: &str, action: &Action, delegate: &WorkflowDelegate, working_dir: &PathBuf) {
-    println!("{}: ", Cyan.paint(name.to_string()));
+fn print_action(
+    out: &mut dyn Write,
+    name: &str,
+    action: &Action,
+    delegate: &WorkflowDelegate,
+    working_dir: &PathBuf,
+    width: usize,
+) -> io::Result<()> {
+    writeln!(out, "{}: ", Cyan.paint(name.to_string()))?;
     let records = vec![
         AlignedRecord::new(
             "program",
@@ -182,26 +424,361 @@ fn print_action(name: &str, action: &Action, delegate: &WorkflowDelegate, workin
         ),
         AlignedRecord::new(
             "args",
-            format_result(action.arg_list(delegate).map(|l| format!("{:?}", l))),
+            format_result(
+                action
+                    .arg_list(delegate, working_dir)
+                    .map(|l| format!("{:?}", l)),
+            ),
+        ),
+        AlignedRecord::new(
+            "cwd",
+            format_result(effective_cwd(action, delegate, working_dir)),
         ),
     ];
-    let mut max = 0;
-    for r in &records {
-        max = cmp::max(max, r.size);
+    print_records(out, &records, width)
+}
+
+/// Returns the directory the action's tool runs in, the one `workflow` was
+/// started in unless the action sets a cwd.
+fn effective_cwd(
+    action: &Action,
+    delegate: &WorkflowDelegate,
+    working_dir: &Path,
+) -> anyhow::Result<String> {
+    let cwd = match action.cwd(delegate, working_dir)? {
+        Some(cwd) => cwd,
+        None => std::env::current_dir()?,
+    };
+    Ok(cwd.display().to_string())
+}
+
+fn print_node(out: &mut dyn Write, node: &Node, width: usize) -> io::Result<()> {
+    writeln!(out, "{}: ", Cyan.paint(node.name().to_string()))?;
+    let records = vec![
+        AlignedRecord::new(
+            "actions",
+            format!("{}", Green.paint(node.action_count().to_string())),
+        ),
+        AlignedRecord::new("has next", format_bool(node.has_next())),
+        AlignedRecord::new(
+            "requires_lock",
+            format!("{}", Green.paint(format!("{:?}", node.locks()))),
+        ),
+        AlignedRecord::new(
+            "timeout",
+            format_optional_string(node.timeout().map(|t| format!("{}s", t.as_secs()))),
+        ),
+        AlignedRecord::new(
+            "retries",
+            format!("{}", Green.paint(node.retries().to_string())),
+        ),
+    ];
+    print_records(out, &records, width)
+}
+
+/// Returns the value as json, or an object with the error which stopped it
+/// from being resolved.
+fn json_result<T: serde::Serialize>(v: anyhow::Result<T>) -> serde_json::Value {
+    match v {
+        Ok(v) => json!(v),
+        Err(e) => json!({ "error": format!("{:#}", e) }),
+    }
+}
+
+/// Returns the names of the actions in the scope, null if every action is.
+fn scope_json(scope: VariableScope) -> serde_json::Value {
+    match scope {
+        VariableScope::Global => serde_json::Value::Null,
+        VariableScope::Restricted(names) => json!(names),
     }
+}
+
+fn variable_json(
+    name: &str,
+    id: &str,
+    var: &VariableEntry,
+    sources: Option<&[String]>,
+) -> serde_json::Value {
+    let value_ctx = var.value_ctx();
+    let mut entry = json!({
+        "name": name,
+        "id": id,
+        "const": var.is_const(),
+        "group": var.group(),
+        "doc": var.doc(),
+        "env": var.env(),
+        "env_mode": var.env().map(|_| var.env_mode().to_string()),
+        "cli_flag": var.cli_flag(),
+        "var": var.qualified_name(),
+        "deprecated": var.deprecated(),
+        "readers": scope_json(var.readers()),
+        "writers": scope_json(var.writers()),
+        "secret": var.is_secret(),
+        "required": var.is_required(),
+        "default": shown_value(var, var.default_value()),
+        "value": shown_value(var, var.value()),
+        "provenance": value_ctx.as_ref().map(|v| &v.updated_by),
+    });
+    if let Some(workflow_args) = sources {
+        let sources: Vec<_> = variable_sources(var, workflow_args)
+            .into_iter()
+            .map(|(source, value, used)| {
+                json!({
+                    "source": source,
+                    "name": source_name(&source),
+                    "value": value,
+                    "used": used,
+                })
+            })
+            .collect();
+        entry["sources"] = json!(sources);
+    }
+    entry
+}
+
+fn tool_json(
+    name: &str,
+    tool: &Tool,
+    delegate: &WorkflowDelegate,
+    working_dir: &PathBuf,
+) -> serde_json::Value {
+    json!({
+        "name": name,
+        "builtin": tool.is_builtin(),
+        "native": tool.is_native(),
+        "path": json_result(tool.path(delegate, working_dir)),
+        "real_path": json_result(tool.real_path(delegate, working_dir)),
+    })
+}
+
+fn action_json(
+    name: &str,
+    action: &Action,
+    delegate: &WorkflowDelegate,
+    working_dir: &PathBuf,
+) -> serde_json::Value {
+    json!({
+        "name": name,
+        "program": json_result(
+            action
+                .command(delegate, working_dir)
+                .map(|c| c.get_program().to_string_lossy().into_owned())
+        ),
+        "args": json_result(action.arg_list(delegate, working_dir)),
+        "cwd": json_result(effective_cwd(action, delegate, working_dir)),
+    })
+}
+
+fn node_json(node: &Node) -> serde_json::Value {
+    json!({
+        "name": node.name(),
+        "actions": node.action_count(),
+        "graph": node.graph().iter().map(|n| n.name()).collect::<Vec<_>>(),
+        "next": node.next_name().or_else(|| node.when().map(|when| when.to_string())),
+        // null when a next function picks the node
+        "targets": match (node.when(), node.has_next()) {
+            (Some(when), _) => json!(when.targets()),
+            (None, true) => serde_json::Value::Null,
+            (None, false) => json!([]),
+        },
+        "requires_lock": node.locks(),
+        "timeout_secs": node.timeout().map(|t| t.as_secs()),
+        "retries": node.retries(),
+        "tags": node.tags(),
+        "artifacts": node.artifacts(),
+    })
+}
 
-    for record in &records {
-        println!("  - {}", record.display_with_size(max));
+impl DescribeArgs {
+    fn shows(&self, section: Section) -> bool {
+        self.only.is_empty() || self.only.contains(&section)
     }
+}
 
-    println!("");
+/// What a workflow defines, by the names it is bound to.
+struct Entries<'v> {
+    consts: Vec<(FrozenStringValue, &'v VariableRef)>,
+    vars: Vec<(FrozenStringValue, &'v VariableRef)>,
+    tools: Vec<(FrozenStringValue, &'v Tool<'v>)>,
+    actions: Vec<(FrozenStringValue, &'v Action<'v>)>,
+    workflows: Vec<&'v Workflow<'v>>,
+}
+
+/// Collects the entries of the module whose names match the filter, the
+/// workflows are collected whatever their name.
+fn collect_entries<'v>(
+    module: &'v Module,
+    delegate: &WorkflowDelegate,
+    filter: &NameFilter,
+) -> Entries<'v> {
+    let mut vars: Vec<(FrozenStringValue, &VariableRef)> = Vec::new();
+    let mut tools: Vec<(FrozenStringValue, &Tool)> = Vec::new();
+    let mut actions: Vec<(FrozenStringValue, &Action)> = Vec::new();
+    let mut workflows: Vec<&Workflow> = Vec::new();
+
+    let names = module.names();
+    for name in names {
+        if let Some(value) = module.get(&name) {
+            if let Some(entry) = Workflow::from_value(value) {
+                workflows.push(entry);
+            } else if !filter.matches(&name) {
+                continue;
+            } else if let Some(entry) = VariableRef::from_value(value) {
+                vars.push((name, entry));
+            } else if let Some(entry) = Tool::from_value(value) {
+                tools.push((name, entry));
+            } else if let Some(entry) = Action::from_value(value) {
+                actions.push((name, entry));
+            }
+        }
+    }
+
+    // consts are listed on their own
+    let (consts, vars): (Vec<_>, Vec<_>) = vars.into_iter().partition(|(_, var)| {
+        let mut is_const = false;
+        delegate
+            .variable_store()
+            .with_variable(var.identifier(), |v| is_const = v.is_const());
+        is_const
+    });
+    Entries {
+        consts,
+        vars,
+        tools,
+        actions,
+        workflows,
+    }
+}
+
+/// Parses the workflow and returns its description as json, with the
+/// sections `shows` picks and the entries whose name matches `name`. The
+/// value each source would give the variables is included if `sources`.
+pub(crate) fn describe_json(
+    workflow: &Path,
+    workflow_args: &[String],
+    name: Option<&str>,
+    sources: bool,
+    shows: &dyn Fn(Section) -> bool,
+) -> anyhow::Result<serde_json::Value> {
+    let runner = Runner::new(
+        workflow.to_path_buf(),
+        WorkflowDelegate::with_args(workflow_args.to_vec()),
+    )?;
+    let module: Module = Module::new();
+    let mut eval: Evaluator = Evaluator::new(&module);
+    runner.parse_workflow(&mut eval)?;
+
+    let holder = runner.delegate();
+    let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+    let working_dir = runner.working_dir();
+    let filter = NameFilter::new(name)?;
+    let sources = sources.then_some(workflow_args);
+    let entries = collect_entries(&module, delegate, &filter);
+
+    let mut description = serde_json::Map::new();
+    description.insert("workflow".to_string(), json!(workflow));
+    if shows(Section::Vars) {
+        let mut vars = vec![];
+        for (name, var) in entries.consts.iter().chain(&entries.vars) {
+            delegate
+                .variable_store()
+                .with_variable(var.identifier(), |v| {
+                    vars.push(variable_json(name, var.identifier(), v, sources))
+                });
+        }
+        description.insert("variables".to_string(), json!(vars));
+    }
+    if shows(Section::Tools) {
+        let tools: Vec<_> = entries
+            .tools
+            .iter()
+            .map(|(name, tool)| tool_json(name, tool, delegate, &working_dir))
+            .collect();
+        description.insert("tools".to_string(), json!(tools));
+    }
+    if shows(Section::Actions) {
+        let actions: Vec<_> = entries
+            .actions
+            .iter()
+            .map(|(name, action)| action_json(name, action, delegate, &working_dir))
+            .collect();
+        description.insert("actions".to_string(), json!(actions));
+    }
+    if shows(Section::Graph) {
+        let graphs: Vec<_> = entries
+            .workflows
+            .iter()
+            .map(|workflow| {
+                let nodes: Vec<_> = workflow
+                    .nodes()
+                    .into_iter()
+                    .filter(|node| filter.matches(node.name()))
+                    .map(node_json)
+                    .collect();
+                json!({
+                    "entrypoint": workflow.first_node().ok().map(|n| n.name()),
+                    "env_policy": workflow
+                        .env_policy()
+                        .map(|policy| json_result(policy.describe(delegate))),
+                    "nodes": nodes,
+                })
+            })
+            .collect();
+        description.insert("graph".to_string(), json!(graphs));
+    }
+    Ok(serde_json::Value::Object(description))
+}
+
+/// Parses the workflow and returns the graph of each workflow it defines
+/// as declared, in the format.
+pub(crate) fn describe_graph(
+    workflow: &Path,
+    workflow_args: &[String],
+    format: GraphFormat,
+) -> anyhow::Result<String> {
+    let runner = Runner::new(
+        workflow.to_path_buf(),
+        WorkflowDelegate::with_args(workflow_args.to_vec()),
+    )?;
+    let module: Module = Module::new();
+    let mut eval: Evaluator = Evaluator::new(&module);
+    runner.parse_workflow(&mut eval)?;
+
+    let holder = runner.delegate();
+    let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+    let entries = collect_entries(&module, delegate, &NameFilter::new(None)?);
+    let mut out = String::new();
+    for workflow in entries.workflows {
+        let nodes = declared_graph(workflow);
+        out.push_str(&match format {
+            GraphFormat::Dot => declared_graph_dot(&nodes),
+            GraphFormat::Mermaid => declared_graph_mermaid(&nodes),
+        });
+    }
+    Ok(out)
 }
 
 impl RunCommand for DescribeArgs {
-    fn run(&self, _global_args: &GlobalArgs) -> anyhow::Result<()> {
+    fn run(&self, global_args: &GlobalArgs) -> anyhow::Result<()> {
         if self.workflow.exists() {
-            let column_width = 80;
-            println!("Parsing workflow at {:?}", self.workflow);
+            if let Some(format) = self.graph {
+                print!(
+                    "{}",
+                    describe_graph(&self.workflow, &self.workflow_args, format)?
+                );
+                return Ok(());
+            }
+            if self.format == Format::Json {
+                let description = describe_json(
+                    &self.workflow,
+                    &self.workflow_args,
+                    self.name.as_deref(),
+                    self.sources,
+                    &|section| self.shows(section),
+                )?;
+                println!("{:#}", description);
+                return Ok(());
+            }
 
             let runner = Runner::new(
                 self.workflow.clone(),
@@ -210,50 +787,317 @@ impl RunCommand for DescribeArgs {
             let module: Module = Module::new();
             let mut eval: Evaluator = Evaluator::new(&module);
 
-            let _result = runner.parse_workflow(&mut eval).unwrap();
+            runner.parse_workflow(&mut eval)?;
 
             let holder = runner.delegate();
             let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
             let working_dir = runner.working_dir();
 
-            let mut vars: Vec<(FrozenStringValue, &VariableRef)> = Vec::new();
-            let mut tools: Vec<(FrozenStringValue, &Tool)> = Vec::new();
-            let mut actions: Vec<(FrozenStringValue, &Action)> = Vec::new();
-
-            let names = module.names();
-            for name in names {
-                if let Some(value) = module.get(&name) {
-                    if let Some(entry) = VariableRef::from_value(value) {
-                        vars.push((name, entry));
-                    } else if let Some(entry) = Tool::from_value(value) {
-                        tools.push((name, entry));
-                    } else if let Some(entry) = Action::from_value(value) {
-                        actions.push((name, entry));
+            let filter = NameFilter::new(self.name.as_deref())?;
+            let sources = self.sources.then_some(self.workflow_args.as_slice());
+            let Entries {
+                consts,
+                vars,
+                tools,
+                actions,
+                workflows,
+            } = collect_entries(&module, delegate, &filter);
+
+            let column_width = terminal_width().max(40);
+            let mut pager = Pager::start(global_args.no_pager);
+            let out: &mut dyn Write = &mut pager;
+            writeln!(out, "Parsing workflow at {:?}", self.workflow)?;
+
+            if self.shows(Section::Vars) && !consts.is_empty() {
+                print_header(out, "Consts", column_width)?;
+                let mut records = vec![];
+                for (name, var) in consts {
+                    delegate
+                        .variable_store()
+                        .with_variable(var.identifier(), |v| {
+                            records.push(AlignedRecord::new(
+                                name.as_str(),
+                                format_optional_string(v.value()),
+                            ))
+                        });
+                }
+                print_records(out, &records, column_width)?;
+            }
+
+            if self.shows(Section::Vars) {
+                print_header(out, "Variables", column_width)?;
+                // grouped variables are listed after the ungrouped ones,
+                // under a heading for each group
+                let mut grouped: Vec<(Option<String>, FrozenStringValue, &VariableRef)> = vars
+                    .into_iter()
+                    .map(|(name, var)| {
+                        let mut group = None;
+                        delegate
+                            .variable_store()
+                            .with_variable(var.identifier(), |v| group = v.group());
+                        (group, name, var)
+                    })
+                    .collect();
+                grouped.sort_by(|a, b| a.0.cmp(&b.0));
+
+                let mut current_group = None;
+                for (group, name, var) in grouped {
+                    if group.is_some() && group != current_group {
+                        print_group_header(out, group.as_deref().unwrap_or_default())?;
+                        current_group = group;
                     }
+                    let mut result = Ok(());
+                    delegate
+                        .variable_store()
+                        .with_variable(var.identifier(), |v| {
+                            result = print_variable_entry(out, &name, v, sources, column_width);
+                        });
+                    result?;
                 }
             }
 
-            print_header("Variables", column_width);
-            for (name, var) in vars {
-                delegate
-                    .variable_store()
-                    .with_variable(var.identifier(), |v| {
-                        print_variable_entry(&name, v);
-                    });
+            if self.shows(Section::Tools) {
+                print_header(out, "Tools", column_width)?;
+                for (name, tool) in tools {
+                    print_tool(out, &name, &tool, &delegate, &working_dir, column_width)?;
+                }
             }
 
-            print_header("Tools", column_width);
-            for (name, tool) in tools {
-                print_tool(&name, &tool, &delegate, &working_dir);
+            if self.shows(Section::Actions) {
+                print_header(out, "Actions", column_width)?;
+                for (name, action) in actions {
+                    print_action(out, &name, &action, &delegate, &working_dir, column_width)?;
+                }
             }
 
-            print_header("Actions", column_width);
-            for (name, action) in actions {
-                print_action(&name, &action, &delegate, &working_dir);
+            if self.shows(Section::Graph) {
+                print_header(out, "Graph", column_width)?;
+                for workflow in workflows {
+                    if let Some(policy) = workflow.env_policy() {
+                        let records = vec![AlignedRecord::new(
+                            "env_policy",
+                            format_result(policy.describe(delegate)),
+                        )];
+                        print_records(out, &records, column_width)?;
+                    }
+                    for node in workflow.nodes() {
+                        if filter.matches(node.name()) {
+                            print_node(out, node, column_width)?;
+                        }
+                    }
+                }
             }
+            pager.finish()?;
         } else {
             bail!("Workflow does not exist at path {:?}", self.workflow);
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib::test_utils::TempWorkflowFile;
+
+    #[test]
+    fn test_name_filter() {
+        let filter = NameFilter::new(Some("db_*")).unwrap();
+        assert!(filter.matches("db_host"));
+        assert!(filter.matches("db_"));
+        assert!(!filter.matches("my_db_host"));
+
+        let filter = NameFilter::new(Some("v?.tool")).unwrap();
+        assert!(filter.matches("v1.tool"));
+        assert!(!filter.matches("v10.tool"));
+        assert!(!filter.matches("v1xtool"));
+
+        assert!(NameFilter::new(None).unwrap().matches("anything"));
+    }
+
+    #[test]
+    fn test_wrap_painted_plain() {
+        assert_eq!(wrap_painted("short", 80), vec!["short"]);
+        assert_eq!(wrap_painted("", 80), vec![""]);
+        let long = "a".repeat(45);
+        assert_eq!(
+            wrap_painted(&long, 20),
+            vec!["a".repeat(20), "a".repeat(20), "a".repeat(5)]
+        );
+    }
+
+    #[test]
+    fn test_wrap_painted_keeps_colour_per_line() {
+        let value = format!("{}", Green.paint("b".repeat(30)));
+        let lines = wrap_painted(&value, 20);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], format!("{}", Green.paint("b".repeat(20))));
+        assert_eq!(lines[1], format!("{}", Green.paint("b".repeat(10))));
+    }
+
+    #[test]
+    fn test_wrap_painted_truncates() {
+        let lines = wrap_painted(&"c".repeat(200), 20);
+        assert_eq!(lines.len(), MAX_VALUE_LINES + 1);
+        assert_eq!(lines[3], "c".repeat(20));
+        assert_eq!(lines[4], "… 6 more lines");
+
+        let lines = wrap_painted(&"c".repeat(100), 20);
+        assert_eq!(lines[4], "… 1 more line");
+    }
+
+    #[test]
+    fn test_display_wrapped_aligns_continuation_lines() {
+        let record = AlignedRecord::new("path", "d".repeat(30));
+        assert_eq!(
+            record.display_wrapped(6, 33),
+            format!(
+                "path   = {}\n{}{}",
+                "d".repeat(20),
+                " ".repeat(13),
+                "d".repeat(10)
+            )
+        );
+    }
+
+    #[test]
+    fn test_variable_json() {
+        let mut var = VariableEntry::for_test(Some("abc"), Some("name"), Some("NAME"));
+        var.update_value("def", ValueUpdatedBy::CLIFlag("name".to_string()));
+        assert_eq!(
+            variable_json("name", "id-1", &var, None),
+            json!({
+                "name": "name",
+                "id": "id-1",
+                "const": false,
+                "group": null,
+                "doc": null,
+                "env": "NAME",
+                "env_mode": "snapshot",
+                "cli_flag": "name",
+                "var": null,
+                "deprecated": null,
+                "readers": null,
+                "writers": null,
+                "secret": false,
+                "required": false,
+                "default": "abc",
+                "value": "def",
+                "provenance": {"CLIFlag": "name"},
+            })
+        );
+        assert_eq!(
+            scope_json(VariableScope::Restricted(vec!["a".to_string()])),
+            json!(["a"])
+        );
+        assert_eq!(
+            json_result::<String>(Err(anyhow::anyhow!("no value"))),
+            json!({ "error": "no value" })
+        );
+    }
+
+    #[test]
+    fn test_variable_sources() {
+        let mut var = VariableEntry::for_test(Some("abc"), Some("--name"), None);
+        var.set_name("name");
+        let args = vec!["--name".to_string(), "def".to_string()];
+        var.try_update_value_from_cli_flag(&args).unwrap();
+
+        let entry = variable_json("name", "id-1", &var, Some(&args));
+        assert_eq!(
+            entry["sources"],
+            json!([
+                {"source": {"CLIFlag": "--var name"}, "name": "--var name", "value": null, "used": false},
+                {"source": {"CLIFlag": "--name"}, "name": "--name", "value": "def", "used": true},
+                {"source": "DefaultValue", "name": "default", "value": "abc", "used": false},
+            ])
+        );
+
+        var.update_value("ghi", ValueUpdatedBy::Source("vault".to_string()));
+        let sources = variable_sources(&var, &[]);
+        assert_eq!(
+            sources[0],
+            (
+                ValueUpdatedBy::Source("vault".to_string()),
+                Some("ghi".to_string()),
+                true
+            )
+        );
+        assert_eq!(source_name(&sources[0].0), "source vault");
+    }
+
+    #[test]
+    fn test_secret_is_masked() {
+        let mut var = VariableEntry::for_test(None, Some("--token"), None).into_secret();
+        let args = vec!["--token".to_string(), "hunter2".to_string()];
+        var.try_update_value_from_cli_flag(&args).unwrap();
+
+        let entry = variable_json("token", "id-1", &var, Some(&args));
+        assert_eq!(entry["secret"], json!(true));
+        assert_eq!(entry["value"], json!(SECRET_MASK));
+        assert_eq!(entry["sources"][0]["value"], json!(SECRET_MASK));
+        assert!(!entry.to_string().contains("hunter2"));
+    }
+
+    #[test]
+    fn test_describe_graph() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+profile = variable(default = "debug")
+cargo = builtin_tool(name = "cargo")
+
+def _pick(ctx):
+    return None
+
+main = workflow(
+    entrypoint = "build",
+    graph = [
+        node(name = "deploy", graph = [node(name = "push", action = action(tool = cargo))]),
+        node(
+            name = "build",
+            action = action(tool = cargo),
+            next = when(variable = profile, equals = "release", then = "deploy"),
+        ),
+        sequence(name = "test", actions = [action(tool = cargo)], next = next(implementation = _pick)()),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        let dot = describe_graph(&file.path(), &[], GraphFormat::Dot).unwrap();
+        assert!(dot.starts_with("digraph workflow {\n  start [shape=point];\n  start -> n0;\n"));
+        assert!(dot.contains("  n0 [label=\"build\\n1 action\", shape=box];\n"));
+        assert!(dot.contains("  n1 [label=\"deploy\", shape=box3d];\n"));
+        assert!(dot.contains("    n1_0 [label=\"push\\n1 action\", shape=box];\n"));
+        assert!(dot.contains("  n0 -> n1 [label=\"== \\\"release\\\"\"];\n"));
+        assert!(dot.contains("  n2 -> n2_next [style=dashed, label=\"_pick\"];\n"));
+
+        let mermaid = describe_graph(&file.path(), &[], GraphFormat::Mermaid).unwrap();
+        assert!(mermaid.starts_with("flowchart TD\n  start((start)) --> n0\n"));
+        assert!(mermaid.contains("  n1 -.->|runs| n1_0\n"));
+    }
+
+    #[test]
+    fn test_only_sections() {
+        let args = DescribeArgs {
+            workflow: PathBuf::new(),
+            only: vec![],
+            name: None,
+            format: Format::Text,
+            sources: false,
+            graph: None,
+            workflow_args: vec![],
+        };
+        assert!(args.shows(Section::Vars));
+        assert!(args.shows(Section::Graph));
+
+        let args = DescribeArgs {
+            only: vec![Section::Vars, Section::Graph],
+            ..args
+        };
+        assert!(args.shows(Section::Vars));
+        assert!(!args.shows(Section::Tools));
+        assert!(args.shows(Section::Graph));
+    }
+}
@@ -0,0 +1,400 @@
+use crate::cmd::describe::describe_json;
+use crate::cmd::{GlobalArgs, RunCommand};
+use anyhow::bail;
+use clap::{Args, ValueEnum};
+use serde_json::Value;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+
+/// How the documentation is written.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum DocFormat {
+    Markdown,
+    Html,
+}
+
+#[derive(Args, Debug)]
+pub struct DocgenArgs {
+    /// The path to the workflow to document
+    pub workflow: PathBuf,
+
+    /// Writes the documentation as markdown or html
+    #[arg(long, value_enum, default_value = "markdown")]
+    pub format: DocFormat,
+
+    /// The file the documentation is written to, stdout if not given
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    /// The additional arguments that will be passed along to the workflow
+    #[arg(allow_hyphen_values = true, trailing_var_arg = true)]
+    pub workflow_args: Vec<String>,
+}
+
+/// A section of the documentation, laid out as a table.
+#[derive(Debug, PartialEq)]
+struct Table {
+    title: String,
+    headers: &'static [&'static str],
+    rows: Vec<Vec<String>>,
+}
+
+/// The documentation of a workflow, independent of how it is written.
+#[derive(Debug, PartialEq)]
+struct Doc {
+    title: String,
+    tables: Vec<Table>,
+    /// The edges of each graph, a None target is picked by a next function
+    /// when the workflow runs.
+    graphs: Vec<Vec<(String, Option<String>)>>,
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(values) => values.iter().map(text).collect::<Vec<_>>().join(", "),
+        Value::Object(object) => match object.get("error") {
+            Some(error) => format!("error: {}", text(error)),
+            None => value.to_string(),
+        },
+        other => other.to_string(),
+    }
+}
+
+/// Returns the actions a variable's scope allows, all of them if it is not
+/// restricted.
+fn scope(value: &Value) -> String {
+    match value {
+        Value::Null => "all".to_string(),
+        value => text(value),
+    }
+}
+
+fn variable_row(var: &Value) -> Vec<String> {
+    let flag = match (&var["cli_flag"], &var["var"]) {
+        (Value::String(flag), _) => flag.clone(),
+        (_, Value::String(name)) => format!("--var {}=<VALUE>", name),
+        _ => String::new(),
+    };
+    let mut notes = vec![];
+    for (field, note) in [
+        ("const", "const"),
+        ("required", "required"),
+        ("secret", "secret"),
+    ] {
+        if var[field] == Value::Bool(true) {
+            notes.push(note.to_string());
+        }
+    }
+    if let Value::String(deprecated) = &var["deprecated"] {
+        notes.push(format!("deprecated: {}", deprecated));
+    }
+    vec![
+        text(&var["name"]),
+        text(&var["group"]),
+        flag,
+        text(&var["env"]),
+        text(&var["default"]),
+        format!(
+            "read by {}, written by {}",
+            scope(&var["readers"]),
+            scope(&var["writers"])
+        ),
+        text(&var["doc"]),
+        notes.join(", "),
+    ]
+}
+
+fn tool_row(tool: &Value) -> Vec<String> {
+    let kind = match (&tool["builtin"], &tool["native"]) {
+        (Value::Bool(true), _) => "builtin",
+        (_, Value::Bool(true)) => "native",
+        _ => "path",
+    };
+    vec![text(&tool["name"]), kind.to_string(), text(&tool["path"])]
+}
+
+fn node_row(node: &Value) -> Vec<String> {
+    let actions = match node["graph"].as_array() {
+        Some(graph) if !graph.is_empty() => format!("graph of {}", text(&node["graph"])),
+        _ => text(&node["actions"]),
+    };
+    vec![
+        text(&node["name"]),
+        actions,
+        text(&node["next"]),
+        text(&node["tags"]),
+    ]
+}
+
+impl Doc {
+    /// Builds the documentation from the json description of the workflow.
+    fn from_description(title: String, description: &Value) -> Self {
+        let rows = |section: &str, row: fn(&Value) -> Vec<String>| {
+            description[section]
+                .as_array()
+                .map(|entries| entries.iter().map(row).collect())
+                .unwrap_or_default()
+        };
+        let mut tables = vec![
+            Table {
+                title: "Variables".to_string(),
+                headers: &[
+                    "Name", "Group", "Flag", "Env", "Default", "Scope", "Doc", "Notes",
+                ],
+                rows: rows("variables", variable_row),
+            },
+            Table {
+                title: "Tools".to_string(),
+                headers: &["Name", "Kind", "Path"],
+                rows: rows("tools", tool_row),
+            },
+        ];
+        let mut graphs = vec![];
+        for graph in description["graph"].as_array().into_iter().flatten() {
+            let nodes = graph["nodes"].as_array().cloned().unwrap_or_default();
+            tables.push(Table {
+                title: format!("Nodes, starting at {}", text(&graph["entrypoint"])),
+                headers: &["Node", "Actions", "Next", "Tags"],
+                rows: nodes.iter().map(node_row).collect(),
+            });
+            let mut edges = vec![];
+            for node in &nodes {
+                let name = text(&node["name"]);
+                match node["targets"].as_array() {
+                    Some(targets) => {
+                        edges.extend(targets.iter().map(|t| (name.clone(), Some(text(t)))))
+                    }
+                    None => edges.push((name, None)),
+                }
+            }
+            graphs.push(edges);
+        }
+        Doc {
+            title,
+            tables,
+            graphs,
+        }
+    }
+
+    /// Renders the edges as a mermaid flowchart, the nodes get ids by the
+    /// order they first appear in as names are not valid ids.
+    fn mermaid(edges: &[(String, Option<String>)]) -> String {
+        let mut names: Vec<&str> = vec![];
+        for (from, to) in edges {
+            for name in std::iter::once(from).chain(to) {
+                if !names.contains(&name.as_str()) {
+                    names.push(name);
+                }
+            }
+        }
+        let id = |name: &str| names.iter().position(|n| *n == name).unwrap_or(0);
+        let mut out = String::from("flowchart TD\n");
+        for (index, name) in names.iter().enumerate() {
+            let _ = writeln!(out, "  n{}[\"{}\"]", index, name.replace('"', "#quot;"));
+        }
+        for (from, to) in edges {
+            let _ = match to {
+                Some(to) => writeln!(out, "  n{} --> n{}", id(from), id(to)),
+                None => writeln!(
+                    out,
+                    "  n{0} -. picked at run time .-> n{0}_next((?))",
+                    id(from)
+                ),
+            };
+        }
+        out
+    }
+
+    fn to_markdown(&self) -> String {
+        let cell = |text: &str| text.replace('|', "\\|").replace('\n', "<br>");
+        let mut out = format!("# {}\n", self.title);
+        for table in &self.tables {
+            let _ = write!(out, "\n## {}\n\n", table.title);
+            if table.rows.is_empty() {
+                out.push_str("None\n");
+                continue;
+            }
+            let _ = writeln!(out, "| {} |", table.headers.join(" | "));
+            let _ = writeln!(out, "|{}", "---|".repeat(table.headers.len()));
+            for row in &table.rows {
+                let row: Vec<String> = row.iter().map(|c| cell(c)).collect();
+                let _ = writeln!(out, "| {} |", row.join(" | "));
+            }
+        }
+        for edges in self.graphs.iter().filter(|edges| !edges.is_empty()) {
+            let _ = write!(
+                out,
+                "\n## Graph\n\n```mermaid\n{}```\n",
+                Doc::mermaid(edges)
+            );
+        }
+        out
+    }
+
+    fn to_html(&self) -> String {
+        let escape = |text: &str| {
+            text.replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+                .replace('"', "&quot;")
+        };
+        let mut out = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n</head>\n<body>\n<h1>{0}</h1>\n",
+            escape(&self.title)
+        );
+        for table in &self.tables {
+            let _ = writeln!(out, "<h2>{}</h2>", escape(&table.title));
+            if table.rows.is_empty() {
+                out.push_str("<p>None</p>\n");
+                continue;
+            }
+            out.push_str("<table>\n<tr>");
+            for header in table.headers {
+                let _ = write!(out, "<th>{}</th>", header);
+            }
+            out.push_str("</tr>\n");
+            for row in &table.rows {
+                out.push_str("<tr>");
+                for cell in row {
+                    let _ = write!(out, "<td>{}</td>", escape(cell));
+                }
+                out.push_str("</tr>\n");
+            }
+            out.push_str("</table>\n");
+        }
+        for edges in self.graphs.iter().filter(|edges| !edges.is_empty()) {
+            // rendered by mermaid if the page loads it, readable otherwise
+            let _ = write!(
+                out,
+                "<h2>Graph</h2>\n<pre class=\"mermaid\">\n{}</pre>\n",
+                escape(&Doc::mermaid(edges))
+            );
+        }
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+}
+
+impl RunCommand for DocgenArgs {
+    fn run(&self, _global_args: &GlobalArgs) -> anyhow::Result<()> {
+        if !self.workflow.exists() {
+            bail!("Workflow does not exist at path {:?}", self.workflow);
+        }
+        let description =
+            describe_json(&self.workflow, &self.workflow_args, None, false, &|_| true)?;
+        let title = match self.workflow.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => self.workflow.display().to_string(),
+        };
+        let doc = Doc::from_description(title, &description);
+        let text = match self.format {
+            DocFormat::Markdown => doc.to_markdown(),
+            DocFormat::Html => doc.to_html(),
+        };
+        match &self.output {
+            Some(path) => fs::write(path, text)?,
+            None => print!("{}", text),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib::test_utils::TempWorkflowFile;
+
+    fn doc() -> Doc {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+profile = variable(default = "debug", cli_flag = "--profile", doc = "The | profile")
+token = secret(env = "TOKEN")
+cargo = builtin_tool(name = "cargo")
+
+def _pick(ctx):
+    return None
+
+main = workflow(
+    entrypoint = "build",
+    graph = [
+        node(
+            name = "build",
+            action = action(tool = cargo, args = ["build"]),
+            next = when(variable = profile, equals = "release", then = "package"),
+        ),
+        node(name = "package", action = action(tool = cargo), next = next(implementation = _pick)()),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        let description = describe_json(&file.path(), &[], None, false, &|_| true).unwrap();
+        Doc::from_description("test.workflow".to_string(), &description)
+    }
+
+    #[test]
+    fn test_from_description() {
+        let doc = doc();
+        assert_eq!(
+            doc.tables[0].rows,
+            [
+                [
+                    "profile",
+                    "",
+                    "--profile",
+                    "",
+                    "debug",
+                    "read by all, written by all",
+                    "The | profile",
+                    ""
+                ],
+                [
+                    "token",
+                    "",
+                    "--var token=<VALUE>",
+                    "TOKEN",
+                    "",
+                    "read by all, written by all",
+                    "",
+                    "secret"
+                ],
+            ]
+        );
+        assert_eq!(doc.tables[1].rows, [["cargo", "builtin", "cargo"]]);
+        assert_eq!(doc.tables[2].title, "Nodes, starting at build");
+        assert_eq!(doc.tables[2].rows[1][2], "_pick");
+        assert_eq!(
+            doc.graphs,
+            [vec![
+                ("build".to_string(), Some("package".to_string())),
+                ("package".to_string(), None),
+            ]]
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let doc = doc();
+        let markdown = doc.to_markdown();
+        assert!(markdown.starts_with("# test.workflow\n\n## Variables\n\n| Name | Group |"));
+        assert!(markdown.contains("| The \\| profile |"));
+        assert!(markdown.contains(
+            "```mermaid\n\
+             flowchart TD\n\
+             \x20 n0[\"build\"]\n\
+             \x20 n1[\"package\"]\n\
+             \x20 n0 --> n1\n\
+             \x20 n1 -. picked at run time .-> n1_next((?))\n\
+             ```\n"
+        ));
+
+        let html = doc.to_html();
+        assert!(html.contains("<h2>Tools</h2>\n<table>\n<tr><th>Name</th><th>Kind</th><th>Path</th></tr>\n<tr><td>cargo</td><td>builtin</td><td>cargo</td></tr>\n</table>\n"));
+        assert!(html.contains("n0[&quot;build&quot;]\n  n1[&quot;package&quot;]\n  n0 --&gt; n1\n"));
+        assert!(html.ends_with("</body>\n</html>\n"));
+    }
+}
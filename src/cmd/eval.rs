@@ -0,0 +1,98 @@
+use crate::cmd::{GlobalArgs, RunCommand};
+use crate::downcast_delegate_ref;
+use crate::runner::{Runner, WorkflowDelegate};
+use crate::stdlib::variable_resolver::string_from_value;
+use anyhow::bail;
+use clap::Args;
+use starlark::environment::Module;
+use starlark::eval::Evaluator;
+use std::ops::Deref;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct EvalArgs {
+    /// The path to the workflow to evaluate the expression in
+    pub workflow: PathBuf,
+
+    /// The starlark expression to evaluate, e.g. 'format("{}", my_var)'
+    pub expression: String,
+
+    /// The additional arguments that will be passed along to the workflow
+    #[arg(allow_hyphen_values = true, trailing_var_arg = true)]
+    pub workflow_args: Vec<String>,
+}
+
+/// Parses the workflow, realizes its variables and then evaluates the
+/// expression in the workflow's module. Variables and formatters are
+/// resolved to their values.
+pub(crate) fn eval_in_workflow(
+    workflow: &PathBuf,
+    workflow_args: &[String],
+    expression: &str,
+) -> anyhow::Result<String> {
+    if !workflow.exists() {
+        bail!("Workflow does not exist at path {:?}", workflow);
+    }
+
+    let runner = Runner::new(
+        workflow.clone(),
+        WorkflowDelegate::with_args(workflow_args.to_vec()),
+    )?;
+    let module: Module = Module::new();
+    let mut eval: Evaluator = Evaluator::new(&module);
+    runner.parse_workflow(&mut eval)?;
+
+    let value = runner.eval_expression(expression, &mut eval)?;
+    let holder = runner.delegate();
+    let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+    string_from_value(value, delegate)
+}
+
+impl RunCommand for EvalArgs {
+    fn run(&self, _global_args: &GlobalArgs) -> anyhow::Result<()> {
+        println!(
+            "{}",
+            eval_in_workflow(&self.workflow, &self.workflow_args, &self.expression)?
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib::test_utils::TempWorkflowFile;
+
+    const WORKFLOW: &str = r#"
+version = variable(default = "1.0", cli_flag = "--version")
+count = 3
+"#;
+
+    #[test]
+    fn test_eval_resolves_variables() {
+        let file = TempWorkflowFile::new("test.workflow", WORKFLOW).unwrap();
+        assert_eq!(
+            eval_in_workflow(&file.path(), &[], r#"format("v{}", version)"#).unwrap(),
+            "v1.0"
+        );
+        assert_eq!(
+            eval_in_workflow(
+                &file.path(),
+                &["--version".to_string(), "2.0".to_string()],
+                "version"
+            )
+            .unwrap(),
+            "2.0"
+        );
+    }
+
+    #[test]
+    fn test_eval_plain_values() {
+        let file = TempWorkflowFile::new("test.workflow", WORKFLOW).unwrap();
+        assert_eq!(
+            eval_in_workflow(&file.path(), &[], "count * 2").unwrap(),
+            "6"
+        );
+        assert!(eval_in_workflow(&file.path(), &[], "unknown_name").is_err());
+    }
+}
@@ -0,0 +1,91 @@
+use crate::cmd::{GlobalArgs, RunCommand};
+use crate::runner::format_source;
+use anyhow::bail;
+use clap::Args;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Args, Debug)]
+pub struct FmtArgs {
+    /// The workflow files to format
+    #[arg(required = true)]
+    pub workflows: Vec<PathBuf>,
+
+    /// Lists the files which are not formatted, and fails if there are
+    /// any, instead of formatting them
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub check: bool,
+}
+
+impl FmtArgs {
+    /// Formats the file, or checks it is formatted, and returns whether it
+    /// was already formatted.
+    fn format(&self, out: &mut dyn Write, path: &Path) -> anyhow::Result<bool> {
+        let text = fs::read_to_string(path)?;
+        let formatted = format_source(&path.display().to_string(), &text)?;
+        if formatted == text {
+            return Ok(true);
+        }
+        match self.check {
+            true => writeln!(out, "{} is not formatted", path.display())?,
+            false => {
+                fs::write(path, formatted)?;
+                writeln!(out, "Formatted {}", path.display())?;
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl RunCommand for FmtArgs {
+    fn run(&self, _global_args: &GlobalArgs) -> anyhow::Result<()> {
+        let mut out = io::stdout();
+        let mut unformatted = 0;
+        for path in &self.workflows {
+            if !self.format(&mut out, path)? {
+                unformatted += 1;
+            }
+        }
+        if self.check && unformatted > 0 {
+            bail!(
+                "{} of {} files are not formatted",
+                unformatted,
+                self.workflows.len()
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib::test_utils::TempWorkflowFile;
+
+    #[test]
+    fn test_check_does_not_write() {
+        let file = TempWorkflowFile::new("test.workflow", "v=variable(default='a')").unwrap();
+        let content = fs::read_to_string(file.path()).unwrap();
+        let mut args = FmtArgs {
+            workflows: vec![file.path()],
+            check: true,
+        };
+
+        let mut out = vec![];
+        assert!(!args.format(&mut out, &file.path()).unwrap());
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), content);
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .ends_with(" is not formatted\n"));
+
+        args.check = false;
+        assert!(!args.format(&mut vec![], &file.path()).unwrap());
+        assert_eq!(
+            fs::read_to_string(file.path()).unwrap(),
+            "v = variable(default = 'a')\n"
+        );
+        args.check = true;
+        assert!(args.format(&mut vec![], &file.path()).unwrap());
+    }
+}
@@ -0,0 +1,317 @@
+use crate::cmd::{GlobalArgs, RunCommand};
+use crate::runner::{state_dir, History, NodeCache};
+use crate::stdlib::capture::stale_spill_files;
+use crate::stdlib::inline_file::stale_scratch_dirs;
+use anyhow::bail;
+use clap::Args;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Args, Debug)]
+pub struct GcArgs {
+    /// Removes the runs in the history, along with their artifacts, the
+    /// scratch dirs and spilled output left behind by killed runs and the
+    /// node caches of workflows which have not run, which are older than
+    /// this, e.g. 12h, 30d or 2w. The dirs given to --log-dir are kept
+    #[arg(long, default_value = "30d", value_parser = parse_age)]
+    pub older_than: Duration,
+
+    /// Then removes the oldest runs until the history is at most this
+    /// size, e.g. 500K or 1G
+    #[arg(long, value_parser = parse_size)]
+    pub max_size: Option<u64>,
+
+    /// Lists what would be removed without removing anything
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub dry_run: bool,
+}
+
+/// Parses an age such as `90s`, `30m`, `12h`, `30d` or `2w`.
+fn parse_age(age: &str) -> anyhow::Result<Duration> {
+    let split = age.find(|c: char| !c.is_ascii_digit()).unwrap_or(age.len());
+    let (count, unit) = age.split_at(split);
+    let count: u64 = match count.parse() {
+        Ok(count) => count,
+        Err(_) => bail!("'{}' is not an age, expected a number and a unit", age),
+    };
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => bail!("unknown unit in '{}', expected one of s, m, h, d or w", age),
+    };
+    Ok(Duration::from_secs(count * seconds))
+}
+
+/// Parses a size in bytes such as `100`, `500K`, `10MB` or `1G`. The
+/// units are powers of 1024.
+fn parse_size(size: &str) -> anyhow::Result<u64> {
+    let split = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (count, unit) = size.split_at(split);
+    let count: u64 = match count.parse() {
+        Ok(count) => count,
+        Err(_) => bail!("'{}' is not a size, expected a number and a unit", size),
+    };
+    let shift = match unit.to_uppercase().trim_end_matches(['B', 'I']) {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => bail!("unknown unit in '{}', expected one of K, M, G or T", size),
+    };
+    match count.checked_mul(1 << shift) {
+        Some(bytes) => Ok(bytes),
+        None => bail!("'{}' is too large", size),
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    let units = ["K", "M", "G", "T"];
+    let mut size = bytes as f64;
+    let mut unit = "B";
+    for next in units {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    match unit {
+        "B" => format!("{}B", bytes),
+        _ => format!("{:.1}{}", size, unit),
+    }
+}
+
+fn format_age(seconds: u64) -> String {
+    match seconds {
+        s if s < 60 * 60 => format!("{}m", s / 60),
+        s if s < 24 * 60 * 60 => format!("{}h", s / (60 * 60)),
+        s => format!("{}d", s / (24 * 60 * 60)),
+    }
+}
+
+/// Returns the size of everything below the path, without following links.
+fn disk_usage(path: &Path) -> u64 {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return 0,
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    match fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| disk_usage(&entry.path()))
+            .sum(),
+        Err(_) => 0,
+    }
+}
+
+impl GcArgs {
+    fn gc(
+        &self,
+        out: &mut dyn Write,
+        history: &History,
+        state_dir: &Path,
+        temp_dir: &Path,
+        now: SystemTime,
+    ) -> anyhow::Result<()> {
+        let cutoff = now.checked_sub(self.older_than).unwrap_or(UNIX_EPOCH);
+        let now_secs = now.duration_since(UNIX_EPOCH)?.as_secs();
+        let verb = match self.dry_run {
+            true => "Would remove",
+            false => "Removed",
+        };
+
+        let history_size = fs::metadata(history.path()).map_or(0, |m| m.len());
+        let mut artifacts_freed = 0;
+        let runs = history.prune(
+            Some(cutoff.duration_since(UNIX_EPOCH)?.as_secs()),
+            self.max_size,
+            self.dry_run,
+        )?;
+        for (id, record) in &runs {
+            writeln!(
+                out,
+                "{} run {}: {} {} ({} old)",
+                verb,
+                id,
+                record.workflow.display(),
+                record.args.join(" "),
+                format_age(now_secs.saturating_sub(record.started_at))
+            )?;
+            if let Some(dir) = record.artifacts_dir.as_ref().filter(|d| d.exists()) {
+                let size = disk_usage(dir);
+                writeln!(out, "{} {} ({})", verb, dir.display(), format_size(size))?;
+                if !self.dry_run {
+                    fs::remove_dir_all(dir)?;
+                }
+                artifacts_freed += size;
+            }
+        }
+        let history_freed = match self.dry_run {
+            true => 0,
+            false => {
+                history_size.saturating_sub(fs::metadata(history.path()).map_or(0, |m| m.len()))
+            }
+        };
+
+        let mut left_behind_freed = 0;
+        let dirs = stale_scratch_dirs(temp_dir, cutoff);
+        let spill_files = stale_spill_files(temp_dir, cutoff);
+        let caches = NodeCache::stale(state_dir, cutoff);
+        for path in dirs.iter().chain(&spill_files).chain(&caches) {
+            let size = disk_usage(path);
+            writeln!(out, "{} {} ({})", verb, path.display(), format_size(size))?;
+            if !self.dry_run {
+                match path.is_dir() {
+                    true => fs::remove_dir_all(path)?,
+                    false => fs::remove_file(path)?,
+                }
+            }
+            left_behind_freed += size;
+        }
+
+        let summary = format!(
+            "{} runs from the history, {} scratch dirs, {} spill files and {} node caches",
+            runs.len(),
+            dirs.len(),
+            spill_files.len(),
+            caches.len()
+        );
+        match self.dry_run {
+            true => writeln!(out, "{} {}", verb, summary)?,
+            false => writeln!(
+                out,
+                "{} {}, freeing {}",
+                verb,
+                summary,
+                format_size(history_freed + artifacts_freed + left_behind_freed)
+            )?,
+        }
+        Ok(())
+    }
+}
+
+impl RunCommand for GcArgs {
+    fn run(&self, _global_args: &GlobalArgs) -> anyhow::Result<()> {
+        let history = History::default_location()?;
+        self.gc(
+            &mut io::stdout(),
+            &history,
+            &state_dir()?,
+            &std::env::temp_dir(),
+            SystemTime::now(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::HistoryRecord;
+    use std::path::PathBuf;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_age("12h").unwrap(), Duration::from_secs(12 * 3600));
+        assert_eq!(parse_age("2w").unwrap(), Duration::from_secs(14 * 86400));
+        assert!(parse_age("30").is_err());
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("3y").is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("100").unwrap(), 100);
+        assert_eq!(parse_size("500K").unwrap(), 500 * 1024);
+        assert_eq!(parse_size("10MB").unwrap(), 10 << 20);
+        assert_eq!(parse_size("1gib").unwrap(), 1 << 30);
+        assert!(parse_size("1X").is_err());
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("99999999999T").is_err());
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(10), "10B");
+        assert_eq!(format_size(1536), "1.5K");
+        assert_eq!(format_size(3 << 30), "3.0G");
+    }
+
+    #[test]
+    fn test_gc() {
+        let state = tempdir().unwrap();
+        let temp = tempdir().unwrap();
+        let history = History::new(state.path().join("history.jsonl"));
+        let day = 24 * 60 * 60;
+        let now = UNIX_EPOCH + Duration::from_secs(100 * day);
+        let artifacts = state.path().join("artifacts/1");
+        fs::create_dir_all(&artifacts).unwrap();
+        fs::write(artifacts.join("out.tar.gz"), "abcd").unwrap();
+        for started_at in [10 * day, 95 * day] {
+            let mut record = HistoryRecord::new(PathBuf::from("/a.workflow"), vec![], None);
+            record.started_at = started_at;
+            record.artifacts_dir = (started_at == 10 * day).then(|| artifacts.clone());
+            history.append(&record).unwrap();
+        }
+        let scratch = temp.path().join("workflow-scratch-1");
+        fs::create_dir(&scratch).unwrap();
+        fs::write(scratch.join("f"), "abc").unwrap();
+        let spill = temp.path().join("workflow-output-1");
+        fs::write(&spill, "ab").unwrap();
+        let cache = state.path().join("node_cache/1.json");
+        fs::create_dir_all(cache.parent().unwrap()).unwrap();
+        fs::write(&cache, "{}").unwrap();
+
+        let gc = |dry_run: bool, now: SystemTime| {
+            let args = GcArgs {
+                older_than: parse_age("30d").unwrap(),
+                max_size: None,
+                dry_run,
+            };
+            let mut out = vec![];
+            args.gc(&mut out, &history, state.path(), temp.path(), now)
+                .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let out = gc(true, now);
+        assert_eq!(
+            out,
+            format!(
+                "Would remove run 1: /a.workflow  (90d old)\n\
+                 Would remove {} (4B)\n\
+                 Would remove 1 runs from the history, 0 scratch dirs, 0 spill files and 0 node \
+                 caches\n",
+                artifacts.display()
+            )
+        );
+        assert_eq!(history.records().unwrap().len(), 2);
+        assert!(artifacts.exists());
+
+        let out = gc(false, now);
+        assert!(out.starts_with("Removed run 1: /a.workflow  (90d old)\n"));
+        assert_eq!(history.records().unwrap().len(), 1);
+        assert!(!artifacts.exists());
+
+        // the scratch dir was just made so it is only stale in the future
+        let out = gc(false, SystemTime::now() + Duration::from_secs(31 * day));
+        assert!(out.contains(&format!("Removed {} (3B)\n", scratch.display())));
+        assert!(out.contains(&format!("Removed {} (2B)\n", spill.display())));
+        assert!(out.contains(&format!("Removed {} (2B)\n", cache.display())));
+        assert!(!scratch.exists());
+        assert!(!spill.exists());
+        assert!(!cache.exists());
+    }
+}
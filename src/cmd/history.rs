@@ -0,0 +1,285 @@
+use crate::cmd::pager::Pager;
+use crate::cmd::{GlobalArgs, RunCommand};
+use crate::runner::{History, HistoryRecord, NodeRecord};
+use crate::stdlib::env_capture::ActionEnv;
+use ansi_term::Colour::{Cyan, Green, Red};
+use anyhow::bail;
+use clap::{Args, Subcommand};
+use std::io::Write;
+
+#[derive(Args, Debug)]
+pub struct HistoryArgs {
+    #[command(subcommand)]
+    pub command: HistoryCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum HistoryCommand {
+    /// Lists the stored runs, oldest first, along with their ids
+    List,
+    /// Shows the nodes of a stored run
+    Show(ShowArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct ShowArgs {
+    /// The id of the run, as shown by `history list`
+    pub id: usize,
+
+    /// Shows the environment of each tool the node spawned and how it
+    /// differs from that of the tool spawned before it. Run with -vv to
+    /// record the variables rather than only a hash.
+    #[arg(long, value_name = "NODE")]
+    pub env: Option<String>,
+}
+
+fn status(succeeded: bool) -> String {
+    match succeeded {
+        true => Green.paint("ok").to_string(),
+        false => Red.paint("failed").to_string(),
+    }
+}
+
+fn print_list(out: &mut dyn Write, records: &[HistoryRecord]) -> anyhow::Result<()> {
+    for (index, record) in records.iter().enumerate() {
+        writeln!(
+            out,
+            "{}: {} {} {} ({}ms)",
+            index + 1,
+            status(record.succeeded()),
+            record.workflow.display(),
+            record.args.join(" "),
+            record.duration_ms
+        )?;
+    }
+    Ok(())
+}
+
+fn print_node(out: &mut dyn Write, node: &NodeRecord) -> anyhow::Result<()> {
+    let mut exit_code = match node.exit_code {
+        Some(code) => format!("exit code {}", code),
+        None => "no exit code".to_string(),
+    };
+    if node.cached {
+        exit_code.push_str(", unchanged");
+    }
+    writeln!(
+        out,
+        "  {}: {} {} ({}ms)",
+        Cyan.paint(&node.name),
+        status(node.succeeded()),
+        exit_code,
+        node.duration_ms
+    )?;
+    if let Some(error) = &node.error {
+        writeln!(out, "    {}", error)?;
+    }
+    if let Some(approval) = &node.approval {
+        writeln!(out, "    approved by {}", approval.by)?;
+    }
+    for artifact in &node.artifacts {
+        writeln!(out, "    artifact {}", artifact.display())?;
+    }
+    Ok(())
+}
+
+fn print_run(out: &mut dyn Write, id: usize, record: &HistoryRecord) -> anyhow::Result<()> {
+    writeln!(
+        out,
+        "Run {} of {} {}: {} ({}ms)",
+        id,
+        record.workflow.display(),
+        record.args.join(" "),
+        status(record.succeeded()),
+        record.duration_ms
+    )?;
+    if let Some(injection) = &record.failure_injection {
+        writeln!(
+            out,
+            "  with failures injected, --inject-failures {}",
+            injection
+        )?;
+    }
+    if let Some(error) = &record.error {
+        writeln!(out, "  {}", error)?;
+    }
+    for node in &record.nodes {
+        print_node(out, node)?;
+    }
+    Ok(())
+}
+
+/// Prints the environment of every tool spawned by the node, each time it
+/// ran, compared to the tool spawned before it in the run.
+fn print_env(out: &mut dyn Write, record: &HistoryRecord, name: &str) -> anyhow::Result<()> {
+    if !record.nodes.iter().any(|n| n.name == name) {
+        bail!("Node '{}' did not run", name);
+    }
+    // every spawned tool in the order they ran, along with its node
+    let envs: Vec<(&str, &ActionEnv)> = record
+        .nodes
+        .iter()
+        .flat_map(|n| n.envs.iter().map(move |env| (n.name.as_str(), env)))
+        .collect();
+    let mut spawned = vec![];
+    for (index, (node, env)) in envs.iter().enumerate() {
+        if *node != name {
+            continue;
+        }
+        writeln!(
+            out,
+            "{} ({}, tool {} in the run): env {}",
+            Cyan.paint(name),
+            env.action,
+            index + 1,
+            env.hash
+        )?;
+        let before = index.checked_sub(1).map(|i| envs[i].1);
+        print_env_changes(out, env, before)?;
+        spawned.push(env);
+    }
+    if spawned.is_empty() {
+        writeln!(out, "Node '{}' did not spawn any tools", name)?;
+    } else if spawned.iter().any(|env| env.vars.is_none()) {
+        writeln!(out, "Run with -vv to record the variables")?;
+    }
+    Ok(())
+}
+
+fn print_env_changes(
+    out: &mut dyn Write,
+    env: &ActionEnv,
+    before: Option<&ActionEnv>,
+) -> anyhow::Result<()> {
+    let before = match before {
+        Some(before) => before,
+        None => {
+            for (k, v) in env.vars.iter().flatten() {
+                writeln!(out, "  {}={}", k, v)?;
+            }
+            return Ok(());
+        }
+    };
+    if before.hash == env.hash {
+        writeln!(out, "  same as {}", before.action)?;
+        return Ok(());
+    }
+    match env.diff(before) {
+        Some(changes) => {
+            writeln!(out, "  changed since {}:", before.action)?;
+            for change in changes {
+                writeln!(out, "    {}", change)?;
+            }
+        }
+        None => writeln!(out, "  differs from {} ({})", before.action, before.hash)?,
+    }
+    Ok(())
+}
+
+impl RunCommand for HistoryArgs {
+    fn run(&self, global_args: &GlobalArgs) -> anyhow::Result<()> {
+        let records = History::default_location()?.records()?;
+        let mut pager = Pager::start(global_args.no_pager);
+        match &self.command {
+            HistoryCommand::List => print_list(&mut pager, &records)?,
+            HistoryCommand::Show(args) => {
+                let record = match args.id.checked_sub(1).and_then(|i| records.get(i)) {
+                    Some(record) => record,
+                    None => bail!(
+                        "No run with id {} in the history, the ids are 1 to {}",
+                        args.id,
+                        records.len()
+                    ),
+                };
+                match &args.env {
+                    Some(node) => print_env(&mut pager, record, node)?,
+                    None => print_run(&mut pager, args.id, record)?,
+                }
+            }
+        }
+        pager.finish()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    fn env(action: &str, hash: &str, vars: Option<&[(&str, &str)]>) -> ActionEnv {
+        ActionEnv {
+            action: action.to_string(),
+            hash: hash.to_string(),
+            vars: vars.map(|vars| {
+                vars.iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect::<BTreeMap<_, _>>()
+            }),
+        }
+    }
+
+    fn record(nodes: Vec<(&str, Vec<ActionEnv>)>) -> HistoryRecord {
+        let mut record = HistoryRecord::new(PathBuf::from("/foo.workflow"), vec![], None);
+        record.nodes = nodes
+            .into_iter()
+            .map(|(name, envs)| NodeRecord {
+                name: name.to_string(),
+                duration_ms: 0,
+                exit_code: Some(0),
+                exit_code_ok: None,
+                error: None,
+                variables: None,
+                envs,
+                artifacts: vec![],
+                cached: false,
+                approval: None,
+            })
+            .collect();
+        record
+    }
+
+    fn show_env(record: &HistoryRecord, name: &str) -> String {
+        let mut out = vec![];
+        print_env(&mut out, record, name).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_print_env_diffs_with_previous_tool() {
+        let record = record(vec![
+            ("a", vec![env("make", "1", Some(&[("A", "1"), ("B", "2")]))]),
+            (
+                "b",
+                vec![
+                    env("cc", "2", Some(&[("A", "1"), ("C", "3")])),
+                    env("ld", "2", Some(&[("A", "1"), ("C", "3")])),
+                ],
+            ),
+        ]);
+        let out = show_env(&record, "a");
+        assert!(out.contains("A=1\n  B=2\n"));
+
+        let out = show_env(&record, "b");
+        assert!(out.contains("(cc, tool 2 in the run): env 2\n"));
+        assert!(out.contains("  changed since make:\n    - B\n    + C=3\n"));
+        assert!(out.contains("(ld, tool 3 in the run): env 2\n  same as cc\n"));
+    }
+
+    #[test]
+    fn test_print_env_without_vars() {
+        let record = record(vec![
+            ("a", vec![env("make", "1", None)]),
+            ("b", vec![env("cc", "2", None)]),
+            ("c", vec![]),
+        ]);
+        let out = show_env(&record, "b");
+        assert!(out.contains("  differs from make (1)\n"));
+        assert!(out.ends_with("Run with -vv to record the variables\n"));
+
+        assert_eq!(show_env(&record, "c"), "Node 'c' did not spawn any tools\n");
+        let mut out = vec![];
+        assert!(print_env(&mut out, &record, "d").is_err());
+    }
+}
@@ -0,0 +1,278 @@
+use crate::cmd::{GlobalArgs, RunCommand};
+use anyhow::bail;
+use clap::{Args, ValueEnum};
+use std::fs;
+use std::path::PathBuf;
+
+/// The starter workflows `init` can write.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Template {
+    /// A variable, a tool and two nodes
+    Basic,
+    /// Builds and tests a cargo project, and packages release builds
+    Build,
+    /// Deploys, after an approval for production
+    Deploy,
+}
+
+impl Template {
+    /// Returns the source of the workflow, `{file}` is replaced by the name
+    /// of the file it is written to.
+    fn source(&self) -> &'static str {
+        match self {
+            Template::Basic => BASIC,
+            Template::Build => BUILD,
+            Template::Deploy => DEPLOY,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct InitArgs {
+    /// The path to write the workflow to
+    pub workflow: PathBuf,
+
+    /// The starter workflow to write
+    #[arg(long, value_enum, default_value = "basic")]
+    pub template: Template,
+
+    /// Overwrites the file if it exists
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub force: bool,
+}
+
+impl InitArgs {
+    /// Writes the template to the path, creating the directories it is in.
+    fn init(&self) -> anyhow::Result<()> {
+        if self.workflow.exists() && !self.force {
+            bail!(
+                "{} already exists, pass --force to overwrite it",
+                self.workflow.display()
+            );
+        }
+        let file = self
+            .workflow
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if let Some(dir) = self.workflow.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(
+            &self.workflow,
+            self.template.source().replace("{file}", &file),
+        )?;
+        Ok(())
+    }
+}
+
+impl RunCommand for InitArgs {
+    fn run(&self, global_args: &GlobalArgs) -> anyhow::Result<()> {
+        self.init()?;
+        if !global_args.quiet {
+            println!("Created {}", self.workflow.display());
+        }
+        Ok(())
+    }
+}
+
+const BASIC: &str = r#"# A starter workflow. Run it with `workflow run {file} --name you`
+# and see what it defines with `workflow describe {file}`.
+
+# A variable holds a value which the actions use. This one is set with
+# --name on the command line or the GREETING_NAME env var, and is "world"
+# otherwise.
+name = variable(
+    default = "world",
+    env = "GREETING_NAME",
+    cli_flag = "--name",
+    doc = "Who to greet",
+)
+
+# A tool is a program which the actions run. A builtin tool is looked up
+# on the PATH, tool(path = ...) runs a program relative to this file.
+echo = builtin_tool(name = "echo")
+
+# A next function picks the node to run after a node, by its name, or
+# returns None to stop the workflow.
+def _after_greet(ctx, args):
+    if ctx.exit_code != 0:
+        return None
+    return "goodbye"
+
+# A workflow is a graph of nodes which starts at its entrypoint. A node
+# runs its action, a tool with args, and then the node its next picks. The
+# workflow stops after a node without a next.
+main = workflow(
+    entrypoint = "greet",
+    graph = [
+        node(
+            name = "greet",
+            action = action(tool = echo, args = [format("hello, {}", name)]),
+            next = next(implementation = _after_greet)(),
+        ),
+        node(
+            name = "goodbye",
+            action = action(tool = echo, args = ["goodbye"]),
+        ),
+    ],
+)
+"#;
+
+const BUILD: &str = r#"# A starter workflow which builds and tests a cargo project and packages it
+# for release builds. Run it with `workflow run {file} --profile
+# release` and see its graph with `workflow describe {file}`.
+
+# A variable holds a value which the actions use. It is set with its
+# cli_flag on the command line or its env var, and has the default
+# otherwise.
+profile = variable(
+    default = "debug",
+    env = "BUILD_PROFILE",
+    cli_flag = "--profile",
+    doc = "The cargo profile to build with, debug or release",
+)
+
+# A tool is a program which the actions run. A builtin tool is looked up
+# on the PATH, tool(path = ...) runs a program relative to this file.
+cargo = builtin_tool(name = "cargo")
+
+# An action runs a tool with args. Variables in the args are replaced by
+# their values when the action runs.
+build = action(tool = cargo, args = ["build", "--profile", profile])
+
+test = action(tool = cargo, args = ["test", "--profile", profile])
+
+# A workflow is a graph of nodes which starts at its entrypoint. A node
+# runs its action and a sequence runs its actions one after the other. The
+# next of a node picks the node to run after it, when() picks it by the
+# value of a variable. The workflow stops after a node without a next.
+main = workflow(
+    entrypoint = "build",
+    graph = [
+        sequence(
+            name = "build",
+            actions = [build, test],
+            next = when(variable = profile, equals = "release", then = "package"),
+        ),
+        node(
+            name = "package",
+            action = action(tool = cargo, args = ["package", "--allow-dirty"]),
+        ),
+    ],
+)
+"#;
+
+const DEPLOY: &str = r#"# A starter workflow which deploys, after it is approved for production.
+# Run it with `DEPLOY_TOKEN=... workflow run {file} --environment
+# production` and see its graph with `workflow describe {file}`.
+
+# A variable holds a value which the actions use. It is set with its
+# cli_flag on the command line or its env var, and has the default
+# otherwise.
+environment = variable(
+    default = "staging",
+    env = "DEPLOY_ENVIRONMENT",
+    cli_flag = "--environment",
+    doc = "Where to deploy, staging or production",
+)
+
+# A secret is a variable whose value is never shown, in the output of the
+# actions or in the history.
+token = secret(env = "DEPLOY_TOKEN", doc = "The token to deploy with")
+
+# A tool is a program which the actions run. A builtin tool is looked up
+# on the PATH, tool(path = ...) runs a program relative to this file, e.g.
+# a script which does the deploy.
+echo = builtin_tool(name = "echo")
+
+# A next function picks the node to run after a node, by its name, or
+# returns None to stop the workflow.
+def _after_approval(ctx, args):
+    return "deploy"
+
+# A workflow is a graph of nodes which starts at its entrypoint. The next
+# of a node picks the node to run after it, when() picks it by the value
+# of a variable. A manual gate waits until the run is approved. The
+# workflow stops after a node without a next.
+main = workflow(
+    entrypoint = "check",
+    graph = [
+        node(
+            name = "check",
+            action = action(tool = echo, args = [format("deploying to {}", environment)]),
+            next = when(
+                variable = environment,
+                equals = "production",
+                then = "approve",
+                else_ = "deploy",
+            ),
+        ),
+        manual_gate(
+            name = "approve",
+            message = "Deploy to production?",
+            next = next(implementation = _after_approval)(),
+        ),
+        node(
+            name = "deploy",
+            action = action(
+                tool = echo,
+                args = ["deployed to", environment],
+                env = {"DEPLOY_TOKEN": token},
+            ),
+        ),
+    ],
+)
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::format_source;
+    use crate::runner::lint::{lint_file, LintConfig};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_templates_are_clean() {
+        let dir = tempdir().unwrap();
+        for template in [Template::Basic, Template::Build, Template::Deploy] {
+            let args = InitArgs {
+                workflow: dir.path().join("new").join("ci.workflow"),
+                template,
+                force: true,
+            };
+            args.init().unwrap();
+            let source = fs::read_to_string(&args.workflow).unwrap();
+            assert!(source.contains("workflow run ci.workflow"));
+            assert_eq!(format_source("ci.workflow", &source).unwrap(), source);
+            let findings = lint_file(&args.workflow, vec![], &LintConfig::default()).unwrap();
+            assert!(findings.is_empty(), "{:?}: {:?}", template, findings);
+        }
+    }
+
+    #[test]
+    fn test_refuses_to_overwrite() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("ci.workflow");
+        fs::write(&path, "# mine").unwrap();
+        let mut args = InitArgs {
+            workflow: path.clone(),
+            template: Template::Basic,
+            force: false,
+        };
+        assert_eq!(
+            args.init().unwrap_err().to_string(),
+            format!(
+                "{} already exists, pass --force to overwrite it",
+                path.display()
+            )
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "# mine");
+
+        args.force = true;
+        args.init().unwrap();
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .starts_with("# A starter workflow"));
+    }
+}
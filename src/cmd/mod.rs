@@ -1,8 +1,39 @@
+pub mod agent;
+pub mod check;
+pub mod compile;
 pub mod describe;
+pub mod docgen;
+pub mod eval;
+pub mod fmt;
+pub mod gc;
+pub mod history;
+pub mod init;
+mod pager;
+mod progress;
+pub mod repl;
+pub mod rerun;
 pub mod run;
+pub mod schedule;
+pub mod serve;
+pub mod stats;
 use crate::cmd::describe::DescribeArgs;
+use crate::cmd::docgen::DocgenArgs;
+use crate::stdlib::env_capture::EnvCapture;
+use agent::AgentArgs;
+use check::CheckArgs;
 use clap::{Args, Parser, Subcommand};
+use compile::CompileArgs;
+use eval::EvalArgs;
+use fmt::FmtArgs;
+use gc::GcArgs;
+use history::HistoryArgs;
+use init::InitArgs;
+use repl::ReplArgs;
+use rerun::RerunArgs;
 use run::RunArgs;
+use schedule::ScheduleArgs;
+use serve::ServeArgs;
+use stats::StatsArgs;
 
 pub trait RunCommand {
     fn run(&self, global_args: &GlobalArgs) -> anyhow::Result<()>;
@@ -10,16 +41,69 @@ pub trait RunCommand {
 
 #[derive(Args)]
 pub struct GlobalArgs {
-    /// If set, will suppress extra log information
+    /// If set, will suppress extra log information and the output of the
+    /// actions, which is still available to their setters
     #[arg(short, long, action = clap::ArgAction::SetTrue)]
     pub quiet: bool,
+
+    /// If set, long output is written directly to stdout instead of $PAGER
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub no_pager: bool,
+
+    /// Records more about each run in the history, -vv records the
+    /// environment of every tool that is spawned
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+}
+
+impl GlobalArgs {
+    /// Returns how much of the environment of each tool to record.
+    pub fn env_capture(&self) -> EnvCapture {
+        match self.verbose {
+            0 | 1 => EnvCapture::Hash,
+            _ => EnvCapture::Full,
+        }
+    }
 }
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
+    /// Runs a compiled workflow read from stdin, or sent to --listen, and
+    /// streams its progress back as ndjson events
+    Agent(AgentArgs),
+    /// Checks the given workflow for likely mistakes
+    Check(CheckArgs),
+    /// Compiles the given workflow to a json IR which `run` can run
+    Compile(CompileArgs),
     /// Describes the given workflow
     Describe(DescribeArgs),
+    /// Writes the documentation of the given workflow's variables, tools and
+    /// graph as markdown or html
+    Docgen(DocgenArgs),
+    /// Evaluates an expression in the context of the given workflow
+    Eval(EvalArgs),
+    /// Formats workflow files in the canonical style
+    Fmt(FmtArgs),
+    /// Removes old runs from the history, the files left behind by killed
+    /// runs and the node caches of workflows which have not run
+    Gc(GcArgs),
+    /// Lists and shows the runs stored in the history
+    History(HistoryArgs),
+    /// Writes a starter workflow, with comments explaining what it defines
+    Init(InitArgs),
     Run(RunArgs),
+    /// Starts an interactive prompt for exploring the given workflow
+    Repl(ReplArgs),
+    /// Re-runs the most recent invocation with the same file and args
+    Rerun(RerunArgs),
+    /// Runs the given workflow whenever a cron expression matches, until
+    /// stopped
+    Schedule(ScheduleArgs),
+    /// Serves runs of the given workflows and their history over a local
+    /// HTTP API
+    Serve(ServeArgs),
+    /// Shows per node failure rates and durations across stored runs
+    Stats(StatsArgs),
 }
 
 #[derive(Parser)]
@@ -35,12 +119,22 @@ pub struct Cli {
 impl Cli {
     pub fn parse_and_run(&self) -> anyhow::Result<()> {
         match &self.command {
-            Commands::Describe(args) => {
-                return args.run(&self.global_args);
-            }
-            Commands::Run(args) => {
-                return args.run(&self.global_args);
-            }
+            Commands::Agent(args) => args.run(&self.global_args),
+            Commands::Check(args) => args.run(&self.global_args),
+            Commands::Compile(args) => args.run(&self.global_args),
+            Commands::Describe(args) => args.run(&self.global_args),
+            Commands::Docgen(args) => args.run(&self.global_args),
+            Commands::Eval(args) => args.run(&self.global_args),
+            Commands::Fmt(args) => args.run(&self.global_args),
+            Commands::Gc(args) => args.run(&self.global_args),
+            Commands::History(args) => args.run(&self.global_args),
+            Commands::Init(args) => args.run(&self.global_args),
+            Commands::Run(args) => args.run(&self.global_args),
+            Commands::Repl(args) => args.run(&self.global_args),
+            Commands::Rerun(args) => args.run(&self.global_args),
+            Commands::Schedule(args) => args.run(&self.global_args),
+            Commands::Serve(args) => args.run(&self.global_args),
+            Commands::Stats(args) => args.run(&self.global_args),
         }
     }
 }
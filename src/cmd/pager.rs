@@ -0,0 +1,123 @@
+use std::env;
+use std::io::{self, IsTerminal, Stdout, Write};
+use std::process::{Child, Command, Stdio};
+
+/// Where long command output is written. When stdout is a terminal the
+/// output is piped through $PAGER, like git does, otherwise it is written
+/// straight to stdout.
+pub(crate) enum Pager {
+    Stdout(Stdout),
+    Process(Child),
+}
+
+impl Pager {
+    /// Starts the pager unless `disabled` is set, stdout is not a terminal or
+    /// the pager is set to `cat`. If the pager can not be started the output
+    /// falls back to stdout.
+    pub(crate) fn start(disabled: bool) -> Self {
+        if disabled || !io::stdout().is_terminal() {
+            return Pager::Stdout(io::stdout());
+        }
+        match pager_command() {
+            Some(cmd) => Pager::spawn(&cmd).unwrap_or_else(|_| Pager::Stdout(io::stdout())),
+            None => Pager::Stdout(io::stdout()),
+        }
+    }
+
+    fn spawn(cmd: &str) -> io::Result<Self> {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(cmd).stdin(Stdio::piped());
+        // Like git, have less exit when the output fits on one screen and
+        // pass the colour codes through.
+        if env::var_os("LESS").is_none() {
+            command.env("LESS", "FRX");
+        }
+        Ok(Pager::Process(command.spawn()?))
+    }
+
+    /// Waits for the user to exit the pager.
+    pub(crate) fn finish(self) -> io::Result<()> {
+        match self {
+            Pager::Stdout(mut stdout) => stdout.flush(),
+            Pager::Process(mut child) => {
+                drop(child.stdin.take());
+                child.wait().map(|_| ())
+            }
+        }
+    }
+}
+
+/// Returns the command to page with, None if paging is turned off.
+fn pager_command() -> Option<String> {
+    pager_from(env::var("PAGER").ok())
+}
+
+fn pager_from(pager: Option<String>) -> Option<String> {
+    match pager {
+        Some(pager) if pager.trim().is_empty() || pager.trim() == "cat" => None,
+        Some(pager) => Some(pager),
+        None => Some("less".to_string()),
+    }
+}
+
+impl Write for Pager {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Pager::Stdout(stdout) => stdout.write(buf),
+            Pager::Process(child) => ignore_closed_pager(child.stdin.as_mut().unwrap().write(buf))
+                .map(|n| n.unwrap_or(buf.len())),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Pager::Stdout(stdout) => stdout.flush(),
+            Pager::Process(child) => {
+                ignore_closed_pager(child.stdin.as_mut().unwrap().flush()).map(|_| ())
+            }
+        }
+    }
+}
+
+/// The user can quit the pager before all of the output is written, which
+/// is not an error.
+fn ignore_closed_pager<T>(result: io::Result<T>) -> io::Result<Option<T>> {
+    match result {
+        Ok(v) => Ok(Some(v)),
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pager_from() {
+        assert_eq!(pager_from(None), Some("less".to_string()));
+        assert_eq!(
+            pager_from(Some("more -s".to_string())),
+            Some("more -s".to_string())
+        );
+        assert_eq!(pager_from(Some("".to_string())), None);
+        assert_eq!(pager_from(Some("cat".to_string())), None);
+    }
+
+    #[test]
+    fn test_process_pager_ignores_closed_pipe() {
+        // the pager exits without reading its input
+        let mut pager = Pager::spawn("true").unwrap();
+        if let Pager::Process(child) = &mut pager {
+            // wait() would close stdin, so poll for the exit instead
+            while child.try_wait().unwrap().is_none() {
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+        }
+        let line = vec![b'a'; 1 << 16];
+        for _ in 0..16 {
+            pager.write_all(&line).unwrap();
+        }
+        pager.finish().unwrap();
+    }
+}
@@ -0,0 +1,313 @@
+use crate::runner::RunEvent;
+use crate::stdlib::{ActionStatus, RunResult};
+use ansi_term::Colour::{Cyan, Green, Red, Yellow};
+use ansi_term::Style;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, IsTerminal, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Reports the nodes and actions of a run on stdout as they run, with how
+/// long each action took, and a summary of the run once it finishes. The
+/// lines are styled when stdout is a terminal and plain otherwise, they are
+/// never rewritten so the output of the actions can be interleaved with
+/// them.
+pub(crate) struct Progress {
+    out: Mutex<Box<dyn Write + Send>>,
+    styled: bool,
+    // when the actions which are running started, by their node and index
+    started: Mutex<HashMap<(String, usize), Instant>>,
+    // when the run started and the nodes which ran so far
+    run: Mutex<(Instant, RunResult)>,
+}
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Progress")
+            .field("styled", &self.styled)
+            .finish()
+    }
+}
+
+impl Progress {
+    pub(crate) fn stdout() -> Self {
+        Progress::new(Box::new(io::stdout()), io::stdout().is_terminal())
+    }
+
+    fn new(out: Box<dyn Write + Send>, styled: bool) -> Self {
+        Progress {
+            out: Mutex::new(out),
+            styled,
+            started: Mutex::new(HashMap::new()),
+            run: Mutex::new((Instant::now(), RunResult::default())),
+        }
+    }
+
+    /// Reports the event, called as it is published.
+    pub(crate) fn handle(&self, event: &RunEvent) {
+        match event {
+            RunEvent::Started { nodes, .. } => {
+                *self.run.lock().unwrap() = (
+                    Instant::now(),
+                    RunResult {
+                        graph_nodes: nodes.clone(),
+                        ..Default::default()
+                    },
+                );
+            }
+            RunEvent::NodeStarted { node } => self.node_started(node),
+            RunEvent::NodeFinished(node) => self.run.lock().unwrap().1.nodes.push(node.clone()),
+            RunEvent::ActionStarted { node, index, .. } => {
+                self.started
+                    .lock()
+                    .unwrap()
+                    .insert((node.clone(), *index), Instant::now());
+            }
+            RunEvent::ActionFinished {
+                node,
+                index,
+                status,
+            } => self.action_finished(node, *index, status),
+            RunEvent::Finished { .. } => {
+                let summary = {
+                    let (started, result) = &*self.run.lock().unwrap();
+                    summary(result, started.elapsed(), self.styled)
+                };
+                let mut out = self.out.lock().unwrap();
+                let _ = write!(out, "{}", summary).and_then(|_| out.flush());
+            }
+        }
+    }
+
+    fn paint(&self, style: Style, text: &str) -> String {
+        match self.styled {
+            true => style.paint(text).to_string(),
+            false => text.to_string(),
+        }
+    }
+
+    fn line(&self, line: String) {
+        let mut out = self.out.lock().unwrap();
+        // progress is best effort, it does not fail the run
+        let _ = writeln!(out, "{}", line).and_then(|_| out.flush());
+    }
+
+    fn node_started(&self, node: &str) {
+        let marker = if self.styled { "▶" } else { ">" };
+        self.line(format!(
+            "{} {}",
+            marker,
+            self.paint(Style::new().bold(), node)
+        ));
+    }
+
+    fn action_finished(&self, node: &str, index: usize, status: &ActionStatus) {
+        let elapsed = self
+            .started
+            .lock()
+            .unwrap()
+            .remove(&(node.to_string(), index))
+            .map(|started| started.elapsed())
+            .unwrap_or_default();
+        let line = match status {
+            ActionStatus::Ran(result) if result.success => format!(
+                "  {} {} ({})",
+                self.paint(Green.normal(), "ok"),
+                result.name,
+                format_duration(elapsed)
+            ),
+            ActionStatus::Ran(result) => format!(
+                "  {} {} ({}, exit code {})",
+                self.paint(Red.normal(), "failed"),
+                result.name,
+                format_duration(elapsed),
+                result.exit_code
+            ),
+            ActionStatus::Failed(error) => format!(
+                "  {} action {} ({}): {}",
+                self.paint(Red.normal(), "failed"),
+                index,
+                format_duration(elapsed),
+                error
+            ),
+        };
+        self.line(line);
+    }
+}
+
+/// Formats the duration to a tenth of a second, in minutes from a minute.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs_f64();
+    match secs < 60.0 {
+        true => format!("{:.1}s", secs),
+        false => format!(
+            "{}m{:02}s",
+            duration.as_secs() / 60,
+            duration.as_secs() % 60
+        ),
+    }
+}
+
+/// Returns a table of how each node of the run went, followed by the
+/// number of nodes which ran, were skipped and failed and how long the run
+/// took.
+pub(crate) fn summary(result: &RunResult, duration: Duration, styled: bool) -> String {
+    let paint = |colour: ansi_term::Colour, text: &str| match styled {
+        true => colour.paint(text).to_string(),
+        false => text.to_string(),
+    };
+    let mut rows: Vec<(&str, (ansi_term::Colour, &str), String)> = vec![];
+    for node in &result.nodes {
+        let status = match (node.cached, node.succeeded()) {
+            (true, _) => (Yellow, "unchanged"),
+            (false, true) => (Green, "ok"),
+            (false, false) => (Red, "failed"),
+        };
+        rows.push((&node.name, status, format_duration(node.duration)));
+    }
+    let not_run = result.skipped();
+    for name in &not_run {
+        rows.push((name, (Yellow, "skipped"), "-".to_string()));
+    }
+    let cached = result.nodes.iter().filter(|node| node.cached).count();
+    let failed = result.nodes.iter().filter(|node| !node.succeeded()).count();
+
+    let width = rows
+        .iter()
+        .map(|(name, _, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    let mut summary = String::from("\nSummary:\n");
+    for (name, (colour, status), duration) in &rows {
+        // padded before painting, the colour codes would count otherwise
+        summary.push_str(&format!(
+            "  {}  {}  {}\n",
+            paint(Cyan, &format!("{:width$}", name)),
+            paint(*colour, &format!("{:9}", status)),
+            duration
+        ));
+    }
+    summary.push_str(&format!(
+        "{} run, {} skipped, {} failed in {}\n",
+        result.nodes.len() - cached,
+        not_run.len() + cached,
+        failed,
+        format_duration(duration)
+    ));
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib::action::ActionResult;
+    use crate::stdlib::NodeResult;
+    use std::sync::Arc;
+
+    // a writer whose output can be read once the progress is done with it
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn node(name: &str, error: Option<&str>, cached: bool) -> NodeResult {
+        NodeResult {
+            name: name.to_string(),
+            duration: Duration::from_millis(1500),
+            exit_code: None,
+            exit_code_ok: None,
+            error: error.map(|e| e.to_string()),
+            memory: None,
+            variables: None,
+            envs: vec![],
+            artifacts: vec![],
+            cached,
+            approval: None,
+        }
+    }
+
+    #[test]
+    fn test_plain_progress() {
+        let output = Output::default();
+        let progress = Progress::new(Box::new(output.clone()), false);
+        let events = [
+            RunEvent::Started {
+                workflow: "ci.workflow".into(),
+                nodes: vec!["build".to_string(), "deploy".to_string()],
+            },
+            RunEvent::NodeStarted {
+                node: "build".to_string(),
+            },
+            RunEvent::ActionStarted {
+                node: "build".to_string(),
+                index: 0,
+                action: "cargo".to_string(),
+            },
+            RunEvent::ActionFinished {
+                node: "build".to_string(),
+                index: 0,
+                status: ActionStatus::Ran(ActionResult {
+                    name: "cargo".to_string(),
+                    exit_code: 101,
+                    success: false,
+                    duration_ms: 0,
+                }),
+            },
+            RunEvent::ActionFinished {
+                node: "build".to_string(),
+                index: 1,
+                status: ActionStatus::Failed("no such tool".to_string()),
+            },
+            RunEvent::NodeFinished(node("build", Some("no such tool"), false)),
+            RunEvent::Finished { succeeded: false },
+        ];
+        for event in &events {
+            progress.handle(event);
+        }
+        assert_eq!(
+            String::from_utf8(output.0.lock().unwrap().clone()).unwrap(),
+            "> build\n\
+             \x20 failed cargo (0.0s, exit code 101)\n\
+             \x20 failed action 1 (0.0s): no such tool\n\
+             \n\
+             Summary:\n\
+             \x20 build   failed     1.5s\n\
+             \x20 deploy  skipped    -\n\
+             1 run, 1 skipped, 1 failed in 0.0s\n"
+        );
+    }
+
+    #[test]
+    fn test_summary() {
+        let result = RunResult {
+            nodes: vec![
+                node("fetch", None, true),
+                node("build", None, false),
+                node("test", Some("exit code 1"), false),
+            ],
+            graph_nodes: ["fetch", "build", "test", "deploy"]
+                .map(String::from)
+                .to_vec(),
+            redactor: None,
+        };
+        assert_eq!(
+            summary(&result, Duration::from_secs(75), false),
+            "\nSummary:\n\
+             \x20 fetch   unchanged  1.5s\n\
+             \x20 build   ok         1.5s\n\
+             \x20 test    failed     1.5s\n\
+             \x20 deploy  skipped    -\n\
+             2 run, 2 skipped, 1 failed in 1m15s\n"
+        );
+        assert!(summary(&result, Duration::from_secs(1), true).contains("\x1b["));
+    }
+}
@@ -0,0 +1,177 @@
+use crate::cmd::{GlobalArgs, RunCommand};
+use crate::downcast_delegate_ref;
+use crate::runner::{Runner, WorkflowDelegate};
+use crate::stdlib::dev::dev_stdlib;
+use crate::stdlib::format::ValueFormatter;
+use crate::stdlib::variable_resolver::string_from_value;
+use crate::stdlib::VariableRef;
+use anyhow::bail;
+use clap::Args;
+use starlark::environment::Module;
+use starlark::eval::Evaluator;
+use starlark::values::Value;
+use std::io::{self, BufRead, Write};
+use std::ops::Deref;
+use std::path::PathBuf;
+
+const HELP: &str = "Enter starlark to evaluate it in the workflow's module.
+Use fake_ctx(stdout, stderr, exit_code) to call setter and next functions.
+  :vars   list the workflow's variables and their values
+  :help   show this message
+  :quit   exit the repl";
+
+#[derive(Args, Debug)]
+pub struct ReplArgs {
+    /// The path to the workflow to explore
+    pub workflow: PathBuf,
+
+    /// The additional arguments that will be passed along to the workflow
+    #[arg(allow_hyphen_values = true, trailing_var_arg = true)]
+    pub workflow_args: Vec<String>,
+}
+
+/// Formats a value for display, variables and formatters are resolved
+/// to their current values.
+fn display_value(value: Value, delegate: &WorkflowDelegate) -> anyhow::Result<String> {
+    if VariableRef::from_value(value).is_some() || ValueFormatter::from_value(value).is_some() {
+        string_from_value(value, delegate)
+    } else {
+        Ok(value.to_repr())
+    }
+}
+
+fn print_variables<W: Write>(
+    module: &Module,
+    delegate: &WorkflowDelegate,
+    output: &mut W,
+) -> anyhow::Result<()> {
+    for name in module.names() {
+        if let Some(value) = module.get(&name) {
+            if let Some(var) = VariableRef::from_value(value) {
+                let value = delegate
+                    .variable_store()
+                    .get_variable_value(var.identifier())
+                    .unwrap_or_else(|| "<no value>".to_string());
+                writeln!(output, "{} = {}", name.as_str(), value)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parses the workflow and then evaluates each line of the input in its
+/// module until the input ends or `:quit` is entered.
+pub(crate) fn run_repl<R: BufRead, W: Write>(
+    workflow: &PathBuf,
+    workflow_args: &[String],
+    input: R,
+    output: &mut W,
+    prompt: bool,
+) -> anyhow::Result<()> {
+    if !workflow.exists() {
+        bail!("Workflow does not exist at path {:?}", workflow);
+    }
+
+    let runner = Runner::new_with_globals(
+        workflow.clone(),
+        WorkflowDelegate::with_args(workflow_args.to_vec()),
+        &[dev_stdlib],
+    )?;
+    let module: Module = Module::new();
+    let mut eval: Evaluator = Evaluator::new(&module);
+    runner.parse_workflow(&mut eval)?;
+
+    let holder = runner.delegate();
+    let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+
+    let mut lines = input.lines();
+    loop {
+        if prompt {
+            write!(output, ">>> ")?;
+            output.flush()?;
+        }
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+        match line.trim() {
+            "" => (),
+            ":quit" | ":q" => break,
+            ":help" => writeln!(output, "{}", HELP)?,
+            ":vars" => print_variables(&module, delegate, output)?,
+            line => match runner
+                .eval_expression(line, &mut eval)
+                .and_then(|v| display_value(v, delegate).map(|s| (v.is_none(), s)))
+            {
+                Ok((true, _)) => (),
+                Ok((false, value)) => writeln!(output, "{}", value)?,
+                Err(e) => writeln!(output, "error: {:#}", e)?,
+            },
+        }
+    }
+    Ok(())
+}
+
+impl RunCommand for ReplArgs {
+    fn run(&self, global_args: &GlobalArgs) -> anyhow::Result<()> {
+        if !global_args.quiet {
+            println!("Exploring {:?}, type :help for help", self.workflow);
+        }
+        run_repl(
+            &self.workflow,
+            &self.workflow_args,
+            io::stdin().lock(),
+            &mut io::stdout(),
+            true,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib::test_utils::TempWorkflowFile;
+    use std::io::Cursor;
+
+    fn repl(input: &str) -> String {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+version = variable(default = "1.0")
+def double(x):
+    return x * 2
+"#,
+        )
+        .unwrap();
+        let mut output = vec![];
+        run_repl(&file.path(), &[], Cursor::new(input), &mut output, false).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_repl_evaluates_lines() {
+        assert_eq!(repl("double(2)\ny = 'a'\ny\n"), "4\n\"a\"\n");
+    }
+
+    #[test]
+    fn test_repl_resolves_variables() {
+        assert_eq!(repl("version\n:vars\n"), "1.0\nversion = 1.0\n");
+    }
+
+    #[test]
+    fn test_repl_reports_errors_and_continues() {
+        let output = repl("unknown\n1 + 1\n");
+        assert!(output.starts_with("error: "));
+        assert!(output.ends_with("2\n"));
+    }
+
+    #[test]
+    fn test_repl_fake_ctx() {
+        assert_eq!(repl("fake_ctx(exit_code = 2).exit_code\n"), "2\n");
+    }
+
+    #[test]
+    fn test_repl_quit() {
+        assert_eq!(repl(":quit\n1\n"), "");
+    }
+}
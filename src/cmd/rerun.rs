@@ -0,0 +1,130 @@
+use crate::cmd::run::{check_result, run_and_record, RunOptions};
+use crate::cmd::{GlobalArgs, RunCommand};
+use crate::runner::{History, HistoryRecord};
+use anyhow::bail;
+use clap::Args;
+
+#[derive(Args, Debug)]
+pub struct RerunArgs {
+    /// Start at the first node that failed in the last run instead of
+    /// the entrypoint. The variables are restored to their values from
+    /// before the node failed.
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub last_failed: bool,
+}
+
+impl RerunArgs {
+    /// Returns the node to start at when re-running the record.
+    fn start_at(&self, record: &HistoryRecord) -> anyhow::Result<Option<String>> {
+        if !self.last_failed {
+            return Ok(None);
+        }
+        if let Some(node) = record.first_failed_node() {
+            return Ok(Some(node.name.clone()));
+        }
+        if record.succeeded() {
+            bail!(
+                "The last run of {:?} succeeded, there is no failed node to start from",
+                record.workflow
+            );
+        }
+        // The run failed before any node ran so start from the beginning.
+        Ok(None)
+    }
+}
+
+impl RunCommand for RerunArgs {
+    fn run(&self, global_args: &GlobalArgs) -> anyhow::Result<()> {
+        let history = History::default_location()?;
+        let record = match history.last()? {
+            Some(record) => record,
+            None => bail!("No previous runs found in {:?}", history.path()),
+        };
+        let start_at = self.start_at(&record)?;
+
+        if !global_args.quiet {
+            println!("Re-running {:?} {}", record.workflow, record.args.join(" "));
+        }
+        let checkpoint = match self.last_failed {
+            true => record.checkpoint_before_failure().cloned(),
+            false => None,
+        };
+        let result = run_and_record(
+            &record.workflow,
+            &record.args,
+            true,
+            RunOptions {
+                start_at,
+                checkpoint,
+                check_args: record.check_args,
+                env_capture: global_args.env_capture(),
+                quiet: global_args.quiet,
+                tag_filter: record.tag_filter(),
+                log_dir: record.log_dir.clone(),
+                sandbox: record.sandboxed,
+                prompt: !global_args.quiet,
+                failure_injection: record.failure_injection,
+                ..Default::default()
+            },
+        )?;
+        check_result(&result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::NodeRecord;
+    use std::path::PathBuf;
+
+    fn record(nodes: Vec<(&str, i32)>) -> HistoryRecord {
+        let mut record = HistoryRecord::new(PathBuf::from("/foo.workflow"), vec![], None);
+        record.nodes = nodes
+            .into_iter()
+            .map(|(name, exit_code)| NodeRecord {
+                name: name.to_string(),
+                duration_ms: 0,
+                exit_code: Some(exit_code),
+                exit_code_ok: None,
+                error: None,
+                variables: None,
+                envs: vec![],
+                artifacts: vec![],
+                cached: false,
+                approval: None,
+            })
+            .collect();
+        record
+    }
+
+    #[test]
+    fn test_start_at_without_last_failed() {
+        let args = RerunArgs { last_failed: false };
+        assert_eq!(args.start_at(&record(vec![("a", 1)])).unwrap(), None);
+    }
+
+    #[test]
+    fn test_start_at_first_failed_node() {
+        let args = RerunArgs { last_failed: true };
+        assert_eq!(
+            args.start_at(&record(vec![("a", 0), ("b", 1), ("c", 1)]))
+                .unwrap(),
+            Some("b".to_string())
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "there is no failed node to start from")]
+    fn test_start_at_fails_if_last_run_succeeded() {
+        let args = RerunArgs { last_failed: true };
+        args.start_at(&record(vec![("a", 0)])).unwrap();
+    }
+
+    #[test]
+    fn test_start_at_run_failed_before_nodes() {
+        let args = RerunArgs { last_failed: true };
+        let mut r = record(vec![]);
+        r.error = Some("parse error".to_string());
+        assert_eq!(args.start_at(&r).unwrap(), None);
+    }
+}
@@ -1,48 +1,1302 @@
-use crate::cmd::{GlobalArgs, RunCommand};
+use crate::cmd::progress::Progress;
+use crate::cmd::{Cli, GlobalArgs, RunCommand};
 use crate::downcast_delegate_ref;
-use crate::runner::{Runner, WorkflowDelegate};
-use crate::stdlib::Workflow;
+use crate::runner::{
+    arguments_help, run_graph_dot, run_graph_mermaid, Combination, EventHandler, History,
+    HistoryRecord, Matrix, NodeCache, PromptApprover, Runner, TerminalPrompter, WorkflowDelegate,
+};
+use crate::stdlib::env_capture::EnvCapture;
+use crate::stdlib::failure_injection::FailureInjection;
+use crate::stdlib::ir::WorkflowIr;
+use crate::stdlib::plan::NodePlan;
+use crate::stdlib::variable_resolver::VariableSnapshot;
+use crate::stdlib::{RunResult, TagFilter};
 use anyhow::bail;
-use clap::Args;
+use clap::{Args, CommandFactory};
 use starlark::environment::Module;
 use starlark::eval::Evaluator;
 use std::ops::Deref;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
 
 #[derive(Args, Debug)]
+#[command(disable_help_flag = true)]
 pub struct RunArgs {
-    /// The path to the workflow to describe
-    pub workflow: PathBuf,
+    /// The path to the workflow to run, or to a workflow compiled to json
+    #[arg(required_unless_present = "help")]
+    pub workflow: Option<PathBuf>,
+
+    /// Writes a graph of the run highlighting the path taken to this file,
+    /// as mermaid for .mmd files and DOT otherwise
+    #[arg(long)]
+    pub graph: Option<PathBuf>,
+
+    /// Reports the starlark heap and process memory after each node
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub profile_memory: bool,
+
+    /// Fails an action before it runs if one of its resolved arguments
+    /// contains a newline or NUL byte
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub check_args: bool,
+
+    /// Prints the commands each node would run, with their tools and args
+    /// resolved, instead of running the workflow
+    #[arg(long, conflicts_with_all = ["graph", "profile_memory"], action = clap::ArgAction::SetTrue)]
+    pub dry_run: bool,
+
+    /// Runs the workflow once for every combination of the values of the
+    /// variables, given as <variable>=<value>,<value>,... and repeated for
+    /// each variable. Replaces the workflow's matrix for the same variable
+    #[arg(long, value_name = "AXIS")]
+    pub matrix: Vec<String>,
+
+    /// How many combinations of the matrix run at once
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    pub matrix_jobs: usize,
+
+    /// Runs the cached nodes even if they are unchanged since their last
+    /// successful run
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub no_skip: bool,
+
+    /// Writes the output of each action to a log file in this directory,
+    /// at <node>/<attempt>/<action>-stdout.log and -stderr.log
+    #[arg(long, value_name = "DIR")]
+    pub log_dir: Option<PathBuf>,
+
+    /// Runs the workflow in a new directory holding copies of the inputs it
+    /// declares, only the outputs it declares are copied back once it
+    /// succeeds. This is best-effort isolation, tools can still write
+    /// outside of the directory
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub sandbox: bool,
+
+    /// Makes tools fail at random instead of running, to check how the
+    /// workflow handles failures. Given as rate=<0 to 1>[,seed=<n>], the
+    /// seed is recorded in the history so `rerun` fails the same tools
+    #[arg(long, value_name = "SPEC")]
+    pub inject_failures: Option<String>,
+
+    /// Fails instead of asking on the terminal for the values of the
+    /// required variables which have none
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub no_input: bool,
+
+    /// Skips the nodes with this tag, can be repeated
+    #[arg(long, value_name = "TAG")]
+    pub skip_tag: Vec<String>,
+
+    /// Skips the nodes which have none of these tags, can be repeated
+    #[arg(long, value_name = "TAG")]
+    pub only_tag: Vec<String>,
+
+    /// Prints help, followed by the arguments the workflow takes if one is
+    /// given
+    #[arg(short, long, action = clap::ArgAction::SetTrue)]
+    pub help: bool,
 
     /// The additional arguments that will be passed along to the workflow
     #[arg(allow_hyphen_values = true, trailing_var_arg = true)]
     pub workflow_args: Vec<String>,
 }
 
-impl RunCommand for RunArgs {
-    fn run(&self, _global_args: &GlobalArgs) -> anyhow::Result<()> {
-        if self.workflow.exists() {
-            let runner = Runner::new(
-                self.workflow.clone(),
-                WorkflowDelegate::with_args(self.workflow_args.clone()),
-            )?;
-            let module: Module = Module::new();
-            let mut eval: Evaluator = Evaluator::new(&module);
-
-            let _result = runner.parse_workflow(&mut eval).unwrap();
-
-            let holder = runner.delegate();
-            let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
-            let working_dir = runner.working_dir();
-
-            // TOOD: add run_workflow function instead of looking for main
-            if let Some(main) = module.get("main") {
-                let workflow = Workflow::from_value(main).unwrap();
-                workflow.run(delegate, &working_dir, &mut eval)?;
+/// How a workflow is run, apart from the workflow and its arguments.
+#[derive(Default)]
+pub(crate) struct RunOptions {
+    /// The node to start at instead of the entrypoint.
+    pub start_at: Option<String>,
+    /// Whether the memory in use is recorded after each node.
+    pub profile_memory: bool,
+    /// The variables restored before the run starts.
+    pub checkpoint: Option<VariableSnapshot>,
+    /// Whether the resolved args of the actions are checked before they run.
+    pub check_args: bool,
+    /// How much of the environment of each action is recorded.
+    pub env_capture: EnvCapture,
+    /// Whether the output of the actions is hidden.
+    pub quiet: bool,
+    /// Picks the nodes which run, the others are skipped.
+    pub tag_filter: TagFilter,
+    /// The directory the artifacts of the nodes which succeed are copied
+    /// into, None if they are not collected.
+    pub artifacts_dir: Option<PathBuf>,
+    /// The outputs of the cached nodes, the nodes which it recorded
+    /// unchanged are skipped.
+    pub node_cache: Option<NodeCache>,
+    /// The directory the output of the actions is logged in.
+    pub log_dir: Option<PathBuf>,
+    /// Whether the run works on copies of the workflow's inputs.
+    pub sandbox: bool,
+    /// Whether the values of the required variables which have none are
+    /// asked for on the terminal, otherwise the run fails.
+    pub prompt: bool,
+    /// What is called with the events of the run as they are published.
+    pub event_handler: Option<EventHandler>,
+    /// How tools are made to fail at random instead of running.
+    pub failure_injection: Option<FailureInjection>,
+}
+
+/// Parses and runs the workflow with its arguments as the options say.
+pub(crate) fn run_workflow(
+    workflow: &PathBuf,
+    workflow_args: &[String],
+    options: RunOptions,
+) -> anyhow::Result<RunResult> {
+    if !workflow.exists() {
+        bail!("Workflow does not exist at path {:?}", workflow);
+    }
+
+    let mut delegate = WorkflowDelegate::with_args(workflow_args.to_vec())
+        .with_check_args(options.check_args)
+        .with_env_capture(options.env_capture)
+        .with_quiet(options.quiet)
+        .with_approver(Arc::new(PromptApprover));
+    if let Some(dir) = options.artifacts_dir {
+        delegate = delegate.with_artifacts_dir(dir);
+    }
+    if let Some(node_cache) = options.node_cache {
+        delegate = delegate.with_node_cache(node_cache);
+    }
+    if let Some(dir) = options.log_dir {
+        delegate = delegate.with_log_dir(dir);
+    }
+    if options.prompt {
+        delegate = delegate.with_prompter(Arc::new(TerminalPrompter));
+    }
+    if let Some(handler) = options.event_handler {
+        delegate = delegate.with_handler(handler);
+    }
+    if let Some(injection) = options.failure_injection {
+        delegate = delegate.with_failure_injection(injection);
+    }
+    let runner = open_workflow(workflow, delegate)?;
+    runner.set_profile_memory(options.profile_memory);
+    runner.set_tag_filter(options.tag_filter);
+    runner.set_sandboxed(options.sandbox);
+    if let Some(checkpoint) = options.checkpoint {
+        runner.set_checkpoint(checkpoint);
+    }
+    runner.run(options.start_at.as_deref())
+}
+
+/// Returns a runner for the workflow file, or for the compiled workflow if
+/// it is a .json file written by `workflow compile`, which runs in the
+/// directory the file is in.
+pub(crate) fn open_workflow(workflow: &Path, delegate: WorkflowDelegate) -> anyhow::Result<Runner> {
+    if workflow.extension().and_then(|ext| ext.to_str()) != Some("json") {
+        return Runner::new(workflow.to_path_buf(), delegate);
+    }
+    let ir = WorkflowIr::from_json(&std::fs::read_to_string(workflow)?)?;
+    let working_dir = match workflow.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    Runner::from_ir(working_dir, &ir, delegate)
+}
+
+/// Parses the workflow and returns what running it, starting at the node
+/// named `start_at` if given, would do. Nothing is run or recorded.
+pub(crate) fn plan_workflow(
+    workflow: &PathBuf,
+    workflow_args: &[String],
+    start_at: Option<&str>,
+    tag_filter: TagFilter,
+) -> anyhow::Result<Vec<NodePlan>> {
+    if !workflow.exists() {
+        bail!("Workflow does not exist at path {:?}", workflow);
+    }
+
+    let runner = open_workflow(
+        workflow,
+        WorkflowDelegate::with_args(workflow_args.to_vec()),
+    )?;
+    runner.set_tag_filter(tag_filter);
+    runner.plan(start_at)
+}
+
+/// Runs the workflow and records the invocation in the history, along
+/// with the options a rerun repeats. The artifacts of the run are collected
+/// in a new directory next to it and the outputs of the cached nodes are
+/// recorded, replacing those of the options. The cached nodes which are
+/// unchanged are skipped if `skip_unchanged` is set.
+///
+/// Failing to write the history is reported but does not fail the run.
+pub(crate) fn run_and_record(
+    workflow: &PathBuf,
+    workflow_args: &[String],
+    skip_unchanged: bool,
+    mut options: RunOptions,
+) -> anyhow::Result<RunResult> {
+    let workflow = std::fs::canonicalize(workflow).unwrap_or(workflow.clone());
+    let mut record = HistoryRecord::new(
+        workflow.clone(),
+        workflow_args.to_vec(),
+        options.start_at.clone(),
+    );
+    record.checkpoint = options.checkpoint.clone();
+    record.check_args = options.check_args;
+    record.skip_tags = options.tag_filter.skip.clone();
+    record.only_tags = options.tag_filter.only.clone();
+    record.log_dir = options.log_dir.clone();
+    record.sandboxed = options.sandbox;
+    record.failure_injection = options.failure_injection;
+    let history = History::default_location();
+    record.artifacts_dir = history.as_ref().ok().map(History::new_artifacts_dir);
+    options.artifacts_dir = record.artifacts_dir.clone();
+    options.node_cache = match NodeCache::for_workflow(&workflow) {
+        Ok(node_cache) => Some(node_cache.with_reuse(skip_unchanged)),
+        Err(e) => {
+            eprintln!("Unable to open the node cache: {:#}", e);
+            None
+        }
+    };
+
+    let started = Instant::now();
+    let result = run_workflow(&workflow, workflow_args, options);
+    record.finish(&result, started.elapsed().as_millis() as u64);
+
+    if let Err(e) = history.and_then(|h| h.append(&record)) {
+        eprintln!("Unable to record run history: {:#}", e);
+    }
+
+    result
+}
+
+/// Parses the workflow and returns the help for the arguments it takes,
+/// one for each of its variables which can be set.
+pub(crate) fn workflow_help(workflow: &PathBuf) -> anyhow::Result<String> {
+    if !workflow.exists() {
+        bail!("Workflow does not exist at path {:?}", workflow);
+    }
+
+    let runner = open_workflow(workflow, WorkflowDelegate::new())?;
+    let module: Module = Module::new();
+    let mut eval: Evaluator = Evaluator::new(&module);
+    runner.parse_workflow(&mut eval)?;
+    let holder = runner.delegate();
+    let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+    Ok(arguments_help(&delegate.variable_store().arguments()))
+}
+
+/// Prints the help of the run command, followed by the arguments of the
+/// workflow if given.
+fn print_help(workflow: Option<&PathBuf>) -> anyhow::Result<()> {
+    let mut command = Cli::command();
+    command.build();
+    if let Some(run) = command.find_subcommand_mut("run") {
+        print!("{}", run.render_help());
+    }
+    if let Some(workflow) = workflow {
+        print!("\n{}", workflow_help(workflow)?);
+    }
+    Ok(())
+}
+
+/// Parses the workflow and returns its matrix with the `axes` given as
+/// `<variable>=<value>,...` added to it.
+pub(crate) fn load_matrix(workflow: &PathBuf, axes: &[String]) -> anyhow::Result<Matrix> {
+    if !workflow.exists() {
+        bail!("Workflow does not exist at path {:?}", workflow);
+    }
+
+    let axes = axes
+        .iter()
+        .map(|spec| Matrix::parse_axis(spec))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let runner = open_workflow(workflow, WorkflowDelegate::new())?;
+    runner.matrix(&axes)
+}
+
+/// Calls `run` for each of the combinations, with up to `jobs` of them
+/// running at once, and returns what each returned in the order of the
+/// combinations.
+fn run_combinations<R: Send>(
+    combinations: &[Combination],
+    jobs: usize,
+    run: impl Fn(&Combination) -> R + Sync,
+) -> Vec<R> {
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(combinations.iter().map(|_| None).collect());
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, combinations.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                let combination = match combinations.get(index) {
+                    Some(combination) => combination,
+                    None => break,
+                };
+                let result = run(combination);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("every combination is run"))
+        .collect()
+}
+
+/// Returns a report of whether the run of each combination succeeded.
+fn matrix_report(results: &[(Combination, anyhow::Result<()>)]) -> String {
+    let mut report = String::from("Matrix results:\n");
+    for (combination, result) in results {
+        match result {
+            Ok(()) => report.push_str(&format!("  {}: ok\n", combination)),
+            Err(e) => report.push_str(&format!("  {}: failed, {:#}\n", combination, e)),
+        }
+    }
+    report
+}
+
+/// Fails if a node stopped the run with an error.
+pub(crate) fn check_result(result: &RunResult) -> anyhow::Result<()> {
+    if let Some(node) = result.error() {
+        bail!(
+            "Node '{}' failed: {}",
+            node.name,
+            node.error.clone().unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+/// Writes the graph of the run to the path, as mermaid if the file has a
+/// `.mmd` or `.mermaid` extension and as DOT otherwise.
+fn write_run_graph(path: &Path, result: &RunResult) -> anyhow::Result<()> {
+    let graph = match path.extension().and_then(|e| e.to_str()) {
+        Some("mmd") | Some("mermaid") => run_graph_mermaid(result),
+        _ => run_graph_dot(result),
+    };
+    std::fs::write(path, graph)?;
+    Ok(())
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Returns a list of the artifacts collected by each node, None if there
+/// are none.
+fn artifacts_report(result: &RunResult) -> Option<String> {
+    let mut report = String::from("Artifacts:\n");
+    let mut any = false;
+    for node in &result.nodes {
+        for artifact in &node.artifacts {
+            report.push_str(&format!("  {}: {}\n", node.name, artifact.display()));
+            any = true;
+        }
+    }
+    any.then_some(report)
+}
+
+/// Returns the nodes which were skipped as they are unchanged since their
+/// last successful run, None if there are none.
+fn cached_report(result: &RunResult) -> Option<String> {
+    let cached: Vec<&str> = result
+        .nodes
+        .iter()
+        .filter(|node| node.cached)
+        .map(|node| node.name.as_str())
+        .collect();
+    (!cached.is_empty()).then(|| format!("Unchanged, not run: {}\n", cached.join(", ")))
+}
+
+/// Returns a report of the memory in use after each node ran.
+fn memory_report(result: &RunResult) -> String {
+    let mut report = String::from("Memory profile:\n");
+    for node in &result.nodes {
+        if let Some(memory) = &node.memory {
+            report.push_str(&format!(
+                "  {}: heap = {} ({} values), rss = {}\n",
+                node.name,
+                format_bytes(memory.heap_bytes as u64),
+                memory.heap_values,
+                memory
+                    .rss_bytes
+                    .map(format_bytes)
+                    .unwrap_or_else(|| "-".to_string()),
+            ));
+        }
+    }
+    report
+}
+
+impl RunArgs {
+    /// Runs, or plans with --dry-run, the workflow once for each
+    /// combination of the matrix and reports how each went.
+    fn run_matrix(
+        &self,
+        workflow: &PathBuf,
+        global_args: &GlobalArgs,
+        matrix: &Matrix,
+        tag_filter: TagFilter,
+        failure_injection: Option<FailureInjection>,
+    ) -> anyhow::Result<()> {
+        if self.graph.is_some() || self.profile_memory {
+            bail!("--graph and --profile-memory can not be used with a matrix");
+        }
+        if self.log_dir.is_some() {
+            bail!("--log-dir can not be used with a matrix");
+        }
+        if self.matrix_jobs == 0 {
+            bail!("--matrix-jobs must be at least 1");
+        }
+        let combinations = matrix.combinations();
+        let args = |combination: &Combination| {
+            let mut args = self.workflow_args.clone();
+            args.extend(combination.var_args());
+            args
+        };
+
+        if self.dry_run {
+            for combination in &combinations {
+                let plans = plan_workflow(workflow, &args(combination), None, tag_filter.clone())?;
+                let plans: Vec<String> = plans.iter().map(|p| p.to_string()).collect();
+                println!("[{}]\n{}\n", combination, plans.join("\n\n"));
             }
-        } else {
-            bail!("Workflow does not exist at path {:?}", self.workflow);
+            return Ok(());
+        }
+
+        let results = run_combinations(&combinations, self.matrix_jobs, |combination| {
+            run_and_record(
+                workflow,
+                &args(combination),
+                !self.no_skip,
+                RunOptions {
+                    check_args: self.check_args,
+                    env_capture: global_args.env_capture(),
+                    quiet: global_args.quiet,
+                    tag_filter: tag_filter.clone(),
+                    sandbox: self.sandbox,
+                    // the combinations would all ask for the same values
+                    prompt: false,
+                    failure_injection,
+                    ..Default::default()
+                },
+            )
+            .and_then(|result| check_result(&result))
+        });
+        let results: Vec<(Combination, anyhow::Result<()>)> =
+            combinations.into_iter().zip(results).collect();
+        print!("{}", matrix_report(&results));
+
+        let failed = results.iter().filter(|(_, r)| r.is_err()).count();
+        if failed > 0 {
+            bail!("{} of {} matrix combinations failed", failed, results.len());
         }
         Ok(())
     }
 }
+
+impl RunCommand for RunArgs {
+    fn run(&self, global_args: &GlobalArgs) -> anyhow::Result<()> {
+        let workflow = match &self.workflow {
+            Some(workflow) if !self.help => workflow,
+            workflow => return print_help(workflow.as_ref()),
+        };
+        let tag_filter = TagFilter::new(self.skip_tag.clone(), self.only_tag.clone());
+        let failure_injection = self
+            .inject_failures
+            .as_deref()
+            .map(FailureInjection::parse)
+            .transpose()?;
+        if let (Some(injection), false) = (failure_injection, global_args.quiet || self.dry_run) {
+            // the seed is shown as it may have been picked at random
+            eprintln!("Injecting failures with {}", injection);
+        }
+        let matrix = load_matrix(workflow, &self.matrix)?;
+        if !matrix.is_empty() {
+            return self.run_matrix(
+                workflow,
+                global_args,
+                &matrix,
+                tag_filter,
+                failure_injection,
+            );
+        }
+        if self.dry_run {
+            let plans = plan_workflow(workflow, &self.workflow_args, None, tag_filter)?;
+            let plans: Vec<String> = plans.iter().map(|p| p.to_string()).collect();
+            println!("{}", plans.join("\n\n"));
+            return Ok(());
+        }
+        // reports the run, ending with its summary, unless it is quiet
+        let progress: Option<EventHandler> = match global_args.quiet {
+            true => None,
+            false => {
+                let progress = Progress::stdout();
+                Some(Arc::new(move |event| progress.handle(event)))
+            }
+        };
+        let result = run_and_record(
+            workflow,
+            &self.workflow_args,
+            !self.no_skip,
+            RunOptions {
+                profile_memory: self.profile_memory,
+                check_args: self.check_args,
+                env_capture: global_args.env_capture(),
+                quiet: global_args.quiet,
+                tag_filter,
+                log_dir: self.log_dir.clone(),
+                sandbox: self.sandbox,
+                prompt: !self.no_input && !global_args.quiet,
+                event_handler: progress,
+                failure_injection,
+                ..Default::default()
+            },
+        )?;
+        if let Some(path) = &self.graph {
+            write_run_graph(path, &result)?;
+        }
+        if self.profile_memory {
+            print!("{}", memory_report(&result));
+        }
+        if let Some(report) = cached_report(&result) {
+            print!("{}", report);
+        }
+        if let Some(report) = artifacts_report(&result) {
+            print!("{}", report);
+        }
+        check_result(&result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib::plan::PlannedAction;
+    use crate::stdlib::test_utils::TempWorkflowFile;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_plan_does_not_run() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+out = variable(default = "created.txt")
+later = variable(cli_flag = "--later")
+
+def _noop():
+    return None
+
+def _to_b(ctx, args):
+    return "b"
+
+main = workflow(
+    entrypoint = "b",
+    graph = [
+        sequence(
+            name = "a",
+            actions = [
+                action(tool = builtin_tool(name = "touch"), args = [out]),
+                fn_action(implementation = _noop),
+            ],
+            next = next(implementation = _to_b)(),
+        ),
+        node(name = "b", action = action(tool = builtin_tool(name = "echo"), args = [later])),
+    ],
+)
+"#,
+        )
+        .unwrap();
+
+        let plans = plan_workflow(&file.path(), &[], None, TagFilter::default()).unwrap();
+        let names: Vec<&str> = plans.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["b", "a"]);
+        assert!(matches!(
+            plans[0].actions[0],
+            PlannedAction::Unresolved { .. }
+        ));
+        assert_eq!(plans[0].next, None);
+        match &plans[1].actions[0] {
+            PlannedAction::Command { program, args, .. } => {
+                assert!(program.ends_with("touch"));
+                assert_eq!(args, &["created.txt"]);
+            }
+            action => panic!("expected a command, got {:?}", action),
+        }
+        assert_eq!(
+            plans[1].actions[1],
+            PlannedAction::InProcess {
+                label: "fn_action".to_string(),
+                args: vec![],
+            }
+        );
+        assert_eq!(plans[1].next.as_deref(), Some("_to_b"));
+        assert!(!file.path().with_file_name("created.txt").exists());
+
+        let plans = plan_workflow(
+            &file.path(),
+            &["--later".to_string(), "x".to_string()],
+            Some("a"),
+            TagFilter::default(),
+        )
+        .unwrap();
+        assert_eq!(plans[0].name, "a");
+        assert!(plans[1].actions[0].to_string().ends_with("echo x"));
+    }
+
+    #[test]
+    fn test_write_run_graph_format_from_extension() {
+        let dir = tempdir().unwrap();
+        let result = RunResult::default();
+
+        let dot = dir.path().join("run.dot");
+        write_run_graph(&dot, &result).unwrap();
+        assert!(std::fs::read_to_string(&dot)
+            .unwrap()
+            .starts_with("digraph"));
+
+        let mermaid = dir.path().join("run.mmd");
+        write_run_graph(&mermaid, &result).unwrap();
+        assert!(std::fs::read_to_string(&mermaid)
+            .unwrap()
+            .starts_with("flowchart"));
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024), "3.0 MiB");
+    }
+
+    #[test]
+    fn test_inject_failures() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+main = workflow(
+    entrypoint = "a",
+    graph = [
+        node(name = "a", action = action(tool = builtin_tool(name = "true"), retries = 2, cwd = ".")),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        let run = |rate: f64| {
+            run_workflow(
+                &file.path(),
+                &[],
+                RunOptions {
+                    quiet: true,
+                    failure_injection: Some(FailureInjection { rate, seed: 42 }),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+
+        // every retry of the action fails as well
+        let result = run(1.0);
+        assert!(!result.succeeded());
+        assert_eq!(result.nodes[0].exit_code, Some(1));
+        assert!(run(0.0).succeeded());
+    }
+
+    #[test]
+    fn test_profile_memory_records_each_node() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+def _noop():
+    return None
+
+main = workflow(
+    entrypoint = "a",
+    graph = [node(name = "a", action = fn_action(implementation = _noop))],
+)
+"#,
+        )
+        .unwrap();
+
+        let result = run_workflow(&file.path(), &[], RunOptions::default()).unwrap();
+        assert_eq!(result.nodes[0].memory, None);
+
+        let result = run_workflow(
+            &file.path(),
+            &[],
+            RunOptions {
+                profile_memory: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let memory = result.nodes[0].memory.unwrap();
+        assert!(memory.heap_values > 0);
+
+        let report = memory_report(&result);
+        assert!(report.starts_with("Memory profile:\n  a: heap = "));
+    }
+
+    #[test]
+    fn test_resume_from_checkpoint() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+v = variable(default = "unset")
+def _set():
+    return "set"
+
+def _stdout(ctx):
+    return ctx.stdout
+
+def _to_b(ctx, args):
+    return "b"
+
+def _check(v):
+    return 0 if v == "set" else 1
+
+main = workflow(
+    entrypoint = "a",
+    graph = [
+        node(
+            name = "a",
+            action = fn_action(
+                implementation = _set,
+                setters = [setter(implementation = _stdout, variable = v)],
+            ),
+            next = next(implementation = _to_b)(),
+        ),
+        node(name = "b", action = fn_action(implementation = _check, args = [v])),
+    ],
+)
+"#,
+        )
+        .unwrap();
+
+        let result = run_workflow(&file.path(), &[], RunOptions::default()).unwrap();
+        assert!(result.succeeded());
+        let checkpoint = result.nodes[0].variables.clone().unwrap();
+        assert_eq!(checkpoint["v"].value, "set");
+
+        // without the checkpoint b sees the default value
+        let result = run_workflow(
+            &file.path(),
+            &[],
+            RunOptions {
+                start_at: Some("b".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(result.nodes[0].exit_code, Some(1));
+
+        let result = run_workflow(
+            &file.path(),
+            &[],
+            RunOptions {
+                start_at: Some("b".to_string()),
+                checkpoint: Some(checkpoint),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(result.nodes[0].exit_code, Some(0));
+    }
+
+    #[test]
+    fn test_records_env_of_spawned_tools() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+def _noop():
+    return None
+
+main = workflow(
+    entrypoint = "a",
+    graph = [
+        sequence(
+            name = "a",
+            actions = [
+                action(tool = builtin_tool(name = "true")),
+                fn_action(implementation = _noop),
+            ],
+        ),
+    ],
+)
+"#,
+        )
+        .unwrap();
+
+        let result = run_workflow(&file.path(), &[], RunOptions::default()).unwrap();
+        let envs = &result.nodes[0].envs;
+        assert_eq!(envs.len(), 1);
+        assert_eq!(envs[0].action, "true");
+        assert_eq!(envs[0].vars, None);
+
+        let result = run_workflow(
+            &file.path(),
+            &[],
+            RunOptions {
+                env_capture: EnvCapture::Full,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let env = &result.nodes[0].envs[0];
+        assert_eq!(
+            env.vars.as_ref().unwrap().get("PATH"),
+            std::env::var("PATH").ok().as_ref()
+        );
+    }
+
+    #[test]
+    fn test_check_args() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+message = variable(default = "two\nlines")
+def _echo(a, b):
+    return a + b
+
+main = workflow(
+    entrypoint = "a",
+    graph = [
+        node(
+            name = "a",
+            action = fn_action(implementation = _echo, args = ["ok", format("-{}-", message)]),
+        ),
+    ],
+)
+"#,
+        )
+        .unwrap();
+
+        let result = run_workflow(&file.path(), &[], RunOptions::default()).unwrap();
+        assert!(result.succeeded());
+
+        let result = run_workflow(
+            &file.path(),
+            &[],
+            RunOptions {
+                check_args: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            result.nodes[0].error.as_deref(),
+            Some("argument 2 of the action running 'fn_action' contains a newline from variable 'message'")
+        );
+    }
+
+    #[test]
+    fn test_workflow_arguments() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+profile = variable(default = "debug", cli_flag = "--profile", doc = "The build profile")
+region = variable(env = "REGION", required = True)
+
+main = workflow(
+    entrypoint = "a",
+    graph = [
+        node(name = "a", action = action(tool = builtin_tool(name = "echo"), args = [profile])),
+    ],
+)
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            workflow_help(&file.path()).unwrap(),
+            "Workflow arguments:\n\
+             \x20 --profile <VALUE>     The build profile [default: debug]\n\
+             \x20 --var region=<VALUE>  [env: REGION] [required]\n\
+             \n\
+             Every variable can also be set with --var <name>=<value>\n"
+        );
+
+        let err = run_workflow(
+            &file.path(),
+            &["--profle".to_string(), "release".to_string()],
+            RunOptions {
+                quiet: true,
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown argument '--profle', see --help for the arguments of the workflow"
+        );
+    }
+
+    #[test]
+    fn test_collects_artifacts() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+def _exit(code):
+    return int(code)
+
+main = workflow(
+    entrypoint = "build",
+    graph = [
+        node(
+            name = "build",
+            action = fn_action(implementation = _exit, args = ["0"]),
+            next = next(implementation = lambda ctx, args: "upload")(),
+            artifacts = ["dist/*.tar.gz", "dist/**/*.tar.gz"],
+        ),
+        node(
+            name = "upload",
+            action = fn_action(implementation = _exit, args = ["1"]),
+            artifacts = ["dist/*"],
+        ),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        let working_dir = file.path().parent().unwrap().to_path_buf();
+        for path in ["dist/a.tar.gz", "dist/notes.txt", "dist/sub/b.tar.gz"] {
+            std::fs::create_dir_all(working_dir.join(path).parent().unwrap()).unwrap();
+            std::fs::write(working_dir.join(path), path).unwrap();
+        }
+        let artifacts = tempdir().unwrap();
+
+        let result = run_workflow(
+            &file.path(),
+            &[],
+            RunOptions {
+                artifacts_dir: Some(artifacts.path().to_path_buf()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let build = artifacts.path().join("build");
+        assert_eq!(
+            result.nodes[0].artifacts,
+            [build.join("dist/a.tar.gz"), build.join("dist/sub/b.tar.gz")]
+        );
+        assert_eq!(
+            std::fs::read_to_string(build.join("dist/sub/b.tar.gz")).unwrap(),
+            "dist/sub/b.tar.gz"
+        );
+        // the upload failed so nothing it produced is kept
+        assert!(result.nodes[1].artifacts.is_empty());
+        assert!(!artifacts.path().join("upload").exists());
+        assert!(artifacts_report(&result).unwrap().contains(&format!(
+            "  build: {}\n",
+            build.join("dist/a.tar.gz").display()
+        )));
+    }
+
+    #[test]
+    fn test_artifact_args() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+def _build():
+    return 0
+
+def _deploy(*paths):
+    if [p.split("/build/")[1] for p in paths] != ["dist/a.tar.gz"]:
+        fail("unexpected paths", paths)
+    return 0
+
+main = workflow(
+    entrypoint = "build",
+    graph = [
+        node(
+            name = "build",
+            action = fn_action(implementation = _build),
+            next = next(implementation = lambda ctx, args: "deploy")(),
+            artifacts = ["dist/*"],
+        ),
+        node(
+            name = "deploy",
+            action = fn_action(implementation = _deploy, args = [artifact("build", "**/*.tar.gz")]),
+            next = next(implementation = lambda ctx, args: "missing")(),
+        ),
+        node(
+            name = "missing",
+            action = fn_action(implementation = _deploy, args = [artifact("build", "*.zip")]),
+        ),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        let working_dir = file.path().parent().unwrap().to_path_buf();
+        std::fs::create_dir_all(working_dir.join("dist")).unwrap();
+        std::fs::write(working_dir.join("dist/a.tar.gz"), "").unwrap();
+        let artifacts = tempdir().unwrap();
+        let run = |start_at: Option<&str>| {
+            run_workflow(
+                &file.path(),
+                &[],
+                RunOptions {
+                    start_at: start_at.map(|s| s.to_string()),
+                    artifacts_dir: Some(artifacts.path().to_path_buf()),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+
+        let result = run(None);
+        assert_eq!(result.nodes[1].error, None);
+        assert_eq!(
+            result.nodes[2].error.as_deref(),
+            Some(
+                r#"artifact("build", "*.zip") did not match any of the artifacts node 'build' collected"#
+            )
+        );
+
+        // the node which produces them did not run
+        let result = run(Some("deploy"));
+        assert_eq!(
+            result.nodes[0].error.as_deref(),
+            Some(
+                r#"artifact("build", "**/*.tar.gz") can not be used, node 'build' has not collected its artifacts in this run"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_sandbox() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+main = workflow(
+    graph = [
+        sequence(
+            name = "build",
+            actions = [
+                action(tool = builtin_tool(name = "cat"), args = ["src/a.txt"]),
+                action(tool = builtin_tool(name = "touch"), args = ["src/new.txt"]),
+                action(tool = builtin_tool(name = "touch"), args = ["out.txt"]),
+            ],
+        ),
+    ],
+    inputs = ["src/*.txt"],
+    outputs = ["out.txt"],
+)
+"#,
+        )
+        .unwrap();
+        let working_dir = file.path().parent().unwrap().to_path_buf();
+        std::fs::create_dir_all(working_dir.join("src")).unwrap();
+        std::fs::write(working_dir.join("src/a.txt"), "a").unwrap();
+
+        let result = run_workflow(
+            &file.path(),
+            &[],
+            RunOptions {
+                quiet: true,
+                sandbox: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(result.succeeded());
+        assert_eq!(result.nodes[0].exit_code, Some(0));
+        assert!(working_dir.join("out.txt").is_file());
+        assert!(!working_dir.join("src/new.txt").exists());
+    }
+
+    #[test]
+    fn test_logs_action_output() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+log = variable(default = "")
+
+def _build():
+    return "built"
+
+def _log(ctx):
+    return ctx.stdout_log
+
+def _check(log):
+    return 0 if log.endswith("build/1/1-stdout.log") else 1
+
+main = workflow(
+    entrypoint = "build",
+    graph = [
+        node(
+            name = "build",
+            action = fn_action(
+                implementation = _build,
+                setters = [setter(implementation = _log, variable = log)],
+            ),
+            next = next(implementation = lambda ctx, args: "check")(),
+        ),
+        node(name = "check", action = fn_action(implementation = _check, args = [log])),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        let logs = tempdir().unwrap();
+        let result = run_workflow(
+            &file.path(),
+            &[],
+            RunOptions {
+                quiet: true,
+                log_dir: Some(logs.path().to_path_buf()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(result.succeeded());
+        let read = |path: &str| std::fs::read_to_string(logs.path().join(path)).unwrap();
+        assert_eq!(read("build/1/1-stdout.log"), "built");
+        assert_eq!(read("build/1/1-stderr.log"), "");
+        assert_eq!(read("check/1/1-stdout.log"), "");
+    }
+
+    #[test]
+    fn test_skips_unchanged_cached_nodes() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+version = variable(default = "1", cli_flag = "--release")
+built = variable(default = "unset")
+
+def _build(version):
+    return "built " + version
+
+def _stdout(ctx):
+    return ctx.stdout
+
+def _check(built, version):
+    return 0 if built == "built " + version else 1
+
+main = workflow(
+    entrypoint = "build",
+    graph = [
+        node(
+            name = "build",
+            action = fn_action(
+                implementation = _build,
+                args = [version],
+                setters = [setter(implementation = _stdout, variable = built)],
+            ),
+            next = next(implementation = lambda ctx, args: "check")(),
+            cache = True,
+            inputs = ["src/*.txt"],
+        ),
+        node(name = "check", action = fn_action(implementation = _check, args = [built, version])),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        let working_dir = file.path().parent().unwrap().to_path_buf();
+        std::fs::create_dir_all(working_dir.join("src")).unwrap();
+        std::fs::write(working_dir.join("src/a.txt"), "a").unwrap();
+        let cache_dir = tempdir().unwrap();
+        let run = |args: &[String], reuse: bool| {
+            let node_cache = NodeCache::open(cache_dir.path().join("cache.json")).unwrap();
+            let result = run_workflow(
+                &file.path(),
+                args,
+                RunOptions {
+                    node_cache: Some(node_cache.with_reuse(reuse)),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            assert!(result.succeeded());
+            result.nodes.iter().map(|n| n.cached).collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(&[], true), [false, false]);
+        // the setters of the cached node still set the variables
+        assert_eq!(run(&[], true), [true, false]);
+        assert_eq!(run(&[], false), [false, false]);
+
+        // a changed input or variable runs it again
+        std::fs::write(working_dir.join("src/a.txt"), "b").unwrap();
+        assert_eq!(run(&[], true), [false, false]);
+        let args = ["--release".to_string(), "2".to_string()];
+        assert_eq!(run(&args, true), [false, false]);
+        assert_eq!(run(&args, true), [true, false]);
+    }
+
+    #[test]
+    fn test_matrix() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+os = variable(default = "linux")
+
+def _build(os):
+    if os == "macos":
+        fail("unsupported")
+    return 0
+
+main = workflow(
+    entrypoint = "build",
+    graph = [node(name = "build", action = fn_action(implementation = _build, args = [os]))],
+    matrix = {"os": ["linux", "macos"]},
+)
+"#,
+        )
+        .unwrap();
+        let names = |matrix: Matrix| -> Vec<String> {
+            matrix
+                .combinations()
+                .iter()
+                .map(|c| c.to_string())
+                .collect()
+        };
+        assert_eq!(
+            names(load_matrix(&file.path(), &[]).unwrap()),
+            ["os=linux", "os=macos"]
+        );
+        assert_eq!(
+            names(load_matrix(&file.path(), &["os=bsd".to_string()]).unwrap()),
+            ["os=bsd"]
+        );
+        assert_eq!(
+            load_matrix(&file.path(), &["arch=x86".to_string()])
+                .unwrap_err()
+                .to_string(),
+            "The matrix axis 'arch' is not a variable which can be set with --var"
+        );
+
+        let combinations = load_matrix(&file.path(), &[]).unwrap().combinations();
+        let results = run_combinations(&combinations, 2, |combination| {
+            run_workflow(
+                &file.path(),
+                &combination.var_args(),
+                RunOptions {
+                    quiet: true,
+                    ..Default::default()
+                },
+            )
+            .and_then(|result| check_result(&result))
+        });
+        let results: Vec<(Combination, anyhow::Result<()>)> =
+            combinations.into_iter().zip(results).collect();
+        assert!(results[0].1.is_ok());
+        assert!(results[1].1.is_err());
+        assert!(matrix_report(&results).starts_with(
+            "Matrix results:\n  os=linux: ok\n  os=macos: failed, Node 'build' failed"
+        ));
+    }
+}
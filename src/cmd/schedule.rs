@@ -0,0 +1,166 @@
+use crate::cmd::run::{check_result, run_and_record, RunOptions};
+use crate::cmd::{GlobalArgs, RunCommand};
+use crate::runner::{Schedule, UtcTime};
+use crate::stdlib::env_capture::EnvCapture;
+use crate::stdlib::locks::LockManager;
+use anyhow::{anyhow, bail};
+use clap::Args;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Args, Debug)]
+pub struct ScheduleArgs {
+    /// When to run the workflow, a cron expression of the minute, hour, day
+    /// of month, month and day of week in UTC, e.g. "*/15 * * * *"
+    pub schedule: String,
+
+    /// The path to the workflow to run
+    pub workflow: PathBuf,
+
+    /// Delays each run by a random number of seconds up to this, so
+    /// schedules which share a time do not all start at once
+    #[arg(long, value_name = "SECS", default_value_t = 0)]
+    pub jitter: u64,
+
+    /// Stops once the workflow has been started this many times
+    #[arg(long, value_name = "N")]
+    pub max_runs: Option<u32>,
+
+    /// The additional arguments that will be passed along to the workflow
+    #[arg(allow_hyphen_values = true, trailing_var_arg = true)]
+    pub workflow_args: Vec<String>,
+}
+
+/// Runs `run` on its own thread while holding `lock`, None if the lock is
+/// held because an earlier run has not finished.
+fn start_locked<F>(lock: &str, run: F) -> Option<JoinHandle<()>>
+where
+    F: FnOnce() + Send + 'static,
+{
+    if LockManager::global().is_locked(lock) {
+        return None;
+    }
+    let lock = lock.to_string();
+    let (acquired, wait) = mpsc::channel();
+    let handle = thread::spawn(move || {
+        let guard = LockManager::global().acquire(&[lock]);
+        acquired.send(()).unwrap();
+        if guard.is_ok() {
+            run();
+        }
+    });
+    // the next run must see the lock as held
+    wait.recv().unwrap();
+    Some(handle)
+}
+
+/// Returns a random delay of up to `jitter` seconds.
+fn jitter_delay(jitter: u64) -> Duration {
+    let random = uuid::Uuid::new_v4().as_u128();
+    Duration::from_secs((random % (jitter as u128 + 1)) as u64)
+}
+
+/// Runs the workflow once and reports how it went, a failed run does not
+/// stop the schedule.
+fn run_once(workflow: PathBuf, workflow_args: Vec<String>, env_capture: EnvCapture, quiet: bool) {
+    let result = run_and_record(
+        &workflow,
+        &workflow_args,
+        true,
+        RunOptions {
+            env_capture,
+            quiet,
+            ..Default::default()
+        },
+    )
+    .and_then(|result| check_result(&result));
+    match result {
+        Ok(()) if !quiet => println!("Run of {} succeeded", workflow.display()),
+        Ok(()) => {}
+        Err(e) => eprintln!("Run of {} failed: {:#}", workflow.display(), e),
+    }
+}
+
+impl RunCommand for ScheduleArgs {
+    fn run(&self, global_args: &GlobalArgs) -> anyhow::Result<()> {
+        let schedule = Schedule::parse(&self.schedule)?;
+        if !self.workflow.exists() {
+            bail!("Workflow does not exist at path {:?}", self.workflow);
+        }
+        let workflow = std::fs::canonicalize(&self.workflow)?;
+        let lock = format!("schedule:{}", workflow.display());
+
+        let mut started = 0;
+        let mut last = None;
+        while self.max_runs != Some(started) {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+            let next = schedule
+                .next_after(now)
+                .ok_or_else(|| anyhow!("The schedule '{}' does not run again", schedule))?;
+            let at = UNIX_EPOCH + Duration::from_secs(next) + jitter_delay(self.jitter);
+            if !global_args.quiet {
+                let secs = at.duration_since(UNIX_EPOCH)?.as_secs();
+                println!(
+                    "Next run of {} at {}",
+                    workflow.display(),
+                    UtcTime::from_secs(secs)
+                );
+            }
+            thread::sleep(at.duration_since(SystemTime::now()).unwrap_or_default());
+
+            let (workflow, args, quiet) = (
+                workflow.clone(),
+                self.workflow_args.clone(),
+                global_args.quiet,
+            );
+            let env_capture = global_args.env_capture();
+            match start_locked(&lock, move || run_once(workflow, args, env_capture, quiet)) {
+                Some(handle) => {
+                    started += 1;
+                    last = Some(handle);
+                }
+                None => eprintln!(
+                    "Skipped the run of {}, the previous run has not finished",
+                    self.workflow.display()
+                ),
+            }
+        }
+        if let Some(handle) = last {
+            handle
+                .join()
+                .map_err(|_| anyhow!("The last run of the workflow panicked"))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_start_locked_skips_overlapping_runs() {
+        let lock = "schedule:test_start_locked";
+        let (finish, wait) = mpsc::channel::<()>();
+        let first = start_locked(lock, move || wait.recv().unwrap()).unwrap();
+        assert!(LockManager::global().is_locked(lock));
+        assert!(start_locked(lock, || panic!("overlapping run")).is_none());
+
+        finish.send(()).unwrap();
+        first.join().unwrap();
+        let (ran, check) = mpsc::channel();
+        start_locked(lock, move || ran.send(()).unwrap())
+            .unwrap()
+            .join()
+            .unwrap();
+        check.recv().unwrap();
+    }
+
+    #[test]
+    fn test_jitter_delay() {
+        assert_eq!(jitter_delay(0), Duration::ZERO);
+        assert!(jitter_delay(10) <= Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,72 @@
+use crate::cmd::{GlobalArgs, RunCommand};
+use crate::runner::{History, Policy, Server, Webhook};
+use anyhow::bail;
+use clap::Args;
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// The workflows which can be run, each is named after its file name
+    /// without the extension
+    #[arg(required = true)]
+    pub workflows: Vec<PathBuf>,
+
+    /// The address to listen on, a --policy is needed to listen beyond
+    /// localhost
+    #[arg(long, default_value = "127.0.0.1:7878")]
+    pub addr: String,
+
+    /// A json file of the tokens which requests can authenticate with and
+    /// the workflows and commands each may use. Without it any request is
+    /// allowed to do anything
+    #[arg(long, value_name = "FILE")]
+    pub policy: Option<PathBuf>,
+
+    /// Runs a workflow when `/hooks/<workflow>` is posted to, setting its
+    /// variables from fields of the json payload, e.g.
+    /// `ci:ref=git_ref,repository.name=repo`
+    #[arg(long, value_name = "WORKFLOW:FIELD=VARIABLE,...")]
+    pub webhook: Vec<String>,
+
+    /// How many runs of the workflow can run at once, e.g. `test=4`. The
+    /// runs of a workflow without a limit run one at a time
+    #[arg(long, value_name = "WORKFLOW=LIMIT")]
+    pub concurrency: Vec<String>,
+}
+
+impl RunCommand for ServeArgs {
+    fn run(&self, global_args: &GlobalArgs) -> anyhow::Result<()> {
+        let mut server = Server::bind(&self.addr, &self.workflows, History::default_location()?)?;
+        match &self.policy {
+            Some(policy) => server = server.with_policy(Policy::load(policy)?),
+            None if !server.local_addr()?.ip().is_loopback() => bail!(
+                "Refusing to listen on {} without a --policy, anyone who can connect could run the workflows",
+                self.addr
+            ),
+            None => {}
+        }
+        for concurrency in &self.concurrency {
+            match concurrency.split_once('=') {
+                Some((workflow, limit)) => match limit.parse() {
+                    Ok(limit) => server.set_concurrency(workflow, limit)?,
+                    Err(_) => bail!("Invalid concurrency limit '{}' for '{}'", limit, workflow),
+                },
+                None => bail!(
+                    "Invalid concurrency '{}', expected <workflow>=<limit>",
+                    concurrency
+                ),
+            }
+        }
+        for webhook in &self.webhook {
+            server.add_webhook(Webhook::parse(webhook)?)?;
+        }
+        if !global_args.quiet {
+            println!(
+                "Serving {} workflows on http://{}",
+                self.workflows.len(),
+                server.local_addr()?
+            );
+        }
+        server.serve()
+    }
+}
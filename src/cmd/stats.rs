@@ -0,0 +1,75 @@
+use crate::cmd::pager::Pager;
+use crate::cmd::{GlobalArgs, RunCommand};
+use crate::runner::{node_stats, History, NodeStats};
+use ansi_term::Colour::{Cyan, Green, Red};
+use anyhow::bail;
+use clap::Args;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+#[derive(Args, Debug)]
+pub struct StatsArgs {
+    /// The path to the workflow to show statistics for
+    pub workflow: PathBuf,
+
+    /// Nodes whose failure rate is above this value (0.0 - 1.0) are flagged as flaky
+    #[arg(long, default_value_t = 0.1)]
+    pub threshold: f64,
+}
+
+fn format_duration(ms: Option<u64>) -> String {
+    match ms {
+        Some(ms) => format!("{}ms", ms),
+        None => "-".to_string(),
+    }
+}
+
+fn print_node_stats(out: &mut dyn Write, stats: &NodeStats, threshold: f64) -> io::Result<()> {
+    let rate = format!("{:.1}%", stats.failure_rate() * 100.0);
+    writeln!(
+        out,
+        "{}: runs = {}, failures = {} ({}), p50 = {}, p90 = {}, p99 = {}{}",
+        Cyan.paint(&stats.name),
+        stats.runs,
+        stats.failures,
+        if stats.failures > 0 {
+            Red.paint(rate)
+        } else {
+            Green.paint(rate)
+        },
+        format_duration(stats.duration_percentile(50.0)),
+        format_duration(stats.duration_percentile(90.0)),
+        format_duration(stats.duration_percentile(99.0)),
+        if stats.is_flaky(threshold) {
+            format!(" {}", Red.paint("FLAKY"))
+        } else {
+            "".to_string()
+        }
+    )
+}
+
+impl RunCommand for StatsArgs {
+    fn run(&self, global_args: &GlobalArgs) -> anyhow::Result<()> {
+        if !(0.0..=1.0).contains(&self.threshold) {
+            bail!("--threshold must be between 0.0 and 1.0");
+        }
+        let workflow = std::fs::canonicalize(&self.workflow)?;
+        let records = History::default_location()?.records()?;
+        let runs = records.iter().filter(|r| r.workflow == workflow).count();
+        if runs == 0 {
+            bail!("No runs of {:?} found in the history", workflow);
+        }
+
+        let mut pager = Pager::start(global_args.no_pager);
+        writeln!(
+            pager,
+            "Statistics for {:?} across {} runs\n",
+            workflow, runs
+        )?;
+        for stats in node_stats(&records, &workflow) {
+            print_node_stats(&mut pager, &stats, self.threshold)?;
+        }
+        pager.finish()?;
+        Ok(())
+    }
+}
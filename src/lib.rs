@@ -0,0 +1,3 @@
+pub mod cmd;
+pub mod runner;
+pub mod stdlib;
@@ -1,9 +1,5 @@
-pub mod cmd;
-pub mod runner;
-pub mod stdlib;
-
-use crate::cmd::Cli;
 use clap::Parser;
+use workflow::cmd::Cli;
 
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
@@ -0,0 +1,295 @@
+use super::{NodeRecord, RunEvent, Runner, WorkflowDelegate};
+use crate::stdlib::ir::WorkflowIr;
+use crate::stdlib::{ActionStatus, RunResult};
+use anyhow::anyhow;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::io::{BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+
+/// What an agent is asked to run, sent as one json document.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AgentRequest {
+    /// The compiled workflow, as written by `workflow compile`. It is read
+    /// once its version is known to be the one this agent reads.
+    workflow: serde_json::Value,
+    /// The values of the variables by the name `--var` sets them with.
+    #[serde(default)]
+    variables: BTreeMap<String, String>,
+    /// Has to match the agent's token if it has one.
+    #[serde(default)]
+    token: Option<String>,
+}
+
+/// Runs compiled workflows sent to it and streams their progress back as
+/// ndjson events, so a workflow can be run on a machine which only has
+/// this binary and the tools the workflow runs.
+///
+/// The events are `started`, then `node_started`, `action_started`,
+/// `action_finished` and `node` for the nodes as they run and at last
+/// `finished`, which tells whether the run succeeded.
+pub struct Agent {
+    working_dir: PathBuf,
+    token: Option<String>,
+}
+
+impl Agent {
+    /// Creates an agent which runs the workflows as if they were in
+    /// `working_dir`.
+    pub fn new(working_dir: &Path) -> Self {
+        Agent {
+            working_dir: working_dir.to_path_buf(),
+            token: None,
+        }
+    }
+
+    /// Only runs the requests which carry the token.
+    pub fn with_token(mut self, token: String) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Reads a request from `input` and runs it, writing its events to
+    /// `out`. A request which can not be read or run is reported as a
+    /// `finished` event which did not succeed. Returns whether the run
+    /// succeeded.
+    pub fn handle(&self, input: &mut dyn Read, out: &mut dyn Write) -> anyhow::Result<bool> {
+        // only the request is read, so the sender can keep the connection
+        // open for the events
+        let request = serde_json::Deserializer::from_reader(input)
+            .into_iter::<AgentRequest>()
+            .next()
+            .unwrap_or_else(|| Err(serde::de::Error::custom("no request was sent")));
+        let request = match request {
+            Ok(request) => request,
+            Err(e) => {
+                let error = format!("Invalid request: {}", e);
+                writeln!(out, "{}", finished_event(Err(&anyhow!(error))))?;
+                return Ok(false);
+            }
+        };
+        if self.token.is_some() && request.token != self.token {
+            let error = anyhow!("The request does not carry the agent's token");
+            writeln!(out, "{}", finished_event(Err(&error)))?;
+            return Ok(false);
+        }
+        let workflow = match WorkflowIr::from_value(request.workflow) {
+            Ok(workflow) => workflow,
+            Err(e) => {
+                writeln!(out, "{}", finished_event(Err(&e)))?;
+                return Ok(false);
+            }
+        };
+        self.run(workflow, &request.variables, out)
+    }
+
+    /// Runs the workflow with the values of its variables, writing its
+    /// events to `out`, and returns whether the run succeeded.
+    pub fn run(
+        &self,
+        workflow: WorkflowIr,
+        variables: &BTreeMap<String, String>,
+        out: &mut dyn Write,
+    ) -> anyhow::Result<bool> {
+        // the run carries on if the sender goes away
+        let mut connected = true;
+        let mut event = |event: serde_json::Value| {
+            connected = connected && writeln!(out, "{}", event).and_then(|_| out.flush()).is_ok();
+        };
+        let args = variables
+            .iter()
+            .flat_map(|(name, value)| ["--var".to_string(), format!("{}={}", name, value)])
+            .collect();
+        let (sender, progress) = mpsc::channel();
+        let working_dir = self.working_dir.clone();
+        let handle = thread::spawn(move || -> anyhow::Result<RunResult> {
+            let delegate = WorkflowDelegate::with_args(args)
+                .with_quiet(true)
+                .with_subscriber(sender);
+            Runner::from_ir(&working_dir, &workflow, delegate)?.run(None)
+        });
+        // ends once the runner, and its sender, are dropped. The end of the
+        // run is reported once its result is known
+        for run_event in progress {
+            if let Some(json) = event_json(&run_event) {
+                event(json);
+            }
+        }
+        let result = handle
+            .join()
+            .unwrap_or_else(|_| Err(anyhow!("The run panicked")));
+        event(finished_event(result.as_ref()));
+        Ok(matches!(result, Ok(result) if result.succeeded()))
+    }
+
+    /// Handles a request on each connection to the listener, one at a
+    /// time, until the process is stopped.
+    pub fn serve(&self, listener: TcpListener) -> anyhow::Result<()> {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("warning: unable to accept a connection: {}", e);
+                    continue;
+                }
+            };
+            let mut input = BufReader::new(stream.try_clone()?);
+            let mut out = stream;
+            if let Err(e) = self.handle(&mut input, &mut out) {
+                eprintln!("warning: unable to handle a request: {:#}", e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the json of the event, None for the events the agent reports
+/// itself.
+fn event_json(event: &RunEvent) -> Option<serde_json::Value> {
+    Some(match event {
+        RunEvent::Started { .. } => json!({ "event": "started" }),
+        RunEvent::NodeStarted { node } => json!({ "event": "node_started", "node": node }),
+        RunEvent::NodeFinished(node) => json!({ "event": "node", "node": NodeRecord::from(node) }),
+        RunEvent::ActionStarted {
+            node,
+            index,
+            action,
+        } => json!({
+            "event": "action_started",
+            "node": node,
+            "index": index,
+            "action": action,
+        }),
+        RunEvent::ActionFinished {
+            node,
+            index,
+            status,
+        } => {
+            let mut json = json!({
+                "event": "action_finished",
+                "node": node,
+                "index": index,
+                "succeeded": status.succeeded(),
+            });
+            match status {
+                ActionStatus::Ran(result) => {
+                    json["exit_code"] = json!(result.exit_code);
+                    json["duration_ms"] = json!(result.duration_ms);
+                }
+                ActionStatus::Failed(error) => json["error"] = json!(error),
+            }
+            json
+        }
+        RunEvent::Finished { .. } => return None,
+    })
+}
+
+fn finished_event(result: Result<&RunResult, &anyhow::Error>) -> serde_json::Value {
+    match result {
+        Ok(result) => json!({
+            "event": "finished",
+            "succeeded": result.succeeded(),
+            "error": result.error().and_then(|node| node.error.clone()),
+        }),
+        Err(e) => json!({
+            "event": "finished",
+            "succeeded": false,
+            "error": format!("{:#}", e),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::{ActionSpec, VariableSpec, WorkflowBuilder};
+    use tempfile::tempdir;
+
+    fn read_events(out: Vec<u8>) -> Vec<serde_json::Value> {
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_runs_request() {
+        let dir = tempdir().unwrap();
+        let builder = WorkflowBuilder::new()
+            .entrypoint("build")
+            .variable(VariableSpec::new("marker").default("built"))
+            .node("build")
+            .action(ActionSpec::builtin_tool("touch").var_arg("marker").cwd("."));
+        let ir = Runner::from_source(
+            dir.path(),
+            builder.to_starlark().unwrap(),
+            WorkflowDelegate::new(),
+        )
+        .unwrap()
+        .compile()
+        .unwrap();
+        let request = json!({
+            "workflow": ir,
+            "variables": { "marker": "touched" },
+        });
+
+        let mut out = vec![];
+        let agent = Agent::new(dir.path());
+        assert!(agent
+            .handle(&mut request.to_string().as_bytes(), &mut out)
+            .unwrap());
+        assert!(dir.path().join("touched").exists());
+        let events = read_events(out);
+        let kinds: Vec<&str> = events
+            .iter()
+            .map(|e| e["event"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                "started",
+                "node_started",
+                "action_started",
+                "action_finished",
+                "node",
+                "finished"
+            ]
+        );
+        assert_eq!(events[3]["exit_code"], 0);
+        assert_eq!(events[5]["succeeded"], true);
+    }
+
+    #[test]
+    fn test_rejects_request() {
+        let dir = tempdir().unwrap();
+        let agent = Agent::new(dir.path()).with_token("secret".to_string());
+
+        let mut out = vec![];
+        assert!(!agent.handle(&mut "{".as_bytes(), &mut out).unwrap());
+        let events = read_events(out);
+        assert_eq!(events[0]["event"], "finished");
+        assert!(events[0]["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Invalid request: "));
+
+        let request = json!({
+            "workflow": { "version": 1, "entrypoint": "a", "nodes": [] },
+            "token": "wrong",
+        });
+        let mut out = vec![];
+        assert!(!agent
+            .handle(&mut request.to_string().as_bytes(), &mut out)
+            .unwrap());
+        assert_eq!(
+            read_events(out)[0]["error"],
+            "The request does not carry the agent's token"
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use crate::stdlib::approval::{Approval, ApprovalRequest, Approver};
+use anyhow::bail;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+// how often a wait checks whether the run was cancelled or timed out
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Asks for the approval of manual gates on the terminal the workflow runs
+/// in, approving them in the name of the user running it.
+#[derive(Debug, Default)]
+pub struct PromptApprover;
+
+impl Approver for PromptApprover {
+    fn ask(&self, request: &ApprovalRequest) -> anyhow::Result<Approval> {
+        if !io::stdin().is_terminal() {
+            bail!(
+                "manual gate '{}' needs an approval but there is no terminal to ask on",
+                request.gate
+            );
+        }
+        eprint!(
+            "{}\nApprove manual gate '{}'? [y/N] ",
+            request.message, request.gate
+        );
+        io::stderr().flush()?;
+        // the thread is left blocked on stdin if the gate times out
+        let (sender, answers) = mpsc::channel();
+        thread::spawn(move || {
+            let mut line = String::new();
+            let _ = sender.send(io::stdin().lock().read_line(&mut line).map(|_| line));
+        });
+        wait_for_answer(request, &answers, &current_user())
+    }
+}
+
+/// Waits for the answer to the prompt, approving the gate in the name of
+/// `user` if it is yes.
+fn wait_for_answer(
+    request: &ApprovalRequest,
+    answers: &Receiver<io::Result<String>>,
+    user: &str,
+) -> anyhow::Result<Approval> {
+    loop {
+        request.check_waiting()?;
+        match answers.recv_timeout(POLL_INTERVAL) {
+            Ok(answer) => match answer?.trim().to_lowercase().as_str() {
+                "y" | "yes" => return Ok(Approval::now(user)),
+                _ => bail!("manual gate '{}' was rejected by {}", request.gate, user),
+            },
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => {
+                bail!("manual gate '{}' got no answer", request.gate)
+            }
+        }
+    }
+}
+
+fn current_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib::cancel::CancelToken;
+    use std::time::Instant;
+
+    fn request(deadline: Option<Instant>, cancel_token: Option<CancelToken>) -> ApprovalRequest {
+        ApprovalRequest {
+            gate: "deploy".to_string(),
+            message: "Deploy to prod?".to_string(),
+            deadline,
+            cancel_token,
+        }
+    }
+
+    #[test]
+    fn test_wait_for_answer() {
+        let answer = |answer: &str| {
+            let (sender, answers) = mpsc::channel();
+            sender.send(Ok(answer.to_string())).unwrap();
+            wait_for_answer(&request(None, None), &answers, "alice")
+        };
+        assert_eq!(answer("y\n").unwrap().by, "alice");
+        assert_eq!(answer("Yes\n").unwrap().by, "alice");
+        assert_eq!(
+            answer("\n").unwrap_err().to_string(),
+            "manual gate 'deploy' was rejected by alice"
+        );
+    }
+
+    #[test]
+    fn test_wait_stops_at_deadline_or_cancel() {
+        let (_sender, answers) = mpsc::channel();
+        let err = wait_for_answer(
+            &request(Some(Instant::now() + POLL_INTERVAL), None),
+            &answers,
+            "alice",
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "manual gate 'deploy' was not approved in time"
+        );
+
+        let token = CancelToken::new();
+        token.cancel();
+        let err = wait_for_answer(&request(None, Some(token)), &answers, "alice").unwrap_err();
+        assert_eq!(err.to_string(), "The run was cancelled");
+    }
+}
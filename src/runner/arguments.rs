@@ -0,0 +1,71 @@
+/// A command line argument of a workflow, which sets one of its variables.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkflowArgument {
+    /// How the argument is given, e.g. `--profile <VALUE>`.
+    pub usage: String,
+    pub doc: Option<String>,
+    pub env: Option<String>,
+    pub default: Option<String>,
+    pub required: bool,
+}
+
+/// Returns the help for the arguments of a workflow, laid out like the help
+/// of the command it is run with.
+pub fn arguments_help(arguments: &[WorkflowArgument]) -> String {
+    if arguments.is_empty() {
+        return "The workflow takes no arguments\n".to_string();
+    }
+    let width = arguments.iter().map(|a| a.usage.len()).max().unwrap_or(0);
+    let mut help = String::from("Workflow arguments:\n");
+    for argument in arguments {
+        let mut description: Vec<String> = argument.doc.iter().cloned().collect();
+        if let Some(env) = &argument.env {
+            description.push(format!("[env: {}]", env));
+        }
+        if let Some(default) = &argument.default {
+            description.push(format!("[default: {}]", default));
+        }
+        if argument.required {
+            description.push("[required]".to_string());
+        }
+        let line = format!("  {:width$}  {}", argument.usage, description.join(" "));
+        help.push_str(line.trim_end());
+        help.push('\n');
+    }
+    help.push_str("\nEvery variable can also be set with --var <name>=<value>\n");
+    help
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arguments_help() {
+        let arguments = vec![
+            WorkflowArgument {
+                usage: "--profile <VALUE>".to_string(),
+                doc: Some("The build profile".to_string()),
+                env: Some("PROFILE".to_string()),
+                default: Some("debug".to_string()),
+                required: false,
+            },
+            WorkflowArgument {
+                usage: "--var region=<VALUE>".to_string(),
+                doc: None,
+                env: None,
+                default: None,
+                required: true,
+            },
+        ];
+        assert_eq!(
+            arguments_help(&arguments),
+            "Workflow arguments:\n\
+             \x20 --profile <VALUE>     The build profile [env: PROFILE] [default: debug]\n\
+             \x20 --var region=<VALUE>  [required]\n\
+             \n\
+             Every variable can also be set with --var <name>=<value>\n"
+        );
+        assert_eq!(arguments_help(&[]), "The workflow takes no arguments\n");
+    }
+}
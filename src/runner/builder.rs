@@ -0,0 +1,1130 @@
+use super::{format_source, Runner};
+use crate::stdlib::ir::{ArgIr, NextIr, SetterIr, ToolIr, WorkflowIr};
+use crate::stdlib::ParseDelegate;
+use anyhow::bail;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Builds a workflow from Rust, for programs which generate workflows.
+/// The workflow is written out as starlark, which can be saved as a
+/// workflow file or run through the same runner as one:
+///
+/// ```ignore
+/// let workflow = WorkflowBuilder::new()
+///     .variable(VariableSpec::new("profile").default("debug").cli_flag("--profile"))
+///     .node("build")
+///     .action(ActionSpec::builtin_tool("cargo").arg("build").var_arg("profile"))
+///     .next("test")
+///     .node("test")
+///     .action(ActionSpec::builtin_tool("cargo").arg("test"));
+/// let result = workflow.runner(&working_dir, WorkflowDelegate::new())?.run(None)?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct WorkflowBuilder {
+    variables: Vec<VariableSpec>,
+    nodes: Vec<NodeSpec>,
+    entrypoint: Option<String>,
+    env_policy: Option<EnvPolicySpec>,
+    // the first of the methods building the last node which was called
+    // before any node, reported once the workflow is built
+    before_node: Option<&'static str>,
+}
+
+/// A variable of a built workflow, bound to its name.
+#[derive(Debug, Clone)]
+pub struct VariableSpec {
+    name: String,
+    default: Option<String>,
+    env: Option<String>,
+    cli_flag: Option<String>,
+    group: Option<String>,
+    doc: Option<String>,
+    required: bool,
+    prompt: Option<String>,
+}
+
+/// An action of a built workflow, which runs a tool.
+#[derive(Debug, Clone)]
+pub struct ActionSpec {
+    tool: ToolSpec,
+    // the names the builtin tool is also found by
+    aliases: Vec<String>,
+    args: Vec<ArgSpec>,
+    setters: Vec<SetterSpec>,
+    encoding: Option<String>,
+    strict_utf8: bool,
+    ok_exit_codes: Vec<i32>,
+    env: Vec<(String, ArgSpec)>,
+    cwd: Option<String>,
+    quiet: bool,
+    retries: u32,
+    retry_delay: u32,
+}
+
+/// A setter of a built action, which sets the variable from the output of
+/// the tool: the value at the json path of the stdout, or the contents of
+/// a file.
+#[derive(Debug, Clone)]
+pub struct SetterSpec {
+    variable: String,
+    from_file: Option<ArgSpec>,
+    strip: bool,
+    json_path: Option<String>,
+}
+
+/// Which environment variables the tools of a built workflow are spawned
+/// with.
+#[derive(Debug, Clone)]
+pub struct EnvPolicySpec {
+    inherit: bool,
+    allow: Vec<String>,
+    set: Vec<(String, ArgSpec)>,
+}
+
+#[derive(Debug, Clone)]
+enum ToolSpec {
+    Builtin(String),
+    Path(String),
+}
+
+#[derive(Debug, Clone)]
+enum ArgSpec {
+    Literal(String),
+    // the name of a variable of the workflow
+    Variable(String),
+}
+
+#[derive(Debug, Clone)]
+enum NextSpec {
+    Node(String),
+    When {
+        variable: String,
+        equals: String,
+        then: String,
+        else_: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct NodeSpec {
+    name: String,
+    actions: Vec<ActionSpec>,
+    next: Option<NextSpec>,
+    timeout: Option<u32>,
+    retries: u32,
+    tags: Vec<String>,
+    pipe: bool,
+}
+
+impl VariableSpec {
+    pub fn new(name: &str) -> Self {
+        VariableSpec {
+            name: name.to_string(),
+            default: None,
+            env: None,
+            cli_flag: None,
+            group: None,
+            doc: None,
+            required: false,
+            prompt: None,
+        }
+    }
+
+    pub fn default(mut self, value: &str) -> Self {
+        self.default = Some(value.to_string());
+        self
+    }
+
+    pub fn env(mut self, env: &str) -> Self {
+        self.env = Some(env.to_string());
+        self
+    }
+
+    pub fn cli_flag(mut self, flag: &str) -> Self {
+        self.cli_flag = Some(flag.to_string());
+        self
+    }
+
+    pub fn group(mut self, group: &str) -> Self {
+        self.group = Some(group.to_string());
+        self
+    }
+
+    pub fn doc(mut self, doc: &str) -> Self {
+        self.doc = Some(doc.to_string());
+        self
+    }
+
+    /// Fails the run if the variable has no value once it started.
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    /// Asks for the value of the required variable with the prompt when it
+    /// has none.
+    pub fn prompt(mut self, prompt: &str) -> Self {
+        self.prompt = Some(prompt.to_string());
+        self
+    }
+}
+
+impl ActionSpec {
+    /// An action running the tool found on the PATH by its name.
+    pub fn builtin_tool(name: &str) -> Self {
+        ActionSpec::with_tool(ToolSpec::Builtin(name.to_string()))
+    }
+
+    /// An action running the tool at the path, relative to the workflow.
+    pub fn tool(path: &str) -> Self {
+        ActionSpec::with_tool(ToolSpec::Path(path.to_string()))
+    }
+
+    fn with_tool(tool: ToolSpec) -> Self {
+        ActionSpec {
+            tool,
+            aliases: vec![],
+            args: vec![],
+            setters: vec![],
+            encoding: None,
+            strict_utf8: false,
+            ok_exit_codes: vec![0],
+            env: vec![],
+            cwd: None,
+            quiet: false,
+            retries: 0,
+            retry_delay: 0,
+        }
+    }
+
+    /// Also finds the builtin tool by the aliases when it is not on the
+    /// PATH by its name.
+    pub fn aliases<I: IntoIterator<Item = S>, S: AsRef<str>>(mut self, aliases: I) -> Self {
+        for alias in aliases {
+            self.aliases.push(alias.as_ref().to_string());
+        }
+        self
+    }
+
+    pub fn arg(mut self, arg: &str) -> Self {
+        self.args.push(ArgSpec::Literal(arg.to_string()));
+        self
+    }
+
+    pub fn args<I: IntoIterator<Item = S>, S: AsRef<str>>(mut self, args: I) -> Self {
+        for arg in args {
+            self.args.push(ArgSpec::Literal(arg.as_ref().to_string()));
+        }
+        self
+    }
+
+    /// Passes the value of the workflow's variable as an arg.
+    pub fn var_arg(mut self, variable: &str) -> Self {
+        self.args.push(ArgSpec::Variable(variable.to_string()));
+        self
+    }
+
+    /// Sets the environment variable for the tool.
+    pub fn env(mut self, name: &str, value: &str) -> Self {
+        self.env
+            .push((name.to_string(), ArgSpec::Literal(value.to_string())));
+        self
+    }
+
+    /// Sets the environment variable for the tool to the value of the
+    /// workflow's variable.
+    pub fn var_env(mut self, name: &str, variable: &str) -> Self {
+        self.env
+            .push((name.to_string(), ArgSpec::Variable(variable.to_string())));
+        self
+    }
+
+    pub fn cwd(mut self, cwd: &str) -> Self {
+        self.cwd = Some(cwd.to_string());
+        self
+    }
+
+    /// Sets a variable from the output once the tool exited, the setters
+    /// run in the order they were added.
+    pub fn setter(mut self, setter: SetterSpec) -> Self {
+        self.setters.push(setter);
+        self
+    }
+
+    /// Decodes the output for the setters as 'utf8', 'latin1' or 'bytes'.
+    pub fn encoding(mut self, encoding: &str) -> Self {
+        self.encoding = Some(encoding.to_string());
+        self
+    }
+
+    /// Fails the action if its output is not valid utf8.
+    pub fn strict_utf8(mut self) -> Self {
+        self.strict_utf8 = true;
+        self
+    }
+
+    /// The exit codes which count as the tool succeeding, 0 by default.
+    pub fn ok_exit_codes<I: IntoIterator<Item = i32>>(mut self, codes: I) -> Self {
+        self.ok_exit_codes = codes.into_iter().collect();
+        self
+    }
+
+    /// Only collects the output for the setters, without showing it.
+    pub fn quiet(mut self) -> Self {
+        self.quiet = true;
+        self
+    }
+
+    /// Runs the tool again up to `retries` times while its exit code is not
+    /// ok, waiting `delay_secs` before each.
+    pub fn retries(mut self, retries: u32, delay_secs: u32) -> Self {
+        self.retries = retries;
+        self.retry_delay = delay_secs;
+        self
+    }
+
+    fn variables(&self) -> impl Iterator<Item = &str> {
+        self.args
+            .iter()
+            .chain(self.env.iter().map(|(_, value)| value))
+            .filter_map(ArgSpec::variable)
+            .chain(self.setters.iter().flat_map(|s| s.variables()))
+    }
+
+    fn to_starlark(&self) -> String {
+        let tool = match &self.tool {
+            ToolSpec::Builtin(name) if self.aliases.is_empty() => {
+                format!("builtin_tool(name = {})", quote(name))
+            }
+            ToolSpec::Builtin(name) => format!(
+                "builtin_tool(name = {}, aliases = [{}])",
+                quote(name),
+                self.aliases
+                    .iter()
+                    .map(|a| quote(a))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            ToolSpec::Path(path) => format!("tool(path = {})", quote(path)),
+        };
+        let args: Vec<String> = self.args.iter().map(ArgSpec::to_starlark).collect();
+        let mut action = format!("action(tool = {}", tool);
+        if !args.is_empty() {
+            let _ = write!(action, ", args = [{}]", args.join(", "));
+        }
+        if !self.setters.is_empty() {
+            let setters: Vec<String> = self.setters.iter().map(SetterSpec::to_starlark).collect();
+            let _ = write!(action, ", setters = [{}]", setters.join(", "));
+        }
+        if let Some(encoding) = &self.encoding {
+            let _ = write!(action, ", encoding = {}", quote(encoding));
+        }
+        if self.strict_utf8 {
+            action.push_str(", strict_utf8 = True");
+        }
+        if self.ok_exit_codes != [0] {
+            let codes: Vec<String> = self.ok_exit_codes.iter().map(|c| c.to_string()).collect();
+            let _ = write!(action, ", ok_exit_codes = [{}]", codes.join(", "));
+        }
+        if !self.env.is_empty() {
+            let _ = write!(action, ", env = {}", env_to_starlark(&self.env));
+        }
+        if let Some(cwd) = &self.cwd {
+            let _ = write!(action, ", cwd = {}", quote(cwd));
+        }
+        if self.quiet {
+            action.push_str(", quiet = True");
+        }
+        if self.retries > 0 {
+            let _ = write!(action, ", retries = {}", self.retries);
+        }
+        if self.retry_delay > 0 {
+            let _ = write!(action, ", retry_delay = {}", self.retry_delay);
+        }
+        action.push(')');
+        action
+    }
+}
+
+impl SetterSpec {
+    /// A setter of the workflow's variable, which reads it from the file
+    /// or the json path set on it.
+    pub fn new(variable: &str) -> Self {
+        SetterSpec {
+            variable: variable.to_string(),
+            from_file: None,
+            strip: true,
+            json_path: None,
+        }
+    }
+
+    /// Reads the file at the path, relative to the cwd of the tool, rather
+    /// than the stdout.
+    pub fn file(mut self, path: &str) -> Self {
+        self.from_file = Some(ArgSpec::Literal(path.to_string()));
+        self
+    }
+
+    /// Reads the file at the path the workflow's variable holds.
+    pub fn var_file(mut self, variable: &str) -> Self {
+        self.from_file = Some(ArgSpec::Variable(variable.to_string()));
+        self
+    }
+
+    /// Keeps the whitespace around the contents of the file.
+    pub fn keep_whitespace(mut self) -> Self {
+        self.strip = false;
+        self
+    }
+
+    /// Sets the variable to the value at the path, e.g. `.items[0].name`,
+    /// of the output parsed as json.
+    pub fn json_path(mut self, path: &str) -> Self {
+        self.json_path = Some(path.to_string());
+        self
+    }
+
+    fn variables(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.variable.as_str())
+            .chain(self.from_file.as_ref().and_then(ArgSpec::variable))
+    }
+
+    fn to_starlark(&self) -> String {
+        let mut setter = format!("setter(variable = {}", self.variable);
+        if let Some(from_file) = &self.from_file {
+            let _ = write!(setter, ", from_file = {}", from_file.to_starlark());
+            if !self.strip {
+                setter.push_str(", strip = False");
+            }
+        }
+        if let Some(json_path) = &self.json_path {
+            let _ = write!(setter, ", json_path = {}", quote(json_path));
+        }
+        setter.push(')');
+        setter
+    }
+}
+
+impl EnvPolicySpec {
+    /// A policy which passes the environment of `workflow` on to the tools
+    /// if it `inherit`s, and only the allowed variables otherwise.
+    pub fn new(inherit: bool) -> Self {
+        EnvPolicySpec {
+            inherit,
+            allow: vec![],
+            set: vec![],
+        }
+    }
+
+    /// Passes the variable of the environment on to the tools.
+    pub fn allow(mut self, name: &str) -> Self {
+        self.allow.push(name.to_string());
+        self
+    }
+
+    /// Sets the environment variable for every tool.
+    pub fn set(mut self, name: &str, value: &str) -> Self {
+        self.set
+            .push((name.to_string(), ArgSpec::Literal(value.to_string())));
+        self
+    }
+
+    /// Sets the environment variable for every tool to the value of the
+    /// workflow's variable.
+    pub fn var_set(mut self, name: &str, variable: &str) -> Self {
+        self.set
+            .push((name.to_string(), ArgSpec::Variable(variable.to_string())));
+        self
+    }
+
+    fn to_starlark(&self) -> String {
+        let mut policy = format!(
+            "env_policy(inherit = {}",
+            if self.inherit { "True" } else { "False" }
+        );
+        if !self.allow.is_empty() {
+            let allow: Vec<String> = self.allow.iter().map(|a| quote(a)).collect();
+            let _ = write!(policy, ", allow = [{}]", allow.join(", "));
+        }
+        if !self.set.is_empty() {
+            let _ = write!(policy, ", set = {}", env_to_starlark(&self.set));
+        }
+        policy.push(')');
+        policy
+    }
+}
+
+impl ArgSpec {
+    fn to_starlark(&self) -> String {
+        match self {
+            ArgSpec::Literal(arg) => quote(arg),
+            ArgSpec::Variable(name) => name.clone(),
+        }
+    }
+
+    fn variable(&self) -> Option<&str> {
+        match self {
+            ArgSpec::Variable(name) => Some(name.as_str()),
+            ArgSpec::Literal(_) => None,
+        }
+    }
+}
+
+/// Returns the environment variables as a starlark dict.
+fn env_to_starlark(env: &[(String, ArgSpec)]) -> String {
+    let env: Vec<String> = env
+        .iter()
+        .map(|(name, value)| format!("{}: {}", quote(name), value.to_starlark()))
+        .collect();
+    format!("{{{}}}", env.join(", "))
+}
+
+impl WorkflowBuilder {
+    pub fn new() -> Self {
+        WorkflowBuilder::default()
+    }
+
+    pub fn variable(mut self, variable: VariableSpec) -> Self {
+        self.variables.push(variable);
+        self
+    }
+
+    /// Adds a node, which the following calls to `action` and `next` build.
+    /// The first node is the entrypoint unless another one is set.
+    pub fn node(mut self, name: &str) -> Self {
+        self.nodes.push(NodeSpec {
+            name: name.to_string(),
+            actions: vec![],
+            next: None,
+            timeout: None,
+            retries: 0,
+            tags: vec![],
+            pipe: false,
+        });
+        self
+    }
+
+    /// Adds an action to the last node, a node with several actions runs
+    /// them in order.
+    pub fn action(self, action: ActionSpec) -> Self {
+        self.last_node("action", |node| node.actions.push(action))
+    }
+
+    /// Sets the node which runs after the last node.
+    pub fn next(self, node: &str) -> Self {
+        self.last_node("next", |last| {
+            last.next = Some(NextSpec::Node(node.to_string()))
+        })
+    }
+
+    /// Runs `then` after the last node if the variable equals the value
+    /// once it ran, `else_` otherwise or stops without one.
+    pub fn when(self, variable: &str, equals: &str, then: &str, else_: Option<&str>) -> Self {
+        self.last_node("when", |last| {
+            last.next = Some(NextSpec::When {
+                variable: variable.to_string(),
+                equals: equals.to_string(),
+                then: then.to_string(),
+                else_: else_.map(|e| e.to_string()),
+            })
+        })
+    }
+
+    /// Stops each attempt of the last node after `secs` seconds.
+    pub fn timeout(self, secs: u32) -> Self {
+        self.last_node("timeout", |last| last.timeout = Some(secs))
+    }
+
+    /// Runs the last node again up to `retries` times if it fails.
+    pub fn retries(self, retries: u32) -> Self {
+        self.last_node("retries", |last| last.retries = retries)
+    }
+
+    /// Tags the last node, for the tag filters of the run.
+    pub fn tags<I: IntoIterator<Item = S>, S: AsRef<str>>(self, tags: I) -> Self {
+        self.last_node("tags", |last| {
+            last.tags
+                .extend(tags.into_iter().map(|t| t.as_ref().to_string()))
+        })
+    }
+
+    /// Pipes the stdout of each action of the last node into the next one.
+    pub fn pipe(self) -> Self {
+        self.last_node("pipe", |last| last.pipe = true)
+    }
+
+    /// Sets which environment variables the tools are spawned with.
+    pub fn env_policy(mut self, policy: EnvPolicySpec) -> Self {
+        self.env_policy = Some(policy);
+        self
+    }
+
+    // builds the last node, or records that the method was called before
+    // there was one
+    fn last_node(mut self, method: &'static str, build: impl FnOnce(&mut NodeSpec)) -> Self {
+        match self.nodes.last_mut() {
+            Some(node) => build(node),
+            None => {
+                self.before_node.get_or_insert(method);
+            }
+        }
+        self
+    }
+
+    pub fn entrypoint(mut self, node: &str) -> Self {
+        self.entrypoint = Some(node.to_string());
+        self
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        if let Some(method) = self.before_node {
+            bail!("'{}' was called before any node was added", method);
+        }
+        let reserved = reserved_names();
+        let mut variables = HashSet::new();
+        for variable in &self.variables {
+            if !is_identifier(&variable.name) || reserved.contains(variable.name.as_str()) {
+                bail!("'{}' can not be the name of a variable", variable.name);
+            }
+            if !variables.insert(variable.name.as_str()) {
+                bail!("variable '{}' is declared more than once", variable.name);
+            }
+        }
+        let nodes: HashSet<&str> = self.nodes.iter().map(|n| n.name.as_str()).collect();
+        if nodes.len() != self.nodes.len() {
+            bail!("the workflow has more than one node with the same name");
+        }
+        for node in &self.nodes {
+            if node.actions.is_empty() {
+                bail!("node '{}' has no action", node.name);
+            }
+            let aliased_path = node.actions.iter().find_map(|a| match &a.tool {
+                ToolSpec::Path(path) if !a.aliases.is_empty() => Some(path),
+                _ => None,
+            });
+            if let Some(path) = aliased_path {
+                bail!(
+                    "node '{}' gives aliases to the tool at '{}', only a builtin tool has them",
+                    node.name,
+                    path
+                );
+            }
+            let (targets, used) = match &node.next {
+                None => (vec![], None),
+                Some(NextSpec::Node(next)) => (vec![next], None),
+                Some(NextSpec::When {
+                    variable,
+                    then,
+                    else_,
+                    ..
+                }) => (
+                    std::iter::once(then).chain(else_).collect(),
+                    Some(variable.as_str()),
+                ),
+            };
+            if let Some(next) = targets.into_iter().find(|n| !nodes.contains(n.as_str())) {
+                bail!(
+                    "node '{}' runs '{}' next, which is not a node",
+                    node.name,
+                    next
+                );
+            }
+            let used = node.actions.iter().flat_map(|a| a.variables()).chain(used);
+            for name in used {
+                if !variables.contains(name) {
+                    bail!(
+                        "node '{}' uses '{}', which is not a variable",
+                        node.name,
+                        name
+                    );
+                }
+            }
+        }
+        if let Some(entrypoint) = self.entrypoint.as_deref().filter(|e| !nodes.contains(e)) {
+            bail!("the entrypoint '{}' is not a node", entrypoint);
+        }
+        let policy_variables = self
+            .env_policy
+            .iter()
+            .flat_map(|policy| policy.set.iter().filter_map(|(_, value)| value.variable()));
+        for name in policy_variables {
+            if !variables.contains(name) {
+                bail!("the env_policy uses '{}', which is not a variable", name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the workflow as the source of a workflow file, formatted in
+    /// the canonical style.
+    pub fn to_starlark(&self) -> anyhow::Result<String> {
+        self.check()?;
+        let mut source = String::new();
+        for variable in &self.variables {
+            let mut args = vec![];
+            if let Some(default) = &variable.default {
+                args.push(format!("default = {}", quote(default)));
+            }
+            if let Some(env) = &variable.env {
+                args.push(format!("env = {}", quote(env)));
+            }
+            if let Some(flag) = &variable.cli_flag {
+                args.push(format!("cli_flag = {}", quote(flag)));
+            }
+            if let Some(group) = &variable.group {
+                args.push(format!("group = {}", quote(group)));
+            }
+            if variable.required {
+                args.push("required = True".to_string());
+            }
+            if let Some(prompt) = &variable.prompt {
+                args.push(format!("prompt = {}", quote(prompt)));
+            }
+            if let Some(doc) = &variable.doc {
+                args.push(format!("doc = {}", quote(doc)));
+            }
+            let _ = writeln!(source, "{} = variable({})", variable.name, args.join(", "));
+        }
+        if !self.variables.is_empty() {
+            source.push('\n');
+        }
+        if self
+            .nodes
+            .iter()
+            .any(|node| matches!(node.next, Some(NextSpec::Node(_))))
+        {
+            source.push_str(GO_TO);
+        }
+
+        let nodes: Vec<String> = self
+            .nodes
+            .iter()
+            .map(|node| {
+                let mut text = match &node.actions[..] {
+                    [action] if !node.pipe => format!(
+                        "node(name = {}, action = {}",
+                        quote(&node.name),
+                        action.to_starlark()
+                    ),
+                    actions => format!(
+                        "sequence(name = {}, actions = [{}]",
+                        quote(&node.name),
+                        actions
+                            .iter()
+                            .map(|a| a.to_starlark())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                };
+                match &node.next {
+                    None => {}
+                    Some(NextSpec::Node(next)) => {
+                        let _ = write!(text, ", next = _go_to({})", quote(next));
+                    }
+                    Some(NextSpec::When {
+                        variable,
+                        equals,
+                        then,
+                        else_,
+                    }) => {
+                        let _ = write!(
+                            text,
+                            ", next = when(variable = {}, equals = {}, then = {}",
+                            variable,
+                            quote(equals),
+                            quote(then)
+                        );
+                        if let Some(else_) = else_ {
+                            let _ = write!(text, ", else_ = {}", quote(else_));
+                        }
+                        text.push(')');
+                    }
+                }
+                if let Some(timeout) = node.timeout {
+                    let _ = write!(text, ", timeout = {}", timeout);
+                }
+                if node.retries > 0 {
+                    let _ = write!(text, ", retries = {}", node.retries);
+                }
+                if !node.tags.is_empty() {
+                    let tags: Vec<String> = node.tags.iter().map(|t| quote(t)).collect();
+                    let _ = write!(text, ", tags = [{}]", tags.join(", "));
+                }
+                if node.pipe {
+                    text.push_str(", pipe = True");
+                }
+                text.push(')');
+                text
+            })
+            .collect();
+        let mut workflow = String::from("main = workflow(");
+        if let Some(entrypoint) = self
+            .entrypoint
+            .as_ref()
+            .or(self.nodes.first().map(|n| &n.name))
+        {
+            let _ = write!(workflow, "entrypoint = {}, ", quote(entrypoint));
+        }
+        let _ = write!(workflow, "graph = [{},]", nodes.join(", "));
+        if let Some(policy) = &self.env_policy {
+            let _ = write!(workflow, ", env_policy = {}", policy.to_starlark());
+        }
+        workflow.push_str(")\n");
+        source.push_str(&workflow);
+        format_source("<builder>", &source)
+    }
+
+    /// Returns a runner for the workflow, which runs as if it was a workflow
+    /// file in `working_dir`.
+    pub fn runner<T: ParseDelegate + std::fmt::Debug>(
+        &self,
+        working_dir: &Path,
+        delegate: T,
+    ) -> anyhow::Result<Runner> {
+        Runner::from_source(working_dir, self.to_starlark()?, delegate)
+    }
+}
+
+impl From<&WorkflowIr> for WorkflowBuilder {
+    fn from(ir: &WorkflowIr) -> Self {
+        let mut builder = WorkflowBuilder::new().entrypoint(&ir.entrypoint);
+        for variable in &ir.variables {
+            let mut spec = VariableSpec::new(&variable.name);
+            spec.default = variable.default.clone();
+            spec.env = variable.env.clone();
+            spec.cli_flag = variable.cli_flag.clone();
+            spec.group = variable.group.clone();
+            spec.doc = variable.doc.clone();
+            spec.required = variable.required;
+            spec.prompt = variable.prompt.clone();
+            builder = builder.variable(spec);
+        }
+        let arg = |arg: &ArgIr| match arg {
+            ArgIr::Literal(arg) => ArgSpec::Literal(arg.clone()),
+            ArgIr::Variable(name) => ArgSpec::Variable(name.clone()),
+        };
+        let setter = |setter: &SetterIr| SetterSpec {
+            variable: setter.variable.clone(),
+            from_file: setter.from_file.as_ref().map(arg),
+            strip: setter.strip,
+            json_path: setter.json_path.clone(),
+        };
+        for node in &ir.nodes {
+            builder = builder.node(&node.name);
+            for action in &node.actions {
+                let mut spec = match &action.tool {
+                    ToolIr::Builtin { name, aliases } => {
+                        ActionSpec::builtin_tool(name).aliases(aliases)
+                    }
+                    ToolIr::Path { path } => ActionSpec::tool(path),
+                };
+                spec.args = action.args.iter().map(arg).collect();
+                spec.env = action
+                    .env
+                    .iter()
+                    .map(|(name, value)| (name.clone(), arg(value)))
+                    .collect();
+                spec.cwd = action.cwd.clone();
+                spec.setters = action.setters.iter().map(setter).collect();
+                spec.encoding = action.encoding.clone();
+                spec.strict_utf8 = action.strict_utf8;
+                spec.ok_exit_codes = action.ok_exit_codes.clone();
+                spec.quiet = action.quiet;
+                spec.retries = action.retries;
+                spec.retry_delay = action.retry_delay;
+                builder = builder.action(spec);
+            }
+            builder = match &node.next {
+                None => builder,
+                Some(NextIr::Node { node }) => builder.next(node),
+                Some(NextIr::When {
+                    variable,
+                    equals,
+                    then,
+                    else_,
+                }) => builder.when(variable, equals, then, else_.as_deref()),
+            };
+            builder = builder.retries(node.retries).tags(&node.tags);
+            if let Some(timeout) = node.timeout {
+                builder = builder.timeout(timeout);
+            }
+            if node.pipe {
+                builder = builder.pipe();
+            }
+        }
+        if let Some(policy) = &ir.env_policy {
+            builder = builder.env_policy(EnvPolicySpec {
+                inherit: policy.inherit,
+                allow: policy.allow.clone(),
+                set: policy
+                    .set
+                    .iter()
+                    .map(|(name, value)| (name.clone(), arg(value)))
+                    .collect(),
+            });
+        }
+        builder
+    }
+}
+
+// the next of a node which always runs the same node next
+const GO_TO: &str = "def _go_to(node):
+    def _next(ctx, args):
+        return node
+
+    return next(implementation = _next)()
+
+";
+
+// the keywords and reserved words of starlark, which can not be bound
+const KEYWORDS: [&str; 33] = [
+    "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif",
+    "else", "except", "finally", "for", "from", "global", "if", "import", "in", "is", "lambda",
+    "load", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while", "with", "yield",
+];
+
+/// Returns the names a variable can not be bound to: the keywords, the
+/// builtins of a workflow and the globals of the generated source.
+fn reserved_names() -> HashSet<String> {
+    let globals = super::workflow_globals().build();
+    KEYWORDS
+        .iter()
+        .map(|k| k.to_string())
+        .chain(globals.names().map(|name| name.as_str().to_string()))
+        .chain(["main".to_string(), "_go_to".to_string()])
+        .collect()
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Returns the string as a starlark string literal.
+fn quote(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::WorkflowDelegate;
+    use tempfile::tempdir;
+
+    fn workflow() -> WorkflowBuilder {
+        WorkflowBuilder::new()
+            .variable(
+                VariableSpec::new("greeting")
+                    .default("hi \"there\"")
+                    .cli_flag("--greeting"),
+            )
+            .node("a")
+            .action(ActionSpec::builtin_tool("echo").var_arg("greeting"))
+            .next("b")
+            .node("b")
+            .action(ActionSpec::builtin_tool("true"))
+            .action(ActionSpec::builtin_tool("echo").args(["a", "b"]))
+    }
+
+    #[test]
+    fn test_to_starlark() {
+        assert_eq!(
+            workflow().to_starlark().unwrap(),
+            r#"greeting = variable(default = "hi \"there\"", cli_flag = "--greeting")
+
+def _go_to(node):
+    def _next(ctx, args):
+        return node
+
+    return next(implementation = _next)()
+
+main = workflow(
+    entrypoint = "a",
+    graph = [
+        node(
+            name = "a",
+            action = action(tool = builtin_tool(name = "echo"), args = [greeting]),
+            next = _go_to("b"),
+        ),
+        sequence(
+            name = "b",
+            actions = [
+                action(tool = builtin_tool(name = "true")),
+                action(tool = builtin_tool(name = "echo"), args = ["a", "b"]),
+            ],
+        ),
+    ],
+)
+"#
+        );
+    }
+
+    #[test]
+    fn test_to_starlark_attributes() {
+        let workflow = WorkflowBuilder::new()
+            .variable(VariableSpec::new("host").required().prompt("Which host?"))
+            .variable(VariableSpec::new("version"))
+            .env_policy(
+                EnvPolicySpec::new(false)
+                    .allow("PATH")
+                    .var_set("HOST", "host"),
+            )
+            .node("read")
+            .action(
+                ActionSpec::builtin_tool("python3")
+                    .aliases(["python"])
+                    .arg("read.py")
+                    .setter(SetterSpec::new("version").file("VERSION").keep_whitespace())
+                    .ok_exit_codes([0, 3])
+                    .quiet()
+                    .retries(2, 5),
+            )
+            .timeout(60)
+            .tags(["read"])
+            .node("json")
+            .action(
+                ActionSpec::builtin_tool("cat")
+                    .setter(SetterSpec::new("version").json_path(".version")),
+            )
+            .pipe();
+        assert_eq!(
+            workflow.to_starlark().unwrap(),
+            r#"host = variable(required = True, prompt = "Which host?")
+version = variable()
+
+main = workflow(
+    entrypoint = "read",
+    graph = [
+        node(
+            name = "read",
+            action = action(
+                tool = builtin_tool(name = "python3", aliases = ["python"]),
+                args = ["read.py"],
+                setters = [setter(variable = version, from_file = "VERSION", strip = False)],
+                ok_exit_codes = [0, 3],
+                quiet = True,
+                retries = 2,
+                retry_delay = 5,
+            ),
+            timeout = 60,
+            tags = ["read"],
+        ),
+        sequence(
+            name = "json",
+            actions = [
+                action(
+                    tool = builtin_tool(name = "cat"),
+                    setters = [setter(variable = version, json_path = ".version")],
+                ),
+            ],
+            pipe = True,
+        ),
+    ],
+    env_policy = env_policy(inherit = False, allow = ["PATH"], set = {"HOST": host}),
+)
+"#
+        );
+        let dir = tempdir().unwrap();
+        workflow
+            .runner(dir.path(), WorkflowDelegate::new())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_check() {
+        let err = |builder: WorkflowBuilder| builder.to_starlark().unwrap_err().to_string();
+        assert_eq!(
+            err(workflow().next("c")),
+            "node 'b' runs 'c' next, which is not a node"
+        );
+        assert_eq!(err(workflow().node("c")), "node 'c' has no action");
+        assert_eq!(
+            err(workflow().variable(VariableSpec::new("greeting"))),
+            "variable 'greeting' is declared more than once"
+        );
+        assert_eq!(
+            err(workflow().variable(VariableSpec::new("not a name"))),
+            "'not a name' can not be the name of a variable"
+        );
+        for name in [
+            "main", "_go_to", "if", "node", "action", "workflow", "when", "len",
+        ] {
+            assert_eq!(
+                err(workflow().variable(VariableSpec::new(name))),
+                format!("'{}' can not be the name of a variable", name)
+            );
+        }
+        assert_eq!(
+            err(WorkflowBuilder::new()
+                .action(ActionSpec::tool("x"))
+                .next("a")
+                .node("a")),
+            "'action' was called before any node was added"
+        );
+        assert_eq!(
+            err(WorkflowBuilder::new().when("v", "1", "a", None).node("a")),
+            "'when' was called before any node was added"
+        );
+        assert_eq!(
+            err(WorkflowBuilder::new().retries(1).node("a")),
+            "'retries' was called before any node was added"
+        );
+        assert_eq!(
+            err(workflow()
+                .node("c")
+                .action(ActionSpec::tool("x").aliases(["y"]))),
+            "node 'c' gives aliases to the tool at 'x', only a builtin tool has them"
+        );
+        assert_eq!(
+            err(workflow()
+                .node("c")
+                .action(ActionSpec::tool("x").setter(SetterSpec::new("missing").json_path(".")))),
+            "node 'c' uses 'missing', which is not a variable"
+        );
+        assert_eq!(
+            err(workflow().env_policy(EnvPolicySpec::new(true).var_set("X", "missing"))),
+            "the env_policy uses 'missing', which is not a variable"
+        );
+        assert_eq!(
+            err(workflow()
+                .node("c")
+                .action(ActionSpec::tool("x").var_arg("missing"))),
+            "node 'c' uses 'missing', which is not a variable"
+        );
+        assert_eq!(
+            err(workflow().entrypoint("z")),
+            "the entrypoint 'z' is not a node"
+        );
+    }
+
+    #[test]
+    fn test_run() {
+        let dir = tempdir().unwrap();
+        let runner = workflow()
+            .runner(
+                dir.path(),
+                WorkflowDelegate::with_args(vec!["--greeting".to_string(), "hello".to_string()]),
+            )
+            .unwrap();
+        let result = runner.run(None).unwrap();
+        assert!(result.succeeded());
+        let names: Vec<&str> = result.nodes.iter().map(|n| n.name.as_str()).collect();
+        assert_eq!(names, ["a", "b"]);
+    }
+}
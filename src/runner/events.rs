@@ -0,0 +1,163 @@
+use crate::stdlib::{ActionStatus, NodeResult};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+
+/// What happens while a workflow runs, published on the delegate's bus for
+/// the reporters which subscribed to it.
+#[derive(Debug, Clone)]
+pub enum RunEvent {
+    /// The workflow started running, once its variables have their values.
+    /// `nodes` are the names of all of the nodes in its graph.
+    Started {
+        workflow: PathBuf,
+        nodes: Vec<String>,
+    },
+    /// A node is about to run.
+    NodeStarted { node: String },
+    /// A node ran or was skipped.
+    NodeFinished(NodeResult),
+    /// The action at `index` of the node is about to run, `action` names
+    /// what it runs.
+    ActionStarted {
+        node: String,
+        index: usize,
+        action: String,
+    },
+    /// The action at `index` of the node ran or failed to.
+    ActionFinished {
+        node: String,
+        index: usize,
+        status: ActionStatus,
+    },
+    /// The run ended. It did not succeed if a node failed or the run could
+    /// not carry on.
+    Finished { succeeded: bool },
+}
+
+/// Called with each event as it is published, before it is sent to the
+/// subscribers, so what it prints is in order with the output of the
+/// actions.
+pub type EventHandler = Arc<dyn Fn(&RunEvent) + Send + Sync>;
+
+/// Sends the events of a run to every subscriber and handler, so a new way
+/// of reporting a run does not need changes to the runner.
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Vec<Sender<RunEvent>>,
+    handlers: Vec<EventHandler>,
+}
+
+impl fmt::Debug for EventBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventBus")
+            .field("subscribers", &self.subscribers.len())
+            .field("handlers", &self.handlers.len())
+            .finish()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        EventBus::default()
+    }
+
+    /// Returns a receiver for the events published from now on. It ends
+    /// once the bus is dropped.
+    pub fn subscribe(&mut self) -> Receiver<RunEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Sends the events published from now on to `sender`.
+    pub fn add_subscriber(&mut self, sender: Sender<RunEvent>) {
+        self.subscribers.push(sender);
+    }
+
+    /// Calls `handler` with the events published from now on.
+    pub fn add_handler(&mut self, handler: EventHandler) {
+        self.handlers.push(handler);
+    }
+
+    pub fn publish(&self, event: RunEvent) {
+        for handler in &self.handlers {
+            handler(&event);
+        }
+        for subscriber in &self.subscribers {
+            // the subscriber may have stopped listening
+            let _ = subscriber.send(event.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::{Runner, WorkflowDelegate};
+    use crate::stdlib::test_utils::TempWorkflowFile;
+
+    #[test]
+    fn test_publish() {
+        let mut bus = EventBus::new();
+        let first = bus.subscribe();
+        let second = bus.subscribe();
+        drop(second);
+        let handled = Arc::new(std::sync::Mutex::new(vec![]));
+        bus.add_handler({
+            let handled = handled.clone();
+            Arc::new(move |event| handled.lock().unwrap().push(format!("{:?}", event)))
+        });
+        bus.publish(RunEvent::Finished { succeeded: true });
+        drop(bus);
+        assert_eq!(*handled.lock().unwrap(), ["Finished { succeeded: true }"]);
+        let events: Vec<RunEvent> = first.into_iter().collect();
+        assert!(matches!(
+            events[..],
+            [RunEvent::Finished { succeeded: true }]
+        ));
+    }
+
+    #[test]
+    fn test_run_publishes_events() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+def _fail():
+    return 1
+
+main = workflow(
+    entrypoint = "a",
+    graph = [
+        node(name = "a", action = fn_action(implementation = _fail)),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        let (sender, events) = mpsc::channel();
+        let runner =
+            Runner::new(file.path(), WorkflowDelegate::new().with_subscriber(sender)).unwrap();
+        runner.run(None).unwrap();
+        drop(runner);
+
+        let events: Vec<RunEvent> = events.into_iter().collect();
+        assert!(matches!(
+            &events[0],
+            RunEvent::Started { workflow, nodes } if *workflow == file.path() && nodes == &["a"]
+        ));
+        assert!(matches!(&events[1], RunEvent::NodeStarted { node } if node == "a"));
+        assert!(matches!(
+            &events[2],
+            RunEvent::ActionStarted { node, index: 0, action } if node == "a" && action == "fn_action"
+        ));
+        assert!(matches!(
+            &events[3],
+            RunEvent::ActionFinished { node, index: 0, status } if node == "a" && !status.succeeded()
+        ));
+        assert!(matches!(&events[4], RunEvent::NodeFinished(node) if node.name == "a"));
+        assert!(matches!(events[5], RunEvent::Finished { succeeded: false }));
+        assert_eq!(events.len(), 6);
+    }
+}
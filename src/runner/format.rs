@@ -0,0 +1,699 @@
+use super::lint::source::{Source, Token, TokenKind};
+use super::syntax_errors;
+use starlark::syntax::{AstModule, Dialect};
+use std::mem;
+
+const MAX_WIDTH: usize = 100;
+const INDENT: usize = 4;
+
+/// The keywords which are not values, an open paren after one of them is
+/// not a call.
+const KEYWORDS: [&str; 15] = [
+    "and", "break", "continue", "def", "elif", "else", "for", "if", "in", "lambda", "not", "or",
+    "pass", "return", "while",
+];
+
+/// The builtins and their args in the order they are formatted in, which
+/// is the order they are documented in.
+const BUILTIN_ARGS: [(&str, &[&str]); 23] = [
+    (
+        "action",
+        &[
+            "tool",
+            "args",
+            "setters",
+            "encoding",
+            "strict_utf8",
+            "ok_exit_codes",
+            "env",
+            "cwd",
+            "quiet",
+            "retries",
+            "retry_delay",
+        ],
+    ),
+    ("archive", &["paths", "dest", "root", "setters"]),
+    ("builtin_tool", &["name", "aliases"]),
+    ("const", &["value"]),
+    ("env_policy", &["inherit", "allow", "set"]),
+    ("file", &["name", "content"]),
+    ("fn_action", &["implementation", "args", "setters"]),
+    ("glob", &["allow_empty"]),
+    ("manual_gate", &["name", "message", "timeout", "next"]),
+    ("native_tool", &["name"]),
+    ("next", &["implementation", "args"]),
+    (
+        "node",
+        &[
+            "name",
+            "action",
+            "graph",
+            "imports",
+            "exports",
+            "next",
+            "requires_lock",
+            "timeout",
+            "retries",
+            "tags",
+            "artifacts",
+            "cache",
+            "inputs",
+        ],
+    ),
+    ("render_template", &["src", "dest", "vars", "setters"]),
+    (
+        "sequence",
+        &[
+            "name",
+            "actions",
+            "next",
+            "requires_lock",
+            "timeout",
+            "retries",
+            "tags",
+            "artifacts",
+            "cache",
+            "inputs",
+            "pipe",
+        ],
+    ),
+    (
+        "secret",
+        &[
+            "env", "env_mode", "cli_flag", "readers", "writers", "group", "doc",
+        ],
+    ),
+    (
+        "setter",
+        &[
+            "implementation",
+            "variable",
+            "returns",
+            "from_file",
+            "strip",
+            "json_path",
+        ],
+    ),
+    (
+        "shell",
+        &[
+            "script",
+            "shell",
+            "setters",
+            "ok_exit_codes",
+            "env",
+            "cwd",
+            "quiet",
+            "retries",
+            "retry_delay",
+        ],
+    ),
+    ("tool", &["path", "wasm"]),
+    (
+        "unarchive",
+        &["src", "dest", "keep_special_bits", "setters"],
+    ),
+    (
+        "variable",
+        &[
+            "default",
+            "env",
+            "env_mode",
+            "cli_flag",
+            "readers",
+            "writers",
+            "group",
+            "deprecated",
+            "required",
+            "prompt",
+            "doc",
+        ],
+    ),
+    ("verify", &["path", "sha256", "setters"]),
+    ("when", &["variable", "equals", "then", "else_"]),
+    (
+        "workflow",
+        &[
+            "entrypoint",
+            "graph",
+            "requires",
+            "redact_patterns",
+            "matrix",
+            "inputs",
+            "outputs",
+            "env_policy",
+        ],
+    ),
+];
+
+#[derive(Debug)]
+enum Node<'a> {
+    Token(&'a Token),
+    Group(Group<'a>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum GroupKind {
+    Call,
+    Subscript,
+    /// A parenthesized expression or a tuple.
+    Paren,
+    List,
+    Dict,
+}
+
+/// Brackets and the comma separated items in them.
+#[derive(Debug)]
+struct Group<'a> {
+    kind: GroupKind,
+    open: &'a Token,
+    close: &'a Token,
+    items: Vec<Item<'a>>,
+    trailing_comma: bool,
+    // a comment on the line of the open bracket
+    comment: Option<&'a Token>,
+    // comments after the last item
+    dangling: Vec<&'a Token>,
+}
+
+#[derive(Debug, Default)]
+struct Item<'a> {
+    nodes: Vec<Node<'a>>,
+    // comments on the lines before the item
+    leading: Vec<&'a Token>,
+    // a comment at the end of the item's line
+    trailing: Option<&'a Token>,
+}
+
+/// A logical line or a comment on its own line.
+#[derive(Debug)]
+enum Line<'a> {
+    Statement {
+        nodes: Vec<Node<'a>>,
+        indent: usize,
+        comment: Option<&'a Token>,
+    },
+    Comment(&'a Token),
+}
+
+impl<'a> Node<'a> {
+    /// Returns the text of an ident or an operator, and nothing for a
+    /// literal or a group, so a string is never mistaken for syntax.
+    fn text(&self) -> &str {
+        match self {
+            Node::Token(token) if token.kind != TokenKind::Str => &token.text,
+            _ => "",
+        }
+    }
+
+    /// Whether the node is a value, which makes the operator after it a
+    /// binary one and the brackets after it a call or subscript.
+    fn is_value(&self) -> bool {
+        match self {
+            Node::Group(_) => true,
+            Node::Token(token) => match token.kind {
+                TokenKind::Ident => !KEYWORDS.contains(&token.text.as_str()),
+                TokenKind::Str => true,
+                _ => token.text.starts_with(|c: char| c.is_ascii_digit()),
+            },
+        }
+    }
+}
+
+impl<'a> Item<'a> {
+    fn keyword(&self) -> Option<&str> {
+        match self.nodes.as_slice() {
+            [Node::Token(name), eq, ..] if name.kind == TokenKind::Ident && eq.text() == "=" => {
+                Some(&name.text)
+            }
+            _ => None,
+        }
+    }
+
+    fn has_comments(&self) -> bool {
+        !self.leading.is_empty() || self.trailing.is_some()
+    }
+}
+
+impl<'a> Group<'a> {
+    fn is_comprehension(&self) -> bool {
+        self.items.len() == 1 && self.items[0].nodes.iter().any(|n| n.text() == "for")
+    }
+
+    /// Whether the group is a tuple with a single item, e.g. `(a,)`.
+    fn is_single_tuple(&self) -> bool {
+        self.kind == GroupKind::Paren && self.items.len() == 1 && self.trailing_comma
+    }
+
+    /// Whether each item ends with a comma when the group is split over
+    /// lines.
+    fn comma_per_item(&self) -> bool {
+        match self.kind {
+            GroupKind::Call | GroupKind::List | GroupKind::Dict => !self.is_comprehension(),
+            GroupKind::Paren => self.items.len() > 1 || self.trailing_comma,
+            GroupKind::Subscript => false,
+        }
+    }
+
+    /// Whether the group has to be split over lines whatever its width,
+    /// because it has comments or a trailing comma or contains a group
+    /// which does.
+    fn must_split(&self) -> bool {
+        let magic_comma = self.trailing_comma && self.comma_per_item() && !self.is_single_tuple();
+        magic_comma
+            || self.comment.is_some()
+            || !self.dangling.is_empty()
+            || self.items.iter().any(|item| {
+                item.has_comments()
+                    || item.nodes.iter().any(|node| match node {
+                        Node::Group(group) => group.must_split(),
+                        Node::Token(token) => token.text.contains('\n'),
+                    })
+            })
+    }
+
+    /// Orders the keyword args of a call of a builtin as they are
+    /// documented, positional args stay first and unknown args last.
+    fn order_args(&mut self, params: &[&str]) {
+        self.items.sort_by_key(|item| match item.keyword() {
+            Some(name) => match params.iter().position(|p| *p == name) {
+                Some(i) => (1, i),
+                None => (2, 0),
+            },
+            None if matches!(item.nodes.first(), Some(n) if n.text().starts_with('*')) => (2, 0),
+            None => (0, 0),
+        });
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    tokens: Vec<&'a Token>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    /// Whether there is no newline between the end of `a` and `b`.
+    fn same_line(&self, a: &Token, b: &Token) -> bool {
+        !self.text[a.end..b.start].contains('\n')
+    }
+
+    fn node(&mut self, before: &[Node<'a>]) -> Node<'a> {
+        let token = self.tokens[self.pos];
+        let follows_value = before.last().is_some_and(|n| n.is_value());
+        let bracket = match token.kind {
+            TokenKind::Other => token.text.as_str(),
+            _ => "",
+        };
+        let kind = match bracket {
+            "(" if follows_value => GroupKind::Call,
+            "(" => GroupKind::Paren,
+            "[" if follows_value => GroupKind::Subscript,
+            "[" => GroupKind::List,
+            "{" => GroupKind::Dict,
+            _ => {
+                self.pos += 1;
+                return Node::Token(token);
+            }
+        };
+        let mut group = self.group(kind);
+        if kind == GroupKind::Call {
+            let method = before.len() > 1 && before[before.len() - 2].text() == ".";
+            let callee = before.last().map(|n| n.text()).unwrap_or_default();
+            if let Some((_, params)) = BUILTIN_ARGS.iter().find(|(name, _)| *name == callee) {
+                if !method {
+                    group.order_args(params);
+                }
+            }
+        }
+        Node::Group(group)
+    }
+
+    fn group(&mut self, kind: GroupKind) -> Group<'a> {
+        let open = self.tokens[self.pos];
+        self.pos += 1;
+        let mut items: Vec<Item> = vec![];
+        let mut item = Item::default();
+        let mut comment = None;
+        let mut after_comma = false;
+        loop {
+            let token = self.tokens[self.pos];
+            let previous = self.tokens[self.pos - 1];
+            match (token.kind, token.text.as_str()) {
+                (TokenKind::Comment, _) => {
+                    self.pos += 1;
+                    if !self.same_line(previous, token) {
+                        item.leading.push(token);
+                    } else if std::ptr::eq(previous, open) {
+                        comment = Some(token);
+                    } else if item.nodes.is_empty() {
+                        match items.last_mut() {
+                            Some(last) => last.trailing = Some(token),
+                            None => comment = Some(token),
+                        }
+                    } else {
+                        item.trailing = Some(token);
+                    }
+                }
+                (TokenKind::Other, ",") => {
+                    self.pos += 1;
+                    items.push(mem::take(&mut item));
+                    after_comma = true;
+                }
+                (TokenKind::Other, ")" | "]" | "}") => {
+                    self.pos += 1;
+                    let dangling = match item.nodes.is_empty() {
+                        true => mem::take(&mut item.leading),
+                        false => vec![],
+                    };
+                    if !item.nodes.is_empty() {
+                        items.push(item);
+                    }
+                    return Group {
+                        kind,
+                        open,
+                        close: token,
+                        trailing_comma: after_comma && !items.is_empty(),
+                        items,
+                        comment,
+                        dangling,
+                    };
+                }
+                _ => {
+                    let node = self.node(&item.nodes);
+                    item.nodes.push(node);
+                    after_comma = false;
+                }
+            }
+        }
+    }
+
+    fn lines(&mut self) -> Vec<(Line<'a>, usize)> {
+        let mut lines = vec![];
+        let mut end = 0;
+        while self.pos < self.tokens.len() {
+            let first = self.tokens[self.pos];
+            let blank_lines = match lines.is_empty() {
+                true => 0,
+                false => self.text[end..first.start]
+                    .matches('\n')
+                    .count()
+                    .saturating_sub(1),
+            };
+            if first.kind == TokenKind::Comment {
+                self.pos += 1;
+                end = first.end;
+                lines.push((Line::Comment(first), blank_lines));
+                continue;
+            }
+            let mut nodes = vec![];
+            let mut comment = None;
+            while let Some(token) = self.tokens.get(self.pos) {
+                if token.kind == TokenKind::Comment {
+                    if !self.same_line(self.tokens[self.pos - 1], token) {
+                        break;
+                    }
+                    comment = Some(*token);
+                    self.pos += 1;
+                } else if token.line_start && !nodes.is_empty() {
+                    break;
+                } else {
+                    let node = self.node(&nodes);
+                    nodes.push(node);
+                }
+            }
+            end = self.tokens[self.pos - 1].end;
+            lines.push((
+                Line::Statement {
+                    nodes,
+                    indent: first.indent,
+                    comment,
+                },
+                blank_lines,
+            ));
+        }
+        lines
+    }
+}
+
+struct Printer<'a> {
+    text: &'a str,
+    out: String,
+}
+
+impl<'a> Printer<'a> {
+    fn column(&self) -> usize {
+        let line_start = self.out.rfind('\n').map_or(0, |i| i + 1);
+        self.out[line_start..].chars().count()
+    }
+
+    fn newline(&mut self, indent: usize) {
+        self.out.push('\n');
+        self.out.push_str(&" ".repeat(indent));
+    }
+
+    fn token(&mut self, token: &Token) {
+        self.out.push_str(&self.text[token.start..token.end]);
+    }
+
+    fn comment(&mut self, comment: &Token) {
+        self.out.push_str("  ");
+        self.out.push_str(&comment.text);
+    }
+
+    fn nodes(&mut self, nodes: &[Node], indent: usize, in_subscript: bool, flat: bool) {
+        let mut previous: Option<&Node> = None;
+        let mut unary = false;
+        for node in nodes {
+            if let Some(previous) = previous {
+                if space_between(previous, node, unary, in_subscript) {
+                    self.out.push(' ');
+                }
+            }
+            match node {
+                Node::Token(token) => self.token(token),
+                Node::Group(group) => self.group(group, indent, flat),
+            }
+            unary = matches!(node.text(), "-" | "+" | "~" | "*" | "**")
+                && !previous.is_some_and(|p| p.is_value());
+            previous = Some(node);
+        }
+    }
+
+    fn flat_group(&mut self, group: &Group) {
+        let in_subscript = group.kind == GroupKind::Subscript;
+        self.token(group.open);
+        for (i, item) in group.items.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.nodes(&item.nodes, 0, in_subscript, true);
+        }
+        if group.is_single_tuple() {
+            self.out.push(',');
+        }
+        self.token(group.close);
+    }
+
+    fn group(&mut self, group: &Group, indent: usize, flat: bool) {
+        let start = self.out.len();
+        let column = self.column();
+        self.flat_group(group);
+        let width = self.out[start..].chars().count();
+        if flat || (!group.must_split() && column + width <= MAX_WIDTH) {
+            return;
+        }
+        self.out.truncate(start);
+
+        let inner = indent + INDENT;
+        self.token(group.open);
+        if let Some(comment) = group.comment {
+            self.comment(comment);
+        }
+        for (i, item) in group.items.iter().enumerate() {
+            for comment in &item.leading {
+                self.newline(inner);
+                self.out.push_str(&comment.text);
+            }
+            self.newline(inner);
+            self.nodes(
+                &item.nodes,
+                inner,
+                group.kind == GroupKind::Subscript,
+                false,
+            );
+            if i + 1 < group.items.len() || group.comma_per_item() {
+                self.out.push(',');
+            }
+            if let Some(comment) = item.trailing {
+                self.comment(comment);
+            }
+        }
+        for comment in &group.dangling {
+            self.newline(inner);
+            self.out.push_str(&comment.text);
+        }
+        self.newline(indent);
+        self.token(group.close);
+    }
+}
+
+/// Whether the nodes are separated by a space, `unary` is whether `a` is
+/// a unary operator.
+fn space_between(a: &Node, b: &Node, unary: bool, in_subscript: bool) -> bool {
+    if unary {
+        return false;
+    }
+    if let Node::Group(group) = b {
+        return !matches!(group.kind, GroupKind::Call | GroupKind::Subscript);
+    }
+    match (a.text(), b.text()) {
+        (_, "," | ";" | ":") | (".", _) | (_, ".") => false,
+        (":", _) => !in_subscript,
+        _ => true,
+    }
+}
+
+/// Formats the source of a workflow file in the canonical style. Brackets
+/// which fit on the line are joined onto it, and those which do not, or
+/// which have a trailing comma or comments, get an item per line with a
+/// trailing comma. Blocks are indented by four spaces, the args of
+/// builtins are ordered as they are documented and comments are kept.
+pub fn format_source(name: &str, text: &str) -> anyhow::Result<String> {
+    let errors = syntax_errors(name, text);
+    if !errors.is_empty() {
+        let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        anyhow::bail!("{}", errors.join("\n\n"));
+    }
+    let source = Source::new(text);
+    let mut tokens: Vec<&Token> = source.tokens().iter().chain(source.comments()).collect();
+    tokens.sort_by_key(|t| t.start);
+    let mut parser = Parser {
+        text,
+        tokens,
+        pos: 0,
+    };
+    let lines = parser.lines();
+
+    let mut printer = Printer {
+        text,
+        out: String::new(),
+    };
+    // the indents of the open blocks in the source
+    let mut blocks = vec![0];
+    let mut opens_block = false;
+    for (line, blank_lines) in &lines {
+        if *blank_lines > 0 {
+            printer.out.push('\n');
+        }
+        match line {
+            Line::Statement {
+                nodes,
+                indent,
+                comment,
+            } => {
+                while blocks.len() > 1 && indent < blocks.last().unwrap() {
+                    blocks.pop();
+                }
+                if indent > blocks.last().unwrap() {
+                    blocks.push(*indent);
+                }
+                let indent = (blocks.len() - 1) * INDENT;
+                printer.out.push_str(&" ".repeat(indent));
+                printer.nodes(nodes, indent, false, false);
+                if let Some(comment) = comment {
+                    printer.comment(comment);
+                }
+                opens_block = nodes.last().is_some_and(|n| n.text() == ":");
+            }
+            Line::Comment(comment) => {
+                let mut depth = blocks.iter().filter(|i| **i <= comment.column).count() - 1;
+                if opens_block && comment.column > *blocks.last().unwrap() {
+                    depth = blocks.len();
+                }
+                printer.out.push_str(&" ".repeat(depth * INDENT));
+                printer.out.push_str(&comment.text);
+            }
+        }
+        printer.out.push('\n');
+    }
+
+    if let Err(e) = AstModule::parse(name, printer.out.clone(), &Dialect::Standard) {
+        anyhow::bail!(
+            "formatting {} gave invalid starlark, which is a bug: {}",
+            name,
+            e.into_anyhow()
+        );
+    }
+    Ok(printer.out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(text: &str) -> String {
+        let formatted = format_source("test.workflow", text).unwrap();
+        assert_eq!(
+            format_source("test.workflow", &formatted).unwrap(),
+            formatted,
+            "formatting is not idempotent"
+        );
+        formatted
+    }
+
+    #[test]
+    fn test_spacing() {
+        assert_eq!(
+            format("v=variable(env='V',default='a')  # v\nn = -1+2*x[1:-1]\nt=( 1, )\n"),
+            "v = variable(default = 'a', env = 'V')  # v\nn = -1 + 2 * x[1:-1]\nt = (1,)\n"
+        );
+        assert_eq!(
+            format("d = {'a':1,'b':[x for x in range(3)]}\nf = 1.5e-3\n"),
+            "d = {'a': 1, 'b': [x for x in range(3)]}\nf = 1.5e-3\n"
+        );
+    }
+
+    #[test]
+    fn test_blocks_and_blank_lines() {
+        assert_eq!(
+            format("def _next(ctx,args):\n  # why\n  if ctx.exit_code==0:\n     return 'b'\n\n\n\n  return None\nx = 1"),
+            "def _next(ctx, args):\n    # why\n    if ctx.exit_code == 0:\n        return 'b'\n\n    return None\nx = 1\n"
+        );
+    }
+
+    #[test]
+    fn test_splitting() {
+        // a trailing comma keeps a list split and comments stay with their items
+        assert_eq!(
+            format("x = [1,2,]\nmain = workflow(graph = [\n  # a\n  node(action = a, name = 'a'),  # the a node\n], entrypoint = 'a')\n"),
+            r#"x = [
+    1,
+    2,
+]
+main = workflow(
+    entrypoint = 'a',
+    graph = [
+        # a
+        node(name = 'a', action = a),  # the a node
+    ],
+)
+"#
+        );
+        // too long to fit on one line
+        let long = format!("x = f(a = \"{}\", b = [1, 2])\n", "x".repeat(90));
+        assert_eq!(
+            format(&long),
+            format!(
+                "x = f(\n    a = \"{}\",\n    b = [1, 2],\n)\n",
+                "x".repeat(90)
+            )
+        );
+    }
+
+    #[test]
+    fn test_syntax_error() {
+        let err = format_source("test.workflow", "x = (").unwrap_err();
+        assert!(err.to_string().contains("test.workflow"));
+    }
+}
@@ -0,0 +1,457 @@
+use crate::stdlib::{Node, RunResult, Workflow};
+use std::fmt::Write;
+
+/// How a node in the graph took part in a run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NodeState {
+    Ran,
+    Failed,
+    Skipped,
+}
+
+/// Returns every node in the graph along with how it took part in the run.
+/// Nodes which ran but are not in the graph, e.g. when the graph was not
+/// recorded, are included as well.
+fn node_states(result: &RunResult) -> Vec<(&str, NodeState)> {
+    let mut names: Vec<&str> = result.graph_nodes.iter().map(|n| n.as_str()).collect();
+    for node in &result.nodes {
+        if !names.contains(&node.name.as_str()) {
+            names.push(&node.name);
+        }
+    }
+
+    names
+        .into_iter()
+        .map(|name| {
+            let runs: Vec<_> = result.nodes.iter().filter(|n| n.name == name).collect();
+            let state = if runs.is_empty() {
+                NodeState::Skipped
+            } else if runs.iter().any(|n| !n.succeeded()) {
+                NodeState::Failed
+            } else {
+                NodeState::Ran
+            };
+            (name, state)
+        })
+        .collect()
+}
+
+/// Renders the graph as DOT with the path taken by the run highlighted.
+/// Edges are labelled with the step at which they were taken.
+pub fn run_graph_dot(result: &RunResult) -> String {
+    let escape = |name: &str| name.replace('\\', "\\\\").replace('"', "\\\"");
+    let mut out = String::from("digraph workflow {\n");
+    for (name, state) in node_states(result) {
+        let style = match state {
+            NodeState::Ran => "style=filled, fillcolor=palegreen",
+            NodeState::Failed => "style=filled, fillcolor=salmon",
+            NodeState::Skipped => "style=dashed, color=gray, fontcolor=gray",
+        };
+        let _ = writeln!(out, "  \"{}\" [{}];", escape(name), style);
+    }
+    for (step, pair) in result.nodes.windows(2).enumerate() {
+        let _ = writeln!(
+            out,
+            "  \"{}\" -> \"{}\" [label=\"{}\"];",
+            escape(&pair[0].name),
+            escape(&pair[1].name),
+            step + 1
+        );
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Renders the graph as a mermaid flowchart with the path taken by the
+/// run highlighted. Edges are labelled with the step at which they were taken.
+pub fn run_graph_mermaid(result: &RunResult) -> String {
+    let states = node_states(result);
+    let id = |name: &str| {
+        let index = states.iter().position(|(n, _)| *n == name).unwrap_or(0);
+        format!("n{}", index)
+    };
+
+    let mut out = String::from("flowchart TD\n");
+    for (name, state) in &states {
+        let class = match state {
+            NodeState::Ran => "ran",
+            NodeState::Failed => "failed",
+            NodeState::Skipped => "skipped",
+        };
+        let _ = writeln!(
+            out,
+            "  {}[\"{}\"]:::{}",
+            id(name),
+            name.replace('"', "#quot;"),
+            class
+        );
+    }
+    for (step, pair) in result.nodes.windows(2).enumerate() {
+        let _ = writeln!(
+            out,
+            "  {} -->|{}| {}",
+            id(&pair[0].name),
+            step + 1,
+            id(&pair[1].name)
+        );
+    }
+    out.push_str("  classDef ran fill:#9f9\n");
+    out.push_str("  classDef failed fill:#f99\n");
+    out.push_str("  classDef skipped stroke-dasharray:5 5,color:#999\n");
+    out
+}
+
+/// Where the workflow can go after a node, as far as it is known before the
+/// workflow runs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeclaredNext {
+    /// The workflow stops after the node.
+    Stop,
+    /// The nodes a `when()` can go to, each with the comparison which leads
+    /// there.
+    Edges(Vec<(String, String)>),
+    /// A next function picks the node at run time, by the function's name.
+    Picked(String),
+}
+
+/// A node as it is declared, which is what `describe --graph` draws.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeclaredNode {
+    pub name: String,
+    pub actions: usize,
+    pub next: DeclaredNext,
+    /// The nodes of the node's graph, which starts at the first of them.
+    pub graph: Vec<DeclaredNode>,
+}
+
+impl DeclaredNode {
+    fn new(node: &Node) -> Self {
+        let next = match (node.when(), node.next_name()) {
+            (Some(when), _) => DeclaredNext::Edges(
+                when.edges()
+                    .into_iter()
+                    .map(|(target, label)| (target.to_string(), label))
+                    .collect(),
+            ),
+            (None, Some(name)) => DeclaredNext::Picked(name),
+            (None, None) => DeclaredNext::Stop,
+        };
+        DeclaredNode {
+            name: node.name().to_string(),
+            actions: node.action_count(),
+            next,
+            graph: node.graph().into_iter().map(DeclaredNode::new).collect(),
+        }
+    }
+
+    fn label(&self) -> String {
+        match self.actions {
+            0 => self.name.clone(),
+            1 => format!("{}\n1 action", self.name),
+            n => format!("{}\n{} actions", self.name, n),
+        }
+    }
+}
+
+/// Walks the nodes of the workflow and those of the graphs of its nodes,
+/// the node the workflow starts at comes first.
+pub fn declared_graph(workflow: &Workflow) -> Vec<DeclaredNode> {
+    let mut nodes: Vec<_> = workflow
+        .nodes()
+        .into_iter()
+        .map(DeclaredNode::new)
+        .collect();
+    if let Ok(first) = workflow.first_node() {
+        if let Some(index) = nodes.iter().position(|n| n.name == first.name()) {
+            let first = nodes.remove(index);
+            nodes.insert(0, first);
+        }
+    }
+    nodes
+}
+
+/// The edges out of the nodes, by the ids of the nodes. Names are only
+/// unique within a graph so the nodes get ids by their position, prefixed
+/// by the id of the node whose graph they are in. The target of an edge
+/// which a next function picks is None.
+fn declared_edges<'a>(
+    nodes: &'a [DeclaredNode],
+    prefix: &str,
+) -> Vec<(String, Option<String>, &'a str)> {
+    let id = |name: &str| {
+        nodes
+            .iter()
+            .position(|n| n.name == name)
+            .map(|index| format!("{}{}", prefix, index))
+    };
+    let mut edges = vec![];
+    for (index, node) in nodes.iter().enumerate() {
+        let from = format!("{}{}", prefix, index);
+        match &node.next {
+            DeclaredNext::Stop => {}
+            DeclaredNext::Edges(targets) => {
+                for (target, label) in targets {
+                    if let Some(to) = id(target) {
+                        edges.push((from.clone(), Some(to), label.as_str()));
+                    }
+                }
+            }
+            DeclaredNext::Picked(name) => edges.push((from, None, name.as_str())),
+        }
+    }
+    edges
+}
+
+/// Renders the graph as declared as DOT. The graph of a node is drawn as a
+/// cluster which the node runs, the node a next function picks as a `?`.
+pub fn declared_graph_dot(nodes: &[DeclaredNode]) -> String {
+    fn walk(out: &mut String, nodes: &[DeclaredNode], prefix: &str, indent: &str) {
+        let escape = |text: &str| {
+            text.replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n")
+        };
+        for (index, node) in nodes.iter().enumerate() {
+            let id = format!("{}{}", prefix, index);
+            let shape = if node.graph.is_empty() {
+                "box"
+            } else {
+                "box3d"
+            };
+            let label = escape(&node.label());
+            let _ = writeln!(
+                out,
+                "{}{} [label=\"{}\", shape={}];",
+                indent, id, label, shape
+            );
+            if !node.graph.is_empty() {
+                let _ = writeln!(out, "{}subgraph cluster_{} {{", indent, id);
+                let _ = writeln!(out, "{}  label=\"{}\";", indent, escape(&node.name));
+                walk(
+                    out,
+                    &node.graph,
+                    &format!("{}_", id),
+                    &format!("{}  ", indent),
+                );
+                let _ = writeln!(out, "{}}}", indent);
+                let _ = writeln!(
+                    out,
+                    "{}{} -> {}_0 [style=dotted, label=\"runs\"];",
+                    indent, id, id
+                );
+            }
+        }
+        for (from, to, label) in declared_edges(nodes, prefix) {
+            let _ = match to {
+                Some(to) => writeln!(out, "{}{} -> {} [label=\"{}\"];", indent, from, to, escape(label)),
+                None => writeln!(
+                    out,
+                    "{0}{1}_next [label=\"?\", shape=circle];\n{0}{1} -> {1}_next [style=dashed, label=\"{2}\"];",
+                    indent,
+                    from,
+                    escape(label)
+                ),
+            };
+        }
+    }
+
+    let mut out = String::from("digraph workflow {\n");
+    if !nodes.is_empty() {
+        out.push_str("  start [shape=point];\n  start -> n0;\n");
+    }
+    walk(&mut out, nodes, "n", "  ");
+    out.push_str("}\n");
+    out
+}
+
+/// Renders the graph as declared as a mermaid flowchart. The graph of a
+/// node is drawn as a subgraph which the node runs, the node a next
+/// function picks as a `?`.
+pub fn declared_graph_mermaid(nodes: &[DeclaredNode]) -> String {
+    fn walk(out: &mut String, nodes: &[DeclaredNode], prefix: &str, indent: &str) {
+        let escape = |text: &str| text.replace('"', "#quot;");
+        for (index, node) in nodes.iter().enumerate() {
+            let id = format!("{}{}", prefix, index);
+            let label = escape(&node.label()).replace('\n', "<br/>");
+            let _ = match node.graph.is_empty() {
+                true => writeln!(out, "{}{}[\"{}\"]", indent, id, label),
+                false => writeln!(out, "{}{}[[\"{}\"]]", indent, id, label),
+            };
+            if !node.graph.is_empty() {
+                let _ = writeln!(
+                    out,
+                    "{}subgraph {}_graph[\"{}\"]",
+                    indent,
+                    id,
+                    escape(&node.name)
+                );
+                walk(
+                    out,
+                    &node.graph,
+                    &format!("{}_", id),
+                    &format!("{}  ", indent),
+                );
+                let _ = writeln!(out, "{}end", indent);
+                let _ = writeln!(out, "{}{} -.->|runs| {}_0", indent, id, id);
+            }
+        }
+        for (from, to, label) in declared_edges(nodes, prefix) {
+            let _ = match to {
+                Some(to) => writeln!(out, "{}{} -->|\"{}\"| {}", indent, from, escape(label), to),
+                None => writeln!(
+                    out,
+                    "{0}{1} -.->|\"{2}\"| {1}_next((?))",
+                    indent,
+                    from,
+                    escape(label)
+                ),
+            };
+        }
+    }
+
+    let mut out = String::from("flowchart TD\n");
+    if !nodes.is_empty() {
+        out.push_str("  start((start)) --> n0\n");
+    }
+    walk(&mut out, nodes, "n", "  ");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib::NodeResult;
+    use std::time::Duration;
+
+    fn result() -> RunResult {
+        let node = |name: &str, exit_code: i32| NodeResult {
+            name: name.to_string(),
+            duration: Duration::default(),
+            exit_code: Some(exit_code),
+            exit_code_ok: None,
+            error: None,
+            memory: None,
+            variables: None,
+            envs: vec![],
+            artifacts: vec![],
+            cached: false,
+            approval: None,
+        };
+        RunResult {
+            nodes: vec![node("check", 0), node("build", 1)],
+            graph_nodes: vec![
+                "check".to_string(),
+                "build".to_string(),
+                "deploy".to_string(),
+            ],
+            redactor: None,
+        }
+    }
+
+    #[test]
+    fn test_run_graph_dot() {
+        assert_eq!(
+            run_graph_dot(&result()),
+            r#"digraph workflow {
+  "check" [style=filled, fillcolor=palegreen];
+  "build" [style=filled, fillcolor=salmon];
+  "deploy" [style=dashed, color=gray, fontcolor=gray];
+  "check" -> "build" [label="1"];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_run_graph_mermaid() {
+        assert_eq!(
+            run_graph_mermaid(&result()),
+            r#"flowchart TD
+  n0["check"]:::ran
+  n1["build"]:::failed
+  n2["deploy"]:::skipped
+  n0 -->|1| n1
+  classDef ran fill:#9f9
+  classDef failed fill:#f99
+  classDef skipped stroke-dasharray:5 5,color:#999
+"#
+        );
+    }
+
+    fn declared() -> Vec<DeclaredNode> {
+        let node = |name: &str, actions: usize, next: DeclaredNext| DeclaredNode {
+            name: name.to_string(),
+            actions,
+            next,
+            graph: vec![],
+        };
+        vec![
+            node(
+                "build",
+                2,
+                DeclaredNext::Edges(vec![
+                    ("deploy".to_string(), "== \"release\"".to_string()),
+                    ("test".to_string(), "!= \"release\"".to_string()),
+                ]),
+            ),
+            node("test", 1, DeclaredNext::Picked("_pick".to_string())),
+            DeclaredNode {
+                graph: vec![node("push", 1, DeclaredNext::Stop)],
+                ..node("deploy", 0, DeclaredNext::Stop)
+            },
+        ]
+    }
+
+    #[test]
+    fn test_declared_graph_dot() {
+        assert_eq!(
+            declared_graph_dot(&declared()),
+            r#"digraph workflow {
+  start [shape=point];
+  start -> n0;
+  n0 [label="build\n2 actions", shape=box];
+  n1 [label="test\n1 action", shape=box];
+  n2 [label="deploy", shape=box3d];
+  subgraph cluster_n2 {
+    label="deploy";
+    n2_0 [label="push\n1 action", shape=box];
+  }
+  n2 -> n2_0 [style=dotted, label="runs"];
+  n0 -> n2 [label="== \"release\""];
+  n0 -> n1 [label="!= \"release\""];
+  n1_next [label="?", shape=circle];
+  n1 -> n1_next [style=dashed, label="_pick"];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_declared_graph_mermaid() {
+        assert_eq!(
+            declared_graph_mermaid(&declared()),
+            r#"flowchart TD
+  start((start)) --> n0
+  n0["build<br/>2 actions"]
+  n1["test<br/>1 action"]
+  n2[["deploy"]]
+  subgraph n2_graph["deploy"]
+    n2_0["push<br/>1 action"]
+  end
+  n2 -.->|runs| n2_0
+  n0 -->|"== #quot;release#quot;"| n2
+  n0 -->|"!= #quot;release#quot;"| n1
+  n1 -.->|"_pick"| n1_next((?))
+"#
+        );
+        assert_eq!(declared_graph_mermaid(&[]), "flowchart TD\n");
+    }
+
+    #[test]
+    fn test_nodes_outside_graph_are_included() {
+        let mut result = result();
+        result.graph_nodes.clear();
+        assert!(run_graph_dot(&result).contains("\"build\" [style=filled, fillcolor=salmon]"));
+        assert!(!run_graph_dot(&result).contains("deploy"));
+    }
+}
@@ -0,0 +1,585 @@
+use crate::stdlib::approval::Approval;
+use crate::stdlib::env_capture::ActionEnv;
+use crate::stdlib::failure_injection::FailureInjection;
+use crate::stdlib::redact::Redactor;
+use crate::stdlib::schema::Schema;
+use crate::stdlib::variable_resolver::VariableSnapshot;
+use crate::stdlib::{NodeResult, RunResult, TagFilter};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const HISTORY_FILE_NAME: &str = "history.jsonl";
+const ARTIFACTS_DIR_NAME: &str = "artifacts";
+
+const RECORD_SCHEMA: Schema = Schema {
+    name: "the history record",
+    version: 1,
+    oldest: 0,
+    migrations: &[unversioned_record],
+};
+
+// the records written before they were versioned only lack the version
+fn unversioned_record(value: serde_json::Value) -> anyhow::Result<serde_json::Value> {
+    Ok(value)
+}
+
+/// Returns the directory where workflow state, such as the run history,
+/// is stored.
+///
+/// The directory is taken from $WORKFLOW_STATE_DIR if set, then
+/// $XDG_STATE_HOME/workflow and finally $HOME/.local/state/workflow.
+pub fn state_dir() -> anyhow::Result<PathBuf> {
+    if let Ok(dir) = std::env::var("WORKFLOW_STATE_DIR") {
+        return Ok(PathBuf::from(dir));
+    }
+    if let Ok(dir) = std::env::var("XDG_STATE_HOME") {
+        return Ok(PathBuf::from(dir).join("workflow"));
+    }
+    match std::env::var("HOME") {
+        Ok(home) => Ok(PathBuf::from(home).join(".local/state/workflow")),
+        Err(_) => anyhow::bail!("Unable to determine the state directory, $HOME is not set"),
+    }
+}
+
+/// A record of a single node run stored in the history.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeRecord {
+    pub name: String,
+    pub duration_ms: u64,
+    pub exit_code: Option<i32>,
+    /// Whether the exit code is one of the action's ok_exit_codes, missing
+    /// from records written before ok_exit_codes existed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code_ok: Option<bool>,
+    pub error: Option<String>,
+    /// The values of the variables once the node finished.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variables: Option<VariableSnapshot>,
+    /// The environment of each action in the node which spawned a tool.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub envs: Vec<ActionEnv>,
+    /// Where the artifacts of the node were copied to.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<PathBuf>,
+    /// Whether the node was skipped because it was unchanged since its last
+    /// successful run.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub cached: bool,
+    /// Who approved the node if it is a manual gate which was approved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub approval: Option<Approval>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+impl From<&NodeResult> for NodeRecord {
+    fn from(node: &NodeResult) -> Self {
+        NodeRecord {
+            name: node.name.clone(),
+            duration_ms: node.duration.as_millis() as u64,
+            exit_code: node.exit_code,
+            exit_code_ok: node.exit_code_ok,
+            error: node.error.clone(),
+            variables: node.variables.clone(),
+            envs: node.envs.clone(),
+            artifacts: node.artifacts.clone(),
+            cached: node.cached,
+            approval: node.approval.clone(),
+        }
+    }
+}
+
+impl NodeRecord {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+            && self
+                .exit_code_ok
+                .unwrap_or(self.exit_code.unwrap_or(0) == 0)
+    }
+}
+
+/// A record of a single invocation of a workflow.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryRecord {
+    pub workflow: PathBuf,
+    pub args: Vec<String>,
+    /// Seconds since the unix epoch when the run started.
+    pub started_at: u64,
+    pub duration_ms: u64,
+    /// The node the run started at if it was not the entrypoint.
+    pub start_at: Option<String>,
+    pub nodes: Vec<NodeRecord>,
+    /// An error which stopped the workflow before any node ran,
+    /// for example a parse error.
+    pub error: Option<String>,
+    /// The variables restored before the run started when it resumed an
+    /// earlier run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checkpoint: Option<VariableSnapshot>,
+    /// The directory the artifacts of the run were collected in, which is
+    /// removed along with the record.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifacts_dir: Option<PathBuf>,
+    /// Whether the action args were checked before the actions ran, a
+    /// rerun checks them as well.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub check_args: bool,
+    /// The tags of the nodes which were skipped, a rerun skips them as well.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skip_tags: Vec<String>,
+    /// The tags of the only nodes which ran, a rerun only runs them as
+    /// well.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub only_tags: Vec<String>,
+    /// The directory the output of the actions was logged in, a rerun logs
+    /// in it as well.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_dir: Option<PathBuf>,
+    /// Whether the run worked on copies of the workflow's inputs, a rerun
+    /// is sandboxed as well.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub sandboxed: bool,
+    /// How the tools of the run were made to fail, a rerun fails the same
+    /// ones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_injection: Option<FailureInjection>,
+}
+
+impl HistoryRecord {
+    pub fn new(workflow: PathBuf, args: Vec<String>, start_at: Option<String>) -> Self {
+        HistoryRecord {
+            workflow,
+            args,
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            duration_ms: 0,
+            start_at,
+            nodes: vec![],
+            error: None,
+            checkpoint: None,
+            artifacts_dir: None,
+            check_args: false,
+            skip_tags: vec![],
+            only_tags: vec![],
+            log_dir: None,
+            sandboxed: false,
+            failure_injection: None,
+        }
+    }
+
+    /// Returns the filter which picked the nodes of the run.
+    pub fn tag_filter(&self) -> TagFilter {
+        TagFilter::new(self.skip_tags.clone(), self.only_tags.clone())
+    }
+
+    /// Fills in the record from the result of running the workflow. The
+    /// args which hold a secret, or something else which was redacted, are
+    /// left out so a rerun has to be given them again.
+    pub fn finish(&mut self, result: &anyhow::Result<RunResult>, duration_ms: u64) {
+        self.duration_ms = duration_ms;
+        match result {
+            Ok(result) => {
+                self.nodes = result.nodes.iter().map(NodeRecord::from).collect();
+                if let Some(redactor) = &result.redactor {
+                    self.args = redact_args(&self.args, redactor);
+                }
+            }
+            Err(e) => self.error = Some(format!("{:#}", e)),
+        }
+        // the directory is only created once an artifact is copied into it
+        if self.nodes.iter().all(|n| n.artifacts.is_empty()) {
+            self.artifacts_dir = None;
+        }
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none() && self.nodes.iter().all(|n| n.succeeded())
+    }
+
+    pub fn first_failed_node(&self) -> Option<&NodeRecord> {
+        self.nodes.iter().find(|n| !n.succeeded())
+    }
+
+    /// Returns the variables as they were before the first failed node
+    /// ran, which is the snapshot of the node before it or the checkpoint
+    /// the run itself was resumed from.
+    pub fn checkpoint_before_failure(&self) -> Option<&VariableSnapshot> {
+        match self.nodes.iter().position(|n| !n.succeeded()) {
+            Some(0) => self.checkpoint.as_ref(),
+            Some(index) => self.nodes[index - 1].variables.as_ref(),
+            None => None,
+        }
+    }
+}
+
+/// Removes the args the redactor would change, along with the flag a
+/// removed value was given to.
+fn redact_args(args: &[String], redactor: &Redactor) -> Vec<String> {
+    let mut kept: Vec<String> = vec![];
+    for arg in args {
+        if redactor.redact(arg) == *arg {
+            kept.push(arg.clone());
+        } else if !arg.starts_with('-')
+            && kept
+                .last()
+                .is_some_and(|flag| flag.starts_with('-') && !flag.contains('='))
+        {
+            kept.pop();
+        }
+    }
+    kept
+}
+
+/// The history of workflow invocations, stored as one json record
+/// per line so appending never has to rewrite the file. Each record
+/// carries the version of its schema, so the records written by older
+/// versions of workflow can still be read.
+#[derive(Debug)]
+pub struct History {
+    path: PathBuf,
+}
+
+impl History {
+    pub fn new(path: PathBuf) -> Self {
+        History { path }
+    }
+
+    /// Returns the history stored in the default state directory.
+    pub fn default_location() -> anyhow::Result<Self> {
+        Ok(History::new(state_dir()?.join(HISTORY_FILE_NAME)))
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// Returns a new directory, next to the history, for the artifacts of a
+    /// run. It is only created once an artifact is copied into it.
+    pub fn new_artifacts_dir(&self) -> PathBuf {
+        let parent = self.path.parent().unwrap_or(Path::new("."));
+        parent
+            .join(ARTIFACTS_DIR_NAME)
+            .join(uuid::Uuid::new_v4().to_string())
+    }
+
+    pub fn append(&self, record: &HistoryRecord) -> anyhow::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", RECORD_SCHEMA.write(record)?)?;
+        Ok(())
+    }
+
+    /// Returns all of the records in the order they were added.
+    pub fn records(&self) -> anyhow::Result<Vec<HistoryRecord>> {
+        if !self.path.exists() {
+            return Ok(vec![]);
+        }
+        let mut records = vec![];
+        for line in BufReader::new(File::open(&self.path)?).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            records.push(RECORD_SCHEMA.read(serde_json::from_str(&line)?)?);
+        }
+        Ok(records)
+    }
+
+    pub fn last(&self) -> anyhow::Result<Option<HistoryRecord>> {
+        Ok(self.records()?.pop())
+    }
+
+    /// Removes the records of runs which started before `started_before`,
+    /// in seconds since the unix epoch, and then the oldest records until
+    /// the history is at most `max_size` bytes. Returns the removed records
+    /// along with their ids, the history is left as is on a dry run.
+    pub fn prune(
+        &self,
+        started_before: Option<u64>,
+        max_size: Option<u64>,
+        dry_run: bool,
+    ) -> anyhow::Result<Vec<(usize, HistoryRecord)>> {
+        if !self.path.exists() {
+            return Ok(vec![]);
+        }
+        // the lines are kept as they are so pruning never rewrites a record
+        let mut lines = vec![];
+        for line in BufReader::new(File::open(&self.path)?).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: HistoryRecord = RECORD_SCHEMA.read(serde_json::from_str(&line)?)?;
+            let keep = started_before.is_none_or(|before| record.started_at >= before);
+            lines.push((line, record, keep));
+        }
+        if let Some(max_size) = max_size {
+            let mut size = 0;
+            for (line, _, keep) in lines.iter_mut().rev().filter(|(_, _, keep)| *keep) {
+                size += line.len() as u64 + 1;
+                *keep = size <= max_size;
+            }
+        }
+
+        let mut kept = String::new();
+        let mut removed = vec![];
+        for (index, (line, record, keep)) in lines.into_iter().enumerate() {
+            match keep {
+                true => {
+                    kept.push_str(&line);
+                    kept.push('\n');
+                }
+                false => removed.push((index + 1, record)),
+            }
+        }
+        if !dry_run && !removed.is_empty() {
+            // written next to the history and renamed so an interrupted
+            // prune can not lose the records being kept
+            let tmp = self.path.with_extension("jsonl.tmp");
+            fs::write(&tmp, kept)?;
+            fs::rename(&tmp, &self.path)?;
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib::{NodeResult, ValueContext, ValueUpdatedBy};
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    fn record(args: &[&str]) -> HistoryRecord {
+        HistoryRecord::new(
+            PathBuf::from("/foo.workflow"),
+            args.iter().map(|a| a.to_string()).collect(),
+            None,
+        )
+    }
+
+    #[test]
+    fn test_empty_history() {
+        let dir = tempdir().unwrap();
+        let history = History::new(dir.path().join("history.jsonl"));
+        assert_eq!(history.records().unwrap(), vec![]);
+        assert_eq!(history.last().unwrap(), None);
+    }
+
+    #[test]
+    fn test_append_and_last() {
+        let dir = tempdir().unwrap();
+        let history = History::new(dir.path().join("nested/history.jsonl"));
+        history.append(&record(&["--a", "1"])).unwrap();
+        history.append(&record(&["--b", "2"])).unwrap();
+
+        assert_eq!(history.records().unwrap().len(), 2);
+        assert_eq!(history.last().unwrap().unwrap().args, vec!["--b", "2"]);
+    }
+
+    #[test]
+    fn test_records_run_options() {
+        let dir = tempdir().unwrap();
+        let history = History::new(dir.path().join("history.jsonl"));
+        let mut r = record(&[]);
+        r.check_args = true;
+        r.skip_tags = vec!["slow".to_string()];
+        r.only_tags = vec!["ci".to_string()];
+        r.log_dir = Some(PathBuf::from("/logs"));
+        history.append(&r).unwrap();
+
+        let last = history.last().unwrap().unwrap();
+        assert_eq!(last, r);
+        assert_eq!(
+            last.tag_filter(),
+            TagFilter::new(vec!["slow".to_string()], vec!["ci".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_redact_args() {
+        let redactor = Redactor::default().with_values(&["s3cret".to_string()]);
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            redact_args(
+                &args(&["--token", "s3cret", "--a=1", "--b=s3cret", "-v", "s3cret"]),
+                &redactor
+            ),
+            args(&["--a=1"])
+        );
+        assert_eq!(
+            redact_args(&args(&["--v", "x", "s3cret"]), &redactor),
+            args(&["--v", "x"])
+        );
+    }
+
+    #[test]
+    fn test_finish_with_result() {
+        let mut r = record(&[]);
+        let result = RunResult {
+            nodes: vec![
+                NodeResult {
+                    name: "a".to_string(),
+                    duration: Duration::from_millis(5),
+                    exit_code: Some(0),
+                    exit_code_ok: Some(true),
+                    error: None,
+                    memory: None,
+                    variables: None,
+                    envs: vec![],
+                    artifacts: vec![],
+                    cached: false,
+                    approval: None,
+                },
+                NodeResult {
+                    name: "b".to_string(),
+                    duration: Duration::from_millis(5),
+                    exit_code: Some(2),
+                    exit_code_ok: Some(false),
+                    error: None,
+                    memory: None,
+                    variables: None,
+                    envs: vec![],
+                    artifacts: vec![],
+                    cached: false,
+                    approval: None,
+                },
+            ],
+            ..Default::default()
+        };
+        r.finish(&Ok(result), 10);
+        assert!(!r.succeeded());
+        assert_eq!(r.first_failed_node().unwrap().name, "b");
+        assert_eq!(r.nodes[0].duration_ms, 5);
+    }
+
+    #[test]
+    fn test_node_record_ok_exit_code() {
+        let json = r#"{"name":"a","duration_ms":0,"exit_code":1,"error":null}"#;
+        let mut node: NodeRecord = serde_json::from_str(json).unwrap();
+        assert!(!node.succeeded());
+        node.exit_code_ok = Some(true);
+        assert!(node.succeeded());
+    }
+
+    #[test]
+    fn test_checkpoint_before_failure() {
+        let snapshot = |value: &str| {
+            VariableSnapshot::from([(
+                "v".to_string(),
+                ValueContext {
+                    value: value.to_string(),
+                    updated_by: ValueUpdatedBy::DefaultValue,
+                },
+            )])
+        };
+        let node = |name: &str, exit_code: i32, value: &str| NodeRecord {
+            name: name.to_string(),
+            duration_ms: 0,
+            exit_code: Some(exit_code),
+            exit_code_ok: None,
+            error: None,
+            variables: Some(snapshot(value)),
+            envs: vec![],
+            artifacts: vec![],
+            cached: false,
+            approval: None,
+        };
+
+        let mut r = record(&[]);
+        r.checkpoint = Some(snapshot("start"));
+        r.nodes = vec![node("a", 0, "a"), node("b", 1, "b")];
+        assert_eq!(r.checkpoint_before_failure(), Some(&snapshot("a")));
+
+        r.nodes = vec![node("b", 1, "b")];
+        assert_eq!(r.checkpoint_before_failure(), Some(&snapshot("start")));
+
+        r.nodes = vec![node("a", 0, "a")];
+        assert_eq!(r.checkpoint_before_failure(), None);
+    }
+
+    #[test]
+    fn test_read_record_without_variables() {
+        let dir = tempdir().unwrap();
+        let history = History::new(dir.path().join("history.jsonl"));
+        fs::write(
+            history.path(),
+            r#"{"workflow":"/foo.workflow","args":[],"started_at":0,"duration_ms":0,"start_at":null,"nodes":[{"name":"a","duration_ms":0,"exit_code":0,"error":null}],"error":null}"#,
+        )
+        .unwrap();
+        let record = history.last().unwrap().unwrap();
+        assert_eq!(record.checkpoint, None);
+        assert_eq!(record.nodes[0].variables, None);
+    }
+
+    #[test]
+    fn test_record_versions() {
+        let dir = tempdir().unwrap();
+        let history = History::new(dir.path().join("history.jsonl"));
+        history.append(&record(&[])).unwrap();
+        assert!(fs::read_to_string(history.path())
+            .unwrap()
+            .contains(r#""version":1"#));
+        assert_eq!(history.records().unwrap(), vec![record(&[])]);
+
+        fs::write(
+            history.path(),
+            r#"{"version":2,"workflow":"/foo.workflow"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            history.records().unwrap_err().to_string(),
+            "the history record was produced by a newer version of workflow (version 2), this one reads up to version 1"
+        );
+    }
+
+    #[test]
+    fn test_prune() {
+        let dir = tempdir().unwrap();
+        let history = History::new(dir.path().join("history.jsonl"));
+        assert!(history.prune(Some(10), Some(0), false).unwrap().is_empty());
+        for (started_at, arg) in [(1, "a"), (2, "b"), (3, "c"), (4, "d")] {
+            let mut r = record(&[arg]);
+            r.started_at = started_at;
+            history.append(&r).unwrap();
+        }
+        let size = fs::metadata(history.path()).unwrap().len();
+
+        let ids = |removed: Vec<(usize, HistoryRecord)>| -> Vec<usize> {
+            removed.into_iter().map(|(id, _)| id).collect()
+        };
+        assert_eq!(ids(history.prune(Some(3), None, true).unwrap()), [1, 2]);
+        assert_eq!(fs::metadata(history.path()).unwrap().len(), size);
+
+        // every record has the same size so half the size keeps two
+        assert_eq!(
+            ids(history.prune(Some(2), Some(size / 2), false).unwrap()),
+            [1, 2]
+        );
+        let records = history.records().unwrap();
+        let args: Vec<&str> = records.iter().map(|r| r.args[0].as_str()).collect();
+        assert_eq!(args, ["c", "d"]);
+        assert!(history.prune(None, None, false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_finish_with_error() {
+        let mut r = record(&[]);
+        r.finish(&Err(anyhow::anyhow!("parse failed")), 1);
+        assert!(!r.succeeded());
+        assert_eq!(r.error, Some("parse failed".to_string()));
+        assert_eq!(r.first_failed_node(), None);
+    }
+}
@@ -0,0 +1,344 @@
+mod rules;
+pub(crate) mod source;
+
+use crate::downcast_delegate_ref;
+use crate::runner::{Runner, WorkflowDelegate};
+use crate::stdlib::{VariableRef, Workflow};
+use anyhow::bail;
+use serde::Deserialize;
+use source::Source;
+use starlark::environment::Module;
+use starlark::eval::Evaluator;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+pub use rules::RULES;
+
+/// The name of the project config file, it applies to the workflows in
+/// the directory it is in and those below it.
+pub const CONFIG_FILE_NAME: &str = ".workflow.json";
+
+/// How a finding of a rule is reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// The rule is not checked.
+    Allow,
+    #[serde(alias = "warn")]
+    Warning,
+    /// Fails the check.
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Allow => write!(f, "allow"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A check over a parsed workflow file.
+pub struct Rule {
+    pub id: &'static str,
+    /// The severity unless it is changed by the config or on the command
+    /// line.
+    pub severity: Severity,
+    pub description: &'static str,
+    check: fn(&LintContext) -> Vec<Problem>,
+}
+
+/// Something a rule found, before it is given a severity.
+#[derive(Debug, PartialEq)]
+struct Problem {
+    line: Option<usize>,
+    message: String,
+    fix: Option<Fix>,
+}
+
+impl Problem {
+    fn new(line: Option<usize>, message: String) -> Self {
+        Problem {
+            line,
+            message,
+            fix: None,
+        }
+    }
+
+    fn with_fix(mut self, fix: Option<Fix>) -> Self {
+        self.fix = fix;
+        self
+    }
+}
+
+/// An edit of the workflow file which fixes a finding, it replaces the
+/// bytes from `start` to `end` with `replacement`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fix {
+    pub start: usize,
+    pub end: usize,
+    pub replacement: String,
+    /// What the edit does, e.g. `add entrypoint = "a"`.
+    pub description: String,
+}
+
+impl Fix {
+    fn insert(offset: usize, text: String, description: String) -> Self {
+        Fix {
+            start: offset,
+            end: offset,
+            replacement: text,
+            description,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub rule: &'static str,
+    pub severity: Severity,
+    /// The line in the workflow file, if the problem has one.
+    pub line: Option<usize>,
+    pub message: String,
+    pub fix: Option<Fix>,
+}
+
+/// What the rules check, the workflow file once it has been parsed.
+struct LintContext<'a, 'v> {
+    source: Source,
+    /// The workflows along with the names they are bound to.
+    workflows: Vec<(String, &'v Workflow<'v>)>,
+    /// The names the variables and consts are bound to.
+    variables: Vec<String>,
+    /// The names the deprecated variables are bound to, with their hints.
+    deprecated: Vec<(String, String)>,
+    delegate: &'a WorkflowDelegate,
+    working_dir: PathBuf,
+}
+
+/// The severity of every rule, the defaults unless they are changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintConfig {
+    severities: BTreeMap<&'static str, Severity>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ProjectConfig {
+    #[serde(default)]
+    lint: BTreeMap<String, Severity>,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        LintConfig {
+            severities: RULES.iter().map(|r| (r.id, r.severity)).collect(),
+        }
+    }
+}
+
+impl LintConfig {
+    /// Returns the config for the workflow, the defaults changed by the
+    /// `lint` section of the closest project config in the workflow's
+    /// directory or above it.
+    pub fn for_workflow(workflow: &Path) -> anyhow::Result<Self> {
+        let mut config = LintConfig::default();
+        let dir = workflow.canonicalize()?;
+        let path = match dir
+            .ancestors()
+            .map(|d| d.join(CONFIG_FILE_NAME))
+            .find(|p| p.is_file())
+        {
+            Some(path) => path,
+            None => return Ok(config),
+        };
+        let project: ProjectConfig = match serde_json::from_str(&fs::read_to_string(&path)?) {
+            Ok(project) => project,
+            Err(e) => bail!("Invalid config {:?}: {}", path, e),
+        };
+        for (rule, severity) in project.lint {
+            if let Err(e) = config.set(&rule, severity) {
+                bail!("Invalid config {:?}: {}", path, e);
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn set(&mut self, rule: &str, severity: Severity) -> anyhow::Result<()> {
+        match RULES.iter().find(|r| r.id == rule) {
+            Some(r) => {
+                self.severities.insert(r.id, severity);
+                Ok(())
+            }
+            None => bail!(
+                "unknown lint rule '{}', the rules are {}",
+                rule,
+                RULES.iter().map(|r| r.id).collect::<Vec<_>>().join(", ")
+            ),
+        }
+    }
+
+    pub fn severity(&self, rule: &str) -> Severity {
+        self.severities
+            .get(rule)
+            .copied()
+            .unwrap_or(Severity::Allow)
+    }
+
+    fn check(&self, ctx: &LintContext) -> Vec<Finding> {
+        let mut findings = vec![];
+        for rule in RULES.iter() {
+            let severity = self.severity(rule.id);
+            if severity == Severity::Allow {
+                continue;
+            }
+            for problem in (rule.check)(ctx) {
+                findings.push(Finding {
+                    rule: rule.id,
+                    severity,
+                    line: problem.line,
+                    message: problem.message,
+                    fix: problem.fix,
+                });
+            }
+        }
+        findings.sort_by_key(|f| f.line);
+        findings
+    }
+}
+
+/// Parses the workflow file, with the args, and returns what the rules
+/// which are not allowed by the config find in it, ordered by line.
+pub fn lint_file(
+    workflow: &Path,
+    args: Vec<String>,
+    config: &LintConfig,
+) -> anyhow::Result<Vec<Finding>> {
+    let source = Source::new(&fs::read_to_string(workflow)?);
+    let runner = Runner::new(workflow.to_path_buf(), WorkflowDelegate::with_args(args))?;
+    let module = Module::new();
+    let mut eval = Evaluator::new(&module);
+    runner.parse_workflow(&mut eval)?;
+
+    let holder = runner.delegate();
+    let delegate = match downcast_delegate_ref!(holder, WorkflowDelegate) {
+        Some(delegate) => delegate,
+        None => bail!("Checking a workflow requires a WorkflowDelegate"),
+    };
+    let mut workflows = vec![];
+    let mut variables = vec![];
+    let mut deprecated = vec![];
+    for name in module.names() {
+        if let Some(value) = module.get(&name) {
+            if let Some(workflow) = Workflow::from_value(value) {
+                workflows.push((name.as_str().to_string(), workflow));
+            } else if let Some(var) = VariableRef::from_value(value) {
+                variables.push(name.as_str().to_string());
+                delegate
+                    .variable_store()
+                    .with_variable(var.identifier(), |v| {
+                        if let Some(hint) = v.deprecated() {
+                            deprecated.push((name.as_str().to_string(), hint));
+                        }
+                    });
+            }
+        }
+    }
+    workflows.sort_by(|a, b| a.0.cmp(&b.0));
+    variables.sort();
+    deprecated.sort();
+
+    let ctx = LintContext {
+        source,
+        workflows,
+        variables,
+        deprecated,
+        delegate,
+        working_dir: runner.working_dir(),
+    };
+    Ok(config.check(&ctx))
+}
+
+/// Returns the text with the fixes applied. A fix which overlaps one
+/// before it is left out, checking again finds it if it is still needed.
+pub fn apply_fixes(text: &str, fixes: &[&Fix]) -> String {
+    let mut fixes = fixes.to_vec();
+    fixes.sort_by_key(|f| (f.start, f.end));
+    let mut fixed = String::new();
+    let mut end = 0;
+    for fix in fixes {
+        if fix.start < end || !text.is_char_boundary(fix.start) {
+            continue;
+        }
+        fixed.push_str(&text[end..fix.start]);
+        fixed.push_str(&fix.replacement);
+        end = fix.end;
+    }
+    fixed.push_str(&text[end..]);
+    fixed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_apply_fixes() {
+        let fix = |start, end, replacement: &str| Fix {
+            start,
+            end,
+            replacement: replacement.to_string(),
+            description: String::new(),
+        };
+        let (a, b, c) = (fix(4, 4, "x"), fix(0, 3, "def"), fix(1, 2, "overlaps"));
+        assert_eq!(apply_fixes("abc ghi", &[&a, &b, &c]), "def xghi");
+        assert_eq!(apply_fixes("abc", &[]), "abc");
+    }
+
+    #[test]
+    fn test_config_set() {
+        let mut config = LintConfig::default();
+        assert_eq!(config.severity("unused-variable"), Severity::Warning);
+        config.set("unused-variable", Severity::Error).unwrap();
+        assert_eq!(config.severity("unused-variable"), Severity::Error);
+        let err = config.set("nope", Severity::Error).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("unknown lint rule 'nope', the rules are unused-variable"));
+    }
+
+    #[test]
+    fn test_config_for_workflow() {
+        let dir = tempdir().unwrap();
+        let nested = dir.path().join("a/b");
+        fs::create_dir_all(&nested).unwrap();
+        let workflow = nested.join("test.workflow");
+        fs::write(&workflow, "").unwrap();
+        assert_eq!(
+            LintConfig::for_workflow(&workflow).unwrap(),
+            LintConfig::default()
+        );
+
+        fs::write(
+            dir.path().join(CONFIG_FILE_NAME),
+            r#"{"lint": {"unused-variable": "error", "unreachable-node": "allow"}}"#,
+        )
+        .unwrap();
+        let config = LintConfig::for_workflow(&workflow).unwrap();
+        assert_eq!(config.severity("unused-variable"), Severity::Error);
+        assert_eq!(config.severity("unreachable-node"), Severity::Allow);
+
+        fs::write(
+            dir.path().join(CONFIG_FILE_NAME),
+            r#"{"lint": {"x": "warn"}}"#,
+        )
+        .unwrap();
+        let err = LintConfig::for_workflow(&workflow).unwrap_err();
+        assert!(err.to_string().contains("unknown lint rule 'x'"));
+    }
+}
@@ -0,0 +1,662 @@
+use super::source::Return;
+use super::{Fix, LintContext, Problem, Rule, Severity};
+use crate::stdlib::Node;
+use std::collections::BTreeSet;
+
+pub const RULES: [Rule; 10] = [
+    Rule {
+        id: "unused-variable",
+        severity: Severity::Warning,
+        description: "a variable or const which is never read",
+        check: unused_variable,
+    },
+    Rule {
+        id: "unreachable-node",
+        severity: Severity::Warning,
+        description: "a node which no next can lead to from the entrypoint",
+        check: unreachable_node,
+    },
+    Rule {
+        id: "missing-tool",
+        severity: Severity::Error,
+        description: "a required command or a tool which can not be found",
+        check: missing_tool,
+    },
+    Rule {
+        id: "stringly-next",
+        severity: Severity::Error,
+        description: "a next which returns the name of a node that is not in the graph",
+        check: stringly_next,
+    },
+    Rule {
+        id: "empty-graph",
+        severity: Severity::Error,
+        description: "a workflow without any nodes",
+        check: empty_graph,
+    },
+    Rule {
+        id: "unknown-entrypoint",
+        severity: Severity::Error,
+        description: "a workflow whose entrypoint is not a node in its graph",
+        check: unknown_entrypoint,
+    },
+    Rule {
+        id: "unnamed-node",
+        severity: Severity::Warning,
+        description: "a node or sequence without a name",
+        check: unnamed_node,
+    },
+    Rule {
+        id: "implicit-entrypoint",
+        severity: Severity::Warning,
+        description: "a workflow with one node and no entrypoint, adding a node breaks it",
+        check: implicit_entrypoint,
+    },
+    Rule {
+        id: "setter-returns",
+        severity: Severity::Error,
+        description:
+            "a setter which returns a type it does not declare, or one a setter can not return",
+        check: setter_returns,
+    },
+    Rule {
+        id: "deprecated-variable",
+        severity: Severity::Warning,
+        description: "a deprecated variable which is still read",
+        check: deprecated_variable,
+    },
+];
+
+fn unused_variable(ctx: &LintContext) -> Vec<Problem> {
+    ctx.variables
+        .iter()
+        .filter(|name| ctx.source.reads(name) == 0)
+        .map(|name| {
+            Problem::new(
+                ctx.source.assignment_line(name),
+                format!("'{}' is never used", name),
+            )
+        })
+        .collect()
+}
+
+fn deprecated_variable(ctx: &LintContext) -> Vec<Problem> {
+    ctx.deprecated
+        .iter()
+        .filter_map(|(name, hint)| {
+            let line = *ctx.source.read_lines(name).first()?;
+            Some(Problem::new(
+                Some(line),
+                format!("'{}' is deprecated: {}", name, hint),
+            ))
+        })
+        .collect()
+}
+
+/// Returns the names of the nodes the node's next can return, None if
+/// some of them are only known when the workflow runs.
+fn next_targets(ctx: &LintContext, node: &Node) -> Option<Vec<String>> {
+    if let Some(when) = node.when() {
+        return Some(when.targets().into_iter().map(String::from).collect());
+    }
+    let function = match node.next_name() {
+        Some(function) => function,
+        None => return Some(vec![]),
+    };
+    let mut targets = vec![];
+    for ret in ctx.source.returns(&function)? {
+        match ret {
+            Return::Literal(name, _) => targets.push(name),
+            Return::None(_) => {}
+            Return::Value(..) | Return::Computed(_) => return None,
+        }
+    }
+    Some(targets)
+}
+
+fn unreachable_node(ctx: &LintContext) -> Vec<Problem> {
+    let mut problems = vec![];
+    for (name, workflow) in &ctx.workflows {
+        let start = match workflow.first_node() {
+            Ok(start) => start,
+            Err(_) => continue,
+        };
+        let mut reached = BTreeSet::from([start.name().to_string()]);
+        let mut queue = vec![start];
+        let mut known = true;
+        while let Some(node) = queue.pop() {
+            let targets = match next_targets(ctx, node) {
+                Some(targets) => targets,
+                None => {
+                    known = false;
+                    break;
+                }
+            };
+            for target in targets {
+                let next = workflow.nodes().into_iter().find(|n| n.name() == target);
+                if let Some(next) = next {
+                    if reached.insert(target) {
+                        queue.push(next);
+                    }
+                }
+            }
+        }
+        if !known {
+            continue;
+        }
+        for node in workflow.nodes() {
+            if !reached.contains(node.name()) {
+                problems.push(Problem::new(
+                    ctx.source.attr_line("name", node.name()),
+                    format!(
+                        "node '{}' in '{}' can not be reached from '{}'",
+                        node.name(),
+                        name,
+                        start.name()
+                    ),
+                ));
+            }
+        }
+    }
+    problems
+}
+
+fn missing_tool(ctx: &LintContext) -> Vec<Problem> {
+    let mut problems = vec![];
+    let mut seen = BTreeSet::new();
+    for (name, workflow) in &ctx.workflows {
+        for command in workflow.missing_requirements() {
+            problems.push(Problem::new(
+                ctx.source.string_line(command),
+                format!(
+                    "'{}' is required by '{}' but is not on the PATH",
+                    command, name
+                ),
+            ));
+        }
+        for node in workflow.nodes() {
+            for tool in node.actions().iter().filter_map(|a| a.tool()) {
+                if tool.is_native() {
+                    continue;
+                }
+                if let Err(e) = tool.real_path(ctx.delegate, &ctx.working_dir) {
+                    let message =
+                        format!("the tool of node '{}' can not be found: {}", node.name(), e);
+                    if seen.insert(message.clone()) {
+                        problems.push(Problem::new(
+                            ctx.source.attr_line("name", node.name()),
+                            message,
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    problems
+}
+
+fn stringly_next(ctx: &LintContext) -> Vec<Problem> {
+    let mut problems = vec![];
+    for (name, workflow) in &ctx.workflows {
+        let nodes: BTreeSet<&str> = workflow.nodes().iter().map(|n| n.name()).collect();
+        // several nodes can share a next
+        let mut checked = BTreeSet::new();
+        for node in workflow.nodes() {
+            let function = match node.next_name() {
+                Some(function) => function,
+                None => continue,
+            };
+            if !checked.insert(function.clone()) {
+                continue;
+            }
+            for ret in ctx.source.returns(&function).unwrap_or_default() {
+                if let Return::Literal(target, line) = ret {
+                    if !nodes.contains(target.as_str()) {
+                        problems.push(Problem::new(
+                            Some(line),
+                            format!(
+                                "'{}' returns '{}' which is not a node in '{}'",
+                                function, target, name
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    problems
+}
+
+fn empty_graph(ctx: &LintContext) -> Vec<Problem> {
+    ctx.workflows
+        .iter()
+        .filter(|(_, workflow)| workflow.nodes().is_empty())
+        .map(|(name, _)| {
+            Problem::new(
+                ctx.source.assignment_line(name),
+                format!("'{}' has no nodes", name),
+            )
+        })
+        .collect()
+}
+
+fn unknown_entrypoint(ctx: &LintContext) -> Vec<Problem> {
+    let mut problems = vec![];
+    for (name, workflow) in &ctx.workflows {
+        let entrypoint = workflow.entrypoint();
+        let nodes = workflow.nodes();
+        // a workflow with one node starts there without an entrypoint
+        if nodes.is_empty() || (entrypoint.is_empty() && nodes.len() == 1) {
+            continue;
+        }
+        if nodes.iter().any(|node| node.name() == entrypoint) {
+            continue;
+        }
+        let message = match entrypoint.is_empty() {
+            true => format!("'{}' has no entrypoint", name),
+            false => format!(
+                "the entrypoint '{}' of '{}' is not a node in its graph",
+                entrypoint, name
+            ),
+        };
+        let line = match entrypoint.is_empty() {
+            true => ctx.source.assignment_line(name),
+            false => ctx.source.attr_line("entrypoint", entrypoint),
+        };
+        problems.push(Problem::new(line, message));
+    }
+    problems
+}
+
+fn unnamed_node(ctx: &LintContext) -> Vec<Problem> {
+    let mut problems = vec![];
+    for function in ["node", "sequence"] {
+        for call in ctx.source.calls(function) {
+            if call.keywords.iter().any(|k| k == "name") {
+                continue;
+            }
+            // named after what it is assigned to, unless a node already is
+            let fix = ctx
+                .source
+                .binding(&call)
+                .filter(|name| ctx.source.attr_line("name", name).is_none())
+                .map(|name| {
+                    let value = format!("{:?}", name);
+                    let (offset, text) = ctx.source.insert_keyword(&call, "name", &value);
+                    Fix::insert(offset, text, format!("add name = {}", value))
+                });
+            problems.push(
+                Problem::new(Some(call.line), format!("the {} has no name", function))
+                    .with_fix(fix),
+            );
+        }
+    }
+    problems
+}
+
+fn implicit_entrypoint(ctx: &LintContext) -> Vec<Problem> {
+    let mut problems = vec![];
+    for (name, workflow) in &ctx.workflows {
+        let nodes = workflow.nodes();
+        if nodes.len() != 1 || !workflow.entrypoint().is_empty() {
+            continue;
+        }
+        let call = ctx
+            .source
+            .calls("workflow")
+            .into_iter()
+            .find(|c| ctx.source.binding(c).as_ref() == Some(name));
+        // an unnamed node is named first
+        let fix = call
+            .as_ref()
+            .filter(|_| !nodes[0].name().is_empty())
+            .map(|call| {
+                let value = format!("{:?}", nodes[0].name());
+                let (offset, text) = ctx.source.insert_keyword(call, "entrypoint", &value);
+                Fix::insert(offset, text, format!("add entrypoint = {}", value))
+            });
+        problems.push(
+            Problem::new(
+                call.map(|c| c.line),
+                format!("'{}' has no entrypoint and starts at its only node", name),
+            )
+            .with_fix(fix),
+        );
+    }
+    problems
+}
+
+fn setter_returns(ctx: &LintContext) -> Vec<Problem> {
+    let mut problems = vec![];
+    let mut seen = BTreeSet::new();
+    for (_, workflow) in &ctx.workflows {
+        for node in workflow.nodes() {
+            for setter in node.actions().iter().flat_map(|a| a.setters()) {
+                if setter.implementation().is_none() {
+                    continue;
+                }
+                let function = setter.implementation_name();
+                let declared = setter.returns();
+                for ret in ctx.source.returns(&function).unwrap_or_default() {
+                    let (typ, line) = match ret {
+                        Return::Literal(_, line) => ("string", line),
+                        Return::None(line) => ("None", line),
+                        Return::Value(typ, line) => (typ, line),
+                        Return::Computed(_) => continue,
+                    };
+                    let message = match declared.is_empty() {
+                        true if typ == "string" || typ == "None" => continue,
+                        true => format!(
+                            "setter '{}' of node '{}' returns {}, a setter must return string or None",
+                            function,
+                            node.name(),
+                            typ
+                        ),
+                        false if declared.iter().any(|d| d == typ) => continue,
+                        false => format!(
+                            "setter '{}' of node '{}' returns {} but declares it returns {}",
+                            function,
+                            node.name(),
+                            typ,
+                            declared.join(" | ")
+                        ),
+                    };
+                    if seen.insert(message.clone()) {
+                        problems.push(Problem::new(Some(line), message));
+                    }
+                }
+            }
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{apply_fixes, lint_file, Finding, LintConfig};
+    use super::*;
+    use crate::stdlib::test_utils::TempWorkflowFile;
+
+    fn lint(content: &str) -> Vec<Finding> {
+        let file = TempWorkflowFile::new("test.workflow", content).unwrap();
+        lint_file(&file.path(), vec![], &LintConfig::default()).unwrap()
+    }
+
+    fn rules(findings: &[Finding]) -> Vec<(&str, Option<usize>)> {
+        findings.iter().map(|f| (f.rule, f.line)).collect()
+    }
+
+    #[test]
+    fn test_clean_workflow() {
+        let findings = lint(
+            r#"
+v = variable(default = "a")
+
+def _next(ctx, args):
+    if ctx.exit_code == 0:
+        return "b"
+    return None
+
+main = workflow(
+    entrypoint = "a",
+    graph = [
+        node(
+            name = "a",
+            action = action(tool = builtin_tool(name = "echo"), args = [v]),
+            next = next(implementation = _next)(),
+        ),
+        node(name = "b", action = action(tool = builtin_tool(name = "true"))),
+    ],
+)
+"#,
+        );
+        assert_eq!(findings, vec![]);
+    }
+
+    #[test]
+    fn test_findings() {
+        let findings = lint(
+            r#"
+v = variable(default = "a")
+def _next(ctx, args):
+    return "c"
+
+main = workflow(
+    entrypoint = "a",
+    requires = ["__workflow_missing__"],
+    graph = [
+        node(
+            name = "a",
+            action = action(tool = tool(path = "missing.sh")),
+            next = next(implementation = _next)(),
+        ),
+        node(name = "b", action = action(tool = builtin_tool(name = "true"))),
+    ],
+)
+empty = workflow(graph = [])
+"#,
+        );
+        assert_eq!(
+            rules(&findings),
+            [
+                ("unused-variable", Some(2)),
+                ("stringly-next", Some(4)),
+                ("missing-tool", Some(8)),
+                ("missing-tool", Some(11)),
+                ("unreachable-node", Some(15)),
+                ("empty-graph", Some(18)),
+            ]
+        );
+        assert_eq!(findings[0].message, "'v' is never used");
+        assert_eq!(
+            findings[1].message,
+            "'_next' returns 'c' which is not a node in 'main'"
+        );
+        assert_eq!(
+            findings[4].message,
+            "node 'b' in 'main' can not be reached from 'a'"
+        );
+        assert_eq!(findings[5].severity, Severity::Error);
+    }
+
+    #[test]
+    fn test_unknown_entrypoint() {
+        let findings = lint(
+            r#"
+a = node(name = "a", action = action(tool = builtin_tool(name = "true")))
+b = node(name = "b", action = action(tool = builtin_tool(name = "true")))
+main = workflow(entrypoint = "build", graph = [a, b])
+other = workflow(graph = [a, b])
+single = workflow(entrypoint = "a", graph = [a])
+"#,
+        );
+        let findings: Vec<&Finding> = findings
+            .iter()
+            .filter(|f| f.rule == "unknown-entrypoint")
+            .collect();
+        assert_eq!(findings.len(), 2);
+        assert_eq!(findings[0].line, Some(4));
+        assert_eq!(
+            findings[0].message,
+            "the entrypoint 'build' of 'main' is not a node in its graph"
+        );
+        assert_eq!(findings[1].line, Some(5));
+        assert_eq!(findings[1].message, "'other' has no entrypoint");
+    }
+
+    #[test]
+    fn test_missing_builtin_tool_suggests_alternatives() {
+        let findings = lint(
+            r#"
+main = workflow(
+    entrypoint = "a",
+    graph = [node(name = "a", action = action(tool = builtin_tool(name = "catt")))],
+)
+other = workflow(
+    entrypoint = "b",
+    graph = [
+        node(
+            name = "b",
+            action = action(tool = builtin_tool(name = "__missing__", aliases = ["true"])),
+        ),
+    ],
+)
+"#,
+        );
+        assert_eq!(rules(&findings), [("missing-tool", Some(4))]);
+        assert!(
+            findings[0].message.starts_with(
+                "the tool of node 'a' can not be found: 'catt' is not on the PATH, did you mean "
+            ),
+            "{}",
+            findings[0].message
+        );
+        assert!(findings[0].message.contains("'cat'"));
+    }
+
+    #[test]
+    fn test_deprecated_variable() {
+        let findings = lint(
+            r#"
+old = variable(default = "a", deprecated = "use new instead")
+unread = variable(default = "a", deprecated = "use new instead")
+new = variable(default = "a")
+main = workflow(
+    entrypoint = "a",
+    graph = [
+        node(
+            name = "a",
+            action = action(tool = builtin_tool(name = "echo"), args = [old, new]),
+        ),
+    ],
+)
+"#,
+        );
+        let findings: Vec<&Finding> = findings
+            .iter()
+            .filter(|f| f.rule == "deprecated-variable")
+            .collect();
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].line, Some(10));
+        assert_eq!(findings[0].message, "'old' is deprecated: use new instead");
+        assert_eq!(findings[0].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn test_computed_next_is_not_unreachable() {
+        let findings = lint(
+            r#"
+def _next(ctx, args):
+    return ctx.stdout.strip()
+
+main = workflow(
+    entrypoint = "a",
+    graph = [
+        node(
+            name = "a",
+            action = action(tool = builtin_tool(name = "true")),
+            next = next(implementation = _next)(),
+        ),
+        node(name = "b", action = action(tool = builtin_tool(name = "true"))),
+    ],
+)
+"#,
+        );
+        assert_eq!(findings, vec![]);
+    }
+
+    #[test]
+    fn test_fixes() {
+        let content = r#"
+only = workflow(
+    graph = [
+        node(
+            action = action(tool = builtin_tool(name = "true")),
+        ),
+    ],
+)
+named = workflow(graph=[node(name="a", action=action(tool=builtin_tool(name="true")))])
+"#;
+        let findings = lint(content);
+        assert_eq!(
+            rules(&findings),
+            [
+                ("implicit-entrypoint", Some(2)),
+                ("unnamed-node", Some(4)),
+                ("implicit-entrypoint", Some(9)),
+            ]
+        );
+        // the entrypoint of an unnamed node is only fixed once it is named
+        assert_eq!(findings[0].fix, None);
+        let fixes: Vec<&Fix> = findings.iter().filter_map(|f| f.fix.as_ref()).collect();
+        assert_eq!(fixes[0].description, "add name = \"only\"");
+        assert_eq!(
+            apply_fixes(content, &fixes),
+            r#"
+only = workflow(
+    graph = [
+        node(
+            name = "only",
+            action = action(tool = builtin_tool(name = "true")),
+        ),
+    ],
+)
+named = workflow(entrypoint="a", graph=[node(name="a", action=action(tool=builtin_tool(name="true")))])
+"#
+        );
+    }
+
+    #[test]
+    fn test_setter_returns() {
+        let findings = lint(
+            r#"
+v = variable(default = "a")
+
+def _count(ctx):
+    return 1
+
+def _version(ctx):
+    if ctx.exit_code != 0:
+        return None
+    return ctx.stdout.strip()
+
+main = workflow(
+    entrypoint = "a",
+    graph = [
+        node(
+            name = "a",
+            action = action(
+                tool = builtin_tool(name = "true"),
+                setters = [
+                    setter(implementation = _count, variable = v),
+                    setter(implementation = _version, variable = v, returns = "string"),
+                    setter(implementation = _version, variable = v, returns = "string | None"),
+                ],
+            ),
+        ),
+    ],
+)
+"#,
+        );
+        assert_eq!(
+            rules(&findings),
+            [("setter-returns", Some(5)), ("setter-returns", Some(9))]
+        );
+        assert_eq!(
+            findings[0].message,
+            "setter '_count' of node 'a' returns int, a setter must return string or None"
+        );
+        assert_eq!(
+            findings[1].message,
+            "setter '_version' of node 'a' returns None but declares it returns string"
+        );
+    }
+
+    #[test]
+    fn test_allowed_rules_are_not_checked() {
+        let file = TempWorkflowFile::new("test.workflow", "main = workflow(graph = [])").unwrap();
+        let mut config = LintConfig::default();
+        config.set("empty-graph", Severity::Allow).unwrap();
+        assert_eq!(lint_file(&file.path(), vec![], &config).unwrap(), vec![]);
+    }
+}
@@ -0,0 +1,565 @@
+/// The tokens of a workflow file, enough of them to find where names are
+/// used and what functions return without the starlark AST, which is not
+/// part of the starlark crate's public api.
+#[derive(Debug)]
+pub(crate) struct Source {
+    tokens: Vec<Token>,
+    comments: Vec<Token>,
+}
+
+/// Where a token starts, captured before it is consumed.
+#[derive(Debug, Clone, Copy)]
+struct Mark {
+    pos: usize,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum TokenKind {
+    Ident,
+    /// A string literal, the text is its contents.
+    Str,
+    /// A comment, the text includes the `#`.
+    Comment,
+    Other,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Token {
+    pub(crate) kind: TokenKind,
+    pub(crate) text: String,
+    /// 1-based
+    pub(crate) line: usize,
+    pub(crate) column: usize,
+    /// The byte offsets of the token in the file.
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+    /// Whether the token starts a logical line, lines continued inside
+    /// brackets are not logical lines.
+    pub(crate) line_start: bool,
+    /// The indentation of the logical line the token is on.
+    pub(crate) indent: usize,
+}
+
+/// What a `return` statement returns.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Return {
+    /// A string literal and the line it is on.
+    Literal(String, usize),
+    /// None, which is also what a bare `return` returns, and the line it is
+    /// on.
+    None(usize),
+    /// A literal which is not a string, its type and the line it is on.
+    Value(&'static str, usize),
+    /// Anything else, which can only be known by running the function.
+    Computed(usize),
+}
+
+/// A call of a function by name, e.g. `node(name = "a", ...)`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Call {
+    // the index of the open paren
+    open: usize,
+    pub(crate) line: usize,
+    /// The names of the keyword args.
+    pub(crate) keywords: Vec<String>,
+}
+
+const OPERATORS: [&str; 12] = [
+    "==", "!=", "<=", ">=", "+=", "-=", "*=", "/=", "%=", "**", "//", "->",
+];
+
+struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    column: usize,
+    // open brackets, a newline inside brackets does not end the line
+    depth: usize,
+    line_start: bool,
+    indent: usize,
+    // the byte offset of each char, and of the end of the text
+    offsets: Vec<usize>,
+    tokens: Vec<Token>,
+    comments: Vec<Token>,
+}
+
+impl Lexer {
+    fn mark(&self) -> Mark {
+        Mark {
+            pos: self.pos,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    /// Adds a token which started at the mark and ends at the current
+    /// position.
+    fn push(&mut self, kind: TokenKind, text: String, mark: Mark) {
+        if self.line_start {
+            self.indent = mark.column;
+        }
+        self.tokens.push(Token {
+            kind,
+            text,
+            line: mark.line,
+            column: mark.column,
+            start: self.offsets[mark.pos],
+            end: self.offsets[self.pos],
+            line_start: self.line_start,
+            indent: self.indent,
+        });
+        self.line_start = false;
+    }
+
+    /// Adds a comment, which is kept apart from the tokens so it does not
+    /// start a logical line.
+    fn push_comment(&mut self, text: String, mark: Mark) {
+        self.comments.push(Token {
+            kind: TokenKind::Comment,
+            text,
+            line: mark.line,
+            column: mark.column,
+            start: self.offsets[mark.pos],
+            end: self.offsets[self.pos],
+            line_start: false,
+            indent: mark.column,
+        });
+    }
+
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    /// Advances past `count` chars, which must not contain a newline.
+    fn skip(&mut self, count: usize) {
+        self.pos += count;
+        self.column += count;
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> String {
+        let start = self.pos;
+        while self.peek(0).is_some_and(&f) {
+            self.pos += 1;
+        }
+        self.column += self.pos - start;
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    /// Returns the length of the prefix of a string literal starting at the
+    /// current position, e.g. 1 for `r"..."`, None if there is none.
+    fn string_prefix(&self) -> Option<usize> {
+        for len in 0..=2 {
+            match self.peek(len) {
+                Some('"') | Some('\'') => return Some(len),
+                Some('r') | Some('b') | Some('R') | Some('B') => continue,
+                _ => return None,
+            }
+        }
+        None
+    }
+
+    fn string(&mut self, prefix: usize) {
+        let mark = self.mark();
+        self.skip(prefix);
+        let quote = self.peek(0).unwrap();
+        let triple = self.peek(1) == Some(quote) && self.peek(2) == Some(quote);
+        self.skip(if triple { 3 } else { 1 });
+        let mut text = String::new();
+        while let Some(c) = self.peek(0) {
+            if triple && c == quote && self.peek(1) == Some(quote) && self.peek(2) == Some(quote) {
+                self.skip(3);
+                break;
+            } else if !triple && (c == quote || c == '\n') {
+                self.skip(1);
+                break;
+            }
+            if c == '\\' {
+                if let Some(escaped) = self.peek(1) {
+                    text.push(c);
+                    text.push(escaped);
+                    self.pos += 2;
+                    if escaped == '\n' {
+                        self.line += 1;
+                    }
+                    continue;
+                }
+            }
+            text.push(c);
+            self.pos += 1;
+            if c == '\n' {
+                self.line += 1;
+                self.column = 0;
+            }
+        }
+        self.push(TokenKind::Str, text, mark);
+    }
+
+    fn run(mut self) -> (Vec<Token>, Vec<Token>) {
+        while let Some(c) = self.peek(0) {
+            if c == '\n' {
+                self.pos += 1;
+                self.line += 1;
+                self.column = 0;
+                self.line_start |= self.depth == 0;
+            } else if c == '#' {
+                let mark = self.mark();
+                let text = self.take_while(|c| c != '\n');
+                self.push_comment(text.trim_end().to_string(), mark);
+            } else if c == '\\' && self.peek(1) == Some('\n') {
+                self.pos += 2;
+                self.line += 1;
+                self.column = 0;
+            } else if c.is_whitespace() {
+                self.skip(1);
+            } else if let Some(prefix) = self.string_prefix() {
+                self.string(prefix);
+            } else if c.is_alphabetic() || c == '_' {
+                let mark = self.mark();
+                let text = self.take_while(|c| c.is_alphanumeric() || c == '_');
+                self.push(TokenKind::Ident, text, mark);
+            } else if c.is_ascii_digit() {
+                let mark = self.mark();
+                let mut text = self.take_while(|c| c.is_alphanumeric() || c == '.');
+                // the sign of an exponent, e.g. 1e-5
+                if text.ends_with(['e', 'E']) && !text.starts_with("0x") {
+                    if let Some(sign @ ('+' | '-')) = self.peek(0) {
+                        self.skip(1);
+                        text.push(sign);
+                        text.push_str(&self.take_while(|c| c.is_ascii_digit()));
+                    }
+                }
+                self.push(TokenKind::Other, text, mark);
+            } else {
+                let pair: String = [Some(c), self.peek(1)].iter().flatten().collect();
+                let text = match OPERATORS.contains(&pair.as_str()) {
+                    true => pair,
+                    false => c.to_string(),
+                };
+                match c {
+                    '(' | '[' | '{' => self.depth += 1,
+                    ')' | ']' | '}' => self.depth = self.depth.saturating_sub(1),
+                    _ => {}
+                }
+                let mark = self.mark();
+                self.skip(text.chars().count());
+                self.push(TokenKind::Other, text, mark);
+            }
+        }
+        (self.tokens, self.comments)
+    }
+}
+
+impl Source {
+    pub(crate) fn new(text: &str) -> Self {
+        let lexer = Lexer {
+            chars: text.chars().collect(),
+            offsets: text
+                .char_indices()
+                .map(|(i, _)| i)
+                .chain([text.len()])
+                .collect(),
+            pos: 0,
+            line: 1,
+            column: 0,
+            depth: 0,
+            line_start: true,
+            indent: 0,
+            tokens: vec![],
+            comments: vec![],
+        };
+        let (tokens, comments) = lexer.run();
+        Source { tokens, comments }
+    }
+
+    pub(crate) fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    pub(crate) fn comments(&self) -> &[Token] {
+        &self.comments
+    }
+
+    fn is(&self, index: usize, kind: TokenKind, text: &str) -> bool {
+        self.tokens
+            .get(index)
+            .is_some_and(|t| t.kind == kind && t.text == text)
+    }
+
+    /// Returns the number of times the name is read. Assignments to it,
+    /// keyword args and attributes with the same name are not reads.
+    pub(crate) fn reads(&self, name: &str) -> usize {
+        self.read_lines(name).len()
+    }
+
+    /// Returns the line of each read of the name, see `reads`.
+    pub(crate) fn read_lines(&self, name: &str) -> Vec<usize> {
+        (0..self.tokens.len())
+            .filter(|i| self.is(*i, TokenKind::Ident, name))
+            .filter(|i| !self.is(i + 1, TokenKind::Other, "="))
+            .filter(|i| *i == 0 || !self.is(i - 1, TokenKind::Other, "."))
+            .map(|i| self.tokens[i].line)
+            .collect()
+    }
+
+    /// Returns the line the name is first assigned on.
+    pub(crate) fn assignment_line(&self, name: &str) -> Option<usize> {
+        (0..self.tokens.len())
+            .find(|i| {
+                self.tokens[*i].line_start
+                    && self.is(*i, TokenKind::Ident, name)
+                    && self.is(i + 1, TokenKind::Other, "=")
+            })
+            .map(|i| self.tokens[i].line)
+    }
+
+    /// Returns the line of the first `attr = "value"`, e.g. to find where
+    /// a node with a name is declared.
+    pub(crate) fn attr_line(&self, attr: &str, value: &str) -> Option<usize> {
+        (0..self.tokens.len())
+            .find(|i| {
+                self.is(*i, TokenKind::Ident, attr)
+                    && self.is(i + 1, TokenKind::Other, "=")
+                    && self.is(i + 2, TokenKind::Str, value)
+            })
+            .map(|i| self.tokens[i + 2].line)
+    }
+
+    /// Returns the line of the first string literal with the value.
+    pub(crate) fn string_line(&self, value: &str) -> Option<usize> {
+        self.tokens
+            .iter()
+            .find(|t| t.kind == TokenKind::Str && t.text == value)
+            .map(|t| t.line)
+    }
+
+    /// Returns the calls of the function, which are not method calls.
+    pub(crate) fn calls(&self, function: &str) -> Vec<Call> {
+        let mut calls = vec![];
+        for i in 0..self.tokens.len() {
+            if !self.is(i, TokenKind::Ident, function)
+                || !self.is(i + 1, TokenKind::Other, "(")
+                || (i > 0 && self.is(i - 1, TokenKind::Other, "."))
+            {
+                continue;
+            }
+            let mut keywords = vec![];
+            let mut depth = 0;
+            for j in i + 1..self.tokens.len() {
+                match self.tokens[j].text.as_str() {
+                    "(" | "[" | "{" => depth += 1,
+                    ")" | "]" | "}" => depth -= 1,
+                    _ => {}
+                }
+                if depth == 0 {
+                    break;
+                }
+                let starts_arg =
+                    self.is(j - 1, TokenKind::Other, "(") || self.is(j - 1, TokenKind::Other, ",");
+                if depth == 1 && starts_arg && self.is(j + 1, TokenKind::Other, "=") {
+                    keywords.push(self.tokens[j].text.clone());
+                }
+            }
+            calls.push(Call {
+                open: i + 1,
+                line: self.tokens[i].line,
+                keywords,
+            });
+        }
+        calls
+    }
+
+    /// Returns the name the statement the call is in assigns to, e.g. `a`
+    /// for both calls in `a = workflow(graph = [node()])`.
+    pub(crate) fn binding(&self, call: &Call) -> Option<String> {
+        let start = (0..=call.open).rev().find(|i| self.tokens[*i].line_start)?;
+        match self.is(start + 1, TokenKind::Other, "=") {
+            true if self.tokens[start].kind == TokenKind::Ident => {
+                Some(self.tokens[start].text.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the byte offset and text which add `name = value` as the
+    /// first arg of the call, laid out like the args which are there.
+    pub(crate) fn insert_keyword(&self, call: &Call, name: &str, value: &str) -> (usize, String) {
+        let open = &self.tokens[call.open];
+        let spaced = !(call.open..self.tokens.len())
+            .take_while(|i| !self.is(*i, TokenKind::Other, ")"))
+            .any(|i| {
+                self.is(i, TokenKind::Other, "=") && self.tokens[i].start == self.tokens[i - 1].end
+            });
+        let arg = match spaced {
+            true => format!("{} = {}", name, value),
+            false => format!("{}={}", name, value),
+        };
+        match self.tokens.get(call.open + 1) {
+            Some(first) if first.text == ")" => (open.end, arg),
+            Some(first) if first.line != open.line => (
+                first.start,
+                format!("{},\n{}", arg, " ".repeat(first.column)),
+            ),
+            Some(first) => (first.start, format!("{}, ", arg)),
+            None => (open.end, arg),
+        }
+    }
+
+    /// Returns what each `return` in the function with the name returns,
+    /// None if there is no such function.
+    pub(crate) fn returns(&self, function: &str) -> Option<Vec<Return>> {
+        let def = (0..self.tokens.len()).find(|i| {
+            self.tokens[*i].line_start
+                && self.is(*i, TokenKind::Ident, "def")
+                && self.is(i + 1, TokenKind::Ident, function)
+        })?;
+        let def_indent = self.tokens[def].indent;
+        let body_end = (def + 1..self.tokens.len())
+            .find(|i| self.tokens[*i].line_start && self.tokens[*i].indent <= def_indent)
+            .unwrap_or(self.tokens.len());
+        // a return ends where its logical line does
+        let ends_line = |i: usize| i >= body_end || self.tokens[i].line_start;
+
+        let mut returns = vec![];
+        for i in def + 2..body_end {
+            if !self.is(i, TokenKind::Ident, "return") {
+                continue;
+            }
+            let value = &self.tokens.get(i + 1);
+            let line = self.tokens[i].line;
+            returns.push(match value {
+                _ if ends_line(i + 1) => Return::None(line),
+                Some(t) if ends_line(i + 2) => match literal_type(t) {
+                    _ if t.kind == TokenKind::Str => Return::Literal(t.text.clone(), t.line),
+                    Some("None") => Return::None(line),
+                    Some(typ) => Return::Value(typ, line),
+                    None => Return::Computed(line),
+                },
+                _ => Return::Computed(line),
+            });
+        }
+        Some(returns)
+    }
+}
+
+/// Returns the type of a literal which is not a string, None unless the
+/// token is one.
+fn literal_type(token: &Token) -> Option<&'static str> {
+    match (token.kind, token.text.as_str()) {
+        (TokenKind::Ident, "None") => Some("None"),
+        (TokenKind::Ident, "True" | "False") => Some("bool"),
+        (TokenKind::Other, text) if text.starts_with(|c: char| c.is_ascii_digit()) => {
+            let hex = text.starts_with("0x") || text.starts_with("0X");
+            match text.contains('.') || (!hex && text.contains(['e', 'E'])) {
+                true => Some("float"),
+                false => Some("int"),
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r#"
+# a comment with name = "x"
+v = variable(default = "v")
+unused = variable(default = 'u')
+
+def _next(ctx, args):
+    if ctx.exit_code == 0:
+        return "pass"
+    elif ctx.v:
+        return None
+    return (
+        "fail"
+    )
+
+def _dynamic(ctx, args): return args.name
+
+main = workflow(
+    graph = [
+        node(name = "pass", action = action(tool = tool(path = v))),
+        node(name = """fail"""),
+    ],
+)
+
+def _count(args):
+    if args:
+        return 1.5
+    return 1
+"#;
+
+    #[test]
+    fn test_reads() {
+        let source = Source::new(SOURCE);
+        assert_eq!(source.reads("v"), 1);
+        assert_eq!(source.reads("unused"), 0);
+        assert_eq!(source.reads("name"), 0);
+        assert_eq!(source.reads("ctx"), 4);
+    }
+
+    #[test]
+    fn test_lines() {
+        let source = Source::new(SOURCE);
+        assert_eq!(source.assignment_line("unused"), Some(4));
+        assert_eq!(source.assignment_line("default"), None);
+        assert_eq!(source.attr_line("name", "fail"), Some(20));
+        assert_eq!(source.string_line("pass"), Some(8));
+        assert_eq!(source.string_line("x"), None);
+    }
+
+    #[test]
+    fn test_returns() {
+        let source = Source::new(SOURCE);
+        assert_eq!(
+            source.returns("_next").unwrap(),
+            [
+                Return::Literal("pass".to_string(), 8),
+                Return::None(10),
+                Return::Computed(11),
+            ]
+        );
+        assert_eq!(source.returns("_dynamic").unwrap(), [Return::Computed(15)]);
+        assert_eq!(
+            source.returns("_count").unwrap(),
+            [Return::Value("float", 26), Return::Value("int", 27)]
+        );
+        assert_eq!(source.returns("main"), None);
+    }
+
+    #[test]
+    fn test_calls() {
+        let source = Source::new(SOURCE);
+        let calls = source.calls("node");
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].line, 19);
+        assert_eq!(calls[0].keywords, ["name", "action"]);
+        assert_eq!(source.binding(&calls[0]), Some("main".to_string()));
+        assert_eq!(source.calls("tool")[0].keywords, ["path"]);
+        assert_eq!(
+            source.binding(&source.calls("variable")[1]),
+            Some("unused".to_string())
+        );
+    }
+
+    #[test]
+    fn test_insert_keyword() {
+        let insert = |text: &str| {
+            let source = Source::new(text);
+            let call = &source.calls("f")[0];
+            let (offset, arg) = source.insert_keyword(call, "name", "\"a\"");
+            format!("{}{}{}", &text[..offset], arg, &text[offset..])
+        };
+        assert_eq!(insert("f()"), "f(name = \"a\")");
+        assert_eq!(insert("f(x = 1)"), "f(name = \"a\", x = 1)");
+        assert_eq!(insert("f(x=1, y=[])"), "f(name=\"a\", x=1, y=[])");
+        assert_eq!(
+            insert("f(\n    x = 1,\n)"),
+            "f(\n    name = \"a\",\n    x = 1,\n)"
+        );
+    }
+}
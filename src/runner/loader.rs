@@ -0,0 +1,177 @@
+use crate::runner::Runner;
+use anyhow::{anyhow, bail};
+use starlark::environment::{FrozenModule, Module};
+use starlark::eval::{Evaluator, FileLoader};
+use starlark::syntax::{AstModule, Dialect};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Resolves the `path` of a `load()` statement in `loading_file`. Paths are
+/// relative to the loading file and must stay inside of `root`, the
+/// directory of the workflow.
+fn resolve_load_path(root: &Path, loading_file: &Path, path: &str) -> anyhow::Result<PathBuf> {
+    if Path::new(path).is_absolute() {
+        bail!(
+            "Unable to load '{}', paths must be relative to the file which loads them",
+            path
+        );
+    }
+    let dir = loading_file.parent().unwrap_or(root);
+    let file = fs::canonicalize(dir.join(path))
+        .map_err(|e| anyhow!("Unable to load '{}': {}", path, e))?;
+    if !file.starts_with(root) {
+        bail!(
+            "Unable to load '{}', it is outside of the workflow directory {:?}",
+            path,
+            root
+        );
+    }
+    Ok(file)
+}
+
+impl Runner {
+    /// Evaluates the file loaded by a `load()` statement of the file being
+    /// parsed. Each file is only evaluated once, so the variables and tools
+    /// it creates are shared by all of the files which load it.
+    fn load_module(&self, path: &str) -> anyhow::Result<FrozenModule> {
+        let root = self.working_dir();
+        let loading_file = match self.loading.borrow().last() {
+            Some(file) => file.clone(),
+            None => self.workflow_file.clone(),
+        };
+        let file = resolve_load_path(&root, &loading_file, path)?;
+        if let Some(module) = self.loaded.borrow().get(&file) {
+            return Ok(module.clone());
+        }
+        if file == self.workflow_file || self.loading.borrow().contains(&file) {
+            let mut cycle = vec![self.workflow_file.clone()];
+            cycle.extend(self.loading.borrow().iter().cloned());
+            cycle.push(file);
+            let cycle: Vec<String> = cycle
+                .iter()
+                .map(|f| f.strip_prefix(&root).unwrap_or(f).display().to_string())
+                .collect();
+            bail!("Cycle in load(): {}", cycle.join(" -> "));
+        }
+
+        let ast = AstModule::parse_file(&file, &Dialect::Standard).map_err(|e| e.into_anyhow())?;
+        let module = Module::new();
+        self.loading.borrow_mut().push(file.clone());
+        let result = {
+            let mut eval = Evaluator::new(&module);
+            eval.extra = Some(&self.delegate);
+            eval.set_loader(self);
+            eval.eval_module(ast, &self.globals)
+                .map(|_| ())
+                .map_err(|e| e.into_anyhow())
+        };
+        self.loading.borrow_mut().pop();
+        result?;
+
+        self.name_variables(&module);
+        let module = module.freeze()?;
+        self.loaded.borrow_mut().insert(file, module.clone());
+        Ok(module)
+    }
+}
+
+impl FileLoader for Runner {
+    fn load(&self, path: &str) -> anyhow::Result<FrozenModule> {
+        self.load_module(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downcast_delegate_ref;
+    use crate::runner::WorkflowDelegate;
+    use crate::stdlib::test_utils::TempWorkflowFile;
+    use std::ops::Deref;
+
+    fn write(dir: &Path, path: &str, content: &str) {
+        fs::create_dir_all(dir.join(path).parent().unwrap()).unwrap();
+        fs::write(dir.join(path), content).unwrap();
+    }
+
+    fn parse(file: &TempWorkflowFile) -> anyhow::Result<Vec<String>> {
+        let runner = Runner::new(file.path(), WorkflowDelegate::new())?;
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        runner.parse_workflow(&mut eval)?;
+        let holder = runner.delegate();
+        let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+        Ok(delegate.variable_store().settable_names())
+    }
+
+    #[test]
+    fn test_load_relative_to_loading_file() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+load("lib/tools.star", "make", "os")
+load("common.star", "version")
+
+main = workflow(
+    entrypoint = "build",
+    graph = [node(name = "build", action = action(tool = make, args = [os, version]))],
+)
+"#,
+        )
+        .unwrap();
+        let dir = file.path().parent().unwrap().to_path_buf();
+        write(
+            &dir,
+            "lib/tools.star",
+            "load('../common.star', 'version')\nos = variable(default = 'linux')\nmake = tool(path = 'make')\n",
+        );
+        write(&dir, "common.star", "version = variable(default = '1')\n");
+
+        // common.star is evaluated once, so there is a single version
+        assert_eq!(parse(&file).unwrap(), ["os", "version"]);
+    }
+
+    #[test]
+    fn test_invalid_loads() {
+        let file = TempWorkflowFile::new("test.workflow", "load('a.star', 'a')").unwrap();
+        let dir = file.path().parent().unwrap().to_path_buf();
+        write(&dir, "a.star", "load('b.star', 'b')\na = 1\n");
+        write(&dir, "b.star", "load('a.star', 'a')\nb = 1\n");
+        assert!(format!("{:#}", parse(&file).unwrap_err())
+            .contains("Cycle in load(): test.workflow -> a.star -> b.star -> a.star"));
+
+        write(&dir, "b.star", "load('test.workflow', 'main')\nb = 1\n");
+        assert!(format!("{:#}", parse(&file).unwrap_err())
+            .contains("Cycle in load(): test.workflow -> a.star -> b.star -> test.workflow"));
+
+        write(&dir, "a.star", "load('../outside.star', 'b')\na = 1\n");
+        assert!(format!("{:#}", parse(&file).unwrap_err())
+            .contains("Unable to load '../outside.star': No such file or directory"));
+
+        write(&dir, "a.star", "load('/etc/passwd', 'b')\na = 1\n");
+        assert!(format!("{:#}", parse(&file).unwrap_err()).contains(
+            "Unable to load '/etc/passwd', paths must be relative to the file which loads them"
+        ));
+    }
+
+    #[test]
+    fn test_resolve_load_path() {
+        let root = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(root.path()).unwrap();
+        write(&root, "workflow/lib.star", "");
+        write(&root, "other.star", "");
+        let workflow_dir = root.join("workflow");
+        let loading = workflow_dir.join("test.workflow");
+
+        assert_eq!(
+            resolve_load_path(&workflow_dir, &loading, "lib.star").unwrap(),
+            workflow_dir.join("lib.star")
+        );
+        assert!(resolve_load_path(&workflow_dir, &loading, "../other.star")
+            .unwrap_err()
+            .to_string()
+            .starts_with(
+                "Unable to load '../other.star', it is outside of the workflow directory"
+            ));
+    }
+}
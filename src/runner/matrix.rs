@@ -0,0 +1,181 @@
+use anyhow::bail;
+use std::fmt;
+
+/// The values each of a set of variables takes, the workflow is run once
+/// for every combination of them. An axis is named by the qualified name
+/// of the variable it sets, the one used with `--var`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Matrix {
+    axes: Vec<(String, Vec<String>)>,
+}
+
+/// One value for each axis of a matrix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Combination(Vec<(String, String)>);
+
+impl Matrix {
+    pub fn new() -> Self {
+        Matrix::default()
+    }
+
+    /// Parses an axis given as `<variable>=<value>,<value>,...`.
+    pub fn parse_axis(spec: &str) -> anyhow::Result<(String, Vec<String>)> {
+        match spec.split_once('=') {
+            Some((name, values)) if !name.is_empty() => Ok((
+                name.to_string(),
+                values.split(',').map(String::from).collect(),
+            )),
+            _ => bail!(
+                "Invalid matrix axis '{}', expected <variable>=<value>,...",
+                spec
+            ),
+        }
+    }
+
+    /// Adds an axis, replacing the one for the same variable if there is
+    /// one. Fails if the axis has no values or repeats one.
+    pub fn set_axis(&mut self, name: &str, values: Vec<String>) -> anyhow::Result<()> {
+        if values.is_empty() {
+            bail!("The matrix axis '{}' has no values", name);
+        }
+        for (index, value) in values.iter().enumerate() {
+            if values[..index].contains(value) {
+                bail!("The matrix axis '{}' has the value '{}' twice", name, value);
+            }
+        }
+        match self.axes.iter_mut().find(|(n, _)| n == name) {
+            Some(axis) => axis.1 = values,
+            None => self.axes.push((name.to_string(), values)),
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.axes.is_empty()
+    }
+
+    /// Fails if an axis does not name one of the variables.
+    pub fn check_variables(&self, settable: &[String]) -> anyhow::Result<()> {
+        for (name, _) in &self.axes {
+            if !settable.contains(name) {
+                bail!(
+                    "The matrix axis '{}' is not a variable which can be set with --var",
+                    name
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns every combination of the values, the values of the first
+    /// axis change slowest.
+    pub fn combinations(&self) -> Vec<Combination> {
+        let mut combinations = vec![Combination(vec![])];
+        for (name, values) in &self.axes {
+            combinations = combinations
+                .into_iter()
+                .flat_map(|c| {
+                    values.iter().map(move |value| {
+                        let mut pairs = c.0.clone();
+                        pairs.push((name.clone(), value.clone()));
+                        Combination(pairs)
+                    })
+                })
+                .collect();
+        }
+        combinations
+    }
+}
+
+impl Combination {
+    /// Returns the `--var` args which set the variables of the combination.
+    pub fn var_args(&self) -> Vec<String> {
+        self.0
+            .iter()
+            .flat_map(|(name, value)| ["--var".to_string(), format!("{}={}", name, value)])
+            .collect()
+    }
+}
+
+impl fmt::Display for Combination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pairs: Vec<String> = self
+            .0
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        write!(f, "{}", pairs.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_combinations() {
+        let mut matrix = Matrix::new();
+        assert_eq!(matrix.combinations(), [Combination(vec![])]);
+
+        let (name, values) = Matrix::parse_axis("os=linux,macos").unwrap();
+        matrix.set_axis(&name, values).unwrap();
+        matrix
+            .set_axis("arch", vec!["x86".to_string(), "arm".to_string()])
+            .unwrap();
+        let combinations: Vec<String> = matrix
+            .combinations()
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        assert_eq!(
+            combinations,
+            [
+                "os=linux arch=x86",
+                "os=linux arch=arm",
+                "os=macos arch=x86",
+                "os=macos arch=arm"
+            ]
+        );
+        assert_eq!(
+            matrix.combinations()[1].var_args(),
+            ["--var", "os=linux", "--var", "arch=arm"]
+        );
+
+        // a later axis for the same variable replaces it
+        matrix.set_axis("os", vec!["bsd".to_string()]).unwrap();
+        assert_eq!(matrix.combinations().len(), 2);
+    }
+
+    #[test]
+    fn test_invalid_axes() {
+        let error = |spec: &str| {
+            Matrix::parse_axis(spec)
+                .and_then(|(name, values)| Matrix::new().set_axis(&name, values))
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("os"),
+            "Invalid matrix axis 'os', expected <variable>=<value>,..."
+        );
+        assert_eq!(
+            error("os=linux,linux"),
+            "The matrix axis 'os' has the value 'linux' twice"
+        );
+        assert_eq!(
+            Matrix::new()
+                .set_axis("os", vec![])
+                .unwrap_err()
+                .to_string(),
+            "The matrix axis 'os' has no values"
+        );
+
+        let mut matrix = Matrix::new();
+        matrix.set_axis("os", vec!["linux".to_string()]).unwrap();
+        assert!(matrix.check_variables(&["os".to_string()]).is_ok());
+        assert_eq!(
+            matrix.check_variables(&[]).unwrap_err().to_string(),
+            "The matrix axis 'os' is not a variable which can be set with --var"
+        );
+    }
+}
@@ -1,50 +1,434 @@
+mod agent;
+mod approval;
+mod arguments;
+mod builder;
+mod events;
+mod format;
+mod graph;
+mod history;
+pub mod lint;
+mod loader;
+mod matrix;
+mod node_cache;
+#[cfg(feature = "plugins")]
+mod plugin;
+mod prompt;
+mod sandbox;
+mod schedule;
+mod server;
+mod stats;
+mod syntax;
+mod variable_source;
 mod variable_store;
 mod workflow_delegate;
 
+pub use self::agent::Agent;
+pub use self::approval::PromptApprover;
+pub use self::arguments::{arguments_help, WorkflowArgument};
+pub use self::builder::{ActionSpec, EnvPolicySpec, SetterSpec, VariableSpec, WorkflowBuilder};
+pub use self::events::{EventBus, EventHandler, RunEvent};
+pub use self::format::format_source;
+pub use self::graph::{
+    declared_graph, declared_graph_dot, declared_graph_mermaid, run_graph_dot, run_graph_mermaid,
+    DeclaredNext, DeclaredNode,
+};
+pub use self::history::{state_dir, History, HistoryRecord, NodeRecord};
+pub use self::matrix::{Combination, Matrix};
+pub use self::node_cache::NodeCache;
+#[cfg(feature = "plugins")]
+pub use self::plugin::{register_plugin, registered_plugins, Plugin};
+pub use self::prompt::{Prompter, TerminalPrompter};
+pub use self::sandbox::Sandbox;
+pub use self::schedule::{Schedule, UtcTime};
+pub use self::server::{Policy, Server, Webhook};
+pub use self::stats::{node_stats, NodeStats};
+pub use self::syntax::{syntax_errors, SyntaxError};
+pub use self::variable_source::{SourcePosition, VariableSource, VariableSources};
 pub use self::variable_store::VariableStore;
 pub use self::workflow_delegate::WorkflowDelegate;
 
+use crate::downcast_delegate_ref;
 use crate::stdlib::arg_spec::arg_spec;
-use crate::stdlib::{starlark_stdlib, ParseDelegate, ParseDelegateHolder};
-use starlark::environment::{Globals, GlobalsBuilder, LibraryExtension};
+use crate::stdlib::ir::WorkflowIr;
+use crate::stdlib::plan::NodePlan;
+use crate::stdlib::variable_resolver::VariableSnapshot;
+use crate::stdlib::{
+    starlark_stdlib, ParseDelegate, ParseDelegateHolder, RunResult, TagFilter, VariableRef,
+    Workflow,
+};
+use anyhow::bail;
+use starlark::environment::{FrozenModule, Globals, GlobalsBuilder, LibraryExtension, Module};
 use starlark::eval::Evaluator;
 use starlark::syntax::AstModule;
 use starlark::syntax::Dialect;
 use starlark::values::Value;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::fs;
 use std::ops::Deref;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+/// Adds the builtins of all registered plugins.
+#[cfg(feature = "plugins")]
+fn plugin_globals(builder: &mut GlobalsBuilder) {
+    for plugin in registered_plugins() {
+        plugin.register_globals(builder);
+    }
+}
+
+#[cfg(not(feature = "plugins"))]
+fn plugin_globals(_builder: &mut GlobalsBuilder) {}
+
+/// Returns the builtins every workflow can use.
+fn workflow_globals() -> GlobalsBuilder {
+    GlobalsBuilder::extended_by(&[LibraryExtension::Json])
+        .with(starlark_stdlib)
+        .with(arg_spec)
+        .with(plugin_globals)
+}
+
+/// The lifecycle of a Runner.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunnerState {
+    Created,
+    Parsing,
+    Parsed,
+    Running,
+    /// The workflow has run, or parsing or setting up the run failed.
+    Finished,
+}
+
+/// Parses and runs a single workflow file.
+///
+/// A Runner is single use: the workflow can be parsed once and run once
+/// after it has been parsed. Calling the methods out of order, or again,
+/// returns an error. Create a new Runner to run the workflow again.
 pub struct Runner {
     pub globals: Globals,
     delegate: ParseDelegateHolder,
     workflow_file: PathBuf,
+    state: Cell<RunnerState>,
+    profile_memory: Cell<bool>,
+    // variables to restore before the workflow runs, when resuming a run
+    checkpoint: RefCell<Option<VariableSnapshot>>,
+    // picks the nodes which run by their tags
+    tag_filter: RefCell<TagFilter>,
+    // if set the workflow runs in a sandbox rather than next to its file
+    sandboxed: Cell<bool>,
+    // the files whose load() statements are being evaluated, innermost last
+    loading: RefCell<Vec<PathBuf>>,
+    // the modules loaded so far, by their canonical path
+    loaded: RefCell<HashMap<PathBuf, FrozenModule>>,
+    // the source of the workflow if it was not read from workflow_file
+    source: Option<String>,
 }
 
 impl Runner {
     pub fn new<T: ParseDelegate + std::fmt::Debug>(
         workflow_file: PathBuf,
         delegate: T,
+    ) -> anyhow::Result<Self> {
+        Runner::new_with_globals(workflow_file, delegate, &[])
+    }
+
+    /// Creates a runner whose workflow can also use the builtins added by
+    /// `extra_globals`, e.g. the dev_stdlib in the repl.
+    pub fn new_with_globals<T: ParseDelegate + std::fmt::Debug>(
+        workflow_file: PathBuf,
+        delegate: T,
+        extra_globals: &[fn(&mut GlobalsBuilder)],
     ) -> anyhow::Result<Self> {
         /*
         TODO: Look at https://github.com/facebook/starlark-rust/blob/9efb6cab8bf609b500c9669eabd1bd7944feaa3d/starlark/src/stdlib/funcs/globals.rs#L33C1-L33C63
         for a better way of doing this.
         */
-        let globals = GlobalsBuilder::extended_by(&[LibraryExtension::Json])
-            .with(starlark_stdlib)
-            .with(arg_spec)
-            .build();
+        let mut builder = workflow_globals();
+        for extra in extra_globals {
+            builder = builder.with(*extra);
+        }
 
         Ok(Runner {
-            globals,
+            globals: builder.build(),
             delegate: ParseDelegateHolder::new(delegate),
             workflow_file: fs::canonicalize(workflow_file)?,
+            state: Cell::new(RunnerState::Created),
+            profile_memory: Cell::new(false),
+            checkpoint: RefCell::new(None),
+            tag_filter: RefCell::new(TagFilter::default()),
+            sandboxed: Cell::new(false),
+            loading: RefCell::new(vec![]),
+            loaded: RefCell::new(HashMap::new()),
+            source: None,
         })
     }
 
+    /// Creates a runner for a workflow given as its source, e.g. one built
+    /// with a WorkflowBuilder, which runs as if it was a workflow file in
+    /// `working_dir`.
+    pub fn from_source<T: ParseDelegate + std::fmt::Debug>(
+        working_dir: &Path,
+        source: String,
+        delegate: T,
+    ) -> anyhow::Result<Self> {
+        let mut runner = Runner::new(working_dir.to_path_buf(), delegate)?;
+        runner.workflow_file.push("generated.workflow");
+        runner.source = Some(source);
+        Ok(runner)
+    }
+
+    /// Creates a runner for a compiled workflow, which runs as if it was a
+    /// workflow file in `working_dir`.
+    pub fn from_ir<T: ParseDelegate + std::fmt::Debug>(
+        working_dir: &Path,
+        ir: &WorkflowIr,
+        delegate: T,
+    ) -> anyhow::Result<Self> {
+        WorkflowBuilder::from(ir).runner(working_dir, delegate)
+    }
+
+    /// Parses the workflow and runs its `main` workflow starting at the node
+    /// named `start_at` if given. The module and evaluator are created and
+    /// dropped here so callers do not need to manage their lifetimes.
+    ///
+    /// Running requires the delegate to be a WorkflowDelegate.
+    pub fn run(&self, start_at: Option<&str>) -> anyhow::Result<RunResult> {
+        let module: Module = Module::new();
+        let mut eval: Evaluator = Evaluator::new(&module);
+        self.parse_workflow(&mut eval)?;
+
+        // TOOD: add run_workflow function instead of looking for main
+        let workflow = match module.get("main") {
+            Some(main) => match Workflow::from_value(main) {
+                Some(workflow) => workflow,
+                None => {
+                    self.state.set(RunnerState::Finished);
+                    bail!("main must be a workflow")
+                }
+            },
+            None => {
+                self.state.set(RunnerState::Finished);
+                return Ok(RunResult::default());
+            }
+        };
+
+        #[cfg(feature = "plugins")]
+        for plugin in registered_plugins() {
+            plugin.will_run_workflow(&self.workflow_file);
+        }
+
+        let result = self.run_workflow(workflow, start_at, &mut eval)?;
+
+        #[cfg(feature = "plugins")]
+        for plugin in registered_plugins() {
+            plugin.did_run_workflow(&self.workflow_file, &result);
+        }
+        Ok(result)
+    }
+
+    /// Parses the workflow and returns what running its `main` workflow
+    /// starting at the node named `start_at` would do, without running
+    /// anything.
+    ///
+    /// Planning requires the delegate to be a WorkflowDelegate.
+    pub fn plan(&self, start_at: Option<&str>) -> anyhow::Result<Vec<NodePlan>> {
+        let module: Module = Module::new();
+        let mut eval: Evaluator = Evaluator::new(&module);
+        self.parse_workflow(&mut eval)?;
+        self.state.set(RunnerState::Finished);
+
+        let workflow = match module.get("main") {
+            Some(main) => match Workflow::from_value(main) {
+                Some(workflow) => workflow,
+                None => bail!("main must be a workflow"),
+            },
+            None => return Ok(vec![]),
+        };
+
+        let holder = self.delegate();
+        let delegate = match downcast_delegate_ref!(holder, WorkflowDelegate) {
+            Some(delegate) => delegate,
+            None => bail!("Planning a workflow requires a WorkflowDelegate"),
+        };
+        if let Some(checkpoint) = self.checkpoint.take() {
+            delegate.variable_store().restore(&checkpoint);
+        }
+        workflow.plan(
+            start_at,
+            delegate,
+            &self.working_dir(),
+            &self.tag_filter.borrow(),
+        )
+    }
+
+    /// Parses the workflow and compiles its `main` workflow to the IR, which
+    /// can be run without the source of the workflow.
+    ///
+    /// Compiling requires the delegate to be a WorkflowDelegate.
+    pub fn compile(&self) -> anyhow::Result<WorkflowIr> {
+        let module: Module = Module::new();
+        let mut eval: Evaluator = Evaluator::new(&module);
+        self.parse_workflow(&mut eval)?;
+        self.state.set(RunnerState::Finished);
+
+        let workflow = match module.get("main") {
+            Some(main) => match Workflow::from_value(main) {
+                Some(workflow) => workflow,
+                None => bail!("main must be a workflow"),
+            },
+            None => bail!("the workflow has no main"),
+        };
+
+        let holder = self.delegate();
+        let delegate = match downcast_delegate_ref!(holder, WorkflowDelegate) {
+            Some(delegate) => delegate,
+            None => bail!("Compiling a workflow requires a WorkflowDelegate"),
+        };
+        workflow.compile(delegate.variable_store().compile_variables()?, delegate)
+    }
+
+    /// Parses the workflow and returns the matrix it declares, failing if
+    /// an axis of it or of `axes`, which are added to it, is not a variable
+    /// which can be set.
+    pub fn matrix(&self, axes: &[(String, Vec<String>)]) -> anyhow::Result<Matrix> {
+        let module: Module = Module::new();
+        let mut eval: Evaluator = Evaluator::new(&module);
+        self.parse_workflow(&mut eval)?;
+        self.state.set(RunnerState::Finished);
+
+        let mut matrix = Matrix::new();
+        if let Some(main) = module.get("main") {
+            let workflow = match Workflow::from_value(main) {
+                Some(workflow) => workflow,
+                None => bail!("main must be a workflow"),
+            };
+            for (name, values) in workflow.matrix() {
+                matrix.set_axis(name, values.clone())?;
+            }
+        }
+        for (name, values) in axes {
+            matrix.set_axis(name, values.clone())?;
+        }
+
+        let holder = self.delegate();
+        match downcast_delegate_ref!(holder, WorkflowDelegate) {
+            Some(delegate) => {
+                matrix.check_variables(&delegate.variable_store().settable_names())?
+            }
+            None => bail!("A matrix run requires a WorkflowDelegate"),
+        }
+        Ok(matrix)
+    }
+
+    /// Runs a workflow which was created by parsing this runner's workflow file.
+    pub fn run_workflow<'a>(
+        &self,
+        workflow: &Workflow<'a>,
+        start_at: Option<&str>,
+        eval: &mut Evaluator<'a, '_>,
+    ) -> anyhow::Result<RunResult> {
+        match self.state.get() {
+            RunnerState::Parsed => (),
+            RunnerState::Created | RunnerState::Parsing => {
+                bail!("The workflow must be parsed before it can be run")
+            }
+            RunnerState::Running => bail!("The workflow is already running"),
+            RunnerState::Finished => {
+                bail!("The workflow has already run, create a new Runner to run it again")
+            }
+        }
+        // a run which fails before its nodes start has still used the runner
+        self.state.set(RunnerState::Finished);
+
+        let holder = self.delegate();
+        let delegate = match downcast_delegate_ref!(holder, WorkflowDelegate) {
+            Some(delegate) => delegate,
+            None => bail!("Running a workflow requires a WorkflowDelegate"),
+        };
+
+        workflow.check_requirements()?;
+        let tag_filter = self.tag_filter.borrow().clone();
+        for warning in workflow.tag_filter_warnings(start_at, &tag_filter)? {
+            eprintln!("warning: {}", warning);
+        }
+        delegate.set_redactor(workflow.redactor()?);
+        delegate.set_env_policy(workflow.env_policy().cloned());
+        if let Some(checkpoint) = self.checkpoint.take() {
+            delegate.variable_store().restore(&checkpoint);
+        }
+        delegate.prompt_for_required()?;
+
+        let sandbox = match self.sandboxed.get() {
+            true => Some(Sandbox::create(&self.working_dir(), workflow.inputs())?),
+            false => None,
+        };
+        let working_dir = match &sandbox {
+            Some(sandbox) => sandbox.path().to_path_buf(),
+            None => self.working_dir(),
+        };
+        delegate.set_sandbox_dir(sandbox.as_ref().map(|s| s.path().to_path_buf()));
+
+        self.state.set(RunnerState::Running);
+        delegate.publish(RunEvent::Started {
+            workflow: self.workflow_file.clone(),
+            nodes: workflow
+                .nodes()
+                .iter()
+                .map(|n| n.name().to_string())
+                .collect(),
+        });
+        let result = workflow.run_from(
+            start_at,
+            delegate,
+            &working_dir,
+            self.profile_memory.get(),
+            &tag_filter,
+            eval,
+        );
+        self.state.set(RunnerState::Finished);
+        delegate.publish(RunEvent::Finished {
+            succeeded: result.as_ref().is_ok_and(|r| r.succeeded()),
+        });
+        match (&sandbox, &result) {
+            (Some(sandbox), Ok(result)) if result.succeeded() => {
+                sandbox.publish(workflow.outputs())?;
+            }
+            _ => {}
+        }
+        result
+    }
+
+    /// Records the memory in use after each node in the RunResult.
+    pub fn set_profile_memory(&self, enabled: bool) {
+        self.profile_memory.set(enabled);
+    }
+
+    /// Runs the workflow in a sandbox holding copies of its declared inputs,
+    /// its declared outputs are copied back once the run succeeds.
+    pub fn set_sandboxed(&self, enabled: bool) {
+        self.sandboxed.set(enabled);
+    }
+
+    /// Skips the nodes which the filter does not pick when the workflow runs.
+    pub fn set_tag_filter(&self, tag_filter: TagFilter) {
+        self.tag_filter.replace(tag_filter);
+    }
+
+    /// Sets the variables to restore, after they are realized, before the
+    /// workflow runs so a resumed run sees the values of the earlier run.
+    pub fn set_checkpoint(&self, checkpoint: VariableSnapshot) {
+        self.checkpoint.replace(Some(checkpoint));
+    }
+
     pub fn parse_workflow<'a>(&'a self, eval: &mut Evaluator<'a, 'a>) -> anyhow::Result<Value> {
-        let ast = AstModule::parse_file(self.workflow_file.as_path(), &Dialect::Standard)
-            .map_err(|e| e.into_anyhow())?;
+        let ast = match &self.source {
+            Some(source) => AstModule::parse(
+                &self.workflow_file.to_string_lossy(),
+                source.clone(),
+                &Dialect::Standard,
+            ),
+            None => AstModule::parse_file(self.workflow_file.as_path(), &Dialect::Standard),
+        }
+        .map_err(|e| e.into_anyhow())?;
         self.parse_ast(ast, eval)
     }
 
@@ -53,19 +437,69 @@ impl Runner {
         ast: AstModule,
         eval: &mut Evaluator<'a, 'a>,
     ) -> anyhow::Result<Value> {
+        match self.state.get() {
+            RunnerState::Created => (),
+            RunnerState::Parsing => bail!("The workflow is already being parsed"),
+            _ => bail!("The workflow has already been parsed, a Runner can only parse once"),
+        }
+        self.state.set(RunnerState::Parsing);
         eval.extra = Some(&self.delegate);
+        eval.set_loader(self);
 
         self.delegate
             .deref()
             .will_parse_workflow(self.workflow_file.clone());
-        let res = eval
-            .eval_module(ast, &self.globals)
-            .map_err(|e| e.into_anyhow())?;
+        let res = match eval.eval_module(ast, &self.globals) {
+            Ok(res) => res,
+            Err(e) => {
+                self.state.set(RunnerState::Finished);
+                bail!(e.into_anyhow())
+            }
+        };
 
-        self.delegate.deref().did_parse_workflow();
+        self.name_variables(eval.module());
+        if let Err(e) = self.delegate.deref().did_parse_workflow() {
+            self.state.set(RunnerState::Finished);
+            return Err(e);
+        }
+        self.state.set(RunnerState::Parsed);
         Ok(res)
     }
 
+    /// Tells the delegate the names the variables of the module are bound to.
+    fn name_variables(&self, module: &Module) {
+        for name in module.names() {
+            if let Some(variable) = module.get(&name).and_then(VariableRef::from_value) {
+                self.delegate
+                    .deref()
+                    .on_variable_name(variable.identifier(), &name);
+            }
+        }
+    }
+
+    /// Evaluates a starlark expression in the module of the parsed workflow,
+    /// which gives it access to all of the workflow's bindings.
+    pub fn eval_expression<'a>(
+        &'a self,
+        expression: &str,
+        eval: &mut Evaluator<'a, 'a>,
+    ) -> anyhow::Result<Value<'a>> {
+        match self.state.get() {
+            RunnerState::Created | RunnerState::Parsing => {
+                bail!("The workflow must be parsed before expressions can be evaluated")
+            }
+            _ => (),
+        }
+        let ast = AstModule::parse("<expression>", expression.to_string(), &Dialect::Standard)
+            .map_err(|e| e.into_anyhow())?;
+        eval.eval_module(ast, &self.globals)
+            .map_err(|e| e.into_anyhow())
+    }
+
+    pub fn state(&self) -> RunnerState {
+        self.state.get()
+    }
+
     pub fn delegate(&self) -> &ParseDelegateHolder {
         &self.delegate
     }
@@ -80,9 +514,9 @@ impl Runner {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::downcast_delegate_ref;
+    use crate::stdlib::dev::dev_stdlib;
     use crate::stdlib::test_utils::{TempWorkflowFile, TestParseDelegate};
-    use starlark::environment::Module;
+    use crate::stdlib::variable_resolver::VariableResolver;
 
     #[test]
     fn test_parse_file_calls_will_and_did_parse() {
@@ -148,4 +582,207 @@ mod tests {
 
         let _result = runner.parse_workflow(&mut eval).unwrap();
     }
+
+    #[test]
+    fn test_cannot_parse_twice() {
+        let file = TempWorkflowFile::new("test.workflow", "1").unwrap();
+        let runner = Runner::new(file.path(), TestParseDelegate::default()).unwrap();
+        assert_eq!(runner.state(), RunnerState::Created);
+
+        let module: Module = Module::new();
+        let mut eval: Evaluator = Evaluator::new(&module);
+        runner.parse_workflow(&mut eval).unwrap();
+        assert_eq!(runner.state(), RunnerState::Parsed);
+
+        let module: Module = Module::new();
+        let mut eval: Evaluator = Evaluator::new(&module);
+        assert_eq!(
+            runner.parse_workflow(&mut eval).unwrap_err().to_string(),
+            "The workflow has already been parsed, a Runner can only parse once"
+        );
+    }
+
+    #[test]
+    fn test_eval_expression() {
+        let file = TempWorkflowFile::new("test.workflow", "x = 20").unwrap();
+        let runner = Runner::new(file.path(), TestParseDelegate::default()).unwrap();
+        let module: Module = Module::new();
+        let mut eval: Evaluator = Evaluator::new(&module);
+        assert!(runner.eval_expression("x", &mut eval).is_err());
+
+        runner.parse_workflow(&mut eval).unwrap();
+        let value = runner.eval_expression("x + 1", &mut eval).unwrap();
+        assert_eq!(value.unpack_i32(), Some(21));
+    }
+
+    #[test]
+    fn test_var_args_use_bound_names() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            "host = variable(group = 'database', default = 'a')\nport = variable(default = '1')",
+        )
+        .unwrap();
+        let runner = Runner::new(
+            file.path(),
+            WorkflowDelegate::with_args(vec![
+                "--var".to_string(),
+                "database.host=b".to_string(),
+                "--var=port=2".to_string(),
+            ]),
+        )
+        .unwrap();
+        let module: Module = Module::new();
+        let mut eval: Evaluator = Evaluator::new(&module);
+        runner.parse_workflow(&mut eval).unwrap();
+
+        let holder = runner.delegate();
+        let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+        let value = |name: &str| {
+            let var = VariableRef::from_value(module.get(name).unwrap()).unwrap();
+            delegate.resolve(var.identifier()).unwrap()
+        };
+        assert_eq!(value("host"), "b");
+        assert_eq!(value("port"), "2");
+    }
+
+    #[test]
+    fn test_var_arg_can_not_set_const() {
+        let file =
+            TempWorkflowFile::new("test.workflow", "version = const(value = '1.0')").unwrap();
+        let runner = Runner::new(
+            file.path(),
+            WorkflowDelegate::with_args(vec!["--var=version=2.0".to_string()]),
+        )
+        .unwrap();
+        let module: Module = Module::new();
+        let mut eval: Evaluator = Evaluator::new(&module);
+        let err = runner.parse_workflow(&mut eval).unwrap_err();
+        assert_eq!(err.to_string(), "cannot set const 'version'");
+        assert_eq!(runner.state(), RunnerState::Finished);
+    }
+
+    #[test]
+    fn test_extra_globals() {
+        let file = TempWorkflowFile::new("test.workflow", "fake_ctx(exit_code = 3)").unwrap();
+        let runner = Runner::new(file.path(), TestParseDelegate::default()).unwrap();
+        let module: Module = Module::new();
+        let mut eval: Evaluator = Evaluator::new(&module);
+        assert!(runner.parse_workflow(&mut eval).is_err());
+
+        let runner =
+            Runner::new_with_globals(file.path(), TestParseDelegate::default(), &[dev_stdlib])
+                .unwrap();
+        let module: Module = Module::new();
+        let mut eval: Evaluator = Evaluator::new(&module);
+        assert!(runner.parse_workflow(&mut eval).is_ok());
+    }
+
+    #[test]
+    fn test_parse_failure_finishes() {
+        let file = TempWorkflowFile::new("test.workflow", "fail('boom')").unwrap();
+        let runner = Runner::new(file.path(), TestParseDelegate::default()).unwrap();
+        let module: Module = Module::new();
+        let mut eval: Evaluator = Evaluator::new(&module);
+        assert!(runner.parse_workflow(&mut eval).is_err());
+        assert_eq!(runner.state(), RunnerState::Finished);
+    }
+
+    const WORKFLOW: &str = r#"
+main = workflow(
+    graph = node(
+        name = "a",
+        action = action(tool = builtin_tool(name = "true")),
+    ),
+)
+"#;
+
+    #[test]
+    fn test_run_before_parse_fails() {
+        let file = TempWorkflowFile::new("test.workflow", WORKFLOW).unwrap();
+        let parsed = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        let module: Module = Module::new();
+        let mut eval: Evaluator = Evaluator::new(&module);
+        parsed.parse_workflow(&mut eval).unwrap();
+        let workflow = Workflow::from_value(module.get("main").unwrap()).unwrap();
+
+        let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        assert_eq!(
+            runner
+                .run_workflow(workflow, None, &mut eval)
+                .unwrap_err()
+                .to_string(),
+            "The workflow must be parsed before it can be run"
+        );
+    }
+
+    #[test]
+    fn test_run_only_once() {
+        let file = TempWorkflowFile::new("test.workflow", WORKFLOW).unwrap();
+        let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        let result = runner.run(None).unwrap();
+        assert!(result.succeeded());
+        assert_eq!(runner.state(), RunnerState::Finished);
+
+        assert_eq!(
+            runner.run(None).unwrap_err().to_string(),
+            "The workflow has already been parsed, a Runner can only parse once"
+        );
+    }
+
+    #[test]
+    fn test_failed_run_can_not_run_again() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+main = workflow(
+    requires = ["__not_a_command__"],
+    graph = node(
+        name = "a",
+        action = action(tool = builtin_tool(name = "true")),
+    ),
+)
+"#,
+        )
+        .unwrap();
+        let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        let module: Module = Module::new();
+        let mut eval: Evaluator = Evaluator::new(&module);
+        runner.parse_workflow(&mut eval).unwrap();
+        let workflow = Workflow::from_value(module.get("main").unwrap()).unwrap();
+
+        assert!(runner
+            .run_workflow(workflow, None, &mut eval)
+            .unwrap_err()
+            .to_string()
+            .contains("__not_a_command__"));
+        assert_eq!(runner.state(), RunnerState::Finished);
+        assert_eq!(
+            runner
+                .run_workflow(workflow, None, &mut eval)
+                .unwrap_err()
+                .to_string(),
+            "The workflow has already run, create a new Runner to run it again"
+        );
+    }
+
+    #[test]
+    fn test_main_which_is_not_a_workflow_finishes() {
+        let file = TempWorkflowFile::new("test.workflow", "main = 1").unwrap();
+        let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        assert_eq!(
+            runner.run(None).unwrap_err().to_string(),
+            "main must be a workflow"
+        );
+        assert_eq!(runner.state(), RunnerState::Finished);
+    }
+
+    #[test]
+    fn test_run_requires_workflow_delegate() {
+        let file = TempWorkflowFile::new("test.workflow", WORKFLOW).unwrap();
+        let runner = Runner::new(file.path(), TestParseDelegate::default()).unwrap();
+        assert_eq!(
+            runner.run(None).unwrap_err().to_string(),
+            "Running a workflow requires a WorkflowDelegate"
+        );
+    }
 }
@@ -0,0 +1,164 @@
+use super::state_dir;
+use crate::stdlib::node_cache::CachedNode;
+use crate::stdlib::schema::Schema;
+use anyhow::{anyhow, bail};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const NODE_CACHE_DIR_NAME: &str = "node_cache";
+
+const CACHE_SCHEMA: Schema = Schema {
+    name: "the node cache",
+    version: 1,
+    oldest: 0,
+    migrations: &[unversioned_cache],
+};
+
+// the caches written before they were versioned are the map of the nodes
+fn unversioned_cache(value: Value) -> anyhow::Result<Value> {
+    Ok(json!({ "nodes": value }))
+}
+
+/// The outputs of the cached nodes of a workflow on their last successful
+/// run, by node name, stored as json in the state dir along with the
+/// version of its schema.
+#[derive(Debug)]
+pub struct NodeCache {
+    path: PathBuf,
+    nodes: RefCell<BTreeMap<String, CachedNode>>,
+    // if not set the recorded outputs are not used, they are still updated
+    reuse: bool,
+}
+
+impl NodeCache {
+    /// Opens the cache of the workflow at `workflow` in the state dir.
+    pub fn for_workflow(workflow: &Path) -> anyhow::Result<Self> {
+        let name = format!(
+            "{:x}.json",
+            Sha256::digest(workflow.to_string_lossy().as_bytes())
+        );
+        NodeCache::open(state_dir()?.join(NODE_CACHE_DIR_NAME).join(name))
+    }
+
+    /// Returns the caches in `state_dir` which were last written before
+    /// `before`, those of workflows which have not run since or were moved.
+    pub fn stale(state_dir: &Path, before: SystemTime) -> Vec<PathBuf> {
+        let entries = match fs::read_dir(state_dir.join(NODE_CACHE_DIR_NAME)) {
+            Ok(entries) => entries,
+            Err(_) => return vec![],
+        };
+        let mut caches: Vec<PathBuf> = entries
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let metadata = entry.metadata().ok()?;
+                let stale = metadata.is_file() && metadata.modified().ok()? < before;
+                stale.then(|| entry.path())
+            })
+            .collect();
+        caches.sort();
+        caches
+    }
+
+    pub fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let nodes = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(anyhow::Error::from)
+                .and_then(|value| CACHE_SCHEMA.migrate(value))
+                .and_then(|mut value| Ok(serde_json::from_value(value["nodes"].take())?))
+                .map_err(|e| anyhow!("Invalid node cache {:?}: {}", path, e))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => bail!("Unable to read node cache {:?}: {}", path, e),
+        };
+        Ok(NodeCache {
+            path,
+            nodes: RefCell::new(nodes),
+            reuse: true,
+        })
+    }
+
+    /// Sets whether the recorded outputs are used to skip unchanged nodes.
+    pub fn with_reuse(mut self, reuse: bool) -> Self {
+        self.reuse = reuse;
+        self
+    }
+
+    /// Returns what the node recorded, None if there is no record or the
+    /// records are not reused.
+    pub fn get(&self, node: &str) -> Option<CachedNode> {
+        match self.reuse {
+            true => self.nodes.borrow().get(node).cloned(),
+            false => None,
+        }
+    }
+
+    /// Records the outputs of the node, replacing its earlier record, and
+    /// writes the cache.
+    pub fn set(&self, node: &str, cached: CachedNode) -> anyhow::Result<()> {
+        self.nodes.borrow_mut().insert(node.to_string(), cached);
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let cache = CACHE_SCHEMA.write(&json!({ "nodes": &*self.nodes.borrow() }))?;
+        fs::write(&self.path, cache.to_string())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib::node_cache::CachedAction;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_set_and_reopen() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("cache/test.json");
+        let cached = CachedNode {
+            key: "abc".to_string(),
+            actions: vec![CachedAction {
+                exit_code: 0,
+                stdout: b"out".to_vec(),
+                stderr: vec![],
+            }],
+        };
+
+        let cache = NodeCache::open(path.clone()).unwrap();
+        assert_eq!(cache.get("build"), None);
+        cache.set("build", cached.clone()).unwrap();
+        assert_eq!(cache.get("build"), Some(cached.clone()));
+
+        let cache = NodeCache::open(path.clone()).unwrap();
+        assert_eq!(cache.get("build"), Some(cached.clone()));
+        assert_eq!(cache.with_reuse(false).get("build"), None);
+
+        // caches written before they were versioned are still read
+        let unversioned = json!({ "build": &cached }).to_string();
+        fs::write(&path, unversioned).unwrap();
+        assert_eq!(
+            NodeCache::open(path.clone()).unwrap().get("build"),
+            Some(cached)
+        );
+
+        fs::write(&path, r#"{"version": 2, "nodes": {}}"#).unwrap();
+        assert_eq!(
+            NodeCache::open(path.clone()).unwrap_err().to_string(),
+            format!(
+                "Invalid node cache {:?}: the node cache was produced by a newer version of workflow (version 2), this one reads up to version 1",
+                path
+            )
+        );
+
+        fs::write(&path, "[").unwrap();
+        assert!(NodeCache::open(path)
+            .unwrap_err()
+            .to_string()
+            .starts_with("Invalid node cache"));
+    }
+}
@@ -0,0 +1,136 @@
+use crate::runner::{RunEvent, SourcePosition, VariableSource};
+use crate::stdlib::RunResult;
+use starlark::environment::GlobalsBuilder;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// A plugin which can extend the workflow tool without forking the crate.
+///
+/// Plugins are registered at compile time by the binary embedding this
+/// crate, before the command line is parsed:
+///
+/// ```ignore
+/// workflow::runner::register_plugin(MyCompanyPlugin::default());
+/// workflow::cmd::Cli::parse().parse_and_run()
+/// ```
+pub trait Plugin: Send + Sync {
+    /// The name of the plugin, used in error messages.
+    fn name(&self) -> &str;
+
+    /// Called when the globals for the workflow are built so the plugin
+    /// can add its own builtins, e.g. a `deploy_tool()` function.
+    fn register_globals(&self, _builder: &mut GlobalsBuilder) {}
+
+    /// Returns the variable sources the plugin provides, e.g. a lookup in
+    /// a key value store, and where they sit in the chain of sources.
+    fn variable_sources(&self) -> Vec<(SourcePosition, Arc<dyn VariableSource>)> {
+        vec![]
+    }
+
+    /// Called before the workflow starts running.
+    fn will_run_workflow(&self, _workflow: &Path) {}
+
+    /// Called after the workflow has finished running.
+    fn did_run_workflow(&self, _workflow: &Path, _result: &RunResult) {}
+
+    /// Called with every event of every run, e.g. to report the runs to
+    /// another system as they happen.
+    fn on_event(&self, _event: &RunEvent) {}
+}
+
+static PLUGINS: Mutex<Vec<Arc<dyn Plugin>>> = Mutex::new(Vec::new());
+
+/// Registers the plugin for all Runners created after this call.
+pub fn register_plugin<P: Plugin + 'static>(plugin: P) {
+    PLUGINS.lock().unwrap().push(Arc::new(plugin));
+}
+
+/// Returns all of the registered plugins in registration order.
+pub fn registered_plugins() -> Vec<Arc<dyn Plugin>> {
+    PLUGINS.lock().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::{Runner, WorkflowDelegate};
+    use crate::stdlib::test_utils::{TempWorkflowFile, TestParseDelegate};
+    use starlark::environment::Module;
+    use starlark::eval::Evaluator;
+    use starlark::starlark_module;
+
+    #[starlark_module]
+    fn test_plugin_globals(builder: &mut GlobalsBuilder) {
+        fn test_plugin_builtin() -> anyhow::Result<i32> {
+            Ok(42)
+        }
+    }
+
+    struct TestPlugin {}
+
+    impl Plugin for TestPlugin {
+        fn name(&self) -> &str {
+            "test_plugin"
+        }
+
+        fn register_globals(&self, builder: &mut GlobalsBuilder) {
+            test_plugin_globals(builder);
+        }
+    }
+
+    // the names of the nodes the plugin saw finish
+    static FINISHED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    struct EventPlugin {}
+
+    impl Plugin for EventPlugin {
+        fn name(&self) -> &str {
+            "event_plugin"
+        }
+
+        fn on_event(&self, event: &RunEvent) {
+            if let RunEvent::NodeFinished(node) = event {
+                FINISHED.lock().unwrap().push(node.name.clone());
+            }
+        }
+    }
+
+    #[test]
+    fn test_registered_plugin_adds_builtins() {
+        register_plugin(TestPlugin {});
+        assert!(registered_plugins()
+            .iter()
+            .any(|p| p.name() == "test_plugin"));
+
+        let file = TempWorkflowFile::new("test.workflow", "test_plugin_builtin()").unwrap();
+        let runner = Runner::new(file.path(), TestParseDelegate::default()).unwrap();
+        let module: Module = Module::new();
+        let mut eval: Evaluator = Evaluator::new(&module);
+
+        let result = runner.parse_workflow(&mut eval).unwrap();
+        assert_eq!(result.unpack_i32(), Some(42));
+    }
+
+    #[test]
+    fn test_registered_plugin_sees_events() {
+        register_plugin(EventPlugin {});
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+main = workflow(
+    entrypoint = "plugin_sees_me",
+    graph = [node(name = "plugin_sees_me", action = action(tool = builtin_tool(name = "true")))],
+)
+"#,
+        )
+        .unwrap();
+        Runner::new(file.path(), WorkflowDelegate::new())
+            .unwrap()
+            .run(None)
+            .unwrap();
+        assert!(FINISHED
+            .lock()
+            .unwrap()
+            .contains(&"plugin_sees_me".to_string()));
+    }
+}
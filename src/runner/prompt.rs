@@ -0,0 +1,52 @@
+use anyhow::bail;
+use std::fmt;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+/// Asks for the values of the required variables which have none when a
+/// run starts.
+pub trait Prompter: fmt::Debug + Send + Sync {
+    /// Returns the answer to the prompt, failing if there is no one to ask.
+    fn ask(&self, prompt: &str) -> anyhow::Result<String>;
+}
+
+/// Asks for the values on the terminal the workflow runs in.
+#[derive(Debug, Default)]
+pub struct TerminalPrompter;
+
+impl Prompter for TerminalPrompter {
+    fn ask(&self, prompt: &str) -> anyhow::Result<String> {
+        if !io::stdin().is_terminal() {
+            bail!("there is no terminal to ask on");
+        }
+        eprint!("{}: ", prompt);
+        io::stderr().flush()?;
+        read_answer(&mut io::stdin().lock())
+    }
+}
+
+/// Reads a line of input, without its line ending.
+fn read_answer(input: &mut dyn BufRead) -> anyhow::Result<String> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        bail!("got no answer");
+    }
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_answer() {
+        assert_eq!(
+            read_answer(&mut "example.com\n".as_bytes()).unwrap(),
+            "example.com"
+        );
+        assert_eq!(read_answer(&mut " a b \r\n".as_bytes()).unwrap(), " a b ");
+        assert_eq!(
+            read_answer(&mut "".as_bytes()).unwrap_err().to_string(),
+            "got no answer"
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use crate::stdlib::glob::match_pattern;
+use anyhow::anyhow;
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// A directory of its own for a run to work in, holding copies of the
+/// workflow's declared inputs so the run does not change the source tree
+/// by accident. Tools can still write outside of it, it only keeps the
+/// relative paths of commands without a cwd in it. Removed when dropped.
+#[derive(Debug)]
+pub struct Sandbox {
+    source: PathBuf,
+    path: PathBuf,
+}
+
+impl Sandbox {
+    /// Creates a sandbox in the temp dir with a copy of every file below
+    /// `source` which matches one of the `inputs`.
+    pub fn create(source: &Path, inputs: &[String]) -> anyhow::Result<Self> {
+        let sandbox = Sandbox {
+            source: source.to_path_buf(),
+            path: std::env::temp_dir().join(format!("workflow-sandbox-{}", Uuid::new_v4())),
+        };
+        fs::create_dir_all(&sandbox.path)?;
+        copy_matching(inputs, &sandbox.source, &sandbox.path)
+            .map_err(|e| e.context("Unable to copy the inputs into the sandbox"))?;
+        Ok(sandbox)
+    }
+
+    /// The directory the run works in.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Copies the files the run produced which match one of the `outputs`
+    /// back into the source tree and returns their paths relative to it.
+    pub fn publish(&self, outputs: &[String]) -> anyhow::Result<Vec<String>> {
+        copy_matching(outputs, &self.path, &self.source)
+            .map_err(|e| e.context("Unable to publish the outputs of the sandbox"))
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Copies the files below `from` matching the patterns to the same paths
+/// below `to`. `fs::copy` clones the file instead of copying its contents
+/// where the file system supports it.
+fn copy_matching(patterns: &[String], from: &Path, to: &Path) -> anyhow::Result<Vec<String>> {
+    let mut copied = vec![];
+    for pattern in patterns {
+        for path in match_pattern(pattern, from)? {
+            let source = from.join(&path);
+            if !source.is_file() || copied.contains(&path) {
+                continue;
+            }
+            let dest = to.join(&path);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&source, &dest).map_err(|e| anyhow!("Unable to copy {:?}: {}", path, e))?;
+            copied.push(path);
+        }
+    }
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_sandbox() {
+        let source = tempdir().unwrap();
+        fs::create_dir_all(source.path().join("src")).unwrap();
+        fs::write(source.path().join("src/a.txt"), "a").unwrap();
+        fs::write(source.path().join("notes.txt"), "notes").unwrap();
+
+        let sandbox = Sandbox::create(source.path(), &["src/*.txt".to_string()]).unwrap();
+        let path = sandbox.path().to_path_buf();
+        assert_eq!(fs::read_to_string(path.join("src/a.txt")).unwrap(), "a");
+        assert!(!path.join("notes.txt").exists());
+
+        fs::write(path.join("src/a.txt"), "changed").unwrap();
+        fs::create_dir_all(path.join("dist")).unwrap();
+        fs::write(path.join("dist/out.txt"), "out").unwrap();
+        let published = sandbox.publish(&["dist/**".to_string()]).unwrap();
+        assert_eq!(published, ["dist/out.txt"]);
+        assert_eq!(
+            fs::read_to_string(source.path().join("dist/out.txt")).unwrap(),
+            "out"
+        );
+        assert_eq!(
+            fs::read_to_string(source.path().join("src/a.txt")).unwrap(),
+            "a"
+        );
+
+        drop(sandbox);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,264 @@
+use anyhow::bail;
+use std::fmt;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+// the furthest ahead a schedule is searched for its next time, a schedule
+// like "0 0 30 2 *" never matches
+const SEARCH_YEARS: u64 = 5;
+
+/// A UTC time broken down into the parts a schedule matches on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UtcTime {
+    pub year: i64,
+    /// 1-12
+    pub month: u32,
+    /// 1-31
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    /// 0 is Sunday
+    pub weekday: u32,
+}
+
+impl UtcTime {
+    /// Breaks down the seconds since the unix epoch.
+    pub fn from_secs(secs: u64) -> Self {
+        let days = (secs / SECS_PER_DAY) as i64;
+        let rem = secs % SECS_PER_DAY;
+        // from Howard Hinnant's civil_from_days
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        UtcTime {
+            year,
+            month,
+            day,
+            hour: (rem / 3600) as u32,
+            minute: (rem % 3600 / 60) as u32,
+            second: (rem % 60) as u32,
+            // the epoch was a Thursday
+            weekday: ((days + 4).rem_euclid(7)) as u32,
+        }
+    }
+}
+
+impl fmt::Display for UtcTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// The times matched by one field of a schedule, bit n is set if n matches.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Field {
+    bits: u64,
+    // whether the field starts with a `*`, which matters for the days
+    any: bool,
+}
+
+impl Field {
+    fn parse(text: &str, name: &str, min: u32, max: u32) -> anyhow::Result<Self> {
+        let invalid =
+            |reason: &str| anyhow::anyhow!("Invalid {} '{}' in schedule, {}", name, text, reason);
+        let number = |s: &str| -> anyhow::Result<u32> {
+            match s.parse::<u32>() {
+                Ok(n) if (min..=max).contains(&n) => Ok(n),
+                _ => Err(invalid(&format!(
+                    "expected a number from {} to {}",
+                    min, max
+                ))),
+            }
+        };
+        let mut bits = 0;
+        for part in text.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => match step.parse::<u32>() {
+                    Ok(step) if step > 0 => (range, Some(step)),
+                    _ => return Err(invalid("the step must be a positive number")),
+                },
+                None => (part, None),
+            };
+            let (start, end) = match range.split_once('-') {
+                _ if range == "*" => (min, max),
+                Some((start, end)) => (number(start)?, number(end)?),
+                // "5/10" runs from 5 to the end
+                None if step.is_some() => (number(range)?, max),
+                None => (number(range)?, number(range)?),
+            };
+            if start > end {
+                return Err(invalid("a range must not end before it starts"));
+            }
+            for n in (start..=end).step_by(step.unwrap_or(1) as usize) {
+                bits |= 1 << n;
+            }
+        }
+        Ok(Field {
+            bits,
+            any: text.starts_with('*'),
+        })
+    }
+
+    fn matches(&self, n: u32) -> bool {
+        self.bits & (1 << n) != 0
+    }
+}
+
+/// When a scheduled workflow runs, parsed from a cron expression of the
+/// minute, hour, day of month, month and day of week. Times are in UTC.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    expression: String,
+    minutes: Field,
+    hours: Field,
+    days: Field,
+    months: Field,
+    weekdays: Field,
+}
+
+impl Schedule {
+    pub fn parse(expression: &str) -> anyhow::Result<Self> {
+        let expanded = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" => "0 0 1 1 *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        if fields.len() != 5 {
+            bail!(
+                "Invalid schedule '{}', expected 5 fields: minute, hour, day of month, month and day of week",
+                expression
+            );
+        }
+        let mut weekdays = Field::parse(fields[4], "day of week", 0, 7)?;
+        // both 0 and 7 are Sunday
+        if weekdays.matches(7) {
+            weekdays.bits |= 1;
+        }
+        let schedule = Schedule {
+            expression: expression.to_string(),
+            minutes: Field::parse(fields[0], "minute", 0, 59)?,
+            hours: Field::parse(fields[1], "hour", 0, 23)?,
+            days: Field::parse(fields[2], "day of month", 1, 31)?,
+            months: Field::parse(fields[3], "month", 1, 12)?,
+            weekdays,
+        };
+        if schedule.next_after(0).is_none() {
+            bail!("Invalid schedule '{}', it never matches", expression);
+        }
+        Ok(schedule)
+    }
+
+    /// Whether the schedule runs on the day. As in cron, when both the day
+    /// of month and day of week are set a day matching either runs.
+    fn matches_day(&self, time: &UtcTime) -> bool {
+        if !self.months.matches(time.month) {
+            return false;
+        }
+        let day = self.days.matches(time.day);
+        let weekday = self.weekdays.matches(time.weekday);
+        match (self.days.any, self.weekdays.any) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    /// Returns the first time the schedule runs after `secs`, as seconds
+    /// since the unix epoch. None if it does not run in the next few years.
+    pub fn next_after(&self, secs: u64) -> Option<u64> {
+        let mut t = secs / 60 * 60 + 60;
+        let limit = t + SEARCH_YEARS * 366 * SECS_PER_DAY;
+        while t < limit {
+            let time = UtcTime::from_secs(t);
+            if !self.matches_day(&time) {
+                t = (t / SECS_PER_DAY + 1) * SECS_PER_DAY;
+            } else if !self.hours.matches(time.hour) {
+                t = (t / 3600 + 1) * 3600;
+            } else if !self.minutes.matches(time.minute) {
+                t += 60;
+            } else {
+                return Some(t);
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-02-28 23:59:30 UTC, a Wednesday
+    const T: u64 = 1709164770;
+
+    fn next(expression: &str, secs: u64) -> String {
+        let schedule = Schedule::parse(expression).unwrap();
+        UtcTime::from_secs(schedule.next_after(secs).unwrap()).to_string()
+    }
+
+    #[test]
+    fn test_utc_time() {
+        assert_eq!(UtcTime::from_secs(0).to_string(), "1970-01-01 00:00:00 UTC");
+        let time = UtcTime::from_secs(T);
+        assert_eq!(time.to_string(), "2024-02-28 23:59:30 UTC");
+        assert_eq!(time.weekday, 3);
+    }
+
+    #[test]
+    fn test_next_after() {
+        assert_eq!(next("* * * * *", T), "2024-02-29 00:00:00 UTC");
+        assert_eq!(next("*/15 * * * *", T + 60 * 20), "2024-02-29 00:30:00 UTC");
+        assert_eq!(next("30 9 * * 1-5", T), "2024-02-29 09:30:00 UTC");
+        // a Saturday or the 1st, whichever comes first
+        assert_eq!(next("0 0 1 * 6", T), "2024-03-01 00:00:00 UTC");
+        assert_eq!(next("0 12 * * 7", T), "2024-03-03 12:00:00 UTC");
+        assert_eq!(next("0 0 29 2 *", T + 3600), "2028-02-29 00:00:00 UTC");
+        assert_eq!(next("@monthly", T), "2024-03-01 00:00:00 UTC");
+        assert_eq!(next("5,10-12/2 * * * *", T), "2024-02-29 00:05:00 UTC");
+    }
+
+    #[test]
+    fn test_invalid_schedules() {
+        let error = |expression: &str| Schedule::parse(expression).unwrap_err().to_string();
+        assert_eq!(
+            error("* * * *"),
+            "Invalid schedule '* * * *', expected 5 fields: minute, hour, day of month, month and day of week"
+        );
+        assert_eq!(
+            error("60 * * * *"),
+            "Invalid minute '60' in schedule, expected a number from 0 to 59"
+        );
+        assert_eq!(
+            error("*/0 * * * *"),
+            "Invalid minute '*/0' in schedule, the step must be a positive number"
+        );
+        assert_eq!(
+            error("* 5-1 * * *"),
+            "Invalid hour '5-1' in schedule, a range must not end before it starts"
+        );
+        assert_eq!(
+            error("0 0 30 2 *"),
+            "Invalid schedule '0 0 30 2 *', it never matches"
+        );
+    }
+}
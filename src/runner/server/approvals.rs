@@ -0,0 +1,188 @@
+use crate::stdlib::approval::{Approval, ApprovalRequest, Approver};
+use anyhow::anyhow;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+// how often a waiting gate checks whether its run was cancelled or timed out
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A manual gate of a run which is waiting to be approved.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PendingGate {
+    pub id: u64,
+    /// The id of the queued run the gate belongs to.
+    pub job: u64,
+    pub workflow: String,
+    pub gate: String,
+    pub message: String,
+}
+
+#[derive(Debug, Default)]
+struct BoardState {
+    next_id: u64,
+    // in the order they started waiting
+    pending: Vec<PendingGate>,
+    // the decisions which the waiting gates have not picked up yet
+    decided: BTreeMap<u64, Result<Approval, String>>,
+}
+
+/// The manual gates which are waiting for a decision made through the API.
+#[derive(Debug, Default)]
+pub struct ApprovalBoard {
+    state: Mutex<BoardState>,
+    changed: Condvar,
+}
+
+impl ApprovalBoard {
+    pub fn new() -> Self {
+        ApprovalBoard::default()
+    }
+
+    /// Returns the approver for the gates of a run of the workflow.
+    pub fn for_job(self: &Arc<Self>, job: u64, workflow: &str) -> JobApprover {
+        JobApprover {
+            board: self.clone(),
+            job,
+            workflow: workflow.to_string(),
+        }
+    }
+
+    /// Returns the gates which are waiting, oldest first.
+    pub fn pending(&self) -> Vec<PendingGate> {
+        self.state.lock().unwrap().pending.clone()
+    }
+
+    pub fn gate(&self, id: u64) -> Option<PendingGate> {
+        let state = self.state.lock().unwrap();
+        state.pending.iter().find(|g| g.id == id).cloned()
+    }
+
+    /// Approves or rejects the waiting gate in the name of `by`. Returns
+    /// the gate, None if there is no such gate waiting.
+    pub fn decide(&self, id: u64, approve: bool, by: &str) -> Option<PendingGate> {
+        let mut state = self.state.lock().unwrap();
+        let index = state.pending.iter().position(|g| g.id == id)?;
+        let gate = state.pending.remove(index);
+        let decision = match approve {
+            true => Ok(Approval::now(by)),
+            false => Err(format!(
+                "manual gate '{}' was rejected by {}",
+                gate.gate, by
+            )),
+        };
+        state.decided.insert(id, decision);
+        self.changed.notify_all();
+        Some(gate)
+    }
+
+    /// Lists the gate as pending and blocks until it is decided, the run
+    /// is cancelled or the gate times out.
+    fn wait(
+        &self,
+        job: u64,
+        workflow: &str,
+        request: &ApprovalRequest,
+    ) -> anyhow::Result<Approval> {
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+        let id = state.next_id;
+        state.pending.push(PendingGate {
+            id,
+            job,
+            workflow: workflow.to_string(),
+            gate: request.gate.clone(),
+            message: request.message.clone(),
+        });
+        loop {
+            if let Some(decision) = state.decided.remove(&id) {
+                return decision.map_err(|e| anyhow!(e));
+            }
+            if let Err(e) = request.check_waiting() {
+                state.pending.retain(|g| g.id != id);
+                return Err(e);
+            }
+            state = self.changed.wait_timeout(state, POLL_INTERVAL).unwrap().0;
+        }
+    }
+}
+
+/// Asks for the approval of the gates of one run on the board.
+#[derive(Debug)]
+pub struct JobApprover {
+    board: Arc<ApprovalBoard>,
+    job: u64,
+    workflow: String,
+}
+
+impl Approver for JobApprover {
+    fn ask(&self, request: &ApprovalRequest) -> anyhow::Result<Approval> {
+        self.board.wait(self.job, &self.workflow, request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Instant;
+
+    fn request(deadline: Option<Instant>) -> ApprovalRequest {
+        ApprovalRequest {
+            gate: "deploy".to_string(),
+            message: "Deploy to prod?".to_string(),
+            deadline,
+            cancel_token: None,
+        }
+    }
+
+    fn wait_for_gate(board: &ApprovalBoard) -> PendingGate {
+        loop {
+            if let Some(gate) = board.pending().pop() {
+                return gate;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_decide() {
+        let board = Arc::new(ApprovalBoard::new());
+        let ask = |board: &Arc<ApprovalBoard>| {
+            let approver = board.for_job(7, "cd");
+            thread::spawn(move || approver.ask(&request(None)))
+        };
+
+        let waiting = ask(&board);
+        let gate = wait_for_gate(&board);
+        assert_eq!((gate.job, gate.workflow.as_str()), (7, "cd"));
+        assert_eq!(board.gate(gate.id), Some(gate.clone()));
+        assert!(board.decide(gate.id, true, "alice").is_some());
+        assert_eq!(waiting.join().unwrap().unwrap().by, "alice");
+        assert!(board.pending().is_empty());
+        assert!(board.decide(gate.id, true, "alice").is_none());
+
+        let waiting = ask(&board);
+        let gate = wait_for_gate(&board);
+        board.decide(gate.id, false, "bob");
+        assert_eq!(
+            waiting.join().unwrap().unwrap_err().to_string(),
+            "manual gate 'deploy' was rejected by bob"
+        );
+    }
+
+    #[test]
+    fn test_timeout() {
+        let board = Arc::new(ApprovalBoard::new());
+        let err = board
+            .for_job(1, "cd")
+            .ask(&request(Some(Instant::now() + POLL_INTERVAL)))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "manual gate 'deploy' was not approved in time"
+        );
+        assert!(board.pending().is_empty());
+    }
+}
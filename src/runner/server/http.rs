@@ -0,0 +1,269 @@
+use anyhow::bail;
+use std::collections::BTreeMap;
+use std::io::{BufRead, Read, Write};
+use thiserror::Error;
+
+// requests are small json documents, anything bigger is a mistake
+const MAX_BODY_LEN: usize = 1024 * 1024;
+// the longest request line or header, with its line ending
+const MAX_LINE_LEN: usize = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+
+/// A request which is too large to be read, answered with its status
+/// rather than the 400 of other invalid requests.
+#[derive(Debug, Error)]
+#[error("{message}")]
+pub struct RequestTooLarge {
+    pub status: u16,
+    message: String,
+}
+
+impl RequestTooLarge {
+    fn new(status: u16, message: String) -> Self {
+        RequestTooLarge { status, message }
+    }
+}
+
+/// An HTTP request, only what the server needs of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: BTreeMap<String, String>,
+    /// The headers by their lowercased name.
+    pub headers: BTreeMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Reads a request, failing if it is not valid HTTP/1.x or with
+    /// RequestTooLarge if its lines are too long or it has too many headers.
+    pub fn read(reader: &mut dyn BufRead) -> anyhow::Result<Self> {
+        let line = read_line(reader)?;
+        if line.len() > MAX_LINE_LEN {
+            bail!(RequestTooLarge::new(
+                400,
+                format!("The request line is longer than {} bytes", MAX_LINE_LEN)
+            ));
+        }
+        let mut parts = line.split_whitespace();
+        let (method, target) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/1.") => {
+                (method.to_string(), target.to_string())
+            }
+            _ => bail!("Invalid request line {:?}", line.trim_end()),
+        };
+
+        let mut headers = BTreeMap::new();
+        for count in 0.. {
+            let header = read_line(reader)?;
+            if header.is_empty() {
+                bail!("The connection closed before the end of the headers");
+            }
+            if header.len() > MAX_LINE_LEN {
+                bail!(RequestTooLarge::new(
+                    431,
+                    format!("A header is longer than {} bytes", MAX_LINE_LEN)
+                ));
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if count == MAX_HEADERS {
+                bail!(RequestTooLarge::new(
+                    431,
+                    format!("The request has more than {} headers", MAX_HEADERS)
+                ));
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+            }
+        }
+        let content_length: usize = match headers.get("content-length") {
+            Some(length) => length.parse()?,
+            None => 0,
+        };
+        if content_length > MAX_BODY_LEN {
+            bail!("The request body is larger than {} bytes", MAX_BODY_LEN);
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path.to_string(), parse_query(query)),
+            None => (target, BTreeMap::new()),
+        };
+        Ok(Request {
+            method,
+            path,
+            query,
+            headers,
+            body,
+        })
+    }
+
+    /// Returns the token from an `Authorization: Bearer` header, or from
+    /// the `token` query parameter for clients which can only be given a
+    /// url, like most webhook senders.
+    pub fn token(&self) -> Option<&str> {
+        match self.headers.get("authorization") {
+            Some(value) => value.strip_prefix("Bearer ").map(str::trim),
+            None => self.query.get("token").map(String::as_str),
+        }
+    }
+
+    /// Returns the parts of the path, e.g. ["workflows", "ci", "runs"].
+    pub fn segments(&self) -> Vec<&str> {
+        self.path.split('/').filter(|s| !s.is_empty()).collect()
+    }
+}
+
+/// Reads a line, empty at the end of the stream. At most one byte more than
+/// MAX_LINE_LEN is read, so a longer line is cut off there and can be told
+/// apart by its length.
+fn read_line(reader: &mut dyn BufRead) -> anyhow::Result<String> {
+    let mut line = String::new();
+    reader.take(MAX_LINE_LEN as u64 + 1).read_line(&mut line)?;
+    Ok(line)
+}
+
+/// Parses `a=1&b=2`, the names and values are percent decoded.
+fn parse_query(query: &str) -> BTreeMap<String, String> {
+    query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| match p.split_once('=') {
+            Some((k, v)) => (percent_decode(k), percent_decode(v)),
+            None => (percent_decode(p), String::new()),
+        })
+        .collect()
+}
+
+/// Decodes the `%XX` escapes of a query component and `+` as a space. An
+/// escape which is not two hex digits is kept as is.
+fn percent_decode(component: &str) -> String {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+            (b'+', _) => decoded.push(b' '),
+            (byte, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        431 => "Request Header Fields Too Large",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Writes the status line and headers of a response whose body is written
+/// after them and ends when the connection is closed.
+pub fn write_head(out: &mut dyn Write, status: u16, content_type: &str) -> std::io::Result<()> {
+    write!(
+        out,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason(status),
+        content_type
+    )
+}
+
+/// Writes a complete response with a json body.
+pub fn write_json(
+    out: &mut dyn Write,
+    status: u16,
+    body: &serde_json::Value,
+) -> std::io::Result<()> {
+    let body = body.to_string();
+    write!(
+        out,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason(status),
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_request() {
+        let raw = "POST /workflows/ci/runs?limit=5&x HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\r\nbodyextra";
+        let request = Request::read(&mut raw.as_bytes()).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.segments(), ["workflows", "ci", "runs"]);
+        assert_eq!(request.query["limit"], "5");
+        assert_eq!(request.query["x"], "");
+        assert_eq!(request.body, b"body");
+        assert_eq!(request.headers["host"], "localhost");
+        assert_eq!(request.token(), None);
+
+        let raw = "GET /history HTTP/1.1\r\nauthorization: Bearer abc\r\n\r\n";
+        assert_eq!(
+            Request::read(&mut raw.as_bytes()).unwrap().token(),
+            Some("abc")
+        );
+        let raw = "GET /history?token=def HTTP/1.1\r\n\r\n";
+        assert_eq!(
+            Request::read(&mut raw.as_bytes()).unwrap().token(),
+            Some("def")
+        );
+
+        assert!(Request::read(&mut "hello\r\n\r\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_too_large_request_is_rejected() {
+        let status = |raw: String| {
+            Request::read(&mut raw.as_bytes())
+                .unwrap_err()
+                .downcast_ref::<RequestTooLarge>()
+                .map(|e| e.status)
+        };
+        let long = "a".repeat(MAX_LINE_LEN);
+        assert_eq!(status(format!("GET /{} HTTP/1.1\r\n\r\n", long)), Some(400));
+        assert_eq!(
+            status(format!("GET / HTTP/1.1\r\nx-big: {}\r\n\r\n", long)),
+            Some(431)
+        );
+        let many = "x-a: b\r\n".repeat(MAX_HEADERS + 1);
+        assert_eq!(status(format!("GET / HTTP/1.1\r\n{}\r\n", many)), Some(431));
+
+        let most = "x-a: b\r\n".repeat(MAX_HEADERS);
+        assert!(Request::read(&mut format!("GET / HTTP/1.1\r\n{}\r\n", most).as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_query_is_percent_decoded() {
+        let query = parse_query("token=a%2Bb%3D&msg=hello+world%21&bad=%zz%4&na%6De=1");
+        assert_eq!(query["token"], "a+b=");
+        assert_eq!(query["msg"], "hello world!");
+        assert_eq!(query["bad"], "%zz%4");
+        assert_eq!(query["name"], "1");
+    }
+}
@@ -0,0 +1,892 @@
+mod approvals;
+mod http;
+mod policy;
+mod queue;
+mod webhook;
+
+use self::approvals::ApprovalBoard;
+use self::http::{write_head, write_json, Request, RequestTooLarge};
+pub use self::policy::Policy;
+use self::policy::{Access, Command};
+use self::queue::RunQueue;
+pub use self::webhook::Webhook;
+use super::{History, HistoryRecord, NodeRecord, RunEvent, Runner, WorkflowDelegate};
+use crate::stdlib::{downcast_delegate_ref, RunResult};
+use anyhow::{anyhow, bail};
+use serde::Deserialize;
+use serde_json::json;
+use starlark::environment::Module;
+use starlark::eval::Evaluator;
+use std::collections::BTreeMap;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// the number of history records returned when no limit is given
+const DEFAULT_HISTORY_LIMIT: usize = 20;
+
+// how long a client has to send each part of its request, so a client
+// which stops sending does not hold its thread forever
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+// how much more of a request which can not be read is discarded before the
+// connection is closed, closing with unread input resets the connection
+// and the client would not see why its request failed
+const MAX_DISCARDED_LEN: u64 = 1024 * 1024;
+
+/// The body of a request to decide a manual gate.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Decision {
+    approve: bool,
+}
+
+/// The body of a request to run a workflow.
+#[derive(Debug, Default, Deserialize)]
+struct RunRequest {
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+/// Serves a local HTTP API to run a set of registered workflows and query
+/// their history.
+///
+/// - `GET /workflows` lists the workflows by name.
+/// - `POST /workflows/<name>/runs` runs a workflow with the `args` of the
+///   json body and streams its progress as ndjson events.
+/// - `POST /hooks/<name>` runs a workflow with the variables its webhook
+///   maps from the json payload, streaming its progress like a run.
+/// - `GET /history?workflow=<name>&limit=<n>` returns the latest runs.
+/// - `GET /queue` lists the runs which are queued or running.
+/// - `DELETE /queue/<id>` cancels a queued or running run.
+/// - `GET /approvals` lists the manual gates waiting to be approved.
+/// - `POST /approvals/<id>` approves or rejects a waiting gate with a json
+///   body like `{"approve": true}`, recording the name of the token as
+///   the approver.
+///
+/// The runs of a workflow wait in a queue until fewer of them are running
+/// than its concurrency limit, which is 1 unless it is set.
+///
+/// With a policy every request needs a token which is allowed to do what
+/// it asks, otherwise anyone who can connect can do anything.
+pub struct Server {
+    listener: TcpListener,
+    // the workflows which can be run, by name
+    workflows: BTreeMap<String, PathBuf>,
+    // the webhooks, by the name of the workflow they run
+    webhooks: BTreeMap<String, Webhook>,
+    history: History,
+    policy: Option<Policy>,
+    queue: RunQueue,
+    approvals: Arc<ApprovalBoard>,
+    read_timeout: Duration,
+}
+
+impl Server {
+    /// Listens on `addr` for requests to run the workflows, which are named
+    /// after their file name without its extension.
+    pub fn bind(addr: &str, workflows: &[PathBuf], history: History) -> anyhow::Result<Self> {
+        let mut named = BTreeMap::new();
+        for workflow in workflows {
+            if !workflow.exists() {
+                bail!("Workflow does not exist at path {:?}", workflow);
+            }
+            let name = match workflow.file_stem() {
+                Some(name) => name.to_string_lossy().to_string(),
+                None => bail!("Unable to name the workflow at {:?}", workflow),
+            };
+            if named.contains_key(&name) {
+                bail!("More than one workflow is named '{}'", name);
+            }
+            named.insert(name, std::fs::canonicalize(workflow)?);
+        }
+        Ok(Server {
+            listener: TcpListener::bind(addr)?,
+            workflows: named,
+            webhooks: BTreeMap::new(),
+            history,
+            policy: None,
+            queue: RunQueue::new(),
+            approvals: Arc::new(ApprovalBoard::new()),
+            read_timeout: DEFAULT_READ_TIMEOUT,
+        })
+    }
+
+    /// Sets how many runs of the workflow can run at once.
+    pub fn set_concurrency(&mut self, workflow: &str, limit: usize) -> anyhow::Result<()> {
+        if !self.workflows.contains_key(workflow) {
+            bail!("Concurrency limit for unknown workflow '{}'", workflow);
+        }
+        if limit == 0 {
+            bail!("The concurrency limit of '{}' must be at least 1", workflow);
+        }
+        self.queue.set_limit(workflow, limit);
+        Ok(())
+    }
+
+    /// Sets how long a client can take to send the next part of its
+    /// request before the connection is closed.
+    pub fn with_read_timeout(mut self, read_timeout: Duration) -> Self {
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    /// Only serves requests whose token the policy allows.
+    pub fn with_policy(mut self, policy: Policy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Returns what the request may do, None if it is not authenticated.
+    fn access(&self, request: &Request) -> Option<Access<'_>> {
+        match &self.policy {
+            Some(policy) => policy.access(request.token()),
+            None => Some(Access::Unrestricted),
+        }
+    }
+
+    /// Adds the webhook, failing if its workflow is not served or does not
+    /// have a variable the webhook sets.
+    pub fn add_webhook(&mut self, webhook: Webhook) -> anyhow::Result<()> {
+        let path = match self.workflows.get(&webhook.workflow) {
+            Some(path) => path,
+            None => bail!("Webhook for unknown workflow '{}'", webhook.workflow),
+        };
+        if self.webhooks.contains_key(&webhook.workflow) {
+            bail!("More than one webhook runs workflow '{}'", webhook.workflow);
+        }
+        let settable = settable_variables(path)?;
+        if let Some(variable) = webhook
+            .variables()
+            .find(|v| !settable.iter().any(|s| s == v))
+        {
+            bail!(
+                "Webhook for '{}' sets '{}' which is not a variable of the workflow",
+                webhook.workflow,
+                variable
+            );
+        }
+        self.webhooks.insert(webhook.workflow.clone(), webhook);
+        Ok(())
+    }
+
+    pub fn local_addr(&self) -> anyhow::Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Handles each connection on its own thread until the process is
+    /// stopped.
+    pub fn serve(self) -> anyhow::Result<()> {
+        let server = Arc::new(self);
+        for stream in server.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("warning: unable to accept a connection: {}", e);
+                    continue;
+                }
+            };
+            let server = server.clone();
+            thread::spawn(move || {
+                if let Err(e) = server.handle(stream) {
+                    eprintln!("warning: unable to handle a request: {:#}", e);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    fn handle(&self, stream: TcpStream) -> anyhow::Result<()> {
+        stream.set_read_timeout(Some(self.read_timeout))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut out = stream;
+        match Request::read(&mut reader) {
+            Ok(request) => self.respond(&request, &mut out),
+            Err(e) => {
+                write_json(
+                    &mut out,
+                    e.downcast_ref::<RequestTooLarge>()
+                        .map_or(400, |e| e.status),
+                    &json!({ "error": format!("{:#}", e) }),
+                )?;
+                out.shutdown(Shutdown::Write)?;
+                let _ = io::copy(&mut reader.take(MAX_DISCARDED_LEN), &mut io::sink());
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes the response to the request.
+    fn respond(&self, request: &Request, out: &mut dyn Write) -> anyhow::Result<()> {
+        let access = match self.access(request) {
+            Some(access) => access,
+            None => {
+                let error = "A valid token is required";
+                return Ok(write_json(out, 401, &json!({ "error": error }))?);
+            }
+        };
+        match (request.method.as_str(), request.segments().as_slice()) {
+            ("GET", ["workflows"]) => {
+                let workflows: Vec<_> = self
+                    .workflows
+                    .iter()
+                    .filter(|(name, _)| access.allows(Command::List, name))
+                    .map(|(name, path)| json!({ "name": name, "path": path }))
+                    .collect();
+                Ok(write_json(out, 200, &json!(workflows))?)
+            }
+            ("POST", ["workflows", name, "runs"]) | ("POST", ["hooks", name])
+                if !access.allows(Command::Run, name) =>
+            {
+                forbidden(out, name)
+            }
+            ("POST", ["workflows", name, "runs"]) => {
+                if !self.workflows.contains_key(*name) {
+                    return not_found(out, &format!("No workflow named '{}'", name));
+                }
+                let run: RunRequest = match request.body.is_empty() {
+                    true => RunRequest::default(),
+                    false => match serde_json::from_slice(&request.body) {
+                        Ok(run) => run,
+                        Err(e) => {
+                            let error = format!("Invalid run request: {}", e);
+                            return Ok(write_json(out, 400, &json!({ "error": error }))?);
+                        }
+                    },
+                };
+                self.run(name, run.args, out)
+            }
+            ("POST", ["hooks", name]) => {
+                let webhook = match self.webhooks.get(*name) {
+                    Some(webhook) => webhook,
+                    None => return not_found(out, &format!("No webhook for '{}'", name)),
+                };
+                let args = serde_json::from_slice(&request.body)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|payload| webhook.var_args(&payload));
+                match args {
+                    Ok(args) => self.run(name, args, out),
+                    Err(e) => {
+                        let error = format!("Invalid payload: {:#}", e);
+                        Ok(write_json(out, 400, &json!({ "error": error }))?)
+                    }
+                }
+            }
+            ("GET", ["history"]) => self.write_history(request, &access, out),
+            ("GET", ["queue"]) => {
+                let jobs: Vec<_> = self
+                    .queue
+                    .jobs()
+                    .into_iter()
+                    .filter(|job| access.allows(Command::List, &job.workflow))
+                    .collect();
+                Ok(write_json(out, 200, &serde_json::to_value(jobs)?)?)
+            }
+            ("DELETE", ["queue", id]) => {
+                let job = match id.parse().ok().and_then(|id| self.queue.job(id)) {
+                    Some(job) => job,
+                    None => return not_found(out, &format!("No queued or running run {}", id)),
+                };
+                if !access.allows(Command::Run, &job.workflow) {
+                    return forbidden(out, &job.workflow);
+                }
+                match self.queue.cancel(job.id) {
+                    Some(job) => Ok(write_json(out, 200, &serde_json::to_value(job)?)?),
+                    None => not_found(out, &format!("No queued or running run {}", id)),
+                }
+            }
+            ("GET", ["approvals"]) => {
+                let gates: Vec<_> = self
+                    .approvals
+                    .pending()
+                    .into_iter()
+                    .filter(|gate| access.allows(Command::List, &gate.workflow))
+                    .collect();
+                Ok(write_json(out, 200, &serde_json::to_value(gates)?)?)
+            }
+            ("POST", ["approvals", id]) => {
+                let gate = match id.parse().ok().and_then(|id| self.approvals.gate(id)) {
+                    Some(gate) => gate,
+                    None => return not_found(out, &format!("No manual gate {} is waiting", id)),
+                };
+                if !access.allows(Command::Approve, &gate.workflow) {
+                    return forbidden(out, &gate.workflow);
+                }
+                let decision = match serde_json::from_slice::<Decision>(&request.body) {
+                    Ok(decision) => decision,
+                    Err(e) => {
+                        let error = format!("Invalid decision: {}", e);
+                        return Ok(write_json(out, 400, &json!({ "error": error }))?);
+                    }
+                };
+                match self
+                    .approvals
+                    .decide(gate.id, decision.approve, access.approver())
+                {
+                    Some(gate) => Ok(write_json(out, 200, &serde_json::to_value(gate)?)?),
+                    None => not_found(out, &format!("No manual gate {} is waiting", id)),
+                }
+            }
+            (_, ["workflows"])
+            | (_, ["workflows", _, "runs"])
+            | (_, ["hooks", _])
+            | (_, ["history"])
+            | (_, ["queue"])
+            | (_, ["queue", _])
+            | (_, ["approvals"])
+            | (_, ["approvals", _]) => Ok(write_json(
+                out,
+                405,
+                &json!({ "error": "Method not allowed" }),
+            )?),
+            _ => not_found(out, &format!("No endpoint at {}", request.path)),
+        }
+    }
+
+    /// Queues a run of the workflow, writing an event once it is queued,
+    /// as it starts, after each node and once it finishes. The run is
+    /// recorded in the history unless it is cancelled before it starts.
+    fn run(&self, name: &str, args: Vec<String>, out: &mut dyn Write) -> anyhow::Result<()> {
+        let workflow = &self.workflows[name];
+        write_head(out, 200, "application/x-ndjson")?;
+        // the run carries on if the client goes away
+        let mut connected = true;
+        let mut event = |event: serde_json::Value| {
+            connected = connected && writeln!(out, "{}", event).and_then(|_| out.flush()).is_ok();
+        };
+        let job = self.queue.push(name, args.clone());
+        event(json!({ "event": "queued", "id": job.id }));
+        if !self.queue.wait_turn(job.id) {
+            event(json!({
+                "event": "finished",
+                "succeeded": false,
+                "error": "The run was cancelled before it started",
+            }));
+            return Ok(());
+        }
+        event(json!({ "event": "started", "workflow": workflow, "args": args }));
+
+        let mut record = HistoryRecord::new(workflow.to_path_buf(), args.clone(), None);
+        let artifacts_dir = self.history.new_artifacts_dir();
+        record.artifacts_dir = Some(artifacts_dir.clone());
+        let started = Instant::now();
+        let (sender, progress) = mpsc::channel();
+        let path = workflow.to_path_buf();
+        let approver = Arc::new(self.approvals.for_job(job.id, name));
+        let handle = thread::spawn(move || -> anyhow::Result<RunResult> {
+            let runner = Runner::new(
+                path,
+                WorkflowDelegate::with_args(args)
+                    .with_subscriber(sender)
+                    .with_cancel_token(job.cancel_token)
+                    .with_artifacts_dir(artifacts_dir)
+                    .with_approver(approver),
+            )?;
+            runner.run(None)
+        });
+        // ends once the runner, and its sender, are dropped. The server
+        // reports the start and end of the run itself, along with the job
+        for run_event in progress {
+            if let RunEvent::NodeFinished(node) = run_event {
+                event(json!({ "event": "node", "node": NodeRecord::from(&node) }));
+            }
+        }
+        let result = handle
+            .join()
+            .unwrap_or_else(|_| Err(anyhow!("The run panicked")));
+        self.queue.finish(job.id);
+        record.finish(&result, started.elapsed().as_millis() as u64);
+        if let Err(e) = self.history.append(&record) {
+            eprintln!("Unable to record run history: {:#}", e);
+        }
+
+        event(match &result {
+            Ok(result) => json!({
+                "event": "finished",
+                "succeeded": result.succeeded(),
+                "error": result.error().and_then(|node| node.error.clone()),
+            }),
+            Err(e) => json!({
+                "event": "finished",
+                "succeeded": false,
+                "error": format!("{:#}", e),
+            }),
+        });
+        Ok(())
+    }
+
+    /// Writes the latest runs of the registered workflows, or of the one
+    /// named by the `workflow` query parameter, oldest first.
+    fn write_history(
+        &self,
+        request: &Request,
+        access: &Access,
+        out: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        let paths: Vec<&PathBuf> = match request.query.get("workflow") {
+            Some(name) if !access.allows(Command::History, name) => return forbidden(out, name),
+            Some(name) => match self.workflows.get(name) {
+                Some(path) => vec![path],
+                None => return not_found(out, &format!("No workflow named '{}'", name)),
+            },
+            None => self
+                .workflows
+                .iter()
+                .filter(|(name, _)| access.allows(Command::History, name))
+                .map(|(_, path)| path)
+                .collect(),
+        };
+        let limit = match request.query.get("limit").map(|l| l.parse::<usize>()) {
+            Some(Ok(limit)) => limit,
+            Some(Err(_)) => {
+                return Ok(write_json(
+                    out,
+                    400,
+                    &json!({ "error": "limit must be a number" }),
+                )?)
+            }
+            None => DEFAULT_HISTORY_LIMIT,
+        };
+        let mut records: Vec<HistoryRecord> = self
+            .history
+            .records()?
+            .into_iter()
+            .filter(|r| paths.contains(&&r.workflow))
+            .collect();
+        let skip = records.len().saturating_sub(limit);
+        records.drain(..skip);
+        Ok(write_json(out, 200, &serde_json::to_value(records)?)?)
+    }
+}
+
+/// Parses the workflow and returns the names of the variables which can be
+/// set when running it.
+fn settable_variables(workflow: &Path) -> anyhow::Result<Vec<String>> {
+    let runner = Runner::new(workflow.to_path_buf(), WorkflowDelegate::new())?;
+    let module = Module::new();
+    let mut eval = Evaluator::new(&module);
+    runner.parse_workflow(&mut eval)?;
+    let holder = runner.delegate();
+    match downcast_delegate_ref!(holder, WorkflowDelegate) {
+        Some(delegate) => Ok(delegate.variable_store().settable_names()),
+        None => bail!("Serving a workflow requires a WorkflowDelegate"),
+    }
+}
+
+fn forbidden(out: &mut dyn Write, workflow: &str) -> anyhow::Result<()> {
+    let error = format!("The token is not allowed to do that with '{}'", workflow);
+    Ok(write_json(out, 403, &json!({ "error": error }))?)
+}
+
+fn not_found(out: &mut dyn Write, error: &str) -> anyhow::Result<()> {
+    Ok(write_json(out, 404, &json!({ "error": error }))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib::test_utils::TempWorkflowFile;
+    use crate::stdlib::variable::SECRET_MASK;
+    use std::io::Read;
+    use tempfile::tempdir;
+
+    fn request(method: &str, target: &str, body: &str) -> Request {
+        let raw = format!(
+            "{} {} HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            target,
+            body.len(),
+            body
+        );
+        Request::read(&mut raw.as_bytes()).unwrap()
+    }
+
+    fn respond(server: &Server, request: &Request) -> (String, String) {
+        let mut out = vec![];
+        server.respond(request, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let (head, body) = out.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    }
+
+    #[test]
+    fn test_secrets_are_not_recorded() {
+        let file = TempWorkflowFile::new(
+            "deploy.workflow",
+            r#"
+token = secret(cli_flag = "--token")
+region = variable(default = "us", cli_flag = "--region")
+header = variable(default = "")
+def _header(ctx):
+    return "Bearer " + ctx.stdout
+
+def _check(header):
+    fail("bad header: " + header)
+
+main = workflow(
+    entrypoint = "login",
+    graph = [
+        node(
+            name = "login",
+            action = action(
+                tool = builtin_tool(name = "printf"),
+                args = [token],
+                setters = [setter(implementation = _header, variable = header)],
+            ),
+            next = next(implementation = lambda ctx, args: "deploy")(),
+        ),
+        node(name = "deploy", action = fn_action(implementation = _check, args = [header])),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        let dir = tempdir().unwrap();
+        let history = History::new(dir.path().join("history.jsonl"));
+        let server = Server::bind("127.0.0.1:0", &[file.path()], history).unwrap();
+
+        let run = request(
+            "POST",
+            "/workflows/deploy/runs",
+            r#"{"args": ["--token", "s3cret", "--region", "eu"]}"#,
+        );
+        let (status, body) = respond(&server, &run);
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert!(body.contains("bad header: Bearer [REDACTED]"), "{}", body);
+
+        let recorded = std::fs::read_to_string(dir.path().join("history.jsonl")).unwrap();
+        assert!(!recorded.contains("s3cret"), "{}", recorded);
+        let records = server.history.records().unwrap();
+        assert_eq!(records[0].args, ["--region", "eu"]);
+        let variables = records[0].nodes[0].variables.as_ref().unwrap();
+        assert_eq!(variables["token"].value, SECRET_MASK);
+        assert_eq!(variables["header"].value, "Bearer [REDACTED]");
+    }
+
+    #[test]
+    fn test_run_streams_progress() {
+        let file = TempWorkflowFile::new(
+            "ci.workflow",
+            r#"
+greeting = variable(default = "hi", cli_flag = "--greeting")
+def _check(greeting):
+    return 0 if greeting == "hello" else 1
+
+def _to_b(ctx, args):
+    return "b"
+
+main = workflow(
+    entrypoint = "a",
+    graph = [
+        node(name = "a", action = fn_action(implementation = _check, args = [greeting]), next = next(implementation = _to_b)()),
+        node(name = "b", action = fn_action(implementation = _check, args = [greeting])),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        let dir = tempdir().unwrap();
+        let history = History::new(dir.path().join("history.jsonl"));
+        let server = Server::bind("127.0.0.1:0", &[file.path()], history).unwrap();
+
+        let (status, body) = respond(&server, &request("GET", "/workflows", ""));
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert!(body.starts_with(r#"[{"name":"ci","path":"#));
+
+        let run = request(
+            "POST",
+            "/workflows/ci/runs",
+            r#"{"args": ["--greeting", "hello"]}"#,
+        );
+        let (status, body) = respond(&server, &run);
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let events: Vec<serde_json::Value> = body
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        let kinds: Vec<&str> = events
+            .iter()
+            .map(|e| e["event"].as_str().unwrap())
+            .collect();
+        assert_eq!(kinds, ["queued", "started", "node", "node", "finished"]);
+        assert_eq!(events[3]["node"]["name"], "b");
+        assert_eq!(events[4]["succeeded"], true);
+
+        let (_, body) = respond(&server, &request("GET", "/history?workflow=ci&limit=1", ""));
+        let records: Vec<HistoryRecord> = serde_json::from_str(&body).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].args, ["--greeting", "hello"]);
+
+        let (status, _) = respond(&server, &request("POST", "/workflows/cd/runs", ""));
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+        let (status, _) = respond(&server, &request("DELETE", "/history", ""));
+        assert_eq!(status, "HTTP/1.1 405 Method Not Allowed");
+        let (status, _) = respond(&server, &request("POST", "/workflows/ci/runs", "[1]"));
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+    }
+
+    #[test]
+    fn test_webhook() {
+        let file = TempWorkflowFile::new(
+            "deploy.workflow",
+            r#"
+git_ref = variable()
+region = const(value = "eu")
+def _check(git_ref):
+    return 0 if git_ref == "refs/heads/main" else 1
+
+main = workflow(
+    entrypoint = "a",
+    graph = [node(name = "a", action = fn_action(implementation = _check, args = [git_ref]))],
+)
+"#,
+        )
+        .unwrap();
+        let dir = tempdir().unwrap();
+        let history = History::new(dir.path().join("history.jsonl"));
+        let mut server = Server::bind("127.0.0.1:0", &[file.path()], history).unwrap();
+        let error = |server: &mut Server, spec: &str| {
+            let webhook = Webhook::parse(spec).unwrap();
+            server.add_webhook(webhook).unwrap_err().to_string()
+        };
+        assert_eq!(
+            error(&mut server, "ci:ref=git_ref"),
+            "Webhook for unknown workflow 'ci'"
+        );
+        assert_eq!(
+            error(&mut server, "deploy:region=region"),
+            "Webhook for 'deploy' sets 'region' which is not a variable of the workflow"
+        );
+        server
+            .add_webhook(Webhook::parse("deploy:ref=git_ref").unwrap())
+            .unwrap();
+
+        let hook = request("POST", "/hooks/deploy", r#"{"ref": "refs/heads/main"}"#);
+        let (status, body) = respond(&server, &hook);
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let finished: serde_json::Value =
+            serde_json::from_str(body.lines().last().unwrap()).unwrap();
+        assert_eq!(finished["succeeded"], true);
+
+        let (status, body) = respond(&server, &request("POST", "/hooks/deploy", "{}"));
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+        assert!(body.contains("The payload has no field 'ref' for variable 'git_ref'"));
+        let (status, _) = respond(&server, &request("GET", "/hooks/deploy", ""));
+        assert_eq!(status, "HTTP/1.1 405 Method Not Allowed");
+    }
+
+    #[test]
+    fn test_policy() {
+        let ci = TempWorkflowFile::new("ci.workflow", "").unwrap();
+        let deploy = TempWorkflowFile::new("deploy.workflow", "").unwrap();
+        let dir = tempdir().unwrap();
+        let history = History::new(dir.path().join("history.jsonl"));
+        let policy = Policy::parse(
+            r#"{"tokens": [
+                {"token": "tester", "workflows": ["ci"], "commands": ["run"]},
+                {"token": "viewer", "workflows": ["*"], "commands": ["list", "history"]}
+            ]}"#,
+        )
+        .unwrap();
+        let server = Server::bind("127.0.0.1:0", &[ci.path(), deploy.path()], history)
+            .unwrap()
+            .with_policy(policy);
+        let status = |method: &str, target: &str| respond(&server, &request(method, target, "")).0;
+
+        assert_eq!(status("GET", "/workflows"), "HTTP/1.1 401 Unauthorized");
+        assert_eq!(
+            status("GET", "/workflows?token=nope"),
+            "HTTP/1.1 401 Unauthorized"
+        );
+        assert_eq!(
+            status("POST", "/workflows/ci/runs?token=tester"),
+            "HTTP/1.1 200 OK"
+        );
+        assert_eq!(
+            status("POST", "/workflows/deploy/runs?token=tester"),
+            "HTTP/1.1 403 Forbidden"
+        );
+        assert_eq!(
+            status("POST", "/workflows/ci/runs?token=viewer"),
+            "HTTP/1.1 403 Forbidden"
+        );
+        assert_eq!(
+            status("GET", "/history?workflow=ci&token=tester"),
+            "HTTP/1.1 403 Forbidden"
+        );
+
+        let (_, body) = respond(&server, &request("GET", "/history?token=viewer", ""));
+        let records: Vec<HistoryRecord> = serde_json::from_str(&body).unwrap();
+        assert_eq!(records.len(), 1);
+        let (_, body) = respond(&server, &request("GET", "/workflows?token=tester", ""));
+        assert_eq!(body, "[]");
+    }
+
+    #[test]
+    fn test_cancel_running_job() {
+        let file = TempWorkflowFile::new(
+            "slow.workflow",
+            r#"
+main = workflow(
+    entrypoint = "wait",
+    graph = [node(name = "wait", action = action(tool = builtin_tool(name = "sleep"), args = ["30"]))],
+)
+"#,
+        )
+        .unwrap();
+        let dir = tempdir().unwrap();
+        let history = History::new(dir.path().join("history.jsonl"));
+        let server = Arc::new(Server::bind("127.0.0.1:0", &[file.path()], history).unwrap());
+        let running = {
+            let server = server.clone();
+            thread::spawn(move || respond(&server, &request("POST", "/workflows/slow/runs", "")))
+        };
+        while !respond(&server, &request("GET", "/queue", ""))
+            .1
+            .contains("running")
+        {
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        let (status, body) = respond(&server, &request("DELETE", "/queue/1", ""));
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert!(body.contains(r#""workflow":"slow""#));
+        let (_, body) = running.join().unwrap();
+        let finished: serde_json::Value =
+            serde_json::from_str(body.lines().last().unwrap()).unwrap();
+        assert_eq!(finished["error"], "The run was cancelled");
+        assert_eq!(respond(&server, &request("GET", "/queue", "")).1, "[]");
+        let (status, _) = respond(&server, &request("DELETE", "/queue/1", ""));
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+    }
+
+    #[test]
+    fn test_approve_manual_gate() {
+        let file = TempWorkflowFile::new(
+            "cd.workflow",
+            r#"
+def _noop():
+    return None
+
+main = workflow(
+    entrypoint = "approve",
+    graph = [
+        manual_gate(
+            name = "approve",
+            message = "Deploy to prod?",
+            next = next(implementation = lambda ctx, args: "deploy")(),
+        ),
+        node(name = "deploy", action = fn_action(implementation = _noop)),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        let dir = tempdir().unwrap();
+        let history = History::new(dir.path().join("history.jsonl"));
+        let policy = Policy::parse(
+            r#"{"tokens": [
+                {"token": "runner", "workflows": ["cd"], "commands": ["list", "run"]},
+                {"token": "a", "name": "alice", "workflows": ["cd"], "commands": ["approve"]}
+            ]}"#,
+        )
+        .unwrap();
+        let server = Arc::new(
+            Server::bind("127.0.0.1:0", &[file.path()], history)
+                .unwrap()
+                .with_policy(policy),
+        );
+        let running = {
+            let server = server.clone();
+            let run = request("POST", "/workflows/cd/runs?token=runner", "");
+            thread::spawn(move || respond(&server, &run))
+        };
+        let pending = request("GET", "/approvals?token=runner", "");
+        while respond(&server, &pending).1 == "[]" {
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let (_, body) = respond(&server, &pending);
+        assert!(body.contains(r#""gate":"approve""#));
+        assert!(body.contains(r#""message":"Deploy to prod?""#));
+
+        let (status, _) = respond(
+            &server,
+            &request("POST", "/approvals/1?token=runner", r#"{"approve": true}"#),
+        );
+        assert_eq!(status, "HTTP/1.1 403 Forbidden");
+        let (status, _) = respond(
+            &server,
+            &request(
+                "POST",
+                "/approvals/1?token=a",
+                r#"{"approve": true, "by": "mallory"}"#,
+            ),
+        );
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+        let decide = request("POST", "/approvals/1?token=a", r#"{"approve": true}"#);
+        assert_eq!(respond(&server, &decide).0, "HTTP/1.1 200 OK");
+        let (_, body) = running.join().unwrap();
+        assert!(body.lines().last().unwrap().contains(r#""succeeded":true"#));
+        assert_eq!(respond(&server, &decide).0, "HTTP/1.1 404 Not Found");
+
+        let records = server.history.records().unwrap();
+        let approval = records[0].nodes[0].approval.as_ref().unwrap();
+        assert_eq!(approval.by, "alice");
+    }
+
+    #[test]
+    fn test_serve_over_tcp() {
+        let file = TempWorkflowFile::new("ci.workflow", "").unwrap();
+        let dir = tempdir().unwrap();
+        let history = History::new(dir.path().join("history.jsonl"));
+        let server = Server::bind("127.0.0.1:0", &[file.path()], history).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET /nowhere HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.ends_with(r#"{"error":"No endpoint at /nowhere"}"#));
+    }
+
+    #[test]
+    fn test_oversized_header_is_rejected() {
+        let file = TempWorkflowFile::new("ci.workflow", "").unwrap();
+        let dir = tempdir().unwrap();
+        let history = History::new(dir.path().join("history.jsonl"));
+        let server = Server::bind("127.0.0.1:0", &[file.path()], history).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let header = "a".repeat(64 * 1024);
+        write!(stream, "GET /history HTTP/1.1\r\nx-big: {}\r\n\r\n", header).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+    }
+
+    #[test]
+    fn test_client_which_stops_sending_times_out() {
+        let file = TempWorkflowFile::new("ci.workflow", "").unwrap();
+        let dir = tempdir().unwrap();
+        let history = History::new(dir.path().join("history.jsonl"));
+        let server = Server::bind("127.0.0.1:0", &[file.path()], history)
+            .unwrap()
+            .with_read_timeout(Duration::from_millis(100));
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET /history HTTP/1.1\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+}
@@ -0,0 +1,184 @@
+use anyhow::{anyhow, bail};
+use serde::Deserialize;
+use std::path::Path;
+
+/// What a request asks the server to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Command {
+    /// List the workflows and their queued runs.
+    List,
+    /// Run a workflow, directly or through its webhook, and cancel its runs.
+    Run,
+    /// Read the history of a workflow.
+    History,
+    /// Approve or reject the manual gates of a workflow.
+    Approve,
+}
+
+/// The workflows a token can use and what it can do with them. A workflow
+/// of `*` grants every workflow. The name is who the token belongs to,
+/// which is recorded as the approver of the gates it decides.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Grant {
+    token: String,
+    #[serde(default)]
+    name: Option<String>,
+    workflows: Vec<String>,
+    commands: Vec<Command>,
+}
+
+impl Grant {
+    fn allows(&self, command: Command, workflow: &str) -> bool {
+        self.commands.contains(&command) && self.workflows.iter().any(|w| w == "*" || w == workflow)
+    }
+}
+
+/// Maps the tokens requests authenticate with to what they may do, read
+/// from a json file like
+/// `{"tokens": [{"token": "...", "workflows": ["ci"], "commands": ["run"]}]}`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    tokens: Vec<Grant>,
+}
+
+/// What a request may do.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access<'p> {
+    /// The server has no policy, anything goes.
+    Unrestricted,
+    Granted(&'p Grant),
+}
+
+impl Access<'_> {
+    pub fn allows(&self, command: Command, workflow: &str) -> bool {
+        match self {
+            Access::Unrestricted => true,
+            Access::Granted(grant) => grant.allows(command, workflow),
+        }
+    }
+
+    /// Returns who approves the gates the request decides.
+    pub fn approver(&self) -> &str {
+        match self {
+            Access::Unrestricted => "anonymous",
+            Access::Granted(grant) => grant.name.as_deref().unwrap_or_default(),
+        }
+    }
+}
+
+/// Compares the tokens in time which does not depend on where they differ.
+fn same_token(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+impl Policy {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Unable to read policy file {:?}: {}", path, e))?;
+        Policy::parse(&contents).map_err(|e| anyhow!("Invalid policy file {:?}: {:#}", path, e))
+    }
+
+    pub fn parse(json: &str) -> anyhow::Result<Self> {
+        let policy: Policy = serde_json::from_str(json)?;
+        for (index, grant) in policy.tokens.iter().enumerate() {
+            if grant.token.is_empty() {
+                bail!("token {} is empty", index + 1);
+            }
+            if policy.tokens[..index]
+                .iter()
+                .any(|g| g.token == grant.token)
+            {
+                bail!("token {} is listed more than once", index + 1);
+            }
+            let named = grant.name.as_ref().is_some_and(|name| !name.is_empty());
+            if grant.commands.contains(&Command::Approve) && !named {
+                bail!("token {} can approve but has no name", index + 1);
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Returns what the token may do, None if it is missing or unknown.
+    pub fn access(&self, token: Option<&str>) -> Option<Access<'_>> {
+        let token = token?;
+        self.tokens
+            .iter()
+            .find(|grant| same_token(&grant.token, token))
+            .map(Access::Granted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_access() {
+        let policy = Policy::parse(
+            r#"{"tokens": [
+                {"token": "deployer", "workflows": ["deploy"], "commands": ["run", "history"]},
+                {"token": "viewer", "workflows": ["*"], "commands": ["list", "history"]}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(policy.access(None), None);
+        assert_eq!(policy.access(Some("deploy")), None);
+
+        let deployer = policy.access(Some("deployer")).unwrap();
+        assert!(deployer.allows(Command::Run, "deploy"));
+        assert!(!deployer.allows(Command::Run, "ci"));
+        assert!(!deployer.allows(Command::List, "deploy"));
+        let viewer = policy.access(Some("viewer")).unwrap();
+        assert!(viewer.allows(Command::History, "ci"));
+        assert!(!viewer.allows(Command::Run, "ci"));
+        assert!(Access::Unrestricted.allows(Command::Run, "ci"));
+        assert_eq!(Access::Unrestricted.approver(), "anonymous");
+    }
+
+    #[test]
+    fn test_approver() {
+        let policy = Policy::parse(
+            r#"{"tokens": [
+                {"token": "a", "name": "alice", "workflows": ["deploy"], "commands": ["approve"]}
+            ]}"#,
+        )
+        .unwrap();
+        let alice = policy.access(Some("a")).unwrap();
+        assert!(alice.allows(Command::Approve, "deploy"));
+        assert!(!alice.allows(Command::Run, "deploy"));
+        assert_eq!(alice.approver(), "alice");
+    }
+
+    #[test]
+    fn test_invalid_policy() {
+        let error = |json: &str| Policy::parse(json).unwrap_err().to_string();
+        assert_eq!(
+            error(r#"{"tokens": [{"token": "", "workflows": [], "commands": []}]}"#),
+            "token 1 is empty"
+        );
+        assert_eq!(
+            error(
+                r#"{"tokens": [
+                    {"token": "a", "workflows": [], "commands": []},
+                    {"token": "a", "workflows": [], "commands": []}
+                ]}"#
+            ),
+            "token 2 is listed more than once"
+        );
+        assert_eq!(
+            error(r#"{"tokens": [{"token": "a", "workflows": [], "commands": ["approve"]}]}"#),
+            "token 1 can approve but has no name"
+        );
+        assert!(
+            error(r#"{"tokens": [{"token": "a", "workflows": [], "commands": ["rm"]}]}"#)
+                .starts_with("unknown variant `rm`")
+        );
+    }
+}
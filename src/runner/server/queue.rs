@@ -0,0 +1,197 @@
+use crate::stdlib::cancel::CancelToken;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Condvar, Mutex};
+
+// the runs of a workflow without a limit are serialized
+const DEFAULT_LIMIT: usize = 1;
+
+/// Whether a job is waiting for its turn or running.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Running,
+}
+
+/// A run which was requested and has not finished.
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub id: u64,
+    pub workflow: String,
+    pub args: Vec<String>,
+    pub state: JobState,
+    #[serde(skip)]
+    pub cancel_token: CancelToken,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    next_id: u64,
+    // in the order they were queued
+    jobs: Vec<Job>,
+    limits: BTreeMap<String, usize>,
+}
+
+impl QueueState {
+    /// Whether the job can start, which is when fewer runs of its workflow
+    /// than the limit are running and no earlier run of it is queued.
+    fn may_start(&self, job: &Job) -> bool {
+        let limit = self
+            .limits
+            .get(&job.workflow)
+            .copied()
+            .unwrap_or(DEFAULT_LIMIT);
+        let same: Vec<&Job> = self
+            .jobs
+            .iter()
+            .filter(|j| j.workflow == job.workflow)
+            .collect();
+        let running = same.iter().filter(|j| j.state == JobState::Running).count();
+        let first_queued = same.iter().find(|j| j.state == JobState::Queued);
+        running < limit && first_queued.map(|j| j.id) == Some(job.id)
+    }
+}
+
+/// Queues the runs of each workflow so that no more of them run at once
+/// than the workflow's limit, starting them in the order they were queued.
+#[derive(Debug, Default)]
+pub struct RunQueue {
+    state: Mutex<QueueState>,
+    changed: Condvar,
+}
+
+impl RunQueue {
+    pub fn new() -> Self {
+        RunQueue::default()
+    }
+
+    /// Sets how many runs of the workflow can run at once.
+    pub fn set_limit(&self, workflow: &str, limit: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.limits.insert(workflow.to_string(), limit);
+        self.changed.notify_all();
+    }
+
+    /// Queues a run of the workflow and returns its job.
+    pub fn push(&self, workflow: &str, args: Vec<String>) -> Job {
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+        let job = Job {
+            id: state.next_id,
+            workflow: workflow.to_string(),
+            args,
+            state: JobState::Queued,
+            cancel_token: CancelToken::new(),
+        };
+        state.jobs.push(job.clone());
+        job
+    }
+
+    /// Blocks until the job can start and marks it as running. Returns
+    /// false if it was cancelled while it was queued.
+    pub fn wait_turn(&self, id: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let job = match state.jobs.iter().find(|j| j.id == id) {
+                Some(job) => job.clone(),
+                None => return false,
+            };
+            if state.may_start(&job) {
+                let job = state.jobs.iter_mut().find(|j| j.id == id).unwrap();
+                job.state = JobState::Running;
+                return true;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    /// Removes the job once it has finished, so the next can start.
+    pub fn finish(&self, id: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.jobs.retain(|j| j.id != id);
+        self.changed.notify_all();
+    }
+
+    /// Cancels the job, a queued job is removed and a running one stops
+    /// before its next node. Returns the job, None if there is no such job.
+    pub fn cancel(&self, id: u64) -> Option<Job> {
+        let mut state = self.state.lock().unwrap();
+        let job = state.jobs.iter().find(|j| j.id == id)?.clone();
+        job.cancel_token.cancel();
+        if job.state == JobState::Queued {
+            state.jobs.retain(|j| j.id != id);
+            self.changed.notify_all();
+        }
+        Some(job)
+    }
+
+    pub fn job(&self, id: u64) -> Option<Job> {
+        let state = self.state.lock().unwrap();
+        state.jobs.iter().find(|j| j.id == id).cloned()
+    }
+
+    /// Returns the jobs in the order they were queued.
+    pub fn jobs(&self) -> Vec<Job> {
+        self.state.lock().unwrap().jobs.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn states(queue: &RunQueue) -> Vec<(u64, JobState)> {
+        queue.jobs().iter().map(|j| (j.id, j.state)).collect()
+    }
+
+    #[test]
+    fn test_limits() {
+        let queue = RunQueue::new();
+        queue.set_limit("test", 2);
+        let deploys = [queue.push("deploy", vec![]), queue.push("deploy", vec![])];
+        let tests = [queue.push("test", vec![]), queue.push("test", vec![])];
+        assert!(queue.wait_turn(deploys[0].id));
+        assert!(queue.wait_turn(tests[0].id));
+        assert!(queue.wait_turn(tests[1].id));
+        assert_eq!(
+            states(&queue),
+            [
+                (1, JobState::Running),
+                (2, JobState::Queued),
+                (3, JobState::Running),
+                (4, JobState::Running),
+            ]
+        );
+
+        let queue = Arc::new(queue);
+        let waiting = {
+            let queue = queue.clone();
+            thread::spawn(move || queue.wait_turn(2))
+        };
+        queue.finish(deploys[0].id);
+        assert!(waiting.join().unwrap());
+        assert_eq!(queue.job(2).unwrap().state, JobState::Running);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let queue = Arc::new(RunQueue::new());
+        let running = queue.push("deploy", vec![]);
+        let queued = queue.push("deploy", vec![]);
+        assert!(queue.wait_turn(running.id));
+        let waiting = {
+            let queue = queue.clone();
+            thread::spawn(move || queue.wait_turn(queued.id))
+        };
+
+        assert!(queue.cancel(queued.id).is_some());
+        assert!(!waiting.join().unwrap());
+        assert!(queue.cancel(running.id).is_some());
+        assert!(running.cancel_token.is_cancelled());
+        assert_eq!(states(&queue), [(1, JobState::Running)]);
+        assert!(queue.cancel(7).is_none());
+    }
+}
@@ -0,0 +1,140 @@
+use anyhow::bail;
+use serde_json::Value;
+
+/// Runs a workflow when its route is posted to, setting variables from the
+/// fields of the json payload. Parsed from `<workflow>:<field>=<variable>`
+/// with a mapping for each variable separated by commas, e.g.
+/// `ci:ref=git_ref,repository.name=repo`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Webhook {
+    pub workflow: String,
+    // the dotted path of a field in the payload and the qualified name of
+    // the variable it sets
+    mappings: Vec<(String, String)>,
+}
+
+impl Webhook {
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        let invalid = |reason: &str| anyhow::anyhow!("Invalid webhook '{}', {}", spec, reason);
+        let (workflow, mappings) = match spec.split_once(':') {
+            Some((workflow, mappings)) if !workflow.is_empty() => (workflow, mappings),
+            _ => return Err(invalid("expected <workflow>:<field>=<variable>,...")),
+        };
+        let mut parsed: Vec<(String, String)> = vec![];
+        for mapping in mappings.split(',') {
+            let (field, variable) = match mapping.split_once('=') {
+                Some((field, variable)) if !variable.is_empty() => (field, variable),
+                _ => {
+                    return Err(invalid(&format!(
+                        "expected <field>=<variable> in '{}'",
+                        mapping
+                    )))
+                }
+            };
+            if field.split('.').any(|part| part.is_empty()) {
+                return Err(invalid(&format!("'{}' is not a field path", field)));
+            }
+            if parsed.iter().any(|(_, v)| v == variable) {
+                return Err(invalid(&format!("variable '{}' is set twice", variable)));
+            }
+            parsed.push((field.to_string(), variable.to_string()));
+        }
+        Ok(Webhook {
+            workflow: workflow.to_string(),
+            mappings: parsed,
+        })
+    }
+
+    /// The qualified names of the variables the webhook sets.
+    pub fn variables(&self) -> impl Iterator<Item = &str> {
+        self.mappings.iter().map(|(_, variable)| variable.as_str())
+    }
+
+    /// Returns the `--var` args which set the variables from the payload.
+    /// Fails if a field is missing or holds something other than a string,
+    /// number or bool, as a variable can only hold one of those.
+    pub fn var_args(&self, payload: &Value) -> anyhow::Result<Vec<String>> {
+        let mut args = vec![];
+        for (field, variable) in &self.mappings {
+            let value = field
+                .split('.')
+                .try_fold(payload, |value, part| value.get(part));
+            let value = match value {
+                Some(Value::String(s)) => s.clone(),
+                Some(Value::Number(n)) => n.to_string(),
+                Some(Value::Bool(b)) => b.to_string(),
+                Some(other) => bail!(
+                    "The payload field '{}' for variable '{}' must be a string, number or bool, got {}",
+                    field,
+                    variable,
+                    other
+                ),
+                None => bail!(
+                    "The payload has no field '{}' for variable '{}'",
+                    field,
+                    variable
+                ),
+            };
+            args.push("--var".to_string());
+            args.push(format!("{}={}", variable, value));
+        }
+        Ok(args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_var_args() {
+        let webhook = Webhook::parse("ci:ref=git_ref,repository.id=build.repo_id").unwrap();
+        assert_eq!(webhook.workflow, "ci");
+        assert_eq!(
+            webhook.variables().collect::<Vec<_>>(),
+            ["git_ref", "build.repo_id"]
+        );
+        let payload = json!({"ref": "refs/heads/main", "repository": {"id": 42}});
+        assert_eq!(
+            webhook.var_args(&payload).unwrap(),
+            [
+                "--var",
+                "git_ref=refs/heads/main",
+                "--var",
+                "build.repo_id=42"
+            ]
+        );
+
+        let error = |payload: Value| webhook.var_args(&payload).unwrap_err().to_string();
+        assert_eq!(
+            error(json!({"repository": {"id": 42}})),
+            "The payload has no field 'ref' for variable 'git_ref'"
+        );
+        assert_eq!(
+            error(json!({"ref": ["main"], "repository": {"id": 42}})),
+            r#"The payload field 'ref' for variable 'git_ref' must be a string, number or bool, got ["main"]"#
+        );
+    }
+
+    #[test]
+    fn test_invalid_webhooks() {
+        let error = |spec: &str| Webhook::parse(spec).unwrap_err().to_string();
+        assert_eq!(
+            error("ci"),
+            "Invalid webhook 'ci', expected <workflow>:<field>=<variable>,..."
+        );
+        assert_eq!(
+            error("ci:ref"),
+            "Invalid webhook 'ci:ref', expected <field>=<variable> in 'ref'"
+        );
+        assert_eq!(
+            error("ci:a..b=x"),
+            "Invalid webhook 'ci:a..b=x', 'a..b' is not a field path"
+        );
+        assert_eq!(
+            error("ci:a=x,b=x"),
+            "Invalid webhook 'ci:a=x,b=x', variable 'x' is set twice"
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use crate::runner::HistoryRecord;
+use std::path::Path;
+
+/// Statistics for a single node aggregated across all of the runs
+/// of a workflow stored in the history.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeStats {
+    pub name: String,
+    pub runs: usize,
+    pub failures: usize,
+    /// The duration of every run of the node, sorted ascending.
+    durations_ms: Vec<u64>,
+}
+
+impl NodeStats {
+    fn new(name: &str) -> Self {
+        NodeStats {
+            name: name.to_string(),
+            runs: 0,
+            failures: 0,
+            durations_ms: vec![],
+        }
+    }
+
+    pub fn failure_rate(&self) -> f64 {
+        if self.runs == 0 {
+            return 0.0;
+        }
+        self.failures as f64 / self.runs as f64
+    }
+
+    /// Returns the duration at the given percentile (0-100) using the
+    /// nearest-rank method.
+    pub fn duration_percentile(&self, percentile: f64) -> Option<u64> {
+        if self.durations_ms.is_empty() {
+            return None;
+        }
+        let rank = ((percentile / 100.0) * self.durations_ms.len() as f64).ceil() as usize;
+        let index = rank.clamp(1, self.durations_ms.len()) - 1;
+        Some(self.durations_ms[index])
+    }
+
+    /// A node is considered flaky if it fails more often than the threshold.
+    pub fn is_flaky(&self, threshold: f64) -> bool {
+        self.failure_rate() > threshold
+    }
+}
+
+/// Aggregates the history records for the given workflow into per node
+/// statistics. Nodes are returned in the order they were first seen.
+pub fn node_stats(records: &[HistoryRecord], workflow: &Path) -> Vec<NodeStats> {
+    let mut stats: Vec<NodeStats> = vec![];
+    for record in records.iter().filter(|r| r.workflow == workflow) {
+        for node in &record.nodes {
+            let index = match stats.iter().position(|s| s.name == node.name) {
+                Some(index) => index,
+                None => {
+                    stats.push(NodeStats::new(&node.name));
+                    stats.len() - 1
+                }
+            };
+            let entry = &mut stats[index];
+            entry.runs += 1;
+            if !node.succeeded() {
+                entry.failures += 1;
+            }
+            entry.durations_ms.push(node.duration_ms);
+        }
+    }
+
+    for entry in &mut stats {
+        entry.durations_ms.sort();
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::NodeRecord;
+    use std::path::PathBuf;
+
+    fn record(workflow: &str, nodes: Vec<(&str, i32, u64)>) -> HistoryRecord {
+        let mut record = HistoryRecord::new(PathBuf::from(workflow), vec![], None);
+        record.nodes = nodes
+            .into_iter()
+            .map(|(name, exit_code, duration_ms)| NodeRecord {
+                name: name.to_string(),
+                duration_ms,
+                exit_code: Some(exit_code),
+                exit_code_ok: None,
+                error: None,
+                variables: None,
+                envs: vec![],
+                artifacts: vec![],
+                cached: false,
+                approval: None,
+            })
+            .collect();
+        record
+    }
+
+    #[test]
+    fn test_node_stats_aggregates_runs() {
+        let records = vec![
+            record("/a.workflow", vec![("build", 0, 10), ("test", 1, 100)]),
+            record("/a.workflow", vec![("build", 0, 20), ("test", 0, 200)]),
+            record("/b.workflow", vec![("build", 1, 30)]),
+        ];
+        let stats = node_stats(&records, Path::new("/a.workflow"));
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].name, "build");
+        assert_eq!(stats[0].runs, 2);
+        assert_eq!(stats[0].failures, 0);
+        assert_eq!(stats[1].name, "test");
+        assert_eq!(stats[1].failure_rate(), 0.5);
+    }
+
+    #[test]
+    fn test_duration_percentile() {
+        let records = (1..=10)
+            .map(|i| record("/a.workflow", vec![("a", 0, i * 10)]))
+            .collect::<Vec<_>>();
+        let stats = node_stats(&records, Path::new("/a.workflow"));
+        assert_eq!(stats[0].duration_percentile(50.0), Some(50));
+        assert_eq!(stats[0].duration_percentile(90.0), Some(90));
+        assert_eq!(stats[0].duration_percentile(100.0), Some(100));
+        assert_eq!(stats[0].duration_percentile(0.0), Some(10));
+    }
+
+    #[test]
+    fn test_is_flaky() {
+        let records = vec![
+            record("/a.workflow", vec![("a", 0, 0)]),
+            record("/a.workflow", vec![("a", 0, 0)]),
+            record("/a.workflow", vec![("a", 0, 0)]),
+            record("/a.workflow", vec![("a", 1, 0)]),
+        ];
+        let stats = node_stats(&records, Path::new("/a.workflow"));
+        assert!(stats[0].is_flaky(0.1));
+        assert!(!stats[0].is_flaky(0.25));
+    }
+
+    #[test]
+    fn test_no_durations() {
+        assert_eq!(NodeStats::new("a").duration_percentile(50.0), None);
+        assert_eq!(NodeStats::new("a").failure_rate(), 0.0);
+    }
+}
@@ -0,0 +1,167 @@
+use super::lint::source::{Source, TokenKind};
+use starlark::syntax::{AstModule, Dialect};
+use std::fmt;
+
+/// A problem found while parsing a workflow file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    pub file: String,
+    /// 1-based
+    pub line: usize,
+    /// 1-based, in chars
+    pub column: usize,
+    /// How many chars of the line the error covers.
+    pub len: usize,
+    pub message: String,
+    /// The line of the file the error is on.
+    pub source_line: String,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let gutter = " ".repeat(self.line.to_string().len());
+        writeln!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.line, self.source_line)?;
+        write!(
+            f,
+            "{} | {}{}",
+            gutter,
+            " ".repeat(self.column - 1),
+            "^".repeat(self.len.max(1))
+        )
+    }
+}
+
+/// Returns where each top level statement starts, as byte offsets. A
+/// statement starts at an ident in the first column which does not
+/// continue the statement before it.
+fn statement_starts(source: &Source) -> Vec<usize> {
+    let mut starts = vec![0];
+    for token in source.tokens() {
+        let continues = matches!(token.text.as_str(), "elif" | "else");
+        if token.column == 0 && token.kind == TokenKind::Ident && !continues && token.start > 0 {
+            starts.push(token.start);
+        }
+    }
+    starts
+}
+
+/// Returns the innermost bracket which is not closed, as a line, column
+/// and the bracket.
+fn unclosed_bracket(source: &Source) -> Option<(usize, usize, String)> {
+    let mut open = vec![];
+    for token in source.tokens() {
+        if token.kind != TokenKind::Other {
+            continue;
+        }
+        match token.text.as_str() {
+            "(" | "[" | "{" => open.push(token),
+            ")" | "]" | "}" => {
+                open.pop();
+            }
+            _ => {}
+        }
+    }
+    open.last().map(|t| (t.line, t.column + 1, t.text.clone()))
+}
+
+fn parse_error(file: &str, text: &str, e: starlark::Error) -> SyntaxError {
+    let (line, column, len) = match e.span() {
+        Some(span) => {
+            let span = span.resolve_span();
+            let len = match span.end.line == span.begin.line {
+                true => span.end.column.saturating_sub(span.begin.column),
+                false => 1,
+            };
+            (span.begin.line + 1, span.begin.column + 1, len)
+        }
+        None => (1, 1, 1),
+    };
+    new_error(file, text, line, column, len, e.kind().to_string())
+}
+
+fn new_error(
+    file: &str,
+    text: &str,
+    line: usize,
+    column: usize,
+    len: usize,
+    message: String,
+) -> SyntaxError {
+    SyntaxError {
+        file: file.to_string(),
+        line,
+        column,
+        len,
+        message,
+        source_line: text.lines().nth(line - 1).unwrap_or_default().to_string(),
+    }
+}
+
+/// Parses the workflow file and returns its syntax errors. The parser
+/// stops at the first error, so once there is one each top level
+/// statement is parsed on its own to find the errors in the others too.
+pub fn syntax_errors(file: &str, text: &str) -> Vec<SyntaxError> {
+    let first = match AstModule::parse(file, text.to_string(), &Dialect::Standard) {
+        Ok(_) => return vec![],
+        Err(e) => parse_error(file, text, e),
+    };
+
+    let starts = statement_starts(&Source::new(text));
+    let mut errors = vec![];
+    for (i, start) in starts.iter().enumerate() {
+        let end = starts.get(i + 1).copied().unwrap_or(text.len());
+        // padded so the lines match those of the file
+        let padding = "\n".repeat(text[..*start].matches('\n').count());
+        let statement = format!("{}{}", padding, &text[*start..end]);
+        if let Err(e) = AstModule::parse(file, statement.clone(), &Dialect::Standard) {
+            errors.push(match unclosed_bracket(&Source::new(&statement)) {
+                Some((line, column, bracket)) => new_error(
+                    file,
+                    text,
+                    line,
+                    column,
+                    1,
+                    format!("'{}' is never closed", bracket),
+                ),
+                None => parse_error(file, text, e),
+            });
+        }
+    }
+    // the error depends on more than one statement
+    if errors.is_empty() {
+        errors.push(first);
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_errors() {
+        assert_eq!(
+            syntax_errors("a.workflow", "x = 1\ndef f():\n    pass\n"),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_independent_errors() {
+        let text = "a = 1 +\nb = (1,\n\nc = 3\nif a:\n    pass\nelse:\n    d = ]\n";
+        let errors = syntax_errors("a.workflow", text);
+        let positions: Vec<_> = errors.iter().map(|e| (e.line, e.column)).collect();
+        assert_eq!(positions, [(1, 8), (2, 5), (8, 9)]);
+        assert_eq!(errors[1].message, "'(' is never closed");
+        assert_eq!(
+            errors[1].to_string(),
+            "a.workflow:2:5: '(' is never closed\n  |\n2 | b = (1,\n  |     ^"
+        );
+    }
+}
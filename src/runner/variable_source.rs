@@ -0,0 +1,132 @@
+use crate::stdlib::VariableEntry;
+use std::fmt;
+use std::sync::Arc;
+
+/// A source of values for variables, e.g. a key value store, which is
+/// consulted when the variables are realized after parsing.
+pub trait VariableSource: Send + Sync {
+    /// The name of the source, shown in describe and in warnings.
+    fn name(&self) -> &str;
+
+    /// Returns the value of the variable or None if the source does not
+    /// know about it. Sources will usually look the variable up by its
+    /// `qualified_name()`.
+    fn lookup(&self, variable: &VariableEntry) -> anyhow::Result<Option<String>>;
+}
+
+/// Where a custom source sits in the chain of sources. The built in
+/// sources are checked in the order `--var`/cli_flag, env and then the
+/// default value, the first source with a value wins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SourcePosition {
+    BeforeCli,
+    BeforeEnv,
+    BeforeDefault,
+    /// Only used for variables without a default value.
+    AfterDefault,
+}
+
+/// A step in the chain of sources used to realize a variable.
+#[derive(Clone)]
+pub(crate) enum SourceStep {
+    Cli,
+    Env,
+    Default,
+    Custom(Arc<dyn VariableSource>),
+}
+
+impl fmt::Debug for SourceStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceStep::Cli => write!(f, "Cli"),
+            SourceStep::Env => write!(f, "Env"),
+            SourceStep::Default => write!(f, "Default"),
+            SourceStep::Custom(source) => write!(f, "Custom({})", source.name()),
+        }
+    }
+}
+
+/// The custom variable sources, kept in the order they were added.
+#[derive(Clone, Default)]
+pub struct VariableSources {
+    sources: Vec<(SourcePosition, Arc<dyn VariableSource>)>,
+}
+
+impl fmt::Debug for VariableSources {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.sources.iter().map(|(p, s)| (p, s.name())))
+            .finish()
+    }
+}
+
+impl VariableSources {
+    pub fn new() -> Self {
+        VariableSources::default()
+    }
+
+    /// Adds a source at the given position. Sources added at the same
+    /// position are checked in the order they were added.
+    pub fn add(&mut self, position: SourcePosition, source: Arc<dyn VariableSource>) {
+        self.sources.push((position, source));
+    }
+
+    fn at(&self, position: SourcePosition) -> impl Iterator<Item = SourceStep> + '_ {
+        self.sources
+            .iter()
+            .filter(move |(p, _)| *p == position)
+            .map(|(_, s)| SourceStep::Custom(s.clone()))
+    }
+
+    /// Returns the full chain of sources in the order they are checked.
+    pub(crate) fn chain(&self) -> Vec<SourceStep> {
+        let mut chain: Vec<SourceStep> = self.at(SourcePosition::BeforeCli).collect();
+        chain.push(SourceStep::Cli);
+        chain.extend(self.at(SourcePosition::BeforeEnv));
+        chain.push(SourceStep::Env);
+        chain.extend(self.at(SourcePosition::BeforeDefault));
+        chain.push(SourceStep::Default);
+        chain.extend(self.at(SourcePosition::AfterDefault));
+        chain
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Named(&'static str);
+
+    impl VariableSource for Named {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn lookup(&self, _variable: &VariableEntry) -> anyhow::Result<Option<String>> {
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn test_chain_order() {
+        let mut sources = VariableSources::new();
+        sources.add(SourcePosition::AfterDefault, Arc::new(Named("kv")));
+        sources.add(SourcePosition::BeforeCli, Arc::new(Named("override")));
+        sources.add(SourcePosition::BeforeEnv, Arc::new(Named("a")));
+        sources.add(SourcePosition::BeforeEnv, Arc::new(Named("b")));
+
+        let chain: Vec<String> = sources.chain().iter().map(|s| format!("{:?}", s)).collect();
+        assert_eq!(
+            chain,
+            vec![
+                "Custom(override)",
+                "Cli",
+                "Custom(a)",
+                "Custom(b)",
+                "Env",
+                "Default",
+                "Custom(kv)",
+            ]
+        );
+    }
+}
@@ -1,26 +1,153 @@
+use super::arguments::WorkflowArgument;
+use super::prompt::Prompter;
+use super::variable_source::{SourceStep, VariableSources};
+use crate::stdlib::ir::VariableIr;
+use crate::stdlib::variable::SECRET_MASK;
+use crate::stdlib::variable_resolver::{VariableResolverError, VariableSnapshot};
 use crate::stdlib::{ValueUpdatedBy, VariableEntry};
+use anyhow::bail;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, Default, PartialEq)]
 pub struct VariableStore {
     vars: RefCell<HashMap<String, VariableEntry>>,
+    // identifiers of the deprecated variables which have been warned about
+    warned: RefCell<HashSet<String>>,
+    deprecation_warnings: RefCell<Vec<String>>,
+    // the values once the variables were realized, before any node ran
+    initial: RefCell<HashMap<String, String>>,
 }
 
 impl VariableStore {
     pub fn new() -> Self {
-        VariableStore {
-            vars: HashMap::new().into(),
+        VariableStore::default()
+    }
+
+    /// Warns that a deprecated variable is used, once per variable.
+    fn warn_if_deprecated(&self, identifier: &str, var: &VariableEntry) {
+        if let Some(warning) = var.deprecation_warning() {
+            if self.warned.borrow_mut().insert(identifier.to_string()) {
+                eprintln!("warning: {}", warning);
+                self.deprecation_warnings.borrow_mut().push(warning);
+            }
+        }
+    }
+
+    /// The deprecation warnings shown so far.
+    pub fn deprecation_warnings(&self) -> Vec<String> {
+        self.deprecation_warnings.borrow().clone()
+    }
+
+    /// Returns the qualified names of the deprecated variables along with
+    /// their hints.
+    pub fn deprecated_variables(&self) -> Vec<(String, String)> {
+        let mut deprecated: Vec<(String, String)> = self
+            .vars
+            .borrow()
+            .values()
+            .filter_map(|var| {
+                var.deprecated().map(|hint| {
+                    (
+                        var.qualified_name()
+                            .unwrap_or_else(|| "<unnamed>".to_string()),
+                        hint,
+                    )
+                })
+            })
+            .collect();
+        deprecated.sort();
+        deprecated
+    }
+
+    /// Returns the qualified names of the variables which can be set, i.e.
+    /// the named variables which are not consts.
+    pub fn settable_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .vars
+            .borrow()
+            .values()
+            .filter(|var| !var.is_const())
+            .filter_map(|var| var.qualified_name())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Returns the values of the secrets, sorted.
+    pub fn secret_values(&self) -> Vec<String> {
+        let mut values: Vec<String> = self
+            .vars
+            .borrow()
+            .values()
+            .filter(|var| var.is_secret())
+            .filter_map(|var| var.value())
+            .filter(|value| !value.is_empty())
+            .collect();
+        values.sort();
+        values
+    }
+
+    /// Sets the required variables which have no value to the answer
+    /// `prompter` gives, failing with the names of all of them if there is
+    /// no prompter.
+    pub fn prompt_for_required(&self, prompter: Option<&dyn Prompter>) -> anyhow::Result<()> {
+        let mut missing: Vec<(String, String, String)> = self
+            .vars
+            .borrow()
+            .iter()
+            .filter(|(_, var)| var.is_required() && var.value().is_none())
+            .map(|(identifier, var)| {
+                let name = var
+                    .qualified_name()
+                    .unwrap_or_else(|| "<unnamed>".to_string());
+                (name, identifier.clone(), var.prompt())
+            })
+            .collect();
+        missing.sort();
+        let prompter = match prompter {
+            Some(prompter) => prompter,
+            None if missing.is_empty() => return Ok(()),
+            None => {
+                let names: Vec<&str> = missing.iter().map(|(name, _, _)| name.as_str()).collect();
+                bail!(
+                    "required variables have no value: {}, set them with --var",
+                    names.join(", ")
+                )
+            }
+        };
+        for (name, identifier, prompt) in missing {
+            let value = prompter
+                .ask(&prompt)
+                .map_err(|e| e.context(format!("Unable to ask for variable '{}'", name)))?;
+            if value.is_empty() {
+                bail!("required variable '{}' was given no value", name);
+            }
+            if let Some(var) = self.vars.borrow_mut().get_mut(&identifier) {
+                var.update_value(value.clone(), ValueUpdatedBy::Prompt);
+            }
+            // the answer is the value the run starts with
+            self.initial.borrow_mut().insert(identifier, value);
         }
+        Ok(())
     }
 
     pub fn register_variable(&self, identifier: &str, var: VariableEntry) {
         self.vars.borrow_mut().insert(identifier.to_string(), var);
     }
 
+    /// Returns the value the variable was realized with, before any node
+    /// set it.
+    pub fn initial_variable_value(&self, identifier: &str) -> Option<String> {
+        self.initial.borrow().get(identifier).cloned()
+    }
+
     pub fn get_variable_value<'a>(&self, identifier: &str) -> Option<String> {
-        let vars = self.vars.borrow();
-        vars.get(identifier).map(|v| v.value()).flatten().clone()
+        let mut vars = self.vars.borrow_mut();
+        let var = vars.get_mut(identifier)?;
+        var.refresh_from_live_env();
+        self.warn_if_deprecated(identifier, var);
+        var.value()
     }
 
     pub fn update_variable_value<'a>(
@@ -28,11 +155,182 @@ impl VariableStore {
         identifier: &str,
         value: String,
         updated_by: ValueUpdatedBy,
-    ) {
+    ) -> anyhow::Result<()> {
         let mut vars = self.vars.borrow_mut();
         if let Some(var) = vars.get_mut(identifier) {
+            if var.is_const() {
+                bail!(var.const_error());
+            }
             var.update_value(value, updated_by);
         }
+        Ok(())
+    }
+
+    /// Fails if the command line tries to set a const.
+    pub fn check_consts(&self, workflow_args: &[String]) -> anyhow::Result<()> {
+        for var in self.vars.borrow().values() {
+            if var.is_const() && var.is_set_by_var_arg(workflow_args) {
+                bail!(var.const_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails on the first workflow argument which sets no variable, e.g. a
+    /// misspelt flag, instead of ignoring it.
+    pub fn check_args(&self, workflow_args: &[String]) -> anyhow::Result<()> {
+        let names = self.settable_names();
+        let flags: HashSet<String> = self
+            .vars
+            .borrow()
+            .values()
+            .filter(|var| !var.is_const())
+            .filter_map(|var| var.cli_flag())
+            .collect();
+        let mut iter = workflow_args.iter();
+        while let Some(arg) = iter.next() {
+            let assignment = match arg.strip_prefix("--var=") {
+                Some(assignment) => Some(assignment),
+                None if arg == "--var" => match iter.next() {
+                    Some(assignment) => Some(assignment.as_str()),
+                    None => bail!("--var needs a value of the form <name>=<value>"),
+                },
+                None => None,
+            };
+            if let Some(assignment) = assignment {
+                match assignment.split_once('=') {
+                    Some((name, _)) if names.iter().any(|n| n == name) => continue,
+                    Some((name, _)) => bail!(
+                        "'{}' is not a variable which can be set with --var, see --help",
+                        name
+                    ),
+                    None => bail!(
+                        "--var needs a value of the form <name>=<value>, got '{}'",
+                        assignment
+                    ),
+                }
+            }
+            if !flags.contains(arg) {
+                bail!(
+                    "unknown argument '{}', see --help for the arguments of the workflow",
+                    arg
+                );
+            }
+            if iter.next().is_none() {
+                bail!("the argument '{}' needs a value", arg);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the command line arguments which set the variables, sorted.
+    /// Variables with a `cli_flag` are set with it, the others with --var.
+    pub fn arguments(&self) -> Vec<WorkflowArgument> {
+        let mut arguments: Vec<WorkflowArgument> = self
+            .vars
+            .borrow()
+            .values()
+            .filter(|var| !var.is_const())
+            .filter_map(|var| {
+                let usage = match (var.cli_flag(), var.qualified_name()) {
+                    (Some(flag), _) => format!("{} <VALUE>", flag),
+                    (None, Some(name)) => format!("--var {}=<VALUE>", name),
+                    (None, None) => return None,
+                };
+                Some(WorkflowArgument {
+                    usage,
+                    doc: var.doc(),
+                    env: var.env(),
+                    default: var.default_value(),
+                    required: var.is_required(),
+                })
+            })
+            .collect();
+        arguments.sort_by(|a, b| a.usage.cmp(&b.usage));
+        arguments
+    }
+
+    /// Compiles the variables bound to a name, sorted by it. The others can
+    /// not be used by a workflow which compiles.
+    pub fn compile_variables(&self) -> anyhow::Result<Vec<VariableIr>> {
+        let mut variables = self
+            .vars
+            .borrow()
+            .values()
+            .filter(|var| var.qualified_name().is_some())
+            .map(|var| var.compile())
+            .collect::<anyhow::Result<Vec<VariableIr>>>()?;
+        variables.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(variables)
+    }
+
+    /// Updates the values of several variables at once. If any of the
+    /// variables is unknown none of the values are updated.
+    pub fn update_variable_values(
+        &self,
+        updates: Vec<(String, String)>,
+        updated_by: ValueUpdatedBy,
+    ) -> anyhow::Result<()> {
+        let mut vars = self.vars.borrow_mut();
+        for (identifier, _) in &updates {
+            match vars.get(identifier) {
+                Some(var) if var.is_const() => bail!(var.const_error()),
+                Some(_) => (),
+                None => bail!(VariableResolverError::UnknownVariable(identifier.clone())),
+            }
+        }
+        for (identifier, value) in updates {
+            if let Some(var) = vars.get_mut(&identifier) {
+                var.update_value(value, updated_by.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the values of the named variables which have a value. The
+    /// values of the secrets are masked since snapshots are recorded.
+    pub fn snapshot(&self) -> VariableSnapshot {
+        self.vars
+            .borrow()
+            .values()
+            .filter_map(|var| {
+                let mut value_ctx = var.value_ctx()?;
+                if var.is_secret() {
+                    value_ctx.value = SECRET_MASK.to_string();
+                }
+                Some((var.qualified_name()?, value_ctx))
+            })
+            .collect()
+    }
+
+    /// Returns the values of the named variables which have a value, the
+    /// secrets included.
+    pub fn values(&self) -> VariableSnapshot {
+        self.vars
+            .borrow()
+            .values()
+            .filter_map(|var| Some((var.qualified_name()?, var.value_ctx()?)))
+            .collect()
+    }
+
+    /// Sets the values, and how they were set, of the variables in the
+    /// snapshot. Variables which are not in the snapshot keep their value,
+    /// as do the secrets whose values are masked in it.
+    pub fn restore(&self, snapshot: &VariableSnapshot) {
+        for var in self.vars.borrow_mut().values_mut() {
+            if var.is_const() || var.is_secret() {
+                continue;
+            }
+            if let Some(ctx) = var.qualified_name().and_then(|name| snapshot.get(&name)) {
+                var.update_value(ctx.value.clone(), ctx.updated_by.clone());
+            }
+        }
+    }
+
+    pub fn set_variable_name(&self, identifier: &str, name: &str) {
+        if let Some(var) = self.vars.borrow_mut().get_mut(identifier) {
+            var.set_name(name);
+        }
     }
 
     pub fn with_variable<F>(&self, name: &str, f: F)
@@ -46,24 +344,77 @@ impl VariableStore {
     }
 
     pub fn realize_variables(&self, workflow_args: &Vec<String>) {
+        self.realize_variables_with_sources(workflow_args, &VariableSources::new())
+    }
+
+    /// Sets the value of every variable from the first source in the chain
+    /// which has a value for it.
+    pub fn realize_variables_with_sources(
+        &self,
+        workflow_args: &Vec<String>,
+        sources: &VariableSources,
+    ) {
+        let chain = sources.chain();
         let mut vars = self.vars.borrow_mut();
-        for var in vars.values_mut() {
-            // First, check to see if there is a command line flag that matches
-            if var.try_update_value_from_cli_flag(workflow_args).is_ok() {
+        for (identifier, var) in vars.iter_mut() {
+            if var.is_const() {
                 continue;
             }
-            // Next,  try to set the value from the env
-            if var.try_update_value_from_env().is_ok() {
-                continue;
+            for step in &chain {
+                let found = match step {
+                    // An explicit `--var group.name=value` takes precedence
+                    // over a matching command line flag
+                    SourceStep::Cli => {
+                        var.try_update_value_from_var_arg(workflow_args).is_ok()
+                            || var.try_update_value_from_cli_flag(workflow_args).is_ok()
+                    }
+                    SourceStep::Env => var.try_update_value_from_env().is_ok(),
+                    SourceStep::Default => {
+                        if var.value().is_some() {
+                            break;
+                        }
+                        false
+                    }
+                    SourceStep::Custom(source) => match source.lookup(var) {
+                        Ok(Some(value)) => {
+                            var.update_value(
+                                value,
+                                ValueUpdatedBy::Source(source.name().to_string()),
+                            );
+                            true
+                        }
+                        Ok(None) => false,
+                        Err(e) => {
+                            eprintln!(
+                                "warning: variable source '{}' failed: {:#}",
+                                source.name(),
+                                e
+                            );
+                            false
+                        }
+                    },
+                };
+                if found {
+                    // setting a deprecated variable is a use of it
+                    self.warn_if_deprecated(identifier, var);
+                    break;
+                }
             }
         }
+        *self.initial.borrow_mut() = vars
+            .iter()
+            .filter_map(|(identifier, var)| Some((identifier.clone(), var.value()?)))
+            .collect();
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::runner::{SourcePosition, VariableSource};
     use crate::stdlib::test_utils::TempEnvVar;
+    use crate::stdlib::{EnvMode, ValueContext};
+    use std::sync::Arc;
 
     #[test]
     fn test_register_variable() {
@@ -80,7 +431,9 @@ mod tests {
         let store = VariableStore::new();
         let var = VariableEntry::for_test(None, None, None);
         store.register_variable("123", var);
-        store.update_variable_value("123", "new value".into(), ValueUpdatedBy::ForTest);
+        store
+            .update_variable_value("123", "new value".into(), ValueUpdatedBy::ForTest)
+            .unwrap();
         let var = store.get_variable_value("123");
         assert_eq!(var, Some("new value".to_string()));
     }
@@ -113,4 +466,365 @@ mod tests {
         );
         assert_eq!(store.get_variable_value("3"), Some("bar_value".to_string()));
     }
+
+    struct MapSource(HashMap<&'static str, &'static str>);
+
+    impl VariableSource for MapSource {
+        fn name(&self) -> &str {
+            "map"
+        }
+
+        fn lookup(&self, variable: &VariableEntry) -> anyhow::Result<Option<String>> {
+            Ok(variable
+                .qualified_name()
+                .and_then(|name| self.0.get(name.as_str()).map(|v| v.to_string())))
+        }
+    }
+
+    fn named(default: Option<&str>, cli_flag: Option<&str>, name: &str) -> VariableEntry {
+        let mut var = VariableEntry::for_test(default, cli_flag, None);
+        var.set_name(name);
+        var
+    }
+
+    #[test]
+    fn test_realize_with_sources() {
+        let source = Arc::new(MapSource(HashMap::from([
+            ("a", "kv"),
+            ("b", "kv"),
+            ("c", "kv"),
+        ])));
+        let store = VariableStore::new();
+        store.register_variable("a", named(None, Some("--a"), "a"));
+        store.register_variable("b", named(Some("default"), None, "b"));
+        store.register_variable("c", named(None, None, "c"));
+
+        let mut sources = VariableSources::new();
+        sources.add(SourcePosition::AfterDefault, source.clone());
+        store.realize_variables_with_sources(&vec!["--a".to_string(), "cli".to_string()], &sources);
+        assert_eq!(store.get_variable_value("a"), Some("cli".to_string()));
+        assert_eq!(store.get_variable_value("b"), Some("default".to_string()));
+        assert_eq!(store.get_variable_value("c"), Some("kv".to_string()));
+
+        let store = VariableStore::new();
+        store.register_variable("a", named(None, Some("--a"), "a"));
+        store.register_variable("b", named(Some("default"), None, "b"));
+        let mut sources = VariableSources::new();
+        sources.add(SourcePosition::BeforeCli, source);
+        store.realize_variables_with_sources(&vec!["--a".to_string(), "cli".to_string()], &sources);
+        assert_eq!(store.get_variable_value("a"), Some("kv".to_string()));
+        assert_eq!(store.get_variable_value("b"), Some("kv".to_string()));
+        store.with_variable("b", |v| {
+            assert_eq!(
+                v.value_ctx().unwrap().updated_by,
+                ValueUpdatedBy::Source("map".to_string())
+            )
+        });
+    }
+
+    #[test]
+    fn test_update_variable_values_is_all_or_nothing() {
+        let store = VariableStore::new();
+        store.register_variable("1", VariableEntry::for_test(Some("a"), None, None));
+        store.register_variable("2", VariableEntry::for_test(Some("b"), None, None));
+
+        let updated_by = ValueUpdatedBy::Action("".to_string());
+        let err = store
+            .update_variable_values(
+                vec![
+                    ("1".to_string(), "x".to_string()),
+                    ("missing".to_string(), "y".to_string()),
+                ],
+                updated_by.clone(),
+            )
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Unknown variable with id 'missing'");
+        assert_eq!(store.get_variable_value("1"), Some("a".to_string()));
+
+        store
+            .update_variable_values(
+                vec![
+                    ("1".to_string(), "x".to_string()),
+                    ("2".to_string(), "y".to_string()),
+                ],
+                updated_by,
+            )
+            .unwrap();
+        assert_eq!(store.get_variable_value("1"), Some("x".to_string()));
+        assert_eq!(store.get_variable_value("2"), Some("y".to_string()));
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let store = VariableStore::new();
+        store.register_variable("1", named(Some("a"), None, "a"));
+        store.register_variable("2", named(None, None, "b"));
+        // unnamed variables can not be restored so are left out
+        store.register_variable("3", VariableEntry::for_test(Some("c"), None, None));
+        store
+            .update_variable_values(
+                vec![("1".to_string(), "x".to_string())],
+                ValueUpdatedBy::Action("".to_string()),
+            )
+            .unwrap();
+
+        let snapshot = store.snapshot();
+        assert_eq!(snapshot.keys().collect::<Vec<_>>(), vec!["a"]);
+
+        let restored = VariableStore::new();
+        restored.register_variable("4", named(Some("a"), None, "a"));
+        restored.register_variable("5", named(Some("b"), None, "b"));
+        restored.restore(&snapshot);
+        restored.with_variable("4", |v| {
+            assert_eq!(
+                v.value_ctx().unwrap(),
+                ValueContext {
+                    value: "x".to_string(),
+                    updated_by: ValueUpdatedBy::Action("".to_string()),
+                }
+            )
+        });
+        assert_eq!(restored.get_variable_value("5"), Some("b".to_string()));
+    }
+
+    #[test]
+    fn test_secrets_are_masked_in_snapshots() {
+        let store = VariableStore::new();
+        store.register_variable("1", named(Some("hunter2"), None, "token").into_secret());
+
+        let snapshot = store.snapshot();
+        assert_eq!(snapshot["token"].value, SECRET_MASK);
+        assert_eq!(store.values()["token"].value, "hunter2");
+
+        let restored = VariableStore::new();
+        restored.register_variable("2", named(Some("s3cret"), None, "token").into_secret());
+        restored.restore(&snapshot);
+        assert_eq!(restored.get_variable_value("2"), Some("s3cret".to_string()));
+    }
+
+    #[test]
+    fn test_consts_can_not_be_set() {
+        let store = VariableStore::new();
+        let mut var = VariableEntry::constant("1.0".to_string());
+        var.set_name("version");
+        store.register_variable("1", var);
+
+        let err = store
+            .update_variable_value("1", "2.0".to_string(), ValueUpdatedBy::ForTest)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "cannot set const 'version'");
+        assert!(store
+            .update_variable_values(
+                vec![("1".to_string(), "2.0".to_string())],
+                ValueUpdatedBy::ForTest
+            )
+            .is_err());
+
+        let args = vec!["--var".to_string(), "version=2.0".to_string()];
+        assert_eq!(
+            store.check_consts(&args).unwrap_err().to_string(),
+            "cannot set const 'version'"
+        );
+        assert!(store.check_consts(&[]).is_ok());
+        store.realize_variables(&args);
+        assert_eq!(store.get_variable_value("1"), Some("1.0".to_string()));
+    }
+
+    #[test]
+    fn test_check_args() {
+        let store = VariableStore::new();
+        let mut var = VariableEntry::for_test(None, Some("--profile"), None);
+        var.set_name("profile");
+        store.register_variable("1", var);
+        let mut var = VariableEntry::constant("1.0".to_string());
+        var.set_name("version");
+        store.register_variable("2", var);
+
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+        store.check_args(&[]).unwrap();
+        store
+            .check_args(&args(&["--profile", "release", "--var=profile=debug"]))
+            .unwrap();
+        store
+            .check_args(&args(&["--var", "profile=debug"]))
+            .unwrap();
+
+        let err = |a: &[&str]| store.check_args(&args(a)).unwrap_err().to_string();
+        assert_eq!(
+            err(&["--profle", "release"]),
+            "unknown argument '--profle', see --help for the arguments of the workflow"
+        );
+        assert_eq!(
+            err(&["release"]),
+            "unknown argument 'release', see --help for the arguments of the workflow"
+        );
+        assert_eq!(
+            err(&["--profile"]),
+            "the argument '--profile' needs a value"
+        );
+        assert_eq!(
+            err(&["--var", "region=eu"]),
+            "'region' is not a variable which can be set with --var, see --help"
+        );
+        assert_eq!(
+            err(&["--var=version=2.0"]),
+            "'version' is not a variable which can be set with --var, see --help"
+        );
+        assert_eq!(
+            err(&["--var", "profile"]),
+            "--var needs a value of the form <name>=<value>, got 'profile'"
+        );
+        assert_eq!(
+            err(&["--var"]),
+            "--var needs a value of the form <name>=<value>"
+        );
+    }
+
+    #[test]
+    fn test_arguments() {
+        let store = VariableStore::new();
+        let mut var = VariableEntry::for_test(Some("debug"), Some("--profile"), Some("PROFILE"))
+            .with_doc(Some("The build profile"))
+            .unwrap();
+        var.set_name("profile");
+        store.register_variable("1", var);
+        let mut var = VariableEntry::for_test(None, None, None)
+            .with_required(true, None)
+            .unwrap();
+        var.set_name("region");
+        store.register_variable("2", var);
+        store.register_variable("3", VariableEntry::for_test(None, None, None));
+        let mut var = VariableEntry::constant("1.0".to_string());
+        var.set_name("version");
+        store.register_variable("4", var);
+
+        assert_eq!(
+            store.arguments(),
+            vec![
+                WorkflowArgument {
+                    usage: "--profile <VALUE>".to_string(),
+                    doc: Some("The build profile".to_string()),
+                    env: Some("PROFILE".to_string()),
+                    default: Some("debug".to_string()),
+                    required: false,
+                },
+                WorkflowArgument {
+                    usage: "--var region=<VALUE>".to_string(),
+                    doc: None,
+                    env: None,
+                    default: None,
+                    required: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_initial_value_is_kept() {
+        let store = VariableStore::new();
+        store.register_variable("1", VariableEntry::for_test(Some("default"), None, None));
+        store.realize_variables(&vec![]);
+        store
+            .update_variable_value("1", "new value".into(), ValueUpdatedBy::ForTest)
+            .unwrap();
+        assert_eq!(store.get_variable_value("1"), Some("new value".to_string()));
+        assert_eq!(
+            store.initial_variable_value("1"),
+            Some("default".to_string())
+        );
+    }
+
+    #[test]
+    fn test_live_env_is_read_on_each_resolve() {
+        let key = "ENV_VAR_FOR_test_live_env_is_read_on_each_resolve";
+        let store = VariableStore::new();
+        let mut var = VariableEntry::for_test(None, None, Some(key));
+        var.set_env_mode(EnvMode::Live);
+        store.register_variable("1", var);
+        store.register_variable("2", VariableEntry::for_test(None, None, Some(key)));
+
+        let env = TempEnvVar::new(key, "before");
+        store.realize_variables(&vec![]);
+        drop(env);
+
+        let _env = TempEnvVar::new(key, "after");
+        assert_eq!(store.get_variable_value("1"), Some("after".to_string()));
+        assert_eq!(store.get_variable_value("2"), Some("before".to_string()));
+    }
+
+    #[test]
+    fn test_deprecated_variable_warns_once() {
+        let store = VariableStore::new();
+        let mut var = VariableEntry::for_test(Some("a"), Some("--old"), None);
+        var.set_name("old_url");
+        var.set_deprecated("use db_url instead");
+        store.register_variable("1", var);
+        store.register_variable("2", VariableEntry::for_test(Some("b"), None, None));
+
+        assert_eq!(
+            store.deprecated_variables(),
+            vec![("old_url".to_string(), "use db_url instead".to_string())]
+        );
+
+        store.realize_variables(&vec!["--old".to_string(), "x".to_string()]);
+        store.get_variable_value("1");
+        store.get_variable_value("2");
+        assert_eq!(
+            store.deprecation_warnings(),
+            vec!["variable 'old_url' is deprecated: use db_url instead"]
+        );
+    }
+
+    #[derive(Debug)]
+    struct Answers(Vec<&'static str>);
+
+    impl Prompter for Answers {
+        fn ask(&self, prompt: &str) -> anyhow::Result<String> {
+            let index = self.0.iter().position(|a| a.starts_with(prompt)).unwrap();
+            Ok(self.0[index][prompt.len()..].to_string())
+        }
+    }
+
+    #[test]
+    fn test_prompt_for_required() {
+        let store = VariableStore::new();
+        let required = |name: &str, default: Option<&str>, prompt: Option<&str>| {
+            let mut var = VariableEntry::for_test(default, None, None)
+                .with_required(true, prompt)
+                .unwrap();
+            var.set_name(name);
+            var
+        };
+        store.register_variable("1", required("host", None, Some("Which host?")));
+        store.register_variable("2", required("port", None, None));
+        store.register_variable("3", required("user", Some("me"), None));
+        store.realize_variables(&vec![]);
+
+        let err = store.prompt_for_required(None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "required variables have no value: host, port, set them with --var"
+        );
+
+        let answers = Answers(vec!["Which host?example.com", "Value for port"]);
+        let err = store.prompt_for_required(Some(&answers)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "required variable 'port' was given no value"
+        );
+
+        let answers = Answers(vec!["Which host?example.com", "Value for port8080"]);
+        store.prompt_for_required(Some(&answers)).unwrap();
+        assert_eq!(store.get_variable_value("2"), Some("8080".to_string()));
+        assert_eq!(
+            store.initial_variable_value("1"),
+            Some("example.com".to_string())
+        );
+        let mut updated_by = None;
+        store.with_variable("1", |v| updated_by = v.value_ctx().map(|c| c.updated_by));
+        assert_eq!(updated_by, Some(ValueUpdatedBy::Prompt));
+
+        // nothing is left to ask for
+        store.prompt_for_required(None).unwrap();
+    }
 }
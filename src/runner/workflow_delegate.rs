@@ -1,18 +1,69 @@
-use super::VariableStore;
-use crate::stdlib::variable_resolver::VariableResolver;
+use super::{
+    EventBus, EventHandler, NodeCache, Prompter, RunEvent, SourcePosition, VariableSource,
+    VariableSources, VariableStore,
+};
+use crate::stdlib::approval::{Approval, ApprovalRequest, Approver};
+use crate::stdlib::cancel::CancelToken;
+use crate::stdlib::env_capture::EnvCapture;
+use crate::stdlib::env_policy::EnvPolicy;
+use crate::stdlib::failure_injection::{FailureInjection, FailureInjector};
+use crate::stdlib::inline_file::ScratchDir;
+use crate::stdlib::node_cache::CachedNode;
+use crate::stdlib::redact::Redactor;
+use crate::stdlib::run_context::{RunContext, RunHost};
 use crate::stdlib::variable_resolver::VariableUpdater;
-use crate::stdlib::ParseDelegate;
+use crate::stdlib::variable_resolver::{VariableResolver, VariableSnapshot};
 use crate::stdlib::ValueUpdatedBy;
 use crate::stdlib::VariableEntry;
+use crate::stdlib::{ActionStatus, NodeResult, ParseDelegate, RunDelegate};
 use anyhow::bail;
 use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[derive(Debug)]
 pub struct WorkflowDelegate {
     workflow_file: RefCell<Option<PathBuf>>,
     variable_store: VariableStore,
     workflow_args: Vec<String>,
+    variable_sources: VariableSources,
+    // if set action args are checked for newlines and NUL bytes
+    check_args: bool,
+    // where inline files are written, removed with the delegate
+    scratch_dir: ScratchDir,
+    env_capture: EnvCapture,
+    // set from the workflow's redact_patterns once it is parsed
+    redactor: RefCell<Option<Arc<Redactor>>>,
+    // the redactor which also redacts the values of the secrets, rebuilt
+    // once one of them changes
+    secret_redactor: RefCell<Option<(Vec<String>, Arc<Redactor>)>>,
+    // set from the workflow's env_policy once it is parsed
+    env_policy: RefCell<Option<Arc<EnvPolicy>>>,
+    // the reporters of the run
+    events: EventBus,
+    cancel_token: Option<CancelToken>,
+    // makes tools fail at random instead of running
+    failure_injector: Option<Arc<FailureInjector>>,
+    artifacts_dir: Option<PathBuf>,
+    // where the output of every action is logged
+    log_dir: Option<PathBuf>,
+    // set by the runner if the run works in a sandbox
+    sandbox_dir: RefCell<Option<PathBuf>>,
+    // the nodes which collected their artifacts in this run
+    collected: RefCell<BTreeSet<String>>,
+    // if set the output of the actions is not shown
+    quiet: bool,
+    // the outputs of the cached nodes on their last successful run
+    node_cache: Option<NodeCache>,
+    // asks for the approval of the manual gates
+    approver: Option<Arc<dyn Approver>>,
+    // asks for the required variables which have no value
+    prompter: Option<Arc<dyn Prompter>>,
+    // the approvals of the manual gates which ran, by gate name
+    approvals: RefCell<BTreeMap<String, Approval>>,
 }
 
 impl WorkflowDelegate {
@@ -21,13 +72,183 @@ impl WorkflowDelegate {
     }
 
     pub fn with_args(args: Vec<String>) -> Self {
+        #[allow(unused_mut)]
+        let mut variable_sources = VariableSources::new();
+        #[cfg(feature = "plugins")]
+        for plugin in super::registered_plugins() {
+            for (position, source) in plugin.variable_sources() {
+                variable_sources.add(position, source);
+            }
+        }
         return WorkflowDelegate {
             workflow_file: None.into(),
             variable_store: VariableStore::new(),
             workflow_args: args,
+            variable_sources,
+            check_args: false,
+            scratch_dir: ScratchDir::new(),
+            env_capture: EnvCapture::default(),
+            redactor: None.into(),
+            secret_redactor: None.into(),
+            env_policy: None.into(),
+            events: EventBus::new(),
+            cancel_token: None,
+            failure_injector: None,
+            artifacts_dir: None,
+            log_dir: None,
+            sandbox_dir: None.into(),
+            collected: RefCell::new(BTreeSet::new()),
+            quiet: false,
+            node_cache: None,
+            approver: None,
+            prompter: None,
+            approvals: RefCell::new(BTreeMap::new()),
         };
     }
 
+    /// Adds a custom source which is consulted, at the given position in the
+    /// chain, when the variables are realized after parsing.
+    pub fn with_variable_source(
+        mut self,
+        position: SourcePosition,
+        source: Arc<dyn VariableSource>,
+    ) -> Self {
+        self.variable_sources.add(position, source);
+        self
+    }
+
+    /// Checks the resolved args of every action for newlines and NUL bytes
+    /// before it runs.
+    pub fn with_check_args(mut self, enabled: bool) -> Self {
+        self.check_args = enabled;
+        self
+    }
+
+    /// Sets how much of the environment of each spawned tool is recorded.
+    pub fn with_env_capture(mut self, env_capture: EnvCapture) -> Self {
+        self.env_capture = env_capture;
+        self
+    }
+
+    /// Sends the events of the run to `subscriber`.
+    pub fn with_subscriber(mut self, subscriber: Sender<RunEvent>) -> Self {
+        self.events.add_subscriber(subscriber);
+        self
+    }
+
+    /// Calls `handler` with the events of the run as they are published.
+    pub fn with_handler(mut self, handler: EventHandler) -> Self {
+        self.events.add_handler(handler);
+        self
+    }
+
+    /// Sends the event to the subscribers of the run and the registered
+    /// plugins.
+    pub fn publish(&self, event: RunEvent) {
+        #[cfg(feature = "plugins")]
+        for plugin in super::registered_plugins() {
+            plugin.on_event(&event);
+        }
+        self.events.publish(event);
+    }
+
+    /// Lets the run be cancelled with `cancel_token`.
+    pub fn with_cancel_token(mut self, cancel_token: CancelToken) -> Self {
+        self.cancel_token = Some(cancel_token);
+        self
+    }
+
+    /// Makes tools fail at random instead of running, as the injection
+    /// picks them.
+    pub fn with_failure_injection(mut self, injection: FailureInjection) -> Self {
+        self.failure_injector = Some(Arc::new(FailureInjector::new(injection)));
+        self
+    }
+
+    /// Copies the artifacts of each node which succeeds into `dir`.
+    pub fn with_artifacts_dir(mut self, dir: PathBuf) -> Self {
+        self.artifacts_dir = Some(dir);
+        self
+    }
+
+    /// Writes the output of every action to log files in `dir`, by node and
+    /// attempt.
+    pub fn with_log_dir(mut self, dir: PathBuf) -> Self {
+        self.log_dir = Some(dir);
+        self
+    }
+
+    /// Hides the output of every action from the terminal, it is still
+    /// collected for the setters.
+    pub fn with_quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Skips the cached nodes which are unchanged since their last
+    /// successful run recorded in `node_cache`.
+    pub fn with_node_cache(mut self, node_cache: NodeCache) -> Self {
+        self.node_cache = Some(node_cache);
+        self
+    }
+
+    /// Asks `approver` for the approval of the manual gates.
+    pub fn with_approver(mut self, approver: Arc<dyn Approver>) -> Self {
+        self.approver = Some(approver);
+        self
+    }
+
+    /// Asks `prompter` for the values of the required variables which have
+    /// none when the run starts.
+    pub fn with_prompter(mut self, prompter: Arc<dyn Prompter>) -> Self {
+        self.prompter = Some(prompter);
+        self
+    }
+
+    /// Asks for the values of the required variables which have none,
+    /// failing if there is no prompter to ask.
+    pub fn prompt_for_required(&self) -> anyhow::Result<()> {
+        self.variable_store
+            .prompt_for_required(self.prompter.as_deref())
+    }
+
+    /// Sets the redactor applied to the output of every action.
+    pub fn set_redactor(&self, redactor: Option<Redactor>) {
+        self.redactor.replace(redactor.map(Arc::new));
+        self.secret_redactor.replace(None);
+    }
+
+    /// Returns the redactor applied to the output of every action, which
+    /// also masks the values of the secrets set so far.
+    fn redactor(&self) -> Option<Arc<Redactor>> {
+        let secrets = self.variable_store.secret_values();
+        if secrets.is_empty() {
+            return self.redactor.borrow().clone();
+        }
+        let mut cached = self.secret_redactor.borrow_mut();
+        match &*cached {
+            Some((values, redactor)) if *values == secrets => Some(redactor.clone()),
+            _ => {
+                let redactor = Arc::new(match &*self.redactor.borrow() {
+                    Some(redactor) => redactor.with_values(&secrets),
+                    None => Redactor::default().with_values(&secrets),
+                });
+                *cached = Some((secrets, redactor.clone()));
+                Some(redactor)
+            }
+        }
+    }
+
+    /// Sets which environment variables tools are spawned with.
+    pub fn set_env_policy(&self, env_policy: Option<EnvPolicy>) {
+        self.env_policy.replace(env_policy.map(Arc::new));
+    }
+
+    /// Sets the sandbox the run works in.
+    pub fn set_sandbox_dir(&self, dir: Option<PathBuf>) {
+        self.sandbox_dir.replace(dir);
+    }
+
     pub fn variable_store(&self) -> &VariableStore {
         &self.variable_store
     }
@@ -42,12 +263,20 @@ impl ParseDelegate for WorkflowDelegate {
         self.variable_store.register_variable(identifier, variable);
     }
 
+    fn on_variable_name(&self, identifier: &str, name: &str) {
+        self.variable_store.set_variable_name(identifier, name);
+    }
+
     fn will_parse_workflow(&self, workflow: PathBuf) {
         self.workflow_file.replace(Some(workflow));
     }
 
-    fn did_parse_workflow(&self) {
-        self.variable_store.realize_variables(&self.workflow_args);
+    fn did_parse_workflow(&self) -> anyhow::Result<()> {
+        self.variable_store.check_consts(&self.workflow_args)?;
+        self.variable_store.check_args(&self.workflow_args)?;
+        self.variable_store
+            .realize_variables_with_sources(&self.workflow_args, &self.variable_sources);
+        Ok(())
     }
 }
 
@@ -58,6 +287,95 @@ impl VariableResolver for WorkflowDelegate {
             None => bail!("No value for variable"),
         }
     }
+
+    fn initial_value(&self, identifier: &str) -> anyhow::Result<String> {
+        match self.variable_store.initial_variable_value(identifier) {
+            Some(v) => Ok(v),
+            None => self.resolve(identifier),
+        }
+    }
+
+    fn snapshot(&self) -> Option<VariableSnapshot> {
+        Some(self.variable_store.snapshot())
+    }
+
+    fn values(&self) -> Option<VariableSnapshot> {
+        Some(self.variable_store.values())
+    }
+
+    fn variable_name(&self, identifier: &str) -> Option<String> {
+        let mut name = None;
+        self.variable_store
+            .with_variable(identifier, |v| name = v.qualified_name());
+        name
+    }
+
+    fn context(&self) -> RunContext<'_> {
+        RunContext {
+            check_args: self.check_args,
+            scratch_dir: Some(self.scratch_dir.path().to_path_buf()),
+            env_capture: self.env_capture,
+            redactor: self.redactor(),
+            run_delegate: Some(self),
+            cancel_token: self.cancel_token.clone(),
+            failure_injector: self.failure_injector.clone(),
+            env_policy: self.env_policy.borrow().clone(),
+            quiet: self.quiet,
+            artifacts_dir: self.artifacts_dir.clone(),
+            log_dir: self.log_dir.clone(),
+            sandbox_dir: self.sandbox_dir.borrow().clone(),
+            host: Some(self),
+            graphs: vec![],
+        }
+    }
+}
+
+impl RunHost for WorkflowDelegate {
+    fn node_artifacts_dir(&self, node: &str) -> Option<PathBuf> {
+        match self.collected.borrow().contains(node) {
+            true => self.artifacts_dir.as_ref().map(|dir| dir.join(node)),
+            false => None,
+        }
+    }
+
+    fn cached_node(&self, node: &str) -> Option<CachedNode> {
+        self.node_cache.as_ref()?.get(node)
+    }
+
+    fn cache_node(&self, node: &str, cached: CachedNode) {
+        if let Some(node_cache) = &self.node_cache {
+            // the run is not failed by a cache which can not be written
+            if let Err(e) = node_cache.set(node, cached) {
+                eprintln!("Unable to record the outputs of node '{}': {:#}", node, e);
+            }
+        }
+    }
+
+    fn approve(
+        &self,
+        gate: &str,
+        message: &str,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<Approval> {
+        let approver = match &self.approver {
+            Some(approver) => approver,
+            None => bail!("manual gate '{}' can not be approved in this run", gate),
+        };
+        let approval = approver.ask(&ApprovalRequest {
+            gate: gate.to_string(),
+            message: message.to_string(),
+            deadline: timeout.map(|timeout| Instant::now() + timeout),
+            cancel_token: self.cancel_token.clone(),
+        })?;
+        self.approvals
+            .borrow_mut()
+            .insert(gate.to_string(), approval.clone());
+        Ok(approval)
+    }
+
+    fn take_approval(&self, gate: &str) -> Option<Approval> {
+        self.approvals.borrow_mut().remove(gate)
+    }
 }
 
 impl VariableUpdater for WorkflowDelegate {
@@ -66,14 +384,52 @@ impl VariableUpdater for WorkflowDelegate {
             identifier,
             value,
             ValueUpdatedBy::Action("".to_string()),
-        );
-        Ok(())
+        )
+    }
+
+    fn update_all(&self, updates: Vec<(String, String)>) -> anyhow::Result<()> {
+        self.variable_store
+            .update_variable_values(updates, ValueUpdatedBy::Action("".to_string()))
+    }
+}
+
+impl RunDelegate for WorkflowDelegate {
+    fn will_run_node(&self, node: &str) {
+        self.publish(RunEvent::NodeStarted {
+            node: node.to_string(),
+        });
+    }
+
+    fn did_run_node(&self, node: &NodeResult) {
+        if self.artifacts_dir.is_some() && node.succeeded() {
+            self.collected.borrow_mut().insert(node.name.clone());
+        }
+        self.publish(RunEvent::NodeFinished(node.clone()));
+    }
+
+    fn will_run_action(&self, node: &str, index: usize, action: &str) {
+        self.publish(RunEvent::ActionStarted {
+            node: node.to_string(),
+            index,
+            action: action.to_string(),
+        });
+    }
+
+    fn did_run_action(&self, node: &str, index: usize, status: &ActionStatus) {
+        self.publish(RunEvent::ActionFinished {
+            node: node.to_string(),
+            index,
+            status: status.clone(),
+        });
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::runner::Runner;
+    use crate::stdlib::test_utils::TempWorkflowFile;
+    use std::sync::Mutex;
 
     #[test]
     fn test_will_parse_workflow() {
@@ -81,4 +437,85 @@ mod tests {
         delegate.will_parse_workflow(PathBuf::from("foo"));
         assert_eq!(delegate.workflow_file, Some(PathBuf::from("foo")).into());
     }
+
+    // describes the events of the nodes and actions
+    fn describe(event: &RunEvent) -> Option<String> {
+        Some(match event {
+            RunEvent::NodeStarted { node } => format!("will run {}", node),
+            RunEvent::NodeFinished(node) => format!("did run {}", node.name),
+            RunEvent::ActionStarted {
+                node,
+                index,
+                action,
+            } => format!("will run {}[{}] {}", node, index, action),
+            RunEvent::ActionFinished {
+                node,
+                index,
+                status,
+            } => {
+                let status = match status {
+                    ActionStatus::Ran(result) => format!("exited {}", result.exit_code),
+                    ActionStatus::Failed(_) => "failed".to_string(),
+                };
+                format!("did run {}[{}] {}", node, index, status)
+            }
+            _ => return None,
+        })
+    }
+
+    #[test]
+    fn test_handler_is_told_about_nodes_and_actions() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+def _ok():
+    return 0
+
+def _to_b(ctx, args):
+    return "b"
+
+main = workflow(
+    entrypoint = "a",
+    graph = [
+        sequence(
+            name = "a",
+            actions = [fn_action(implementation = _ok), action(tool = builtin_tool(name = "true"))],
+            next = next(implementation = _to_b)(),
+        ),
+        node(name = "b", action = action(tool = tool(path = "does-not-exist"))),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        let recording = Arc::new(Mutex::new(vec![]));
+        let handler: EventHandler = {
+            let recording = recording.clone();
+            Arc::new(move |event| recording.lock().unwrap().extend(describe(event)))
+        };
+        let runner = Runner::new(
+            file.path(),
+            WorkflowDelegate::new()
+                .with_quiet(true)
+                .with_handler(handler),
+        )
+        .unwrap();
+        runner.run(None).unwrap();
+
+        assert_eq!(
+            *recording.lock().unwrap(),
+            vec![
+                "will run a",
+                "will run a[0] fn_action",
+                "did run a[0] exited 0",
+                "will run a[1] true",
+                "did run a[1] exited 0",
+                "did run a",
+                "will run b",
+                "will run b[0] does-not-exist",
+                "did run b[0] failed",
+                "did run b",
+            ]
+        );
+    }
 }
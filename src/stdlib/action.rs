@@ -1,10 +1,29 @@
+use crate::stdlib::archive::ArchiveFormat;
+use crate::stdlib::artifact::Artifact;
+use crate::stdlib::builtin_action::{is_sha256, BuiltinAction};
+use crate::stdlib::cancel::CancelToken;
+use crate::stdlib::capture::{ActionLog, CaptureBuffer, CapturedOutput, OutputEncoding};
+use crate::stdlib::env_capture::ActionEnv;
+use crate::stdlib::errors::{ArgCheck, StdlibError, ValueError};
+use crate::stdlib::format::ValueFormatter;
+use crate::stdlib::glob::Glob;
+use crate::stdlib::ir::{compile_arg, ActionIr, ArgIr};
+use crate::stdlib::native::native_tool;
+use crate::stdlib::node_cache::CachedAction;
+use crate::stdlib::plan::PlannedAction;
+use crate::stdlib::redact::{LineRedactor, Redactor};
+use crate::stdlib::run_context::RunContext;
+use crate::stdlib::tool::tool_impl;
 use crate::stdlib::variable_resolver::VariableUpdater;
 use crate::stdlib::variable_resolver::{string_from_value, VariableResolver};
-use crate::stdlib::Setter;
+#[cfg(feature = "wasm")]
+use crate::stdlib::wasm::run_wasm_module;
+use crate::stdlib::{Setter, VariableRef};
 use crate::stdlib::{Tool, ACTION_CTX_TYPE, ACTION_TYPE, TOOL_TYPE};
 use allocative::Allocative;
-use anyhow::bail;
+use anyhow::{anyhow, bail};
 use starlark::coerce::Coerce;
+use starlark::collections::SmallMap;
 use starlark::environment::Methods;
 use starlark::environment::MethodsBuilder;
 use starlark::environment::MethodsStatic;
@@ -12,9 +31,12 @@ use starlark::eval::Evaluator;
 use starlark::starlark_complex_value;
 use starlark::starlark_module;
 use starlark::starlark_simple_value;
+use starlark::values::none::NoneOr;
 use starlark::values::starlark_value;
+use starlark::values::structs::AllocStruct;
 use starlark::values::Freeze;
 use starlark::values::Freezer;
+use starlark::values::Heap;
 use starlark::values::NoSerialize;
 use starlark::values::ProvidesStaticType;
 use starlark::values::StarlarkValue;
@@ -23,29 +45,352 @@ use starlark::values::UnpackValue;
 use starlark::values::Value;
 use starlark::values::ValueLike;
 use starlark::StarlarkDocs;
+use std::borrow::Cow;
+use std::cell::RefCell;
 use std::fmt::Display;
+use std::fs::File;
 use std::io::BufRead;
 use std::io::BufReader;
 use std::io::Write;
 use std::os::unix::process::ExitStatusExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
-use std::process::{Command, ExitStatus};
+use std::process::{Child, Command};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 use std::{fmt, io};
 
+// how often a running tool checks whether its run was cancelled
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn action_impl<'v>(
     tool: Value<'v>,
     args: Vec<Value<'v>>,
     setters: Vec<Value<'v>>,
+    encoding: Option<&str>,
+    strict_utf8: bool,
+    ok_exit_codes: Option<Vec<i32>>,
+    env: SmallMap<String, Value<'v>>,
+    cwd: Option<Value<'v>>,
+    quiet: bool,
+    retries: Option<i32>,
+    retry_delay: Option<i32>,
 ) -> anyhow::Result<Action<'v>> {
-    if tool.get_type() != TOOL_TYPE {
-        bail!("A tool must be passed as the tool in an action")
+    ValueError::check_type("action", "tool", TOOL_TYPE, tool)?;
+    ArgCheck::new("action")
+        .arg("retries", retries.is_some())
+        .arg("retry_delay", retry_delay.is_some())
+        .requires("retry_delay", "retries")?;
+    let (retries, retry_delay_secs) = retry_policy(retries, retry_delay)?;
+    let encoding = OutputEncoding::from_attributes(encoding.unwrap_or("utf8"), strict_utf8)?;
+    let ok_exit_codes = validate_ok_exit_codes(ok_exit_codes)?;
+    validate_env(tool, &env)?;
+    if let Some(cwd) = cwd {
+        validate_cwd(tool, cwd)?;
     }
 
     Ok(Action {
         tool: tool,
         args: args,
         setters: setters,
+        implementation: Value::new_none(),
+        builtin: None,
+        encoding,
+        ok_exit_codes,
+        env,
+        cwd: cwd.unwrap_or_else(Value::new_none),
+        quiet,
+        retries,
+        retry_delay_secs,
+    })
+}
+
+/// Validates the `retries` of an action and the `retry_delay`, in seconds,
+/// between them.
+fn retry_policy(retries: Option<i32>, retry_delay: Option<i32>) -> anyhow::Result<(u32, u32)> {
+    let retries = match retries {
+        Some(r) if r < 0 => bail!(StdlibError::new_invalid_attr(
+            "retries",
+            "cannot be negative",
+            r.to_string()
+        )),
+        r => r.unwrap_or_default() as u32,
+    };
+    let retry_delay = match retry_delay {
+        Some(d) if d < 0 => bail!(StdlibError::new_invalid_attr(
+            "retry_delay",
+            "cannot be a negative number of seconds",
+            d.to_string()
+        )),
+        d => d.unwrap_or_default() as u32,
+    };
+    Ok((retries, retry_delay))
+}
+
+/// Checks the names of the environment variables, and that the tool is
+/// spawned as a process or runs a wasm module which they can be passed to.
+fn validate_env(tool: Value, env: &SmallMap<String, Value>) -> anyhow::Result<()> {
+    if let Some(name) = env
+        .keys()
+        .find(|k| k.is_empty() || k.contains('=') || k.contains('\0'))
+    {
+        bail!(StdlibError::new_invalid_attr(
+            "env",
+            "names cannot be empty or contain '=' or NUL",
+            format!("{:?}", name)
+        ));
+    }
+    match env.is_empty() || Tool::from_value(tool).is_some_and(|t| t.is_wasm()) {
+        true => Ok(()),
+        false => check_runs_as_process("env", tool),
+    }
+}
+
+/// Checks the cwd is a string, variable or `format()` value, and that the
+/// tool is spawned as a process which can be run in it.
+fn validate_cwd(tool: Value, cwd: Value) -> anyhow::Result<()> {
+    let valid = match cwd.unpack_str() {
+        Some(s) => !s.is_empty(),
+        None => VariableRef::from_value(cwd).is_some() || ValueFormatter::from_value(cwd).is_some(),
+    };
+    if !valid {
+        bail!(StdlibError::new_invalid_attr(
+            "cwd",
+            "must be a non empty string, a variable or a format",
+            cwd.to_repr()
+        ));
+    }
+    check_runs_as_process("cwd", tool)
+}
+
+fn check_runs_as_process(attr: &str, tool: Value) -> anyhow::Result<()> {
+    match Tool::from_value(tool) {
+        Some(tool) if tool.is_native() || tool.is_wasm() => {
+            bail!(StdlibError::new_invalid_attr(
+                attr,
+                "is only supported for tools which run as a process",
+                tool.name()
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn validate_ok_exit_codes(ok_exit_codes: Option<Vec<i32>>) -> anyhow::Result<Vec<i32>> {
+    match ok_exit_codes {
+        None => Ok(vec![0]),
+        Some(codes) if codes.is_empty() => bail!(StdlibError::new_invalid_attr(
+            "ok_exit_codes",
+            "cannot be empty",
+            "[]"
+        )),
+        Some(codes) => Ok(codes),
+    }
+}
+
+pub(crate) fn fn_action_impl<'v>(
+    implementation: Value<'v>,
+    args: Vec<Value<'v>>,
+    setters: Vec<Value<'v>>,
+) -> anyhow::Result<Action<'v>> {
+    ValueError::check_type("fn_action", "implementation", "function", implementation)?;
+
+    Ok(Action {
+        tool: Value::new_none(),
+        args,
+        setters,
+        implementation,
+        builtin: None,
+        encoding: OutputEncoding::default(),
+        ok_exit_codes: vec![0],
+        env: SmallMap::new(),
+        cwd: Value::new_none(),
+        quiet: false,
+        retries: 0,
+        retry_delay_secs: 0,
+    })
+}
+
+/// An action which runs the script with `shell -c`. The values a `format()`
+/// script is given are passed to the shell as positional parameters, with
+/// their placeholders replaced by `"${1}"`, `"${2}"`, ..., so the shell
+/// never parses them and they need no quoting.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn shell_impl<'v>(
+    script: Value<'v>,
+    shell: Option<Value<'v>>,
+    setters: Vec<Value<'v>>,
+    ok_exit_codes: Option<Vec<i32>>,
+    env: SmallMap<String, Value<'v>>,
+    cwd: Option<Value<'v>>,
+    quiet: bool,
+    retries: Option<i32>,
+    retry_delay: Option<i32>,
+    heap: &'v Heap,
+) -> anyhow::Result<Action<'v>> {
+    let shell = shell.unwrap_or_else(|| heap.alloc("/bin/bash"));
+    if shell.unpack_str().is_none() && VariableRef::from_value(shell).is_none() {
+        bail!(StdlibError::new_invalid_attr(
+            "shell",
+            "must be a string or a variable",
+            shell.to_repr()
+        ));
+    }
+    let mut args = vec![heap.alloc("-c")];
+    if let Some(formatter) = ValueFormatter::from_value(script) {
+        let (fmt_str, values) = formatter.parts();
+        let mut script = fmt_str.to_string();
+        for index in 1..=values.len() {
+            script = script.replacen("{}", &format!("\"${{{}}}\"", index), 1);
+        }
+        args.push(heap.alloc(script));
+        // $0, which the shell names itself with in its errors
+        args.push(shell);
+        for value in values {
+            args.push(heap.alloc(ValueFormatter::new("{}", vec![value.clone()])));
+        }
+    } else if script.unpack_str().is_some() || VariableRef::from_value(script).is_some() {
+        args.push(script);
+    } else {
+        bail!(StdlibError::new_invalid_attr(
+            "script",
+            "must be a string, a variable or a format",
+            script.to_repr()
+        ));
+    }
+    action_impl(
+        heap.alloc(tool_impl(shell)?),
+        args,
+        setters,
+        None,
+        false,
+        ok_exit_codes,
+        env,
+        cwd,
+        quiet,
+        retries,
+        retry_delay,
+    )
+}
+
+pub(crate) fn render_template_impl<'v>(
+    src: Value<'v>,
+    dest: Value<'v>,
+    vars: SmallMap<String, Value<'v>>,
+    setters: Vec<Value<'v>>,
+    heap: &'v Heap,
+) -> anyhow::Result<Action<'v>> {
+    let mut args = vec![src, dest];
+    for (name, value) in vars {
+        args.push(heap.alloc(name));
+        args.push(value);
+    }
+    builtin_action(BuiltinAction::RenderTemplate, args, setters)
+}
+
+pub(crate) fn verify_impl<'v>(
+    path: Value<'v>,
+    sha256: Value<'v>,
+    setters: Vec<Value<'v>>,
+) -> anyhow::Result<Action<'v>> {
+    // a checksum from a variable can only be checked once it is resolved
+    if let Some(checksum) = sha256.unpack_str() {
+        if !is_sha256(checksum) {
+            bail!(StdlibError::new_invalid_attr(
+                "sha256",
+                "must be 64 hex digits",
+                checksum
+            ));
+        }
+    }
+    builtin_action(BuiltinAction::Verify, vec![path, sha256], setters)
+}
+
+pub(crate) fn archive_impl<'v>(
+    paths: Vec<Value<'v>>,
+    dest: Value<'v>,
+    root: Option<Value<'v>>,
+    setters: Vec<Value<'v>>,
+    heap: &'v Heap,
+) -> anyhow::Result<Action<'v>> {
+    check_archive_path("dest", dest)?;
+    let mut args = vec![dest, root.unwrap_or_else(|| heap.alloc("."))];
+    args.extend(paths);
+    builtin_action(BuiltinAction::Archive, args, setters)
+}
+
+pub(crate) fn unarchive_impl<'v>(
+    src: Value<'v>,
+    dest: Value<'v>,
+    keep_special_bits: bool,
+    setters: Vec<Value<'v>>,
+    heap: &'v Heap,
+) -> anyhow::Result<Action<'v>> {
+    check_archive_path("src", src)?;
+    let args = vec![src, dest, heap.alloc(keep_special_bits.to_string())];
+    builtin_action(BuiltinAction::Unarchive, args, setters)
+}
+
+/// Checks that an archive path which is known when parsing has an
+/// extension the format can be picked from.
+fn check_archive_path(attr: &str, path: Value) -> anyhow::Result<()> {
+    if let Some(path) = path.unpack_str() {
+        if ArchiveFormat::from_path(path).is_none() {
+            bail!(StdlibError::new_invalid_attr(
+                attr,
+                &format!("must end in {}", ArchiveFormat::EXTENSIONS),
+                path
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The action of a manual gate, which waits for the gate to be approved
+/// for at most `timeout_secs`, 0 to wait until it is decided.
+pub(crate) fn manual_gate_action_impl<'v>(
+    name: &str,
+    message: Value<'v>,
+    timeout_secs: u32,
+    heap: &'v Heap,
+) -> anyhow::Result<Action<'v>> {
+    let args = vec![
+        heap.alloc(name),
+        message,
+        heap.alloc(timeout_secs.to_string()),
+    ];
+    builtin_action(BuiltinAction::ManualGate, args, vec![])
+}
+
+fn builtin_action<'v>(
+    builtin: BuiltinAction,
+    args: Vec<Value<'v>>,
+    setters: Vec<Value<'v>>,
+) -> anyhow::Result<Action<'v>> {
+    // the builtins expect their args in fixed positions, which a glob
+    // expanding to any number of paths would shift
+    if args.iter().any(|arg| Glob::from_value(*arg).is_some()) {
+        bail!("{} does not accept a glob", builtin.name());
+    }
+    if args.iter().any(|arg| Artifact::from_value(*arg).is_some()) {
+        bail!("{} does not accept an artifact", builtin.name());
+    }
+    Ok(Action {
+        tool: Value::new_none(),
+        args,
+        setters,
+        implementation: Value::new_none(),
+        builtin: Some(builtin),
+        encoding: OutputEncoding::default(),
+        ok_exit_codes: vec![0],
+        env: SmallMap::new(),
+        cwd: Value::new_none(),
+        quiet: false,
+        retries: 0,
+        retry_delay_secs: 0,
     })
 }
 
@@ -57,6 +402,27 @@ pub struct ActionGen<V> {
     tool: V,
     args: Vec<V>,
     setters: Vec<V>,
+    // if not None, a starlark function which is called instead of running the tool
+    implementation: V,
+    // if set, work the stdlib does in process instead of running the tool
+    #[trace(static)]
+    builtin: Option<BuiltinAction>,
+    // how the captured output is decoded for the setters
+    #[trace(static)]
+    encoding: OutputEncoding,
+    // the exit codes which count as the tool succeeding
+    ok_exit_codes: Vec<i32>,
+    // environment variables set for the spawned tool, on top of the
+    // inherited ones
+    env: SmallMap<String, V>,
+    // the directory the spawned tool runs in, None to inherit it
+    cwd: V,
+    // if set the output is only collected for the setters, not shown
+    quiet: bool,
+    // how many times the tool is run again when its exit code is not ok
+    retries: u32,
+    // how long to wait before running it again
+    retry_delay_secs: u32,
 }
 starlark_complex_value!(pub Action);
 
@@ -64,167 +430,1003 @@ starlark_complex_value!(pub Action);
 impl<'v, V: ValueLike<'v> + 'v> StarlarkValue<'v> for ActionGen<V> where Self: ProvidesStaticType<'v>
 {}
 
+/// Kills the child if the run is cancelled or the deadline passes before
+/// `finished` is dropped. Returns whether it was killed at the deadline.
+fn kill_when_stopped(
+    token: Option<CancelToken>,
+    deadline: Option<Instant>,
+    child: Arc<Mutex<Child>>,
+    finished: Receiver<()>,
+) -> JoinHandle<bool> {
+    thread::spawn(move || {
+        while let Err(RecvTimeoutError::Timeout) = finished.recv_timeout(CANCEL_POLL_INTERVAL) {
+            let cancelled = token.as_ref().is_some_and(|t| t.is_cancelled());
+            let timed_out = deadline.is_some_and(|deadline| Instant::now() >= deadline);
+            if cancelled || timed_out {
+                // it may have exited already
+                let _ = child.lock().unwrap().kill();
+                return timed_out;
+            }
+        }
+        false
+    })
+}
+
+/// Passes the output of a spawned tool to the collector until both of its
+/// streams are closed.
+fn forward_output(
+    stdout: &mut dyn BufRead,
+    stderr: &mut dyn BufRead,
+    output_collector: &mut OutputCollector,
+) -> anyhow::Result<()> {
+    loop {
+        let (stdout_bytes, stderr_bytes) = match (stdout.fill_buf(), stderr.fill_buf()) {
+            (Ok(stdout), Ok(stderr)) => {
+                output_collector.emit(stdout, stderr)?;
+                (stdout.len(), stderr.len())
+            }
+            (Err(e), _) => bail!("Unable to read stdout: {}", e),
+            (_, Err(e)) => bail!("Unable to read stderr: {}", e),
+        };
+        if stdout_bytes == 0 && stderr_bytes == 0 {
+            return Ok(());
+        }
+
+        stdout.consume(stdout_bytes);
+        stderr.consume(stderr_bytes);
+    }
+}
+
+/// Returns what is wrong with a resolved argument, None if it is fine.
+fn arg_problem(arg: &str) -> Option<&'static str> {
+    if arg.contains('\0') {
+        Some("a NUL byte")
+    } else if arg.contains('\n') {
+        Some("a newline")
+    } else {
+        None
+    }
+}
+
+/// A resolver which records the variables resolved through it so a bad
+/// argument can be traced back to the variable it came from.
+struct RecordingResolver<'r, T: VariableResolver> {
+    inner: &'r T,
+    resolved: RefCell<Vec<(String, String)>>,
+}
+
+impl<'r, T: VariableResolver> VariableResolver for RecordingResolver<'r, T> {
+    fn resolve(&self, identifier: &str) -> anyhow::Result<String> {
+        let value = self.inner.resolve(identifier)?;
+        self.resolved
+            .borrow_mut()
+            .push((identifier.to_string(), value.clone()));
+        Ok(value)
+    }
+
+    fn context(&self) -> RunContext<'_> {
+        self.inner.context()
+    }
+}
+
 impl<'a> Action<'a> {
-    pub fn arg_list<T: VariableResolver>(&self, resolver: &T) -> anyhow::Result<Vec<String>> {
+    /// Whether the action waits for the approval of a manual gate.
+    pub fn is_manual_gate(&self) -> bool {
+        self.builtin == Some(BuiltinAction::ManualGate)
+    }
+
+    /// Whether the action spawns its tool as a child process, which is what
+    /// can be given stdin.
+    pub fn spawns_process(&self) -> bool {
+        self.builtin.is_none() && self.tool().is_some_and(|t| !t.is_native() && !t.is_wasm())
+    }
+
+    /// Returns the tool the action runs, None for function and builtin
+    /// actions.
+    pub fn tool(&self) -> Option<&Tool<'a>> {
+        Tool::from_value(self.tool)
+    }
+
+    /// Returns the setters which update variables from the output.
+    pub fn setters(&self) -> Vec<&Setter<'a>> {
+        self.setters
+            .iter()
+            .filter_map(|s| Setter::from_value(*s))
+            .collect()
+    }
+
+    /// Resolves the environment variables which are set for the tool.
+    pub fn env_list<T: VariableResolver>(
+        &self,
+        resolver: &T,
+    ) -> anyhow::Result<Vec<(String, String)>> {
+        self.env
+            .iter()
+            .map(|(name, value)| Ok((name.clone(), string_from_value(*value, resolver)?)))
+            .collect()
+    }
+
+    /// Resolves the directory the tool runs in, relative to the working dir,
+    /// None if the action does not set one and the tool inherits it.
+    pub fn cwd<T: VariableResolver>(
+        &self,
+        resolver: &T,
+        working_dir: &Path,
+    ) -> anyhow::Result<Option<PathBuf>> {
+        if self.cwd.is_none() {
+            return Ok(None);
+        }
+        let cwd = working_dir.join(string_from_value(self.cwd, resolver)?);
+        if !cwd.is_dir() {
+            bail!(
+                "cwd {:?} of the action running '{}' is not a directory",
+                cwd,
+                self.label(resolver)
+            );
+        }
+        Ok(Some(cwd))
+    }
+
+    /// Returns the artifacts of other nodes which the action uses as args.
+    pub fn artifact_args(&self) -> Vec<&'a Artifact> {
+        self.args
+            .iter()
+            .filter_map(|v| Artifact::from_value(*v))
+            .collect()
+    }
+
+    /// Resolves the args, a glob expands to one arg for each path it
+    /// matches in the working dir and an artifact to one for each of the
+    /// collected files it matches.
+    pub fn arg_list<T: VariableResolver>(
+        &self,
+        resolver: &T,
+        working_dir: &Path,
+    ) -> anyhow::Result<Vec<String>> {
         let mut args_list: Vec<String> = Vec::new();
-        for v in self.args.clone() {
-            let r = string_from_value(v, resolver)?;
+        for (index, v) in self.args.iter().enumerate() {
+            if let Some(glob) = Glob::from_value(*v) {
+                args_list.extend(glob.expand(resolver, working_dir)?);
+                continue;
+            }
+            if let Some(artifact) = Artifact::from_value(*v) {
+                args_list.extend(artifact.expand(resolver)?);
+                continue;
+            }
+            let r = match resolver.context().check_args {
+                true => self.checked_arg(index, *v, resolver)?,
+                false => string_from_value(*v, resolver)?,
+            };
             args_list.push(r);
         }
         Ok(args_list)
     }
 
+    /// Resolves the argument, failing if it contains a newline or NUL byte.
+    fn checked_arg<T: VariableResolver>(
+        &self,
+        index: usize,
+        value: Value<'a>,
+        resolver: &T,
+    ) -> anyhow::Result<String> {
+        let recording = RecordingResolver {
+            inner: resolver,
+            resolved: RefCell::new(vec![]),
+        };
+        let arg = string_from_value(value, &recording)?;
+        let problem = match arg_problem(&arg) {
+            Some(problem) => problem,
+            None => return Ok(arg),
+        };
+        let source = recording
+            .resolved
+            .into_inner()
+            .into_iter()
+            .find(|(_, value)| arg_problem(value) == Some(problem))
+            .map(|(identifier, _)| {
+                format!(
+                    " from variable '{}'",
+                    resolver.variable_name(&identifier).unwrap_or(identifier)
+                )
+            });
+        bail!(
+            "argument {} of the action running '{}' contains {}{}",
+            index + 1,
+            self.label(resolver),
+            problem,
+            source.unwrap_or_default()
+        )
+    }
+
+    /// Returns a short description of what the action runs for messages.
+    pub(crate) fn label<T: VariableResolver>(&self, resolver: &T) -> String {
+        if let Some(builtin) = self.builtin {
+            return builtin.name().to_string();
+        }
+        match Tool::from_value(self.tool) {
+            Some(tool) if !tool.name().is_empty() => tool.name().to_string(),
+            Some(tool) => tool
+                .path(resolver, &PathBuf::new())
+                .map(|p| p.display().to_string())
+                .unwrap_or_else(|_| "tool".to_string()),
+            None => "fn_action".to_string(),
+        }
+    }
+
     pub fn command<T: VariableResolver>(
         &self,
         resolver: &T,
         working_dir: &PathBuf,
     ) -> anyhow::Result<Command> {
-        let tool = Tool::from_value(self.tool.clone()).unwrap();
+        let tool = match Tool::from_value(self.tool.clone()) {
+            Some(tool) => tool,
+            None => bail!("{} does not run a command", self.label(resolver)),
+        };
         let program = tool.real_path(resolver, working_dir)?.into_os_string();
 
         let mut cmd = Command::new(program);
-        for arg in self.arg_list(resolver)? {
+        for arg in self.arg_list(resolver, working_dir)? {
             cmd.arg(arg);
         }
+        if let Some(policy) = resolver.context().env_policy {
+            if !policy.inherit() {
+                cmd.env_clear();
+            }
+            cmd.envs(policy.vars(resolver)?);
+        }
+        cmd.envs(self.env_list(resolver)?);
+        if let Some(cwd) = self
+            .cwd(resolver, working_dir)?
+            .or(resolver.context().sandbox_dir)
+        {
+            cmd.current_dir(cwd);
+        }
 
         Ok(cmd)
     }
 
+    /// Returns what running the action would do, without running it. The
+    /// variables resolve to the values they have now.
+    pub fn plan<T: VariableResolver>(&self, resolver: &T, working_dir: &PathBuf) -> PlannedAction {
+        let label = self.label(resolver);
+        let unresolved = |e: anyhow::Error| PlannedAction::Unresolved {
+            label: label.clone(),
+            reason: format!("{:#}", e),
+        };
+        let args = match self.arg_list(resolver, working_dir) {
+            Ok(args) => args,
+            Err(e) => return unresolved(e),
+        };
+        let tool = match Tool::from_value(self.tool) {
+            Some(tool) if self.builtin.is_none() && !tool.is_native() => tool,
+            _ => return PlannedAction::InProcess { label, args },
+        };
+        match tool.real_path(resolver, working_dir) {
+            Ok(path) if tool.is_wasm() => PlannedAction::InProcess {
+                label: format!("wasm {}", path.display()),
+                args,
+            },
+            Ok(program) => match (self.env_list(resolver), self.cwd(resolver, working_dir)) {
+                (Ok(env), Ok(cwd)) => PlannedAction::Command {
+                    program,
+                    args,
+                    env,
+                    cwd,
+                },
+                (Err(e), _) | (_, Err(e)) => unresolved(e),
+            },
+            Err(e) => unresolved(e),
+        }
+    }
+
+    /// Returns the action as IR, failing if running it calls into starlark
+    /// or it sets what the IR can not express.
+    pub fn compile<T: VariableResolver>(&self, resolver: &T) -> anyhow::Result<ActionIr> {
+        let label = self.label(resolver);
+        let tool = match Tool::from_value(self.tool) {
+            Some(tool) if self.builtin.is_none() => tool.compile()?,
+            _ => bail!("the action running '{}' does not run a tool", label),
+        };
+        let arg = |value: Value<'a>| -> anyhow::Result<ArgIr> {
+            compile_arg(value, resolver)?.ok_or_else(|| {
+                anyhow!(
+                    "the action running '{}' is given a {}, only strings and variables can be compiled",
+                    label,
+                    value.get_type()
+                )
+            })
+        };
+        let (encoding, strict_utf8) = self.encoding.attributes();
+        let cwd = match self.cwd.is_none() {
+            true => None,
+            false => match self.cwd.unpack_str() {
+                Some(cwd) => Some(cwd.to_string()),
+                None => bail!(
+                    "the cwd of the action running '{}' is not a string, which can not be compiled",
+                    label
+                ),
+            },
+        };
+        Ok(ActionIr {
+            tool,
+            args: self
+                .args
+                .iter()
+                .map(|value| arg(*value))
+                .collect::<anyhow::Result<_>>()?,
+            env: self
+                .env
+                .iter()
+                .map(|(name, value)| Ok((name.clone(), arg(*value)?)))
+                .collect::<anyhow::Result<_>>()?,
+            cwd,
+            setters: self
+                .setters
+                .iter()
+                .filter_map(|s| Setter::from_value(*s))
+                .map(|setter| setter.compile(resolver))
+                .collect::<anyhow::Result<_>>()
+                .map_err(|e| {
+                    e.context(format!(
+                        "the action running '{}' can not be compiled",
+                        label
+                    ))
+                })?,
+            encoding: match encoding {
+                "utf8" => None,
+                encoding => Some(encoding.to_string()),
+            },
+            strict_utf8,
+            ok_exit_codes: self.ok_exit_codes.clone(),
+            quiet: self.quiet,
+            retries: self.retries,
+            retry_delay: self.retry_delay_secs,
+        })
+    }
+
     pub fn run<T: VariableResolver + VariableUpdater>(
         &self,
         resolver: &T,
         working_dir: &PathBuf,
         eval: &mut Evaluator<'a, '_>,
     ) -> anyhow::Result<ActionCtx> {
-        let mut cmd = self.command(resolver, working_dir)?;
-        let mut child = cmd
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()?;
-
-        let needs_action_ctx = self.setters.len() > 0;
-        let mut output_collector = OutputCollector::new(needs_action_ctx);
-
-        let (mut stdout, mut stderr) = {
-            match (child.stdout.as_mut(), child.stderr.as_mut()) {
-                (Some(child_stdout), Some(child_stderr)) => {
-                    (BufReader::new(child_stdout), BufReader::new(child_stderr))
-                }
-                _ => bail!("Could not create stdout/stderr"),
-            }
-        };
+        self.run_attempt(
+            resolver,
+            working_dir,
+            Attempt::default(),
+            None,
+            Piping::default(),
+            eval,
+        )
+    }
 
-        loop {
-            let (stdout_bytes, stderr_bytes) = match (stdout.fill_buf(), stderr.fill_buf()) {
-                (Ok(stdout), Ok(stderr)) => {
-                    output_collector.collect(stdout, stderr)?;
-
-                    // TODO: add `quiet` to action and check that before we print
-                    io::stdout().write_all(stdout).expect("foo");
-                    io::stderr().write_all(stderr).expect("foo");
-                    (stdout.len(), stderr.len())
-                }
-                other => panic!("Some better error handling here... {:?}", other),
+    /// Runs the action as the given attempt, the attempt is exposed to the
+    /// setters and next functions through the ActionCtx. The output is also
+    /// written to the `log` if given, holding the output of the last time
+    /// the tool ran when the action retries it. The `piping` connects it to
+    /// the actions around it in a piped sequence.
+    pub(crate) fn run_attempt<T: VariableResolver + VariableUpdater>(
+        &self,
+        resolver: &T,
+        working_dir: &PathBuf,
+        attempt: Attempt,
+        log: Option<&ActionLog>,
+        piping: Piping,
+        eval: &mut Evaluator<'a, '_>,
+    ) -> anyhow::Result<ActionCtx> {
+        let needs_action_ctx = self.setters.len() > 0 || piping.stdout;
+        let started = Instant::now();
+        let mut action_attempt = 1;
+        let (exit_code, env, stdout, stderr) = loop {
+            let mut output_collector = OutputCollector::new(needs_action_ctx)
+                .with_redactor(resolver.context().redactor)
+                .with_quiet(self.quiet || resolver.context().quiet)
+                .with_piped_stdout(piping.stdout)
+                .with_log(log.map(|log| log.create()).transpose()?);
+            let injected = resolver
+                .context()
+                .failure_injector
+                .is_some_and(|injector| injector.should_fail());
+            let (exit_code, env) = match injected {
+                true => self.inject_failure(resolver, &mut output_collector)?,
+                false => self.run_once(
+                    resolver,
+                    working_dir,
+                    piping.stdin,
+                    attempt.deadline,
+                    eval,
+                    &mut output_collector,
+                )?,
             };
-            if stdout_bytes == 0 && stderr_bytes == 0 {
-                break;
+            let (stdout, stderr) = output_collector.finish()?;
+            if self.ok_exit_codes.contains(&exit_code)
+                || !self.retry_allowed(action_attempt, &attempt)
+            {
+                break (exit_code, env, stdout, stderr);
             }
+            self.wait_to_retry(resolver)?;
+            action_attempt += 1;
+        };
 
-            stdout.consume(stdout_bytes);
-            stderr.consume(stderr_bytes);
+        let action_ctx = ActionCtx {
+            stdout,
+            stderr,
+            encoding: self.encoding,
+            success: self.ok_exit_codes.contains(&exit_code),
+            action_attempt,
+            env,
+            log: log.cloned(),
+            name: self.label(resolver),
+            duration_ms: started.elapsed().as_millis() as u64,
+            ..ActionCtx::new(String::new(), String::new(), exit_code)
         }
+        .with_attempt(attempt);
+        self.apply_setters(action_ctx, resolver, working_dir, eval)
+    }
 
-        let status = child.wait().expect("Waiting for child failed");
+    /// Gives the ctx of an earlier run of the action, recorded in the node
+    /// cache, to its setters instead of running it again.
+    pub fn replay<T: VariableResolver + VariableUpdater>(
+        &self,
+        cached: &CachedAction,
+        attempt: Attempt,
+        resolver: &T,
+        working_dir: &Path,
+        eval: &mut Evaluator<'a, '_>,
+    ) -> anyhow::Result<ActionCtx> {
+        let action_ctx = ActionCtx {
+            stdout: CapturedOutput::InMemory(cached.stdout.clone()),
+            stderr: CapturedOutput::InMemory(cached.stderr.clone()),
+            encoding: self.encoding,
+            success: self.ok_exit_codes.contains(&cached.exit_code),
+            name: self.label(resolver),
+            ..ActionCtx::new(String::new(), String::new(), cached.exit_code)
+        }
+        .with_attempt(attempt);
+        self.apply_setters(action_ctx, resolver, working_dir, eval)
+    }
 
-        let heap = eval.module().heap();
-        let action_ctx = ActionCtx::new(
-            output_collector.stdout()?,
-            output_collector.stderr()?,
-            status,
-        );
-        let ctx = heap.alloc(action_ctx.clone());
+    /// Calls the setters with the ctx and updates the variables with what
+    /// they return, or with what is in the files of `from_file` setters
+    /// which are read from the directory the tool ran in. The setters
+    /// without an implementation are skipped when the exit code is not ok,
+    /// so the action fails with it rather than with a missing file or json.
+    /// Those with one can check `ctx.success` themselves.
+    fn apply_setters<T: VariableResolver + VariableUpdater>(
+        &self,
+        action_ctx: ActionCtx,
+        resolver: &T,
+        working_dir: &Path,
+        eval: &mut Evaluator<'a, '_>,
+    ) -> anyhow::Result<ActionCtx> {
+        let ctx = eval.module().heap().alloc(action_ctx.clone());
 
-        for setter in self.setters.clone() {
-            if let Some(setter) = Setter::from_value(setter) {
+        // The results are buffered and only applied once all of the setters
+        // succeed so a failing setter never leaves the variables half updated.
+        let mut updates = Vec::new();
+        let count = self.setters.len();
+        for (index, setter) in self.setters.iter().enumerate() {
+            if let Some(setter) = Setter::from_value(*setter) {
+                let failed = |e: anyhow::Error| {
+                    e.context(format!(
+                        "setter {} of {} failed, no variables were updated",
+                        index + 1,
+                        count
+                    ))
+                };
+                if setter.implementation().is_none() {
+                    if !action_ctx.success {
+                        continue;
+                    }
+                    let output = match setter.from_file() {
+                        Some(_) => {
+                            let dir = self
+                                .cwd(resolver, working_dir)
+                                .map_err(failed)?
+                                .unwrap_or_else(|| working_dir.to_path_buf());
+                            setter.read_file(resolver, &dir).map_err(failed)?
+                        }
+                        None => action_ctx.stdout().map_err(failed)?,
+                    };
+                    if let Some(value) = setter.value_from_output(output).map_err(failed)? {
+                        updates.push((setter.variable_identifier().to_string(), value));
+                    }
+                    continue;
+                }
                 match eval.eval_function(setter.implementation(), &[ctx], &[]) {
                     Ok(res) => {
+                        setter.check_returned(res).map_err(failed)?;
                         if res.get_type() == "string" {
-                            let _ = resolver.update(setter.variable_identifier(), res.to_str());
+                            updates.push((setter.variable_identifier().to_string(), res.to_str()));
                         } else if res.get_type() != "NoneType" {
                             // None means don't update
-                            bail!("setter must return string or None")
+                            return Err(failed(anyhow!("setter must return string or None")));
                         }
                     }
-                    Err(e) => bail!(e.into_anyhow()),
+                    Err(e) => return Err(failed(e.into_anyhow())),
                 }
             }
         }
+        resolver.update_all(updates)?;
 
-        // run the command then call the variable updater function
         Ok(action_ctx)
     }
-}
-
-impl<'v> Freeze for Action<'v> {
-    type Frozen = FrozenAction;
-    fn freeze(self, freezer: &Freezer) -> anyhow::Result<Self::Frozen> {
-        Ok(ActionGen {
-            tool: self.tool.freeze(freezer)?,
-            args: self.args.freeze(freezer)?,
-            setters: self.setters.freeze(freezer)?,
-        })
-    }
-}
 
-impl<V> Display for ActionGen<V> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "action")
+    /// Whether the tool can be run again after the attempt, which is while
+    /// there are retries left and waiting for the next one does not pass the
+    /// node's deadline.
+    fn retry_allowed(&self, action_attempt: u32, attempt: &Attempt) -> bool {
+        let delay = Duration::from_secs(self.retry_delay_secs.into());
+        action_attempt <= self.retries
+            && attempt
+                .deadline
+                .is_none_or(|deadline| Instant::now() + delay < deadline)
     }
-}
-
-//
-// -- ActionCtx
-//
-#[derive(Debug, ProvidesStaticType, NoSerialize, Allocative, Clone)]
-pub struct ActionCtx {
-    stdout: String,
-    stderr: String,
-    exit_code: i32,
-}
-starlark_simple_value!(ActionCtx);
 
-#[starlark_value(type = ACTION_CTX_TYPE )]
-impl<'v> StarlarkValue<'v> for ActionCtx {
-    fn get_methods() -> Option<&'static Methods> {
-        static RES: MethodsStatic = MethodsStatic::new();
-        RES.methods(action_ctx_methods)
+    /// Waits for the retry delay, failing if the run is cancelled meanwhile.
+    fn wait_to_retry<T: VariableResolver>(&self, resolver: &T) -> anyhow::Result<()> {
+        let until = Instant::now() + Duration::from_secs(self.retry_delay_secs.into());
+        let cancel_token = resolver.context().cancel_token;
+        loop {
+            if cancel_token.as_ref().is_some_and(|t| t.is_cancelled()) {
+                bail!("The run was cancelled");
+            }
+            let now = Instant::now();
+            if now >= until {
+                return Ok(());
+            }
+            thread::sleep(CANCEL_POLL_INTERVAL.min(until - now));
+        }
     }
-}
 
-impl<'v> UnpackValue<'v> for ActionCtx {
-    fn unpack_value(value: Value<'v>) -> Option<Self> {
-        ActionCtx::from_value(value).map(|v| v.clone())
+    /// Fails as if the tool had exited with the first exit code which is
+    /// not ok, without running it.
+    fn inject_failure<T: VariableResolver>(
+        &self,
+        resolver: &T,
+        output_collector: &mut OutputCollector,
+    ) -> anyhow::Result<(i32, Option<ActionEnv>)> {
+        let exit_code = (1..)
+            .find(|code| !self.ok_exit_codes.contains(code))
+            .unwrap_or(1);
+        let message = format!(
+            "workflow: injected a failure instead of running {}\n",
+            self.label(resolver)
+        );
+        output_collector.emit(b"", message.as_bytes())?;
+        Ok((exit_code, None))
     }
-}
 
-#[starlark_module]
-fn action_ctx_methods(builder: &mut MethodsBuilder) {
-    #[starlark(attribute)]
-    fn stdout(this: ActionCtx) -> anyhow::Result<String> {
-        Ok(this.stdout)
+    /// Runs the action's tool, builtin or function once, returning its exit
+    /// code and the environment a spawned tool was given. A spawned tool is
+    /// given `stdin` if set and is killed if it runs past the `deadline`.
+    fn run_once<T: VariableResolver>(
+        &self,
+        resolver: &T,
+        working_dir: &PathBuf,
+        stdin: Option<&[u8]>,
+        deadline: Option<Instant>,
+        eval: &mut Evaluator<'a, '_>,
+        output_collector: &mut OutputCollector,
+    ) -> anyhow::Result<(i32, Option<ActionEnv>)> {
+        let mut env = None;
+        let exit_code = if let Some(builtin) = self.builtin {
+            self.run_builtin(builtin, resolver, working_dir, output_collector)?
+        } else if let Some(tool) = Tool::from_value(self.tool) {
+            if tool.is_native() {
+                self.run_native(tool, resolver, working_dir, output_collector)?
+            } else if tool.is_wasm() {
+                self.run_wasm(tool, resolver, working_dir, output_collector)?
+            } else {
+                let label = self.label(resolver);
+                let mut vars = self.env_list(resolver)?;
+                env = Some(match resolver.context().env_policy {
+                    Some(policy) => {
+                        vars.splice(0..0, policy.vars(resolver)?);
+                        match policy.inherit() {
+                            true => {
+                                ActionEnv::capture(&label, &vars, resolver.context().env_capture)
+                            }
+                            false => {
+                                ActionEnv::isolated(&label, &vars, resolver.context().env_capture)
+                            }
+                        }
+                    }
+                    None => ActionEnv::capture(&label, &vars, resolver.context().env_capture),
+                });
+                self.run_process(resolver, working_dir, stdin, deadline, output_collector)?
+            }
+        } else {
+            self.run_function(resolver, working_dir, eval, output_collector)?
+        };
+        Ok((exit_code, env))
     }
 
-    #[starlark(attribute)]
-    fn stderr(this: ActionCtx) -> anyhow::Result<String> {
-        Ok(this.stderr)
-    }
+    /// Spawns the tool as a child process, writing `stdin` to it if set,
+    /// returning the exit code. It is killed if it runs past the `deadline`.
+    fn run_process<T: VariableResolver>(
+        &self,
+        resolver: &T,
+        working_dir: &PathBuf,
+        stdin: Option<&[u8]>,
+        deadline: Option<Instant>,
+        output_collector: &mut OutputCollector,
+    ) -> anyhow::Result<i32> {
+        let mut cmd = self.command(resolver, working_dir)?;
+        let mut child = cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        // written from its own thread so a tool which writes before it has
+        // read all of its input does not block on a full pipe. A tool which
+        // exits without reading it all closes the pipe, as in a shell
+        let writer = match (stdin, child.stdin.take()) {
+            (Some(input), Some(mut pipe)) => {
+                let input = input.to_vec();
+                Some(thread::spawn(move || {
+                    let _ = pipe.write_all(&input);
+                }))
+            }
+            (_, pipe) => {
+                child.stdin = pipe;
+                None
+            }
+        };
 
-    #[starlark(attribute)]
-    fn exit_code(this: ActionCtx) -> anyhow::Result<i32> {
-        Ok(this.exit_code)
-    }
-}
+        let (mut stdout, mut stderr) = {
+            match (child.stdout.take(), child.stderr.take()) {
+                (Some(child_stdout), Some(child_stderr)) => {
+                    (BufReader::new(child_stdout), BufReader::new(child_stderr))
+                }
+                _ => bail!("Could not create stdout/stderr"),
+            }
+        };
+        let child = Arc::new(Mutex::new(child));
+        let cancel_token = resolver.context().cancel_token;
+        // stops watching once the output has been read
+        let (done, finished) = mpsc::channel::<()>();
+        let watcher = (cancel_token.is_some() || deadline.is_some())
+            .then(|| kill_when_stopped(cancel_token.clone(), deadline, child.clone(), finished));
+
+        let forwarded = forward_output(&mut stdout, &mut stderr, output_collector);
+        drop(done);
+        if let Some(writer) = writer {
+            let _ = writer.join();
+        }
+        let timed_out = watcher.is_some_and(|watcher| watcher.join().unwrap_or(false));
+        let label = self.label(resolver);
+        if let Err(e) = forwarded {
+            // the tool is not left running with nothing reading its output
+            let mut child = child.lock().unwrap();
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e.context(format!("the output of the action running '{}'", label)));
+        }
+        let status = child.lock().unwrap().wait().map_err(|e| {
+            anyhow!(
+                "Unable to wait for the action running '{}' to exit: {}",
+                label,
+                e
+            )
+        })?;
+        if cancel_token.is_some_and(|t| t.is_cancelled()) {
+            bail!("The run was cancelled");
+        }
+        if timed_out {
+            bail!("the action running '{}' was killed at its deadline", label);
+        }
+        Ok(status.code().or(status.signal()).unwrap_or(-1))
+    }
+
+    /// Calls the fn_action implementation in the evaluator with the resolved
+    /// args. The returned string is used as stdout and an int as the exit code.
+    fn run_function<T: VariableResolver>(
+        &self,
+        resolver: &T,
+        working_dir: &Path,
+        eval: &mut Evaluator<'a, '_>,
+        output_collector: &mut OutputCollector,
+    ) -> anyhow::Result<i32> {
+        let heap = eval.module().heap();
+        let args: Vec<Value> = self
+            .arg_list(resolver, working_dir)?
+            .into_iter()
+            .map(|arg| heap.alloc(arg))
+            .collect();
+        let res = match eval.eval_function(self.implementation, &args, &[]) {
+            Ok(res) => res,
+            Err(e) => bail!(e.into_anyhow()),
+        };
+
+        let (stdout, exit_code) = match res.get_type() {
+            "string" => (res.to_str(), 0),
+            "int" => ("".to_string(), res.unpack_i32().unwrap_or(-1)),
+            "NoneType" => ("".to_string(), 0),
+            _ => bail!("fn_action implementation must return string, int or None"),
+        };
+
+        output_collector.emit(stdout.as_bytes(), b"")?;
+        Ok(exit_code)
+    }
+
+    /// Calls the tool's registered closure in process, returning the exit code.
+    fn run_native<T: VariableResolver>(
+        &self,
+        tool: &Tool,
+        resolver: &T,
+        working_dir: &Path,
+        output_collector: &mut OutputCollector,
+    ) -> anyhow::Result<i32> {
+        let f = match native_tool(tool.name()) {
+            Some(f) => f,
+            None => bail!("No native tool registered with name '{}'", tool.name()),
+        };
+        let ctx = f(&self.arg_list(resolver, working_dir)?)?;
+
+        let (stdout, stderr) = (ctx.stdout()?, ctx.stderr()?);
+        output_collector.emit(stdout.as_bytes(), stderr.as_bytes())?;
+        Ok(ctx.exit_code)
+    }
+
+    /// Runs one of the stdlib's in process actions, returning the exit code.
+    fn run_builtin<T: VariableResolver>(
+        &self,
+        builtin: BuiltinAction,
+        resolver: &T,
+        working_dir: &Path,
+        output_collector: &mut OutputCollector,
+    ) -> anyhow::Result<i32> {
+        let ctx = builtin.run(
+            &self.arg_list(resolver, working_dir)?,
+            resolver,
+            working_dir,
+        )?;
+
+        let (stdout, stderr) = (ctx.stdout()?, ctx.stderr()?);
+        output_collector.emit(stdout.as_bytes(), stderr.as_bytes())?;
+        Ok(ctx.exit_code)
+    }
+
+    /// Runs the tool's WASI module in the embedded runtime, returning the exit code.
+    #[cfg(feature = "wasm")]
+    fn run_wasm<T: VariableResolver>(
+        &self,
+        tool: &Tool,
+        resolver: &T,
+        working_dir: &PathBuf,
+        output_collector: &mut OutputCollector,
+    ) -> anyhow::Result<i32> {
+        let module = tool.real_path(resolver, working_dir)?;
+        let mut vars = self.env_list(resolver)?;
+        let inherit = match resolver.context().env_policy {
+            Some(policy) => {
+                vars.splice(0..0, policy.vars(resolver)?);
+                policy.inherit()
+            }
+            None => true,
+        };
+        let output = run_wasm_module(
+            &module,
+            &self.arg_list(resolver, working_dir)?,
+            working_dir,
+            inherit,
+            &vars,
+        )?;
+
+        output_collector.emit(&output.stdout, &output.stderr)?;
+        Ok(output.exit_code)
+    }
+
+    #[cfg(not(feature = "wasm"))]
+    fn run_wasm<T: VariableResolver>(
+        &self,
+        _tool: &Tool,
+        _resolver: &T,
+        _working_dir: &PathBuf,
+        _output_collector: &mut OutputCollector,
+    ) -> anyhow::Result<i32> {
+        bail!("wasm tools are not supported, rebuild with the 'wasm' feature enabled")
+    }
+}
+
+impl<'v> Freeze for Action<'v> {
+    type Frozen = FrozenAction;
+    fn freeze(self, freezer: &Freezer) -> anyhow::Result<Self::Frozen> {
+        Ok(ActionGen {
+            tool: self.tool.freeze(freezer)?,
+            args: self.args.freeze(freezer)?,
+            setters: self.setters.freeze(freezer)?,
+            implementation: self.implementation.freeze(freezer)?,
+            builtin: self.builtin,
+            encoding: self.encoding,
+            ok_exit_codes: self.ok_exit_codes,
+            env: self.env.freeze(freezer)?,
+            cwd: self.cwd.freeze(freezer)?,
+            quiet: self.quiet,
+            retries: self.retries,
+            retry_delay_secs: self.retry_delay_secs,
+        })
+    }
+}
+
+impl<V> Display for ActionGen<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "action")
+    }
+}
+
+/// How an action of a piped sequence is connected to the actions around
+/// it, an action which is not piped is given no stdin and shows its stdout.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Piping<'p> {
+    /// The stdout of the action before it, written to its tool's stdin.
+    pub stdin: Option<&'p [u8]>,
+    /// Whether its stdout is given to the action after it instead of being
+    /// shown.
+    pub stdout: bool,
+}
+
+/// Describes which attempt at running an action is being made.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Attempt {
+    /// The attempt number, starting at 1.
+    pub number: u32,
+    /// The time by which the node must finish, if it has a timeout.
+    pub deadline: Option<Instant>,
+}
+
+impl Default for Attempt {
+    fn default() -> Self {
+        Attempt {
+            number: 1,
+            deadline: None,
+        }
+    }
+}
+
+/// How one of the actions of a node went.
+#[derive(Debug, Clone, PartialEq, Allocative)]
+pub struct ActionResult {
+    pub name: String,
+    pub exit_code: i32,
+    pub success: bool,
+    pub duration_ms: u64,
+}
+
+//
+// -- ActionCtx
+//
+#[derive(Debug, ProvidesStaticType, NoSerialize, Allocative, Clone)]
+pub struct ActionCtx {
+    // large outputs are spilled to disk and read back when accessed
+    stdout: CapturedOutput,
+    stderr: CapturedOutput,
+    // how stdout and stderr are decoded, binary output is only
+    // available as base64
+    encoding: OutputEncoding,
+    exit_code: i32,
+    // whether the exit code is one of the action's ok_exit_codes
+    success: bool,
+    attempt: u32,
+    // which run of the tool this is, counting the action's own retries
+    action_attempt: u32,
+    // the time left before the deadline when the action finished
+    deadline_remaining_ms: Option<u64>,
+    // the environment the tool was spawned with, None if it ran in process
+    env: Option<ActionEnv>,
+    // the files the output was written to, None if it was not logged
+    #[allocative(skip)]
+    log: Option<ActionLog>,
+    // the tool, builtin or "fn_action" the action ran
+    name: String,
+    // how long the action took, including its own retries
+    duration_ms: u64,
+    // the results of every action of the node, in order, only set on the
+    // ctx given to the node's next
+    actions: Vec<ActionResult>,
+}
+starlark_simple_value!(ActionCtx);
+
+#[starlark_value(type = ACTION_CTX_TYPE )]
+impl<'v> StarlarkValue<'v> for ActionCtx {
+    fn get_methods() -> Option<&'static Methods> {
+        static RES: MethodsStatic = MethodsStatic::new();
+        RES.methods(action_ctx_methods)
+    }
+}
+
+impl<'v> UnpackValue<'v> for ActionCtx {
+    fn unpack_value(value: Value<'v>) -> Option<Self> {
+        ActionCtx::from_value(value).map(|v| v.clone())
+    }
+}
+
+#[starlark_module]
+fn action_ctx_methods(builder: &mut MethodsBuilder) {
+    #[starlark(attribute)]
+    fn stdout(this: ActionCtx) -> anyhow::Result<String> {
+        this.stdout()
+    }
+
+    #[starlark(attribute)]
+    fn stderr(this: ActionCtx) -> anyhow::Result<String> {
+        this.stderr()
+    }
+
+    #[starlark(attribute)]
+    fn stdout_bytes_len(this: ActionCtx) -> anyhow::Result<u64> {
+        this.stdout.len()
+    }
+
+    #[starlark(attribute)]
+    fn stdout_base64(this: ActionCtx) -> anyhow::Result<String> {
+        this.stdout.base64()
+    }
+
+    #[starlark(attribute)]
+    fn stderr_base64(this: ActionCtx) -> anyhow::Result<String> {
+        this.stderr.base64()
+    }
+
+    #[starlark(attribute)]
+    fn exit_code(this: ActionCtx) -> anyhow::Result<i32> {
+        Ok(this.exit_code)
+    }
+
+    #[starlark(attribute)]
+    fn success(this: ActionCtx) -> anyhow::Result<bool> {
+        Ok(this.success)
+    }
+
+    #[starlark(attribute)]
+    fn attempt(this: ActionCtx) -> anyhow::Result<u32> {
+        Ok(this.attempt)
+    }
+
+    #[starlark(attribute)]
+    fn action_attempt(this: ActionCtx) -> anyhow::Result<u32> {
+        Ok(this.action_attempt)
+    }
+
+    #[starlark(attribute)]
+    fn deadline_remaining_ms(this: ActionCtx) -> anyhow::Result<NoneOr<u64>> {
+        Ok(NoneOr::from_option(this.deadline_remaining_ms))
+    }
+
+    /// The result of each action of the node, only given to its next.
+    #[starlark(attribute)]
+    fn actions<'v>(this: ActionCtx, heap: &'v Heap) -> anyhow::Result<Vec<Value<'v>>> {
+        Ok(this
+            .actions
+            .iter()
+            .map(|result| {
+                heap.alloc(AllocStruct([
+                    ("name", heap.alloc(result.name.as_str())),
+                    ("exit_code", heap.alloc(result.exit_code)),
+                    ("success", heap.alloc(result.success)),
+                    ("duration_ms", heap.alloc(result.duration_ms)),
+                ]))
+            })
+            .collect())
+    }
+
+    #[starlark(attribute)]
+    fn stdout_log(this: ActionCtx) -> anyhow::Result<NoneOr<String>> {
+        Ok(NoneOr::from_option(
+            this.log
+                .map(|log| log.stdout.to_string_lossy().into_owned()),
+        ))
+    }
+
+    #[starlark(attribute)]
+    fn stderr_log(this: ActionCtx) -> anyhow::Result<NoneOr<String>> {
+        Ok(NoneOr::from_option(
+            this.log
+                .map(|log| log.stderr.to_string_lossy().into_owned()),
+        ))
+    }
+}
 
 impl fmt::Display for ActionCtx {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -233,28 +1435,186 @@ impl fmt::Display for ActionCtx {
 }
 
 impl ActionCtx {
-    fn new(stdout: String, stderr: String, status: ExitStatus) -> Self {
+    pub fn new(stdout: String, stderr: String, exit_code: i32) -> Self {
         ActionCtx {
-            stdout: stdout,
-            stderr: stderr,
-            exit_code: status.code().or(status.signal()).unwrap_or(-1),
+            stdout: stdout.into(),
+            stderr: stderr.into(),
+            encoding: OutputEncoding::default(),
+            exit_code,
+            success: exit_code == 0,
+            attempt: 1,
+            action_attempt: 1,
+            deadline_remaining_ms: None,
+            env: None,
+            log: None,
+            name: String::new(),
+            duration_ms: 0,
+            actions: vec![],
         }
     }
+
+    /// Records the attempt and how much time was left before its deadline.
+    pub fn with_attempt(mut self, attempt: Attempt) -> Self {
+        self.attempt = attempt.number;
+        self.deadline_remaining_ms = attempt.deadline.map(|deadline| {
+            deadline
+                .saturating_duration_since(Instant::now())
+                .as_millis() as u64
+        });
+        self
+    }
+
+    /// Gives the ctx the results of every action of its node, for the
+    /// node's next to branch on.
+    pub fn with_actions(mut self, actions: Vec<ActionResult>) -> Self {
+        self.actions = actions;
+        self
+    }
+
+    /// The result of the action, for the ctx of its node's next.
+    pub fn result(&self) -> ActionResult {
+        ActionResult {
+            name: self.name.clone(),
+            exit_code: self.exit_code,
+            success: self.success,
+            duration_ms: self.duration_ms,
+        }
+    }
+
+    /// The stdout as it was written, which is what a piped sequence gives
+    /// the next action.
+    pub(crate) fn stdout_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        self.stdout.bytes()
+    }
+
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    pub fn action_attempt(&self) -> u32 {
+        self.action_attempt
+    }
+
+    /// Returns the exit code and output to record in the node cache.
+    pub fn cached(&self) -> anyhow::Result<CachedAction> {
+        Ok(CachedAction {
+            exit_code: self.exit_code,
+            stdout: self.stdout.bytes()?,
+            stderr: self.stderr.bytes()?,
+        })
+    }
+
+    pub fn deadline_remaining_ms(&self) -> Option<u64> {
+        self.deadline_remaining_ms
+    }
+
+    /// Returns the captured stdout, reading it from disk if the output was
+    /// too large to keep in memory.
+    pub fn stdout(&self) -> anyhow::Result<String> {
+        self.stdout.decode(self.encoding)
+    }
+
+    /// Returns the captured stderr, reading it from disk if the output was
+    /// too large to keep in memory.
+    pub fn stderr(&self) -> anyhow::Result<String> {
+        self.stderr.decode(self.encoding)
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        self.exit_code
+    }
+
+    /// Whether the exit code is one the action treats as a success, by
+    /// default only 0.
+    pub fn success(&self) -> bool {
+        self.success
+    }
+
+    /// The environment the tool was spawned with, None if the action ran
+    /// in process.
+    pub fn env(&self) -> Option<&ActionEnv> {
+        self.env.as_ref()
+    }
 }
 
 struct OutputCollector {
-    stdout: Vec<u8>,
-    stderr: Vec<u8>,
+    stdout: CaptureBuffer,
+    stderr: CaptureBuffer,
     should_collect: bool,
+    // if set the output is collected but not written to the terminal
+    quiet: bool,
+    // if set the stdout is collected but not written to the terminal
+    piped_stdout: bool,
+    redactors: Option<(LineRedactor, LineRedactor)>,
+    // the stdout and stderr log files the output is also written to
+    log: Option<(File, File)>,
 }
 
 impl OutputCollector {
     fn new(should_collect: bool) -> Self {
         OutputCollector {
-            stdout: Vec::new(),
-            stderr: Vec::new(),
+            stdout: CaptureBuffer::new(),
+            stderr: CaptureBuffer::new(),
             should_collect: should_collect,
+            quiet: false,
+            piped_stdout: false,
+            redactors: None,
+            log: None,
+        }
+    }
+
+    /// Redacts the output before it is shown or collected. Output is then
+    /// passed on a line at a time so a match can not be split by a read.
+    fn with_redactor(mut self, redactor: Option<Arc<Redactor>>) -> Self {
+        self.redactors = redactor.map(|r| (LineRedactor::new(r.clone()), LineRedactor::new(r)));
+        self
+    }
+
+    fn with_quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Collects the stdout without writing it to the terminal, the stderr
+    /// is still written unless the collector is quiet.
+    fn with_piped_stdout(mut self, piped: bool) -> Self {
+        self.piped_stdout = piped;
+        self
+    }
+
+    /// Writes the output, redacted, to the log files as well, even when the
+    /// collector is quiet.
+    fn with_log(mut self, log: Option<(File, File)>) -> Self {
+        self.log = log;
+        self
+    }
+
+    /// Collects the output and writes it to the terminal, redacted, unless
+    /// the collector is quiet.
+    fn emit(&mut self, buf_stdout: &[u8], buf_stderr: &[u8]) -> anyhow::Result<()> {
+        let (buf_stdout, buf_stderr) = match &mut self.redactors {
+            Some((stdout, stderr)) => (
+                Cow::Owned(stdout.push(buf_stdout)),
+                Cow::Owned(stderr.push(buf_stderr)),
+            ),
+            None => (Cow::Borrowed(buf_stdout), Cow::Borrowed(buf_stderr)),
+        };
+        self.write(&buf_stdout, &buf_stderr)
+    }
+
+    fn write(&mut self, buf_stdout: &[u8], buf_stderr: &[u8]) -> anyhow::Result<()> {
+        self.collect(buf_stdout, buf_stderr)?;
+        if let Some((stdout, stderr)) = &mut self.log {
+            stdout.write_all(buf_stdout)?;
+            stderr.write_all(buf_stderr)?;
+        }
+        if !self.quiet {
+            if !self.piped_stdout {
+                io::stdout().write_all(buf_stdout)?;
+            }
+            io::stderr().write_all(buf_stderr)?;
         }
+        Ok(())
     }
 
     fn collect(&mut self, buf_stdout: &[u8], buf_stderr: &[u8]) -> anyhow::Result<()> {
@@ -265,20 +1625,31 @@ impl OutputCollector {
         Ok(())
     }
 
-    fn stdout(&self) -> anyhow::Result<String> {
-        Ok(std::str::from_utf8(&self.stdout).map(|v| v.to_string())?)
-    }
-
-    fn stderr(&self) -> anyhow::Result<String> {
-        Ok(std::str::from_utf8(&self.stderr).map(|v| v.to_string())?)
+    /// Returns the collected stdout and stderr.
+    fn finish(mut self) -> anyhow::Result<(CapturedOutput, CapturedOutput)> {
+        // the last line may not end in a newline
+        if let Some((mut stdout, mut stderr)) = self.redactors.take() {
+            self.write(&stdout.finish(), &stderr.finish())?;
+        }
+        Ok((self.stdout.finish()?, self.stderr.finish()?))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::stdlib::test_utils::assert_env;
+    use crate::downcast_delegate_ref;
+    use crate::runner::{Runner, WorkflowDelegate};
+    use crate::stdlib::env_policy::EnvPolicy;
+    use crate::stdlib::register_native_tool;
+    use crate::stdlib::test_utils::{assert_env, TempEnvVar, TempWorkflowFile};
+    use crate::stdlib::VariableRef;
+    use starlark::environment::Module;
+    use starlark::values::list::ListRef;
     use std::ffi::OsStr;
+    use std::ops::Deref;
+    use std::time::Duration;
+    use tempfile::tempdir;
     use which::which;
 
     #[test]
@@ -286,8 +1657,9 @@ mod tests {
         let mut collector = OutputCollector::new(true);
         let res = collector.collect(&[104, 101, 108, 108, 111], b"world");
         assert!(res.is_ok());
-        assert_eq!(collector.stdout().unwrap(), "hello".to_string());
-        assert_eq!(collector.stderr().unwrap(), "world".to_string());
+        let (stdout, stderr) = collector.finish().unwrap();
+        assert_eq!(stdout.read().unwrap(), "hello".to_string());
+        assert_eq!(stderr.read().unwrap(), "world".to_string());
     }
 
     #[test]
@@ -295,8 +1667,39 @@ mod tests {
         let mut collector = OutputCollector::new(false);
         let res = collector.collect(&[104, 101, 108, 108, 111], b"world");
         assert!(res.is_ok());
-        assert_eq!(collector.stdout().unwrap(), "".to_string());
-        assert_eq!(collector.stderr().unwrap(), "".to_string());
+        let (stdout, stderr) = collector.finish().unwrap();
+        assert_eq!(stdout.read().unwrap(), "".to_string());
+        assert_eq!(stderr.read().unwrap(), "".to_string());
+    }
+
+    #[test]
+    fn test_quiet_output_is_still_collected() {
+        let mut collector = OutputCollector::new(true).with_quiet(true);
+        collector.emit(b"hello", b"world").unwrap();
+        let (stdout, stderr) = collector.finish().unwrap();
+        assert_eq!(stdout.read().unwrap(), "hello".to_string());
+        assert_eq!(stderr.read().unwrap(), "world".to_string());
+
+        let res = assert_env().pass("action(tool=tool(path='foo'), quiet=True)");
+        assert!(Action::from_value(res.value()).unwrap().quiet);
+    }
+
+    #[test]
+    fn test_forward_output_read_error() {
+        struct Broken;
+        impl io::Read for Broken {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("broken pipe"))
+            }
+        }
+        let mut collector = OutputCollector::new(true);
+        let error = forward_output(
+            &mut "out".as_bytes(),
+            &mut BufReader::new(Broken),
+            &mut collector,
+        )
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Unable to read stderr: broken pipe");
     }
 
     #[test]
@@ -308,7 +1711,15 @@ mod tests {
     fn test_require_a_tool() {
         assert_env().fail(
             "action(tool='tool')",
-            "A tool must be passed as the tool in an action",
+            "expected tool for 'tool' in action definition, got",
+        );
+    }
+
+    #[test]
+    fn test_ok_exit_codes_cannot_be_empty() {
+        assert_env().fail(
+            "action(tool=tool(path='grep'), ok_exit_codes=[])",
+            "Invalid attribute 'ok_exit_codes', cannot be empty",
         );
     }
 
@@ -333,7 +1744,7 @@ a = action(
         let action = module.get("a").unwrap();
         let action = Action::from_value(action.value()).unwrap();
 
-        let result = action.arg_list(&"abc").unwrap();
+        let result = action.arg_list(&"abc", Path::new(".")).unwrap();
         let expected = vec![
             "abc".to_string(),
             "--abc".to_string(),
@@ -365,6 +1776,1052 @@ action(
         assert_eq!(args, &["."]);
     }
 
+    #[test]
+    fn test_env() {
+        let mut env = assert_env();
+        let module = env.module(
+            "action.star",
+            r#"
+v = variable()
+a = action(
+  tool = builtin_tool(name = "printenv"),
+  args = ["GREETING"],
+  env = {"GREETING": format("hello {}", v), "PLAIN": "x"},
+)
+"#,
+        );
+        let action = module.get("a").unwrap();
+        let action = Action::from_value(action.value()).unwrap();
+        let command = action.command(&"abc", &PathBuf::new()).unwrap();
+        let envs: Vec<(&OsStr, Option<&OsStr>)> = command.get_envs().collect();
+        assert_eq!(
+            envs,
+            [
+                (OsStr::new("GREETING"), Some(OsStr::new("hello abc"))),
+                (OsStr::new("PLAIN"), Some(OsStr::new("x"))),
+            ]
+        );
+
+        let output = action
+            .command(&"abc", &PathBuf::new())
+            .unwrap()
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "hello abc\n");
+    }
+
+    #[test]
+    fn test_cwd() {
+        let dir = tempfile::tempdir().unwrap();
+        let wd = dir.path().to_path_buf();
+        std::fs::create_dir(wd.join("abc")).unwrap();
+        let mut env = assert_env();
+        let module = env.module(
+            "action.star",
+            r#"
+v = variable()
+a = action(tool = builtin_tool(name = "pwd"), cwd = v)
+b = action(tool = builtin_tool(name = "pwd"), cwd = "missing")
+c = action(tool = builtin_tool(name = "pwd"))
+"#,
+        );
+        let values: Vec<_> = ["a", "b", "c"]
+            .iter()
+            .map(|name| module.get(name).unwrap())
+            .collect();
+        let action = |index: usize| Action::from_value(values[index].value()).unwrap();
+        let output = action(0).command(&"abc", &wd).unwrap().output().unwrap();
+        let expected = wd.join("abc").canonicalize().unwrap();
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap().trim_end(),
+            expected.to_str().unwrap()
+        );
+
+        let error = action(1).command(&"abc", &wd).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "cwd {:?} of the action running 'pwd' is not a directory",
+                wd.join("missing")
+            )
+        );
+        assert_eq!(action(2).cwd(&"abc", &wd).unwrap(), None);
+    }
+
+    #[test]
+    fn test_invalid_cwd() {
+        assert_env().fail(
+            "action(tool=tool(path='foo'), cwd='')",
+            "Invalid attribute 'cwd', must be a non empty string, a variable or a format",
+        );
+        assert_env().fail(
+            "action(tool=tool(path='foo'), cwd=1)",
+            "Invalid attribute 'cwd', must be a non empty string, a variable or a format",
+        );
+        assert_env().fail(
+            "action(tool=native_tool(name='foo'), cwd='bar')",
+            "Invalid attribute 'cwd', is only supported for tools which run as a process",
+        );
+    }
+
+    #[test]
+    fn test_invalid_env() {
+        assert_env().fail(
+            "action(tool=tool(path='foo'), env={'A=B': 'c'})",
+            "Invalid attribute 'env', names cannot be empty or contain '=' or NUL",
+        );
+        assert_env().fail(
+            "action(tool=native_tool(name='foo'), env={'A': 'c'})",
+            "Invalid attribute 'env', is only supported for tools which run as a process",
+        );
+        assert_env().pass("action(tool=tool(wasm='foo.wasm'), env={'A': 'c'})");
+    }
+
+    #[test]
+    fn test_run_native_tool() {
+        register_native_tool("action_test_native", |args| {
+            Ok(ActionCtx::new(args.join(","), "".to_string(), 0))
+        });
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+v = variable(default = "a")
+def _update(ctx):
+    return ctx.stdout
+
+action(
+    tool = native_tool(name = "action_test_native"),
+    args = [v, "b"],
+    setters = [setter(implementation = _update, variable = v)],
+)
+"#,
+        )
+        .unwrap();
+        let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let action = runner.parse_workflow(&mut eval).unwrap();
+        let action = Action::from_value(action).unwrap();
+
+        let holder = runner.delegate();
+        let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+        let ctx = action
+            .run(delegate, &runner.working_dir(), &mut eval)
+            .unwrap();
+        assert_eq!(ctx.stdout().unwrap(), "a,b");
+        assert_eq!(ctx.exit_code(), 0);
+
+        // the setter updated the variable so the next run sees the new value
+        let ctx = action
+            .run(delegate, &runner.working_dir(), &mut eval)
+            .unwrap();
+        assert_eq!(ctx.stdout().unwrap(), "a,b,b");
+    }
+
+    #[test]
+    fn test_can_parse_fn_action() {
+        assert_env().pass("def _f():\n  pass\nfn_action(implementation = _f)");
+    }
+
+    #[test]
+    fn test_fn_action_requires_function() {
+        assert_env().fail(
+            "fn_action(implementation = 'foo')",
+            "expected function for 'implementation' in fn_action definition, got",
+        );
+    }
+
+    #[test]
+    fn test_fn_action_has_no_command() {
+        let res = assert_env().pass("def _f():\n  pass\nfn_action(implementation = _f)");
+        let action = Action::from_value(res.value()).unwrap();
+        assert!(action.command(&"", &PathBuf::new()).is_err());
+    }
+
+    #[test]
+    fn test_run_fn_action() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+major = variable(default = "1")
+version = variable(default = "")
+def _version(major, minor):
+    return "{}.{}".format(major, minor)
+
+def _update(ctx):
+    return ctx.stdout
+
+def _fail():
+    return 3
+
+actions = [
+    fn_action(
+        implementation = _version,
+        args = [major, "2"],
+        setters = [setter(implementation = _update, variable = version)],
+    ),
+    fn_action(implementation = _fail),
+]
+actions
+"#,
+        )
+        .unwrap();
+        let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let actions = runner.parse_workflow(&mut eval).unwrap();
+        let actions = ListRef::from_value(actions).unwrap();
+
+        let holder = runner.delegate();
+        let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+        let action = Action::from_value(actions[0]).unwrap();
+        let ctx = action
+            .run(delegate, &runner.working_dir(), &mut eval)
+            .unwrap();
+        assert_eq!(ctx.stdout().unwrap(), "1.2");
+        assert_eq!(ctx.exit_code(), 0);
+
+        let action = Action::from_value(actions[1]).unwrap();
+        let ctx = action
+            .run(delegate, &runner.working_dir(), &mut eval)
+            .unwrap();
+        assert_eq!(ctx.exit_code(), 3);
+    }
+
+    #[test]
+    fn test_action_ctx_attempt() {
+        let ctx = ActionCtx::new("".to_string(), "".to_string(), 0);
+        assert_eq!(ctx.attempt(), 1);
+        assert_eq!(ctx.deadline_remaining_ms(), None);
+
+        let ctx = ctx.with_attempt(Attempt {
+            number: 3,
+            deadline: Some(Instant::now() + Duration::from_secs(60)),
+        });
+        assert_eq!(ctx.attempt(), 3);
+        let remaining = ctx.deadline_remaining_ms().unwrap();
+        assert!(remaining > 50_000 && remaining <= 60_000);
+
+        let ctx = ctx.with_attempt(Attempt {
+            number: 4,
+            deadline: Some(Instant::now() - Duration::from_secs(1)),
+        });
+        assert_eq!(ctx.deadline_remaining_ms(), Some(0));
+    }
+
+    #[test]
+    fn test_attempt_visible_to_setters() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+v = variable(default = "")
+def _run():
+    return "out"
+
+def _update(ctx):
+    return "{}:{}".format(ctx.attempt, ctx.deadline_remaining_ms)
+
+fn_action(
+    implementation = _run,
+    setters = [setter(implementation = _update, variable = v)],
+)
+"#,
+        )
+        .unwrap();
+        let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let action = runner.parse_workflow(&mut eval).unwrap();
+        let action = Action::from_value(action).unwrap();
+
+        let holder = runner.delegate();
+        let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+        let attempt = Attempt {
+            number: 2,
+            deadline: None,
+        };
+        action
+            .run_attempt(
+                delegate,
+                &runner.working_dir(),
+                attempt,
+                None,
+                Piping::default(),
+                &mut eval,
+            )
+            .unwrap();
+        let v = module.get("v").unwrap();
+        let v = v.downcast_ref::<VariableRef>().unwrap();
+        assert_eq!(delegate.resolve(v.identifier()).unwrap(), "2:None");
+    }
+
+    #[test]
+    fn test_retries() {
+        assert_env().pass("action(tool=tool(path='foo'), retries=2, retry_delay=0)");
+        assert_env().fail(
+            "action(tool=tool(path='foo'), retries=-1)",
+            "Invalid attribute 'retries', cannot be negative",
+        );
+        assert_env().fail(
+            "action(tool=tool(path='foo'), retries=1, retry_delay=-1)",
+            "Invalid attribute 'retry_delay', cannot be a negative number of seconds",
+        );
+        assert_env().fail(
+            "action(tool=tool(path='foo'), retry_delay=1)",
+            "'retry_delay' requires 'retries' in action definition",
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let count = dir.path().join("count");
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            &format!(
+                r#"
+v = variable(default = "")
+sh = builtin_tool(name = "sh")
+script = 'n=$(cat {count:?} 2>/dev/null || echo 0); n=$((n+1)); echo $n > {count:?}; [ $n -ge 3 ]'
+setters = [setter(implementation = lambda ctx: "{{}}:{{}}".format(ctx.action_attempt, ctx.success), variable = v)]
+
+enough = action(tool = sh, args = ["-c", script], retries = 3, setters = setters)
+too_few = action(tool = sh, args = ["-c", script], retries = 1, setters = setters)
+"#,
+                count = count.display().to_string()
+            ),
+        )
+        .unwrap();
+        let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        runner.parse_workflow(&mut eval).unwrap();
+        let holder = runner.delegate();
+        let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+        let v = module.get("v").unwrap();
+        let v = v.downcast_ref::<VariableRef>().unwrap();
+        let mut run = |name: &str| {
+            let action = module.get(name).unwrap();
+            let action = Action::from_value(action).unwrap();
+            action
+                .run(delegate, &runner.working_dir(), &mut eval)
+                .unwrap();
+            delegate.resolve(v.identifier()).unwrap()
+        };
+
+        // the tool succeeds on its third run
+        assert_eq!(run("enough"), "3:True");
+        std::fs::remove_file(&count).unwrap();
+        assert_eq!(run("too_few"), "2:False");
+    }
+
+    #[test]
+    fn test_failing_setter_updates_no_variables() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+a = variable(default = "a")
+b = variable(default = "b")
+def _run():
+    return "out"
+
+def _update(ctx):
+    return ctx.stdout
+
+def _fail(ctx):
+    return 1
+
+fn_action(
+    implementation = _run,
+    setters = [
+        setter(implementation = _update, variable = a),
+        setter(implementation = _fail, variable = b),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let action = runner.parse_workflow(&mut eval).unwrap();
+        let action = Action::from_value(action).unwrap();
+
+        let holder = runner.delegate();
+        let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+        let err = action
+            .run(delegate, &runner.working_dir(), &mut eval)
+            .unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "setter 2 of 2 failed, no variables were updated: setter must return string or None"
+        );
+        let a = module.get("a").unwrap();
+        let a = a.downcast_ref::<VariableRef>().unwrap();
+        assert_eq!(delegate.resolve(a.identifier()).unwrap(), "a");
+    }
+
+    #[test]
+    fn test_setter_returns_what_it_declares() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+a = variable(default = "a")
+def _run():
+    return "out"
+
+def _skip(ctx):
+    return None
+
+fn_action(
+    implementation = _run,
+    setters = [setter(implementation = _skip, variable = a, returns = "string")],
+)
+"#,
+        )
+        .unwrap();
+        let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let action = runner.parse_workflow(&mut eval).unwrap();
+        let action = Action::from_value(action).unwrap();
+
+        let holder = runner.delegate();
+        let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+        let err = action
+            .run(delegate, &runner.working_dir(), &mut eval)
+            .unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "setter 1 of 1 failed, no variables were updated: \
+             setter '_skip' returned None but declares it returns string"
+        );
+    }
+
+    #[test]
+    fn test_shell() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+name = variable(default = "it's $HOME; `true`")
+out = variable()
+
+shell(
+    script = format("printf '%s|' {} {}\necho {}", name, "two words", "*"),
+    shell = "/bin/sh",
+    setters = [setter(implementation = lambda ctx: ctx.stdout, variable = out)],
+)
+"#,
+        )
+        .unwrap();
+        let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let action = runner.parse_workflow(&mut eval).unwrap();
+        let action = Action::from_value(action).unwrap();
+
+        let holder = runner.delegate();
+        let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+        assert_eq!(
+            action.arg_list(delegate, &runner.working_dir()).unwrap(),
+            [
+                "-c",
+                "printf '%s|' \"${1}\" \"${2}\"\necho \"${3}\"",
+                "/bin/sh",
+                "it's $HOME; `true`",
+                "two words",
+                "*",
+            ]
+        );
+        action
+            .run(delegate, &runner.working_dir(), &mut eval)
+            .unwrap();
+        let out = module.get("out").unwrap();
+        let out = out.downcast_ref::<VariableRef>().unwrap();
+        assert_eq!(
+            delegate.resolve(out.identifier()).unwrap(),
+            "it's $HOME; `true`|two words|*\n"
+        );
+    }
+
+    #[test]
+    fn test_env_policy() {
+        let _allowed = TempEnvVar::new("WORKFLOW_TEST_ENV_POLICY_ALLOWED", "a");
+        let _denied = TempEnvVar::new("WORKFLOW_TEST_ENV_POLICY_DENIED", "b");
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+lang = variable(default = "C")
+out = variable()
+
+policy = env_policy(
+    inherit = False,
+    allow = ["WORKFLOW_TEST_ENV_POLICY_ALLOWED", "WORKFLOW_TEST_ENV_POLICY_DENIED"],
+    set = {"LANG": lang, "WORKFLOW_TEST_ENV_POLICY_DENIED": "c"},
+)
+
+action(
+    tool = builtin_tool(name = "env"),
+    env = {"TZ": "UTC"},
+    setters = [setter(implementation = lambda ctx: ctx.stdout, variable = out)],
+)
+"#,
+        )
+        .unwrap();
+        let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let action = runner.parse_workflow(&mut eval).unwrap();
+        let action = Action::from_value(action).unwrap();
+
+        let holder = runner.delegate();
+        let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+        let policy = module.get("policy").unwrap();
+        delegate.set_env_policy(EnvPolicy::from_value(policy).cloned());
+        action
+            .run(delegate, &runner.working_dir(), &mut eval)
+            .unwrap();
+        let out = module.get("out").unwrap();
+        let out = out.downcast_ref::<VariableRef>().unwrap();
+        let mut vars: Vec<String> = delegate
+            .resolve(out.identifier())
+            .unwrap()
+            .lines()
+            .map(|l| l.to_string())
+            .collect();
+        vars.sort();
+        assert_eq!(
+            vars,
+            [
+                "LANG=C",
+                "TZ=UTC",
+                "WORKFLOW_TEST_ENV_POLICY_ALLOWED=a",
+                "WORKFLOW_TEST_ENV_POLICY_DENIED=c",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_setter_from_file() {
+        let dir = tempdir().unwrap();
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            &format!(
+                r#"
+name = variable(default = "version")
+version = variable()
+raw = variable()
+
+shell(
+    script = "printf ' 1.2.3\n' > version.txt",
+    cwd = "{}",
+    setters = [
+        setter(from_file = format("{{}}.txt", name), variable = version),
+        setter(from_file = "version.txt", variable = raw, strip = False),
+    ],
+)
+"#,
+                dir.path().display()
+            ),
+        )
+        .unwrap();
+        let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let action = runner.parse_workflow(&mut eval).unwrap();
+        let action = Action::from_value(action).unwrap();
+
+        let holder = runner.delegate();
+        let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+        action
+            .run(delegate, &runner.working_dir(), &mut eval)
+            .unwrap();
+        let resolve = |name: &str| {
+            let v = module.get(name).unwrap();
+            let v = v.downcast_ref::<VariableRef>().unwrap();
+            delegate.resolve(v.identifier()).unwrap()
+        };
+        assert_eq!(resolve("version"), "1.2.3");
+        assert_eq!(resolve("raw"), " 1.2.3\n");
+    }
+
+    #[test]
+    fn test_setter_json_path() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+version = variable()
+first = variable(default = "unchanged")
+
+shell(
+    script = "echo '{\"version\": \"1.2.3\", \"items\": [null]}'",
+    setters = [
+        setter(json_path = ".version", variable = version),
+        setter(json_path = ".items[0]", variable = first),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let action = runner.parse_workflow(&mut eval).unwrap();
+        let action = Action::from_value(action).unwrap();
+
+        let holder = runner.delegate();
+        let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+        action
+            .run(delegate, &runner.working_dir(), &mut eval)
+            .unwrap();
+        let resolve = |name: &str| {
+            let v = module.get(name).unwrap();
+            let v = v.downcast_ref::<VariableRef>().unwrap();
+            delegate.resolve(v.identifier()).unwrap()
+        };
+        assert_eq!(resolve("version"), "1.2.3");
+        assert_eq!(resolve("first"), "unchanged");
+    }
+
+    #[test]
+    fn test_output_setters_skipped_when_exit_code_not_ok() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+version = variable(default = "unchanged")
+name = variable(default = "unchanged")
+
+shell(
+    script = "echo 'not json'; exit 3",
+    setters = [
+        setter(json_path = ".version", variable = version),
+        setter(from_file = "missing.txt", variable = name),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let action = runner.parse_workflow(&mut eval).unwrap();
+        let action = Action::from_value(action).unwrap();
+
+        let holder = runner.delegate();
+        let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+        let ctx = action
+            .run(delegate, &runner.working_dir(), &mut eval)
+            .unwrap();
+        assert_eq!(ctx.exit_code(), 3);
+        assert!(!ctx.success());
+        let resolve = |name: &str| {
+            let v = module.get(name).unwrap();
+            let v = v.downcast_ref::<VariableRef>().unwrap();
+            delegate.resolve(v.identifier()).unwrap()
+        };
+        assert_eq!(resolve("version"), "unchanged");
+        assert_eq!(resolve("name"), "unchanged");
+    }
+
+    #[test]
+    fn test_shell_fails_if_not_a_script() {
+        assert_env().fail(
+            "shell(script = 1)",
+            "must be a string, a variable or a format",
+        );
+        assert_env().fail(
+            "shell(script = 'true', shell = 1)",
+            "must be a string or a variable",
+        );
+        assert_env().pass("shell(script = 'echo a\\necho b')");
+    }
+
+    #[test]
+    fn test_setter_can_not_set_const() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+version = const(value = "1.0")
+def _run():
+    return "2.0"
+
+def _update(ctx):
+    return ctx.stdout
+
+fn_action(
+    implementation = _run,
+    setters = [setter(implementation = _update, variable = version)],
+)
+"#,
+        )
+        .unwrap();
+        let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let action = runner.parse_workflow(&mut eval).unwrap();
+        let action = Action::from_value(action).unwrap();
+
+        let holder = runner.delegate();
+        let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+        let err = action
+            .run(delegate, &runner.working_dir(), &mut eval)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "cannot set const 'version'");
+    }
+
+    /// Runs printf with an invalid utf8 byte and returns the value of
+    /// `output` read from the ctx by a setter.
+    fn run_printf(output: &str, attributes: &str) -> anyhow::Result<String> {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            &format!(
+                r#"
+v = variable(default = "")
+def _update(ctx):
+    return "{{}}:{{}}".format({}, ctx.stdout_bytes_len)
+
+action(
+    tool = builtin_tool(name = "printf"),
+    args = ["a\\377b"],
+    setters = [setter(implementation = _update, variable = v)],
+    {}
+)
+"#,
+                output, attributes
+            ),
+        )
+        .unwrap();
+        let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let action = runner.parse_workflow(&mut eval)?;
+        let action = Action::from_value(action).unwrap();
+
+        let holder = runner.delegate();
+        let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+        action.run(delegate, &runner.working_dir(), &mut eval)?;
+        let v = module.get("v").unwrap();
+        let v = v.downcast_ref::<VariableRef>().unwrap();
+        delegate.resolve(v.identifier())
+    }
+
+    fn run_false(ok_exit_codes: &str) -> String {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            &format!(
+                r#"
+v = variable(default = "")
+def _update(ctx):
+    return "{{}}:{{}}".format(ctx.exit_code, ctx.success)
+
+action(
+    tool = builtin_tool(name = "false"),
+    setters = [setter(implementation = _update, variable = v)],
+    ok_exit_codes = {},
+)
+"#,
+                ok_exit_codes
+            ),
+        )
+        .unwrap();
+        let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let action = runner.parse_workflow(&mut eval).unwrap();
+        let action = Action::from_value(action).unwrap();
+
+        let holder = runner.delegate();
+        let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+        let ctx = action
+            .run(delegate, &runner.working_dir(), &mut eval)
+            .unwrap();
+        let v = module.get("v").unwrap();
+        let v = v.downcast_ref::<VariableRef>().unwrap();
+        assert_eq!(
+            ctx.success(),
+            delegate.resolve(v.identifier()).unwrap() == "1:True"
+        );
+        delegate.resolve(v.identifier()).unwrap()
+    }
+
+    #[test]
+    fn test_inline_file_arg() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+name = variable(default = "world")
+out = variable(default = "")
+def _update(ctx):
+    return ctx.stdout
+
+action(
+    tool = builtin_tool(name = "cat"),
+    args = [file(name = "greeting.txt", content = format("hello {}", name))],
+    setters = [setter(implementation = _update, variable = out)],
+)
+"#,
+        )
+        .unwrap();
+        let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let action = runner.parse_workflow(&mut eval).unwrap();
+        let action = Action::from_value(action).unwrap();
+
+        let holder = runner.delegate();
+        let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+        let name = module.get("name").unwrap();
+        let name = name.downcast_ref::<VariableRef>().unwrap();
+        delegate
+            .update(name.identifier(), "there".to_string())
+            .unwrap();
+
+        let args = action.arg_list(delegate, &runner.working_dir()).unwrap();
+        assert!(args[0].ends_with("/greeting.txt"));
+        action
+            .run(delegate, &runner.working_dir(), &mut eval)
+            .unwrap();
+        let out = module.get("out").unwrap();
+        let out = out.downcast_ref::<VariableRef>().unwrap();
+        assert_eq!(delegate.resolve(out.identifier()).unwrap(), "hello there");
+    }
+
+    #[test]
+    fn test_glob_arg_expands() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+ext = variable(default = "rs")
+action(
+    tool = builtin_tool(name = "wc"),
+    args = ["-l", glob(format("src/*.{}", ext)), "--"],
+)
+"#,
+        )
+        .unwrap();
+        std::fs::create_dir(file.dir().join("src")).unwrap();
+        for name in ["b.rs", "a.rs", "c.txt"] {
+            std::fs::write(file.dir().join("src").join(name), "").unwrap();
+        }
+        let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let action = runner.parse_workflow(&mut eval).unwrap();
+        let action = Action::from_value(action).unwrap();
+
+        let holder = runner.delegate();
+        let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+        assert_eq!(
+            action.arg_list(delegate, &runner.working_dir()).unwrap(),
+            ["-l", "src/a.rs", "src/b.rs", "--"]
+        );
+        let ext = module.get("ext").unwrap();
+        let ext = ext.downcast_ref::<VariableRef>().unwrap();
+        delegate
+            .update(ext.identifier(), "txt".to_string())
+            .unwrap();
+        assert_eq!(
+            action.arg_list(delegate, &runner.working_dir()).unwrap(),
+            ["-l", "src/c.txt", "--"]
+        );
+    }
+
+    #[test]
+    fn test_glob_only_expands_in_args() {
+        assert_env().fail(
+            "verify(path = glob('*.tar.gz'), sha256 = variable())",
+            "verify does not accept a glob",
+        );
+        assert_env().fail(
+            "render_template(src = 'a.in', dest = 'a', vars = {'files': glob('*')})",
+            "render_template does not accept a glob",
+        );
+    }
+
+    #[test]
+    fn test_render_template() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+port = variable(default = "8080")
+host = variable(default = "localhost")
+render_template(
+    src = "server.conf.in",
+    dest = "server.conf",
+    vars = {"host": format("{}.example.com", host)},
+)
+"#,
+        )
+        .unwrap();
+        std::fs::write(
+            file.dir().join("server.conf.in"),
+            "listen {host}:{port}\nlog {}\n",
+        )
+        .unwrap();
+        let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let action = runner.parse_workflow(&mut eval).unwrap();
+        let action = Action::from_value(action).unwrap();
+        assert!(action.command(&"", &PathBuf::new()).is_err());
+
+        let holder = runner.delegate();
+        let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+        action
+            .run(delegate, &runner.working_dir(), &mut eval)
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(file.dir().join("server.conf")).unwrap(),
+            "listen localhost.example.com:8080\nlog {}\n"
+        );
+    }
+
+    #[test]
+    fn test_render_template_unknown_placeholder() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"render_template(src = "t.in", dest = "t")"#,
+        )
+        .unwrap();
+        std::fs::write(file.dir().join("t.in"), "{missing}").unwrap();
+        let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let action = runner.parse_workflow(&mut eval).unwrap();
+        let action = Action::from_value(action).unwrap();
+
+        let holder = runner.delegate();
+        let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+        let err = action
+            .run(delegate, &runner.working_dir(), &mut eval)
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("unknown placeholders {missing}"));
+        assert!(!file.dir().join("t").exists());
+    }
+
+    #[test]
+    fn test_verify_checks_literal_checksum() {
+        assert_env().pass(&format!(
+            "verify(path = 'a.tar.gz', sha256 = '{}')",
+            "a".repeat(64)
+        ));
+        assert_env().pass("verify(path = 'a.tar.gz', sha256 = variable(default = 'x'))");
+        assert_env().fail(
+            "verify(path = 'a.tar.gz', sha256 = 'abc')",
+            "Invalid attribute 'sha256', must be 64 hex digits",
+        );
+    }
+
+    #[test]
+    fn test_archive_checks_literal_extension() {
+        assert_env().pass("archive(paths = ['bin'], dest = 'out.tar.gz')");
+        assert_env().pass("unarchive(src = 'in.zip', dest = 'out')");
+        assert_env().pass("unarchive(src = 'in.zip', dest = 'out', keep_special_bits = True)");
+        assert_env().fail(
+            "archive(paths = ['bin'], dest = 'out.rar')",
+            "Invalid attribute 'dest', must end in one of .tar, .tar.gz, .tgz or .zip",
+        );
+        assert_env().fail(
+            "unarchive(src = 'in.7z', dest = 'out')",
+            "Invalid attribute 'src', must end in one of .tar, .tar.gz, .tgz or .zip",
+        );
+    }
+
+    #[test]
+    fn test_archive() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"archive(paths = ["pkg"], dest = "pkg.zip")"#,
+        )
+        .unwrap();
+        std::fs::create_dir(file.dir().join("pkg")).unwrap();
+        std::fs::write(file.dir().join("pkg/a.txt"), "a").unwrap();
+        let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let action = runner.parse_workflow(&mut eval).unwrap();
+        let action = Action::from_value(action).unwrap();
+
+        let holder = runner.delegate();
+        let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+        action
+            .run(delegate, &runner.working_dir(), &mut eval)
+            .unwrap();
+        assert!(file.dir().join("pkg.zip").is_file());
+    }
+
+    #[test]
+    fn test_ok_exit_codes() {
+        assert_eq!(run_false("[0]"), "1:False");
+        assert_eq!(run_false("[0, 1]"), "1:True");
+    }
+
+    #[test]
+    fn test_invalid_utf8_output_is_lossy() {
+        assert_eq!(
+            run_printf("ctx.stdout", "strict_utf8 = False").unwrap(),
+            "a\u{FFFD}b:3"
+        );
+    }
+
+    #[test]
+    fn test_invalid_utf8_output_fails_with_strict_utf8() {
+        let err = run_printf("ctx.stdout", "strict_utf8 = True").unwrap_err();
+        assert!(format!("{:#}", err).contains("invalid utf-8"));
+    }
+
+    #[test]
+    fn test_latin1_output() {
+        assert_eq!(
+            run_printf("ctx.stdout", "encoding = \"latin1\"").unwrap(),
+            "a\u{FF}b:3"
+        );
+    }
+
+    #[test]
+    fn test_bytes_output_is_read_as_base64() {
+        assert_eq!(
+            run_printf("ctx.stdout_base64", "encoding = \"bytes\"").unwrap(),
+            "Yf9i:3"
+        );
+        let err = run_printf("ctx.stdout", "encoding = \"bytes\"").unwrap_err();
+        assert!(format!("{:#}", err).contains("read it as base64"));
+    }
+
+    #[test]
+    fn test_strict_utf8_requires_utf8_encoding() {
+        let err = run_printf("ctx.stdout", "encoding = \"latin1\", strict_utf8 = True");
+        assert!(format!("{:#}", err.unwrap_err()).contains("Invalid attribute 'encoding'"));
+    }
+
+    #[test]
+    fn test_arg_problem() {
+        assert_eq!(arg_problem("fine"), None);
+        assert_eq!(arg_problem("a\nb"), Some("a newline"));
+        assert_eq!(arg_problem("a\0b\n"), Some("a NUL byte"));
+    }
+
+    #[test]
+    fn test_run_unregistered_native_tool() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            "action(tool = native_tool(name = \"action_test_missing\"))",
+        )
+        .unwrap();
+        let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let action = runner.parse_workflow(&mut eval).unwrap();
+        let action = Action::from_value(action).unwrap();
+
+        let holder = runner.delegate();
+        let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+        let err = action
+            .run(delegate, &runner.working_dir(), &mut eval)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No native tool registered with name 'action_test_missing'"
+        );
+    }
+
     //         #[test]
     //         fn test_setters_run_and_update() {
     //             let mut env = assert_env();
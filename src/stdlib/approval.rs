@@ -0,0 +1,60 @@
+use crate::stdlib::cancel::CancelToken;
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Who let a manual gate through and when.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Approval {
+    pub by: String,
+    /// Seconds since the unix epoch when it was approved.
+    pub at: u64,
+}
+
+impl Approval {
+    /// An approval given now.
+    pub fn now(by: &str) -> Self {
+        Approval {
+            by: by.to_string(),
+            at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// A manual gate which is waiting to be approved.
+#[derive(Debug, Clone)]
+pub struct ApprovalRequest {
+    /// The name of the gate, which is the name of its node.
+    pub gate: String,
+    pub message: String,
+    /// When the gate gives up waiting, None to wait until it is decided.
+    pub deadline: Option<Instant>,
+    /// Stops the wait when the run is cancelled.
+    pub cancel_token: Option<CancelToken>,
+}
+
+impl ApprovalRequest {
+    /// Fails once the run is cancelled or the deadline has passed, for
+    /// approvers to call while they wait.
+    pub fn check_waiting(&self) -> anyhow::Result<()> {
+        if self.cancel_token.as_ref().is_some_and(|t| t.is_cancelled()) {
+            bail!("The run was cancelled");
+        }
+        if self.deadline.is_some_and(|d| Instant::now() >= d) {
+            bail!("manual gate '{}' was not approved in time", self.gate);
+        }
+        Ok(())
+    }
+}
+
+/// Asks someone to approve manual gates, e.g. on the terminal the workflow
+/// runs in or through the API of the server running it.
+pub trait Approver: fmt::Debug + Send + Sync {
+    /// Blocks until the gate is approved, failing if it is rejected, the
+    /// deadline passes or the run is cancelled.
+    fn ask(&self, request: &ApprovalRequest) -> anyhow::Result<Approval>;
+}
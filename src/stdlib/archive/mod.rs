@@ -0,0 +1,554 @@
+//! Creating and extracting tar, tar.gz and zip archives in process so that
+//! workflows do not depend on the flags of the host's tar and zip.
+
+mod tar;
+mod zip;
+
+use anyhow::{bail, Context};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read};
+use std::os::unix::fs::{symlink, MetadataExt, PermissionsExt};
+use std::path::{Component, Path};
+use std::time::UNIX_EPOCH;
+
+/// An archive format, picked from the extension of the archive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ArchiveFormat {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub(crate) const EXTENSIONS: &'static str = "one of .tar, .tar.gz, .tgz or .zip";
+
+    pub(crate) fn from_path(path: &str) -> Option<Self> {
+        if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else if path.ends_with(".tar") {
+            Some(ArchiveFormat::Tar)
+        } else if path.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// A file, directory or symlink in an archive. Paths are relative and use
+/// `/` without a trailing slash. The contents of files are streamed along
+/// with the entry rather than kept in it.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Entry {
+    path: String,
+    kind: EntryKind,
+    mode: u32,
+    mtime: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum EntryKind {
+    File { size: u64 },
+    Dir,
+    Symlink(String),
+}
+
+/// Called with each entry of an archive and the contents of files, which
+/// are empty for the other kinds of entry.
+pub(crate) type EntryFn<'a> = dyn FnMut(&Entry, &mut dyn Read) -> anyhow::Result<()> + 'a;
+
+/// Archives the paths, which are relative to `root`, into `dest`.
+/// Directories are added along with everything in them.
+pub(crate) fn create(dest: &Path, root: &Path, paths: &[String]) -> anyhow::Result<()> {
+    let format = match ArchiveFormat::from_path(&dest.to_string_lossy()) {
+        Some(format) => format,
+        None => bail!(
+            "cannot tell the format of '{}', it must end in {}",
+            dest.display(),
+            ArchiveFormat::EXTENSIONS
+        ),
+    };
+    let mut names = vec![];
+    for path in paths {
+        match relative_name(Path::new(path)) {
+            Some(name) => names.push((path, name)),
+            None => bail!("cannot archive '{}', it is not inside the root", path),
+        }
+    }
+    let out = File::create(dest).with_context(|| format!("cannot write '{}'", dest.display()))?;
+    let archive = out.metadata()?;
+    let result = write_archive(format, BufWriter::new(out), |add| {
+        for (path, name) in &names {
+            collect(&root.join(path), name.clone(), &archive, add)
+                .with_context(|| format!("cannot archive '{}'", path))?;
+        }
+        Ok(())
+    });
+    if result.is_err() {
+        // a partial archive would look like a finished one to later actions
+        let _ = fs::remove_file(dest);
+    }
+    result
+}
+
+/// Writes an archive in the format to `out`, with the entries `add_all`
+/// adds.
+fn write_archive(
+    format: ArchiveFormat,
+    out: BufWriter<File>,
+    add_all: impl Fn(&mut EntryFn) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let out = match format {
+        ArchiveFormat::Tar => {
+            let mut writer = tar::Writer::new(out);
+            add_all(&mut |entry, data| writer.add(entry, data))?;
+            writer.finish()?
+        }
+        ArchiveFormat::TarGz => {
+            let mut writer = tar::Writer::new(GzEncoder::new(out, Compression::default()));
+            add_all(&mut |entry, data| writer.add(entry, data))?;
+            writer.finish()?.finish()?
+        }
+        ArchiveFormat::Zip => {
+            let mut writer = zip::Writer::new(out);
+            add_all(&mut |entry, data| writer.add(entry, data))?;
+            writer.finish()?
+        }
+    };
+    out.into_inner().map_err(|e| e.into_error())?;
+    Ok(())
+}
+
+/// Extracts the archive `src` into the directory `dest`, creating it if
+/// needed. Entries which would end up outside of `dest` are an error.
+///
+/// Like tar run by a user other than root, the setuid, setgid and sticky
+/// bits of the entries are dropped unless `keep_special_bits` is set, so a
+/// downloaded archive can not create a setuid binary.
+pub(crate) fn extract(src: &Path, dest: &Path, keep_special_bits: bool) -> anyhow::Result<()> {
+    let format = match ArchiveFormat::from_path(&src.to_string_lossy()) {
+        Some(format) => format,
+        None => bail!(
+            "cannot tell the format of '{}', it must end in {}",
+            src.display(),
+            ArchiveFormat::EXTENSIONS
+        ),
+    };
+    let file = File::open(src).with_context(|| format!("cannot read '{}'", src.display()))?;
+    let file = BufReader::new(file);
+    fs::create_dir_all(dest)?;
+
+    // an entry which fails already says which one, any other error is in
+    // reading the archive itself
+    let mut entry_failed = false;
+    let mut write = |entry: &Entry, data: &mut dyn Read| {
+        let result = write_entry(entry, data, dest, keep_special_bits);
+        entry_failed = result.is_err();
+        result.with_context(|| format!("cannot extract '{}'", entry.path))
+    };
+    let result = match format {
+        ArchiveFormat::Tar => tar::read(file, &mut write),
+        ArchiveFormat::TarGz => tar::read(MultiGzDecoder::new(file), &mut write),
+        ArchiveFormat::Zip => zip::read(file, &mut write),
+    };
+    match entry_failed {
+        true => result,
+        false => result.with_context(|| format!("cannot read '{}'", src.display())),
+    }
+}
+
+/// Returns the path as an archive name, None if it would escape the root.
+fn relative_name(path: &Path) -> Option<String> {
+    let mut parts = vec![];
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy().into_owned()),
+            Component::CurDir | Component::RootDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(parts.join("/"))
+}
+
+/// Adds the entry for `path` to the archive, then those of everything in
+/// it if it is a directory.
+fn collect(
+    path: &Path,
+    name: String,
+    archive: &fs::Metadata,
+    add: &mut EntryFn,
+) -> anyhow::Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    // the archive being written may be inside of a directory it archives
+    if metadata.dev() == archive.dev() && metadata.ino() == archive.ino() {
+        return Ok(());
+    }
+    let mtime = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let mode = metadata.permissions().mode() & 0o7777;
+    if metadata.is_symlink() {
+        let target = fs::read_link(path)?.to_string_lossy().into_owned();
+        let entry = Entry {
+            path: name,
+            kind: EntryKind::Symlink(target),
+            mode,
+            mtime,
+        };
+        add(&entry, &mut io::empty())?;
+    } else if metadata.is_dir() {
+        // an empty name is the root itself, which has no entry of its own
+        if !name.is_empty() {
+            let entry = Entry {
+                path: name.clone(),
+                kind: EntryKind::Dir,
+                mode,
+                mtime,
+            };
+            add(&entry, &mut io::empty())?;
+        }
+        let mut children: Vec<_> = fs::read_dir(path)?
+            .map(|e| e.map(|e| e.file_name()))
+            .collect::<Result<_, _>>()?;
+        // sorted so the same files always make the same archive
+        children.sort();
+        for child in children {
+            let child_name = match name.is_empty() {
+                true => child.to_string_lossy().into_owned(),
+                false => format!("{}/{}", name, child.to_string_lossy()),
+            };
+            collect(&path.join(&child), child_name, archive, add)?;
+        }
+    } else {
+        let size = metadata.len();
+        let entry = Entry {
+            path: name,
+            kind: EntryKind::File { size },
+            mode,
+            mtime,
+        };
+        // a file which grows while it is archived is cut at its old size,
+        // the size of the entry was already written
+        add(&entry, &mut File::open(path)?.take(size))?;
+    }
+    Ok(())
+}
+
+/// Writes the entry under `dest`, reading the contents of a file from
+/// `data`.
+fn write_entry(
+    entry: &Entry,
+    data: &mut dyn Read,
+    dest: &Path,
+    keep_special_bits: bool,
+) -> anyhow::Result<()> {
+    let name = match relative_name(Path::new(&entry.path)) {
+        Some(name) if !name.is_empty() => name,
+        Some(_) => return Ok(()),
+        None => bail!("the path is outside of the destination"),
+    };
+    // the symlinks of the archive are not followed, a chain of them which
+    // each stay inside could still lead out of the destination
+    let mut parent = dest.to_path_buf();
+    for part in Path::new(&name).parent().into_iter().flat_map(Path::iter) {
+        parent.push(part);
+        if fs::symlink_metadata(&parent).is_ok_and(|m| m.is_symlink()) {
+            bail!("the path goes through the symlink '{}'", parent.display());
+        }
+    }
+    let path = dest.join(&name);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match &entry.kind {
+        EntryKind::Dir => fs::create_dir_all(&path)?,
+        EntryKind::File { .. } => {
+            if fs::symlink_metadata(&path).is_ok_and(|m| m.is_symlink()) {
+                fs::remove_file(&path)?;
+            }
+            io::copy(data, &mut File::create(&path)?)?;
+            let mode = match keep_special_bits {
+                true => entry.mode & 0o7777,
+                false => entry.mode & 0o777,
+            };
+            if mode != 0 {
+                fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+            }
+        }
+        EntryKind::Symlink(target) => {
+            if !link_stays_inside(&name, target) {
+                bail!(
+                    "the symlink points outside of the destination to '{}'",
+                    target
+                );
+            }
+            if fs::symlink_metadata(&path).is_ok() {
+                fs::remove_file(&path)?;
+            }
+            symlink(target, &path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if a symlink at `name` pointing at `target` resolves to a
+/// path inside of the directory the archive is extracted to. Otherwise a
+/// later entry could be written through the link to anywhere.
+fn link_stays_inside(name: &str, target: &str) -> bool {
+    let mut depth = name.split('/').count() as i64 - 1;
+    for component in Path::new(target).components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir => depth -= 1,
+            Component::RootDir | Component::Prefix(_) => return false,
+        }
+        if depth < 0 {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::process::Command;
+    use tempfile::tempdir;
+
+    fn make_tree(root: &Path) {
+        fs::create_dir_all(root.join("pkg/bin")).unwrap();
+        fs::create_dir_all(root.join("pkg/empty")).unwrap();
+        fs::write(root.join("pkg/bin/tool"), "#!/bin/sh\necho hi\n").unwrap();
+        fs::set_permissions(root.join("pkg/bin/tool"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(root.join("pkg/README"), "read me\n".repeat(50)).unwrap();
+        symlink("bin/tool", root.join("pkg/tool")).unwrap();
+    }
+
+    fn check_tree(root: &Path) {
+        assert_eq!(
+            fs::read_to_string(root.join("pkg/bin/tool")).unwrap(),
+            "#!/bin/sh\necho hi\n"
+        );
+        let mode = fs::metadata(root.join("pkg/bin/tool"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o755);
+        assert_eq!(
+            fs::read_to_string(root.join("pkg/README")).unwrap(),
+            "read me\n".repeat(50)
+        );
+        assert!(root.join("pkg/empty").is_dir());
+        assert_eq!(
+            fs::read_link(root.join("pkg/tool")).unwrap(),
+            PathBuf::from("bin/tool")
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for name in ["out.tar", "out.tar.gz", "out.tgz", "out.zip"] {
+            let dir = tempdir().unwrap();
+            make_tree(dir.path());
+            let archive = dir.path().join(name);
+            create(&archive, dir.path(), &["./pkg".to_string()]).unwrap();
+            let dest = dir.path().join("extracted");
+            extract(&archive, &dest, false).unwrap();
+            check_tree(&dest);
+        }
+    }
+
+    #[test]
+    fn test_host_tools_read_archives() {
+        let dir = tempdir().unwrap();
+        make_tree(dir.path());
+        for (name, command) in [("out.tar.gz", "tar -xzf"), ("out.zip", "unzip -q")] {
+            let archive = dir.path().join(name);
+            create(&archive, dir.path(), &["pkg".to_string()]).unwrap();
+            let dest = dir.path().join(format!("host-{}", name));
+            fs::create_dir_all(&dest).unwrap();
+            let status = Command::new("sh")
+                .arg("-c")
+                .arg(format!("{} {}", command, archive.display()))
+                .current_dir(&dest)
+                .status()
+                .unwrap();
+            assert!(status.success());
+            check_tree(&dest);
+        }
+    }
+
+    #[test]
+    fn test_extract_host_tar_gz() {
+        let dir = tempdir().unwrap();
+        make_tree(dir.path());
+        let status = Command::new("tar")
+            .args(["-czf", "host.tar.gz", "pkg"])
+            .current_dir(dir.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let dest = dir.path().join("extracted");
+        extract(&dir.path().join("host.tar.gz"), &dest, false).unwrap();
+        check_tree(&dest);
+    }
+
+    #[test]
+    fn test_extract_drops_special_bits() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("pkg")).unwrap();
+        fs::write(dir.path().join("pkg/tool"), "tool").unwrap();
+        fs::set_permissions(
+            dir.path().join("pkg/tool"),
+            fs::Permissions::from_mode(0o4755),
+        )
+        .unwrap();
+        // zip is left out as the zip crate only writes the permission bits
+        for name in ["out.tar", "out.tar.gz"] {
+            let archive = dir.path().join(name);
+            create(&archive, dir.path(), &["pkg".to_string()]).unwrap();
+            for (keep_special_bits, expected) in [(false, 0o755), (true, 0o4755)] {
+                let dest = dir.path().join(format!("{}-{}", name, keep_special_bits));
+                extract(&archive, &dest, keep_special_bits).unwrap();
+                let mode = fs::metadata(dest.join("pkg/tool"))
+                    .unwrap()
+                    .permissions()
+                    .mode();
+                assert_eq!(mode & 0o7777, expected, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_unknown_format() {
+        let dir = tempdir().unwrap();
+        let err = create(&dir.path().join("out.rar"), dir.path(), &[]).unwrap_err();
+        assert!(err.to_string().contains("must end in one of .tar"));
+    }
+
+    #[test]
+    fn test_archive_inside_archived_dir() {
+        let dir = tempdir().unwrap();
+        make_tree(dir.path());
+        let archive = dir.path().join("pkg/out.tar.gz");
+        create(&archive, dir.path(), &["pkg".to_string()]).unwrap();
+        let dest = dir.path().join("extracted");
+        extract(&archive, &dest, false).unwrap();
+        check_tree(&dest);
+        assert!(!dest.join("pkg/out.tar.gz").exists());
+    }
+
+    #[test]
+    fn test_failed_archive_is_removed() {
+        let dir = tempdir().unwrap();
+        let archive = dir.path().join("out.zip");
+        let err = create(&archive, dir.path(), &["missing".to_string()]).unwrap_err();
+        assert!(format!("{:#}", err).contains("cannot archive 'missing'"));
+        assert!(!archive.exists());
+    }
+
+    #[test]
+    fn test_paths_outside_root() {
+        let dir = tempdir().unwrap();
+        let err = create(
+            &dir.path().join("out.tar"),
+            dir.path(),
+            &["../x".to_string()],
+        );
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn test_extract_rejects_escaping_entries() {
+        let dir = tempdir().unwrap();
+        let write = |path: &str, kind: EntryKind| {
+            let entry = Entry {
+                path: path.to_string(),
+                kind,
+                mode: 0o644,
+                mtime: 0,
+            };
+            write_entry(&entry, &mut io::empty(), dir.path(), false)
+        };
+        assert!(write("../escaped", EntryKind::File { size: 0 }).is_err());
+        let link = |target: &str| EntryKind::Symlink(target.to_string());
+        assert!(write("a/link", link("../../etc")).is_err());
+        assert!(write("a/link", link("/etc")).is_err());
+        assert!(write("a/link", link("../b")).is_ok());
+    }
+
+    #[test]
+    fn test_extract_rejects_symlink_chains() {
+        let entry = |path: &str, kind: EntryKind| Entry {
+            path: path.to_string(),
+            kind,
+            mode: 0o755,
+            mtime: 0,
+        };
+        // each link stays inside on its own but l2 is created through l1,
+        // next to the destination, so escaped.txt would be written outside
+        let mut writer = tar::Writer::new(vec![]);
+        for (entry, data) in [
+            (entry("x", EntryKind::Dir), ""),
+            (entry("x/l1", EntryKind::Symlink("..".to_string())), ""),
+            (entry("x/l1/l2", EntryKind::Symlink("..".to_string())), ""),
+            (
+                entry("x/l1/l2/escaped.txt", EntryKind::File { size: 3 }),
+                "out",
+            ),
+        ] {
+            writer.add(&entry, &mut data.as_bytes()).unwrap();
+        }
+        let dir = tempdir().unwrap();
+        let archive = dir.path().join("chain.tar");
+        fs::write(&archive, writer.finish().unwrap()).unwrap();
+        let dest = dir.path().join("a/dest");
+        let err = extract(&archive, &dest, false).unwrap_err();
+        assert_eq!(err.to_string(), "cannot extract 'x/l1/l2'");
+        assert!(!dir.path().join("a/escaped.txt").exists());
+        assert!(!dir.path().join("a/l2").exists());
+    }
+
+    #[test]
+    fn test_extract_gzip_members() {
+        let gzip = |data: &[u8]| {
+            let mut encoder = GzEncoder::new(vec![], Compression::default());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        };
+        let dir = tempdir().unwrap();
+        make_tree(dir.path());
+        let tar = dir.path().join("pkg.tar");
+        create(&tar, dir.path(), &["pkg".to_string()]).unwrap();
+        let data = fs::read(&tar).unwrap();
+
+        // the tar split across two gzip members
+        let (first, second) = data.split_at(data.len() / 2);
+        let mut two_members = gzip(first);
+        two_members.extend_from_slice(&gzip(second));
+        let archive = dir.path().join("two.tar.gz");
+        fs::write(&archive, two_members).unwrap();
+        let dest = dir.path().join("two");
+        extract(&archive, &dest, false).unwrap();
+        check_tree(&dest);
+
+        let mut corrupt = gzip(&data);
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 1;
+        let archive = dir.path().join("corrupt.tar.gz");
+        fs::write(&archive, corrupt).unwrap();
+        let err = extract(&archive, &dir.path().join("corrupt"), false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("cannot read '{}'", archive.display())
+        );
+    }
+}
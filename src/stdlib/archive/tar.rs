@@ -0,0 +1,153 @@
+use super::{Entry, EntryFn, EntryKind};
+use anyhow::bail;
+use std::io::{self, Read, Write};
+use tar::{Archive, Builder, EntryType, Header};
+
+/// Writes entries as a tar archive as they are added, with GNU headers for
+/// the paths and symlink targets which are too long for ustar.
+pub(crate) struct Writer<W: Write> {
+    builder: Builder<W>,
+}
+
+impl<W: Write> Writer<W> {
+    pub(crate) fn new(out: W) -> Self {
+        Writer {
+            builder: Builder::new(out),
+        }
+    }
+
+    /// Adds the entry, reading the contents of a file from `data`.
+    pub(crate) fn add(&mut self, entry: &Entry, data: &mut dyn Read) -> anyhow::Result<()> {
+        let mut header = Header::new_gnu();
+        header.set_mode(entry.mode);
+        header.set_mtime(entry.mtime);
+        header.set_size(0);
+        match &entry.kind {
+            EntryKind::File { size } => {
+                header.set_entry_type(EntryType::Regular);
+                header.set_size(*size);
+                self.builder.append_data(&mut header, &entry.path, data)?;
+            }
+            EntryKind::Dir => {
+                header.set_entry_type(EntryType::Directory);
+                self.builder
+                    .append_data(&mut header, &entry.path, io::empty())?;
+            }
+            EntryKind::Symlink(target) => {
+                header.set_entry_type(EntryType::Symlink);
+                self.builder.append_link(&mut header, &entry.path, target)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Ends the archive and returns what it was written to.
+    pub(crate) fn finish(self) -> anyhow::Result<W> {
+        Ok(self.builder.into_inner()?)
+    }
+}
+
+/// Reads the entries of a tar archive, passing each to `f` along with the
+/// contents of files. GNU long names and pax paths are supported, hard
+/// links and device files are not.
+pub(crate) fn read(data: impl Read, f: &mut EntryFn) -> anyhow::Result<()> {
+    let mut archive = Archive::new(data);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let link = entry
+            .link_name()?
+            .map(|link| link.to_string_lossy().into_owned());
+        let (entry_type, mode, mtime) = {
+            let header = entry.header();
+            (header.entry_type(), header.mode()?, header.mtime()?)
+        };
+        let kind = match entry_type {
+            EntryType::Regular | EntryType::Continuous => EntryKind::File { size: entry.size() },
+            EntryType::Directory => EntryKind::Dir,
+            EntryType::Symlink => EntryKind::Symlink(link.unwrap_or_default()),
+            EntryType::XGlobalHeader => continue,
+            other => bail!("unsupported tar entry type {:?} for '{}'", other, path),
+        };
+        let read = Entry {
+            path: path.trim_end_matches('/').to_string(),
+            kind,
+            mode,
+            mtime,
+        };
+        f(&read, &mut entry)?;
+    }
+    // the end of the archive comes before that of a gzip stream, whose
+    // checksum is only checked once it is read
+    io::copy(&mut archive.into_inner(), &mut io::sink())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, data: &str) -> (Entry, Vec<u8>) {
+        let entry = Entry {
+            path: path.to_string(),
+            kind: EntryKind::File {
+                size: data.len() as u64,
+            },
+            mode: 0o644,
+            mtime: 1_700_000_000,
+        };
+        (entry, data.as_bytes().to_vec())
+    }
+
+    fn other(path: &str, kind: EntryKind, mode: u32) -> (Entry, Vec<u8>) {
+        let entry = Entry {
+            path: path.to_string(),
+            kind,
+            mode,
+            mtime: 1_700_000_000,
+        };
+        (entry, vec![])
+    }
+
+    fn write_all(entries: &[(Entry, Vec<u8>)]) -> Vec<u8> {
+        let mut writer = Writer::new(vec![]);
+        for (entry, data) in entries {
+            writer.add(entry, &mut data.as_slice()).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    fn read_all(data: &[u8]) -> anyhow::Result<Vec<(Entry, Vec<u8>)>> {
+        let mut entries = vec![];
+        read(data, &mut |entry, data| {
+            let mut contents = vec![];
+            data.read_to_end(&mut contents)?;
+            entries.push((entry.clone(), contents));
+            Ok(())
+        })?;
+        Ok(entries)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let long = format!("{}/{}", "d".repeat(120), "f".repeat(90));
+        let entries = vec![
+            other("bin", EntryKind::Dir, 0o755),
+            file("bin/tool", "#!/bin/sh\necho hi\n"),
+            other("bin/link", EntryKind::Symlink("tool".to_string()), 0o777),
+            other("bin/long", EntryKind::Symlink("t".repeat(150)), 0o777),
+            file(&long, ""),
+            file(&"a".repeat(300), "long"),
+        ];
+        let data = write_all(&entries);
+        assert_eq!(data.len() % 512, 0);
+        assert_eq!(read_all(&data).unwrap(), entries);
+    }
+
+    #[test]
+    fn test_read_bad_checksum() {
+        let mut data = write_all(&[file("a", "b")]);
+        data[0] = b'c';
+        assert!(read_all(&data).is_err());
+    }
+}
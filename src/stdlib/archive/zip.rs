@@ -0,0 +1,222 @@
+use super::{Entry, EntryFn, EntryKind};
+use anyhow::bail;
+use std::io::{self, Read, Seek, Write};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, DateTime, ZipArchive, ZipWriter};
+
+/// The longest symlink target read from an archive, that of linux paths.
+const MAX_LINK_LEN: u64 = 4096;
+
+/// Writes entries as a zip archive as they are added, with the files
+/// deflated.
+pub(crate) struct Writer<W: Write + Seek> {
+    writer: ZipWriter<W>,
+}
+
+impl<W: Write + Seek> Writer<W> {
+    pub(crate) fn new(out: W) -> Self {
+        Writer {
+            writer: ZipWriter::new(out),
+        }
+    }
+
+    /// Adds the entry, reading the contents of a file from `data`.
+    pub(crate) fn add(&mut self, entry: &Entry, data: &mut dyn Read) -> anyhow::Result<()> {
+        let (time, date) = dos_time(entry.mtime);
+        let options = SimpleFileOptions::default()
+            .unix_permissions(entry.mode)
+            .last_modified_time(DateTime::try_from_msdos(date, time)?);
+        match &entry.kind {
+            EntryKind::File { size } => {
+                let options = options
+                    .compression_method(CompressionMethod::Deflated)
+                    .large_file(*size >= u32::MAX as u64);
+                self.writer.start_file(entry.path.as_str(), options)?;
+                io::copy(data, &mut self.writer)?;
+            }
+            EntryKind::Dir => self.writer.add_directory(entry.path.as_str(), options)?,
+            EntryKind::Symlink(target) => {
+                self.writer
+                    .add_symlink(entry.path.as_str(), target, options)?
+            }
+        }
+        Ok(())
+    }
+
+    /// Ends the archive and returns what it was written to.
+    pub(crate) fn finish(self) -> anyhow::Result<W> {
+        Ok(self.writer.finish()?)
+    }
+}
+
+/// Reads the entries of a zip archive, passing each to `f` along with the
+/// contents of files. Only stored and deflated entries are supported.
+pub(crate) fn read(data: impl Read + Seek, f: &mut EntryFn) -> anyhow::Result<()> {
+    let mut archive = ZipArchive::new(data)?;
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index)?;
+        let name = file.name().to_string();
+        let mtime = file
+            .last_modified()
+            .map(|time| unix_time(time.timepart(), time.datepart()))
+            .unwrap_or_default();
+        // only archives made on unix have a mode
+        let mode = file.unix_mode().unwrap_or_default();
+        let kind = if file.is_dir() {
+            EntryKind::Dir
+        } else if file.is_symlink() {
+            let mut target = vec![];
+            if let Err(e) = file.by_ref().take(MAX_LINK_LEN).read_to_end(&mut target) {
+                bail!(
+                    "the contents of '{}' in the zip archive are corrupt: {}",
+                    name,
+                    e
+                );
+            }
+            EntryKind::Symlink(String::from_utf8_lossy(&target).into_owned())
+        } else {
+            EntryKind::File { size: file.size() }
+        };
+        let read = Entry {
+            path: name.trim_end_matches('/').to_string(),
+            kind,
+            mode: mode & 0o7777,
+            mtime,
+        };
+        f(&read, &mut file)?;
+    }
+    Ok(())
+}
+
+/// Converts seconds since the unix epoch to the MS-DOS time and date used
+/// by zip, which can not represent times before 1980.
+fn dos_time(mtime: u64) -> (u16, u16) {
+    let (days, secs) = ((mtime / 86400) as i64, mtime % 86400);
+    // from Howard Hinnant's days_from_civil algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let time = ((secs / 3600) << 11) | ((secs % 3600 / 60) << 5) | ((secs % 60) / 2);
+    let date = ((year - 1980) << 9) | (month << 5) | day;
+    (time as u16, date as u16)
+}
+
+/// The inverse of `dos_time`.
+fn unix_time(time: u16, date: u16) -> u64 {
+    let (year, month, day) = (
+        (date >> 9) as i64 + 1980,
+        (date >> 5 & 0xf) as i64,
+        (date & 0x1f) as i64,
+    );
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    let secs =
+        (time >> 11) as i64 * 3600 + (time >> 5 & 0x3f) as i64 * 60 + (time & 0x1f) as i64 * 2;
+    (days * 86400 + secs).max(0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn entry(path: &str, kind: EntryKind, mode: u32, mtime: u64) -> Entry {
+        Entry {
+            path: path.to_string(),
+            kind,
+            mode,
+            mtime,
+        }
+    }
+
+    fn write_all(entries: &[(Entry, Vec<u8>)]) -> Vec<u8> {
+        let mut writer = Writer::new(Cursor::new(vec![]));
+        for (entry, data) in entries {
+            writer.add(entry, &mut data.as_slice()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn read_all(data: &[u8]) -> anyhow::Result<Vec<(Entry, Vec<u8>)>> {
+        let mut entries = vec![];
+        read(Cursor::new(data), &mut |entry, data| {
+            let mut contents = vec![];
+            data.read_to_end(&mut contents)?;
+            entries.push((entry.clone(), contents));
+            Ok(())
+        })?;
+        Ok(entries)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let tool = "echo hi\n".repeat(100).into_bytes();
+        let entries = vec![
+            (entry("bin", EntryKind::Dir, 0o755, 1_700_000_000), vec![]),
+            (
+                entry(
+                    "bin/tool",
+                    EntryKind::File {
+                        size: tool.len() as u64,
+                    },
+                    0o755,
+                    1_700_000_002,
+                ),
+                tool,
+            ),
+            (
+                entry(
+                    "bin/empty",
+                    EntryKind::File { size: 0 },
+                    0o644,
+                    1_700_000_000,
+                ),
+                vec![],
+            ),
+            (
+                entry(
+                    "bin/link",
+                    EntryKind::Symlink("tool".to_string()),
+                    0o777,
+                    1_700_000_000,
+                ),
+                vec![],
+            ),
+        ];
+        let data = write_all(&entries);
+        assert_eq!(read_all(&data).unwrap(), entries);
+    }
+
+    #[test]
+    fn test_dos_time() {
+        // 2023-11-14 22:13:20 UTC
+        let (time, date) = dos_time(1_700_000_000);
+        assert_eq!(date, (43 << 9) | (11 << 5) | 14);
+        assert_eq!(time, (22 << 11) | (13 << 5) | 10);
+        assert_eq!(unix_time(time, date), 1_700_000_000);
+        assert_eq!(dos_time(0), (0, (1 << 5) | 1));
+    }
+
+    #[test]
+    fn test_read_corrupt() {
+        assert!(read_all(b"not a zip").is_err());
+        let file = entry("a", EntryKind::File { size: 3 }, 0o644, 0);
+        let mut data = write_all(&[(file, b"abc".to_vec())]);
+        data[30 + 1] = b'x';
+        assert!(read_all(&data).is_err());
+    }
+}
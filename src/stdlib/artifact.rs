@@ -0,0 +1,129 @@
+use crate::stdlib::errors::StdlibError;
+use crate::stdlib::glob::match_pattern;
+use crate::stdlib::node::check_artifact_pattern;
+use crate::stdlib::variable_resolver::VariableResolver;
+use crate::stdlib::ARTIFACT_TYPE;
+use allocative::Allocative;
+use anyhow::bail;
+use starlark::starlark_simple_value;
+use starlark::values::starlark_value;
+use starlark::values::NoSerialize;
+use starlark::values::ProvidesStaticType;
+use starlark::values::StarlarkValue;
+use std::fmt;
+
+pub(crate) fn artifact_impl(node: &str, pattern: &str) -> anyhow::Result<Artifact> {
+    if node.is_empty() {
+        bail!(StdlibError::new_invalid_attr(
+            "node",
+            "must be the name of a node",
+            "\"\""
+        ));
+    }
+    check_artifact_pattern("pattern", pattern)?;
+    Ok(Artifact {
+        node: node.to_string(),
+        pattern: pattern.to_string(),
+    })
+}
+
+/// The artifacts a node collected which match a pattern. Used as an action
+/// arg it expands to the path of each matching copy, so a node can use what
+/// an earlier node produced without knowing where it was built.
+#[derive(Debug, ProvidesStaticType, NoSerialize, Allocative, Clone)]
+pub struct Artifact {
+    node: String,
+    pattern: String,
+}
+starlark_simple_value!(Artifact);
+
+#[starlark_value(type = ARTIFACT_TYPE)]
+impl<'v> StarlarkValue<'v> for Artifact {}
+
+impl Artifact {
+    /// The name of the node which produces the artifact.
+    pub fn node(&self) -> &str {
+        &self.node
+    }
+
+    /// Returns the sorted paths of the collected files which match the
+    /// pattern, failing if the node has not collected its artifacts in this
+    /// run or none of them match.
+    pub fn expand<T: VariableResolver>(&self, resolver: &T) -> anyhow::Result<Vec<String>> {
+        let dir = match resolver.context().node_artifacts_dir(&self.node) {
+            Some(dir) => dir,
+            None => bail!(
+                "{} can not be used, node '{}' has not collected its artifacts in this run",
+                self,
+                self.node
+            ),
+        };
+        let paths: Vec<String> = match_pattern(&self.pattern, &dir)?
+            .into_iter()
+            .map(|path| dir.join(path))
+            .filter(|path| path.is_file())
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+        if paths.is_empty() {
+            bail!(
+                "{} did not match any of the artifacts node '{}' collected",
+                self,
+                self.node
+            );
+        }
+        Ok(paths)
+    }
+}
+
+impl fmt::Display for Artifact {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "artifact({:?}, {:?})", self.node, self.pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stdlib::test_utils::assert_env;
+
+    #[test]
+    fn test_artifact_checks_its_attributes() {
+        assert_env().pass("artifact('build', 'dist/*.tar.gz')");
+        assert_env().fail(
+            "artifact('', 'dist/*')",
+            "Invalid attribute 'node', must be the name of a node",
+        );
+        assert_env().fail(
+            "artifact('build', '/dist/*')",
+            "Invalid attribute 'pattern', must be relative to the working dir",
+        );
+    }
+
+    #[test]
+    fn test_workflow_checks_the_producing_node() {
+        let workflow = |producer: &str, artifacts: &str| {
+            format!(
+                r#"
+workflow(
+    entrypoint = "build",
+    graph = [
+        node(name = "build", action = action(tool = tool(path = "make")), artifacts = {}),
+        node(
+            name = "deploy",
+            action = action(tool = tool(path = "scp"), args = [artifact("{}", "dist/*")]),
+        ),
+    ],
+)"#,
+                artifacts, producer
+            )
+        };
+        assert_env().pass(&workflow("build", "['dist/*']"));
+        assert_env().fail(
+            &workflow("test", "['dist/*']"),
+            r#"node 'deploy' uses artifact("test", "dist/*") but there is no node named 'test'"#,
+        );
+        assert_env().fail(
+            &workflow("build", "[]"),
+            r#"node 'deploy' uses artifact("build", "dist/*") but node 'build' declares no artifacts"#,
+        );
+    }
+}
@@ -0,0 +1,250 @@
+use crate::stdlib::action::ActionCtx;
+use crate::stdlib::archive;
+use crate::stdlib::variable_resolver::VariableResolver;
+use allocative::Allocative;
+use anyhow::{bail, Context};
+use regex::{Captures, Regex};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+/// Actions which the stdlib runs in process rather than by spawning a tool.
+/// Their args are resolved like those of any other action and passed to
+/// `run` in the order the builtin put them in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Allocative)]
+pub(crate) enum BuiltinAction {
+    /// args: src, dest, followed by the name and value of each var.
+    RenderTemplate,
+    /// args: path, sha256.
+    Verify,
+    /// args: dest, root, followed by the paths to archive.
+    Archive,
+    /// args: src, dest, whether to keep the setuid, setgid and sticky bits
+    /// as "true" or "false".
+    Unarchive,
+    /// args: gate name, message, timeout in seconds or 0 to wait until it
+    /// is decided.
+    ManualGate,
+}
+
+impl BuiltinAction {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            BuiltinAction::RenderTemplate => "render_template",
+            BuiltinAction::Verify => "verify",
+            BuiltinAction::Archive => "archive",
+            BuiltinAction::Unarchive => "unarchive",
+            BuiltinAction::ManualGate => "manual_gate",
+        }
+    }
+
+    /// Runs the action with its resolved args. Relative paths are relative
+    /// to the working dir.
+    pub(crate) fn run<T: VariableResolver>(
+        &self,
+        args: &[String],
+        resolver: &T,
+        working_dir: &Path,
+    ) -> anyhow::Result<ActionCtx> {
+        match self {
+            BuiltinAction::RenderTemplate => render_template(args, resolver, working_dir),
+            BuiltinAction::Verify => verify(args, working_dir),
+            BuiltinAction::Archive => {
+                archive::create(
+                    &working_dir.join(&args[0]),
+                    &working_dir.join(&args[1]),
+                    &args[2..],
+                )?;
+                Ok(ActionCtx::new(String::new(), String::new(), 0))
+            }
+            BuiltinAction::Unarchive => {
+                archive::extract(
+                    &working_dir.join(&args[0]),
+                    &working_dir.join(&args[1]),
+                    args[2] == "true",
+                )?;
+                Ok(ActionCtx::new(String::new(), String::new(), 0))
+            }
+            BuiltinAction::ManualGate => {
+                let timeout = match args[2].parse()? {
+                    0 => None,
+                    secs => Some(Duration::from_secs(secs)),
+                };
+                let approval = resolver.context().approve(&args[0], &args[1], timeout)?;
+                Ok(ActionCtx::new(
+                    format!("approved by {}\n", approval.by),
+                    String::new(),
+                    0,
+                ))
+            }
+        }
+    }
+}
+
+fn render_template<T: VariableResolver>(
+    args: &[String],
+    resolver: &T,
+    working_dir: &Path,
+) -> anyhow::Result<ActionCtx> {
+    let (src, dest) = (working_dir.join(&args[0]), working_dir.join(&args[1]));
+    let vars: HashMap<&str, &str> = args[2..]
+        .chunks(2)
+        .map(|kv| (kv[0].as_str(), kv[1].as_str()))
+        .collect();
+    let variables = resolver.values().unwrap_or_default();
+
+    let template = fs::read_to_string(&src)
+        .with_context(|| format!("cannot read template '{}'", src.display()))?;
+    let rendered = render(&template, |name| {
+        vars.get(name)
+            .map(|v| v.to_string())
+            .or_else(|| variables.get(name).map(|c| c.value.clone()))
+    })
+    .with_context(|| format!("cannot render template '{}'", src.display()))?;
+    fs::write(&dest, rendered).with_context(|| format!("cannot write '{}'", dest.display()))?;
+
+    Ok(ActionCtx::new(String::new(), String::new(), 0))
+}
+
+fn verify(args: &[String], working_dir: &Path) -> anyhow::Result<ActionCtx> {
+    let (path, expected) = (working_dir.join(&args[0]), args[1].to_lowercase());
+    if !is_sha256(&expected) {
+        bail!(
+            "cannot verify '{}', '{}' is not a sha256 checksum",
+            path.display(),
+            args[1]
+        );
+    }
+    let actual =
+        sha256_file(&path).with_context(|| format!("cannot verify '{}'", path.display()))?;
+    if actual != expected {
+        bail!(
+            "checksum mismatch for '{}', expected sha256 {} got {}",
+            path.display(),
+            expected,
+            actual
+        );
+    }
+    Ok(ActionCtx::new(String::new(), String::new(), 0))
+}
+
+/// Returns true if the string looks like a hex encoded sha256 checksum.
+pub(crate) fn is_sha256(s: &str) -> bool {
+    s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Returns the hex encoded sha256 of the file, which is read in chunks so
+/// large artifacts are not loaded into memory.
+fn sha256_file(path: &Path) -> anyhow::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Replaces every `{name}` in the template with the value returned by
+/// `lookup`. Braces which do not surround a name, like those in json, are
+/// left alone.
+fn render<F>(template: &str, lookup: F) -> anyhow::Result<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let re = Regex::new(r"\{(?<name>[A-Za-z_][A-Za-z0-9_.]*)\}").unwrap();
+
+    // Look up every placeholder first so all of the unknown ones can be
+    // reported at once.
+    let mut values: HashMap<String, String> = HashMap::new();
+    let mut unknown: Vec<String> = vec![];
+    for caps in re.captures_iter(template) {
+        let name = &caps["name"];
+        match lookup(name) {
+            Some(value) => {
+                values.insert(name.to_string(), value);
+            }
+            None if !unknown.iter().any(|n| n == name) => unknown.push(name.to_string()),
+            None => {}
+        }
+    }
+    if !unknown.is_empty() {
+        bail!("unknown placeholders {{{}}}", unknown.join("}, {"));
+    }
+
+    Ok(re
+        .replace_all(template, |caps: &Captures| values[&caps["name"]].clone())
+        .into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "name" => Some("world".to_string()),
+            "build.dir" => Some("out".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            render("hello {name}, {name}! {build.dir}/bin", lookup).unwrap(),
+            "hello world, world! out/bin"
+        );
+    }
+
+    #[test]
+    fn test_render_leaves_other_braces() {
+        assert_eq!(
+            render(
+                r#"{"name": "{name}", "empty": {}, "a": { "b": 1 }}"#,
+                lookup
+            )
+            .unwrap(),
+            r#"{"name": "world", "empty": {}, "a": { "b": 1 }}"#
+        );
+    }
+
+    #[test]
+    fn test_render_reports_all_unknown_placeholders() {
+        let err = render("{a} {name} {b} {a}", lookup).unwrap_err();
+        assert_eq!(err.to_string(), "unknown placeholders {a}, {b}");
+    }
+
+    #[test]
+    fn test_verify() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("artifact"), "abc").unwrap();
+        let args = |sha256: &str| vec!["artifact".to_string(), sha256.to_string()];
+
+        assert!(verify(&args(ABC_SHA256), dir.path()).is_ok());
+        assert!(verify(&args(&ABC_SHA256.to_uppercase()), dir.path()).is_ok());
+
+        let other = "0".repeat(64);
+        let err = verify(&args(&other), dir.path()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "checksum mismatch for '{}', expected sha256 {} got {}",
+                dir.path().join("artifact").display(),
+                other,
+                ABC_SHA256
+            )
+        );
+        assert!(verify(&args("abc"), dir.path()).is_err());
+        assert!(verify(&["missing".to_string(), other], dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_is_sha256() {
+        assert!(is_sha256(ABC_SHA256));
+        assert!(!is_sha256(&ABC_SHA256[1..]));
+        assert!(!is_sha256(&"g".repeat(64)));
+    }
+}
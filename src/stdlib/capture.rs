@@ -0,0 +1,386 @@
+use crate::stdlib::errors::StdlibError;
+use allocative::Allocative;
+use anyhow::{anyhow, bail};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use uuid::Uuid;
+
+/// The number of bytes of a single stream which are kept in memory before
+/// the output is spilled to a temporary file.
+pub(crate) const MEMORY_CAPTURE_LIMIT: usize = 1024 * 1024;
+
+/// How captured output is decoded into the strings given to setters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Allocative)]
+pub(crate) enum OutputEncoding {
+    /// Invalid utf8 sequences are replaced by U+FFFD.
+    #[default]
+    Utf8,
+    /// Output which is not valid utf8 is an error.
+    StrictUtf8,
+    /// Each byte is decoded as the character with the same code point.
+    Latin1,
+    /// The output is binary, it is only available as base64.
+    Bytes,
+}
+
+impl OutputEncoding {
+    /// Returns the encoding for the `encoding` and `strict_utf8` attributes
+    /// of an action.
+    pub(crate) fn from_attributes(encoding: &str, strict_utf8: bool) -> anyhow::Result<Self> {
+        match (encoding, strict_utf8) {
+            ("utf8", false) => Ok(OutputEncoding::Utf8),
+            ("utf8", true) => Ok(OutputEncoding::StrictUtf8),
+            ("latin1" | "bytes", true) => bail!(StdlibError::new_invalid_attr(
+                "encoding",
+                "must be 'utf8' when strict_utf8 is set",
+                encoding
+            )),
+            ("latin1", false) => Ok(OutputEncoding::Latin1),
+            ("bytes", false) => Ok(OutputEncoding::Bytes),
+            (other, _) => bail!(StdlibError::new_invalid_attr(
+                "encoding",
+                "must be one of 'utf8', 'latin1' or 'bytes'",
+                other
+            )),
+        }
+    }
+
+    /// Returns the `encoding` and `strict_utf8` attributes of an action
+    /// which decodes its output this way.
+    pub(crate) fn attributes(&self) -> (&'static str, bool) {
+        match self {
+            OutputEncoding::Utf8 => ("utf8", false),
+            OutputEncoding::StrictUtf8 => ("utf8", true),
+            OutputEncoding::Latin1 => ("latin1", false),
+            OutputEncoding::Bytes => ("bytes", false),
+        }
+    }
+}
+
+/// The captured output of a stream. Large outputs live in a temporary file
+/// and are only read back when they are accessed.
+#[derive(Debug, Clone, Allocative)]
+pub(crate) enum CapturedOutput {
+    InMemory(Vec<u8>),
+    Spilled(#[allocative(skip)] Arc<SpillFile>),
+}
+
+impl CapturedOutput {
+    /// Returns the raw captured bytes, reading them from disk if they were
+    /// spilled.
+    pub(crate) fn bytes(&self) -> anyhow::Result<Vec<u8>> {
+        match self {
+            CapturedOutput::InMemory(bytes) => Ok(bytes.clone()),
+            CapturedOutput::Spilled(file) => Ok(fs::read(&file.path)?),
+        }
+    }
+
+    /// Returns the captured output with invalid utf8 sequences replaced by
+    /// U+FFFD, tools can emit binary or latin-1 text.
+    pub(crate) fn read(&self) -> anyhow::Result<String> {
+        Ok(String::from_utf8_lossy(&self.bytes()?).into_owned())
+    }
+
+    /// Returns the captured output, failing if it is not valid utf8.
+    pub(crate) fn read_strict(&self) -> anyhow::Result<String> {
+        Ok(String::from_utf8(self.bytes()?).map_err(|e| e.utf8_error())?)
+    }
+
+    /// Returns the captured output decoded with the given encoding. Binary
+    /// output can not be decoded and must be read with `base64()`.
+    pub(crate) fn decode(&self, encoding: OutputEncoding) -> anyhow::Result<String> {
+        match encoding {
+            OutputEncoding::Utf8 => self.read(),
+            OutputEncoding::StrictUtf8 => self.read_strict(),
+            OutputEncoding::Latin1 => Ok(self.bytes()?.into_iter().map(char::from).collect()),
+            OutputEncoding::Bytes => {
+                bail!("the output is binary (encoding = \"bytes\"), read it as base64 instead")
+            }
+        }
+    }
+
+    /// Returns the captured bytes encoded as standard, padded base64.
+    pub(crate) fn base64(&self) -> anyhow::Result<String> {
+        Ok(base64_encode(&self.bytes()?))
+    }
+
+    /// The number of bytes captured.
+    pub(crate) fn len(&self) -> anyhow::Result<u64> {
+        match self {
+            CapturedOutput::InMemory(bytes) => Ok(bytes.len() as u64),
+            CapturedOutput::Spilled(file) => Ok(fs::metadata(&file.path)?.len()),
+        }
+    }
+
+    #[cfg(test)]
+    fn is_spilled(&self) -> bool {
+        matches!(self, CapturedOutput::Spilled(_))
+    }
+}
+
+impl From<String> for CapturedOutput {
+    fn from(s: String) -> Self {
+        CapturedOutput::InMemory(s.into_bytes())
+    }
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3f] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+const SPILL_FILE_PREFIX: &str = "workflow-output-";
+
+/// Returns the spill files in `temp_dir` which were last modified before
+/// `before`. They are left behind by runs which were killed while the
+/// output of an action was spilled.
+pub(crate) fn stale_spill_files(temp_dir: &Path, before: SystemTime) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(temp_dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().into_string().ok()?;
+            let metadata = entry.metadata().ok()?;
+            let stale = name.starts_with(SPILL_FILE_PREFIX)
+                && metadata.is_file()
+                && metadata.modified().ok()? < before;
+            stale.then(|| entry.path())
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// A temporary file holding spilled output, removed when dropped.
+#[derive(Debug)]
+pub(crate) struct SpillFile {
+    path: PathBuf,
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Collects a stream in memory until `limit` bytes have been written after
+/// which all of the output is moved to a temporary file.
+#[derive(Debug)]
+pub(crate) struct CaptureBuffer {
+    memory: Vec<u8>,
+    spill: Option<(File, SpillFile)>,
+    limit: usize,
+}
+
+impl CaptureBuffer {
+    pub(crate) fn new() -> Self {
+        CaptureBuffer::with_limit(MEMORY_CAPTURE_LIMIT)
+    }
+
+    pub(crate) fn with_limit(limit: usize) -> Self {
+        CaptureBuffer {
+            memory: Vec::new(),
+            spill: None,
+            limit,
+        }
+    }
+
+    pub(crate) fn write_all(&mut self, buf: &[u8]) -> anyhow::Result<()> {
+        if self.spill.is_none() && self.memory.len() + buf.len() > self.limit {
+            let spill = SpillFile {
+                path: std::env::temp_dir().join(format!("{}{}", SPILL_FILE_PREFIX, Uuid::new_v4())),
+            };
+            // the output is not redacted yet, so only the user can read it
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&spill.path)?;
+            file.write_all(&self.memory)?;
+            self.memory = Vec::new();
+            self.spill = Some((file, spill));
+        }
+        match &mut self.spill {
+            Some((file, _)) => file.write_all(buf)?,
+            None => self.memory.extend_from_slice(buf),
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(self) -> anyhow::Result<CapturedOutput> {
+        match self.spill {
+            Some((mut file, spill)) => {
+                file.flush()?;
+                Ok(CapturedOutput::Spilled(Arc::new(spill)))
+            }
+            None => Ok(CapturedOutput::InMemory(self.memory)),
+        }
+    }
+}
+
+/// The files the output of an action is written to as it runs, on top of
+/// being shown and collected.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ActionLog {
+    pub(crate) stdout: PathBuf,
+    pub(crate) stderr: PathBuf,
+}
+
+impl ActionLog {
+    /// The log of the `action`th action, counting from 1, of an attempt at
+    /// running the node, e.g. `<dir>/build/1/2-stdout.log`.
+    pub(crate) fn new(dir: &Path, node: &str, attempt: u32, action: usize) -> Self {
+        let dir = dir.join(node).join(attempt.to_string());
+        ActionLog {
+            stdout: dir.join(format!("{}-stdout.log", action)),
+            stderr: dir.join(format!("{}-stderr.log", action)),
+        }
+    }
+
+    /// Creates, or empties, the log files.
+    pub(crate) fn create(&self) -> anyhow::Result<(File, File)> {
+        let create = |path: &Path| -> anyhow::Result<File> {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            File::create(path).map_err(|e| anyhow!("Unable to create log {:?}: {}", path, e))
+        };
+        Ok((create(&self.stdout)?, create(&self.stderr)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_small_output_stays_in_memory() {
+        let mut buffer = CaptureBuffer::with_limit(8);
+        buffer.write_all(b"abc").unwrap();
+        buffer.write_all(b"def").unwrap();
+        let output = buffer.finish().unwrap();
+        assert!(!output.is_spilled());
+        assert_eq!(output.read().unwrap(), "abcdef");
+    }
+
+    #[test]
+    fn test_large_output_spills_to_file() {
+        let mut buffer = CaptureBuffer::with_limit(4);
+        buffer.write_all(b"abc").unwrap();
+        buffer.write_all(b"def").unwrap();
+        buffer.write_all(b"ghi").unwrap();
+        let output = buffer.finish().unwrap();
+        assert!(output.is_spilled());
+        assert_eq!(output.read().unwrap(), "abcdefghi");
+    }
+
+    #[test]
+    fn test_spill_file_removed_with_last_reference() {
+        let mut buffer = CaptureBuffer::with_limit(0);
+        buffer.write_all(b"abc").unwrap();
+        let output = buffer.finish().unwrap();
+        let path = match &output {
+            CapturedOutput::Spilled(file) => file.path.clone(),
+            _ => unreachable!(),
+        };
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let copy = output.clone();
+        drop(output);
+        assert!(path.exists());
+        drop(copy);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_invalid_utf8_is_lossy_unless_strict() {
+        let mut buffer = CaptureBuffer::with_limit(8);
+        buffer.write_all(b"a\xffb\xe9").unwrap();
+        let output = buffer.finish().unwrap();
+        assert_eq!(output.read().unwrap(), "a\u{FFFD}b\u{FFFD}");
+        assert!(output.read_strict().is_err());
+        assert_eq!(output.len().unwrap(), 4);
+    }
+
+    #[test]
+    fn test_spilled_invalid_utf8() {
+        let mut buffer = CaptureBuffer::with_limit(0);
+        buffer.write_all(&[b'a', 0xff]).unwrap();
+        let output = buffer.finish().unwrap();
+        assert!(output.is_spilled());
+        assert_eq!(output.read().unwrap(), "a\u{FFFD}");
+        assert!(output.read_strict().is_err());
+        assert_eq!(output.len().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_decode_latin1_and_bytes() {
+        let output = CapturedOutput::InMemory(b"caf\xe9".to_vec());
+        assert_eq!(output.decode(OutputEncoding::Latin1).unwrap(), "café");
+        assert_eq!(output.decode(OutputEncoding::Utf8).unwrap(), "caf\u{FFFD}");
+        assert!(output.decode(OutputEncoding::Bytes).is_err());
+        assert_eq!(output.base64().unwrap(), "Y2Fm6Q==");
+    }
+
+    #[test]
+    fn test_action_log() {
+        let dir = std::env::temp_dir().join(format!("workflow-logs-{}", Uuid::new_v4()));
+        let log = ActionLog::new(&dir, "build", 2, 1);
+        assert_eq!(log.stdout, dir.join("build/2/1-stdout.log"));
+        assert_eq!(log.stderr, dir.join("build/2/1-stderr.log"));
+
+        let (mut stdout, _) = log.create().unwrap();
+        stdout.write_all(b"first").unwrap();
+        log.create().unwrap();
+        assert_eq!(fs::read_to_string(&log.stdout).unwrap(), "");
+        assert!(log.stderr.is_file());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_base64_encode() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(&[0xff, 0xfe, 0x00, 0x01]), "//4AAQ==");
+    }
+
+    #[test]
+    fn test_encoding_from_attributes() {
+        assert_eq!(
+            OutputEncoding::from_attributes("utf8", true).unwrap(),
+            OutputEncoding::StrictUtf8
+        );
+        assert_eq!(
+            OutputEncoding::from_attributes("bytes", false).unwrap(),
+            OutputEncoding::Bytes
+        );
+        assert!(OutputEncoding::from_attributes("latin1", true).is_err());
+        assert!(OutputEncoding::from_attributes("utf16", false).is_err());
+    }
+}
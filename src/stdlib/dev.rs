@@ -0,0 +1,55 @@
+use crate::stdlib::ActionCtx;
+use starlark::environment::GlobalsBuilder;
+use starlark::starlark_module;
+
+/// Builtins which help when developing workflows. They are only available
+/// in the repl and in tests, never to workflows which are being run.
+#[starlark_module]
+pub fn dev_stdlib(builder: &mut GlobalsBuilder) {
+    /// Creates an action_ctx so setter and next functions can be called
+    /// directly, e.g. `my_setter(fake_ctx(stdout = "1.2.3"))`
+    fn fake_ctx(
+        #[starlark(require = named)] stdout: Option<&str>,
+        #[starlark(require = named)] stderr: Option<&str>,
+        #[starlark(require = named)] exit_code: Option<i32>,
+    ) -> anyhow::Result<ActionCtx> {
+        Ok(ActionCtx::new(
+            stdout.unwrap_or_default().to_string(),
+            stderr.unwrap_or_default().to_string(),
+            exit_code.unwrap_or_default(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stdlib::test_utils::assert_env;
+
+    #[test]
+    fn test_fake_ctx_defaults() {
+        assert_env().pass(
+            r#"
+ctx = fake_ctx()
+assert_eq(ctx.stdout, "")
+assert_eq(ctx.stderr, "")
+assert_eq(ctx.exit_code, 0)
+assert_eq(ctx.attempt, 1)
+"#,
+        );
+    }
+
+    #[test]
+    fn test_fake_ctx_with_setter() {
+        assert_env().pass(
+            r#"
+def _version(ctx):
+    return ctx.stdout.strip()
+
+ctx = fake_ctx(stdout = "1.2.3\n", stderr = "warning", exit_code = 2)
+assert_eq(_version(ctx), "1.2.3")
+assert_eq(ctx.stderr, "warning")
+assert_eq(ctx.exit_code, 2)
+"#,
+        );
+    }
+}
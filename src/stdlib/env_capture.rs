@@ -0,0 +1,189 @@
+use allocative::Allocative;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+
+// values of variables whose names contain one of these are not recorded
+const SECRET_MARKERS: [&str; 6] = ["TOKEN", "SECRET", "PASSWORD", "PASSWD", "CREDENTIAL", "KEY"];
+
+/// How much of the environment of each action is recorded.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum EnvCapture {
+    /// Only a hash, which is enough to tell whether two actions saw the
+    /// same environment.
+    #[default]
+    Hash,
+    /// The hash and all of the variables, with secrets redacted.
+    Full,
+}
+
+/// The environment a tool was spawned with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Allocative)]
+pub struct ActionEnv {
+    /// What the action ran, as shown in messages.
+    pub action: String,
+    pub hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vars: Option<BTreeMap<String, String>>,
+}
+
+impl ActionEnv {
+    /// Captures the environment of the current process, which is the one
+    /// spawned tools inherit, with the action's own variables on top.
+    pub fn capture(action: &str, overrides: &[(String, String)], capture: EnvCapture) -> Self {
+        let mut vars: BTreeMap<String, String> = std::env::vars_os()
+            .map(|(k, v)| {
+                (
+                    k.to_string_lossy().into_owned(),
+                    v.to_string_lossy().into_owned(),
+                )
+            })
+            .collect();
+        vars.extend(overrides.iter().cloned());
+        ActionEnv::from_vars(action, vars, capture)
+    }
+
+    /// Captures the environment of a tool which does not inherit the one
+    /// of the current process, only the variables it is given.
+    pub fn isolated(action: &str, vars: &[(String, String)], capture: EnvCapture) -> Self {
+        ActionEnv::from_vars(action, vars.iter().cloned().collect(), capture)
+    }
+
+    fn from_vars(action: &str, vars: BTreeMap<String, String>, capture: EnvCapture) -> Self {
+        let mut hasher = Sha256::new();
+        for (k, v) in &vars {
+            hasher.update(format!("{}={}\0", k, v));
+        }
+        let hash = format!("{:x}", hasher.finalize());
+        ActionEnv {
+            action: action.to_string(),
+            hash: hash[..16].to_string(),
+            vars: match capture {
+                EnvCapture::Hash => None,
+                EnvCapture::Full => Some(
+                    vars.into_iter()
+                        .map(|(k, v)| {
+                            let v = redact(&k, v);
+                            (k, v)
+                        })
+                        .collect(),
+                ),
+            },
+        }
+    }
+
+    /// Returns how the environment changed since `before`, None if either
+    /// of them only recorded a hash.
+    pub fn diff(&self, before: &ActionEnv) -> Option<Vec<EnvChange>> {
+        let (vars, before) = (self.vars.as_ref()?, before.vars.as_ref()?);
+        let mut changes = vec![];
+        for (k, v) in vars {
+            match before.get(k) {
+                None => changes.push(EnvChange::Added(k.clone(), v.clone())),
+                Some(old) if old != v => {
+                    changes.push(EnvChange::Changed(k.clone(), old.clone(), v.clone()))
+                }
+                Some(_) => {}
+            }
+        }
+        for k in before.keys().filter(|k| !vars.contains_key(*k)) {
+            changes.push(EnvChange::Removed(k.clone()));
+        }
+        changes.sort_by(|a, b| a.name().cmp(b.name()));
+        Some(changes)
+    }
+}
+
+/// Replaces the value of a variable which looks like it holds a secret
+/// with the start of its hash, so a change to it still shows in a diff.
+fn redact(name: &str, value: String) -> String {
+    let upper = name.to_uppercase();
+    if !SECRET_MARKERS.iter().any(|m| upper.contains(m)) {
+        return value;
+    }
+    let hash = format!("{:x}", Sha256::digest(value.as_bytes()));
+    format!("<redacted {}>", &hash[..8])
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EnvChange {
+    Added(String, String),
+    Removed(String),
+    Changed(String, String, String),
+}
+
+impl EnvChange {
+    pub fn name(&self) -> &str {
+        match self {
+            EnvChange::Added(k, _) | EnvChange::Removed(k) | EnvChange::Changed(k, _, _) => k,
+        }
+    }
+}
+
+impl fmt::Display for EnvChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EnvChange::Added(k, v) => write!(f, "+ {}={}", k, v),
+            EnvChange::Removed(k) => write!(f, "- {}", k),
+            EnvChange::Changed(k, old, new) => write!(f, "~ {}={} (was {})", k, new, old),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)], capture: EnvCapture) -> ActionEnv {
+        let vars = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        ActionEnv::from_vars("tool", vars, capture)
+    }
+
+    #[test]
+    fn test_hash() {
+        let a = env(&[("A", "1"), ("B", "2")], EnvCapture::Hash);
+        assert_eq!(a.hash.len(), 16);
+        assert_eq!(a.vars, None);
+        assert_eq!(
+            a.hash,
+            env(&[("B", "2"), ("A", "1")], EnvCapture::Full).hash
+        );
+        assert_ne!(
+            a.hash,
+            env(&[("A", "1"), ("B", "3")], EnvCapture::Hash).hash
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let before = env(&[("A", "1"), ("B", "2"), ("C", "3")], EnvCapture::Full);
+        let after = env(&[("A", "1"), ("B", "4"), ("D", "5")], EnvCapture::Full);
+        let changes: Vec<String> = after
+            .diff(&before)
+            .unwrap()
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        assert_eq!(changes, ["~ B=4 (was 2)", "- C", "+ D=5"]);
+        assert_eq!(before.diff(&before), Some(vec![]));
+        assert_eq!(after.diff(&env(&[], EnvCapture::Hash)), None);
+    }
+
+    #[test]
+    fn test_secrets_are_redacted() {
+        let a = env(
+            &[("GITHUB_TOKEN", "abc"), ("api_key", "def"), ("HOME", "/h")],
+            EnvCapture::Full,
+        );
+        let vars = a.vars.unwrap();
+        assert!(vars["GITHUB_TOKEN"].starts_with("<redacted "));
+        assert!(vars["api_key"].starts_with("<redacted "));
+        assert_eq!(vars["HOME"], "/h");
+        let b = env(&[("GITHUB_TOKEN", "xyz")], EnvCapture::Full);
+        assert_ne!(vars["GITHUB_TOKEN"], b.vars.unwrap()["GITHUB_TOKEN"]);
+    }
+}
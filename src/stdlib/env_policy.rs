@@ -0,0 +1,192 @@
+use crate::stdlib::errors::StdlibError;
+use crate::stdlib::format::late_bound_string;
+use crate::stdlib::ir::EnvPolicyIr;
+use crate::stdlib::variable_resolver::{LateBoundString, VariableResolver};
+use crate::stdlib::ENV_POLICY_TYPE;
+use allocative::Allocative;
+use anyhow::{anyhow, bail};
+use starlark::collections::SmallMap;
+use starlark::starlark_simple_value;
+use starlark::values::starlark_value;
+use starlark::values::NoSerialize;
+use starlark::values::ProvidesStaticType;
+use starlark::values::StarlarkValue;
+use starlark::values::Value;
+use std::fmt;
+
+pub(crate) fn env_policy_impl(
+    inherit: bool,
+    allow: Vec<String>,
+    set: SmallMap<String, Value>,
+) -> anyhow::Result<EnvPolicy> {
+    if inherit && !allow.is_empty() {
+        bail!(StdlibError::new_invalid_attr(
+            "allow",
+            "only applies to a policy which does not inherit the environment",
+            format!("{:?}", allow)
+        ));
+    }
+    if let Some(name) = allow
+        .iter()
+        .chain(set.keys())
+        .find(|k| k.is_empty() || k.contains('=') || k.contains('\0'))
+    {
+        bail!(StdlibError::new_invalid_attr(
+            "env_policy",
+            "names cannot be empty or contain '=' or NUL",
+            format!("{:?}", name)
+        ));
+    }
+    Ok(EnvPolicy {
+        inherit,
+        allow,
+        set: set
+            .into_iter()
+            .map(|(name, value)| (name, late_bound_string(value)))
+            .collect(),
+    })
+}
+
+/// Which environment variables the tools of a workflow are spawned with.
+/// Unless the policy inherits the environment of `workflow` only the
+/// allowed variables are passed on, and the variables it sets are set on
+/// top, below those the action sets itself.
+#[derive(Debug, ProvidesStaticType, NoSerialize, Allocative, Clone)]
+pub struct EnvPolicy {
+    inherit: bool,
+    allow: Vec<String>,
+    set: Vec<(String, LateBoundString)>,
+}
+starlark_simple_value!(EnvPolicy);
+
+#[starlark_value(type = ENV_POLICY_TYPE)]
+impl<'v> StarlarkValue<'v> for EnvPolicy {}
+
+impl EnvPolicy {
+    /// Whether tools inherit the environment of `workflow`.
+    pub fn inherit(&self) -> bool {
+        self.inherit
+    }
+
+    /// Returns the variables the policy gives tools on top of what they
+    /// inherit, the allowed variables of the environment of `workflow`
+    /// followed by those it sets.
+    pub fn vars<T: VariableResolver>(&self, resolver: &T) -> anyhow::Result<Vec<(String, String)>> {
+        let mut vars: Vec<(String, String)> = self
+            .allow
+            .iter()
+            .filter_map(|name| {
+                std::env::var_os(name).map(|v| (name.clone(), v.to_string_lossy().into_owned()))
+            })
+            .collect();
+        for (name, value) in &self.set {
+            vars.push((name.clone(), value.get_value(resolver)?));
+        }
+        Ok(vars)
+    }
+
+    /// Returns the policy as IR, failing if it sets a variable to a format.
+    pub fn compile<T: VariableResolver>(&self, resolver: &T) -> anyhow::Result<EnvPolicyIr> {
+        Ok(EnvPolicyIr {
+            inherit: self.inherit,
+            allow: self.allow.clone(),
+            set: self
+                .set
+                .iter()
+                .map(|(name, value)| {
+                    let value = value.compile(resolver)?.ok_or_else(|| {
+                        anyhow!(
+                            "the env_policy sets {} to a format, only strings and variables can be compiled",
+                            name
+                        )
+                    })?;
+                    Ok((name.clone(), value))
+                })
+                .collect::<anyhow::Result<_>>()?,
+        })
+    }
+
+    /// Returns the policy as it is applied, with the values it sets
+    /// resolved and redacted.
+    pub fn describe<T: VariableResolver>(&self, resolver: &T) -> anyhow::Result<String> {
+        let redact = |value: String| match resolver.context().redactor {
+            Some(redactor) => redactor.redact(&value),
+            None => value,
+        };
+        let mut parts = vec![format!(
+            "inherit = {}",
+            if self.inherit { "True" } else { "False" }
+        )];
+        if !self.inherit {
+            parts.push(format!("allow = {:?}", self.allow));
+        }
+        let mut set = vec![];
+        for (name, value) in &self.set {
+            set.push(format!(
+                "{:?}: {:?}",
+                name,
+                redact(value.get_value(resolver)?)
+            ));
+        }
+        if !set.is_empty() {
+            parts.push(format!("set = {{{}}}", set.join(", ")));
+        }
+        Ok(parts.join(", "))
+    }
+}
+
+impl fmt::Display for EnvPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "env_policy")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib::test_utils::{assert_env, TempEnvVar};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_parse() {
+        assert_env().pass("env_policy()");
+        assert_env().pass("env_policy(inherit = False, allow = ['PATH'], set = {'LANG': 'C'})");
+        assert_env().fail(
+            "env_policy(allow = ['PATH'])",
+            "only applies to a policy which does not inherit the environment",
+        );
+        assert_env().fail(
+            "env_policy(inherit = False, set = {'A=B': 'C'})",
+            "names cannot be empty or contain '=' or NUL",
+        );
+    }
+
+    #[test]
+    fn test_vars() {
+        let _allowed = TempEnvVar::new("WORKFLOW_ENV_POLICY_ALLOWED", "a");
+        let resolver: HashMap<&str, &str> = HashMap::new();
+        let heap = starlark::values::Heap::new();
+        let mut set = SmallMap::new();
+        set.insert("LANG".to_string(), heap.alloc("C"));
+        let policy = env_policy_impl(
+            false,
+            vec![
+                "WORKFLOW_ENV_POLICY_ALLOWED".to_string(),
+                "WORKFLOW_ENV_POLICY_UNSET".to_string(),
+            ],
+            set,
+        )
+        .unwrap();
+        assert_eq!(
+            policy.vars(&resolver).unwrap(),
+            [
+                ("WORKFLOW_ENV_POLICY_ALLOWED".to_string(), "a".to_string()),
+                ("LANG".to_string(), "C".to_string()),
+            ]
+        );
+        assert_eq!(
+            policy.describe(&resolver).unwrap(),
+            r#"inherit = False, allow = ["WORKFLOW_ENV_POLICY_ALLOWED", "WORKFLOW_ENV_POLICY_UNSET"], set = {"LANG": "C"}"#
+        );
+    }
+}
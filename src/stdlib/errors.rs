@@ -1,3 +1,4 @@
+use starlark::values::Value;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -21,3 +22,259 @@ impl StdlibError {
         }
     }
 }
+
+/// Errors for values of the wrong type being passed to the workflow
+/// builtins. The variants are stable so embedders can match on them and
+/// the messages share one format.
+#[derive(Error, Debug, PartialEq)]
+pub enum ValueError {
+    #[error("expected {expected} for '{attr}' in {definition} definition, got {got}")]
+    WrongType {
+        definition: &'static str,
+        attr: &'static str,
+        expected: &'static str,
+        got: String,
+    },
+    #[error("expected a list of {expected} for '{attr}' in {definition} definition, got {got}")]
+    WrongElementType {
+        definition: &'static str,
+        attr: &'static str,
+        expected: &'static str,
+        got: String,
+    },
+    #[error("expected exactly one of {} in {definition} definition", quoted(.attrs))]
+    ExactlyOneOf {
+        definition: &'static str,
+        attrs: Vec<&'static str>,
+    },
+    #[error("expected at most one of {} in {definition} definition", quoted(.attrs))]
+    AtMostOneOf {
+        definition: &'static str,
+        attrs: Vec<&'static str>,
+    },
+    #[error("'{attr}' requires '{requires}' in {definition} definition")]
+    MissingRequiredArg {
+        definition: &'static str,
+        attr: &'static str,
+        requires: &'static str,
+    },
+    #[error("duplicate name '{name}' for '{attr}' in {definition} definition")]
+    DuplicateName {
+        definition: &'static str,
+        attr: &'static str,
+        name: String,
+    },
+}
+
+fn quoted(attrs: &[&str]) -> String {
+    attrs
+        .iter()
+        .map(|a| format!("'{}'", a))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Checks the combination of named arguments passed to a builtin so that
+/// conflicting or incomplete combinations fail with the same phrasing
+/// across all of the builtins.
+///
+/// ```ignore
+/// ArgCheck::new("tool")
+///     .arg("path", path.is_some())
+///     .arg("wasm", wasm.is_some())
+///     .exactly_one_of(&["path", "wasm"])?;
+/// ```
+pub struct ArgCheck {
+    definition: &'static str,
+    args: Vec<(&'static str, bool)>,
+}
+
+impl ArgCheck {
+    pub fn new(definition: &'static str) -> Self {
+        ArgCheck {
+            definition,
+            args: vec![],
+        }
+    }
+
+    /// Records whether the named argument was passed.
+    pub fn arg(mut self, name: &'static str, present: bool) -> Self {
+        self.args.push((name, present));
+        self
+    }
+
+    fn count(&self, names: &[&'static str]) -> usize {
+        names.iter().filter(|name| self.is_present(name)).count()
+    }
+
+    fn is_present(&self, name: &str) -> bool {
+        self.args.iter().any(|(n, present)| *n == name && *present)
+    }
+
+    pub fn exactly_one_of(self, names: &[&'static str]) -> Result<Self, ValueError> {
+        if self.count(names) != 1 {
+            return Err(ValueError::ExactlyOneOf {
+                definition: self.definition,
+                attrs: names.to_vec(),
+            });
+        }
+        Ok(self)
+    }
+
+    pub fn at_most_one_of(self, names: &[&'static str]) -> Result<Self, ValueError> {
+        if self.count(names) > 1 {
+            return Err(ValueError::AtMostOneOf {
+                definition: self.definition,
+                attrs: names.to_vec(),
+            });
+        }
+        Ok(self)
+    }
+
+    /// Fails if `name` was passed without `requires`.
+    pub fn requires(self, name: &'static str, requires: &'static str) -> Result<Self, ValueError> {
+        if self.is_present(name) && !self.is_present(requires) {
+            return Err(ValueError::MissingRequiredArg {
+                definition: self.definition,
+                attr: name,
+                requires,
+            });
+        }
+        Ok(self)
+    }
+}
+
+impl ValueError {
+    /// Fails with WrongType unless the value has the expected starlark type.
+    pub fn check_type(
+        definition: &'static str,
+        attr: &'static str,
+        expected: &'static str,
+        value: Value,
+    ) -> Result<(), ValueError> {
+        if value.get_type() == expected {
+            Ok(())
+        } else {
+            Err(ValueError::WrongType {
+                definition,
+                attr,
+                expected,
+                got: value.get_type().to_string(),
+            })
+        }
+    }
+
+    /// Fails with WrongElementType unless the value has the expected
+    /// starlark type.
+    pub fn check_element_type(
+        definition: &'static str,
+        attr: &'static str,
+        expected: &'static str,
+        value: Value,
+    ) -> Result<(), ValueError> {
+        if value.get_type() == expected {
+            Ok(())
+        } else {
+            Err(ValueError::WrongElementType {
+                definition,
+                attr,
+                expected,
+                got: value.get_type().to_string(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use starlark::values::Heap;
+
+    #[test]
+    fn test_check_type() {
+        let heap = Heap::new();
+        assert_eq!(
+            ValueError::check_type("action", "tool", "tool", heap.alloc("a")),
+            Err(ValueError::WrongType {
+                definition: "action",
+                attr: "tool",
+                expected: "tool",
+                got: "string".to_string(),
+            })
+        );
+        assert_eq!(
+            ValueError::check_type("x", "y", "string", heap.alloc("a")),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_exactly_one_of() {
+        let check = || ArgCheck::new("tool").arg("path", true).arg("wasm", true);
+        assert_eq!(
+            check()
+                .exactly_one_of(&["path", "wasm"])
+                .err()
+                .unwrap()
+                .to_string(),
+            "expected exactly one of 'path', 'wasm' in tool definition"
+        );
+        assert!(check().exactly_one_of(&["path"]).is_ok());
+        assert!(ArgCheck::new("tool")
+            .arg("path", false)
+            .exactly_one_of(&["path", "wasm"])
+            .is_err());
+    }
+
+    #[test]
+    fn test_at_most_one_of() {
+        let check = ArgCheck::new("x").arg("a", true).arg("b", false);
+        let check = check.at_most_one_of(&["a", "b"]).unwrap();
+        assert_eq!(
+            check
+                .arg("b", true)
+                .at_most_one_of(&["a", "b"])
+                .err()
+                .unwrap()
+                .to_string(),
+            "expected at most one of 'a', 'b' in x definition"
+        );
+    }
+
+    #[test]
+    fn test_requires() {
+        assert!(ArgCheck::new("x").requires("a", "b").is_ok());
+        assert_eq!(
+            ArgCheck::new("x")
+                .arg("a", true)
+                .requires("a", "b")
+                .err()
+                .unwrap()
+                .to_string(),
+            "'a' requires 'b' in x definition"
+        );
+    }
+
+    #[test]
+    fn test_messages() {
+        assert_eq!(
+            ValueError::WrongType {
+                definition: "action",
+                attr: "tool",
+                expected: "tool",
+                got: "string".to_string(),
+            }
+            .to_string(),
+            "expected tool for 'tool' in action definition, got string"
+        );
+        assert_eq!(
+            ValueError::DuplicateName {
+                definition: "workflow",
+                attr: "graph",
+                name: "a".to_string(),
+            }
+            .to_string(),
+            "duplicate name 'a' for 'graph' in workflow definition"
+        );
+    }
+}
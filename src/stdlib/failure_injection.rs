@@ -0,0 +1,132 @@
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Mutex;
+
+/// How often tools fail instead of running and the seed which picks them,
+/// written as `rate=0.1,seed=42`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FailureInjection {
+    /// The chance, from 0 to 1, that a tool fails.
+    pub rate: f64,
+    pub seed: u64,
+}
+
+impl FailureInjection {
+    /// Parses `rate=<rate>[,seed=<seed>]`, a random seed is picked if none
+    /// is given.
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        let mut rate = None;
+        let mut seed = None;
+        for part in spec.split(',') {
+            match part.split_once('=') {
+                Some(("rate", value)) => match value.parse::<f64>() {
+                    Ok(value) if (0.0..=1.0).contains(&value) => rate = Some(value),
+                    _ => bail!("'{}' is not a rate, expected a number from 0 to 1", value),
+                },
+                Some(("seed", value)) => match value.parse::<u64>() {
+                    Ok(value) => seed = Some(value),
+                    Err(_) => bail!("'{}' is not a seed, expected a whole number", value),
+                },
+                _ => bail!(
+                    "Invalid failure injection '{}', expected rate=<rate>[,seed=<seed>]",
+                    spec
+                ),
+            }
+        }
+        match rate {
+            Some(rate) => Ok(FailureInjection {
+                rate,
+                seed: seed.unwrap_or_else(|| uuid::Uuid::new_v4().as_u64_pair().0),
+            }),
+            None => bail!("Invalid failure injection '{}', the rate is missing", spec),
+        }
+    }
+}
+
+impl fmt::Display for FailureInjection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "rate={},seed={}", self.rate, self.seed)
+    }
+}
+
+/// Makes tools fail at random instead of running, to check the retries of
+/// a workflow and the nodes it goes to on failure behave. The failures are
+/// picked in the order the tools run by a generator seeded with the seed,
+/// so a run with the same seed fails the same tools.
+#[derive(Debug)]
+pub struct FailureInjector {
+    injection: FailureInjection,
+    state: Mutex<u64>,
+}
+
+impl FailureInjector {
+    pub fn new(injection: FailureInjection) -> Self {
+        FailureInjector {
+            injection,
+            state: Mutex::new(injection.seed),
+        }
+    }
+
+    pub fn injection(&self) -> FailureInjection {
+        self.injection
+    }
+
+    /// Whether the next tool to run fails instead.
+    pub fn should_fail(&self) -> bool {
+        // splitmix64, the top 53 bits make a number from 0 to 1
+        let mut state = self.state.lock().unwrap();
+        *state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        ((z >> 11) as f64 / (1u64 << 53) as f64) < self.injection.rate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            FailureInjection::parse("rate=0.1,seed=42").unwrap(),
+            FailureInjection {
+                rate: 0.1,
+                seed: 42
+            }
+        );
+        assert_eq!(FailureInjection::parse("rate=1").unwrap().rate, 1.0);
+        assert_eq!(
+            FailureInjection::parse("seed=42").unwrap_err().to_string(),
+            "Invalid failure injection 'seed=42', the rate is missing"
+        );
+        assert_eq!(
+            FailureInjection::parse("rate=2").unwrap_err().to_string(),
+            "'2' is not a rate, expected a number from 0 to 1"
+        );
+        assert!(FailureInjection::parse("rate=0.1,often").is_err());
+        assert_eq!(
+            FailureInjection::parse("rate=0.5,seed=7")
+                .unwrap()
+                .to_string(),
+            "rate=0.5,seed=7"
+        );
+    }
+
+    #[test]
+    fn test_failures_follow_the_seed() {
+        let failures = |rate: f64, seed: u64| -> Vec<bool> {
+            let injector = FailureInjector::new(FailureInjection { rate, seed });
+            (0..100).map(|_| injector.should_fail()).collect()
+        };
+        assert_eq!(failures(0.3, 42), failures(0.3, 42));
+        assert_ne!(failures(0.3, 42), failures(0.3, 43));
+        let count = failures(0.3, 42).into_iter().filter(|f| *f).count();
+        assert!((15..45).contains(&count), "{} failures", count);
+        assert!(!failures(0.0, 42).contains(&true));
+        assert!(!failures(1.0, 42).contains(&false));
+    }
+}
@@ -16,24 +16,24 @@ pub(crate) fn format_impl(
     fmt_str: &str,
     args: UnpackTuple<Value>,
 ) -> anyhow::Result<ValueFormatter> {
-    let mut values: Vec<LateBoundString> = vec![];
-    for a in args {
-        if let Some(formatter) = ValueFormatter::from_value(a) {
-            values.push(LateBoundString::with_value_formatter(formatter.clone()));
-        } else if let Some(variable) = VariableRef::from_value(a) {
-            values.push(LateBoundString::with_identifier(
-                variable.identifier().to_string(),
-            ));
-        } else {
-            values.push(LateBoundString::with_value(a.to_str()));
-        }
-    }
     Ok(ValueFormatter {
         fmt_str: fmt_str.to_string(),
-        values: values,
+        values: args.into_iter().map(late_bound_string).collect(),
     })
 }
 
+/// Returns a string which is resolved later from a formatter, a variable
+/// or any other value.
+pub(crate) fn late_bound_string(value: Value) -> LateBoundString {
+    if let Some(formatter) = ValueFormatter::from_value(value) {
+        LateBoundString::with_value_formatter(formatter.clone())
+    } else if let Some(variable) = VariableRef::from_value(value) {
+        LateBoundString::with_identifier(variable.identifier().to_string())
+    } else {
+        LateBoundString::with_value(value.to_str())
+    }
+}
+
 #[derive(Debug, ProvidesStaticType, NoSerialize, Allocative, Clone)]
 pub struct ValueFormatter {
     fmt_str: String,
@@ -55,6 +55,12 @@ impl ValueFormatter {
         }
     }
 
+    /// Returns the format string and the values which fill its
+    /// placeholders.
+    pub(crate) fn parts(&self) -> (&str, &[LateBoundString]) {
+        (&self.fmt_str, &self.values)
+    }
+
     pub fn fmt<T: VariableResolver>(&self, resolver: &T) -> anyhow::Result<String> {
         // TODO: Look into using th normal write! macros here.
         // The problem is that we have a Vec<String> and we would need to expand
@@ -0,0 +1,326 @@
+use crate::stdlib::errors::StdlibError;
+use crate::stdlib::format::late_bound_string;
+use crate::stdlib::variable_resolver::{LateBoundString, VariableResolver};
+use crate::stdlib::GLOB_TYPE;
+use allocative::Allocative;
+use anyhow::bail;
+use regex::Regex;
+use starlark::starlark_simple_value;
+use starlark::values::starlark_value;
+use starlark::values::NoSerialize;
+use starlark::values::ProvidesStaticType;
+use starlark::values::StarlarkValue;
+use starlark::values::Value;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub(crate) fn glob_impl(pattern: Value, allow_empty: bool) -> anyhow::Result<Glob> {
+    // a pattern from a variable can only be checked once it is resolved
+    if let Some(pattern) = pattern.unpack_str() {
+        if let Err(e) = parse(pattern) {
+            bail!(StdlibError::new_invalid_attr(
+                "pattern",
+                &e.to_string(),
+                pattern
+            ));
+        }
+    }
+    Ok(Glob {
+        pattern: late_bound_string(pattern),
+        allow_empty,
+    })
+}
+
+/// A set of paths matching a pattern. The pattern is resolved and matched
+/// when the glob is used as an action arg, which expands to one arg for
+/// each matching path.
+#[derive(Debug, ProvidesStaticType, NoSerialize, Allocative, Clone)]
+pub struct Glob {
+    pattern: LateBoundString,
+    allow_empty: bool,
+}
+starlark_simple_value!(Glob);
+
+#[starlark_value(type = GLOB_TYPE)]
+impl<'v> StarlarkValue<'v> for Glob {}
+
+impl Glob {
+    /// Returns the sorted paths matching the pattern, using the current
+    /// values of the variables. Relative patterns are matched in the
+    /// working dir and return paths relative to it.
+    pub fn expand<T: VariableResolver>(
+        &self,
+        resolver: &T,
+        working_dir: &Path,
+    ) -> anyhow::Result<Vec<String>> {
+        let pattern = self.pattern.get_value(resolver)?;
+        let matches = match_pattern(&pattern, working_dir)?;
+        if matches.is_empty() && !self.allow_empty {
+            bail!("glob '{}' did not match any files", pattern);
+        }
+        Ok(matches)
+    }
+}
+
+/// Checks that the pattern is a valid glob, returning why it is not.
+pub(crate) fn check_pattern(pattern: &str) -> anyhow::Result<()> {
+    parse(pattern).map(|_| ())
+}
+
+/// Returns the sorted paths matching the pattern, which may be empty. A
+/// relative pattern is matched in the working dir and returns paths
+/// relative to it.
+pub(crate) fn match_pattern(pattern: &str, working_dir: &Path) -> anyhow::Result<Vec<String>> {
+    let segments = parse(pattern)?;
+    let (dir, name) = match pattern.starts_with('/') {
+        true => (PathBuf::from("/"), "/".to_string()),
+        false => (working_dir.to_path_buf(), String::new()),
+    };
+    let mut matches = BTreeSet::new();
+    walk(&dir, &name, &segments, &mut matches);
+    Ok(matches.into_iter().collect())
+}
+
+impl fmt::Display for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "glob")
+    }
+}
+
+#[derive(Debug)]
+enum Segment {
+    Literal(String),
+    /// A segment with wildcards, and whether it matches hidden files.
+    Pattern(Regex, bool),
+    /// `**`, which matches any number of directories.
+    AnyDirs,
+}
+
+fn parse(pattern: &str) -> anyhow::Result<Vec<Segment>> {
+    if pattern.is_empty() {
+        bail!("must not be empty");
+    }
+    pattern
+        .split('/')
+        .filter(|s| !s.is_empty() && *s != ".")
+        .map(|s| {
+            Ok(if s == "**" {
+                Segment::AnyDirs
+            } else if s.contains(['*', '?', '[']) {
+                Segment::Pattern(segment_regex(s)?, s.starts_with('.'))
+            } else {
+                Segment::Literal(s.to_string())
+            })
+        })
+        .collect()
+}
+
+/// Translates a segment with `*`, `?` and `[...]` wildcards into a regex
+/// which matches a whole file name.
+fn segment_regex(segment: &str) -> anyhow::Result<Regex> {
+    let mut re = String::from("^");
+    let mut chars = segment.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            '[' => {
+                re.push('[');
+                if chars.next_if(|c| *c == '!' || *c == '^').is_some() {
+                    re.push('^');
+                }
+                // a ']' right after the '[' is part of the class
+                if chars.next_if_eq(&']').is_some() {
+                    re.push_str("\\]");
+                }
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    match c {
+                        ']' => {
+                            closed = true;
+                            break;
+                        }
+                        '-' => re.push('-'),
+                        c => re.push_str(&regex::escape(&c.to_string())),
+                    }
+                }
+                if !closed {
+                    bail!("has an unclosed '[' in '{}'", segment);
+                }
+                re.push(']');
+            }
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    Ok(Regex::new(&re)?)
+}
+
+/// Adds the paths under `dir` which match the segments to `matches`.
+/// `name` is the path of `dir` as it is returned by the glob.
+fn walk(dir: &Path, name: &str, segments: &[Segment], matches: &mut BTreeSet<String>) {
+    let (segment, rest) = match segments.split_first() {
+        Some(first) => first,
+        None => {
+            matches.insert(name.to_string());
+            return;
+        }
+    };
+    let join = |child: &str| match name.is_empty() || name.ends_with('/') {
+        true => format!("{}{}", name, child),
+        false => format!("{}/{}", name, child),
+    };
+    match segment {
+        Segment::Literal(literal) => {
+            let path = dir.join(literal);
+            // only the last segment can match something other than a directory
+            if path.is_dir() || (rest.is_empty() && fs::symlink_metadata(&path).is_ok()) {
+                walk(&path, &join(literal), rest, matches);
+            }
+        }
+        Segment::Pattern(re, hidden) => {
+            for (child, _) in children(dir) {
+                if (*hidden || !child.starts_with('.')) && re.is_match(&child) {
+                    let path = dir.join(&child);
+                    if rest.is_empty() || path.is_dir() {
+                        walk(&path, &join(&child), rest, matches);
+                    }
+                }
+            }
+        }
+        Segment::AnyDirs => {
+            // a trailing `**` matches everything below the directory but not
+            // the directory itself
+            if !rest.is_empty() {
+                walk(dir, name, rest, matches);
+            }
+            for (child, is_dir) in children(dir) {
+                if child.starts_with('.') {
+                    continue;
+                }
+                if rest.is_empty() {
+                    matches.insert(join(&child));
+                }
+                // symlinks to directories are not followed so a link to a
+                // parent can not recurse forever
+                if is_dir {
+                    walk(&dir.join(&child), &join(&child), segments, matches);
+                }
+            }
+        }
+    }
+}
+
+/// Returns the names of the entries in the directory and whether each is a
+/// directory, nothing if it can not be read.
+fn children(dir: &Path) -> Vec<(String, bool)> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().into_string().ok()?;
+            Some((name, entry.file_type().ok()?.is_dir()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib::test_utils::assert_env;
+    use tempfile::tempdir;
+
+    fn expand(pattern: &str, dir: &Path) -> anyhow::Result<Vec<String>> {
+        Glob {
+            pattern: LateBoundString::with_value(pattern.to_string()),
+            allow_empty: false,
+        }
+        .expand(&"", dir)
+    }
+
+    fn make_tree(root: &Path) {
+        for dir in ["src/a/b", "src/.hidden", "docs"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in [
+            "src/main.rs",
+            "src/lib.rs",
+            "src/a/mod.rs",
+            "src/a/b/deep.rs",
+            "src/a/notes.txt",
+            "src/.hidden/secret.rs",
+            "docs/1.md",
+            "docs/2.md",
+            "docs/x.md",
+        ] {
+            fs::write(root.join(file), "").unwrap();
+        }
+    }
+
+    #[test]
+    fn test_expand() {
+        let dir = tempdir().unwrap();
+        make_tree(dir.path());
+        assert_eq!(
+            expand("src/*.rs", dir.path()).unwrap(),
+            ["src/lib.rs", "src/main.rs"]
+        );
+        assert_eq!(
+            expand("src/**/*.rs", dir.path()).unwrap(),
+            [
+                "src/a/b/deep.rs",
+                "src/a/mod.rs",
+                "src/lib.rs",
+                "src/main.rs"
+            ]
+        );
+        assert_eq!(
+            expand("./docs/[0-9].md", dir.path()).unwrap(),
+            ["docs/1.md", "docs/2.md"]
+        );
+        assert_eq!(expand("docs/[!0-9]?md", dir.path()).unwrap(), ["docs/x.md"]);
+        assert_eq!(
+            expand("src/.hidden/*", dir.path()).unwrap(),
+            ["src/.hidden/secret.rs"]
+        );
+        assert_eq!(expand("*", dir.path()).unwrap(), ["docs", "src"]);
+        assert_eq!(expand("src/a/**", dir.path()).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_expand_absolute() {
+        let dir = tempdir().unwrap();
+        make_tree(dir.path());
+        let pattern = format!("{}/docs/x.*", dir.path().display());
+        assert_eq!(
+            expand(&pattern, Path::new("/unused")).unwrap(),
+            [format!("{}/docs/x.md", dir.path().display())]
+        );
+    }
+
+    #[test]
+    fn test_expand_no_matches() {
+        let dir = tempdir().unwrap();
+        let err = expand("*.rs", dir.path()).unwrap_err();
+        assert_eq!(err.to_string(), "glob '*.rs' did not match any files");
+
+        let glob = Glob {
+            pattern: LateBoundString::with_value("*.rs".to_string()),
+            allow_empty: true,
+        };
+        assert!(glob.expand(&"", dir.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_glob_checks_literal_pattern() {
+        assert_env().pass("glob('src/**/*.rs')");
+        assert_env().pass("glob(format('{}/*.rs', variable(default = 'src')))");
+        assert_env().fail("glob('src/[a.rs')", "Invalid attribute 'pattern'");
+        assert_env().fail("glob('')", "Invalid attribute 'pattern', must not be empty");
+    }
+}
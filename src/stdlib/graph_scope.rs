@@ -0,0 +1,148 @@
+use crate::stdlib::run_context::RunContext;
+use crate::stdlib::variable_resolver::{VariableResolver, VariableSnapshot, VariableUpdater};
+use crate::stdlib::{ValueContext, ValueUpdatedBy};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// What the scope around a graph is, taken as a trait object so a graph
+/// nested in a graph runs with the same kind of resolver.
+pub(crate) trait ScopeParent: VariableResolver + VariableUpdater {}
+
+impl<T: VariableResolver + VariableUpdater> ScopeParent for T {}
+
+/// The variables of a nested graph. The graph reads its `imports` from the
+/// scope around it while every other variable starts from the value it had
+/// before the run started, and what the graph sets stays in its scope. The
+/// values of the `exports` are copied out once the graph succeeds.
+pub(crate) struct GraphScope<'p> {
+    // the name of the node the graph belongs to
+    name: &'p str,
+    parent: &'p dyn ScopeParent,
+    imports: &'p [String],
+    exports: &'p [String],
+    // the values set in the scope, by identifier
+    values: RefCell<HashMap<String, String>>,
+}
+
+impl<'p> GraphScope<'p> {
+    pub(crate) fn new(
+        name: &'p str,
+        parent: &'p dyn ScopeParent,
+        imports: &'p [String],
+        exports: &'p [String],
+    ) -> Self {
+        GraphScope {
+            name,
+            parent,
+            imports,
+            exports,
+            values: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the exports which the graph set in the scope around it.
+    pub(crate) fn export(&self) -> anyhow::Result<()> {
+        let values = self.values.borrow();
+        let updates: Vec<(String, String)> = self
+            .exports
+            .iter()
+            .filter_map(|identifier| Some((identifier.clone(), values.get(identifier)?.clone())))
+            .collect();
+        if updates.is_empty() {
+            return Ok(());
+        }
+        self.parent.update_all(updates)
+    }
+
+    // replaces the values of the parent with those set in the scope
+    fn with_values(&self, mut snapshot: VariableSnapshot) -> VariableSnapshot {
+        for (identifier, value) in self.values.borrow().iter() {
+            if let Some(name) = self.parent.variable_name(identifier) {
+                let value_ctx = ValueContext {
+                    value: value.clone(),
+                    updated_by: ValueUpdatedBy::Action(self.name.to_string()),
+                };
+                snapshot.insert(name, value_ctx);
+            }
+        }
+        snapshot
+    }
+}
+
+impl VariableResolver for GraphScope<'_> {
+    fn resolve(&self, identifier: &str) -> anyhow::Result<String> {
+        if let Some(value) = self.values.borrow().get(identifier) {
+            return Ok(value.clone());
+        }
+        match self.imports.iter().any(|i| i == identifier) {
+            true => self.parent.resolve(identifier),
+            false => self.parent.initial_value(identifier),
+        }
+    }
+
+    fn initial_value(&self, identifier: &str) -> anyhow::Result<String> {
+        self.parent.initial_value(identifier)
+    }
+
+    fn snapshot(&self) -> Option<VariableSnapshot> {
+        Some(self.with_values(self.parent.snapshot()?))
+    }
+
+    fn values(&self) -> Option<VariableSnapshot> {
+        Some(self.with_values(self.parent.values()?))
+    }
+
+    fn variable_name(&self, identifier: &str) -> Option<String> {
+        self.parent.variable_name(identifier)
+    }
+
+    fn context(&self) -> RunContext<'_> {
+        self.parent.context().nested(self.name)
+    }
+}
+
+impl VariableUpdater for GraphScope<'_> {
+    fn update(&self, identifier: &str, value: String) -> anyhow::Result<()> {
+        self.values
+            .borrow_mut()
+            .insert(identifier.to_string(), value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::WorkflowDelegate;
+    use crate::stdlib::VariableEntry;
+
+    #[test]
+    fn test_scope() {
+        let delegate = WorkflowDelegate::new();
+        for (identifier, default) in [("imported", "a"), ("local", "b"), ("exported", "c")] {
+            delegate.variable_store().register_variable(
+                identifier,
+                VariableEntry::for_test(Some(default), None, None),
+            );
+        }
+        delegate.variable_store().realize_variables(&vec![]);
+        delegate.update("imported", "outer".to_string()).unwrap();
+        delegate.update("local", "outer".to_string()).unwrap();
+
+        let imports = ["imported".to_string()];
+        let exports = ["exported".to_string()];
+        let scope = GraphScope::new("build", &delegate, &imports, &exports);
+        assert_eq!(scope.resolve("imported").unwrap(), "outer");
+        // the other variables do not see what was set outside of the graph
+        assert_eq!(scope.resolve("local").unwrap(), "b");
+
+        scope.update("local", "inner".to_string()).unwrap();
+        scope.update("exported", "inner".to_string()).unwrap();
+        assert_eq!(scope.resolve("local").unwrap(), "inner");
+        assert_eq!(delegate.resolve("exported").unwrap(), "c");
+
+        scope.export().unwrap();
+        assert_eq!(delegate.resolve("exported").unwrap(), "inner");
+        assert_eq!(delegate.resolve("local").unwrap(), "outer");
+    }
+}
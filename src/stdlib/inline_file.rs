@@ -0,0 +1,215 @@
+use crate::stdlib::errors::StdlibError;
+use crate::stdlib::format::late_bound_string;
+use crate::stdlib::variable_resolver::{LateBoundString, VariableResolver};
+use crate::stdlib::INLINE_FILE_TYPE;
+use allocative::Allocative;
+use anyhow::bail;
+use starlark::starlark_simple_value;
+use starlark::values::starlark_value;
+use starlark::values::NoSerialize;
+use starlark::values::ProvidesStaticType;
+use starlark::values::StarlarkValue;
+use starlark::values::Value;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use uuid::Uuid;
+
+const SCRATCH_DIR_PREFIX: &str = "workflow-scratch-";
+
+pub(crate) fn file_impl(name: &str, content: Value) -> anyhow::Result<InlineFile> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        bail!(StdlibError::new_invalid_attr(
+            "name",
+            "must be a file name without a directory",
+            name
+        ));
+    }
+    Ok(InlineFile {
+        name: name.to_string(),
+        content: late_bound_string(content),
+        id: Uuid::new_v4().to_string(),
+    })
+}
+
+/// A file whose content is embedded in the workflow. The content is
+/// resolved and written to the scratch dir when the file is used as an
+/// action arg, which resolves to the path of the written file.
+#[derive(Debug, ProvidesStaticType, NoSerialize, Allocative, Clone)]
+pub struct InlineFile {
+    name: String,
+    content: LateBoundString,
+    // every file gets its own directory in the scratch dir so files with
+    // the same name do not overwrite each other
+    id: String,
+}
+starlark_simple_value!(InlineFile);
+
+#[starlark_value(type = INLINE_FILE_TYPE)]
+impl<'v> StarlarkValue<'v> for InlineFile {}
+
+impl InlineFile {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Writes the content, using the current values of the variables, to
+    /// the resolver's scratch dir and returns the path of the file.
+    pub fn write<T: VariableResolver>(&self, resolver: &T) -> anyhow::Result<PathBuf> {
+        let dir = match resolver.context().scratch_dir {
+            Some(dir) => dir.join(&self.id),
+            None => bail!("cannot write file '{}', there is no scratch dir", self.name),
+        };
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(&self.name);
+        fs::write(&path, self.content.get_value(resolver)?)?;
+        Ok(path)
+    }
+}
+
+impl fmt::Display for InlineFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "file({})", self.name)
+    }
+}
+
+/// A directory for the files written during a run, removed along with
+/// everything in it when dropped. The directory is only created once
+/// something is written to it.
+#[derive(Debug)]
+pub(crate) struct ScratchDir {
+    path: PathBuf,
+}
+
+impl ScratchDir {
+    pub(crate) fn new() -> Self {
+        ScratchDir {
+            path: std::env::temp_dir().join(format!("{}{}", SCRATCH_DIR_PREFIX, Uuid::new_v4())),
+        }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Returns the scratch dirs in `temp_dir` which were last modified before
+/// `before`. They are left behind by runs which were killed before the
+/// scratch dir could be removed.
+pub(crate) fn stale_scratch_dirs(temp_dir: &Path, before: SystemTime) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(temp_dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut dirs: Vec<PathBuf> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().into_string().ok()?;
+            let metadata = entry.metadata().ok()?;
+            let stale = name.starts_with(SCRATCH_DIR_PREFIX)
+                && metadata.is_dir()
+                && metadata.modified().ok()? < before;
+            stale.then(|| entry.path())
+        })
+        .collect();
+    dirs.sort();
+    dirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib::run_context::RunContext;
+    use crate::stdlib::test_utils::assert_env;
+
+    /// Resolves every variable to the same value.
+    struct ScratchResolver {
+        dir: ScratchDir,
+        value: &'static str,
+    }
+
+    impl VariableResolver for ScratchResolver {
+        fn resolve(&self, _identifier: &str) -> anyhow::Result<String> {
+            Ok(self.value.to_string())
+        }
+
+        fn context(&self) -> RunContext<'_> {
+            RunContext {
+                scratch_dir: Some(self.dir.path().to_path_buf()),
+                ..Default::default()
+            }
+        }
+    }
+
+    #[test]
+    fn test_file_name_must_not_have_a_directory() {
+        assert_env().pass("file(name = 'config.json', content = '{}')");
+        assert_env().fail(
+            "file(name = 'a/config.json', content = '{}')",
+            "Invalid attribute 'name', must be a file name without a directory",
+        );
+        assert_env().fail(
+            "file(name = '..', content = '{}')",
+            "Invalid attribute 'name'",
+        );
+    }
+
+    #[test]
+    fn test_write_resolves_content() {
+        let mut env = assert_env();
+        let module = env.module(
+            "file.star",
+            "v = variable(default = 'x'); f = file(name = 'a.txt', content = format('v={}', v))",
+        );
+        let f = module.get("f").unwrap();
+        let file = InlineFile::from_value(f.value()).unwrap();
+
+        let resolver = ScratchResolver {
+            dir: ScratchDir::new(),
+            value: "42",
+        };
+        let path = file.write(&resolver).unwrap();
+        assert!(path.starts_with(resolver.dir.path()));
+        assert!(path.ends_with("a.txt"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "v=42");
+
+        let dir = resolver.dir.path().to_path_buf();
+        drop(resolver);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn test_stale_scratch_dirs() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        for name in ["workflow-scratch-a", "workflow-scratch-b", "other"] {
+            fs::create_dir(temp_dir.path().join(name)).unwrap();
+        }
+        fs::write(temp_dir.path().join("workflow-scratch-file"), "").unwrap();
+
+        let later = SystemTime::now() + std::time::Duration::from_secs(60);
+        assert_eq!(
+            stale_scratch_dirs(temp_dir.path(), later),
+            [
+                temp_dir.path().join("workflow-scratch-a"),
+                temp_dir.path().join("workflow-scratch-b")
+            ]
+        );
+        assert!(stale_scratch_dirs(temp_dir.path(), SystemTime::UNIX_EPOCH).is_empty());
+    }
+
+    #[test]
+    fn test_write_without_scratch_dir() {
+        let mut env = assert_env();
+        let module = env.module("file.star", "f = file(name = 'a.txt', content = 'a')");
+        let f = module.get("f").unwrap();
+        let file = InlineFile::from_value(f.value()).unwrap();
+        assert!(file.write(&"value").is_err());
+    }
+}
@@ -0,0 +1,338 @@
+use crate::stdlib::schema::Schema;
+use crate::stdlib::variable_resolver::VariableResolver;
+use crate::stdlib::VariableRef;
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use starlark::values::Value;
+use std::collections::BTreeMap;
+
+/// The version of the intermediate representation, raised whenever a
+/// change to it would be misread by a runner built for an older one.
+pub const IR_VERSION: u32 = 2;
+
+const IR_SCHEMA: Schema = Schema {
+    name: "the compiled workflow",
+    version: IR_VERSION,
+    oldest: 1,
+    migrations: &[with_plain_attributes],
+};
+
+/// Version 2 added the plain attributes, e.g. the setters and retries,
+/// whose defaults are what version 1 ran with.
+fn with_plain_attributes(value: serde_json::Value) -> anyhow::Result<serde_json::Value> {
+    Ok(value)
+}
+
+/// A parsed workflow as plain data, written by `workflow compile` and run
+/// by `workflow run` without the starlark it came from. Only workflows
+/// which can be run without calling into starlark can be compiled, i.e.
+/// their actions run tools, their setters read the output without a
+/// function and their nodes go to the next with `when()`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkflowIr {
+    pub version: u32,
+    pub entrypoint: String,
+    #[serde(default)]
+    pub variables: Vec<VariableIr>,
+    pub nodes: Vec<NodeIr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env_policy: Option<EnvPolicyIr>,
+}
+
+/// Which environment variables the tools are spawned with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EnvPolicyIr {
+    pub inherit: bool,
+    /// The variables passed on when the policy does not inherit.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub set: BTreeMap<String, ArgIr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VariableIr {
+    /// The name the variable is bound to, which the args refer to it by.
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cli_flag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub doc: Option<String>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub required: bool,
+    /// What a required variable without a value is asked for with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeIr {
+    pub name: String,
+    /// Run in order, the node fails at the first whose exit code is not ok.
+    pub actions: Vec<ActionIr>,
+    /// None if the workflow stops after this node.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<NextIr>,
+    /// In seconds, for each attempt.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u32>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub retries: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Whether the stdout of each action is piped into the next one.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub pipe: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum NextIr {
+    /// Always runs the node next.
+    Node { node: String },
+    /// Runs `then` next if the variable equals the value once the node ran,
+    /// `else_` otherwise or stops without one.
+    When {
+        variable: String,
+        equals: String,
+        then: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        else_: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActionIr {
+    pub tool: ToolIr,
+    #[serde(default)]
+    pub args: Vec<ArgIr>,
+    /// Set for the tool on top of the inherited environment.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, ArgIr>,
+    /// Relative to the working dir, the tool inherits it when None.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    /// Run in order once the tool exited.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub setters: Vec<SetterIr>,
+    /// How the output is decoded for the setters, utf8 when None.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub strict_utf8: bool,
+    #[serde(
+        default = "default_ok_exit_codes",
+        skip_serializing_if = "is_default_ok_exit_codes"
+    )]
+    pub ok_exit_codes: Vec<i32>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub quiet: bool,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub retries: u32,
+    /// In seconds, between the retries.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub retry_delay: u32,
+}
+
+/// A setter which reads the variable from the output of the tool, the
+/// stdout or the file, rather than with a function.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SetterIr {
+    pub variable: String,
+    /// Relative to the working dir of the tool, the stdout is read when None.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_file: Option<ArgIr>,
+    /// Whether the whitespace around the contents of the file is stripped.
+    #[serde(default = "default_strip", skip_serializing_if = "is_default_strip")]
+    pub strip: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json_path: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ToolIr {
+    /// Found on the PATH by its name, or the first of its aliases which is.
+    Builtin {
+        name: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        aliases: Vec<String>,
+    },
+    /// At the path, relative to the working dir.
+    Path { path: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArgIr {
+    Literal(String),
+    /// The value of the variable bound to the name.
+    Variable(String),
+}
+
+impl WorkflowIr {
+    /// Reads the IR, migrated from the version it was written with, failing
+    /// if it was written by a newer version.
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        WorkflowIr::from_value(serde_json::from_str(json)?)
+    }
+
+    /// Reads the IR from json which was already parsed, like from_json.
+    pub fn from_value(value: serde_json::Value) -> anyhow::Result<Self> {
+        IR_SCHEMA.read(value)
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+fn default_ok_exit_codes() -> Vec<i32> {
+    vec![0]
+}
+
+fn is_default_ok_exit_codes(codes: &[i32]) -> bool {
+    codes == [0]
+}
+
+fn default_strip() -> bool {
+    true
+}
+
+fn is_default_strip(strip: &bool) -> bool {
+    *strip
+}
+
+/// Returns the name the variable is bound to in the workflow, failing for
+/// variables which are not bound to one.
+pub(crate) fn variable_name<T: VariableResolver>(
+    resolver: &T,
+    identifier: &str,
+) -> anyhow::Result<String> {
+    // the qualified name is prefixed by the group, which has no dots
+    let qualified = resolver
+        .variable_name(identifier)
+        .ok_or_else(|| anyhow!("a variable which is not bound to a name can not be compiled"))?;
+    Ok(match qualified.rsplit_once('.') {
+        Some((_, name)) => name.to_string(),
+        None => qualified,
+    })
+}
+
+/// Compiles a string or variable arg, None for any other kind of value.
+pub(crate) fn compile_arg<T: VariableResolver>(
+    value: Value,
+    resolver: &T,
+) -> anyhow::Result<Option<ArgIr>> {
+    if let Some(arg) = value.unpack_str() {
+        return Ok(Some(ArgIr::Literal(arg.to_string())));
+    }
+    match VariableRef::from_value(value) {
+        Some(variable) => Ok(Some(ArgIr::Variable(variable_name(
+            resolver,
+            variable.identifier(),
+        )?))),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_round_trip() {
+        let ir = WorkflowIr {
+            version: IR_VERSION,
+            entrypoint: "build".to_string(),
+            variables: vec![VariableIr {
+                name: "profile".to_string(),
+                group: None,
+                default: Some("debug".to_string()),
+                env: None,
+                cli_flag: Some("--profile".to_string()),
+                doc: None,
+                required: false,
+                prompt: None,
+            }],
+            nodes: vec![NodeIr {
+                name: "build".to_string(),
+                actions: vec![ActionIr {
+                    tool: ToolIr::Builtin {
+                        name: "cargo".to_string(),
+                        aliases: vec![],
+                    },
+                    args: vec![
+                        ArgIr::Literal("build".to_string()),
+                        ArgIr::Variable("profile".to_string()),
+                    ],
+                    env: BTreeMap::new(),
+                    cwd: None,
+                    setters: vec![SetterIr {
+                        variable: "version".to_string(),
+                        from_file: Some(ArgIr::Literal("VERSION".to_string())),
+                        strip: true,
+                        json_path: None,
+                    }],
+                    encoding: None,
+                    strict_utf8: false,
+                    ok_exit_codes: vec![0],
+                    quiet: false,
+                    retries: 2,
+                    retry_delay: 0,
+                }],
+                next: Some(NextIr::When {
+                    variable: "profile".to_string(),
+                    equals: "release".to_string(),
+                    then: "package".to_string(),
+                    else_: None,
+                }),
+                timeout: None,
+                retries: 0,
+                tags: vec!["build".to_string()],
+                pipe: false,
+            }],
+            env_policy: None,
+        };
+        let json = ir.to_json().unwrap();
+        assert!(json.contains(r#""kind": "when""#));
+        assert!(json.contains(r#""variable": "profile""#));
+        assert!(json.contains(r#""retries": 2"#));
+        assert!(!json.contains("ok_exit_codes"));
+        assert!(!json.contains("strip"));
+        assert_eq!(WorkflowIr::from_json(&json).unwrap(), ir);
+
+        let newer = json.replace(r#""version": 2"#, r#""version": 3"#);
+        assert_eq!(
+            WorkflowIr::from_json(&newer).unwrap_err().to_string(),
+            "the compiled workflow was produced by a newer version of workflow (version 3), this one reads up to version 2"
+        );
+    }
+
+    #[test]
+    fn test_reads_version_1() {
+        let ir = WorkflowIr::from_json(
+            r#"{"version": 1, "entrypoint": "a", "nodes": [{"name": "a", "actions": [{"tool": {"kind": "builtin", "name": "true"}}]}]}"#,
+        )
+        .unwrap();
+        assert_eq!(ir.version, IR_VERSION);
+        let action = &ir.nodes[0].actions[0];
+        assert_eq!(action.ok_exit_codes, [0]);
+        assert!(action.setters.is_empty());
+        assert_eq!(ir.nodes[0].retries, 0);
+    }
+}
@@ -0,0 +1,204 @@
+use anyhow::bail;
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::thread::{self, ThreadId};
+
+/// Named in-process locks which keep nodes that declare the same
+/// `requires_lock` from running at the same time.
+///
+/// A node's locks are acquired in sorted order so two nodes can never
+/// deadlock on each other's locks. Locks which are acquired separately,
+/// e.g. by nested runs, can still form a cycle; this is detected and
+/// reported as an error instead of blocking forever.
+#[derive(Debug, Default)]
+pub struct LockManager {
+    state: Mutex<LockState>,
+    released: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct LockState {
+    // lock name -> the thread holding it
+    holders: HashMap<String, ThreadId>,
+    // thread -> the lock it is blocked on
+    waiting: HashMap<ThreadId, String>,
+}
+
+impl LockState {
+    /// Returns true if waiting for `name` would complete a cycle, i.e. the
+    /// chain of holders waiting on other locks leads back to `me`.
+    fn would_deadlock(&self, name: &str, me: ThreadId) -> bool {
+        let mut current = name;
+        for _ in 0..=self.holders.len() {
+            let holder = match self.holders.get(current) {
+                Some(holder) => *holder,
+                None => return false,
+            };
+            if holder == me {
+                return true;
+            }
+            current = match self.waiting.get(&holder) {
+                Some(wanted) => wanted,
+                None => return false,
+            };
+        }
+        false
+    }
+}
+
+/// Holds a set of locks, releasing them when dropped.
+#[derive(Debug)]
+pub struct LockGuard<'a> {
+    manager: &'a LockManager,
+    names: Vec<String>,
+}
+
+impl Drop for LockGuard<'_> {
+    fn drop(&mut self) {
+        if self.names.is_empty() {
+            return;
+        }
+        let mut state = self.manager.state.lock().unwrap();
+        for name in &self.names {
+            state.holders.remove(name);
+        }
+        self.manager.released.notify_all();
+    }
+}
+
+impl LockManager {
+    pub fn new() -> Self {
+        LockManager::default()
+    }
+
+    /// The lock manager shared by all runs in this process.
+    pub fn global() -> &'static LockManager {
+        static GLOBAL: OnceLock<LockManager> = OnceLock::new();
+        GLOBAL.get_or_init(LockManager::new)
+    }
+
+    /// Blocks until all of the named locks are held by the current thread.
+    pub fn acquire(&self, names: &[String]) -> anyhow::Result<LockGuard<'_>> {
+        let mut sorted = names.to_vec();
+        sorted.sort();
+        sorted.dedup();
+
+        let me = thread::current().id();
+        let mut guard = LockGuard {
+            manager: self,
+            names: vec![],
+        };
+        for name in sorted {
+            let mut state = self.state.lock().unwrap();
+            loop {
+                match state.holders.get(&name) {
+                    None => break,
+                    Some(holder) if *holder == me => {
+                        bail!("Lock '{}' is already held by this run", name)
+                    }
+                    Some(_) => {
+                        if state.would_deadlock(&name, me) {
+                            bail!("Deadlock detected while acquiring lock '{}'", name);
+                        }
+                        state.waiting.insert(me, name.clone());
+                        state = self.released.wait(state).unwrap();
+                        state.waiting.remove(&me);
+                    }
+                }
+            }
+            state.holders.insert(name.clone(), me);
+            guard.names.push(name);
+        }
+        Ok(guard)
+    }
+
+    /// Returns true if the named lock is currently held.
+    pub fn is_locked(&self, name: &str) -> bool {
+        self.state.lock().unwrap().holders.contains_key(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn wait_for_waiters(manager: &LockManager, count: usize) {
+        while manager.state.lock().unwrap().waiting.len() < count {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_acquire_and_release() {
+        let manager = LockManager::new();
+        {
+            let _guard = manager.acquire(&names(&["b", "a", "a"])).unwrap();
+            assert!(manager.is_locked("a"));
+            assert!(manager.is_locked("b"));
+        }
+        assert!(!manager.is_locked("a"));
+        assert!(!manager.is_locked("b"));
+    }
+
+    #[test]
+    fn test_reacquire_on_same_thread_fails() {
+        let manager = LockManager::new();
+        let _guard = manager.acquire(&names(&["a"])).unwrap();
+        let err = manager.acquire(&names(&["b", "a"])).unwrap_err();
+        assert_eq!(err.to_string(), "Lock 'a' is already held by this run");
+        // the partially acquired lock is released again
+        assert!(!manager.is_locked("b"));
+    }
+
+    #[test]
+    fn test_waits_for_release() {
+        let manager = Arc::new(LockManager::new());
+        let guard = manager.acquire(&names(&["db"])).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let other = manager.clone();
+        let handle = thread::spawn(move || {
+            let _guard = other.acquire(&names(&["db"])).unwrap();
+            tx.send(()).unwrap();
+        });
+
+        wait_for_waiters(&manager, 1);
+        assert!(rx.try_recv().is_err());
+        drop(guard);
+        rx.recv().unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_detects_lock_cycle() {
+        let manager = Arc::new(LockManager::new());
+        let a = manager.acquire(&names(&["a"])).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let other = manager.clone();
+        let handle = thread::spawn(move || {
+            let _b = other.acquire(&names(&["b"])).unwrap();
+            tx.send(()).unwrap();
+            // blocks until the main thread releases "a"
+            other.acquire(&names(&["a"])).map(|_| ())
+        });
+
+        rx.recv().unwrap();
+        wait_for_waiters(&manager, 1);
+        let err = manager.acquire(&names(&["b"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Deadlock detected while acquiring lock 'b'"
+        );
+
+        drop(a);
+        assert!(handle.join().unwrap().is_ok());
+    }
+}
@@ -0,0 +1,64 @@
+use starlark::values::Heap;
+use std::fs;
+
+/// The memory used by a run, taken after a node finishes when running
+/// with memory profiling enabled.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MemorySnapshot {
+    /// The number of bytes allocated on the starlark heap.
+    pub heap_bytes: usize,
+    /// The number of values allocated on the starlark heap.
+    pub heap_values: usize,
+    /// The resident set size of the process, None if it is not available
+    /// on this platform.
+    pub rss_bytes: Option<u64>,
+}
+
+impl MemorySnapshot {
+    pub fn take(heap: &Heap) -> Self {
+        MemorySnapshot {
+            heap_bytes: heap.allocated_bytes(),
+            heap_values: heap
+                .allocated_summary()
+                .summary()
+                .values()
+                .map(|(count, _)| count)
+                .sum(),
+            rss_bytes: process_rss_bytes(),
+        }
+    }
+}
+
+/// Returns the resident set size of the current process.
+fn process_rss_bytes() -> Option<u64> {
+    parse_vm_rss(&fs::read_to_string("/proc/self/status").ok()?)
+}
+
+fn parse_vm_rss(status: &str) -> Option<u64> {
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_vm_rss() {
+        let status = "Name:\tworkflow\nVmPeak:\t  9000 kB\nVmRSS:\t  1234 kB\nThreads:\t1\n";
+        assert_eq!(parse_vm_rss(status), Some(1234 * 1024));
+        assert_eq!(parse_vm_rss("Name:\tworkflow\n"), None);
+    }
+
+    #[test]
+    fn test_snapshot_counts_heap_values() {
+        let heap = Heap::new();
+        let empty = MemorySnapshot::take(&heap);
+        heap.alloc("a string value");
+        heap.alloc(vec![1, 2, 3]);
+        let snapshot = MemorySnapshot::take(&heap);
+        assert!(snapshot.heap_values > empty.heap_values);
+        assert!(snapshot.heap_bytes > empty.heap_bytes);
+    }
+}
@@ -1,32 +1,78 @@
 pub mod action;
+pub mod approval;
+mod archive;
 pub mod arg_spec;
+pub mod artifact;
+mod builtin_action;
+pub mod cancel;
+pub(crate) mod capture;
+pub mod dev;
+pub mod env_capture;
+pub mod env_policy;
 pub mod errors;
+pub mod failure_injection;
 pub mod format;
+pub mod glob;
+mod graph_scope;
+pub mod inline_file;
+pub mod ir;
+#[cfg(feature = "legacy")]
 pub mod legacy;
+pub mod locks;
+pub mod memory;
+pub mod native;
 pub mod next;
 pub mod node;
+pub mod node_cache;
 pub mod parse_delegate;
+#[cfg(feature = "legacy")]
 pub mod parser;
+pub mod plan;
+pub mod redact;
+pub mod run_context;
+pub mod run_delegate;
+pub mod run_result;
+pub mod schema;
 pub mod setter;
 pub mod tool;
 pub mod variable;
 pub mod variable_resolver;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod when;
 pub mod workflow;
 
 pub use self::parse_delegate::{ParseDelegate, ParseDelegateHolder};
-pub use crate::stdlib::action::Action;
+pub use crate::stdlib::action::{Action, ActionCtx, Attempt};
+pub use crate::stdlib::memory::MemorySnapshot;
+pub use crate::stdlib::native::register_native_tool;
 pub use crate::stdlib::next::{Next, NextStub};
-pub use crate::stdlib::node::Node;
+pub use crate::stdlib::node::{Node, TagFilter};
+pub use crate::stdlib::run_delegate::{ActionStatus, RunDelegate};
+pub use crate::stdlib::run_result::{NodeResult, RunResult};
 use crate::stdlib::setter::Setter;
 use crate::stdlib::tool::Tool;
-pub use crate::stdlib::variable::{ValueContext, ValueUpdatedBy, VariableEntry, VariableRef};
+pub use crate::stdlib::variable::{
+    EnvMode, ValueContext, ValueUpdatedBy, VariableEntry, VariableRef,
+};
 pub use crate::stdlib::workflow::Workflow;
 
-use action::action_impl;
+use action::{
+    action_impl, archive_impl, fn_action_impl, render_template_impl, shell_impl, unarchive_impl,
+    verify_impl,
+};
+use artifact::{artifact_impl, Artifact};
+use env_policy::{env_policy_impl, EnvPolicy};
+use errors::ArgCheck;
 use format::format_impl;
 use format::ValueFormatter;
+use glob::{glob_impl, Glob};
+use inline_file::{file_impl, InlineFile};
 use next::next_impl;
-use node::{node_impl, sequence_impl};
+use node::{
+    artifact_patterns, graph_node_impl, input_patterns, manual_gate_impl, node_impl,
+    output_patterns, sequence_impl, tag_names,
+};
 use setter::setter_impl;
 use starlark::environment::GlobalsBuilder;
 use starlark::eval::Evaluator;
@@ -34,9 +80,11 @@ use starlark::starlark_module;
 use starlark::values::dict::DictOf;
 use starlark::values::list::{ListOf, ListRef};
 use starlark::values::tuple::UnpackTuple;
+use starlark::values::Heap;
 use starlark::values::Value;
-use tool::{builtin_tool_impl, tool_impl};
-use variable::variable_impl;
+use tool::{builtin_tool_impl, native_tool_impl, tool_impl, wasm_tool_impl};
+use variable::{const_impl, variable_impl};
+use when::{when_impl, When};
 use workflow::workflow_impl;
 
 pub const ACTION_TYPE: &str = "action";
@@ -52,6 +100,11 @@ pub const NEXT_STUB_TYPE: &str = "next_stub";
 pub const STRING_ARG_TYPE: &str = "string_arg";
 pub const INT_ARG_TYPE: &str = "int_arg";
 pub const STRUCT_VALUE_TYPE: &str = "struct_value";
+pub const INLINE_FILE_TYPE: &str = "file";
+pub const GLOB_TYPE: &str = "glob";
+pub const ARTIFACT_TYPE: &str = "artifact";
+pub const WHEN_TYPE: &str = "when";
+pub const ENV_POLICY_TYPE: &str = "env_policy";
 
 /// A macro to downcast the delegate to an Option<T> without having
 /// to deal with lifetimes.
@@ -71,15 +124,59 @@ pub use downcast_delegate_ref;
 #[starlark_module]
 pub fn starlark_stdlib(builder: &mut GlobalsBuilder) {
     /// The variable definition
+    #[allow(clippy::too_many_arguments)]
     fn variable(
         #[starlark(require = named)] default: Option<&str>,
         #[starlark(require = named)] env: Option<&str>,
+        #[starlark(require = named)] env_mode: Option<&str>,
         #[starlark(require = named)] cli_flag: Option<&str>,
         #[starlark(require = named)] readers: Option<ListOf<String>>,
         #[starlark(require = named)] writers: Option<ListOf<String>>,
+        #[starlark(require = named)] group: Option<&str>,
+        #[starlark(require = named)] deprecated: Option<&str>,
+        #[starlark(require = named, default = false)] required: bool,
+        #[starlark(require = named)] prompt: Option<&str>,
+        #[starlark(require = named)] doc: Option<&str>,
         eval: &mut Evaluator,
     ) -> anyhow::Result<VariableRef> {
-        variable_impl(default, env, cli_flag, readers, writers, eval)
+        variable_impl(
+            VariableEntry::from_starlark(
+                default, env, env_mode, cli_flag, readers, writers, group, deprecated,
+            )?
+            .with_required(required, prompt)?
+            .with_doc(doc)?,
+            eval,
+        )
+    }
+
+    /// The secret definition
+    #[allow(clippy::too_many_arguments)]
+    fn secret(
+        #[starlark(require = named)] env: Option<&str>,
+        #[starlark(require = named)] env_mode: Option<&str>,
+        #[starlark(require = named)] cli_flag: Option<&str>,
+        #[starlark(require = named)] readers: Option<ListOf<String>>,
+        #[starlark(require = named)] writers: Option<ListOf<String>>,
+        #[starlark(require = named)] group: Option<&str>,
+        #[starlark(require = named)] doc: Option<&str>,
+        eval: &mut Evaluator,
+    ) -> anyhow::Result<VariableRef> {
+        variable_impl(
+            VariableEntry::from_starlark(
+                None, env, env_mode, cli_flag, readers, writers, group, None,
+            )?
+            .into_secret()
+            .with_doc(doc)?,
+            eval,
+        )
+    }
+
+    /// The const definition
+    fn r#const<'v>(
+        #[starlark(require = named)] value: Value<'v>,
+        eval: &mut Evaluator,
+    ) -> anyhow::Result<VariableRef> {
+        const_impl(value, eval)
     }
 
     /// The format definition
@@ -90,67 +187,364 @@ pub fn starlark_stdlib(builder: &mut GlobalsBuilder) {
         format_impl(fmt_str, args)
     }
 
+    /// The file definition
+    fn file<'v>(
+        #[starlark(require = named)] name: &str,
+        #[starlark(require = named)] content: Value<'v>,
+    ) -> anyhow::Result<InlineFile> {
+        file_impl(name, content)
+    }
+
+    /// The glob definition
+    fn glob<'v>(
+        #[starlark(require = pos)] pattern: Value<'v>,
+        #[starlark(require = named)] allow_empty: Option<bool>,
+    ) -> anyhow::Result<Glob> {
+        glob_impl(pattern, allow_empty.unwrap_or(false))
+    }
+
+    /// The artifact definition
+    fn artifact(
+        #[starlark(require = pos)] node: &str,
+        #[starlark(require = pos)] pattern: &str,
+    ) -> anyhow::Result<Artifact> {
+        artifact_impl(node, pattern)
+    }
+
+    /// The when definition
+    fn when<'v>(
+        #[starlark(require = named)] variable: Value<'v>,
+        #[starlark(require = named)] equals: &str,
+        #[starlark(require = named)] then: &str,
+        #[starlark(require = named)] else_: Option<&str>,
+    ) -> anyhow::Result<When> {
+        when_impl(variable, equals, then, else_)
+    }
+
+    /// The render_template definition
+    fn render_template<'v>(
+        #[starlark(require = named)] src: Value<'v>,
+        #[starlark(require = named)] dest: Value<'v>,
+        #[starlark(require = named)] vars: Option<DictOf<'v, String, Value<'v>>>,
+        #[starlark(require = named)] setters: Option<ListOf<'v, Value<'v>>>,
+        heap: &'v Heap,
+    ) -> anyhow::Result<Action<'v>> {
+        render_template_impl(
+            src,
+            dest,
+            vars.map(|v| v.to_dict()).unwrap_or_default(),
+            setters.map(|v| v.to_vec()).unwrap_or_default(),
+            heap,
+        )
+    }
+
+    /// The verify definition
+    fn verify<'v>(
+        #[starlark(require = named)] path: Value<'v>,
+        #[starlark(require = named)] sha256: Value<'v>,
+        #[starlark(require = named)] setters: Option<ListOf<'v, Value<'v>>>,
+    ) -> anyhow::Result<Action<'v>> {
+        verify_impl(
+            path,
+            sha256,
+            setters.map(|v| v.to_vec()).unwrap_or_default(),
+        )
+    }
+
+    /// The archive definition
+    fn archive<'v>(
+        #[starlark(require = named)] paths: ListOf<'v, Value<'v>>,
+        #[starlark(require = named)] dest: Value<'v>,
+        #[starlark(require = named)] root: Option<Value<'v>>,
+        #[starlark(require = named)] setters: Option<ListOf<'v, Value<'v>>>,
+        heap: &'v Heap,
+    ) -> anyhow::Result<Action<'v>> {
+        archive_impl(
+            paths.to_vec(),
+            dest,
+            root,
+            setters.map(|v| v.to_vec()).unwrap_or_default(),
+            heap,
+        )
+    }
+
+    /// The unarchive definition
+    fn unarchive<'v>(
+        #[starlark(require = named)] src: Value<'v>,
+        #[starlark(require = named)] dest: Value<'v>,
+        #[starlark(require = named)] keep_special_bits: Option<bool>,
+        #[starlark(require = named)] setters: Option<ListOf<'v, Value<'v>>>,
+        heap: &'v Heap,
+    ) -> anyhow::Result<Action<'v>> {
+        unarchive_impl(
+            src,
+            dest,
+            keep_special_bits.unwrap_or_default(),
+            setters.map(|v| v.to_vec()).unwrap_or_default(),
+            heap,
+        )
+    }
+
     /// The tool definition
-    fn tool<'v>(#[starlark(require = named)] path: Value<'v>) -> anyhow::Result<Tool<'v>> {
-        tool_impl(path)
+    fn tool<'v>(
+        #[starlark(require = named)] path: Option<Value<'v>>,
+        #[starlark(require = named)] wasm: Option<Value<'v>>,
+    ) -> anyhow::Result<Tool<'v>> {
+        ArgCheck::new("tool")
+            .arg("path", path.is_some())
+            .arg("wasm", wasm.is_some())
+            .exactly_one_of(&["path", "wasm"])?;
+        match (path, wasm) {
+            (Some(path), _) => tool_impl(path),
+            (_, Some(wasm)) => wasm_tool_impl(wasm),
+            _ => unreachable!(),
+        }
     }
 
     /// The builtin_tool definition
-    fn builtin_tool<'v>(#[starlark(require = named)] name: &str) -> anyhow::Result<Tool<'v>> {
-        builtin_tool_impl(name)
+    fn builtin_tool<'v>(
+        #[starlark(require = named)] name: &str,
+        #[starlark(require = named)] aliases: Option<ListOf<String>>,
+    ) -> anyhow::Result<Tool<'v>> {
+        builtin_tool_impl(name, aliases.map(|v| v.to_vec()).unwrap_or_default())
+    }
+
+    /// The native_tool definition
+    fn native_tool<'v>(#[starlark(require = named)] name: &str) -> anyhow::Result<Tool<'v>> {
+        native_tool_impl(name)
     }
 
     /// The action definition
+    #[allow(clippy::too_many_arguments)]
     fn action<'v>(
         #[starlark(require = named)] tool: Value<'v>,
         #[starlark(require = named)] args: Option<ListOf<'v, Value<'v>>>,
         #[starlark(require = named)] setters: Option<ListOf<'v, Value<'v>>>,
+        #[starlark(require = named)] encoding: Option<&str>,
+        #[starlark(require = named)] strict_utf8: Option<bool>,
+        #[starlark(require = named)] ok_exit_codes: Option<ListOf<i32>>,
+        #[starlark(require = named)] env: Option<DictOf<'v, String, Value<'v>>>,
+        #[starlark(require = named)] cwd: Option<Value<'v>>,
+        #[starlark(require = named)] quiet: Option<bool>,
+        #[starlark(require = named)] retries: Option<i32>,
+        #[starlark(require = named)] retry_delay: Option<i32>,
     ) -> anyhow::Result<Action<'v>> {
         action_impl(
             tool,
             args.map(|v| v.to_vec()).unwrap_or_default(),
             setters.map(|v| v.to_vec()).unwrap_or_default(),
+            encoding,
+            strict_utf8.unwrap_or_default(),
+            ok_exit_codes.map(|v| v.to_vec()),
+            env.map(|v| v.to_dict()).unwrap_or_default(),
+            cwd,
+            quiet.unwrap_or_default(),
+            retries,
+            retry_delay,
+        )
+    }
+
+    /// The shell definition
+    #[allow(clippy::too_many_arguments)]
+    fn shell<'v>(
+        #[starlark(require = named)] script: Value<'v>,
+        #[starlark(require = named)] shell: Option<Value<'v>>,
+        #[starlark(require = named)] setters: Option<ListOf<'v, Value<'v>>>,
+        #[starlark(require = named)] ok_exit_codes: Option<ListOf<i32>>,
+        #[starlark(require = named)] env: Option<DictOf<'v, String, Value<'v>>>,
+        #[starlark(require = named)] cwd: Option<Value<'v>>,
+        #[starlark(require = named)] quiet: Option<bool>,
+        #[starlark(require = named)] retries: Option<i32>,
+        #[starlark(require = named)] retry_delay: Option<i32>,
+        heap: &'v Heap,
+    ) -> anyhow::Result<Action<'v>> {
+        shell_impl(
+            script,
+            shell,
+            setters.map(|v| v.to_vec()).unwrap_or_default(),
+            ok_exit_codes.map(|v| v.to_vec()),
+            env.map(|v| v.to_dict()).unwrap_or_default(),
+            cwd,
+            quiet.unwrap_or_default(),
+            retries,
+            retry_delay,
+            heap,
+        )
+    }
+
+    /// The fn_action definition
+    fn fn_action<'v>(
+        #[starlark(require = named)] implementation: Value<'v>,
+        #[starlark(require = named)] args: Option<ListOf<'v, Value<'v>>>,
+        #[starlark(require = named)] setters: Option<ListOf<'v, Value<'v>>>,
+    ) -> anyhow::Result<Action<'v>> {
+        fn_action_impl(
+            implementation,
+            args.map(|v| v.to_vec()).unwrap_or_default(),
+            setters.map(|v| v.to_vec()).unwrap_or_default(),
         )
     }
 
     /// The workflow definition
+    #[allow(clippy::too_many_arguments)]
     fn workflow<'v>(
         #[starlark(require = named)] entrypoint: Option<&str>,
         #[starlark(require = named)] graph: Value<'v>,
+        #[starlark(require = named)] requires: Option<ListOf<String>>,
+        #[starlark(require = named)] redact_patterns: Option<ListOf<String>>,
+        #[starlark(require = named)] matrix: Option<DictOf<'v, String, Value<'v>>>,
+        #[starlark(require = named)] inputs: Option<ListOf<String>>,
+        #[starlark(require = named)] outputs: Option<ListOf<String>>,
+        #[starlark(require = named)] env_policy: Option<Value<'v>>,
     ) -> anyhow::Result<Workflow<'v>> {
-        workflow_impl(entrypoint.unwrap_or_default(), {
-            if let Some(list_ref) = ListRef::from_value(graph) {
-                list_ref.to_vec()
-            } else {
-                vec![graph]
-            }
-        })
+        let inputs = input_patterns(inputs.map(|v| v.to_vec()).unwrap_or_default())?;
+        let outputs = output_patterns(outputs.map(|v| v.to_vec()).unwrap_or_default())?;
+        workflow_impl(
+            entrypoint.unwrap_or_default(),
+            {
+                if let Some(list_ref) = ListRef::from_value(graph) {
+                    list_ref.to_vec()
+                } else {
+                    vec![graph]
+                }
+            },
+            requires.map(|v| v.to_vec()).unwrap_or_default(),
+            redact_patterns.map(|v| v.to_vec()).unwrap_or_default(),
+            matrix.map(|v| v.to_dict()).unwrap_or_default(),
+        )?
+        .with_sandbox(inputs, outputs)
+        .with_env_policy(env_policy)
+    }
+
+    /// The env_policy definition
+    fn env_policy<'v>(
+        #[starlark(require = named)] inherit: Option<bool>,
+        #[starlark(require = named)] allow: Option<ListOf<String>>,
+        #[starlark(require = named)] set: Option<DictOf<'v, String, Value<'v>>>,
+    ) -> anyhow::Result<EnvPolicy> {
+        env_policy_impl(
+            inherit.unwrap_or(true),
+            allow.map(|v| v.to_vec()).unwrap_or_default(),
+            set.map(|v| v.to_dict()).unwrap_or_default(),
+        )
     }
 
     /// The node definition
+    #[allow(clippy::too_many_arguments)]
     fn node<'v>(
         #[starlark(require = named)] name: Option<&str>,
-        #[starlark(require = named)] action: Value<'v>,
+        #[starlark(require = named)] action: Option<Value<'v>>,
+        #[starlark(require = named)] graph: Option<ListOf<'v, Value<'v>>>,
+        #[starlark(require = named)] imports: Option<ListOf<'v, Value<'v>>>,
+        #[starlark(require = named)] exports: Option<ListOf<'v, Value<'v>>>,
+        #[starlark(require = named)] next: Option<Value<'v>>,
+        #[starlark(require = named)] requires_lock: Option<Value<'v>>,
+        #[starlark(require = named)] timeout: Option<i32>,
+        #[starlark(require = named)] retries: Option<i32>,
+        #[starlark(require = named)] tags: Option<ListOf<String>>,
+        #[starlark(require = named)] artifacts: Option<ListOf<String>>,
+        #[starlark(require = named)] cache: Option<bool>,
+        #[starlark(require = named)] inputs: Option<ListOf<String>>,
+    ) -> anyhow::Result<Node<'v>> {
+        ArgCheck::new("node")
+            .arg("action", action.is_some())
+            .arg("graph", graph.is_some())
+            .arg("imports", imports.is_some())
+            .arg("exports", exports.is_some())
+            .arg("inputs", inputs.is_some())
+            .arg("cache", cache == Some(true))
+            .exactly_one_of(&["action", "graph"])?
+            .at_most_one_of(&["graph", "cache"])?
+            .requires("imports", "graph")?
+            .requires("exports", "graph")?
+            .requires("inputs", "cache")?;
+        let tags = tag_names(tags.map(|v| v.to_vec()).unwrap_or_default())?;
+        let artifacts = artifact_patterns(artifacts.map(|v| v.to_vec()).unwrap_or_default())?;
+        let inputs = input_patterns(inputs.map(|v| v.to_vec()).unwrap_or_default())?;
+        let name = name.unwrap_or_default();
+        let node = match (action, graph) {
+            (Some(action), _) => node_impl(name, action, next, requires_lock, timeout, retries)?,
+            (None, graph) => graph_node_impl(
+                name,
+                graph.map(|v| v.to_vec()).unwrap_or_default(),
+                imports.map(|v| v.to_vec()).unwrap_or_default(),
+                exports.map(|v| v.to_vec()).unwrap_or_default(),
+                next,
+                requires_lock,
+                timeout,
+                retries,
+            )?,
+        };
+        Ok(node
+            .with_tags(tags)
+            .with_artifacts(artifacts)
+            .with_cache(cache.unwrap_or_default(), inputs))
+    }
+
+    /// The manual_gate definition
+    fn manual_gate<'v>(
+        #[starlark(require = named)] name: &str,
+        #[starlark(require = named)] message: Value<'v>,
+        #[starlark(require = named)] timeout: Option<i32>,
         #[starlark(require = named)] next: Option<Value<'v>>,
+        heap: &'v Heap,
     ) -> anyhow::Result<Node<'v>> {
-        node_impl(name.unwrap_or_default(), action, next)
+        manual_gate_impl(name, message, timeout, next, heap)
     }
 
     /// The sequence definition
+    #[allow(clippy::too_many_arguments)]
     fn sequence<'v>(
         #[starlark(require = named)] name: Option<&str>,
         #[starlark(require = named)] actions: ListOf<'v, Value<'v>>,
         #[starlark(require = named)] next: Option<Value<'v>>,
+        #[starlark(require = named)] requires_lock: Option<Value<'v>>,
+        #[starlark(require = named)] timeout: Option<i32>,
+        #[starlark(require = named)] retries: Option<i32>,
+        #[starlark(require = named)] tags: Option<ListOf<String>>,
+        #[starlark(require = named)] artifacts: Option<ListOf<String>>,
+        #[starlark(require = named)] cache: Option<bool>,
+        #[starlark(require = named)] inputs: Option<ListOf<String>>,
+        #[starlark(require = named)] pipe: Option<bool>,
     ) -> anyhow::Result<Node<'v>> {
-        sequence_impl(name.unwrap_or_default(), actions.to_vec(), next)
+        ArgCheck::new("sequence")
+            .arg("inputs", inputs.is_some())
+            .arg("cache", cache == Some(true))
+            .requires("inputs", "cache")?;
+        let tags = tag_names(tags.map(|v| v.to_vec()).unwrap_or_default())?;
+        let artifacts = artifact_patterns(artifacts.map(|v| v.to_vec()).unwrap_or_default())?;
+        let inputs = input_patterns(inputs.map(|v| v.to_vec()).unwrap_or_default())?;
+        sequence_impl(
+            name.unwrap_or_default(),
+            actions.to_vec(),
+            next,
+            requires_lock,
+            timeout,
+            retries,
+        )?
+        .with_tags(tags)
+        .with_artifacts(artifacts)
+        .with_cache(cache.unwrap_or_default(), inputs)
+        .with_pipe(pipe.unwrap_or_default())
     }
 
     /// The setter definition
     fn setter<'v>(
-        #[starlark(require = named)] implementation: Value<'v>,
+        #[starlark(require = named)] implementation: Option<Value<'v>>,
         #[starlark(require = named)] variable: Value<'v>,
+        #[starlark(require = named)] returns: Option<&str>,
+        #[starlark(require = named)] from_file: Option<Value<'v>>,
+        #[starlark(require = named)] strip: Option<bool>,
+        #[starlark(require = named)] json_path: Option<&str>,
     ) -> anyhow::Result<Setter<'v>> {
-        setter_impl(implementation, variable)
+        setter_impl(
+            implementation,
+            variable,
+            returns,
+            from_file,
+            strip,
+            json_path,
+        )
     }
 
     /// The next definition
@@ -168,6 +562,7 @@ pub fn starlark_stdlib(builder: &mut GlobalsBuilder) {
 #[cfg(test)]
 pub mod test_utils {
     use super::*;
+    use crate::runner::{Runner, WorkflowDelegate};
     use starlark::assert::Assert;
     use std::any::Any;
     use std::cell::RefCell;
@@ -204,9 +599,24 @@ pub mod test_utils {
         }
     }
 
+    /// Parses and runs the `main` workflow defined in `content`.
+    pub fn run_workflow(content: &str) -> anyhow::Result<RunResult> {
+        let file = TempWorkflowFile::new("test.workflow", content)?;
+        let runner = Runner::new(file.path(), WorkflowDelegate::new())?;
+        runner.run(None)
+    }
+
+    /// Asserts that the run visited exactly the given nodes, in order.
+    pub fn assert_path(result: &RunResult, expected: &[&str]) {
+        assert_eq!(result.path(), expected, "unexpected node path");
+    }
+
     pub fn assert_env<'a>() -> Assert<'a> {
         let mut env = Assert::new();
-        env.globals_add(starlark_stdlib);
+        env.globals_add(|builder| {
+            starlark_stdlib(builder);
+            dev::dev_stdlib(builder);
+        });
         env
     }
 
@@ -231,8 +641,9 @@ pub mod test_utils {
             self.workflow_file.replace(workflow);
         }
 
-        fn did_parse_workflow(&self) {
+        fn did_parse_workflow(&self) -> anyhow::Result<()> {
             self.completed.replace(true);
+            Ok(())
         }
     }
 
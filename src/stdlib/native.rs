@@ -0,0 +1,70 @@
+use crate::stdlib::action::ActionCtx;
+use std::sync::{Arc, Mutex};
+
+/// The signature of a native tool. It receives the fully resolved
+/// arguments of the action and returns the result of running it.
+pub type NativeToolFn = dyn Fn(&[String]) -> anyhow::Result<ActionCtx> + Send + Sync;
+
+static NATIVE_TOOLS: Mutex<Vec<(String, Arc<NativeToolFn>)>> = Mutex::new(Vec::new());
+
+/// Registers a Rust closure which can be used as a tool by workflows
+/// via `native_tool(name = "...")`. This allows applications which embed
+/// the workflow engine to run in-process work alongside external commands.
+/// Registering a name a second time replaces the previous closure.
+///
+/// ```ignore
+/// workflow::stdlib::register_native_tool("version", |_args| {
+///     Ok(ActionCtx::new("1.2.3".to_string(), "".to_string(), 0))
+/// });
+/// ```
+pub fn register_native_tool<F>(name: &str, f: F)
+where
+    F: Fn(&[String]) -> anyhow::Result<ActionCtx> + Send + Sync + 'static,
+{
+    let mut tools = NATIVE_TOOLS.lock().unwrap();
+    tools.retain(|(n, _)| n != name);
+    tools.push((name.to_string(), Arc::new(f)));
+}
+
+/// Returns the native tool registered with the given name.
+pub(crate) fn native_tool(name: &str) -> Option<Arc<NativeToolFn>> {
+    NATIVE_TOOLS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, f)| f.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_native_tool() {
+        register_native_tool("native_test_echo", |args| {
+            Ok(ActionCtx::new(args.join(" "), "".to_string(), 0))
+        });
+        let f = native_tool("native_test_echo").unwrap();
+        let ctx = f(&["a".to_string(), "b".to_string()]).unwrap();
+        assert_eq!(ctx.stdout().unwrap(), "a b");
+        assert_eq!(ctx.exit_code(), 0);
+    }
+
+    #[test]
+    fn test_register_native_tool_replaces() {
+        register_native_tool("native_test_replace", |_| {
+            Ok(ActionCtx::new("".to_string(), "".to_string(), 1))
+        });
+        register_native_tool("native_test_replace", |_| {
+            Ok(ActionCtx::new("".to_string(), "".to_string(), 2))
+        });
+        let f = native_tool("native_test_replace").unwrap();
+        assert_eq!(f(&[]).unwrap().exit_code(), 2);
+    }
+
+    #[test]
+    fn test_unknown_native_tool() {
+        assert!(native_tool("native_test_unknown").is_none());
+    }
+}
@@ -1,7 +1,7 @@
 use crate::stdlib::arg_spec::StructValue;
+use crate::stdlib::errors::ValueError;
 use crate::stdlib::{NEXT_STUB_TYPE, NEXT_TYPE};
 use allocative::Allocative;
-use anyhow::bail;
 use starlark::coerce::Coerce;
 use starlark::collections::SmallMap;
 use starlark::eval::Arguments;
@@ -29,10 +29,7 @@ pub(crate) fn next_impl<'v>(
     implementation: Value<'v>,
     arg_spec: SmallMap<String, Value<'v>>,
 ) -> anyhow::Result<NextStub<'v>> {
-    if implementation.get_type() != "function" {
-        // TODO: look at using ValueError
-        bail!("expected function type in next definition")
-    }
+    ValueError::check_type("next", "implementation", "function", implementation)?;
     Ok(NextStub {
         implementation: implementation,
         arg_spec: arg_spec,
@@ -192,7 +189,7 @@ next(
   implementation = "_foo_impl",
 )
 "#,
-            "expected function type in next definition",
+            "expected function for 'implementation' in next definition, got",
         );
     }
 
@@ -1,16 +1,34 @@
-use crate::stdlib::action::ActionCtx;
+use crate::stdlib::action::{manual_gate_action_impl, ActionCtx, Attempt, Piping};
+use crate::stdlib::capture::ActionLog;
+use crate::stdlib::env_capture::ActionEnv;
+use crate::stdlib::errors::{StdlibError, ValueError};
+use crate::stdlib::glob::{check_pattern, match_pattern};
+use crate::stdlib::graph_scope::GraphScope;
+use crate::stdlib::ir::NodeIr;
+use crate::stdlib::locks::LockManager;
+use crate::stdlib::node_cache::CachedNode;
+use crate::stdlib::plan::{NodePlan, PlannedAction};
 use crate::stdlib::variable_resolver::VariableResolver;
 use crate::stdlib::variable_resolver::VariableUpdater;
-use crate::stdlib::Next;
-use crate::stdlib::{Action, ACTION_TYPE, NEXT_TYPE, NODE_TYPE};
+use crate::stdlib::when::When;
+use crate::stdlib::{Action, ActionStatus, ACTION_TYPE, NEXT_TYPE, NODE_TYPE, VARIABLE_REF_TYPE};
+use crate::stdlib::{Next, VariableRef};
 use allocative::Allocative;
-use anyhow::bail;
+use anyhow::{anyhow, bail};
+use sha2::{Digest, Sha256};
 use starlark::coerce::Coerce;
+use starlark::environment::Methods;
+use starlark::environment::MethodsBuilder;
+use starlark::environment::MethodsStatic;
 use starlark::eval::Evaluator;
 use starlark::starlark_complex_value;
+use starlark::starlark_module;
+use starlark::values::list::ListRef;
+use starlark::values::none::NoneOr;
 use starlark::values::starlark_value;
 use starlark::values::Freeze;
 use starlark::values::Freezer;
+use starlark::values::Heap;
 use starlark::values::NoSerialize;
 use starlark::values::ProvidesStaticType;
 use starlark::values::StarlarkValue;
@@ -18,34 +36,264 @@ use starlark::values::Trace;
 use starlark::values::Value;
 use starlark::values::ValueLike;
 use starlark::StarlarkDocs;
+use std::collections::HashSet;
 use std::fmt;
 use std::fmt::Display;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Returns the name of a function, a function defined with `def` is shown
+/// as its signature or prefixed by the module it is defined in.
+pub(crate) fn function_name(function: Value) -> String {
+    let shown = function.to_str();
+    let name = shown.split('(').next().unwrap_or_default();
+    name.rsplit('.')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_string()
+}
 
 fn next_or_none<'v>(next: Option<Value<'v>>) -> Value<'v> {
     next.unwrap_or(Value::new_none())
 }
 
+/// Converts the `requires_lock` value, a string or a list of strings,
+/// into the lock names.
+fn lock_names(
+    definition: &'static str,
+    requires_lock: Option<Value>,
+) -> anyhow::Result<Vec<String>> {
+    let value = match requires_lock {
+        Some(value) => value,
+        None => return Ok(vec![]),
+    };
+    if let Some(name) = value.unpack_str() {
+        return Ok(vec![name.to_string()]);
+    }
+    if let Some(list) = ListRef::from_value(value) {
+        let mut names = vec![];
+        for item in list.iter() {
+            ValueError::check_element_type(definition, "requires_lock", "string", item)?;
+            names.push(item.to_str());
+        }
+        return Ok(names);
+    }
+    bail!(ValueError::WrongType {
+        definition,
+        attr: "requires_lock",
+        expected: "string or list",
+        got: value.get_type().to_string(),
+    })
+}
+
+/// Validates the tags of a node, a tag can not be empty.
+pub(crate) fn tag_names(tags: Vec<String>) -> anyhow::Result<Vec<String>> {
+    if tags.iter().any(|t| t.is_empty()) {
+        bail!(StdlibError::new_invalid_attr(
+            "tags",
+            "cannot contain an empty tag",
+            "\"\""
+        ));
+    }
+    Ok(tags)
+}
+
+/// Checks the patterns of the files a cached node reads.
+pub(crate) fn input_patterns(patterns: Vec<String>) -> anyhow::Result<Vec<String>> {
+    for pattern in &patterns {
+        check_artifact_pattern("inputs", pattern)?;
+    }
+    Ok(patterns)
+}
+
+/// Checks the patterns of the files a sandboxed run publishes.
+pub(crate) fn output_patterns(patterns: Vec<String>) -> anyhow::Result<Vec<String>> {
+    for pattern in &patterns {
+        check_artifact_pattern("outputs", pattern)?;
+    }
+    Ok(patterns)
+}
+
+/// Validates the artifact patterns of a node.
+pub(crate) fn artifact_patterns(patterns: Vec<String>) -> anyhow::Result<Vec<String>> {
+    for pattern in &patterns {
+        check_artifact_pattern("artifacts", pattern)?;
+    }
+    Ok(patterns)
+}
+
+/// Checks that an artifact pattern is a glob relative to the working dir
+/// which does not reach outside of it.
+pub(crate) fn check_artifact_pattern(attr: &str, pattern: &str) -> anyhow::Result<()> {
+    let reason = if pattern.starts_with('/') {
+        Some("must be relative to the working dir".to_string())
+    } else if pattern.split('/').any(|s| s == "..") {
+        Some("cannot reach outside of the working dir".to_string())
+    } else {
+        check_pattern(pattern).err().map(|e| e.to_string())
+    };
+    match reason {
+        Some(reason) => bail!(StdlibError::new_invalid_attr(attr, &reason, pattern)),
+        None => Ok(()),
+    }
+}
+
+/// Validates the `timeout`, in seconds, and `retries` of a node.
+fn run_policy(timeout: Option<i32>, retries: Option<i32>) -> anyhow::Result<(Option<u32>, u32)> {
+    let timeout = match timeout {
+        Some(t) if t <= 0 => bail!(StdlibError::new_invalid_attr(
+            "timeout",
+            "must be a positive number of seconds",
+            t.to_string()
+        )),
+        t => t.map(|t| t as u32),
+    };
+    let retries = match retries {
+        Some(r) if r < 0 => bail!(StdlibError::new_invalid_attr(
+            "retries",
+            "cannot be negative",
+            r.to_string()
+        )),
+        r => r.unwrap_or_default() as u32,
+    };
+    Ok((timeout, retries))
+}
+
+pub(crate) fn manual_gate_impl<'v>(
+    name: &str,
+    message: Value<'v>,
+    timeout: Option<i32>,
+    next: Option<Value<'v>>,
+    heap: &'v Heap,
+) -> anyhow::Result<Node<'v>> {
+    if name.is_empty() {
+        bail!(StdlibError::new_invalid_attr(
+            "name",
+            "must be the name of the gate",
+            "\"\""
+        ));
+    }
+    let (timeout_secs, _) = run_policy(timeout, None)?;
+    let action = manual_gate_action_impl(name, message, timeout_secs.unwrap_or_default(), heap)?;
+    node_impl(name, heap.alloc(action), next, None, None, None)
+}
+
 pub(crate) fn node_impl<'v>(
     name: &str,
     action: Value<'v>,
     next: Option<Value<'v>>,
+    requires_lock: Option<Value<'v>>,
+    timeout: Option<i32>,
+    retries: Option<i32>,
 ) -> anyhow::Result<Node<'v>> {
-    if action.get_type() != ACTION_TYPE {
-        bail!("An action must be passed as the action in a node")
-    }
+    ValueError::check_type("node", "action", ACTION_TYPE, action)?;
 
     // TODO: let Next be an action as well as a next
-    if let Some(next) = next {
-        if next.get_type() != NEXT_TYPE {
-            bail!("A Next must be passed as the next value in a node")
-        }
+    // a when() picks the next node without a function
+    if let Some(next) = next.filter(|next| When::from_value(*next).is_none()) {
+        ValueError::check_type("node", "next", NEXT_TYPE, next)?;
     }
 
+    let (timeout_secs, retries) = run_policy(timeout, retries)?;
     Ok(Node {
         name: name.to_string(),
         actions: vec![action],
         next: next_or_none(next),
+        locks: lock_names("node", requires_lock)?,
+        timeout_secs,
+        retries,
+        tags: vec![],
+        artifacts: vec![],
+        cache: false,
+        inputs: vec![],
+        pipe: false,
+        graph: vec![],
+        imports: vec![],
+        exports: vec![],
+    })
+}
+
+/// A node which runs the nodes of its `graph`, starting at the first of
+/// them, instead of actions.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn graph_node_impl<'v>(
+    name: &str,
+    graph: Vec<Value<'v>>,
+    imports: Vec<Value<'v>>,
+    exports: Vec<Value<'v>>,
+    next: Option<Value<'v>>,
+    requires_lock: Option<Value<'v>>,
+    timeout: Option<i32>,
+    retries: Option<i32>,
+) -> anyhow::Result<Node<'v>> {
+    if graph.is_empty() {
+        bail!(StdlibError::new_invalid_attr(
+            "graph",
+            "must contain at least one node",
+            "[]"
+        ));
+    }
+    let mut names = HashSet::new();
+    for value in &graph {
+        ValueError::check_element_type("node", "graph", NODE_TYPE, *value)?;
+        let node = Node::from_value(*value).expect("Should be a node");
+        if !names.insert(node.name()) {
+            bail!(ValueError::DuplicateName {
+                definition: "node",
+                attr: "graph",
+                name: node.name().to_string(),
+            });
+        }
+    }
+    for value in &graph {
+        let node = Node::from_value(*value).expect("Should be a node");
+        if let Some(when) = node.when() {
+            if let Some(target) = when.targets().into_iter().find(|t| !names.contains(t)) {
+                bail!(
+                    "node '{}' uses {} but there is no node named '{}' in the graph of '{}'",
+                    node.name(),
+                    when,
+                    target,
+                    name
+                );
+            }
+        }
+    }
+
+    let identifiers = |attr: &'static str, values: Vec<Value>| -> anyhow::Result<Vec<String>> {
+        let mut identifiers = vec![];
+        for value in values {
+            ValueError::check_element_type("node", attr, VARIABLE_REF_TYPE, value)?;
+            let var_ref = VariableRef::from_value(value).expect("Should be a variable");
+            identifiers.push(var_ref.identifier().to_string());
+        }
+        Ok(identifiers)
+    };
+    let imports = identifiers("imports", imports)?;
+    let exports = identifiers("exports", exports)?;
+
+    if let Some(next) = next.filter(|next| When::from_value(*next).is_none()) {
+        ValueError::check_type("node", "next", NEXT_TYPE, next)?;
+    }
+
+    let (timeout_secs, retries) = run_policy(timeout, retries)?;
+    Ok(Node {
+        name: name.to_string(),
+        actions: vec![],
+        next: next_or_none(next),
+        locks: lock_names("node", requires_lock)?,
+        timeout_secs,
+        retries,
+        tags: vec![],
+        artifacts: vec![],
+        cache: false,
+        inputs: vec![],
+        pipe: false,
+        graph,
+        imports,
+        exports,
     })
 }
 
@@ -53,20 +301,49 @@ pub(crate) fn sequence_impl<'v>(
     name: &str,
     actions: Vec<Value<'v>>,
     next: Option<Value<'v>>,
+    requires_lock: Option<Value<'v>>,
+    timeout: Option<i32>,
+    retries: Option<i32>,
 ) -> anyhow::Result<Node<'v>> {
     for action in &actions {
-        if action.get_type() != ACTION_TYPE {
-            bail!("All actions in a sequence must be action types")
-        }
+        ValueError::check_element_type("sequence", "actions", ACTION_TYPE, *action)?;
     }
 
+    let (timeout_secs, retries) = run_policy(timeout, retries)?;
     Ok(Node {
         name: name.to_string(),
         actions: actions,
         next: next_or_none(next),
+        locks: lock_names("sequence", requires_lock)?,
+        timeout_secs,
+        retries,
+        tags: vec![],
+        artifacts: vec![],
+        cache: false,
+        inputs: vec![],
+        pipe: false,
+        graph: vec![],
+        imports: vec![],
+        exports: vec![],
     })
 }
 
+/// The outcome of running a node, the name of the next node to run,
+/// the exit code of the last action that ran, whether that exit code is
+/// one of the action's ok_exit_codes and the number of attempts it took.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeOutcome {
+    pub next: Option<String>,
+    pub exit_code: i32,
+    pub success: bool,
+    pub attempts: u32,
+    /// The environment of each action which spawned a tool, in order.
+    pub envs: Vec<ActionEnv>,
+    /// Whether the node was skipped because it was unchanged since its last
+    /// successful run, its recorded outputs were used instead.
+    pub cached: bool,
+}
+
 #[derive(
     Coerce, Clone, Default, Trace, Debug, ProvidesStaticType, StarlarkDocs, NoSerialize, Allocative,
 )]
@@ -75,52 +352,670 @@ pub struct NodeGen<V> {
     name: String,
     actions: Vec<V>,
     next: V,
+    // the named locks which are held while the node runs
+    locks: Vec<String>,
+    // the time each attempt at running the node, including its setters and
+    // next, may take
+    timeout_secs: Option<u32>,
+    // the number of times the node is run again after failing
+    retries: u32,
+    // labels used to pick the nodes which run, e.g. "slow"
+    tags: Vec<String>,
+    // globs of the files the node produces which are kept once it succeeds
+    artifacts: Vec<String>,
+    // if set the node is skipped when it is unchanged since its last
+    // successful run
+    cache: bool,
+    // globs of the files the node reads, a change to them runs it again
+    inputs: Vec<String>,
+    // if set the stdout of each action is the stdin of the one after it
+    pipe: bool,
+    // the nodes run instead of actions, starting at the first of them
+    graph: Vec<V>,
+    // the identifiers of the variables the graph reads from the scope
+    // around it, and of those it sets there once it succeeds
+    imports: Vec<String>,
+    exports: Vec<String>,
 }
 starlark_complex_value!(pub Node);
 
 #[starlark_value(type = NODE_TYPE)]
-impl<'v, V: ValueLike<'v> + 'v> StarlarkValue<'v> for NodeGen<V> where Self: ProvidesStaticType<'v> {}
+impl<'v, V: ValueLike<'v> + 'v> StarlarkValue<'v> for NodeGen<V>
+where
+    Self: ProvidesStaticType<'v>,
+{
+    fn get_methods() -> Option<&'static Methods> {
+        static RES: MethodsStatic = MethodsStatic::new();
+        RES.methods(node_methods)
+    }
+}
+
+fn this_node<'v>(this: Value<'v>) -> anyhow::Result<&'v Node<'v>> {
+    Node::from_value(this).ok_or_else(|| anyhow!("expected a node, got {}", this.get_type()))
+}
+
+/// What a workflow can read about its nodes while it is parsed, e.g. to
+/// check them with `workflow.validate()`.
+#[starlark_module]
+fn node_methods(builder: &mut MethodsBuilder) {
+    #[starlark(attribute)]
+    fn name<'v>(this: Value<'v>) -> anyhow::Result<String> {
+        Ok(this_node(this)?.name().to_string())
+    }
+
+    #[starlark(attribute)]
+    fn tags<'v>(this: Value<'v>) -> anyhow::Result<Vec<String>> {
+        Ok(this_node(this)?.tags().to_vec())
+    }
+
+    #[starlark(attribute)]
+    fn requires_lock<'v>(this: Value<'v>) -> anyhow::Result<Vec<String>> {
+        Ok(this_node(this)?.locks().to_vec())
+    }
+
+    /// The nodes a `when()` can go to next, an empty list if the workflow
+    /// stops after the node and None if a next function picks the node.
+    #[starlark(attribute)]
+    fn targets<'v>(this: Value<'v>) -> anyhow::Result<NoneOr<Vec<String>>> {
+        let node = this_node(this)?;
+        Ok(match (node.when(), node.has_next()) {
+            (Some(when), _) => {
+                NoneOr::Other(when.targets().into_iter().map(String::from).collect())
+            }
+            (None, true) => NoneOr::None,
+            (None, false) => NoneOr::Other(vec![]),
+        })
+    }
+
+    #[starlark(attribute)]
+    fn manual_gate<'v>(this: Value<'v>) -> anyhow::Result<bool> {
+        Ok(this_node(this)?.is_manual_gate())
+    }
+
+    /// The nodes of the node's graph, empty if it runs actions.
+    #[starlark(attribute)]
+    fn graph<'v>(this: Value<'v>) -> anyhow::Result<Vec<Value<'v>>> {
+        Ok(this_node(this)?
+            .graph
+            .iter()
+            .map(|v| v.to_value())
+            .collect())
+    }
+}
 
 impl<'a> Node<'a> {
     pub fn name(&self) -> &str {
         &self.name
     }
 
+    pub fn locks(&self) -> &[String] {
+        &self.locks
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_secs.map(|t| Duration::from_secs(t as u64))
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub(crate) fn with_tags(mut self, tags: Vec<String>) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    pub fn artifacts(&self) -> &[String] {
+        &self.artifacts
+    }
+
+    pub(crate) fn with_artifacts(mut self, artifacts: Vec<String>) -> Self {
+        self.artifacts = artifacts;
+        self
+    }
+
+    pub fn cache(&self) -> bool {
+        self.cache
+    }
+
+    pub fn inputs(&self) -> &[String] {
+        &self.inputs
+    }
+
+    pub(crate) fn with_cache(mut self, cache: bool, inputs: Vec<String>) -> Self {
+        self.cache = cache;
+        self.inputs = inputs;
+        self
+    }
+
+    /// Whether the stdout of each action is the stdin of the one after it.
+    pub fn pipe(&self) -> bool {
+        self.pipe
+    }
+
+    /// Pipes the stdout of each action into the one after it, which needs
+    /// every action to spawn its tool as a process.
+    pub(crate) fn with_pipe(mut self, pipe: bool) -> anyhow::Result<Self> {
+        if pipe {
+            for (index, action) in self.actions().iter().enumerate() {
+                if !action.spawns_process() {
+                    bail!(
+                        "sequence '{}' pipes its actions but action {} does not spawn a process, only the output of tools which are spawned can be piped",
+                        self.name,
+                        index + 1
+                    );
+                }
+            }
+        }
+        self.pipe = pipe;
+        Ok(self)
+    }
+
+    /// The nodes of the node's graph, empty if it runs actions.
+    pub fn graph(&self) -> Vec<&Node<'a>> {
+        self.graph
+            .iter()
+            .map(|v| Node::from_value(*v).unwrap())
+            .collect()
+    }
+
+    /// Whether the node is a manual gate, which waits to be approved.
+    pub fn is_manual_gate(&self) -> bool {
+        self.actions().iter().any(|a| a.is_manual_gate())
+    }
+
+    pub fn action_count(&self) -> usize {
+        self.actions.len()
+    }
+
+    pub fn has_next(&self) -> bool {
+        !self.next.is_none()
+    }
+
+    pub fn actions(&self) -> Vec<&Action<'a>> {
+        self.actions
+            .iter()
+            .map(|v| Action::from_value(*v).unwrap())
+            .collect()
+    }
+
+    /// The next of the node when it is a `when()`.
+    pub fn when(&self) -> Option<&'a When> {
+        When::from_value(self.next)
+    }
+
+    /// Returns the function which picks the node to run after this one,
+    /// None if the workflow stops after this node.
+    pub fn next_implementation(&self) -> Option<Value<'a>> {
+        Next::from_value(self.next).map(|next| next.implementation())
+    }
+
+    /// Returns the name of the next function, see `function_name`.
+    pub fn next_name(&self) -> Option<String> {
+        Some(function_name(self.next_implementation()?))
+    }
+
+    /// Returns what running the node would do, without running it. The plan
+    /// of a graph has the actions of all of its nodes.
+    pub fn plan<T: VariableResolver>(&self, resolver: &T, working_dir: &PathBuf) -> NodePlan {
+        NodePlan {
+            name: self.name.clone(),
+            actions: self
+                .actions()
+                .iter()
+                .map(|a| a.plan(resolver, working_dir))
+                .chain(
+                    self.graph()
+                        .iter()
+                        .flat_map(|n| n.plan(resolver, working_dir).actions),
+                )
+                .collect(),
+            next: self.next_name(),
+        }
+    }
+
+    /// Returns the node as IR, failing if it sets what the IR can not
+    /// express or its next is a function.
+    pub fn compile<T: VariableResolver>(&self, resolver: &T) -> anyhow::Result<NodeIr> {
+        let unsupported = [
+            ("graph", !self.graph.is_empty()),
+            ("locks", !self.locks.is_empty()),
+            ("artifacts", !self.artifacts.is_empty()),
+            ("cache", self.cache),
+            ("inputs", !self.inputs.is_empty()),
+        ];
+        if let Some((attr, _)) = unsupported.iter().find(|(_, set)| *set) {
+            bail!(
+                "node '{}' sets {}, which can not be compiled",
+                self.name,
+                attr
+            );
+        }
+        let actions = self
+            .actions()
+            .iter()
+            .map(|action| action.compile(resolver))
+            .collect::<anyhow::Result<_>>()
+            .map_err(|e| e.context(format!("node '{}' can not be compiled", self.name)))?;
+        let next = match self.when() {
+            Some(when) => Some(when.compile(resolver)?),
+            None if self.has_next() => bail!(
+                "node '{}' picks its next with a function, only when() can be compiled",
+                self.name
+            ),
+            None => None,
+        };
+        Ok(NodeIr {
+            name: self.name.clone(),
+            actions,
+            next,
+            timeout: self.timeout_secs,
+            retries: self.retries,
+            tags: self.tags.clone(),
+            pipe: self.pipe,
+        })
+    }
+
+    /// Runs the node, running it again up to `retries` times if it fails or
+    /// its last action exits with a code which is not ok, unless the run is
+    /// cancelled. Every attempt gets its own deadline when the node has a
+    /// timeout.
     pub fn run<T: VariableResolver + VariableUpdater>(
         &self,
         resolver: &T,
         working_dir: &PathBuf,
         eval: &mut Evaluator<'a, '_>,
+    ) -> anyhow::Result<NodeOutcome> {
+        let key = match self.cache {
+            true => self.cache_key(resolver, working_dir)?,
+            false => None,
+        };
+        if let Some(key) = &key {
+            let cached = resolver
+                .context()
+                .cached_node(&self.name)
+                .filter(|cached| &cached.key == key && cached.actions.len() == self.actions.len());
+            if let Some(cached) = cached {
+                return self.replay(&cached, resolver, working_dir, eval);
+            }
+        }
+
+        // a cancelled run is not retried
+        let can_retry = |number: u32| {
+            number <= self.retries
+                && !resolver
+                    .context()
+                    .cancel_token
+                    .is_some_and(|t| t.is_cancelled())
+        };
+        let mut number = 1;
+        loop {
+            let attempt = Attempt {
+                number,
+                deadline: self.timeout().map(|t| Instant::now() + t),
+            };
+            match self.run_attempt(resolver, working_dir, attempt, eval) {
+                Ok((outcome, _)) if !outcome.success && can_retry(number) => number += 1,
+                Ok((outcome, ctxs)) => {
+                    if let (Some(key), true) = (key, outcome.success) {
+                        let actions = ctxs
+                            .iter()
+                            .map(|ctx| ctx.cached())
+                            .collect::<anyhow::Result<_>>()?;
+                        resolver
+                            .context()
+                            .cache_node(&self.name, CachedNode { key, actions });
+                    }
+                    return Ok(outcome);
+                }
+                Err(e) if !can_retry(number) => {
+                    if number > 1 {
+                        return Err(e.context(format!("failed after {} attempts", number)));
+                    }
+                    return Err(e);
+                }
+                Err(_) => number += 1,
+            }
+        }
+    }
+
+    /// Returns the key the outputs of the node are cached under, a hash of
+    /// what its actions run, with their variables resolved, and of the
+    /// contents of its inputs. None if the node has no actions or one of
+    /// them can not be resolved before the node runs.
+    fn cache_key<T: VariableResolver>(
+        &self,
+        resolver: &T,
+        working_dir: &PathBuf,
     ) -> anyhow::Result<Option<String>> {
-        let mut last_ctx: Option<ActionCtx> = None;
-        for value in self.actions.clone() {
+        let plan = self.plan(resolver, working_dir);
+        let unresolved = plan
+            .actions
+            .iter()
+            .any(|action| matches!(action, PlannedAction::Unresolved { .. }));
+        if plan.actions.is_empty() || unresolved {
+            return Ok(None);
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(format!("{:?}", plan));
+        for pattern in &self.inputs {
+            for path in match_pattern(pattern, working_dir)? {
+                let file = working_dir.join(&path);
+                if !file.is_file() {
+                    continue;
+                }
+                let contents = fs::read(&file)
+                    .map_err(|e| anyhow!("Unable to read input {:?}: {}", path, e))?;
+                hasher.update(format!("\0{}\0{:x}", path, Sha256::digest(contents)));
+            }
+        }
+        Ok(Some(format!("{:x}", hasher.finalize())))
+    }
+
+    /// Gives the outputs recorded on the node's last successful run to the
+    /// setters of its actions and to its next, instead of running them.
+    fn replay<T: VariableResolver + VariableUpdater>(
+        &self,
+        cached: &CachedNode,
+        resolver: &T,
+        working_dir: &Path,
+        eval: &mut Evaluator<'a, '_>,
+    ) -> anyhow::Result<NodeOutcome> {
+        let mut ctxs = vec![];
+        for (action, outputs) in self.actions().iter().zip(&cached.actions) {
+            ctxs.push(action.replay(outputs, Attempt::default(), resolver, working_dir, eval)?);
+        }
+        let last_ctx = match ctxs.last() {
+            Some(last_ctx) => last_ctx,
+            None => bail!("node '{}' has no actions to replay", self.name),
+        };
+        let results = ctxs.iter().map(|ctx| ctx.result()).collect();
+        let ctx = eval
+            .module()
+            .heap()
+            .alloc(last_ctx.clone().with_actions(results));
+        Ok(NodeOutcome {
+            next: self.next_node(ctx, resolver, eval)?,
+            exit_code: last_ctx.exit_code(),
+            success: last_ctx.success(),
+            attempts: 0,
+            envs: vec![],
+            cached: true,
+        })
+    }
+
+    fn check_deadline(&self, attempt: &Attempt) -> anyhow::Result<()> {
+        if let Some(deadline) = attempt.deadline {
+            if Instant::now() > deadline {
+                bail!(
+                    "node '{}' timed out after {}s",
+                    self.name,
+                    self.timeout_secs.unwrap_or_default()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn run_attempt<T: VariableResolver + VariableUpdater>(
+        &self,
+        resolver: &T,
+        working_dir: &PathBuf,
+        attempt: Attempt,
+        eval: &mut Evaluator<'a, '_>,
+    ) -> anyhow::Result<(NodeOutcome, Vec<ActionCtx>)> {
+        if !self.graph.is_empty() {
+            return Ok((
+                self.run_graph(resolver, working_dir, attempt, eval)?,
+                vec![],
+            ));
+        }
+        let mut ctxs: Vec<ActionCtx> = vec![];
+        let mut envs = vec![];
+        let log_dir = resolver.context().log_dir;
+        // the stdout of the last action when the actions are piped
+        let mut piped: Option<Vec<u8>> = None;
+        for (index, value) in self.actions.clone().into_iter().enumerate() {
+            self.check_deadline(&attempt)?;
             let action = Action::from_value(value).unwrap();
-            last_ctx = Some(action.run(resolver, working_dir, eval)?);
+            let log = log_dir
+                .as_ref()
+                .map(|dir| ActionLog::new(dir, &self.name, attempt.number, index + 1));
+            let run_delegate = resolver.context().run_delegate;
+            if let Some(delegate) = run_delegate {
+                delegate.will_run_action(&self.name, index, &action.label(resolver));
+            }
+            let piping = Piping {
+                stdin: piped.as_deref(),
+                stdout: self.pipe && index + 1 < self.actions.len(),
+            };
+            let ctx =
+                action.run_attempt(resolver, working_dir, attempt, log.as_ref(), piping, eval);
+            // a tool killed at the deadline fails as the node timing out
+            let ctx = ctx.or_else(|e| self.check_deadline(&attempt).and(Err(e)));
+            if let Some(delegate) = run_delegate {
+                let status = match &ctx {
+                    Ok(ctx) => ActionStatus::Ran(ctx.result()),
+                    Err(e) => {
+                        let error = format!("{:#}", e);
+                        ActionStatus::Failed(match resolver.context().redactor {
+                            Some(redactor) => redactor.redact(&error),
+                            None => error,
+                        })
+                    }
+                };
+                delegate.did_run_action(&self.name, index, &status);
+            }
+            let ctx = ctx?;
+            if piping.stdout {
+                piped = Some(ctx.stdout_bytes()?);
+            }
+            envs.extend(ctx.env().cloned());
+            ctxs.push(ctx);
         }
+        let last_ctx = ctxs.last();
+        let results = ctxs.iter().map(|ctx| ctx.result()).collect();
 
         let heap = eval.module().heap();
-        let ctx = match last_ctx {
-            Some(last_ctx) => heap.alloc(last_ctx.clone()),
+        let (ctx, exit_code, success) = match last_ctx {
+            Some(last_ctx) => (
+                heap.alloc(last_ctx.clone().with_actions(results)),
+                last_ctx.exit_code(),
+                last_ctx.success(),
+            ),
             None => {
                 // make it up
                 bail!("TODO")
             }
         };
-        let mut next_node: Option<String> = None;
-        if let Some(next) = Next::from_value(self.next) {
-            match eval.eval_function(next.implementation(), &[ctx, next.args()], &[]) {
-                Ok(res) => {
-                    if res.get_type() == "string" {
-                        next_node = Some(res.to_str());
-                    } else if res.get_type() != "NoneType" {
-                        // None means stop
-                        bail!("setter must return string or None")
-                    }
+        let next_node = self.next_node(ctx, resolver, eval)?;
+        self.check_deadline(&attempt)?;
+        let outcome = NodeOutcome {
+            next: next_node,
+            exit_code,
+            success,
+            attempts: attempt.number,
+            envs,
+            cached: false,
+        };
+        Ok((outcome, ctxs))
+    }
+
+    /// Runs the nodes of the graph in their own variable scope until one of
+    /// them has no next. The last node which ran decides the exit code of
+    /// the graph and the exports are only set when it succeeded.
+    fn run_graph<T: VariableResolver + VariableUpdater>(
+        &self,
+        resolver: &T,
+        working_dir: &PathBuf,
+        attempt: Attempt,
+        eval: &mut Evaluator<'a, '_>,
+    ) -> anyhow::Result<NodeOutcome> {
+        let scope = GraphScope::new(&self.name, resolver, &self.imports, &self.exports);
+        let graph = self.graph();
+        let mut node = graph[0];
+        let mut envs = vec![];
+        let last = loop {
+            self.check_deadline(&attempt)?;
+            if resolver
+                .context()
+                .cancel_token
+                .is_some_and(|t| t.is_cancelled())
+            {
+                bail!("The run was cancelled");
+            }
+            let outcome = {
+                let _guard = LockManager::global().acquire(node.locks())?;
+                node.run(&scope, working_dir, eval).map_err(|e| {
+                    e.context(format!(
+                        "node '{}' in the graph of '{}' failed",
+                        node.name(),
+                        self.name
+                    ))
+                })?
+            };
+            envs.extend(outcome.envs.iter().cloned());
+            node = match &outcome.next {
+                None => break outcome,
+                Some(next) => match graph.iter().find(|n| n.name() == next) {
+                    Some(next) => next,
+                    None => bail!(
+                        "node '{}' in the graph of '{}' returned '{}' but there is no such node in the graph",
+                        node.name(),
+                        self.name,
+                        next
+                    ),
+                },
+            };
+        };
+        if last.success {
+            scope.export()?;
+        }
+
+        let ctx = eval.module().heap().alloc(
+            ActionCtx::new(String::new(), String::new(), last.exit_code).with_attempt(attempt),
+        );
+        let next = self.next_node(ctx, resolver, eval)?;
+        self.check_deadline(&attempt)?;
+        Ok(NodeOutcome {
+            next,
+            exit_code: last.exit_code,
+            success: last.success,
+            attempts: attempt.number,
+            envs,
+            cached: false,
+        })
+    }
+
+    /// Copies the files matching the node's artifacts into a directory named
+    /// after the node in `dir`, at the same paths they have relative to the
+    /// working dir, and returns the paths of the copies. A directory is not
+    /// copied itself, `dist/**` collects everything below `dist`.
+    pub fn collect_artifacts(
+        &self,
+        working_dir: &Path,
+        dir: &Path,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let mut collected = vec![];
+        for pattern in &self.artifacts {
+            for path in match_pattern(pattern, working_dir)? {
+                let source = working_dir.join(&path);
+                let dest = dir.join(&self.name).join(&path);
+                if !source.is_file() || collected.contains(&dest) {
+                    continue;
+                }
+                if let Some(parent) = dest.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::copy(&source, &dest)
+                    .map_err(|e| anyhow!("Unable to copy artifact {:?}: {}", path, e))?;
+                collected.push(dest);
+            }
+        }
+        Ok(collected)
+    }
+
+    /// Calls the node's next with the ctx of its last action and returns
+    /// the name of the node to run after it. A `when()` compares the value
+    /// its variable has now instead.
+    fn next_node<T: VariableResolver>(
+        &self,
+        ctx: Value<'a>,
+        resolver: &T,
+        eval: &mut Evaluator<'a, '_>,
+    ) -> anyhow::Result<Option<String>> {
+        if let Some(when) = self.when() {
+            return when.next(resolver);
+        }
+        let next = match Next::from_value(self.next) {
+            Some(next) => next,
+            None => return Ok(None),
+        };
+        match eval.eval_function(next.implementation(), &[ctx, next.args()], &[]) {
+            Ok(res) => {
+                if res.get_type() == "string" {
+                    Ok(Some(res.to_str()))
+                } else if res.get_type() != "NoneType" {
+                    // None means stop
+                    bail!("setter must return string or None")
+                } else {
+                    Ok(None)
                 }
-                Err(e) => bail!(e.into_anyhow()),
             }
+            Err(e) => bail!(e.into_anyhow()),
+        }
+    }
+
+    /// Skips the node without running its actions, its next is called as
+    /// if they succeeded without any output.
+    pub fn skip<T: VariableResolver>(
+        &self,
+        resolver: &T,
+        eval: &mut Evaluator<'a, '_>,
+    ) -> anyhow::Result<NodeOutcome> {
+        let ctx = eval
+            .module()
+            .heap()
+            .alloc(ActionCtx::new(String::new(), String::new(), 0));
+        Ok(NodeOutcome {
+            next: self.next_node(ctx, resolver, eval)?,
+            exit_code: 0,
+            success: true,
+            attempts: 0,
+            envs: vec![],
+            cached: false,
+        })
+    }
+}
+
+/// Picks the nodes which run by their tags. A node is skipped if it has one
+/// of the `skip` tags or, when there are `only` tags, if it has none of them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TagFilter {
+    pub skip: Vec<String>,
+    pub only: Vec<String>,
+}
+
+impl TagFilter {
+    pub fn new(skip: Vec<String>, only: Vec<String>) -> Self {
+        TagFilter { skip, only }
+    }
+
+    /// Returns why the node is skipped, None if it runs.
+    pub fn skip_reason(&self, node: &Node) -> Option<String> {
+        if let Some(tag) = node.tags().iter().find(|t| self.skip.contains(t)) {
+            return Some(format!("it is tagged '{}'", tag));
         }
-        Ok(next_node)
+        if !self.only.is_empty() && !node.tags().iter().any(|t| self.only.contains(t)) {
+            let tags: Vec<String> = self.only.iter().map(|t| format!("'{}'", t)).collect();
+            return Some(format!("it is not tagged {}", tags.join(" or ")));
+        }
+        None
     }
 }
 
@@ -131,6 +1026,17 @@ impl<'v> Freeze for Node<'v> {
             name: self.name.freeze(freezer)?,
             actions: self.actions.freeze(freezer)?,
             next: self.next.freeze(freezer)?,
+            locks: self.locks.freeze(freezer)?,
+            timeout_secs: self.timeout_secs.freeze(freezer)?,
+            retries: self.retries.freeze(freezer)?,
+            tags: self.tags.freeze(freezer)?,
+            artifacts: self.artifacts.freeze(freezer)?,
+            cache: self.cache,
+            inputs: self.inputs.freeze(freezer)?,
+            pipe: self.pipe,
+            graph: self.graph.freeze(freezer)?,
+            imports: self.imports.freeze(freezer)?,
+            exports: self.exports.freeze(freezer)?,
         })
     }
 }
@@ -144,7 +1050,29 @@ impl<V> Display for NodeGen<V> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::stdlib::test_utils::assert_env;
+    use crate::downcast_delegate_ref;
+    use crate::runner::{Runner, WorkflowDelegate};
+    use crate::stdlib::cancel::CancelToken;
+    use crate::stdlib::test_utils::{assert_env, TempWorkflowFile};
+    use starlark::environment::Module;
+    use std::ops::Deref;
+
+    fn run_node(content: &str) -> anyhow::Result<NodeOutcome> {
+        run_node_with(content, WorkflowDelegate::new())
+    }
+
+    fn run_node_with(content: &str, delegate: WorkflowDelegate) -> anyhow::Result<NodeOutcome> {
+        let file = TempWorkflowFile::new("test.workflow", content).unwrap();
+        let runner = Runner::new(file.path(), delegate).unwrap();
+        let module = Module::new();
+        let mut eval = Evaluator::new(&module);
+        let node = runner.parse_workflow(&mut eval).unwrap();
+        let node = Node::from_value(node).unwrap();
+
+        let holder = runner.delegate();
+        let delegate = downcast_delegate_ref!(holder, WorkflowDelegate).unwrap();
+        node.run(delegate, &runner.working_dir(), &mut eval)
+    }
 
     #[test]
     fn test_can_parse_simple_node() {
@@ -155,7 +1083,7 @@ mod tests {
     fn test_require_an_action_type() {
         assert_env().fail(
             "node(action = 1)",
-            "An action must be passed as the action in a node",
+            "expected action for 'action' in node definition, got",
         );
     }
 
@@ -163,7 +1091,7 @@ mod tests {
     fn test_require_a_next_type() {
         assert_env().fail(
             "node(next ='', action = action(tool = tool(path='')))",
-            "A Next must be passed as the next value in a node",
+            "expected next for 'next' in node definition, got",
         );
     }
 
@@ -174,6 +1102,32 @@ mod tests {
         assert_eq!(node.name(), "foo");
     }
 
+    #[test]
+    fn test_tags() {
+        let res = assert_env()
+            .pass("node(name = 'a', action = action(tool = tool(path='')), tags = ['slow', 'db'])");
+        let node = Node::from_value(res.value()).unwrap();
+        assert_eq!(node.tags(), ["slow", "db"]);
+
+        let filter = TagFilter::new(vec!["db".to_string()], vec![]);
+        assert_eq!(
+            filter.skip_reason(node).as_deref(),
+            Some("it is tagged 'db'")
+        );
+        let filter = TagFilter::new(vec![], vec!["fast".to_string(), "ci".to_string()]);
+        assert_eq!(
+            filter.skip_reason(node).as_deref(),
+            Some("it is not tagged 'fast' or 'ci'")
+        );
+        let filter = TagFilter::new(vec![], vec!["slow".to_string()]);
+        assert_eq!(filter.skip_reason(node), None);
+
+        assert_env().fail(
+            "sequence(actions = [], tags = [''])",
+            "cannot contain an empty tag",
+        );
+    }
+
     #[test]
     fn test_can_parse_simple_sequence() {
         assert_env().pass(
@@ -198,7 +1152,346 @@ mod tests {
       action(tool = tool(path = '')),
     ]
 )"#,
-            "All actions in a sequence must be action types",
+            "expected a list of action for 'actions' in sequence definition, got",
+        );
+    }
+
+    #[test]
+    fn test_pipe() {
+        let outcome = run_node(
+            r#"
+sorted = variable(default = "")
+
+def _sorted(ctx, args):
+    return ctx.stdout.strip().replace("\n", ",")
+
+sequence(
+    actions = [
+        action(tool = builtin_tool(name = "printf"), args = ["b\na\nc\n"], cwd = "."),
+        action(tool = builtin_tool(name = "sort"), cwd = "."),
+        action(
+            tool = builtin_tool(name = "tac"),
+            cwd = ".",
+            setters = [setter(implementation = lambda ctx: ctx.stdout, variable = sorted)],
+        ),
+    ],
+    next = next(implementation = _sorted)(),
+    pipe = True,
+)
+"#,
+        )
+        .unwrap();
+        assert_eq!(outcome.next.as_deref(), Some("c,b,a"));
+
+        assert_env().fail(
+            r#"
+def _run():
+    return "out"
+
+sequence(
+    name = "s",
+    actions = [action(tool = tool(path = '')), fn_action(implementation = _run)],
+    pipe = True,
+)
+"#,
+            "sequence 's' pipes its actions but action 2 does not spawn a process",
+        );
+    }
+
+    #[test]
+    fn test_graph() {
+        let res = assert_env().pass(
+            r#"
+v = variable()
+node(
+    name = "build",
+    graph = [
+        node(name = "a", action = action(tool = tool(path = '')), next = when(variable = v, equals = "x", then = "b")),
+        node(name = "b", action = action(tool = tool(path = ''))),
+    ],
+    imports = [v],
+)"#,
+        );
+        let node = Node::from_value(res.value()).unwrap();
+        let names: Vec<&str> = node.graph().iter().map(|n| n.name()).collect();
+        assert_eq!(names, ["a", "b"]);
+        assert_eq!(node.action_count(), 0);
+    }
+
+    #[test]
+    fn test_graph_checks_its_attributes() {
+        let tool = "action(tool = tool(path = ''))";
+        assert_env().fail(
+            &format!("node(action = {tool}, graph = [node(action = {tool})])"),
+            "expected exactly one of 'action', 'graph' in node definition",
+        );
+        assert_env().fail(
+            &format!("node(action = {tool}, imports = [variable()])"),
+            "'imports' requires 'graph' in node definition",
+        );
+        assert_env().fail(
+            "node(graph = [])",
+            "Invalid attribute 'graph', must contain at least one node",
+        );
+        assert_env().fail(
+            &format!("node(graph = [{tool}])"),
+            "expected a list of node for 'graph' in node definition, got action",
+        );
+        assert_env().fail(
+            &format!("node(graph = [node(name = 'a', action = {tool}), node(name = 'a', action = {tool})])"),
+            "duplicate name 'a' for 'graph' in node definition",
+        );
+        assert_env().fail(
+            &format!("node(graph = [node(action = {tool})], exports = ['v'])"),
+            "expected a list of variable_ref for 'exports' in node definition, got string",
+        );
+        assert_env().fail(
+            &format!(
+                "node(name = 'build', graph = [node(name = 'a', action = {tool}, next = when(variable = variable(), equals = 'x', then = 'deploy'))])"
+            ),
+            "there is no node named 'deploy' in the graph of 'build'",
+        );
+    }
+
+    #[test]
+    fn test_requires_lock() {
+        let res =
+            assert_env().pass("node(action = action(tool = tool(path='')), requires_lock = 'db')");
+        let node = Node::from_value(res.value()).unwrap();
+        assert_eq!(node.locks(), &["db".to_string()]);
+
+        let res = assert_env().pass("sequence(actions = [], requires_lock = ['db', 'cache'])");
+        let node = Node::from_value(res.value()).unwrap();
+        assert_eq!(node.locks(), &["db".to_string(), "cache".to_string()]);
+    }
+
+    #[test]
+    fn test_requires_lock_must_be_strings() {
+        assert_env().fail(
+            "node(action = action(tool = tool(path='')), requires_lock = ['db', 1])",
+            "expected a list of string for 'requires_lock' in node definition, got int",
+        );
+        assert_env().fail(
+            "sequence(actions = [], requires_lock = 1)",
+            "expected string or list for 'requires_lock' in sequence definition, got int",
+        );
+    }
+
+    #[test]
+    fn test_no_priority() {
+        // nodes run one at a time along their nexts, there is no scheduler
+        // which a priority could order
+        assert_env().fail(
+            "node(action = action(tool = tool(path='')), priority = 5)",
+            "priority",
+        );
+        assert_env().fail("sequence(actions = [], priority = 5)", "priority");
+    }
+
+    #[test]
+    fn test_timeout_and_retries() {
+        let res = assert_env().pass("node(action = action(tool = tool(path='')))");
+        let node = Node::from_value(res.value()).unwrap();
+        assert_eq!(node.timeout(), None);
+        assert_eq!(node.retries(), 0);
+
+        let res = assert_env().pass("sequence(actions = [], timeout = 30, retries = 2)");
+        let node = Node::from_value(res.value()).unwrap();
+        assert_eq!(node.timeout(), Some(Duration::from_secs(30)));
+        assert_eq!(node.retries(), 2);
+    }
+
+    #[test]
+    fn test_invalid_timeout_and_retries() {
+        assert_env().fail(
+            "sequence(actions = [], timeout = 0)",
+            "Invalid attribute 'timeout', must be a positive number of seconds",
+        );
+        assert_env().fail(
+            "node(action = action(tool = tool(path='')), retries = -1)",
+            "Invalid attribute 'retries', cannot be negative",
+        );
+    }
+
+    #[test]
+    fn test_retries_until_success() {
+        let outcome = run_node(
+            r#"
+v = variable(default = "")
+def _run():
+    return "out"
+
+def _update(ctx):
+    # fails on the first attempt
+    return ctx.stdout if ctx.attempt > 1 else 1
+
+node(
+    action = fn_action(
+        implementation = _run,
+        setters = [setter(implementation = _update, variable = v)],
+    ),
+    retries = 2,
+)
+"#,
+        )
+        .unwrap();
+        assert_eq!(outcome.attempts, 2);
+    }
+
+    #[test]
+    fn test_next_sees_every_action() {
+        let outcome = run_node(
+            r#"
+def _ok():
+    return 0
+
+def _fail():
+    return 3
+
+def _failed(ctx, args):
+    failed = [str(i) for i, a in enumerate(ctx.actions) if not a.success]
+    codes = [str(a.exit_code) for a in ctx.actions]
+    return "{}:{}:{}".format(ctx.actions[0].name, ",".join(failed), ",".join(codes))
+
+sequence(
+    actions = [
+        fn_action(implementation = _ok),
+        fn_action(implementation = _fail),
+        fn_action(implementation = _ok),
+    ],
+    next = next(implementation = _failed)(),
+)
+"#,
+        )
+        .unwrap();
+        assert_eq!(outcome.next.as_deref(), Some("fn_action:1:0,3,0"));
+        assert!(outcome.success);
+    }
+
+    #[test]
+    fn test_artifacts() {
+        let res = assert_env().pass(
+            "sequence(actions = [action(tool = tool(path=''))], artifacts = ['dist/*.tar.gz'])",
+        );
+        let node = Node::from_value(res.value()).unwrap();
+        assert_eq!(node.artifacts(), ["dist/*.tar.gz"]);
+
+        let fail = |artifacts: &str, reason: &str| {
+            assert_env().fail(
+                &format!(
+                    "node(action = action(tool = tool(path='')), artifacts = {})",
+                    artifacts
+                ),
+                &format!("Invalid attribute 'artifacts', {}", reason),
+            )
+        };
+        fail("['/tmp/*']", "must be relative to the working dir");
+        fail("['../out/*']", "cannot reach outside of the working dir");
+        fail("['dist/[a']", "has an unclosed '['");
+    }
+
+    #[test]
+    fn test_cache_and_inputs() {
+        let res = assert_env().pass(
+            "node(action = action(tool = tool(path='')), cache = True, inputs = ['src/**/*.rs'])",
+        );
+        let node = Node::from_value(res.value()).unwrap();
+        assert!(node.cache());
+        assert_eq!(node.inputs(), ["src/**/*.rs"]);
+
+        assert_env().fail(
+            "node(action = action(tool = tool(path='')), inputs = ['src/*'])",
+            "'inputs' requires 'cache' in node definition",
+        );
+        assert_env().fail(
+            "sequence(actions = [action(tool = tool(path=''))], cache = True, inputs = ['/src/*'])",
+            "Invalid attribute 'inputs', must be relative to the working dir",
+        );
+    }
+
+    #[test]
+    fn test_manual_gate() {
+        let res =
+            assert_env().pass("manual_gate(name = 'approve', message = 'Deploy?', timeout = 60)");
+        let node = Node::from_value(res.value()).unwrap();
+        assert_eq!(node.name(), "approve");
+        assert_eq!(node.action_count(), 1);
+        assert_env().fail(
+            "manual_gate(name = 'approve', message = 'Deploy?', timeout = 0)",
+            "Invalid attribute 'timeout', must be a positive number of seconds",
+        );
+
+        // the delegate has no way to ask for an approval
+        let err = run_node("manual_gate(name = 'approve', message = 'Deploy?')").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "manual gate 'approve' can not be approved in this run"
+        );
+    }
+
+    #[test]
+    fn test_fails_once_retries_are_used_up() {
+        let err = run_node(
+            r#"
+def _run():
+    fail("broken")
+
+node(action = fn_action(implementation = _run), retries = 1)
+"#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("failed after 2 attempts"));
+    }
+
+    #[test]
+    fn test_retries_exit_code_which_is_not_ok() {
+        let workflow = r#"
+def _run():
+    return 1
+
+node(action = fn_action(implementation = _run), retries = 2)
+"#;
+        let outcome = run_node(workflow).unwrap();
+        assert_eq!(outcome.attempts, 3);
+        assert!(!outcome.success);
+
+        // a cancelled run is not retried
+        let token = CancelToken::new();
+        token.cancel();
+        let outcome =
+            run_node_with(workflow, WorkflowDelegate::new().with_cancel_token(token)).unwrap();
+        assert_eq!(outcome.attempts, 1);
+    }
+
+    #[test]
+    fn test_timeout_kills_tool() {
+        let started = Instant::now();
+        let err = run_node(
+            "node(name = 'slow', action = action(tool = builtin_tool(name = 'sleep'), args = ['5']), timeout = 1)",
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "node 'slow' timed out after 1s");
+        assert!(started.elapsed() < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn test_deadline() {
+        let res = assert_env()
+            .pass("node(name = 'slow', action = action(tool = tool(path='')), timeout = 5)");
+        let node = Node::from_value(res.value()).unwrap();
+        let attempt = Attempt {
+            number: 1,
+            deadline: Some(Instant::now() + Duration::from_secs(5)),
+        };
+        assert!(node.check_deadline(&attempt).is_ok());
+
+        let attempt = Attempt {
+            number: 1,
+            deadline: Some(Instant::now() - Duration::from_secs(1)),
+        };
+        assert_eq!(
+            node.check_deadline(&attempt).unwrap_err().to_string(),
+            "node 'slow' timed out after 5s"
         );
     }
 }
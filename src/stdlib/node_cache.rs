@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+/// What the actions of a node produced on its last successful run, kept so
+/// a later run whose inputs are unchanged can skip the node.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedNode {
+    /// A hash of the node's resolved commands and input files, the node is
+    /// only skipped while it is the same.
+    pub key: String,
+    /// The output of each of the node's actions, in order.
+    pub actions: Vec<CachedAction>,
+}
+
+/// The exit code and the output collected for the setters of an action.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedAction {
+    pub exit_code: i32,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
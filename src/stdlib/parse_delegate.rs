@@ -15,11 +15,17 @@ pub trait ParseDelegate: Any {
     /// Called when a variable is found
     fn on_variable(&self, _identifier: &str, _variable: VariableEntry) {}
 
+    /// Called once the module is evaluated for every variable bound to a
+    /// global name, before did_parse_workflow.
+    fn on_variable_name(&self, _identifier: &str, _name: &str) {}
+
     /// Called when the workflow parsing starts
     fn will_parse_workflow(&self, _workflow: PathBuf) {}
 
-    /// Called when the workflow parsing ends
-    fn did_parse_workflow(&self) {}
+    /// Called when the workflow parsing ends, an error fails the parse
+    fn did_parse_workflow(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
 }
 
 /// The ParseDelegateHolder provides a way to hold the delegate
@@ -0,0 +1,135 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// What running an action would do, worked out without running it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlannedAction {
+    /// A tool which is spawned as a process, with `env` set on top of the
+    /// inherited environment. It runs in `cwd`, or the inherited directory
+    /// when None.
+    Command {
+        program: PathBuf,
+        args: Vec<String>,
+        env: Vec<(String, String)>,
+        cwd: Option<PathBuf>,
+    },
+    /// Work done in process by a builtin action, a native or wasm tool or a
+    /// function, nothing is spawned.
+    InProcess { label: String, args: Vec<String> },
+    /// An action whose tool or args can not be resolved before the run,
+    /// e.g. because they use a variable which a setter sets.
+    Unresolved { label: String, reason: String },
+}
+
+/// What running a node would do.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodePlan {
+    pub name: String,
+    pub actions: Vec<PlannedAction>,
+    /// The function which picks the node to run after this one, None if the
+    /// workflow stops after this node.
+    pub next: Option<String>,
+}
+
+/// Quotes the arg so a shell reads it as a single word.
+fn shell_quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+fn write_args(f: &mut fmt::Formatter, args: &[String]) -> fmt::Result {
+    for arg in args {
+        write!(f, " {}", shell_quote(arg))?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for PlannedAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlannedAction::Command {
+                program,
+                args,
+                env,
+                cwd,
+            } => {
+                if let Some(cwd) = cwd {
+                    write!(f, "cd {} && ", shell_quote(&cwd.display().to_string()))?;
+                }
+                for (name, value) in env {
+                    write!(f, "{}={} ", name, shell_quote(value))?;
+                }
+                write!(f, "{}", shell_quote(&program.display().to_string()))?;
+                write_args(f, args)
+            }
+            PlannedAction::InProcess { label, args } => {
+                write!(f, "(in process) {}", label)?;
+                write_args(f, args)
+            }
+            PlannedAction::Unresolved { label, reason } => {
+                write!(f, "(unresolved) {}: {}", label, reason)
+            }
+        }
+    }
+}
+
+impl fmt::Display for NodePlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}:", self.name)?;
+        for action in &self.actions {
+            writeln!(f, "  {}", action)?;
+        }
+        match &self.next {
+            Some(next) => write!(f, "  next: decided by {} when the node runs", next),
+            None => write!(f, "  next: stops"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let command = PlannedAction::Command {
+            program: PathBuf::from("/bin/echo"),
+            args: vec!["a".into(), "two words".into(), "it's".into(), "".into()],
+            env: vec![],
+            cwd: None,
+        };
+        assert_eq!(command.to_string(), r"/bin/echo a 'two words' 'it'\''s' ''");
+        let with_env = PlannedAction::Command {
+            program: PathBuf::from("make"),
+            args: vec![],
+            env: vec![
+                ("CC".into(), "clang".into()),
+                ("CFLAGS".into(), "-O2 -g".into()),
+            ],
+            cwd: Some(PathBuf::from("/src/my app")),
+        };
+        assert_eq!(
+            with_env.to_string(),
+            "cd '/src/my app' && CC=clang CFLAGS='-O2 -g' make"
+        );
+
+        let plan = NodePlan {
+            name: "a".to_string(),
+            actions: vec![
+                command,
+                PlannedAction::Unresolved {
+                    label: "tool".to_string(),
+                    reason: "No value for variable".to_string(),
+                },
+            ],
+            next: None,
+        };
+        assert_eq!(
+            plan.to_string(),
+            "a:\n  /bin/echo a 'two words' 'it'\\''s' ''\n  (unresolved) tool: No value for variable\n  next: stops"
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use regex::bytes::Regex;
+use std::borrow::Cow;
+use std::sync::Arc;
+
+const REDACTED: &[u8] = b"[REDACTED]";
+// output without a newline is redacted and passed on once this much of it
+// is buffered, a match spanning the boundary is not redacted
+const MAX_PENDING: usize = 64 * 1024;
+
+/// Replaces the matches of the workflow's `redact_patterns` so tokens
+/// which end up in tool output are not shown or stored.
+#[derive(Debug, Default)]
+pub struct Redactor {
+    patterns: Vec<Regex>,
+}
+
+impl Redactor {
+    pub fn new(patterns: &[String]) -> anyhow::Result<Self> {
+        let patterns = patterns
+            .iter()
+            .map(|p| Regex::new(p))
+            .collect::<Result<_, _>>()?;
+        Ok(Redactor { patterns })
+    }
+
+    /// Returns a redactor which also replaces every occurrence of the
+    /// values, e.g. of the secrets.
+    pub fn with_values(&self, values: &[String]) -> Self {
+        let mut patterns = self.patterns.clone();
+        patterns.extend(
+            values
+                .iter()
+                .filter_map(|value| Regex::new(&regex::escape(value)).ok()),
+        );
+        Redactor { patterns }
+    }
+
+    pub fn redact_bytes<'a>(&self, bytes: &'a [u8]) -> Cow<'a, [u8]> {
+        let mut bytes = Cow::Borrowed(bytes);
+        for pattern in &self.patterns {
+            if let Cow::Owned(redacted) = pattern.replace_all(&bytes, REDACTED) {
+                bytes = Cow::Owned(redacted);
+            }
+        }
+        bytes
+    }
+
+    pub fn redact(&self, s: &str) -> String {
+        String::from_utf8_lossy(&self.redact_bytes(s.as_bytes())).into_owned()
+    }
+}
+
+/// Redacts a stream of output a line at a time so a match split across
+/// two reads is still found.
+#[derive(Debug)]
+pub(crate) struct LineRedactor {
+    redactor: Arc<Redactor>,
+    pending: Vec<u8>,
+}
+
+impl LineRedactor {
+    pub(crate) fn new(redactor: Arc<Redactor>) -> Self {
+        LineRedactor {
+            redactor,
+            pending: vec![],
+        }
+    }
+
+    /// Returns the redacted lines which were completed by `bytes`.
+    pub(crate) fn push(&mut self, bytes: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(bytes);
+        let end = match self.pending.iter().rposition(|b| *b == b'\n') {
+            Some(newline) => newline + 1,
+            None if self.pending.len() >= MAX_PENDING => self.pending.len(),
+            None => return vec![],
+        };
+        let lines: Vec<u8> = self.pending.drain(..end).collect();
+        self.redactor.redact_bytes(&lines).into_owned()
+    }
+
+    /// Returns whatever is left once the stream has ended, redacted.
+    pub(crate) fn finish(&mut self) -> Vec<u8> {
+        let rest = std::mem::take(&mut self.pending);
+        self.redactor.redact_bytes(&rest).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redactor() -> Redactor {
+        Redactor::new(&["ghp_[A-Za-z0-9]+".to_string(), "hunter2".to_string()]).unwrap()
+    }
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(
+            redactor().redact("token ghp_abc123 and hunter2!"),
+            "token [REDACTED] and [REDACTED]!"
+        );
+        assert!(matches!(
+            redactor().redact_bytes(b"nothing here"),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn test_with_values() {
+        let redactor = redactor().with_values(&["s3cr.t".to_string()]);
+        assert_eq!(
+            redactor.redact("s3cr.t s3cret hunter2"),
+            "[REDACTED] s3cret [REDACTED]"
+        );
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        assert!(Redactor::new(&["(".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_line_redactor_joins_split_matches() {
+        let mut lines = LineRedactor::new(Arc::new(redactor()));
+        assert_eq!(lines.push(b"a ghp_ab"), b"");
+        assert_eq!(lines.push(b"c\nb ghp"), b"a [REDACTED]\n");
+        assert_eq!(lines.push(b"_x"), b"");
+        assert_eq!(lines.finish(), b"b [REDACTED]");
+        assert_eq!(lines.finish(), b"");
+    }
+
+    #[test]
+    fn test_line_redactor_flushes_long_lines() {
+        let mut lines = LineRedactor::new(Arc::new(redactor()));
+        assert_eq!(lines.push(&vec![b'a'; MAX_PENDING]).len(), MAX_PENDING);
+    }
+}
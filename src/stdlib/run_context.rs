@@ -0,0 +1,167 @@
+use crate::stdlib::approval::Approval;
+use crate::stdlib::cancel::CancelToken;
+use crate::stdlib::env_capture::EnvCapture;
+use crate::stdlib::env_policy::EnvPolicy;
+use crate::stdlib::failure_injection::FailureInjector;
+use crate::stdlib::node_cache::CachedNode;
+use crate::stdlib::redact::Redactor;
+use crate::stdlib::RunDelegate;
+use anyhow::bail;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// What a run does for its nodes which needs the state of the run, e.g.
+/// asking for approvals and caching the outputs of nodes.
+pub trait RunHost {
+    /// Returns the directory the artifacts of the node were copied into,
+    /// None if the node has not collected them in this run.
+    fn node_artifacts_dir(&self, node: &str) -> Option<PathBuf>;
+
+    /// Returns what the cached node recorded on its last successful run,
+    /// None if there is no record or recorded outputs are not reused.
+    fn cached_node(&self, node: &str) -> Option<CachedNode>;
+
+    /// Records the outputs of a cached node which succeeded.
+    fn cache_node(&self, node: &str, cached: CachedNode);
+
+    /// Waits for the manual gate to be approved, for at most `timeout`.
+    fn approve(
+        &self,
+        gate: &str,
+        message: &str,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<Approval>;
+
+    /// Returns the approval the manual gate got in this run, removing it so
+    /// a gate which runs again is approved again.
+    fn take_approval(&self, gate: &str) -> Option<Approval>;
+}
+
+/// Everything a run gives the nodes and actions it runs apart from the
+/// values of the variables. A resolver which is not running a workflow,
+/// e.g. while it is described, gives the default context.
+#[derive(Clone, Default)]
+pub struct RunContext<'a> {
+    /// Whether action args are checked for newlines and NUL bytes, which
+    /// are almost always a bug, before the action runs.
+    pub check_args: bool,
+    /// The directory inline files are written to, None if files can not
+    /// be written.
+    pub scratch_dir: Option<PathBuf>,
+    /// How much of the environment of each spawned tool is recorded in the
+    /// run result.
+    pub env_capture: EnvCapture,
+    /// What is applied to the output of every action, None if it is shown
+    /// and captured as is.
+    pub redactor: Option<Arc<Redactor>>,
+    /// What is told about the nodes and actions as they run.
+    pub run_delegate: Option<&'a dyn RunDelegate>,
+    /// What cancels the run, None if it can not be cancelled.
+    pub cancel_token: Option<CancelToken>,
+    /// What makes tools fail at random instead of running, None if they
+    /// all run.
+    pub failure_injector: Option<Arc<FailureInjector>>,
+    /// Which environment variables tools are spawned with, None if they
+    /// inherit the environment of the current process.
+    pub env_policy: Option<Arc<EnvPolicy>>,
+    /// Whether the output of every action is hidden from the terminal, as
+    /// if they were all quiet.
+    pub quiet: bool,
+    /// The directory the artifacts of the run are copied into, None if
+    /// they are not collected.
+    pub artifacts_dir: Option<PathBuf>,
+    /// The directory the output of every action is logged in, None if it
+    /// is not logged.
+    pub log_dir: Option<PathBuf>,
+    /// The sandbox the run works in, None if it is not sandboxed. Commands
+    /// which do not set a cwd run in it.
+    pub sandbox_dir: Option<PathBuf>,
+    /// The run the nodes belong to, None if nothing is run.
+    pub host: Option<&'a dyn RunHost>,
+    /// The names of the graph nodes the nodes are nested in, the outermost
+    /// first.
+    pub graphs: Vec<String>,
+}
+
+impl<'a> RunContext<'a> {
+    /// Returns the context of the nodes of the graph of the named node. They
+    /// log and are cached under its name so they do not clash with the nodes
+    /// around it.
+    pub fn nested(mut self, graph: &str) -> Self {
+        self.log_dir = self.log_dir.map(|dir| dir.join(graph));
+        self.graphs.push(graph.to_string());
+        self
+    }
+
+    /// Returns the directory the artifacts of the node were copied into,
+    /// None if the node has not collected them in this run.
+    pub fn node_artifacts_dir(&self, node: &str) -> Option<PathBuf> {
+        self.host?.node_artifacts_dir(node)
+    }
+
+    /// Returns what the cached node recorded on its last successful run,
+    /// None if there is no record or recorded outputs are not reused.
+    pub fn cached_node(&self, node: &str) -> Option<CachedNode> {
+        self.host?.cached_node(&self.cache_key(node))
+    }
+
+    /// Records the outputs of a cached node which succeeded.
+    pub fn cache_node(&self, node: &str, cached: CachedNode) {
+        if let Some(host) = self.host {
+            host.cache_node(&self.cache_key(node), cached);
+        }
+    }
+
+    /// Waits for the manual gate to be approved, for at most `timeout`.
+    /// Fails if it is rejected, is not approved in time or the run has no
+    /// way to ask for approval.
+    pub fn approve(
+        &self,
+        gate: &str,
+        message: &str,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<Approval> {
+        match self.host {
+            Some(host) => host.approve(gate, message, timeout),
+            None => bail!("manual gate '{}' can not be approved in this run", gate),
+        }
+    }
+
+    /// Returns the approval the manual gate got in this run, removing it so
+    /// a gate which runs again is approved again.
+    pub fn take_approval(&self, gate: &str) -> Option<Approval> {
+        self.host?.take_approval(gate)
+    }
+
+    fn cache_key(&self, node: &str) -> String {
+        self.graphs
+            .iter()
+            .map(|graph| graph.as_str())
+            .chain([node])
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested() {
+        let context = RunContext {
+            log_dir: Some(PathBuf::from("logs")),
+            ..Default::default()
+        };
+        let context = context.nested("outer").nested("inner");
+        assert_eq!(context.log_dir, Some(PathBuf::from("logs/outer/inner")));
+        assert_eq!(context.cache_key("build"), "outer/inner/build");
+        assert!(context.cached_node("build").is_none());
+        assert!(context
+            .approve("gate", "", None)
+            .unwrap_err()
+            .to_string()
+            .contains("manual gate 'gate' can not be approved in this run"));
+    }
+}
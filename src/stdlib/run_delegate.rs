@@ -0,0 +1,37 @@
+use crate::stdlib::action::ActionResult;
+use crate::stdlib::NodeResult;
+use std::fmt;
+
+/// How an action went, given to RunDelegate::did_run_action.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ActionStatus {
+    /// The action ran, the result tells whether its exit code is ok.
+    Ran(ActionResult),
+    /// The action failed before it exited, e.g. its tool does not exist.
+    Failed(String),
+}
+
+impl ActionStatus {
+    pub fn succeeded(&self) -> bool {
+        matches!(self, ActionStatus::Ran(result) if result.success)
+    }
+}
+
+/// How the nodes and actions of a workflow tell the runner about their
+/// progress. The runner publishes it on its event bus, which is what the
+/// run is observed through.
+pub trait RunDelegate: fmt::Debug {
+    /// Called before a node of the workflow runs.
+    fn will_run_node(&self, _node: &str) {}
+
+    /// Called with the result of a node of the workflow once it has run.
+    fn did_run_node(&self, _node: &NodeResult) {}
+
+    /// Called before the action at `index` of the node runs, `action` names
+    /// what it runs. The actions of the nodes of a nested graph are given
+    /// with the name of their own node.
+    fn will_run_action(&self, _node: &str, _index: usize, _action: &str) {}
+
+    /// Called once the action at `index` of the node has run or failed to.
+    fn did_run_action(&self, _node: &str, _index: usize, _status: &ActionStatus) {}
+}
@@ -0,0 +1,188 @@
+use crate::stdlib::approval::Approval;
+use crate::stdlib::env_capture::ActionEnv;
+use crate::stdlib::memory::MemorySnapshot;
+use crate::stdlib::redact::Redactor;
+use crate::stdlib::variable_resolver::VariableSnapshot;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The outcome of running a single node in a workflow.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeResult {
+    pub name: String,
+    pub duration: Duration,
+    /// The exit code of the last action in the node, None if the node
+    /// never got far enough to run an action.
+    pub exit_code: Option<i32>,
+    /// Whether the exit code is one of the action's ok_exit_codes, None
+    /// if there is no exit code.
+    pub exit_code_ok: Option<bool>,
+    /// The error that stopped the node, if any.
+    pub error: Option<String>,
+    /// The memory in use once the node finished, only set when running
+    /// with memory profiling enabled.
+    pub memory: Option<MemorySnapshot>,
+    /// The values of the variables once the node finished, None if the
+    /// resolver does not support snapshots.
+    pub variables: Option<VariableSnapshot>,
+    /// The environment of each action in the node which spawned a tool.
+    pub envs: Vec<ActionEnv>,
+    /// Where the artifacts of the node were copied to.
+    pub artifacts: Vec<PathBuf>,
+    /// Whether the node was skipped because it was unchanged since its last
+    /// successful run.
+    pub cached: bool,
+    /// Who approved the node if it is a manual gate which was approved.
+    pub approval: Option<Approval>,
+}
+
+impl NodeResult {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+            && self
+                .exit_code_ok
+                .unwrap_or(self.exit_code.unwrap_or(0) == 0)
+    }
+
+    /// Redacts the error, the recorded environments, which can include
+    /// the output or arguments of the tools the node ran, and the values of
+    /// the variables, which can include the secrets.
+    pub fn redact(&mut self, redactor: &Redactor) {
+        if let Some(error) = &mut self.error {
+            *error = redactor.redact(error);
+        }
+        for value_ctx in self.variables.iter_mut().flat_map(|vars| vars.values_mut()) {
+            value_ctx.value = redactor.redact(&value_ctx.value);
+        }
+        for env in &mut self.envs {
+            for value in env.vars.iter_mut().flat_map(|vars| vars.values_mut()) {
+                *value = redactor.redact(value);
+            }
+        }
+    }
+}
+
+/// The result of running a workflow. Nodes are stored in the order
+/// in which they were run.
+#[derive(Debug, Default, Clone)]
+pub struct RunResult {
+    pub nodes: Vec<NodeResult>,
+    /// The names of all of the nodes in the workflow's graph.
+    pub graph_nodes: Vec<String>,
+    /// What was redacted from the nodes, including the values of the
+    /// secrets, None if nothing was.
+    pub redactor: Option<Arc<Redactor>>,
+}
+
+impl RunResult {
+    pub fn succeeded(&self) -> bool {
+        self.nodes.iter().all(|n| n.succeeded())
+    }
+
+    /// Returns the first node which did not succeed.
+    pub fn first_failed_node(&self) -> Option<&NodeResult> {
+        self.nodes.iter().find(|n| !n.succeeded())
+    }
+
+    /// Returns the names of the nodes in the order they were run. A node
+    /// appears once for every time it ran.
+    pub fn path(&self) -> Vec<&str> {
+        self.nodes.iter().map(|n| n.name.as_str()).collect()
+    }
+
+    /// Returns the nodes in the graph which never ran.
+    pub fn skipped(&self) -> Vec<&str> {
+        self.graph_nodes
+            .iter()
+            .filter(|name| !self.nodes.iter().any(|n| &n.name == *name))
+            .map(|name| name.as_str())
+            .collect()
+    }
+
+    /// Returns the first node which stopped the run with an error.
+    pub fn error(&self) -> Option<&NodeResult> {
+        self.nodes.iter().find(|n| n.error.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(name: &str, exit_code: Option<i32>, error: Option<&str>) -> NodeResult {
+        NodeResult {
+            name: name.to_string(),
+            duration: Duration::default(),
+            exit_code,
+            exit_code_ok: None,
+            error: error.map(|e| e.to_string()),
+            memory: None,
+            variables: None,
+            envs: vec![],
+            artifacts: vec![],
+            cached: false,
+            approval: None,
+        }
+    }
+
+    #[test]
+    fn test_empty_result_succeeds() {
+        assert!(RunResult::default().succeeded());
+        assert_eq!(RunResult::default().first_failed_node(), None);
+    }
+
+    #[test]
+    fn test_ok_exit_code_succeeds() {
+        let mut grep = node("grep", Some(1), None);
+        grep.exit_code_ok = Some(true);
+        assert!(grep.succeeded());
+        grep.exit_code_ok = Some(false);
+        assert!(!grep.succeeded());
+    }
+
+    #[test]
+    fn test_first_failed_node_non_zero_exit() {
+        let result = RunResult {
+            nodes: vec![node("a", Some(0), None), node("b", Some(1), None)],
+            ..Default::default()
+        };
+        assert!(!result.succeeded());
+        assert_eq!(result.first_failed_node().unwrap().name, "b");
+        assert_eq!(result.error(), None);
+    }
+
+    #[test]
+    fn test_first_failed_node_error() {
+        let result = RunResult {
+            nodes: vec![node("a", None, Some("boom"))],
+            ..Default::default()
+        };
+        assert_eq!(result.first_failed_node().unwrap().name, "a");
+        assert_eq!(result.error().unwrap().error, Some("boom".to_string()));
+    }
+
+    #[test]
+    fn test_path() {
+        let result = RunResult {
+            nodes: vec![
+                node("a", Some(0), None),
+                node("b", Some(1), None),
+                node("a", Some(0), None),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(result.path(), vec!["a", "b", "a"]);
+        assert!(RunResult::default().path().is_empty());
+    }
+
+    #[test]
+    fn test_skipped() {
+        let result = RunResult {
+            nodes: vec![node("a", Some(0), None), node("c", Some(0), None)],
+            graph_nodes: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            redactor: None,
+        };
+        assert_eq!(result.skipped(), vec!["b"]);
+    }
+}
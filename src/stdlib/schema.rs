@@ -0,0 +1,137 @@
+use anyhow::bail;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Migrates json of one version of a schema to the next.
+pub type Migration = fn(Value) -> anyhow::Result<Value>;
+
+/// The schema of json which outlives the binary that wrote it, e.g. the
+/// compiled IR or the files in the state dir. The json carries the version
+/// it was written with in its `version` field. Json of an older version is
+/// migrated to the current one before it is read, json of a newer version
+/// is refused rather than misread.
+#[derive(Debug)]
+pub struct Schema {
+    /// What the json is, for the errors.
+    pub name: &'static str,
+    pub version: u32,
+    /// The oldest version which can still be read. Json written before
+    /// the schema was versioned has version 0.
+    pub oldest: u32,
+    /// `migrations[i]` migrates version `oldest + i` to the one after it,
+    /// there is one for each version from the oldest to the current one.
+    pub migrations: &'static [Migration],
+}
+
+impl Schema {
+    /// Returns the version the json was written with, 0 if it has none.
+    pub fn version_of(value: &Value) -> u32 {
+        value
+            .get("version")
+            .and_then(|v| v.as_u64())
+            .map_or(0, |v| v as u32)
+    }
+
+    /// Migrates the json to the current version, failing with which binary
+    /// produced it if it can not be read.
+    pub fn migrate(&self, mut value: Value) -> anyhow::Result<Value> {
+        debug_assert_eq!(self.migrations.len() as u32, self.version - self.oldest);
+        let version = Schema::version_of(&value);
+        if version > self.version {
+            bail!(
+                "{} was produced by a newer version of workflow (version {}), this one reads up to version {}",
+                self.name,
+                version,
+                self.version
+            );
+        }
+        if version < self.oldest {
+            bail!(
+                "{} was produced by an older version of workflow (version {}) which this one can no longer read, the oldest it reads is version {}",
+                self.name,
+                version,
+                self.oldest
+            );
+        }
+        for migration in &self.migrations[(version - self.oldest) as usize..] {
+            value = migration(value)?;
+        }
+        if let Some(object) = value.as_object_mut() {
+            object.insert("version".to_string(), Value::from(self.version));
+        }
+        Ok(value)
+    }
+
+    /// Reads the json, migrated to the current version.
+    pub fn read<T: DeserializeOwned>(&self, value: Value) -> anyhow::Result<T> {
+        Ok(serde_json::from_value(self.migrate(value)?)?)
+    }
+
+    /// Returns the json of the value with the current version, which has to
+    /// be an object.
+    pub fn write<T: Serialize>(&self, value: &T) -> anyhow::Result<Value> {
+        let mut value = serde_json::to_value(value)?;
+        match value.as_object_mut() {
+            Some(object) => object.insert("version".to_string(), Value::from(self.version)),
+            None => bail!("{} must be written as a json object", self.name),
+        };
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // version 1 named the field `cmd`, version 2 renamed it to `command`
+    fn rename_cmd(mut value: Value) -> anyhow::Result<Value> {
+        if let Some(cmd) = value.as_object_mut().and_then(|o| o.remove("cmd")) {
+            value["command"] = cmd;
+        }
+        Ok(value)
+    }
+
+    // the unversioned files had other fields, which were dropped
+    fn from_unversioned(value: Value) -> anyhow::Result<Value> {
+        Ok(json!({ "cmd": value["cmd"] }))
+    }
+
+    const SCHEMA: Schema = Schema {
+        name: "the test file",
+        version: 2,
+        oldest: 0,
+        migrations: &[from_unversioned, rename_cmd],
+    };
+
+    #[test]
+    fn test_migrate() {
+        let current = json!({ "version": 2, "command": "ls" });
+        assert_eq!(SCHEMA.migrate(json!({ "cmd": "ls" })).unwrap(), current);
+        assert_eq!(
+            SCHEMA
+                .migrate(json!({ "version": 1, "cmd": "ls" }))
+                .unwrap(),
+            current
+        );
+        assert_eq!(SCHEMA.migrate(current.clone()).unwrap(), current);
+    }
+
+    #[test]
+    fn test_unreadable_versions() {
+        assert_eq!(
+            SCHEMA.migrate(json!({ "version": 3 })).unwrap_err().to_string(),
+            "the test file was produced by a newer version of workflow (version 3), this one reads up to version 2"
+        );
+        let schema = Schema {
+            oldest: 1,
+            migrations: &[rename_cmd],
+            ..SCHEMA
+        };
+        assert_eq!(
+            schema.migrate(json!({ "cmd": "ls" })).unwrap_err().to_string(),
+            "the test file was produced by an older version of workflow (version 0) which this one can no longer read, the oldest it reads is version 1"
+        );
+    }
+}
@@ -1,7 +1,12 @@
+use crate::stdlib::errors::{ArgCheck, StdlibError, ValueError};
+use crate::stdlib::format::ValueFormatter;
+use crate::stdlib::ir::{compile_arg, variable_name, SetterIr};
+use crate::stdlib::node::function_name;
+use crate::stdlib::variable_resolver::{string_from_value, VariableResolver};
 use crate::stdlib::VariableRef;
 use crate::stdlib::{SETTER_TYPE, VARIABLE_REF_TYPE};
 use allocative::Allocative;
-use anyhow::bail;
+use anyhow::{anyhow, bail};
 use starlark::coerce::Coerce;
 use starlark::starlark_complex_value;
 use starlark::values::starlark_value;
@@ -16,20 +21,159 @@ use starlark::values::ValueLike;
 use starlark::StarlarkDocs;
 use std::fmt;
 use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+
+/// The types a setter can return, a string updates the variable and None
+/// leaves it as it is.
+const RETURN_TYPES: [&str; 2] = ["string", "None"];
 
 pub(crate) fn setter_impl<'v>(
-    implementation: Value<'v>,
+    implementation: Option<Value<'v>>,
     variable: Value<'v>,
+    returns: Option<&str>,
+    from_file: Option<Value<'v>>,
+    strip: Option<bool>,
+    json_path: Option<&str>,
 ) -> anyhow::Result<Setter<'v>> {
-    if variable.get_type() != VARIABLE_REF_TYPE {
-        bail!("expected variable type in setter definition")
+    let check = ArgCheck::new("setter")
+        .arg("implementation", implementation.is_some())
+        .arg("from_file", from_file.is_some())
+        .arg("json_path", json_path.is_some())
+        .arg("returns", returns.is_some())
+        .arg("strip", strip.is_some())
+        .requires("returns", "implementation")?
+        .requires("strip", "from_file")?;
+    // a json_path reads from the file if there is one and stdout otherwise
+    match json_path {
+        Some(_) => check.at_most_one_of(&["implementation", "json_path"])?,
+        None => check.exactly_one_of(&["implementation", "from_file"])?,
+    };
+    ValueError::check_type("setter", "variable", VARIABLE_REF_TYPE, variable)?;
+    if let Some(json_path) = json_path {
+        parse_json_path(json_path)?;
+    }
+    if implementation.is_none() {
+        let from_file = from_file.unwrap_or_else(Value::new_none);
+        let valid = match from_file.unpack_str() {
+            Some(s) => !s.is_empty(),
+            None => {
+                VariableRef::from_value(from_file).is_some()
+                    || ValueFormatter::from_value(from_file).is_some()
+            }
+        };
+        if !valid && !from_file.is_none() {
+            bail!(StdlibError::new_invalid_attr(
+                "from_file",
+                "must be a non empty string, a variable or a format",
+                from_file.to_repr()
+            ));
+        }
+        return Ok(Setter {
+            implementation: Value::new_none(),
+            variable,
+            returns: vec![],
+            from_file,
+            strip: strip.unwrap_or(true),
+            json_path: json_path.map(String::from),
+        });
     }
-    if implementation.get_type() != "function" {
-        bail!("expected function type in setter definition")
+    let implementation = implementation.unwrap();
+    ValueError::check_type("setter", "implementation", "function", implementation)?;
+    let returns: Vec<String> = returns
+        .map(|r| r.split('|').map(|t| t.trim().to_string()).collect())
+        .unwrap_or_default();
+    for typ in &returns {
+        if !RETURN_TYPES.contains(&typ.as_str()) {
+            bail!(
+                "setter '{}' declares it returns {}, a setter must return string or None",
+                function_name(implementation),
+                if typ.is_empty() { "nothing" } else { typ }
+            );
+        }
     }
     Ok(Setter {
         implementation: implementation,
         variable: variable,
+        returns,
+        from_file: Value::new_none(),
+        strip: false,
+        json_path: None,
+    })
+}
+
+/// A step of a `json_path`, into a field of an object or an element of an
+/// array.
+#[derive(Debug, PartialEq)]
+enum JsonKey {
+    Field(String),
+    Index(usize),
+}
+
+/// Parses a path like `.foo.bar` or `.items[0].name`, `.` is the whole
+/// document.
+fn parse_json_path(path: &str) -> anyhow::Result<Vec<JsonKey>> {
+    let invalid = || {
+        anyhow!(StdlibError::new_invalid_attr(
+            "json_path",
+            "must be a path like .foo.bar or .items[0]",
+            path
+        ))
+    };
+    if path == "." {
+        return Ok(vec![]);
+    }
+    if !path.starts_with('.') {
+        return Err(invalid());
+    }
+    let mut keys = vec![];
+    let mut rest = path;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            if end == 0 {
+                return Err(invalid());
+            }
+            keys.push(JsonKey::Field(after[..end].to_string()));
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']').ok_or_else(invalid)?;
+            keys.push(JsonKey::Index(after[..end].parse().map_err(|_| invalid())?));
+            rest = &after[end + 1..];
+        } else {
+            return Err(invalid());
+        }
+    }
+    Ok(keys)
+}
+
+/// Returns the value at the path of the JSON document, a string as it is
+/// and anything else but null as JSON. Null is None, which leaves the
+/// variable as it is.
+fn extract_json(text: &str, path: &str) -> anyhow::Result<Option<String>> {
+    let json: serde_json::Value = match serde_json::from_str(text) {
+        Ok(json) => json,
+        Err(e) => bail!(
+            "can not read '{}' from the output, it is not JSON: {}",
+            path,
+            e
+        ),
+    };
+    let mut value = &json;
+    for key in parse_json_path(path)? {
+        let next = match &key {
+            JsonKey::Field(name) => value.get(name),
+            JsonKey::Index(index) => value.get(index),
+        };
+        value = match next {
+            Some(next) => next,
+            None => bail!("'{}' is not in the JSON output", path),
+        };
+    }
+    Ok(match value {
+        serde_json::Value::Null => None,
+        serde_json::Value::String(s) => Some(s.clone()),
+        value => Some(value.to_string()),
     })
 }
 
@@ -40,6 +184,13 @@ pub(crate) fn setter_impl<'v>(
 pub struct SetterGen<V> {
     implementation: V,
     variable: V,
+    returns: Vec<String>,
+    // the path of the file the variable is read from, None for a setter
+    // with an implementation
+    from_file: V,
+    strip: bool,
+    // the field of the JSON output, or file, the variable is set to
+    json_path: Option<String>,
 }
 starlark_complex_value!(pub Setter);
 
@@ -52,6 +203,104 @@ impl<'v> Setter<'v> {
         self.implementation.clone()
     }
 
+    /// Returns the name of the implementation, see `function_name`.
+    pub fn implementation_name(&self) -> String {
+        function_name(self.implementation)
+    }
+
+    /// The types the setter declares its implementation returns, e.g.
+    /// `["string", "None"]` for `returns = "string | None"`, empty if it
+    /// does not declare them.
+    pub fn returns(&self) -> &[String] {
+        &self.returns
+    }
+
+    /// Returns the path of the file a `from_file` setter reads the variable
+    /// from, None for a setter with an implementation.
+    pub fn from_file(&self) -> Option<Value<'v>> {
+        match self.from_file.is_none() {
+            true => None,
+            false => Some(self.from_file),
+        }
+    }
+
+    /// Returns the value a setter without an implementation sets the
+    /// variable to from the output of the tool, the stdout or the contents
+    /// of its file, None to leave the variable as it is.
+    pub(crate) fn value_from_output(&self, output: String) -> anyhow::Result<Option<String>> {
+        match &self.json_path {
+            Some(path) => extract_json(&output, path),
+            None => Ok(Some(output)),
+        }
+    }
+
+    /// Reads the value of a `from_file` setter, a relative path is read
+    /// from the directory. The contents are stripped of leading and
+    /// trailing whitespace unless the setter sets `strip = False`.
+    pub(crate) fn read_file<T: VariableResolver>(
+        &self,
+        resolver: &T,
+        dir: &Path,
+    ) -> anyhow::Result<String> {
+        let path = dir.join(string_from_value(self.from_file, resolver)?);
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) => bail!(
+                "can not read the value of the setter from {:?}: {}",
+                path,
+                e
+            ),
+        };
+        Ok(match self.strip {
+            true => contents.trim().to_string(),
+            false => contents,
+        })
+    }
+
+    /// Returns the setter as IR, failing for a setter with an implementation
+    /// or one which reads the file at a formatted path.
+    pub(crate) fn compile<T: VariableResolver>(&self, resolver: &T) -> anyhow::Result<SetterIr> {
+        if !self.implementation.is_none() {
+            bail!(
+                "setter '{}' is a function, only setters which read the output can be compiled",
+                self.implementation_name()
+            );
+        }
+        let variable = variable_name(resolver, self.variable_identifier())?;
+        let from_file = match self.from_file() {
+            Some(path) => Some(compile_arg(path, resolver)?.ok_or_else(|| {
+                anyhow!(
+                    "the setter of '{}' reads the file at a format, only strings and variables can be compiled",
+                    variable
+                )
+            })?),
+            None => None,
+        };
+        Ok(SetterIr {
+            variable,
+            from_file,
+            strip: self.strip,
+            json_path: self.json_path.clone(),
+        })
+    }
+
+    /// Fails unless the value has a type the setter declares it returns.
+    pub(crate) fn check_returned(&self, value: Value) -> anyhow::Result<()> {
+        let typ = match value.get_type() {
+            "NoneType" => "None",
+            typ => typ,
+        };
+        if !self.returns.is_empty() && !self.returns.iter().any(|r| r == typ) {
+            bail!(
+                "setter '{}' returned {} but declares it returns {}",
+                self.implementation_name(),
+                typ,
+                self.returns.join(" | ")
+            );
+        }
+        Ok(())
+    }
+
     pub fn variable_identifier(&self) -> &str {
         // self.variable.
         self.variable
@@ -67,6 +316,10 @@ impl<'v> Freeze for Setter<'v> {
         Ok(SetterGen {
             implementation: self.implementation.freeze(freezer)?,
             variable: self.variable.freeze(freezer)?,
+            returns: self.returns,
+            from_file: self.from_file.freeze(freezer)?,
+            strip: self.strip,
+            json_path: self.json_path,
         })
     }
 }
@@ -79,7 +332,7 @@ impl<V> Display for SetterGen<V> {
 
 #[cfg(test)]
 mod tests {
-
+    use super::*;
     use crate::stdlib::test_utils::assert_env;
 
     #[test]
@@ -111,8 +364,109 @@ v_setter = setter(
   variable = "v"
 )
 "#,
-            "expected variable type in setter definition",
+            "expected variable_ref for 'variable' in setter definition, got",
+        );
+    }
+
+    #[test]
+    fn test_returns() {
+        assert_env().pass(
+            r#"
+def _foo_impl(ctx):
+  return "a"
+
+setter(implementation = _foo_impl, variable = variable(), returns = "string")
+setter(implementation = _foo_impl, variable = variable(), returns = "string | None")
+"#,
+        );
+        assert_env().fail(
+            r#"
+def _foo_impl(ctx):
+  return 1
+
+setter(implementation = _foo_impl, variable = variable(), returns = "int")
+"#,
+            "setter '_foo_impl' declares it returns int, a setter must return string or None",
+        );
+    }
+
+    #[test]
+    fn test_from_file() {
+        assert_env().pass(
+            r#"
+v = variable()
+setter(from_file = "version.txt", variable = v)
+setter(from_file = format("{}.txt", v), variable = v, strip = False)
+"#,
+        );
+        assert_env().fail(
+            r#"
+def _foo_impl(ctx):
+  return "a"
+
+setter(implementation = _foo_impl, from_file = "version.txt", variable = variable())
+"#,
+            "exactly one of",
+        );
+        assert_env().fail(
+            "setter(from_file = '', variable = variable())",
+            "must be a non empty string, a variable or a format",
+        );
+        assert_env().fail(
+            "setter(from_file = 'a', variable = variable(), returns = 'string')",
+            "returns",
+        );
+    }
+
+    #[test]
+    fn test_json_path() {
+        assert_env().pass(
+            r#"
+v = variable()
+setter(json_path = ".version", variable = v)
+setter(json_path = ".", from_file = "out.json", variable = v)
+"#,
+        );
+        assert_env().fail(
+            "setter(json_path = 'version', variable = variable())",
+            "must be a path like .foo.bar or .items[0]",
+        );
+        assert_env().fail(
+            "setter(json_path = '.a', variable = variable(), strip = False)",
+            "strip",
+        );
+
+        assert_eq!(
+            parse_json_path(".items[0].name").unwrap(),
+            [
+                JsonKey::Field("items".to_string()),
+                JsonKey::Index(0),
+                JsonKey::Field("name".to_string()),
+            ]
+        );
+        assert_eq!(parse_json_path(".").unwrap(), []);
+        assert!(parse_json_path(".a..b").is_err());
+        assert!(parse_json_path(".a[x]").is_err());
+        assert!(parse_json_path(".a[0").is_err());
+    }
+
+    #[test]
+    fn test_extract_json() {
+        let json = r#"{"name": "a", "version": {"major": 1}, "tags": ["x", "y"], "next": null}"#;
+        let extract = |path| extract_json(json, path).unwrap();
+        assert_eq!(extract(".name"), Some("a".to_string()));
+        assert_eq!(extract(".version.major"), Some("1".to_string()));
+        assert_eq!(extract(".version"), Some(r#"{"major":1}"#.to_string()));
+        assert_eq!(extract(".tags[1]"), Some("y".to_string()));
+        assert_eq!(extract(".next"), None);
+        assert_eq!(
+            extract_json(json, ".tags[2]").unwrap_err().to_string(),
+            "'.tags[2]' is not in the JSON output"
         );
+        assert!(extract_json("a", ".name")
+            .unwrap_err()
+            .to_string()
+            .starts_with("can not read '.name' from the output, it is not JSON"));
     }
 
     #[test]
@@ -124,7 +478,7 @@ setter(
   variable = variable(),
 )
 "#,
-            "expected function type in setter definition",
+            "expected function for 'implementation' in setter definition, got",
         );
     }
 }
@@ -1,6 +1,8 @@
+use crate::stdlib::ir::ToolIr;
 use crate::stdlib::variable_resolver::{string_from_value, VariableResolver};
 use crate::stdlib::TOOL_TYPE;
 use allocative::Allocative;
+use anyhow::bail;
 use starlark::coerce::Coerce;
 use starlark::starlark_complex_value;
 use starlark::values::starlark_value;
@@ -13,24 +15,59 @@ use starlark::values::Trace;
 use starlark::values::Value;
 use starlark::values::ValueLike;
 use starlark::StarlarkDocs;
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
 use std::fmt;
 use std::fmt::Display;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
 use std::path::PathBuf;
 use which::which;
 
+// at most this many executables are suggested for a missing builtin tool
+const MAX_SUGGESTIONS: usize = 3;
+
 pub(crate) fn tool_impl<'v>(path: Value<'v>) -> anyhow::Result<Tool<'v>> {
     Ok(Tool {
         path: path,
         builtin: false,
+        wasm: false,
+        native: false,
+        name: "".to_string(),
+        aliases: vec![],
+    })
+}
+
+pub(crate) fn wasm_tool_impl<'v>(module: Value<'v>) -> anyhow::Result<Tool<'v>> {
+    Ok(Tool {
+        path: module,
+        builtin: false,
+        wasm: true,
+        native: false,
         name: "".to_string(),
+        aliases: vec![],
     })
 }
 
-pub(crate) fn builtin_tool_impl<'v>(name: &str) -> anyhow::Result<Tool<'v>> {
+pub(crate) fn builtin_tool_impl<'v>(name: &str, aliases: Vec<String>) -> anyhow::Result<Tool<'v>> {
     Ok(Tool {
         path: Value::new_none(),
         builtin: true,
+        wasm: false,
+        native: false,
         name: name.to_string(),
+        aliases,
+    })
+}
+
+pub(crate) fn native_tool_impl<'v>(name: &str) -> anyhow::Result<Tool<'v>> {
+    Ok(Tool {
+        path: Value::new_none(),
+        builtin: false,
+        wasm: false,
+        native: true,
+        name: name.to_string(),
+        aliases: vec![],
     })
 }
 
@@ -40,9 +77,16 @@ pub(crate) fn builtin_tool_impl<'v>(name: &str) -> anyhow::Result<Tool<'v>> {
 #[repr(C)]
 pub struct ToolGen<V> {
     builtin: bool,
+    // if true, path points to a WASI module which is run in an embedded runtime
+    wasm: bool,
+    // if true, the tool is a closure registered with register_native_tool
+    native: bool,
     path: V,
-    // name is only valid if builtin is true
+    // name is only valid if builtin or native is true
     name: String,
+    // the names a builtin tool is looked up by, in order, if name is not on
+    // the PATH
+    aliases: Vec<String>,
 }
 starlark_complex_value!(pub Tool);
 
@@ -51,23 +95,62 @@ impl<'v, V: ValueLike<'v> + 'v> StarlarkValue<'v> for ToolGen<V> where Self: Pro
 
 impl<'a> Tool<'a> {
     /// Returns the real path of the tool. Will return an error if the path does not
-    /// resolve to an executable.
+    /// resolve to an executable, or to a file for wasm tools.
     pub fn real_path<T: VariableResolver>(
         &self,
         resolver: &T,
         working_dir: &PathBuf,
     ) -> anyhow::Result<PathBuf> {
+        if self.native {
+            bail!("native tool '{}' does not have a path", self.name);
+        }
         let path = self.path(resolver, &working_dir)?;
+        if self.wasm {
+            if !path.is_file() {
+                bail!("wasm module does not exist at {:?}", path);
+            }
+            return Ok(path);
+        }
+        if self.builtin {
+            return self.builtin_path();
+        }
         Ok(which(&path)?)
     }
 
+    /// Returns the path of the first of the name and the aliases of a
+    /// builtin tool which is on the PATH. Fails with the executables whose
+    /// names are closest to them if none of them are.
+    fn builtin_path(&self) -> anyhow::Result<PathBuf> {
+        let names: Vec<&str> = std::iter::once(self.name.as_str())
+            .chain(self.aliases.iter().map(|a| a.as_str()))
+            .collect();
+        if let Some(path) = names.iter().find_map(|name| which(name).ok()) {
+            return Ok(path);
+        }
+        let mut message = format!("'{}' is not on the PATH", self.name);
+        if !self.aliases.is_empty() {
+            message.push_str(&format!(
+                ", nor are its aliases {}",
+                quoted(&names[1..], ", ")
+            ));
+        }
+        let suggestions = match std::env::var_os("PATH") {
+            Some(path) => similar_executables(&path, &names),
+            None => vec![],
+        };
+        if !suggestions.is_empty() {
+            message.push_str(&format!(", did you mean {}?", quoted(&suggestions, " or ")));
+        }
+        bail!(message)
+    }
+
     /// Returns the path of the tool. This tool is the raw path and is not validated.
     pub fn path<T: VariableResolver>(
         &self,
         resolver: &T,
         working_dir: &PathBuf,
     ) -> anyhow::Result<PathBuf> {
-        if self.builtin {
+        if self.builtin || self.native {
             Ok(PathBuf::from(self.name.clone()))
         } else {
             let path = PathBuf::from(string_from_value(self.path, resolver)?);
@@ -84,22 +167,122 @@ impl<'a> Tool<'a> {
         }
     }
 
+    /// Returns the tool as IR, failing for the tools which do not run an
+    /// executable at a fixed path.
+    pub fn compile(&self) -> anyhow::Result<ToolIr> {
+        if self.wasm || self.native {
+            bail!("wasm and native tools can not be compiled");
+        }
+        if self.builtin {
+            return Ok(ToolIr::Builtin {
+                name: self.name.clone(),
+                aliases: self.aliases.clone(),
+            });
+        }
+        match self.path.unpack_str() {
+            Some(path) => Ok(ToolIr::Path {
+                path: path.to_string(),
+            }),
+            None => bail!("a tool whose path is not a string can not be compiled"),
+        }
+    }
+
     pub fn is_builtin(&self) -> bool {
         self.builtin
     }
 
+    pub fn is_wasm(&self) -> bool {
+        self.wasm
+    }
+
+    pub fn is_native(&self) -> bool {
+        self.native
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
 }
 
+fn quoted<T: AsRef<str>>(names: &[T], separator: &str) -> String {
+    names
+        .iter()
+        .map(|name| format!("'{}'", name.as_ref()))
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+/// Returns the executables in the directories of `path` whose names are
+/// closest to one of the names, the closest first. A name is only
+/// suggested if it is a few edits away, relative to its length.
+fn similar_executables(path: &OsStr, names: &[&str]) -> Vec<String> {
+    let mut candidates = BTreeSet::new();
+    for dir in std::env::split_paths(path) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let executable = fs::metadata(entry.path())
+                .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false);
+            if executable {
+                candidates.insert(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+    }
+    let mut scored: Vec<(usize, String)> = candidates
+        .into_iter()
+        .filter_map(|candidate| {
+            names
+                .iter()
+                .filter(|name| **name != candidate)
+                .map(|name| (levenshtein(name, &candidate), name.chars().count()))
+                .filter(|(distance, len)| *distance <= (len / 3).max(1))
+                .map(|(distance, _)| distance)
+                .min()
+                .map(|distance| (distance, candidate))
+        })
+        .collect();
+    scored.sort();
+    scored
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+/// Returns the number of single character insertions, deletions and
+/// substitutions which turn a into b.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                diagonal
+            } else {
+                1 + diagonal.min(above).min(row[j])
+            };
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
 impl<'v> Freeze for Tool<'v> {
     type Frozen = FrozenTool;
     fn freeze(self, freezer: &Freezer) -> anyhow::Result<Self::Frozen> {
         Ok(ToolGen {
             path: self.path.freeze(freezer)?,
             builtin: self.builtin.freeze(freezer)?,
+            wasm: self.wasm.freeze(freezer)?,
+            native: self.native.freeze(freezer)?,
             name: self.name.freeze(freezer)?,
+            aliases: self.aliases,
         })
     }
 }
@@ -205,6 +388,44 @@ mod tests {
         assert_eq!(tool.real_path(&"".to_string(), &root).unwrap(), exe.path());
     }
 
+    #[test]
+    fn test_wasm_tool_real_path() {
+        let module = TempWorkflowFile::new("module.wasm", "").unwrap();
+        let mut env = assert_env();
+        let module_env = env.module("tool.star", "t = tool(wasm = 'module.wasm')");
+        let t = module_env.get("t").unwrap();
+        let tool = Tool::from_value(t.value()).unwrap();
+
+        assert!(tool.is_wasm());
+        assert_eq!(
+            tool.real_path(&"".to_string(), &module.dir()).unwrap(),
+            module.path()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "wasm module does not exist")]
+    fn test_wasm_tool_real_path_fail() {
+        let mut env = assert_env();
+        let module = env.module("tool.star", "t = tool(wasm = '__no_file__.wasm')");
+        let t = module.get("t").unwrap();
+        let tool = Tool::from_value(t.value()).unwrap();
+        tool.real_path(&"".to_string(), &PathBuf::default())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_tool_requires_path_or_wasm() {
+        assert_env().fail(
+            "tool()",
+            "expected exactly one of 'path', 'wasm' in tool definition",
+        );
+        assert_env().fail(
+            "tool(path = 'a', wasm = 'b')",
+            "expected exactly one of 'path', 'wasm' in tool definition",
+        );
+    }
+
     #[test]
     fn test_builtin_tool_name_returns_name() {
         let mut env = assert_env();
@@ -232,6 +453,74 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_builtin_tool_aliases() {
+        let mut env = assert_env();
+        let module = env.module(
+            "tool.star",
+            "t = builtin_tool(name = '__INVALID_TOOL__', aliases = ['__ALSO_INVALID__', 'ls'])",
+        );
+        let t = module.get("t").unwrap();
+        let tool = Tool::from_value(t.value()).unwrap();
+        assert_eq!(
+            tool.real_path(&"".to_string(), &PathBuf::default())
+                .unwrap(),
+            which("ls").unwrap()
+        );
+        assert_eq!(
+            tool.compile().unwrap(),
+            ToolIr::Builtin {
+                name: "__INVALID_TOOL__".to_string(),
+                aliases: vec!["__ALSO_INVALID__".to_string(), "ls".to_string()],
+            }
+        );
+
+        let module = env.module(
+            "tool.star",
+            "t = builtin_tool(name = '__INVALID_TOOL__', aliases = ['__ALSO_INVALID__'])",
+        );
+        let t = module.get("t").unwrap();
+        let tool = Tool::from_value(t.value()).unwrap();
+        assert_eq!(
+            tool.real_path(&"".to_string(), &PathBuf::default())
+                .unwrap_err()
+                .to_string(),
+            "'__INVALID_TOOL__' is not on the PATH, nor are its aliases '__ALSO_INVALID__'"
+        );
+    }
+
+    #[test]
+    fn test_similar_executables() {
+        let dir = tempfile::tempdir().unwrap();
+        let other = tempfile::tempdir().unwrap();
+        for (dir, name, mode) in [
+            (&dir, "make", 0o755),
+            (&dir, "cmake", 0o755),
+            (&dir, "gmake.txt", 0o644),
+            (&other, "bmake", 0o755),
+            (&other, "rustc", 0o755),
+        ] {
+            let path = dir.path().join(name);
+            fs::write(&path, "").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        }
+        let path = std::env::join_paths([dir.path(), other.path()]).unwrap();
+        assert_eq!(
+            similar_executables(&path, &["gmake"]),
+            ["bmake", "cmake", "make"]
+        );
+        assert_eq!(similar_executables(&path, &["rust", "mk"]), ["rustc"]);
+        assert!(similar_executables(&path, &["docker"]).is_empty());
+    }
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein("gmake", "make"), 1);
+        assert_eq!(levenshtein("pyhton", "python"), 2);
+        assert_eq!(levenshtein("", "ls"), 2);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+    }
+
     #[test]
     #[should_panic]
     fn test_builtin_tool_real_path_fail() {
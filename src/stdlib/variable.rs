@@ -1,7 +1,10 @@
-use crate::stdlib::errors::StdlibError;
+use crate::stdlib::errors::{StdlibError, ValueError};
+use crate::stdlib::ir::VariableIr;
+use crate::stdlib::variable_resolver::VariableResolverError;
 use crate::stdlib::{ParseDelegateHolder, VARIABLE_REF_TYPE};
 use allocative::Allocative;
 use anyhow::bail;
+use serde::{Deserialize, Serialize};
 use starlark::eval::Evaluator;
 use starlark::starlark_simple_value;
 use starlark::values::list::ListOf;
@@ -9,25 +12,31 @@ use starlark::values::starlark_value;
 use starlark::values::NoSerialize;
 use starlark::values::ProvidesStaticType;
 use starlark::values::StarlarkValue;
+use starlark::values::Value;
 use std::fmt;
 use std::ops::Deref;
 use uuid::Uuid;
 
+pub(crate) fn const_impl(value: Value, eval: &mut Evaluator) -> anyhow::Result<VariableRef> {
+    if !matches!(value.get_type(), "string" | "int" | "bool") {
+        bail!(ValueError::WrongType {
+            definition: "const",
+            attr: "value",
+            expected: "string, int or bool",
+            got: value.get_type().to_string(),
+        });
+    }
+    variable_impl(VariableEntry::constant(value.to_str()), eval)
+}
+
 pub(crate) fn variable_impl(
-    default: Option<&str>,
-    env: Option<&str>,
-    cli_flag: Option<&str>,
-    readers: Option<ListOf<String>>,
-    writers: Option<ListOf<String>>,
+    entry: VariableEntry,
     eval: &mut Evaluator,
 ) -> anyhow::Result<VariableRef> {
     let var_ref = VariableRef::new();
 
     if let Ok(delegate) = ParseDelegateHolder::from_evaluator(&eval) {
-        delegate.deref().on_variable(
-            var_ref.identifier(),
-            VariableEntry::from_starlark(default, env, cli_flag, readers, writers)?,
-        );
+        delegate.deref().on_variable(var_ref.identifier(), entry);
     }
     Ok(var_ref)
 }
@@ -61,12 +70,16 @@ impl VariableRef {
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum ValueUpdatedBy {
     CLIFlag(String),
     EnvironmentVariable(String),
     Action(String),
     DefaultValue,
+    /// A custom variable source with the given name.
+    Source(String),
+    /// The user was asked for the value of a required variable.
+    Prompt,
 
     #[cfg(test)]
     ForTest,
@@ -81,6 +94,8 @@ impl fmt::Display for ValueUpdatedBy {
             }
             ValueUpdatedBy::Action(v) => write!(f, "Updated by action with name'{}'", v),
             ValueUpdatedBy::DefaultValue => write!(f, "Updated by default value"),
+            ValueUpdatedBy::Source(v) => write!(f, "Updated by variable source '{}'", v),
+            ValueUpdatedBy::Prompt => write!(f, "Updated by the answer to a prompt"),
 
             #[cfg(test)]
             ValueUpdatedBy::ForTest => write!(f, "for testing"),
@@ -110,8 +125,31 @@ impl fmt::Display for VariableScope {
     }
 }
 
+/// When the value of a variable's env is read.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum EnvMode {
+    /// The env is read once, when the variables are realized after parsing.
+    #[default]
+    Snapshot,
+    /// The env is re-read each time the variable is resolved so changes made
+    /// by earlier actions are picked up.
+    Live,
+}
+
+impl fmt::Display for EnvMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvMode::Snapshot => write!(f, "snapshot"),
+            EnvMode::Live => write!(f, "live"),
+        }
+    }
+}
+
+/// Shown and recorded in place of the value of a secret.
+pub const SECRET_MASK: &str = "********";
+
 /// A Context holding a variable
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct ValueContext {
     pub value: String,
     pub updated_by: ValueUpdatedBy,
@@ -129,29 +167,167 @@ impl ValueContext {
 #[derive(Default, Debug, PartialEq)]
 pub struct VariableEntry {
     value_ctx: Option<ValueContext>,
+    // the default given in the workflow, kept once another source sets the value
+    default: Option<String>,
     env: Option<String>,
+    env_mode: EnvMode,
     cli_flag: Option<String>,
     readers: VariableScope,
     writers: VariableScope,
+    // the group used to namespace the variable, e.g. "database"
+    group: Option<String>,
+    // the name the variable is bound to in the workflow, known once parsed
+    name: Option<String>,
+    // if set the variable is deprecated, the value is a hint for what to use instead
+    deprecated: Option<String>,
+    // consts have a fixed value which can not be set by any means
+    constant: bool,
+    // the value of a secret is never shown and is redacted from output
+    secret: bool,
+    // a required variable without a value is asked for before the run
+    required: bool,
+    // what to ask for the value of a required variable with
+    prompt: Option<String>,
+    // describes the variable in the workflow's --help
+    doc: Option<String>,
 }
 
 impl VariableEntry {
-    fn from_starlark(
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn from_starlark(
         default: Option<&str>,
         env: Option<&str>,
+        env_mode: Option<&str>,
         cli_flag: Option<&str>,
         readers: Option<ListOf<String>>,
         writers: Option<ListOf<String>>,
+        group: Option<&str>,
+        deprecated: Option<&str>,
     ) -> anyhow::Result<Self> {
         Ok(VariableEntry {
+            constant: false,
+            secret: false,
+            required: false,
+            prompt: None,
+            doc: None,
+            deprecated: deprecated.map(|d| d.to_string()),
+            group: VariableEntry::validate_group(group)?,
+            name: None,
             env: VariableEntry::validate_env(env)?,
+            env_mode: VariableEntry::validate_env_mode(env_mode, env)?,
             cli_flag: VariableEntry::validate_cli_flag(cli_flag)?,
             readers: VariableEntry::validate_scope(readers.map(|v| v.to_vec()))?,
             writers: VariableEntry::validate_scope(writers.map(|v| v.to_vec()))?,
             value_ctx: default.map(|d| ValueContext::new(d, ValueUpdatedBy::DefaultValue)),
+            default: default.map(|d| d.to_string()),
+        })
+    }
+
+    /// Creates a const, which always has the given value.
+    pub(crate) fn constant(value: String) -> Self {
+        VariableEntry {
+            value_ctx: Some(ValueContext::new(
+                value.clone(),
+                ValueUpdatedBy::DefaultValue,
+            )),
+            default: Some(value),
+            constant: true,
+            ..VariableEntry::default()
+        }
+    }
+
+    pub fn is_const(&self) -> bool {
+        self.constant
+    }
+
+    /// Makes the variable a secret.
+    pub(crate) fn into_secret(self) -> Self {
+        VariableEntry {
+            secret: true,
+            ..self
+        }
+    }
+
+    pub fn is_secret(&self) -> bool {
+        self.secret
+    }
+
+    /// Makes the variable required if `required` is set, asking for its
+    /// value with `prompt` if given.
+    pub(crate) fn with_required(
+        self,
+        required: bool,
+        prompt: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        if let Some(prompt) = prompt {
+            if !required {
+                bail!(StdlibError::new_invalid_attr(
+                    "prompt",
+                    "requires required = True",
+                    prompt
+                ));
+            }
+            if prompt.trim().is_empty() {
+                bail!(StdlibError::new_invalid_attr(
+                    "prompt",
+                    "cannot be empty",
+                    prompt
+                ));
+            }
+        }
+        Ok(VariableEntry {
+            required,
+            prompt: prompt.map(|p| p.to_string()),
+            ..self
+        })
+    }
+
+    /// Sets the description of the variable shown by `run --help`.
+    pub(crate) fn with_doc(self, doc: Option<&str>) -> anyhow::Result<Self> {
+        if let Some(doc) = doc {
+            if doc.trim().is_empty() {
+                bail!(StdlibError::new_invalid_attr("doc", "cannot be empty", doc));
+            }
+        }
+        Ok(VariableEntry {
+            doc: doc.map(|d| d.to_string()),
+            ..self
         })
     }
 
+    pub fn doc(&self) -> Option<String> {
+        self.doc.clone()
+    }
+
+    /// The default given in the workflow, if any.
+    pub fn default_value(&self) -> Option<String> {
+        self.default.clone()
+    }
+
+    pub fn is_required(&self) -> bool {
+        self.required
+    }
+
+    /// What to ask for the value of the variable with, by default naming it.
+    pub fn prompt(&self) -> String {
+        match &self.prompt {
+            Some(prompt) => prompt.clone(),
+            None => format!(
+                "Value for {}",
+                self.qualified_name()
+                    .unwrap_or_else(|| "<unnamed>".to_string())
+            ),
+        }
+    }
+
+    /// The error for an attempt to set a const.
+    pub fn const_error(&self) -> VariableResolverError {
+        VariableResolverError::ConstValue(
+            self.qualified_name()
+                .unwrap_or_else(|| "<unnamed>".to_string()),
+        )
+    }
+
     pub fn update_value<T: Into<String>>(&mut self, val: T, updated_by: ValueUpdatedBy) {
         self.value_ctx = Some(ValueContext::new(val, updated_by));
     }
@@ -168,6 +344,10 @@ impl VariableEntry {
         self.env.clone()
     }
 
+    pub fn env_mode(&self) -> EnvMode {
+        self.env_mode
+    }
+
     pub fn cli_flag(&self) -> Option<String> {
         self.cli_flag.clone()
     }
@@ -180,16 +360,64 @@ impl VariableEntry {
         self.writers.clone()
     }
 
+    pub fn group(&self) -> Option<String> {
+        self.group.clone()
+    }
+
+    pub fn deprecated(&self) -> Option<String> {
+        self.deprecated.clone()
+    }
+
+    /// The warning to show when a deprecated variable is used.
+    pub fn deprecation_warning(&self) -> Option<String> {
+        let hint = self.deprecated.as_ref()?;
+        Some(format!(
+            "variable '{}' is deprecated: {}",
+            self.qualified_name()
+                .unwrap_or_else(|| "<unnamed>".to_string()),
+            hint
+        ))
+    }
+
+    pub fn name(&self) -> Option<String> {
+        self.name.clone()
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        self.name = Some(name.to_string());
+    }
+
+    /// Returns the name used to refer to the variable from the command line,
+    /// `group.name` for grouped variables. None until the name is known.
+    pub fn qualified_name(&self) -> Option<String> {
+        let name = self.name.as_ref()?;
+        Some(match &self.group {
+            Some(group) => format!("{}.{}", group, name),
+            None => name.clone(),
+        })
+    }
+
     #[cfg(test)]
     pub fn for_test(default: Option<&str>, cli_flag: Option<&str>, env: Option<&str>) -> Self {
         VariableEntry {
             env: env.map(|v| v.to_string()),
             cli_flag: cli_flag.map(|v| v.to_string()),
             value_ctx: default.map(|v| ValueContext::new(v, ValueUpdatedBy::ForTest)),
+            default: default.map(|v| v.to_string()),
             ..VariableEntry::default()
         }
     }
 
+    #[cfg(test)]
+    pub fn set_env_mode(&mut self, env_mode: EnvMode) {
+        self.env_mode = env_mode;
+    }
+
+    #[cfg(test)]
+    pub fn set_deprecated(&mut self, hint: &str) {
+        self.deprecated = Some(hint.to_string());
+    }
+
     fn validate_env(env: Option<&str>) -> anyhow::Result<Option<String>> {
         if let Some(env) = env {
             if env.is_empty() {
@@ -207,6 +435,23 @@ impl VariableEntry {
         Ok(None)
     }
 
+    fn validate_env_mode(env_mode: Option<&str>, env: Option<&str>) -> anyhow::Result<EnvMode> {
+        match env_mode {
+            None | Some("snapshot") => Ok(EnvMode::Snapshot),
+            Some("live") if env.is_none() => bail!(StdlibError::new_invalid_attr(
+                "env_mode",
+                "requires env to be set",
+                "live"
+            )),
+            Some("live") => Ok(EnvMode::Live),
+            Some(mode) => bail!(StdlibError::new_invalid_attr(
+                "env_mode",
+                "must be one of 'snapshot' or 'live'",
+                mode
+            )),
+        }
+    }
+
     fn validate_cli_flag(cli_flag: Option<&str>) -> anyhow::Result<Option<String>> {
         if let Some(flag) = cli_flag {
             if flag.is_empty() {
@@ -244,6 +489,27 @@ impl VariableEntry {
         Ok(None)
     }
 
+    fn validate_group(group: Option<&str>) -> anyhow::Result<Option<String>> {
+        if let Some(group) = group {
+            if group.is_empty() {
+                bail!(StdlibError::new_invalid_attr(
+                    "group",
+                    "cannot be empty",
+                    group
+                ));
+            }
+            if group.contains(" ") || group.contains(".") {
+                bail!(StdlibError::new_invalid_attr(
+                    "group",
+                    "cannot contain spaces or periods",
+                    group
+                ));
+            }
+            return Ok(Some(group.to_string()));
+        }
+        Ok(None)
+    }
+
     fn validate_scope(scopes: Option<Vec<String>>) -> anyhow::Result<VariableScope> {
         if let Some(scopes) = scopes {
             for scope in &scopes {
@@ -282,6 +548,63 @@ impl VariableEntry {
         Ok(())
     }
 
+    /// Re-reads the env of a variable in live env mode. Values set explicitly,
+    /// from the command line, a variable source or by an action, are kept.
+    /// Returns true if the value was updated.
+    pub fn refresh_from_live_env(&mut self) -> bool {
+        if self.env_mode != EnvMode::Live {
+            return false;
+        }
+        let explicit = self.value_ctx.as_ref().is_some_and(|ctx| {
+            !matches!(
+                ctx.updated_by,
+                ValueUpdatedBy::EnvironmentVariable(_) | ValueUpdatedBy::DefaultValue
+            )
+        });
+        !explicit && self.try_update_value_from_env().is_ok()
+    }
+
+    /// Updates the value from a `--var name=value` argument where the name
+    /// is the qualified name of the variable.
+    pub fn try_update_value_from_var_arg(&mut self, args: &[String]) -> anyhow::Result<()> {
+        let name = match self.qualified_name() {
+            Some(name) => name,
+            None => bail!("Cannot update from --var: the variable is not bound to a name"),
+        };
+        match VariableEntry::find_var_arg_value(&name, args) {
+            Some(value) => {
+                self.update_value(value, ValueUpdatedBy::CLIFlag(format!("--var {}", name)));
+                Ok(())
+            }
+            None => bail!("Cannot update from --var: '{}' is not in args", name),
+        }
+    }
+
+    /// Returns true if a `--var name=value` argument sets the variable.
+    pub fn is_set_by_var_arg(&self, args: &[String]) -> bool {
+        self.qualified_name()
+            .is_some_and(|name| VariableEntry::find_var_arg_value(&name, args).is_some())
+    }
+
+    fn find_var_arg_value(name: &str, workflow_args: &[String]) -> Option<String> {
+        let mut value = None;
+        let mut iter = workflow_args.iter();
+        while let Some(arg) = iter.next() {
+            let assignment = match arg.strip_prefix("--var=") {
+                Some(assignment) => Some(assignment),
+                None if arg == "--var" => iter.next().map(|s| s.as_str()),
+                None => None,
+            };
+            if let Some((key, val)) = assignment.and_then(|a| a.split_once('=')) {
+                if key == name {
+                    // the last assignment wins
+                    value = Some(val.to_string());
+                }
+            }
+        }
+        value
+    }
+
     pub fn try_update_value_from_cli_flag(&mut self, args: &Vec<String>) -> anyhow::Result<()> {
         if let Some(cli_flag) = &self.cli_flag {
             if let Some(value) = VariableEntry::find_cli_flag_value(cli_flag, args) {
@@ -295,6 +618,69 @@ impl VariableEntry {
         Ok(())
     }
 
+    /// Returns the variable as IR, failing if it is not bound to a name or
+    /// it sets what the IR can not express.
+    pub fn compile(&self) -> anyhow::Result<VariableIr> {
+        let name = match &self.name {
+            Some(name) => name.clone(),
+            None => bail!("a variable which is not bound to a name can not be compiled"),
+        };
+        let unsupported = [
+            ("const", self.constant),
+            ("secret", self.secret),
+            ("deprecated", self.deprecated.is_some()),
+            ("env_mode", self.env_mode == EnvMode::Live),
+            ("readers", self.readers != VariableScope::Global),
+            ("writers", self.writers != VariableScope::Global),
+        ];
+        if let Some((attr, _)) = unsupported.iter().find(|(_, set)| *set) {
+            bail!(
+                "variable '{}' sets {}, which can not be compiled",
+                self.qualified_name().unwrap_or_default(),
+                attr
+            );
+        }
+        Ok(VariableIr {
+            name,
+            group: self.group.clone(),
+            default: self.default.clone(),
+            env: self.env.clone(),
+            cli_flag: self.cli_flag.clone(),
+            doc: self.doc.clone(),
+            required: self.required,
+            prompt: self.prompt.clone(),
+        })
+    }
+
+    /// Returns the value each of the builtin sources would give the variable,
+    /// None if it has none, in the order they are tried and along with how
+    /// the source would set it. The env is read as it is now.
+    pub fn source_values(&self, workflow_args: &[String]) -> Vec<(ValueUpdatedBy, Option<String>)> {
+        let mut values = vec![];
+        if !self.constant {
+            if let Some(name) = self.qualified_name() {
+                values.push((
+                    ValueUpdatedBy::CLIFlag(format!("--var {}", name)),
+                    VariableEntry::find_var_arg_value(&name, workflow_args),
+                ));
+            }
+            if let Some(flag) = &self.cli_flag {
+                values.push((
+                    ValueUpdatedBy::CLIFlag(flag.clone()),
+                    VariableEntry::find_cli_flag_value(flag, &workflow_args.to_vec()),
+                ));
+            }
+            if let Some(key) = &self.env {
+                values.push((
+                    ValueUpdatedBy::EnvironmentVariable(key.clone()),
+                    std::env::var(key).ok(),
+                ));
+            }
+        }
+        values.push((ValueUpdatedBy::DefaultValue, self.default.clone()));
+        values
+    }
+
     fn find_cli_flag_value(flag: &str, workflow_args: &Vec<String>) -> Option<String> {
         let mut iter = workflow_args.into_iter();
         while let Some(val) = iter.next() {
@@ -332,12 +718,156 @@ variable(
   readers =  ["foo", "bar"],
   writers =  ["foo", "bar"],
   env =  "VAR_TWO",
+  env_mode = "live",
   cli_flag = "--foo",
+  group = "database",
+  deprecated = "use db_url instead",
 )
 "#,
         );
     }
 
+    #[test]
+    fn test_const() {
+        assert_env().eq("type(const(value = 'a'))", "'variable_ref'");
+        assert_env().pass("const(value = 1)");
+        assert_env().pass("const(value = True)");
+        assert_env().fail(
+            "const(value = [])",
+            "expected string, int or bool for 'value' in const definition, got list",
+        );
+        assert_env().fail("const(value = 'a', env = 'A')", "env");
+    }
+
+    #[test]
+    fn test_secret() {
+        assert_env().eq("type(secret(env = 'TOKEN'))", "'variable_ref'");
+        assert_env().pass("secret(cli_flag = '--token', group = 'registry')");
+        assert_env().fail("secret(default = 'hunter2')", "default");
+
+        let var = VariableEntry::for_test(None, None, Some("TOKEN")).into_secret();
+        assert!(var.is_secret());
+        assert!(!VariableEntry::for_test(None, None, None).is_secret());
+    }
+
+    #[test]
+    fn test_required() {
+        assert_env().pass("variable(required = True)");
+        assert_env().pass("variable(required = True, prompt = 'Which host?')");
+        assert_env().fail(
+            "variable(prompt = 'Which host?')",
+            "Invalid attribute 'prompt', requires required = True",
+        );
+        assert_env().fail(
+            "variable(required = True, prompt = ' ')",
+            "Invalid attribute 'prompt', cannot be empty",
+        );
+
+        let mut var = VariableEntry::for_test(None, None, None)
+            .with_required(true, None)
+            .unwrap();
+        var.set_name("host");
+        assert!(var.is_required());
+        assert_eq!(var.prompt(), "Value for host");
+    }
+
+    #[test]
+    fn test_doc() {
+        assert_env().pass("variable(doc = 'The host to deploy to')");
+        assert_env().pass("secret(doc = 'The registry token')");
+        assert_env().fail(
+            "variable(doc = '')",
+            "Invalid attribute 'doc', cannot be empty",
+        );
+
+        let var = VariableEntry::for_test(None, None, None)
+            .with_doc(Some("The host"))
+            .unwrap();
+        assert_eq!(var.doc(), Some("The host".to_string()));
+    }
+
+    #[test]
+    fn test_invalid_group() {
+        assert!(VariableEntry::validate_group(Some("")).is_err());
+        assert!(VariableEntry::validate_group(Some("a b")).is_err());
+        assert!(VariableEntry::validate_group(Some("a.b")).is_err());
+        assert_eq!(
+            VariableEntry::validate_group(Some("database")).unwrap(),
+            Some("database".to_string())
+        );
+    }
+
+    #[test]
+    fn test_qualified_name() {
+        let mut var = VariableEntry::default();
+        assert_eq!(var.qualified_name(), None);
+        var.set_name("host");
+        assert_eq!(var.qualified_name(), Some("host".to_string()));
+        var.group = Some("database".to_string());
+        assert_eq!(var.qualified_name(), Some("database.host".to_string()));
+    }
+
+    #[test]
+    fn test_try_update_value_from_var_arg() {
+        let mut var = VariableEntry::for_test(Some("default"), None, None);
+        var.group = Some("database".to_string());
+        var.set_name("host");
+
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert!(var
+            .try_update_value_from_var_arg(&args(&["--var", "host=a"]))
+            .is_err());
+        assert_eq!(var.value().unwrap(), "default");
+
+        var.try_update_value_from_var_arg(&args(&[
+            "--var",
+            "database.host=a",
+            "--var=database.host=b=c",
+        ]))
+        .unwrap();
+        assert_eq!(var.value().unwrap(), "b=c");
+        assert_eq!(
+            var.value_ctx().unwrap().updated_by,
+            ValueUpdatedBy::CLIFlag("--var database.host".to_string())
+        );
+    }
+
+    #[test]
+    fn test_source_values() {
+        let _env = TempEnvVar::new("SOURCE_VALUES_HOST", "env");
+        let mut var =
+            VariableEntry::for_test(Some("default"), Some("--host"), Some("SOURCE_VALUES_HOST"));
+        var.set_name("host");
+
+        let args = vec!["--host".to_string(), "flag".to_string()];
+        assert_eq!(
+            var.source_values(&args),
+            vec![
+                (ValueUpdatedBy::CLIFlag("--var host".to_string()), None),
+                (
+                    ValueUpdatedBy::CLIFlag("--host".to_string()),
+                    Some("flag".to_string())
+                ),
+                (
+                    ValueUpdatedBy::EnvironmentVariable("SOURCE_VALUES_HOST".to_string()),
+                    Some("env".to_string())
+                ),
+                (ValueUpdatedBy::DefaultValue, Some("default".to_string())),
+            ]
+        );
+
+        // the default is kept once another source sets the value
+        var.try_update_value_from_env().unwrap();
+        assert_eq!(
+            VariableEntry::constant("a".to_string()).source_values(&args),
+            vec![(ValueUpdatedBy::DefaultValue, Some("a".to_string()))]
+        );
+        assert_eq!(
+            var.source_values(&[]).last(),
+            Some(&(ValueUpdatedBy::DefaultValue, Some("default".to_string())))
+        );
+    }
+
     #[test]
     fn test_variable_ref_type() {
         assert_env().eq("type(variable())", "'variable_ref'");
@@ -412,6 +942,50 @@ variable(
         VariableEntry::validate_env(Some(" ")).unwrap();
     }
 
+    #[test]
+    fn validate_env_mode() {
+        assert_eq!(
+            VariableEntry::validate_env_mode(None, None).unwrap(),
+            EnvMode::Snapshot
+        );
+        assert_eq!(
+            VariableEntry::validate_env_mode(Some("snapshot"), None).unwrap(),
+            EnvMode::Snapshot
+        );
+        assert_eq!(
+            VariableEntry::validate_env_mode(Some("live"), Some("FOO")).unwrap(),
+            EnvMode::Live
+        );
+        assert!(VariableEntry::validate_env_mode(Some("live"), None).is_err());
+        assert!(VariableEntry::validate_env_mode(Some("lazy"), Some("FOO")).is_err());
+    }
+
+    #[test]
+    fn test_refresh_from_live_env() {
+        let key = "ENV_VAR_FOR_test_refresh_from_live_env";
+        let mut var = VariableEntry::for_test(None, None, Some(key));
+        var.update_value("default", ValueUpdatedBy::DefaultValue);
+        assert!(!var.refresh_from_live_env());
+
+        var.set_env_mode(EnvMode::Live);
+        assert!(!var.refresh_from_live_env());
+        assert_eq!(var.value().unwrap(), "default");
+
+        let env = TempEnvVar::new(key, "first");
+        assert!(var.refresh_from_live_env());
+        assert_eq!(var.value().unwrap(), "first");
+        drop(env);
+
+        let _env = TempEnvVar::new(key, "second");
+        assert!(var.refresh_from_live_env());
+        assert_eq!(var.value().unwrap(), "second");
+
+        // explicitly set values are not replaced
+        var.update_value("action", ValueUpdatedBy::Action("a".to_string()));
+        assert!(!var.refresh_from_live_env());
+        assert_eq!(var.value().unwrap(), "action");
+    }
+
     #[test]
     fn test_try_update_value_from_env_success() {
         let env = TempEnvVar::new(
@@ -1,10 +1,15 @@
+use crate::stdlib::artifact::Artifact;
 use crate::stdlib::format::ValueFormatter;
-use crate::stdlib::VariableRef;
+use crate::stdlib::glob::Glob;
+use crate::stdlib::inline_file::InlineFile;
+use crate::stdlib::ir::{variable_name, ArgIr};
+use crate::stdlib::run_context::RunContext;
+use crate::stdlib::{ValueContext, VariableRef};
 use allocative::Allocative;
 use anyhow::bail;
 use starlark::values::ProvidesStaticType;
 use starlark::values::Value;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use thiserror::Error;
 
 pub fn string_from_value<V: VariableResolver>(
@@ -15,6 +20,12 @@ pub fn string_from_value<V: VariableResolver>(
         formatter.fmt(resolver)
     } else if let Some(var_ref) = VariableRef::from_value(value) {
         resolver.resolve(var_ref.identifier())
+    } else if let Some(file) = InlineFile::from_value(value) {
+        Ok(file.write(resolver)?.to_string_lossy().into_owned())
+    } else if Glob::from_value(value).is_some() {
+        bail!("a glob expands to a list of paths and can only be used as an action arg")
+    } else if Artifact::from_value(value).is_some() {
+        bail!("an artifact expands to a list of paths and can only be used as an action arg")
     } else {
         Ok(value.to_str())
     }
@@ -26,8 +37,14 @@ pub enum VariableResolverError {
     UnknownVariable(String),
     #[error("Variable with id '{0}' has no value")]
     NoValueSet(String),
+    #[error("cannot set const '{0}'")]
+    ConstValue(String),
 }
 
+/// The values of the named variables, along with how they were set, keyed
+/// by their qualified name. Used to checkpoint a run between nodes.
+pub type VariableSnapshot = BTreeMap<String, ValueContext>;
+
 /// A trait which is used to resolve a variable's value based on
 /// an identifier.
 pub trait VariableResolver {
@@ -35,6 +52,36 @@ pub trait VariableResolver {
     /// known return VariableResolverError::UnknownVariable and if there
     /// is no value set for the variable return VariableResolverError::NoValueSet
     fn resolve(&self, identifier: &str) -> anyhow::Result<String>;
+
+    /// Returns the value the variable had before any node ran, which is
+    /// what the variables of a nested graph start from.
+    fn initial_value(&self, identifier: &str) -> anyhow::Result<String> {
+        self.resolve(identifier)
+    }
+
+    /// Returns the current values of all of the variables, with the values
+    /// of the secrets masked, None if the resolver does not support
+    /// snapshots.
+    fn snapshot(&self) -> Option<VariableSnapshot> {
+        None
+    }
+
+    /// Returns the current values of all of the variables like `snapshot`
+    /// but with the values of the secrets, which must not be recorded.
+    fn values(&self) -> Option<VariableSnapshot> {
+        self.snapshot()
+    }
+
+    /// Returns the name of the variable to use in error messages.
+    fn variable_name(&self, _identifier: &str) -> Option<String> {
+        None
+    }
+
+    /// Returns what the run gives the nodes and actions it runs, the
+    /// default context if the resolver is not running a workflow.
+    fn context(&self) -> RunContext<'_> {
+        RunContext::default()
+    }
 }
 
 impl VariableResolver for HashMap<&str, &str> {
@@ -63,6 +110,16 @@ impl VariableResolver for &str {
 
 pub trait VariableUpdater {
     fn update(&self, identifier: &str, value: String) -> anyhow::Result<()>;
+
+    /// Applies all of the updates or, if any of them fails, none of them.
+    /// The default implementation applies the updates one at a time so
+    /// implementors which can fail part way through should override it.
+    fn update_all(&self, updates: Vec<(String, String)>) -> anyhow::Result<()> {
+        for (identifier, value) in updates {
+            self.update(&identifier, value)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, ProvidesStaticType, Allocative, Clone)]
@@ -96,6 +153,18 @@ impl LateBoundString {
             OneOf::ValueFormatter(vf) => vf.fmt(resolver),
         }
     }
+
+    /// Returns the string as IR, None for a format.
+    pub(crate) fn compile<V: VariableResolver>(
+        &self,
+        resolver: &V,
+    ) -> anyhow::Result<Option<ArgIr>> {
+        match &self.0 {
+            OneOf::Value(s) => Ok(Some(ArgIr::Literal(s.clone()))),
+            OneOf::Identifier(id) => Ok(Some(ArgIr::Variable(variable_name(resolver, id)?))),
+            OneOf::ValueFormatter(_) => Ok(None),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,161 @@
+use std::path::Path;
+use wasmtime::{Config, Engine, Linker, Module, Store};
+use wasmtime_wasi::pipe::MemoryOutputPipe;
+use wasmtime_wasi::preview1::{self, WasiP1Ctx};
+use wasmtime_wasi::{DirPerms, FilePerms, I32Exit, WasiCtxBuilder};
+
+/// The captured output of a WASI module run.
+pub(crate) struct WasmOutput {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub exit_code: i32,
+}
+
+/// Runs the WASI module at the given path to completion. The module sees the
+/// working directory preopened as "." and cannot touch the rest of the
+/// filesystem. Like a process it is given the `vars` on top of the
+/// environment of `workflow`, which it only sees if it `inherit`s it.
+pub(crate) fn run_wasm_module(
+    module: &Path,
+    args: &[String],
+    working_dir: &Path,
+    inherit: bool,
+    vars: &[(String, String)],
+) -> anyhow::Result<WasmOutput> {
+    let engine = Engine::new(&Config::new())?;
+    let compiled = Module::from_file(&engine, module)?;
+
+    let mut linker: Linker<WasiP1Ctx> = Linker::new(&engine);
+    preview1::add_to_linker_sync(&mut linker, |ctx| ctx)?;
+
+    let program = module
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let stdout = MemoryOutputPipe::new(usize::MAX);
+    let stderr = MemoryOutputPipe::new(usize::MAX);
+    let wasi = WasiCtxBuilder::new()
+        .arg(program)
+        .args(args)
+        .envs(&module_env(inherit, vars))
+        .stdout(stdout.clone())
+        .stderr(stderr.clone())
+        .preopened_dir(working_dir, ".", DirPerms::all(), FilePerms::all())?
+        .build_p1();
+
+    let mut store = Store::new(&engine, wasi);
+    linker.module(&mut store, "", &compiled)?;
+    let start = linker
+        .get_default(&mut store, "")?
+        .typed::<(), ()>(&store)?;
+
+    let exit_code = match start.call(&mut store, ()) {
+        Ok(()) => 0,
+        Err(e) => match e.downcast_ref::<I32Exit>() {
+            Some(exit) => exit.0,
+            None => return Err(e),
+        },
+    };
+    drop(store);
+
+    Ok(WasmOutput {
+        stdout: stdout.contents().to_vec(),
+        stderr: stderr.contents().to_vec(),
+        exit_code,
+    })
+}
+
+/// Returns the environment of the module. Unlike a process a module sees
+/// each time a variable is given, so the ones set on top replace those it
+/// inherits and the last value of a variable set twice wins.
+fn module_env(inherit: bool, vars: &[(String, String)]) -> Vec<(String, String)> {
+    let mut env: Vec<(String, String)> = match inherit {
+        // the variables which are not unicode can not be given to the module
+        true => std::env::vars_os()
+            .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+            .collect(),
+        false => vec![],
+    };
+    for (name, value) in vars {
+        env.retain(|(n, _)| n != name);
+        env.push((name.clone(), value.clone()));
+    }
+    env
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib::test_utils::TempWorkflowFile;
+
+    // Writes "hello\n" to stdout and exits with code 3.
+    const HELLO_WAT: &str = r#"
+(module
+  (import "wasi_snapshot_preview1" "fd_write"
+    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 8) "hello\n")
+  (func (export "_start")
+    (i32.store (i32.const 0) (i32.const 8))
+    (i32.store (i32.const 4) (i32.const 6))
+    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 20)))
+    (call $proc_exit (i32.const 3))))
+"#;
+
+    // Writes its environment to stdout, each variable followed by a NUL.
+    const ENV_WAT: &str = r#"
+(module
+  (import "wasi_snapshot_preview1" "environ_sizes_get"
+    (func $environ_sizes_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "environ_get"
+    (func $environ_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "fd_write"
+    (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 16)
+  (func (export "_start")
+    (drop (call $environ_sizes_get (i32.const 0) (i32.const 4)))
+    (drop (call $environ_get (i32.const 1024) (i32.const 65536)))
+    (i32.store (i32.const 16) (i32.const 65536))
+    (i32.store (i32.const 20) (i32.load (i32.const 4)))
+    (drop (call $fd_write (i32.const 1) (i32.const 16) (i32.const 1) (i32.const 24)))))
+"#;
+
+    #[test]
+    fn test_run_wasm_module() {
+        let file = TempWorkflowFile::new("module.wasm", HELLO_WAT).unwrap();
+        let working_dir = file.dir();
+        let output = run_wasm_module(&file.path(), &[], &working_dir, true, &[]).unwrap();
+        assert_eq!(output.exit_code, 3);
+        assert_eq!(output.stdout, b"hello\n");
+        assert!(output.stderr.is_empty());
+    }
+
+    #[test]
+    fn test_run_wasm_module_invalid_module() {
+        let file = TempWorkflowFile::new("module.wasm", "not a module").unwrap();
+        let working_dir = file.dir();
+        assert!(run_wasm_module(&file.path(), &[], &working_dir, true, &[]).is_err());
+    }
+
+    #[test]
+    fn test_run_wasm_module_env() {
+        let file = TempWorkflowFile::new("module.wasm", ENV_WAT).unwrap();
+        let working_dir = file.dir();
+        let vars = [
+            ("A".to_string(), "1".to_string()),
+            ("B".to_string(), "2".to_string()),
+            ("A".to_string(), "3".to_string()),
+        ];
+        let env = |inherit: bool| {
+            let output = run_wasm_module(&file.path(), &[], &working_dir, inherit, &vars).unwrap();
+            String::from_utf8(output.stdout).unwrap()
+        };
+        assert_eq!(env(false), "B=2\0A=3\0");
+
+        let inherited = env(true);
+        let path = std::env::var("PATH").unwrap();
+        assert!(inherited.contains(&format!("PATH={}\0", path)));
+        assert!(inherited.ends_with("B=2\0A=3\0"));
+    }
+}
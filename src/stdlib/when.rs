@@ -0,0 +1,142 @@
+use crate::stdlib::errors::{StdlibError, ValueError};
+use crate::stdlib::ir::{variable_name, NextIr};
+use crate::stdlib::variable_resolver::VariableResolver;
+use crate::stdlib::VariableRef;
+use crate::stdlib::{VARIABLE_REF_TYPE, WHEN_TYPE};
+use allocative::Allocative;
+use anyhow::bail;
+use starlark::starlark_simple_value;
+use starlark::values::starlark_value;
+use starlark::values::NoSerialize;
+use starlark::values::ProvidesStaticType;
+use starlark::values::StarlarkValue;
+use starlark::values::Value;
+use std::fmt;
+
+pub(crate) fn when_impl(
+    variable: Value,
+    equals: &str,
+    then: &str,
+    else_: Option<&str>,
+) -> anyhow::Result<When> {
+    ValueError::check_type("when", "variable", VARIABLE_REF_TYPE, variable)?;
+    for (attr, target) in [("then", Some(then)), ("else_", else_)] {
+        if target == Some("") {
+            bail!(StdlibError::new_invalid_attr(
+                attr,
+                "must be the name of a node",
+                "\"\""
+            ));
+        }
+    }
+    let identifier = VariableRef::from_value(variable)
+        .expect("Should be a variable")
+        .identifier()
+        .to_string();
+    Ok(When {
+        identifier,
+        equals: equals.to_string(),
+        then: then.to_string(),
+        else_: else_.map(String::from),
+    })
+}
+
+/// A next which picks the node to run after this one by comparing the
+/// value a variable has once the node ran, without a starlark function.
+/// Without an `else_` the workflow stops when the value does not match.
+#[derive(Debug, ProvidesStaticType, NoSerialize, Allocative, Clone)]
+pub struct When {
+    identifier: String,
+    equals: String,
+    then: String,
+    else_: Option<String>,
+}
+starlark_simple_value!(When);
+
+#[starlark_value(type = WHEN_TYPE)]
+impl<'v> StarlarkValue<'v> for When {}
+
+impl When {
+    /// Returns the name of the node to run next, None to stop.
+    pub fn next<T: VariableResolver>(&self, resolver: &T) -> anyhow::Result<Option<String>> {
+        match resolver.resolve(&self.identifier)? == self.equals {
+            true => Ok(Some(self.then.clone())),
+            false => Ok(self.else_.clone()),
+        }
+    }
+
+    pub fn compile<T: VariableResolver>(&self, resolver: &T) -> anyhow::Result<NextIr> {
+        Ok(NextIr::When {
+            variable: variable_name(resolver, &self.identifier)?,
+            equals: self.equals.clone(),
+            then: self.then.clone(),
+            else_: self.else_.clone(),
+        })
+    }
+
+    /// The names of the nodes it can go to.
+    pub fn targets(&self) -> Vec<&str> {
+        std::iter::once(self.then.as_str())
+            .chain(self.else_.as_deref())
+            .collect()
+    }
+
+    /// The nodes it can go to, each with the comparison which leads there.
+    pub fn edges(&self) -> Vec<(&str, String)> {
+        std::iter::once((self.then.as_str(), format!("== {:?}", self.equals)))
+            .chain(
+                self.else_
+                    .as_deref()
+                    .map(|else_| (else_, format!("!= {:?}", self.equals))),
+            )
+            .collect()
+    }
+}
+
+impl fmt::Display for When {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "when(equals = {:?}, then = {:?}", self.equals, self.then)?;
+        if let Some(else_) = &self.else_ {
+            write!(f, ", else_ = {:?}", else_)?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdlib::test_utils::assert_env;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_when_checks_its_attributes() {
+        assert_env().pass("when(variable = variable(), equals = 'x', then = 'a', else_ = 'b')");
+        assert_env().fail(
+            "when(variable = 'v', equals = 'x', then = 'a')",
+            "expected variable_ref for 'variable' in when definition, got",
+        );
+        assert_env().fail(
+            "when(variable = variable(), equals = 'x', then = 'a', else_ = '')",
+            "Invalid attribute 'else_', must be the name of a node",
+        );
+    }
+
+    #[test]
+    fn test_next() {
+        let when = |else_: Option<&str>| When {
+            identifier: "v".to_string(),
+            equals: "x".to_string(),
+            then: "a".to_string(),
+            else_: else_.map(String::from),
+        };
+        let matching = HashMap::from([("v", "x")]);
+        assert_eq!(when(None).next(&matching).unwrap(), Some("a".to_string()));
+        assert_eq!(when(None).targets(), ["a"]);
+
+        let other = HashMap::from([("v", "y")]);
+        assert_eq!(when(Some("b")).next(&other).unwrap(), Some("b".to_string()));
+        assert_eq!(when(None).next(&other).unwrap(), None);
+        assert_eq!(when(Some("b")).targets(), ["a", "b"]);
+    }
+}
@@ -1,13 +1,27 @@
+use crate::stdlib::env_policy::EnvPolicy;
+use crate::stdlib::errors::{StdlibError, ValueError};
+use crate::stdlib::ir::{VariableIr, WorkflowIr, IR_VERSION};
+use crate::stdlib::locks::LockManager;
+use crate::stdlib::memory::MemorySnapshot;
+use crate::stdlib::plan::NodePlan;
+use crate::stdlib::redact::Redactor;
+use crate::stdlib::run_result::{NodeResult, RunResult};
 use crate::stdlib::variable_resolver::VariableResolver;
 use crate::stdlib::variable_resolver::VariableUpdater;
-use crate::stdlib::Node;
-use crate::stdlib::{NODE_TYPE, WORKFLOW_TYPE};
+use crate::stdlib::{Node, TagFilter};
+use crate::stdlib::{ENV_POLICY_TYPE, NODE_TYPE, WORKFLOW_TYPE};
 use allocative::Allocative;
-use anyhow::bail;
+use anyhow::{anyhow, bail};
 use starlark::coerce::Coerce;
 use starlark::collections::SmallMap;
+use starlark::environment::Methods;
+use starlark::environment::MethodsBuilder;
+use starlark::environment::MethodsStatic;
 use starlark::eval::Evaluator;
 use starlark::starlark_complex_value;
+use starlark::starlark_module;
+use starlark::values::list::ListRef;
+use starlark::values::none::NoneType;
 use starlark::values::starlark_value;
 use starlark::values::Freeze;
 use starlark::values::Freezer;
@@ -21,31 +35,112 @@ use starlark::StarlarkDocs;
 use std::fmt;
 use std::fmt::Display;
 use std::path::PathBuf;
+use std::time::Instant;
+use which::which;
 
 pub(crate) fn workflow_impl<'v>(
     entrypoint: &str,
     nodes: Vec<Value<'v>>,
+    requires: Vec<String>,
+    redact_patterns: Vec<String>,
+    matrix: SmallMap<String, Value<'v>>,
 ) -> anyhow::Result<Workflow<'v>> {
     let mut graph: SmallMap<String, Value<'_>> = SmallMap::new();
     for node in &nodes {
-        if node.get_type() != NODE_TYPE {
-            bail!("graph can only contain node values")
-        }
+        ValueError::check_element_type("workflow", "graph", NODE_TYPE, *node)?;
         let name = Node::from_value(*node)
             .expect("Should be a node")
             .name()
             .to_string();
-        if let Some(_) = graph.insert(name, *node) {
-            bail!("nodes must have unique names")
+        if graph.contains_key(&name) {
+            bail!(ValueError::DuplicateName {
+                definition: "workflow",
+                attr: "graph",
+                name,
+            });
+        }
+        graph.insert(name, *node);
+    }
+
+    for node in graph.values() {
+        let node = Node::from_value(*node).expect("Should be a node");
+        for artifact in node.actions().iter().flat_map(|a| a.artifact_args()) {
+            let producer = graph
+                .get(artifact.node())
+                .and_then(|producer| Node::from_value(*producer));
+            match producer {
+                None => bail!(
+                    "node '{}' uses {} but there is no node named '{}'",
+                    node.name(),
+                    artifact,
+                    artifact.node()
+                ),
+                Some(producer) if producer.artifacts().is_empty() => bail!(
+                    "node '{}' uses {} but node '{}' declares no artifacts",
+                    node.name(),
+                    artifact,
+                    artifact.node()
+                ),
+                Some(_) => {}
+            }
+        }
+        if let Some(when) = node.when() {
+            if let Some(target) = when.targets().into_iter().find(|t| !graph.contains_key(*t)) {
+                bail!(
+                    "node '{}' uses {} but there is no node named '{}'",
+                    node.name(),
+                    when,
+                    target
+                );
+            }
+        }
+    }
+
+    for pattern in &redact_patterns {
+        if let Err(e) = regex::Regex::new(pattern) {
+            bail!(StdlibError::new_invalid_attr(
+                "redact_patterns",
+                &e.to_string(),
+                pattern
+            ));
         }
     }
 
     Ok(Workflow {
         entrypoint: entrypoint.to_string(),
         graph: graph,
+        requires,
+        redact_patterns,
+        matrix: matrix_axes(matrix)?,
+        inputs: vec![],
+        outputs: vec![],
+        env_policy: Value::new_none(),
     })
 }
 
+/// Converts the `matrix`, a dict of variable names to lists of strings,
+/// into its axes.
+fn matrix_axes(matrix: SmallMap<String, Value>) -> anyhow::Result<SmallMap<String, Vec<String>>> {
+    let mut axes = SmallMap::new();
+    for (name, values) in matrix {
+        let list = match ListRef::from_value(values) {
+            Some(list) if !list.is_empty() => list,
+            _ => bail!(StdlibError::new_invalid_attr(
+                "matrix",
+                &format!("'{}' must be a non empty list of strings", name),
+                values.to_repr()
+            )),
+        };
+        let mut strings = vec![];
+        for value in list.iter() {
+            ValueError::check_element_type("workflow", "matrix", "string", value)?;
+            strings.push(value.to_str());
+        }
+        axes.insert(name, strings);
+    }
+    Ok(axes)
+}
+
 #[derive(
     Coerce, Clone, Default, Trace, Debug, ProvidesStaticType, StarlarkDocs, NoSerialize, Allocative,
 )]
@@ -53,13 +148,73 @@ pub(crate) fn workflow_impl<'v>(
 pub struct WorkflowGen<V> {
     entrypoint: String,
     graph: SmallMap<String, V>,
+    // external commands which must be on the PATH to run the workflow
+    requires: Vec<String>,
+    // regexes whose matches are removed from the output of every action
+    redact_patterns: Vec<String>,
+    // the values of the variables the workflow is run with, once for
+    // every combination of them
+    matrix: SmallMap<String, Vec<String>>,
+    // globs of the files copied into the sandbox of a sandboxed run, and of
+    // those copied back out of it once the run succeeds
+    inputs: Vec<String>,
+    outputs: Vec<String>,
+    // which environment variables tools are spawned with, None if they
+    // inherit the environment of workflow
+    env_policy: V,
 }
 starlark_complex_value!(pub Workflow);
 
 #[starlark_value(type = WORKFLOW_TYPE)]
-impl<'v, V: ValueLike<'v> + 'v> StarlarkValue<'v> for WorkflowGen<V> where
-    Self: ProvidesStaticType<'v>
+impl<'v, V: ValueLike<'v> + 'v> StarlarkValue<'v> for WorkflowGen<V>
+where
+    Self: ProvidesStaticType<'v>,
 {
+    fn get_methods() -> Option<&'static Methods> {
+        static RES: MethodsStatic = MethodsStatic::new();
+        RES.methods(workflow_methods)
+    }
+}
+
+fn this_workflow<'v>(this: Value<'v>) -> anyhow::Result<&'v Workflow<'v>> {
+    Workflow::from_value(this)
+        .ok_or_else(|| anyhow!("expected a workflow, got {}", this.get_type()))
+}
+
+/// Lets a workflow check its own graph while it is parsed.
+#[starlark_module]
+fn workflow_methods(builder: &mut MethodsBuilder) {
+    /// The nodes of the graph in the order they were declared.
+    fn nodes<'v>(this: Value<'v>) -> anyhow::Result<Vec<Value<'v>>> {
+        Ok(this_workflow(this)?.graph.values().copied().collect())
+    }
+
+    /// Calls `check` with every node, a node fails the check when it
+    /// returns a string saying what is wrong with it. Fails the parse with
+    /// all of the problems found.
+    fn validate<'v>(
+        this: Value<'v>,
+        check: Value<'v>,
+        eval: &mut Evaluator<'v, '_>,
+    ) -> anyhow::Result<NoneType> {
+        ValueError::check_type("validate", "check", "function", check)?;
+        let workflow = this_workflow(this)?;
+        let mut problems = vec![];
+        for (name, node) in workflow.graph.iter() {
+            let res = eval
+                .eval_function(check, &[*node], &[])
+                .map_err(|e| e.into_anyhow())?;
+            match res.unpack_str() {
+                Some(problem) => problems.push(format!("  node '{}': {}", name, problem)),
+                None if res.is_none() => {}
+                None => bail!("validate check must return a string or None"),
+            }
+        }
+        if !problems.is_empty() {
+            bail!("the workflow failed validation:\n{}", problems.join("\n"));
+        }
+        Ok(NoneType)
+    }
 }
 
 impl<'a> Workflow<'a> {
@@ -89,18 +244,302 @@ impl<'a> Workflow<'a> {
         resolver: &T,
         working_dir: &PathBuf,
         eval: &mut Evaluator<'a, '_>,
-    ) -> anyhow::Result<()> {
-        let mut node: Option<&Node> = Some(self.first_node()?);
+    ) -> anyhow::Result<RunResult> {
+        self.run_from(
+            None,
+            resolver,
+            working_dir,
+            false,
+            &TagFilter::default(),
+            eval,
+        )
+    }
+
+    /// Runs the workflow starting at the node with the given name or
+    /// at the first node if no name is given.
+    ///
+    /// An error while running a node stops the run and is recorded in the
+    /// returned RunResult rather than being returned directly.
+    ///
+    /// If `profile_memory` is set a MemorySnapshot is recorded for every node.
+    /// A snapshot of the variables is recorded for every node when the
+    /// resolver supports it.
+    ///
+    /// The nodes which the `tag_filter` skips are not run and are not in the
+    /// RunResult, the run carries on as if they succeeded without output.
+    pub fn run_from<T: VariableResolver + VariableUpdater>(
+        &self,
+        start_at: Option<&str>,
+        resolver: &T,
+        working_dir: &PathBuf,
+        profile_memory: bool,
+        tag_filter: &TagFilter,
+        eval: &mut Evaluator<'a, '_>,
+    ) -> anyhow::Result<RunResult> {
+        let mut result = RunResult {
+            graph_nodes: self.graph.keys().cloned().collect(),
+            ..Default::default()
+        };
+        let mut node: Option<&Node> = Some(self.start_node(start_at)?);
         while let Some(inner_node) = node {
-            if let Some(res) = inner_node.run(resolver, working_dir, eval)? {
-                node = Some(self.node_with_name(&res)?);
-            } else {
-                node = None
+            if tag_filter.skip_reason(inner_node).is_some() {
+                node = match inner_node.skip(resolver, eval)?.next {
+                    Some(next) => Some(self.node_with_name(&next)?),
+                    None => None,
+                };
+                continue;
+            }
+            if let Some(delegate) = resolver.context().run_delegate {
+                delegate.will_run_node(inner_node.name());
+            }
+            let started = Instant::now();
+            let outcome = match resolver.context().cancel_token {
+                Some(token) if token.is_cancelled() => Err(anyhow!("The run was cancelled")),
+                _ => match LockManager::global().acquire(inner_node.locks()) {
+                    Ok(_guard) => inner_node.run(resolver, working_dir, eval),
+                    Err(e) => Err(e),
+                },
+            };
+            let memory = profile_memory.then(|| MemorySnapshot::take(eval.heap()));
+            // checkpoint the variables so a rerun can resume from this node
+            let variables = resolver.snapshot();
+            match outcome {
+                Ok(outcome) => {
+                    let artifacts = match resolver.context().artifacts_dir {
+                        Some(dir) if outcome.success => {
+                            inner_node.collect_artifacts(working_dir, &dir)
+                        }
+                        _ => Ok(vec![]),
+                    };
+                    let (artifacts, error) = match artifacts {
+                        Ok(artifacts) => (artifacts, None),
+                        Err(e) => (
+                            vec![],
+                            Some(format!("Unable to collect artifacts: {:#}", e)),
+                        ),
+                    };
+                    node = match (&error, outcome.next) {
+                        (None, Some(next)) => Some(self.node_with_name(&next)?),
+                        _ => None,
+                    };
+                    result.nodes.push(NodeResult {
+                        name: inner_node.name().to_string(),
+                        duration: started.elapsed(),
+                        exit_code: Some(outcome.exit_code),
+                        exit_code_ok: Some(outcome.success),
+                        error,
+                        memory,
+                        variables,
+                        envs: outcome.envs,
+                        artifacts,
+                        cached: outcome.cached,
+                        approval: resolver.context().take_approval(inner_node.name()),
+                    });
+                }
+                Err(e) => {
+                    result.nodes.push(NodeResult {
+                        name: inner_node.name().to_string(),
+                        duration: started.elapsed(),
+                        exit_code: None,
+                        exit_code_ok: None,
+                        error: Some(format!("{:#}", e)),
+                        memory,
+                        variables,
+                        envs: vec![],
+                        artifacts: vec![],
+                        cached: false,
+                        approval: resolver.context().take_approval(inner_node.name()),
+                    });
+                    node = None;
+                }
+            }
+            // errors and environments end up in reports and the history, a
+            // setter may have changed the secrets
+            if let (Some(redactor), Some(node)) =
+                (resolver.context().redactor, result.nodes.last_mut())
+            {
+                node.redact(&redactor);
+            }
+            if let (Some(delegate), Some(node)) =
+                (resolver.context().run_delegate, result.nodes.last())
+            {
+                delegate.did_run_node(node);
             }
         }
+        result.redactor = resolver.context().redactor;
+
+        Ok(result)
+    }
+
+    /// Returns the workflow, with its `variables`, as IR. Fails if one of
+    /// its nodes can not be compiled or it sets what the IR can not express.
+    pub fn compile<T: VariableResolver>(
+        &self,
+        variables: Vec<VariableIr>,
+        resolver: &T,
+    ) -> anyhow::Result<WorkflowIr> {
+        let unsupported = [
+            ("requires", !self.requires.is_empty()),
+            ("redact_patterns", !self.redact_patterns.is_empty()),
+            ("matrix", !self.matrix.is_empty()),
+            ("inputs", !self.inputs.is_empty()),
+            ("outputs", !self.outputs.is_empty()),
+        ];
+        if let Some((attr, _)) = unsupported.iter().find(|(_, set)| *set) {
+            bail!("the workflow sets {}, which can not be compiled", attr);
+        }
+        Ok(WorkflowIr {
+            version: IR_VERSION,
+            entrypoint: self.first_node()?.name().to_string(),
+            variables,
+            nodes: self
+                .nodes()
+                .iter()
+                .map(|node| node.compile(resolver))
+                .collect::<anyhow::Result<_>>()?,
+            env_policy: self
+                .env_policy()
+                .map(|policy| policy.compile(resolver))
+                .transpose()?,
+        })
+    }
+
+    /// Returns what running the workflow would do, without running it. The
+    /// node run first comes first and the rest follow in the order they were
+    /// declared, since which of them run is only decided by their nexts.
+    /// The nodes which the `tag_filter` skips are left out.
+    pub fn plan<T: VariableResolver>(
+        &self,
+        start_at: Option<&str>,
+        resolver: &T,
+        working_dir: &PathBuf,
+        tag_filter: &TagFilter,
+    ) -> anyhow::Result<Vec<NodePlan>> {
+        let start = self.start_node(start_at)?;
+        let rest = self
+            .nodes()
+            .into_iter()
+            .filter(|n| n.name() != start.name());
+        Ok(std::iter::once(start)
+            .chain(rest)
+            .filter(|n| tag_filter.skip_reason(n).is_none())
+            .map(|n| n.plan(resolver, working_dir))
+            .collect())
+    }
+
+    /// Returns warnings about a tag filter which does not do what was likely
+    /// meant, e.g. one which skips the node the run starts at.
+    pub fn tag_filter_warnings(
+        &self,
+        start_at: Option<&str>,
+        tag_filter: &TagFilter,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut warnings = vec![];
+        let start = self.start_node(start_at)?;
+        if let Some(reason) = tag_filter.skip_reason(start) {
+            warnings.push(format!(
+                "the run starts at '{}' which is skipped because {}",
+                start.name(),
+                reason
+            ));
+        }
+        for tag in tag_filter.skip.iter().chain(&tag_filter.only) {
+            if !self.nodes().iter().any(|n| n.tags().contains(tag)) {
+                warnings.push(format!("no node is tagged '{}'", tag));
+            }
+        }
+        Ok(warnings)
+    }
+
+    /// Returns the nodes in the graph in the order they were declared.
+    pub fn nodes(&self) -> Vec<&Node<'a>> {
+        self.graph
+            .values()
+            .map(|v| Node::from_value(*v).unwrap())
+            .collect()
+    }
+
+    /// Returns the redactor for the workflow's `redact_patterns`, None if it
+    /// has none.
+    pub fn redactor(&self) -> anyhow::Result<Option<Redactor>> {
+        if self.redact_patterns.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Redactor::new(&self.redact_patterns)?))
+    }
 
+    /// Returns the name of the node the workflow starts at, empty if it was
+    /// not set.
+    pub fn entrypoint(&self) -> &str {
+        &self.entrypoint
+    }
+
+    /// Returns the variables the workflow is run with a value of each, by
+    /// their qualified names.
+    pub fn matrix(&self) -> &SmallMap<String, Vec<String>> {
+        &self.matrix
+    }
+
+    pub(crate) fn with_sandbox(mut self, inputs: Vec<String>, outputs: Vec<String>) -> Self {
+        self.inputs = inputs;
+        self.outputs = outputs;
+        self
+    }
+
+    pub(crate) fn with_env_policy(mut self, env_policy: Option<Value<'a>>) -> anyhow::Result<Self> {
+        if let Some(env_policy) = env_policy {
+            ValueError::check_type("workflow", "env_policy", ENV_POLICY_TYPE, env_policy)?;
+            self.env_policy = env_policy;
+        }
+        Ok(self)
+    }
+
+    /// Returns which environment variables tools are spawned with, None if
+    /// they inherit the environment of `workflow`.
+    pub fn env_policy(&self) -> Option<&'a EnvPolicy> {
+        EnvPolicy::from_value(self.env_policy)
+    }
+
+    pub fn inputs(&self) -> &[String] {
+        &self.inputs
+    }
+
+    pub fn outputs(&self) -> &[String] {
+        &self.outputs
+    }
+
+    pub fn requires(&self) -> &[String] {
+        &self.requires
+    }
+
+    /// Returns the required commands which cannot be found on the PATH.
+    pub fn missing_requirements(&self) -> Vec<&str> {
+        self.requires
+            .iter()
+            .filter(|command| which(command).is_err())
+            .map(|command| command.as_str())
+            .collect()
+    }
+
+    /// Fails with all of the missing required commands at once so they can
+    /// be installed before the run instead of failing part way through.
+    pub fn check_requirements(&self) -> anyhow::Result<()> {
+        let missing = self.missing_requirements();
+        if !missing.is_empty() {
+            bail!(
+                "The workflow requires commands which are not on the PATH: {}",
+                missing.join(", ")
+            );
+        }
         Ok(())
     }
+
+    fn start_node(&self, start_at: Option<&str>) -> anyhow::Result<&Node<'a>> {
+        match start_at {
+            Some(name) => self.node_with_name(name),
+            None => self.first_node(),
+        }
+    }
 }
 
 impl<'v> Freeze for Workflow<'v> {
@@ -109,6 +548,12 @@ impl<'v> Freeze for Workflow<'v> {
         Ok(WorkflowGen {
             entrypoint: self.entrypoint.freeze(freezer)?,
             graph: self.graph.freeze(freezer)?,
+            requires: self.requires.freeze(freezer)?,
+            redact_patterns: self.redact_patterns.freeze(freezer)?,
+            matrix: self.matrix.freeze(freezer)?,
+            inputs: self.inputs.freeze(freezer)?,
+            outputs: self.outputs.freeze(freezer)?,
+            env_policy: self.env_policy.freeze(freezer)?,
         })
     }
 }
@@ -122,7 +567,8 @@ impl<V> Display for WorkflowGen<V> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::stdlib::test_utils::assert_env;
+    use crate::runner::{Runner, WorkflowDelegate};
+    use crate::stdlib::test_utils::{assert_env, assert_path, run_workflow, TempWorkflowFile};
 
     #[test]
     fn test_required_values() {
@@ -165,7 +611,7 @@ workflow(
         sequence(name = "a", actions = []),
     ]
 )"#,
-            "nodes must have unique names",
+            "duplicate name 'a' for 'graph' in workflow definition",
         );
     }
 
@@ -181,7 +627,7 @@ workflow(
         sequence(name = "c", actions = []),
     ]
 )"#,
-            "graph can only contain node values",
+            "expected a list of node for 'graph' in workflow definition, got",
         );
     }
 
@@ -225,4 +671,475 @@ workflow(
         let first_node = workflow.first_node().unwrap();
         assert_eq!(first_node.name(), "b");
     }
+
+    #[test]
+    fn test_start_node() {
+        let res = assert_env().pass(
+            r#"
+workflow(
+    entrypoint = "b",
+    graph = [
+      node(name = "a", action = action(tool = tool(path = ""))),
+      node(name = "b", action = action(tool = tool(path = ""))),
+    ],
+)"#,
+        );
+        let workflow = Workflow::from_value(res.value()).unwrap();
+        assert_eq!(workflow.start_node(None).unwrap().name(), "b");
+        assert_eq!(workflow.start_node(Some("a")).unwrap().name(), "a");
+        assert!(workflow.start_node(Some("c")).is_err());
+    }
+
+    #[test]
+    fn test_run_follows_next() {
+        let result = run_workflow(
+            r#"
+def _exit(code):
+    return int(code)
+
+def _branch(ctx, args):
+    if ctx.exit_code == 0:
+        return "pass"
+    return "fail"
+
+branch = next(implementation = _branch)
+
+main = workflow(
+    entrypoint = "check",
+    graph = [
+        node(
+            name = "check",
+            action = fn_action(implementation = _exit, args = ["1"]),
+            next = branch(),
+        ),
+        node(name = "pass", action = fn_action(implementation = _exit, args = ["0"])),
+        node(name = "fail", action = fn_action(implementation = _exit, args = ["0"])),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        assert_path(&result, &["check", "fail"]);
+        assert_eq!(result.skipped(), vec!["pass"]);
+    }
+
+    #[test]
+    fn test_validate() {
+        let graph = r#"
+def _next(ctx, args):
+    return "deploy"
+
+main = workflow(
+    entrypoint = "approve",
+    graph = [
+        manual_gate(name = "approve", message = "ok?", next = when(variable = variable(), equals = "x", then = "deploy")),
+        node(name = "deploy", action = action(tool = tool(path = "")), tags = ["prod"]),
+        node(name = "Build", action = action(tool = tool(path = "")), next = next(implementation = _next)()),
+    ],
+)
+
+def _gated(node):
+    gates = [n for n in main.nodes() if n.manual_gate and node.name in (n.targets or [])]
+    if "prod" in node.tags and not gates:
+        return "is not behind a manual gate"
+    return None
+"#;
+        let code = |code: &str| format!("{}\n{}", graph, code);
+        assert_env().is_true(&code(
+            "[n.name for n in main.nodes()] == ['approve', 'deploy', 'Build']",
+        ));
+        assert_env().is_true(&code(
+            "[n.targets for n in main.nodes()] == [['deploy'], [], None]",
+        ));
+        assert_env().pass(&code("main.validate(_gated)"));
+
+        assert_env().fail(
+            &code("main.validate(lambda node: None if node.name.islower() else 'lower case')"),
+            "the workflow failed validation:\n  node 'Build': lower case",
+        );
+        assert_env().fail(
+            &code("main.validate(lambda node: 'no tags' if not node.tags else None)"),
+            "node 'approve': no tags\n  node 'Build': no tags",
+        );
+        assert_env().fail(
+            &code("main.validate(lambda node: 1)"),
+            "validate check must return a string or None",
+        );
+    }
+
+    #[test]
+    fn test_run_graph_in_its_own_scope() {
+        let result = run_workflow(
+            r#"
+version = variable(default = "1")
+local = variable(default = "clean")
+out = variable(default = "none")
+
+def _return(value):
+    return value
+
+def _stdout(ctx):
+    return ctx.stdout
+
+def _set(value, variable):
+    return fn_action(
+        implementation = _return,
+        args = [value],
+        setters = [setter(implementation = _stdout, variable = variable)],
+    )
+
+main = workflow(
+    entrypoint = "bump",
+    graph = [
+        node(
+            name = "bump",
+            action = _set("2", version),
+            next = when(variable = version, equals = "2", then = "build"),
+        ),
+        node(
+            name = "build",
+            graph = [
+                node(
+                    name = "compile",
+                    action = _set("dirty", local),
+                    next = when(variable = version, equals = "2", then = "package"),
+                ),
+                node(name = "package", action = _set("built", out)),
+            ],
+            imports = [version],
+            exports = [out],
+            next = when(variable = out, equals = "built", then = "check"),
+        ),
+        node(
+            name = "check",
+            action = _set("ok", out),
+            next = when(variable = local, equals = "clean", then = "done"),
+        ),
+        node(name = "done", action = _set("done", out)),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        assert_path(&result, &["bump", "build", "check", "done"]);
+    }
+
+    #[test]
+    fn test_run_follows_when() {
+        let result = run_workflow(
+            r#"
+target = variable(default = "unset")
+
+def _set():
+    return "prod"
+
+def _stdout(ctx):
+    return ctx.stdout
+
+def _noop():
+    return None
+
+main = workflow(
+    entrypoint = "configure",
+    graph = [
+        node(
+            name = "configure",
+            action = fn_action(
+                implementation = _set,
+                setters = [setter(implementation = _stdout, variable = target)],
+            ),
+            next = when(variable = target, equals = "prod", then = "deploy", else_ = "stage"),
+        ),
+        node(name = "deploy", action = fn_action(implementation = _noop)),
+        node(name = "stage", action = fn_action(implementation = _noop)),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        assert_path(&result, &["configure", "deploy"]);
+
+        assert_env().fail(
+            r#"
+v = variable()
+workflow(
+    entrypoint = "a",
+    graph = [
+        node(
+            name = "a",
+            action = action(tool = tool(path = "")),
+            next = when(variable = v, equals = "x", then = "b"),
+        ),
+    ],
+)"#,
+            r#"node 'a' uses when(equals = "x", then = "b") but there is no node named 'b'"#,
+        );
+    }
+
+    #[test]
+    fn test_tag_filter_skips_nodes() {
+        let file = TempWorkflowFile::new(
+            "test.workflow",
+            r#"
+def _noop():
+    return None
+
+def _to(name):
+    def _next(ctx, args):
+        return name if ctx.success else None
+    return next(implementation = _next)()
+
+main = workflow(
+    entrypoint = "build",
+    graph = [
+        node(name = "build", action = fn_action(implementation = _noop), next = _to("integration")),
+        node(
+            name = "integration",
+            action = fn_action(implementation = _noop),
+            next = _to("deploy"),
+            tags = ["slow"],
+        ),
+        node(name = "deploy", action = fn_action(implementation = _noop), tags = ["release"]),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        let run = |skip: &[&str], only: &[&str]| {
+            let runner = Runner::new(file.path(), WorkflowDelegate::new()).unwrap();
+            runner.set_tag_filter(TagFilter::new(
+                skip.iter().map(|t| t.to_string()).collect(),
+                only.iter().map(|t| t.to_string()).collect(),
+            ));
+            runner.run(None).unwrap()
+        };
+
+        assert_path(&run(&[], &[]), &["build", "integration", "deploy"]);
+        let result = run(&["slow"], &[]);
+        assert_path(&result, &["build", "deploy"]);
+        assert_eq!(result.skipped(), vec!["integration"]);
+        assert_path(&run(&[], &["release"]), &["deploy"]);
+    }
+
+    #[test]
+    fn test_tag_filter_warnings() {
+        let res = assert_env().pass(
+            r#"
+workflow(
+    entrypoint = "a",
+    graph = [
+      node(name = "a", action = action(tool = tool(path = "")), tags = ["slow"]),
+      node(name = "b", action = action(tool = tool(path = ""))),
+    ],
+)"#,
+        );
+        let workflow = Workflow::from_value(res.value()).unwrap();
+        let filter = TagFilter::new(vec!["slow".to_string()], vec!["fast".to_string()]);
+        assert_eq!(
+            workflow.tag_filter_warnings(None, &filter).unwrap(),
+            [
+                "the run starts at 'a' which is skipped because it is tagged 'slow'",
+                "no node is tagged 'fast'",
+            ]
+        );
+        assert_eq!(
+            workflow
+                .tag_filter_warnings(Some("b"), &TagFilter::default())
+                .unwrap(),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_run_stops_at_error() {
+        let result = run_workflow(
+            r#"
+def _boom():
+    fail("boom")
+
+def _next(ctx, args):
+    return "b"
+
+main = workflow(
+    entrypoint = "a",
+    graph = [
+        node(
+            name = "a",
+            action = fn_action(implementation = _boom),
+            next = next(implementation = _next)(),
+        ),
+        node(name = "b", action = fn_action(implementation = _boom)),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        assert_path(&result, &["a"]);
+        assert!(result.error().is_some());
+    }
+
+    #[test]
+    fn test_redact_patterns() {
+        assert_env().pass("workflow(graph = [], redact_patterns = ['ghp_[A-Za-z0-9]+'])");
+        assert_env().fail(
+            "workflow(graph = [], redact_patterns = ['ghp_('])",
+            "Invalid attribute 'redact_patterns'",
+        );
+    }
+
+    #[test]
+    fn test_run_redacts_output_and_errors() {
+        let result = run_workflow(
+            r#"
+token = variable(default = "")
+def _update(ctx):
+    return ctx.stdout
+
+def _check(token):
+    fail("bad token: " + token + ", expected hunter2")
+
+main = workflow(
+    entrypoint = "a",
+    redact_patterns = ["ghp_[A-Za-z0-9]+", "hunter2"],
+    graph = [
+        sequence(
+            name = "a",
+            actions = [
+                action(
+                    tool = builtin_tool(name = "printf"),
+                    args = ["one\\ntoken=ghp_abc123"],
+                    setters = [setter(implementation = _update, variable = token)],
+                ),
+                fn_action(implementation = _check, args = [token]),
+            ],
+        ),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        let error = result.error().unwrap().error.as_deref().unwrap();
+        assert!(
+            error.contains("bad token: one\ntoken=[REDACTED], expected [REDACTED]"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_run_redacts_secrets() {
+        let result = run_workflow(
+            r#"
+token = secret(env = "WORKFLOW_TEST_UNSET_TOKEN")
+def _update(ctx):
+    return ctx.stdout
+
+def _check(token):
+    fail("leaked: " + token)
+
+main = workflow(
+    entrypoint = "a",
+    graph = [
+        sequence(
+            name = "a",
+            actions = [
+                action(
+                    tool = builtin_tool(name = "printf"),
+                    args = ["s3cret"],
+                    setters = [setter(implementation = _update, variable = token)],
+                ),
+                fn_action(implementation = _check, args = [token]),
+            ],
+        ),
+    ],
+)
+"#,
+        )
+        .unwrap();
+        let error = result.error().unwrap().error.as_deref().unwrap();
+        assert!(error.contains("leaked: [REDACTED]"), "{}", error);
+    }
+
+    #[test]
+    fn test_matrix() {
+        let workflow = |matrix: &str| {
+            format!(
+                "workflow(entrypoint = 'a', graph = [node(name = 'a', action = action(tool = tool(path = 'make')))], matrix = {})",
+                matrix
+            )
+        };
+        assert_env().pass(&workflow("{'os': ['linux', 'macos']}"));
+        assert_env().fail(
+            &workflow("{'os': []}"),
+            "Invalid attribute 'matrix', 'os' must be a non empty list of strings",
+        );
+        assert_env().fail(
+            &workflow("{'os': [1]}"),
+            "expected a list of string for 'matrix' in workflow definition, got int",
+        );
+    }
+
+    #[test]
+    fn test_requires() {
+        let res = assert_env().pass(
+            r#"
+workflow(
+    graph = [],
+    requires = ["ls", "__workflow_missing_a__", "__workflow_missing_b__"],
+)"#,
+        );
+        let workflow = Workflow::from_value(res.value()).unwrap();
+        assert_eq!(workflow.requires().len(), 3);
+        assert_eq!(
+            workflow.missing_requirements(),
+            vec!["__workflow_missing_a__", "__workflow_missing_b__"]
+        );
+        assert_eq!(
+            workflow.check_requirements().unwrap_err().to_string(),
+            "The workflow requires commands which are not on the PATH: \
+             __workflow_missing_a__, __workflow_missing_b__"
+        );
+    }
+
+    #[test]
+    fn test_run_checks_requirements() {
+        let err = run_workflow(
+            r#"
+def _run():
+    return None
+
+main = workflow(
+    graph = node(name = "a", action = fn_action(implementation = _run)),
+    requires = ["__workflow_missing__"],
+)
+"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The workflow requires commands which are not on the PATH: __workflow_missing__"
+        );
+    }
+
+    #[test]
+    fn test_node_lock_held_while_running() {
+        let result = run_workflow(
+            r#"
+def _run():
+    return None
+
+main = workflow(
+    graph = node(
+        name = "a",
+        action = fn_action(implementation = _run),
+        requires_lock = "workflow_test_lock",
+    ),
+)
+"#,
+        )
+        .unwrap();
+        assert_path(&result, &["a"]);
+        assert!(result.succeeded());
+        assert!(!LockManager::global().is_locked("workflow_test_lock"));
+    }
 }